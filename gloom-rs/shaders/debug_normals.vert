@@ -0,0 +1,19 @@
+#version 430 core
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 2) in vec3 inNormal;
+
+uniform mat4 mvp_matrix;
+uniform mat4 model_matrix;
+
+out VertexData {
+    vec3 worldPosition;
+    vec3 worldNormal;
+} vertexOut;
+
+void main() {
+    // The geometry shader needs world space to place the end of the lines
+    vertexOut.worldPosition = (model_matrix * vec4(inPosition, 1.0)).xyz;
+    vertexOut.worldNormal = normalize(mat3(model_matrix) * inNormal);
+    gl_Position = mvp_matrix * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,36 @@
+#version 430 core
+
+in vec2 fragCorner;
+in vec3 fragRight;
+in vec3 fragUp;
+
+uniform vec3 earth_direction;
+uniform mat4 earth_rotation;  // Its tilt and how far it has spun
+uniform vec3 sun_direction;   // The sunlight travels along it
+uniform float visibility;     // Of the night sky, the unlit side only hides the stars
+uniform sampler2D earth_texture; // Equirectangular, the first row at the south pole
+
+out vec4 outColor;
+
+const float PI = 3.14159265;
+
+void main() {
+    float radius_squared = dot(fragCorner, fragCorner);
+    if (radius_squared > 1.0) {
+        discard;
+    }
+    // The sphere's surface under this pixel, the side facing the camera
+    vec3 normal = fragRight * fragCorner.x + fragUp * fragCorner.y - earth_direction * sqrt(1.0 - radius_squared);
+
+    vec3 surface = (transpose(earth_rotation) * vec4(normal, 0.0)).xyz;
+    vec2 texCoord = vec2(atan(surface.z, surface.x) / (2.0 * PI), asin(clamp(surface.y, -1.0, 1.0)) / PI + 0.5);
+    vec3 albedo = texture(earth_texture, texCoord).rgb;
+
+    // Lit by the sun in space, the time of day doesn't dim it. A thin blue rim of atmosphere.
+    float light = max(dot(normal, -sun_direction), 0.0);
+    float rim = pow(1.0 - sqrt(1.0 - radius_squared), 3.0);
+    vec3 color = albedo * light * 1.5 + vec3(0.3, 0.5, 1.0) * rim * light + albedo * 0.01;
+
+    float edge = 1.0 - smoothstep(0.96, 1.0, radius_squared);
+    outColor = vec4(color, edge * max(smoothstep(0.0, 0.2, light), visibility));
+}
@@ -0,0 +1,27 @@
+#version 430 core
+
+// A quad facing the camera, infinitely far away in the Earth's direction, made up from gl_VertexID
+// (triangle strip of 4). The sphere is worked out in earth.frag.
+
+uniform mat4 view_projection; // Of the camera at the origin
+uniform vec3 earth_direction;
+uniform float earth_radius;   // Tangent of the angle it covers from its middle
+
+out vec2 fragCorner;   // From -1 to 1 across the quad
+out vec3 fragRight;    // The quad's axes in the world
+out vec3 fragUp;
+
+void main() {
+    vec3 helper = abs(earth_direction.y) > 0.99 ? vec3(1.0, 0.0, 0.0) : vec3(0.0, 1.0, 0.0);
+    vec3 right = normalize(cross(earth_direction, helper));
+    vec3 up = cross(right, earth_direction);
+
+    vec2 corner = vec2(float(gl_VertexID & 1), float((gl_VertexID >> 1) & 1)) * 2.0 - 1.0;
+    fragCorner = corner;
+    fragRight = right;
+    fragUp = up;
+
+    vec3 direction = earth_direction + (right * corner.x + up * corner.y) * earth_radius;
+    gl_Position = view_projection * vec4(direction, 0.0);
+    gl_Position.z = 0.0; // Nothing to test against, in the clip volume either way round
+}
@@ -0,0 +1,26 @@
+#version 430 core
+
+// Eases the adapted luminance towards the metered one and works out the exposure from it, into a 1x1
+// target, see auto_exposure.rs
+
+uniform sampler2D meter_texture;    // Mipmapped, the last level is the weighted average
+uniform int meter_level;
+uniform sampler2D previous_texture; // Last frame's result
+uniform float blend;                // How much of the way to go this frame, 1 jumps
+uniform float key_value;
+uniform float min_exposure;
+uniform float max_exposure;
+
+out vec4 outColor;
+
+void main() {
+    vec2 meter = texelFetch(meter_texture, ivec2(0), meter_level).rg;
+    float metered = meter.r / max(meter.g, 1e-4);
+
+    // Eased in log space, so getting twice as bright takes as long as getting half as bright
+    float previous = texelFetch(previous_texture, ivec2(0), 0).r;
+    float adapted = mix(previous, metered, blend);
+
+    float exposure = clamp(key_value / exp(adapted), min_exposure, max_exposure);
+    outColor = vec4(adapted, exposure, 0.0, 1.0);
+}
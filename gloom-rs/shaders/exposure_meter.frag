@@ -0,0 +1,22 @@
+#version 430 core
+
+// Log luminance of the scene for the auto exposure, see auto_exposure.rs. Weighted towards the middle
+// of the window, the weight goes along in G so the average can be divided by it.
+
+in vec2 fragTexCoord;
+
+uniform sampler2D hdr_texture;
+
+out vec4 outColor;
+
+void main() {
+    // Averaged over the pixels this texel covers, the window is a lot bigger than the meter
+    vec3 hdr = texture(hdr_texture, fragTexCoord).rgb;
+    float luminance = dot(hdr, vec3(0.2126, 0.7152, 0.0722));
+
+    // Full weight in the middle, a quarter at the corners
+    float fromCenter = length(fragTexCoord - 0.5) / 0.7071;
+    float weight = mix(1.0, 0.25, fromCenter * fromCenter);
+
+    outColor = vec4(log(luminance + 1e-4) * weight, weight, 0.0, 1.0);
+}
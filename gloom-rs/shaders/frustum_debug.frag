@@ -0,0 +1,11 @@
+#version 430 core
+
+uniform vec3 line_color;
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec4 outMotion; // Not written, the motion comes from the camera alone (see motion.rs)
+
+void main() {
+    outColor = vec4(line_color, 1.0);
+    outMotion = vec4(0.0, 0.0, 0.0, 1.0);
+}
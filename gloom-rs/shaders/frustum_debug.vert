@@ -0,0 +1,9 @@
+#version 430 core
+
+layout(location = 0) in vec3 inPosition;
+
+uniform mat4 mvp_matrix;
+
+void main() {
+    gl_Position = mvp_matrix * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,10 @@
+#version 430 core
+
+// One triangle that covers the whole screen, no vertex buffers needed
+out vec2 fragTexCoord;
+
+void main() {
+    vec2 position = vec2(float((gl_VertexID << 1) & 2), float(gl_VertexID & 2));
+    fragTexCoord = position;
+    gl_Position = vec4(position * 2.0 - 1.0, 0.0, 1.0);
+}
@@ -0,0 +1,30 @@
+#version 430 core
+
+// Radial blur of the god ray mask towards the sun, added to the scene (see god_rays.rs)
+
+in vec2 fragTexCoord; // Inside the view
+
+uniform sampler2D mask_texture;
+uniform vec4 view_rect;    // The view inside the mask texture, (x, y, width, height)
+uniform vec2 sun_position; // In the view, 0 to 1
+uniform int samples;
+uniform float density;
+uniform float decay;
+uniform float strength;
+
+out vec4 outColor;
+
+void main() {
+    vec2 stride = (sun_position - fragTexCoord) * density / float(samples);
+    vec2 position = fragTexCoord;
+    float weight = 1.0;
+    vec3 rays = vec3(0.0);
+    for (int i = 0; i < samples; i++) {
+        position += stride;
+        // Clamped to the view, so the split screen views don't bleed into each other
+        rays += texture(mask_texture, view_rect.xy + clamp(position, 0.0, 1.0) * view_rect.zw).rgb * weight;
+        weight *= decay;
+    }
+
+    outColor = vec4(rays * strength / float(samples), 1.0);
+}
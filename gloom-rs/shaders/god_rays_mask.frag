@@ -0,0 +1,28 @@
+#version 430 core
+
+// The sun disc where the sky is visible, what the god rays are smeared out from (see god_rays.rs)
+
+in vec2 fragTexCoord; // Inside the view
+
+uniform sampler2D depth_texture; // Of the scene
+uniform vec4 view_rect;          // The view inside the depth texture, (x, y, width, height)
+uniform vec2 sun_position;       // In the view, 0 to 1
+uniform float aspect_ratio;      // Of the view
+uniform float sun_radius;        // As a fraction of the view height
+uniform vec3 sun_color;
+uniform int reversed_z;          // Non-zero if the depth buffer is reversed
+
+out vec4 outColor;
+
+void main() {
+    // Only the far plane (sky) lets the sun through
+    float depth = texture(depth_texture, view_rect.xy + fragTexCoord * view_rect.zw).r;
+    if (reversed_z != 0 ? depth > 0.00001 : depth < 0.99999) {
+        outColor = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec2 offset = (fragTexCoord - sun_position) * vec2(aspect_ratio, 1.0);
+    float disc = 1.0 - smoothstep(0.0, sun_radius, length(offset));
+    outColor = vec4(sun_color * disc * disc, 1.0);
+}
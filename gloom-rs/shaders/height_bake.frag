@@ -0,0 +1,9 @@
+#version 430 core
+
+in float height;
+
+out vec4 outHeight;
+
+void main() {
+    outHeight = vec4(height, 0.0, 0.0, 1.0);
+}
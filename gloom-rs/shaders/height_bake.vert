@@ -0,0 +1,16 @@
+#version 430 core
+
+// Renders the terrain mesh from straight above into the height map, the highest point wins the depth test
+
+layout(location = 0) in vec3 inPosition;
+
+uniform vec3 bounds_min;
+uniform vec3 bounds_size;
+
+out float height;
+
+void main() {
+    vec3 t = (inPosition - bounds_min) / bounds_size; // 0 to 1 on every axis
+    height = inPosition.y;
+    gl_Position = vec4(t.x * 2.0 - 1.0, t.z * 2.0 - 1.0, 1.0 - t.y * 2.0, 1.0);
+}
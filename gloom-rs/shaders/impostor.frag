@@ -0,0 +1,68 @@
+#version 430 core
+
+// Impostors, see impostor.rs. Lit by the sun with the normal captured into the atlas, no shadows and
+// no dynamic lights, they are too far away to show them.
+
+in vec2 fragTexCoord;
+in vec3 fragWorldPosition;
+in mat3 fragNormalMatrix;
+
+uniform sampler2D albedo_atlas; // Color in RGB, coverage in A
+uniform sampler2D normal_atlas; // Normal in the subject's space, packed into 0..1
+
+// Motion vectors, see motion.rs. The impostors are drawn where they are, as if they didn't move.
+uniform mat4 motion_view_projection;
+uniform mat4 previous_view_projection;
+
+// Sun and sky, see time_of_day.rs
+uniform vec3 sun_direction; // Direction the light travels in
+uniform vec3 sun_color;
+uniform vec3 ambient_color;
+
+// Distance fog, see FogSettings in settings.rs
+uniform vec3 camera_position;
+uniform int fog_enabled;
+uniform int fog_mode; // 0 linear, 1 exponential, 2 exponential squared
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_density;
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec4 outMotion;
+
+// Same as in simple.frag
+float fogFactor(float distance) {
+    if (fog_mode == 0) {
+        return clamp((distance - fog_start) / (fog_end - fog_start), 0.0, 1.0);
+    } else if (fog_mode == 1) {
+        return 1.0 - exp(-fog_density * distance);
+    } else {
+        float d = fog_density * distance;
+        return 1.0 - exp(-d * d);
+    }
+}
+
+void main() {
+    vec4 albedo = texture(albedo_atlas, fragTexCoord);
+    if (albedo.a < 0.5) {
+        discard;
+    }
+    // The texels around the subject are black and transparent, the mipmaps mix them in
+    vec3 albedoColor = albedo.rgb / albedo.a;
+    vec4 packedNormal = texture(normal_atlas, fragTexCoord);
+    vec3 normal = normalize(fragNormalMatrix * (packedNormal.rgb / max(packedNormal.a, 0.001) * 2.0 - 1.0));
+
+    float lightIntensity = max(dot(normal, -sun_direction), 0.0);
+    vec3 color = albedoColor * (sun_color * lightIntensity + ambient_color);
+
+    if (fog_enabled != 0) {
+        float distance = length(fragWorldPosition - camera_position);
+        color = mix(color, fog_color, fogFactor(distance));
+    }
+
+    outColor = vec4(color, 1.0);
+    vec4 clip = motion_view_projection * vec4(fragWorldPosition, 1.0);
+    vec4 previousClip = previous_view_projection * vec4(fragWorldPosition, 1.0);
+    outMotion = vec4((clip.xy / clip.w - previousClip.xy / previousClip.w) * 0.5, 1.0, 1.0);
+}
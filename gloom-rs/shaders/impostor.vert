@@ -0,0 +1,63 @@
+#version 430 core
+
+// Impostors, see impostor.rs. A quad made up from gl_VertexID (triangle strip of 4), turned towards
+// the camera in the subject's own space and put in the world with the instance's model matrix.
+
+#define MAX_SUBJECTS 8
+#define YAW_FRAMES 8
+#define PITCH_FRAMES 3
+#define PITCH_STEP radians(30.0)
+#define PI 3.14159265
+
+struct Instance {
+    mat4 model;
+    vec4 bounds; // Unused here
+    uvec4 mesh;  // Which subject of the atlas, in x
+};
+
+layout(std430, binding = 0) readonly buffer Instances { Instance instances[]; };
+layout(std430, binding = 1) readonly buffer Visible { uint visible[]; };
+
+uniform mat4 view_projection;
+uniform vec3 camera_position;
+uniform vec4 clip_plane;   // Only used while GL_CLIP_DISTANCE0 is on, for reflections
+uniform int listed;        // Draw the instances listed in visible from first_visible on, instead of all of them
+uniform uint first_visible;
+uniform vec4 subject_bounds[MAX_SUBJECTS]; // (center, radius) in each subject's own space
+uniform int subject_count;
+
+out vec2 fragTexCoord;
+out vec3 fragWorldPosition;
+out mat3 fragNormalMatrix; // From the subject's space to the world
+
+void main() {
+    uint index = listed != 0 ? visible[first_visible + uint(gl_InstanceID)] : uint(gl_InstanceID);
+    mat4 model_matrix = instances[index].model;
+    int subject = int(instances[index].mesh.x);
+    vec4 bounds = subject_bounds[subject];
+    mat3 to_subject = inverse(mat3(model_matrix));
+
+    // Where the camera is seen from the subject, in its own space
+    vec3 world_center = (model_matrix * vec4(bounds.xyz, 1.0)).xyz;
+    vec3 towards_camera = normalize(to_subject * (camera_position - world_center));
+
+    // The frame captured from closest to there
+    float yaw = atan(towards_camera.x, towards_camera.z);
+    float pitch = asin(clamp(towards_camera.y, -1.0, 1.0));
+    int column = (int(round(yaw / (2.0 * PI / float(YAW_FRAMES)))) % YAW_FRAMES + YAW_FRAMES) % YAW_FRAMES;
+    int row = clamp(int(round(pitch / PITCH_STEP)), 0, PITCH_FRAMES - 1);
+
+    // Upright and facing the camera, the way the frames were captured
+    vec3 right = cross(vec3(0.0, 1.0, 0.0), towards_camera);
+    right = length(right) > 0.0001 ? normalize(right) : vec3(1.0, 0.0, 0.0); // Straight above
+    vec3 up = cross(towards_camera, right);
+
+    vec2 corner = vec2(float(gl_VertexID & 1), float((gl_VertexID >> 1) & 1));
+    vec3 local_position = bounds.xyz + ((corner.x * 2.0 - 1.0) * right + (corner.y * 2.0 - 1.0) * up) * bounds.w;
+
+    fragTexCoord = (vec2(column, subject * PITCH_FRAMES + row) + corner) / vec2(YAW_FRAMES, PITCH_FRAMES * subject_count);
+    fragNormalMatrix = transpose(to_subject);
+    fragWorldPosition = (model_matrix * vec4(local_position, 1.0)).xyz;
+    gl_ClipDistance[0] = dot(vec4(fragWorldPosition, 1.0), clip_plane);
+    gl_Position = view_projection * vec4(fragWorldPosition, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 430 core
+
+// Captures a subject into an impostor atlas, see impostor.rs. Its color in one pass, its normal in
+// the subject's own space in the other. A = 1 marks the texels it covers.
+
+in vec4 fragColor;
+in vec3 fragNormal;
+
+uniform vec4 material_albedo; // Set by the scene for nodes with a material, see material.rs
+uniform int capture_normals;
+
+out vec4 outColor;
+
+void main() {
+    if (capture_normals != 0) {
+        outColor = vec4(normalize(fragNormal) * 0.5 + 0.5, 1.0);
+    } else {
+        outColor = vec4((material_albedo * fragColor).rgb, 1.0);
+    }
+}
@@ -0,0 +1,126 @@
+#version 430 core
+
+// The ground plane out to the horizon, found along the line of sight of every pixel, see ground.rs
+
+in vec2 fragTexCoord;
+
+uniform mat4 relative_view_projection; // Of a camera at the origin
+uniform mat4 inverse_view_projection;
+uniform vec3 camera_position;
+uniform float ground_height;
+uniform vec3 ground_color;
+uniform int pattern;       // 0 regolith, 1 checkerboard
+uniform float tile_size;
+uniform float fade_distance;
+uniform vec3 sun_direction; // Towards the sun
+uniform vec3 sun_color;
+uniform vec3 ambient_color;
+uniform int reversed_z;     // Non-zero if the depth buffer is reversed
+
+// Distance fog, see FogSettings in settings.rs
+uniform int fog_enabled;
+uniform int fog_mode;
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_density;
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec4 outMotion; // Not written, the motion comes from the camera alone (see motion.rs)
+
+float fogFactor(float distance) {
+    if (fog_mode == 0) {
+        return clamp((distance - fog_start) / (fog_end - fog_start), 0.0, 1.0);
+    } else if (fog_mode == 1) {
+        return 1.0 - exp(-fog_density * distance);
+    } else {
+        float d = fog_density * distance;
+        return 1.0 - exp(-d * d);
+    }
+}
+
+float hash(vec2 p) {
+    p = fract(p * vec2(123.34, 456.21));
+    p += dot(p, p + 45.32);
+    return fract(p.x * p.y);
+}
+
+float valueNoise(vec2 p) {
+    vec2 i = floor(p);
+    vec2 f = fract(p);
+    vec2 u = f * f * (3.0 - 2.0 * f);
+    return mix(mix(hash(i), hash(i + vec2(1.0, 0.0)), u.x),
+               mix(hash(i + vec2(0.0, 1.0)), hash(i + vec2(1.0, 1.0)), u.x), u.y);
+}
+
+// 0 or 1 in alternating squares of side 1, averaged over the `footprint` of the pixel so it goes to
+// 0.5 in the distance instead of flickering
+float checker(vec2 p, vec2 footprint) {
+    vec2 w = max(footprint, vec2(1e-4));
+    vec2 i = 2.0 * (abs(fract((p - 0.5 * w) * 0.5) - 0.5) - abs(fract((p + 0.5 * w) * 0.5) - 0.5)) / w;
+    return 0.5 - 0.5 * i.x * i.y;
+}
+
+// Brightness of the regolith: noise over a few scales, the ones finer than the pixel left out, and
+// rims of small craters
+float regolith(vec2 p, float footprint) {
+    float brightness = 0.0;
+    float amplitude = 0.5;
+    float frequency = 0.05;
+    for (int octave = 0; octave < 6; octave++) {
+        float detail = 1.0 - smoothstep(0.25, 0.5, footprint * frequency);
+        brightness += (valueNoise(p * frequency) - 0.5) * amplitude * detail;
+        amplitude *= 0.55;
+        frequency *= 2.3;
+    }
+
+    // One crater at most in every cell of 12 units, darker inside and lighter on the rim
+    vec2 cell = floor(p / 12.0);
+    vec2 center = (cell + 0.2 + 0.6 * vec2(hash(cell), hash(cell + 17.0))) * 12.0;
+    float radius = 1.0 + 3.5 * hash(cell + 31.0);
+    float r = length(p - center) / radius;
+    float crater = (-0.25 * (1.0 - smoothstep(0.6, 0.9, r)) + 0.15 * smoothstep(0.7, 0.95, r) * (1.0 - smoothstep(0.95, 1.3, r)))
+                 * (1.0 - smoothstep(0.5, 1.0, footprint / radius));
+    return 1.0 + brightness + crater;
+}
+
+void main() {
+    // The line of sight through this pixel, from the camera at the origin
+    vec4 far = inverse_view_projection * vec4(fragTexCoord * 2.0 - 1.0, 0.5, 1.0);
+    vec3 direction = normalize(far.xyz / far.w);
+
+    // Where it meets the ground, behind the camera or nowhere for the pixels above the horizon
+    float t = (ground_height - camera_position.y) / direction.y;
+    vec3 relative = direction * t;
+    vec2 ground = camera_position.xz + relative.xz;
+    // How much ground the pixel covers, before any pixels are thrown away, the derivatives need them all
+    vec2 footprint = min(abs(dFdx(ground)) + abs(dFdy(ground)), vec2(1e4)); // Next to one past the horizon it is far too big
+    if (!(t > 0.0) || isinf(t)) {
+        discard;
+    }
+    vec4 clip = relative_view_projection * vec4(relative, 1.0);
+    float depth = clip.z / clip.w;
+    depth = reversed_z != 0 ? depth : depth * 0.5 + 0.5;
+    if (depth < 0.0 || depth > 1.0) {
+        discard; // Past the far plane
+    }
+    gl_FragDepth = depth;
+
+    vec3 albedo;
+    if (pattern == 1) {
+        albedo = ground_color * mix(0.55, 1.0, checker(ground / tile_size, footprint / tile_size));
+    } else {
+        albedo = ground_color * regolith(ground, length(footprint));
+    }
+
+    vec3 normal = vec3(0.0, 1.0, 0.0);
+    vec3 color = albedo * (ambient_color + sun_color * max(dot(normal, sun_direction), 0.0));
+
+    // The line of sight is of unit length, so t is the distance to the camera
+    float fog = fog_enabled != 0 ? fogFactor(t) : 0.0;
+    fog = max(fog, smoothstep(0.5 * fade_distance, fade_distance, t));
+    color = mix(color, fog_color, fog);
+
+    outColor = vec4(color, 1.0);
+    outMotion = vec4(0.0, 0.0, 0.0, 1.0);
+}
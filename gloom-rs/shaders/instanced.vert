@@ -0,0 +1,38 @@
+#version 430 core
+
+// Instances of an instance batch. The model matrices stay in a storage buffer, the per instance
+// attribute only says which one to use (the base instance of each draw picks where in the list it starts).
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 color;
+layout(location = 2) in vec3 inNormal;
+layout(location = 4) in uint instanceIndex;
+
+struct Instance {
+    mat4 model;
+    vec4 bounds;
+    uvec4 mesh;
+};
+
+layout(std430, binding = 0) readonly buffer Instances { Instance instances[]; };
+
+uniform mat4 view_projection;
+uniform vec4 clip_plane;   // Only used while GL_CLIP_DISTANCE0 is on, for reflections
+
+out vec4 fragColor;
+out vec3 fragNormal;
+out vec3 fragWorldPosition;
+out vec3 fragPreviousWorldPosition; // The instances don't move, same as fragWorldPosition
+out vec2 fragTexCoord;
+
+void main() {
+    mat4 model_matrix = instances[instanceIndex].model;
+
+    fragColor = color;
+    fragTexCoord = vec2(0.0);
+    fragNormal = normalize(mat3(model_matrix) * inNormal);
+    fragWorldPosition = (model_matrix * vec4(inPosition, 1.0)).xyz;
+    fragPreviousWorldPosition = fragWorldPosition;
+    gl_ClipDistance[0] = dot(vec4(fragWorldPosition, 1.0), clip_plane);
+    gl_Position = view_projection * vec4(fragWorldPosition, 1.0);
+}
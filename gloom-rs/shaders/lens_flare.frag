@@ -0,0 +1,15 @@
+#version 430 core
+
+in vec2 fragTexCoord;
+in float visibility;
+
+uniform sampler2D sprite_atlas; // Brightness in R
+uniform vec4 sprite_rect;       // The element's shape in the atlas, lower left corner and size
+uniform vec3 element_color;
+
+out vec4 outColor;
+
+void main() {
+    float brightness = texture(sprite_atlas, sprite_rect.xy + fragTexCoord * sprite_rect.zw).r;
+    outColor = vec4(element_color * brightness * visibility * 0.6, 1.0);
+}
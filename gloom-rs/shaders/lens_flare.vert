@@ -0,0 +1,42 @@
+#version 430 core
+
+// One flare element, a quad made up from gl_VertexID (triangle strip of 4)
+
+uniform vec3 sun_position;     // Sun in normalized device coordinates of the view (z unused)
+uniform float aspect_ratio;    // Of the view
+uniform vec4 view_rect;        // The view inside the depth texture, (x, y, width, height)
+uniform sampler2D depth_texture;
+uniform int reversed_z;        // Non-zero if the depth buffer is reversed
+
+uniform float element_position; // 0 at the sun, 1 at the center, 2 on the opposite side
+uniform float element_size;     // Radius as a fraction of the view height
+
+out vec2 fragTexCoord;
+out float visibility;
+
+void main() {
+    // How much of the sun is visible: sample the depth around it, only the far plane (sky) counts
+    const int samples = 5;
+    float visible = 0.0;
+    for (int y = 0; y < samples; y++) {
+        for (int x = 0; x < samples; x++) {
+            vec2 offset = (vec2(x, y) / float(samples - 1) - 0.5) * vec2(0.02 / aspect_ratio, 0.02);
+            vec2 screen = (sun_position.xy + offset) * 0.5 + 0.5;
+            if (all(greaterThanEqual(screen, vec2(0.0))) && all(lessThanEqual(screen, vec2(1.0)))) {
+                float depth = texture(depth_texture, view_rect.xy + screen * view_rect.zw).r;
+                visible += (reversed_z != 0 ? depth <= 0.00001 : depth >= 0.99999) ? 1.0 : 0.0;
+            }
+        }
+    }
+    visibility = visible / float(samples * samples);
+
+    // Fade out as the sun nears the edge of the view
+    visibility *= clamp(1.5 - length(sun_position.xy), 0.0, 1.0);
+
+    vec2 corner = vec2(float(gl_VertexID & 1), float((gl_VertexID >> 1) & 1));
+    fragTexCoord = corner;
+
+    vec2 center = sun_position.xy * (1.0 - element_position);
+    vec2 offset = (corner * 2.0 - 1.0) * vec2(element_size / aspect_ratio, element_size);
+    gl_Position = vec4(center + offset, 0.0, 1.0);
+}
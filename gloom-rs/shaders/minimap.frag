@@ -0,0 +1,29 @@
+#version 430 core
+
+in vec2 fragCorner;
+in vec2 fragMapCoord;
+
+uniform int shape;             // 0 the terrain map, 1 a marker sprite
+uniform sampler2D terrain_map;
+uniform vec4 map_rect;         // The area shown in terrain map coordinates, (x, z) of the corner and the size
+uniform sampler2D sprite_atlas;
+uniform vec4 sprite_rect;      // The marker in the atlas, lower left corner and size
+uniform vec3 marker_color;
+
+out vec4 outColor;
+
+void main() {
+    if (shape == 0) {
+        // Up on the minimap is -Z, which is down in the terrain map
+        vec2 uv = map_rect.xy + vec2(fragMapCoord.x, 1.0 - fragMapCoord.y) * map_rect.zw;
+        bool outside = any(lessThan(uv, vec2(0.0))) || any(greaterThan(uv, vec2(1.0)));
+        outColor = outside ? vec4(0.02, 0.025, 0.04, 1.0) : vec4(texture(terrain_map, uv).rgb, 1.0);
+        return;
+    }
+
+    vec4 sprite = texture(sprite_atlas, sprite_rect.xy + (fragCorner * 0.5 + 0.5) * sprite_rect.zw);
+    if (sprite.a < 0.5) {
+        discard;
+    }
+    outColor = vec4(marker_color * sprite.rgb, 1.0);
+}
@@ -0,0 +1,20 @@
+#version 430 core
+
+// One quad of the minimap overlay, made up from gl_VertexID (triangle strip of 4).
+// Coordinates are -1 to 1 across the minimap viewport.
+
+uniform vec4 rect;      // Center (x, y) and half size (width, height) of the quad
+uniform float rotation; // Counterclockwise, in radians
+
+out vec2 fragCorner; // -1 to 1 across the quad, before the rotation
+out vec2 fragMapCoord; // 0 to 1 across the minimap
+
+void main() {
+    vec2 corner = vec2(float(gl_VertexID & 1), float((gl_VertexID >> 1) & 1)) * 2.0 - 1.0;
+    fragCorner = corner;
+
+    mat2 turn = mat2(cos(rotation), sin(rotation), -sin(rotation), cos(rotation));
+    vec2 position = rect.xy + turn * (corner * rect.zw);
+    fragMapCoord = position * 0.5 + 0.5;
+    gl_Position = vec4(position, 0.0, 1.0);
+}
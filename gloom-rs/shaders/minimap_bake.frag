@@ -0,0 +1,28 @@
+#version 430 core
+
+in vec4 fragColor;
+in vec3 fragNormal;
+in float height;
+
+uniform vec3 bounds_min;
+uniform vec3 bounds_size;
+uniform float contour_step; // Height between two contour lines
+
+out vec4 outColor;
+
+void main() {
+    // Lit from the top left of the map, like on a paper map, so slopes facing it are brighter
+    float light = 0.35 + 0.65 * max(dot(normalize(fragNormal), normalize(vec3(-1.0, 1.5, -1.0))), 0.0);
+
+    // Low ground darker and bluer than high ground
+    float t = clamp((height - bounds_min.y) / bounds_size.y, 0.0, 1.0);
+    vec3 tint = mix(vec3(0.35, 0.4, 0.5), vec3(1.0, 0.97, 0.9), t);
+
+    // A thin dark line every contour_step, as wide as a pixel wherever the slope is
+    float contour = abs(fract(height / contour_step + 0.5) - 0.5) / fwidth(height / contour_step);
+    float line = 1.0 - 0.35 * (1.0 - clamp(contour, 0.0, 1.0));
+
+    // The vertex colors are linear (see color.rs), the map is drawn in display colors
+    vec3 color = pow(fragColor.rgb, vec3(1.0 / 2.2));
+    outColor = vec4(color * tint * light * line, 1.0);
+}
@@ -0,0 +1,22 @@
+#version 430 core
+
+// Renders the terrain mesh from straight above into the minimap's terrain map, like height_bake.vert
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 color;
+layout(location = 2) in vec3 inNormal;
+
+uniform vec3 bounds_min;
+uniform vec3 bounds_size;
+
+out vec4 fragColor;
+out vec3 fragNormal;
+out float height;
+
+void main() {
+    vec3 t = (inPosition - bounds_min) / bounds_size; // 0 to 1 on every axis
+    fragColor = color;
+    fragNormal = inNormal;
+    height = inPosition.y;
+    gl_Position = vec4(t.x * 2.0 - 1.0, t.z * 2.0 - 1.0, 1.0 - t.y * 2.0, 1.0);
+}
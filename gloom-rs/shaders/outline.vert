@@ -0,0 +1,25 @@
+#version 430 core
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 2) in vec3 inNormal;
+
+uniform mat4 mvp_matrix;
+uniform mat4 model_matrix;
+uniform mat4 view_projection;
+uniform vec2 viewport_size;  // In pixels
+uniform float outline_width; // In pixels
+
+void main() {
+    // Push every vertex out along its normal, by the same number of pixels no matter the distance
+    vec4 clipPosition = mvp_matrix * vec4(inPosition, 1.0);
+    vec3 worldNormal = normalize(mat3(model_matrix) * inNormal);
+    vec4 clipNormal = view_projection * vec4(worldNormal, 0.0);
+
+    vec2 screenNormal = clipNormal.xy;
+    if (length(screenNormal) > 1e-6) {
+        screenNormal = normalize(screenNormal);
+    }
+    clipPosition.xy += screenNormal / viewport_size * outline_width * 2.0 * clipPosition.w;
+
+    gl_Position = clipPosition;
+}
@@ -0,0 +1,348 @@
+#version 430 core
+
+in vec4 fragColor;         // Vertex color, multiplied into the albedo
+in vec3 fragNormal;        // World space normal
+in vec3 fragWorldPosition; // World space position
+in vec3 fragPreviousWorldPosition; // Where it was last frame
+
+// Motion vectors, see motion.rs. Without the TAA jitter.
+uniform mat4 motion_view_projection;
+uniform mat4 previous_view_projection;
+in vec2 fragTexCoord;      // Texture coordinate, (0, 0) for meshes without them
+
+uniform vec3 camera_position;
+
+// Material, see material.rs
+uniform vec4 material_albedo;
+uniform float material_metallic;
+uniform float material_roughness;
+uniform float material_ao;
+uniform vec3 material_emissive;
+
+uniform int has_albedo_map;
+uniform int has_metallic_roughness_map;
+uniform int has_occlusion_map;
+uniform int has_normal_map;
+uniform int has_emissive_map;
+uniform sampler2D albedo_map;
+uniform sampler2D metallic_roughness_map; // Roughness in G, metallic in B
+uniform sampler2D occlusion_map;          // Occlusion in R
+uniform sampler2D normal_map;             // Tangent space
+uniform sampler2D emissive_map;
+
+// Image based lighting
+uniform samplerCube environment_map;
+uniform float environment_mip_levels;
+uniform float environment_intensity; // Dimmer at night
+
+// Reflection probes, see probes.rs. The nearest one around the fragment stands in for the environment map.
+#define MAX_PROBES 4
+uniform int probe_count;
+uniform vec4 probe_spheres[MAX_PROBES]; // Center and radius of where every probe is used
+uniform samplerCube probe_maps[MAX_PROBES];
+uniform float probe_mip_levels;
+
+// Sun, see time_of_day.rs
+uniform vec3 sun_direction; // Direction the light travels in
+uniform vec3 sun_color;
+
+// Distance fog, see FogSettings in settings.rs
+uniform int fog_enabled;
+uniform int fog_mode; // 0 linear, 1 exponential, 2 exponential squared
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_density;
+
+// Dynamic point and spot lights, sorted into clusters of the view, see lights.rs and clusters.rs
+#define MAX_LIGHTS 256
+#define LIGHT_INDEX_WIDTH 1024u
+struct Light {
+    vec3 position;
+    vec3 direction; // Spot lights only
+    vec3 color;     // Already multiplied by the intensity
+    float range;
+    float cos_inner;
+    float cos_outer;
+};
+uniform sampler2D light_data;      // Three texels per light, one row each
+uniform usampler2D cluster_lights; // Start and length of every cluster's list in light_indices
+uniform usampler2D light_indices;  // The lists one after the other, LIGHT_INDEX_WIDTH to a row
+#pragma uniform_block LightClusters // cluster_view_projection, cluster_grid and cluster_depth
+
+// Sun shadows, see shadows.rs
+#define MAX_CASCADES 4
+#pragma uniform_block ShadowCascades // cascade_matrices, cascade_count and cascade_blend
+uniform int shadows_enabled;
+uniform sampler2DArrayShadow shadow_map; // One layer per cascade
+
+// Shadows of one point light, see point_shadows.rs
+uniform int point_shadow_light; // Index of the light, -1 for none
+uniform samplerCubeShadow point_shadow_map; // Distance from the light over its range
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec4 outMotion; // How far it moved on the screen since last frame, see motion.rs
+
+const float PI = 3.14159265359;
+
+// sun_color is 1 at most, scaled up since the BRDF divides the diffuse light by PI
+const float sunStrength = 3.0;
+
+float fogFactor(float distance) {
+    if (fog_mode == 0) {
+        return clamp((distance - fog_start) / (fog_end - fog_start), 0.0, 1.0);
+    } else if (fog_mode == 1) {
+        return 1.0 - exp(-fog_density * distance);
+    } else {
+        float d = fog_density * distance;
+        return 1.0 - exp(-d * d);
+    }
+}
+
+// Normal map without tangents: build the tangent frame from screen space derivatives
+// (Christian Schüler, "Normal Mapping Without Precomputed Tangents")
+vec3 perturbNormal(vec3 normal, vec3 viewVector, vec2 uv) {
+    vec3 dp1 = dFdx(viewVector);
+    vec3 dp2 = dFdy(viewVector);
+    vec2 duv1 = dFdx(uv);
+    vec2 duv2 = dFdy(uv);
+
+    vec3 dp2perp = cross(dp2, normal);
+    vec3 dp1perp = cross(normal, dp1);
+    vec3 tangent = dp2perp * duv1.x + dp1perp * duv2.x;
+    vec3 bitangent = dp2perp * duv1.y + dp1perp * duv2.y;
+    float invmax = inversesqrt(max(dot(tangent, tangent), dot(bitangent, bitangent)));
+    mat3 tbn = mat3(tangent * invmax, bitangent * invmax, normal);
+
+    vec3 mapNormal = texture(normal_map, uv).xyz * 2.0 - 1.0;
+    return normalize(tbn * mapNormal);
+}
+
+// * Cook-Torrance BRDF terms
+// GGX normal distribution
+float distributionGGX(float NdotH, float roughness) {
+    float a = roughness * roughness;
+    float a2 = a * a;
+    float denominator = NdotH * NdotH * (a2 - 1.0) + 1.0;
+    return a2 / (PI * denominator * denominator);
+}
+
+// Smith geometry term with Schlick-GGX
+float geometrySmith(float NdotV, float NdotL, float roughness) {
+    float k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+    float ggxV = NdotV / (NdotV * (1.0 - k) + k);
+    float ggxL = NdotL / (NdotL * (1.0 - k) + k);
+    return ggxV * ggxL;
+}
+
+vec3 fresnelSchlick(float cosTheta, vec3 F0) {
+    return F0 + (1.0 - F0) * pow(clamp(1.0 - cosTheta, 0.0, 1.0), 5.0);
+}
+
+Light fetchLight(int i) {
+    vec4 positionRange = texelFetch(light_data, ivec2(0, i), 0);
+    vec4 directionInner = texelFetch(light_data, ivec2(1, i), 0);
+    vec4 colorOuter = texelFetch(light_data, ivec2(2, i), 0);
+    return Light(positionRange.xyz, directionInner.xyz, colorOuter.rgb, positionRange.w, directionInner.w, colorOuter.w);
+}
+
+// Start and length of the light list of the cluster `position` is in, found the same way as in
+// LightClusters::assign
+uvec2 clusterOf(vec3 position) {
+    vec4 clip = cluster_view_projection * vec4(position, 1.0);
+    ivec2 tile = clamp(ivec2((clip.xy / clip.w * 0.5 + 0.5) * vec2(cluster_grid.xy)), ivec2(0), cluster_grid.xy - 1);
+    float slices = log(max(clip.w, cluster_depth.x) / cluster_depth.x) / log(cluster_depth.y / cluster_depth.x);
+    int slice = min(int(slices * float(cluster_grid.z)), cluster_grid.z - 1);
+    return texelFetch(cluster_lights, ivec2(tile.y * cluster_grid.x + tile.x, slice), 0).xy;
+}
+
+// Index of light number `n` in a cluster's list starting at `start`
+int clusterLight(uint start, uint n) {
+    uint index = start + n;
+    return int(texelFetch(light_indices, ivec2(index % LIGHT_INDEX_WIDTH, index / LIGHT_INDEX_WIDTH), 0).r);
+}
+
+// Light arriving from light i, after distance and cone falloff. Also gives the direction towards it.
+vec3 lightRadiance(int i, vec3 position, out vec3 towardsLight) {
+    Light light = fetchLight(i);
+    vec3 offset = light.position - position;
+    float distance = length(offset);
+    towardsLight = offset / max(distance, 1e-4);
+
+    // Inverse square falloff, windowed so it reaches exactly zero at the range
+    float window = clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+    float attenuation = window * window / (distance * distance + 1.0);
+
+    // Cone, points lights have one that covers everything
+    float spot = smoothstep(light.cos_outer, light.cos_inner, dot(-towardsLight, light.direction));
+
+    return light.color * attenuation * spot;
+}
+
+// How much of the sun reaches `position` in one cascade, averaged over the texels around it so the
+// shadow edges are soft
+float cascadeShadow(int cascade, vec3 position) {
+    vec3 shadowPosition = (cascade_matrices[cascade] * vec4(position, 1.0)).xyz;
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            lit += texture(shadow_map, vec4(shadowPosition.xy + vec2(x, y) * texel, float(cascade), shadowPosition.z));
+        }
+    }
+    return lit / 9.0;
+}
+
+// 1 where the sun reaches the fragment, 0 where something is in the way. Uses the first cascade the
+// fragment is in, and fades over to the next one near its edge.
+float sunShadow(vec3 position, vec3 normal) {
+    if (shadows_enabled == 0) {
+        return 1.0;
+    }
+    position += normal * 0.2; // Off the surface a little, so it doesn't shadow itself
+    for (int i = 0; i < cascade_count; i++) {
+        vec3 shadowPosition = (cascade_matrices[i] * vec4(position, 1.0)).xyz;
+        if (any(lessThan(shadowPosition, vec3(0.0))) || any(greaterThan(shadowPosition, vec3(1.0)))) {
+            continue;
+        }
+        float shadow = cascadeShadow(i, position);
+        float edge = min(min(shadowPosition.x, 1.0 - shadowPosition.x), min(shadowPosition.y, 1.0 - shadowPosition.y));
+        if (edge < cascade_blend && i + 1 < cascade_count) {
+            shadow = mix(cascadeShadow(i + 1, position), shadow, edge / cascade_blend);
+        }
+        return shadow;
+    }
+    return 1.0; // Further away than the shadows reach
+}
+
+// 1 where the point light with the shadow cube map reaches the fragment, 0 where something is in the way
+float pointShadow(vec3 position, vec3 normal) {
+    Light light = fetchLight(point_shadow_light);
+    vec3 fromLight = position + normal * 0.05 - light.position;
+    float distance = length(fromLight) / light.range;
+    return texture(point_shadow_map, vec4(fromLight, distance - 0.002));
+}
+
+// A probe's cube map in `direction`. ES 3.0 only indexes sampler arrays with constants.
+vec3 probeLod(int probe, vec3 direction, float lod) {
+    if (probe == 0) return textureLod(probe_maps[0], direction, lod).rgb;
+    if (probe == 1) return textureLod(probe_maps[1], direction, lod).rgb;
+    if (probe == 2) return textureLod(probe_maps[2], direction, lod).rgb;
+    return textureLod(probe_maps[3], direction, lod).rgb;
+}
+
+// Light coming in to `position` from `direction`, blurred for `roughness`. From the nearest probe the
+// position is inside of, fading into the environment map towards the edge of its sphere.
+vec3 environmentLight(vec3 position, vec3 direction, float roughness) {
+    vec3 sky = textureLod(environment_map, direction, roughness * (environment_mip_levels - 1.0)).rgb * environment_intensity;
+
+    int nearest = -1;
+    float nearestDistance = 0.0;
+    for (int i = 0; i < probe_count; i++) {
+        float distance = length(position - probe_spheres[i].xyz) / probe_spheres[i].w;
+        if (distance < 1.0 && (nearest < 0 || distance < nearestDistance)) {
+            nearest = i;
+            nearestDistance = distance;
+        }
+    }
+    if (nearest < 0) {
+        return sky;
+    }
+    // The probes were captured with the lighting of the scene, it is not dimmed again at night
+    vec3 probe = probeLod(nearest, direction, roughness * (probe_mip_levels - 1.0));
+    return mix(probe, sky, smoothstep(0.7, 1.0, nearestDistance));
+}
+
+// Light reflected towards the viewer from one light coming in from direction L
+vec3 cookTorrance(vec3 N, vec3 V, vec3 L, vec3 radiance, vec3 albedo, float metallic, float roughness, vec3 F0) {
+    vec3 H = normalize(V + L);
+    float NdotL = max(dot(N, L), 0.0);
+    float NdotV = max(dot(N, V), 1e-4);
+    float NdotH = max(dot(N, H), 0.0);
+
+    vec3 F = fresnelSchlick(max(dot(H, V), 0.0), F0);
+    vec3 specular = distributionGGX(NdotH, roughness) * geometrySmith(NdotV, NdotL, roughness) * F
+                  / (4.0 * NdotV * max(NdotL, 1e-4));
+    vec3 diffuse = (1.0 - F) * (1.0 - metallic) * albedo / PI;
+    return (diffuse + specular) * radiance * NdotL;
+}
+
+// Analytic stand-in for the split sum BRDF lookup table (Karis, "Physically Based Shading on Mobile")
+vec3 environmentBRDF(vec3 F0, float roughness, float NdotV) {
+    const vec4 c0 = vec4(-1.0, -0.0275, -0.572, 0.022);
+    const vec4 c1 = vec4(1.0, 0.0425, 1.04, -0.04);
+    vec4 r = roughness * c0 + c1;
+    float a004 = min(r.x * r.x, exp2(-9.28 * NdotV)) * r.x + r.y;
+    vec2 AB = vec2(-1.04, 1.04) * a004 + r.zw;
+    return F0 * AB.x + AB.y;
+}
+
+void main() {
+    // Gather the material
+    vec4 albedo = material_albedo * fragColor;
+    if (has_albedo_map != 0) {
+        albedo *= texture(albedo_map, fragTexCoord);
+    }
+    float metallic = material_metallic;
+    float roughness = material_roughness;
+    if (has_metallic_roughness_map != 0) {
+        vec4 metallicRoughness = texture(metallic_roughness_map, fragTexCoord);
+        roughness *= metallicRoughness.g;
+        metallic *= metallicRoughness.b;
+    }
+    roughness = clamp(roughness, 0.04, 1.0);
+    float ao = material_ao;
+    if (has_occlusion_map != 0) {
+        ao *= texture(occlusion_map, fragTexCoord).r;
+    }
+
+    vec3 viewVector = camera_position - fragWorldPosition;
+    vec3 V = normalize(viewVector);
+    vec3 N = normalize(fragNormal);
+    if (has_normal_map != 0) {
+        N = perturbNormal(N, -viewVector, fragTexCoord);
+    }
+
+    // Dielectrics reflect about 4%, metals reflect their own color
+    vec3 F0 = mix(vec3(0.04), albedo.rgb, metallic);
+
+    float NdotV = max(dot(N, V), 1e-4);
+
+    // Direct light from the sun and the dynamic lights
+    float shadow = sunShadow(fragWorldPosition, normalize(fragNormal));
+    vec3 color = cookTorrance(N, V, -sun_direction, sun_color * sunStrength * shadow, albedo.rgb, metallic, roughness, F0);
+    uvec2 cluster = clusterOf(fragWorldPosition);
+    for (uint n = 0u; n < cluster.y; n++) {
+        int i = clusterLight(cluster.x, n);
+        vec3 towardsLight;
+        vec3 radiance = lightRadiance(i, fragWorldPosition, towardsLight);
+        if (i == point_shadow_light) {
+            radiance *= pointShadow(fragWorldPosition, normalize(fragNormal));
+        }
+        color += cookTorrance(N, V, towardsLight, radiance, albedo.rgb, metallic, roughness, F0);
+    }
+
+    // Ambient light from the surroundings, blurrier mip levels for rougher surfaces
+    vec3 irradiance = environmentLight(fragWorldPosition, N, 1.0);
+    vec3 reflected = environmentLight(fragWorldPosition, reflect(-V, N), roughness);
+    vec3 ambientDiffuse = irradiance * albedo.rgb * (1.0 - metallic);
+    vec3 ambientSpecular = reflected * environmentBRDF(F0, roughness, NdotV);
+    color += (ambientDiffuse + ambientSpecular) * ao;
+
+    // Light given off by the surface itself, usually way brighter than 1 and left to the tone mapping
+    vec3 emissive = material_emissive;
+    if (has_emissive_map != 0) {
+        emissive *= texture(emissive_map, fragTexCoord).rgb;
+    }
+    color += emissive;
+
+    // Fade into the fog with distance from the camera
+    if (fog_enabled != 0) {
+        color = mix(color, fog_color, fogFactor(length(viewVector)));
+    }
+
+    outColor = vec4(color, albedo.a);
+    vec4 clip = motion_view_projection * vec4(fragWorldPosition, 1.0);
+    vec4 previousClip = previous_view_projection * vec4(fragPreviousWorldPosition, 1.0);
+    outMotion = vec4((clip.xy / clip.w - previousClip.xy / previousClip.w) * 0.5, 1.0, 1.0);
+}
@@ -0,0 +1,9 @@
+#version 430 core
+
+uniform uint node_id; // 0 is left for "nothing here"
+
+out uint outId;
+
+void main() {
+    outId = node_id;
+}
@@ -0,0 +1,13 @@
+#version 430 core
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 1) in vec4 color;
+
+uniform mat4 mvp_matrix;
+
+out vec4 lineColor;
+
+void main() {
+    lineColor = color;
+    gl_Position = mvp_matrix * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,11 @@
+#version 430 core
+
+in vec3 fragWorldPosition;
+
+uniform vec3 light_position;
+uniform float light_range;
+
+void main() {
+    // The distance from the light instead of the depth, so the lookup doesn't depend on the face
+    gl_FragDepth = clamp(length(fragWorldPosition - light_position) / light_range, 0.0, 1.0);
+}
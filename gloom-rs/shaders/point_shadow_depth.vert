@@ -0,0 +1,19 @@
+#version 430 core
+
+// Draws the shadow casters into one face of a point light's cube map, see point_shadows.rs
+
+layout(location = 0) in vec3 inPosition;
+
+// Streamed for every draw, see TransformStream in ring_buffer.rs. The MVP matrix is the cube face's.
+layout(std140) uniform DrawTransforms {
+    mat4 mvp_matrix;
+    mat4 model_matrix;
+    mat4 previous_model_matrix;
+};
+
+out vec3 fragWorldPosition;
+
+void main() {
+    fragWorldPosition = (model_matrix * vec4(inPosition, 1.0)).xyz;
+    gl_Position = mvp_matrix * vec4(inPosition, 1.0);
+}
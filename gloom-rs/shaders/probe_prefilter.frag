@@ -0,0 +1,86 @@
+#version 430 core
+
+// Blurs a captured cube map for a roughness, one face of one mip level of a reflection probe at a
+// time, see probes.rs. Light from around the direction of the texel is averaged the way the GGX
+// distribution in pbr.frag spreads it, looking at it straight on (the usual split sum simplification).
+
+in vec2 fragTexCoord;
+
+uniform samplerCube source_map; // The capture, mipmapped
+uniform float source_resolution;
+uniform int face;               // Of the cube map, in the order GL numbers them
+uniform float roughness;
+
+out vec4 outColor;
+
+const float PI = 3.14159265359;
+const uint SAMPLE_COUNT = 64u;
+
+// Direction through a point (u, v) on a cube map face, same as face_direction in environment.rs
+vec3 faceDirection(int face, vec2 uv) {
+    if (face == 0) return vec3( 1.0, -uv.y, -uv.x);
+    if (face == 1) return vec3(-1.0, -uv.y,  uv.x);
+    if (face == 2) return vec3( uv.x,  1.0,  uv.y);
+    if (face == 3) return vec3( uv.x, -1.0, -uv.y);
+    if (face == 4) return vec3( uv.x, -uv.y,  1.0);
+    return vec3(-uv.x, -uv.y, -1.0);
+}
+
+// Evenly spread points in 0..1 squared, the bits of i mirrored for the second coordinate
+vec2 hammersley(uint i) {
+    uint bits = i;
+    bits = (bits << 16u) | (bits >> 16u);
+    bits = ((bits & 0x55555555u) << 1u) | ((bits & 0xAAAAAAAAu) >> 1u);
+    bits = ((bits & 0x33333333u) << 2u) | ((bits & 0xCCCCCCCCu) >> 2u);
+    bits = ((bits & 0x0F0F0F0Fu) << 4u) | ((bits & 0xF0F0F0F0u) >> 4u);
+    bits = ((bits & 0x00FF00FFu) << 8u) | ((bits & 0xFF00FF00u) >> 8u);
+    return vec2(float(i) / float(SAMPLE_COUNT), float(bits) * 2.3283064365386963e-10);
+}
+
+// A half vector around N, picked more often where the GGX distribution is larger
+vec3 sampleGGX(vec2 xi, vec3 N, float alpha) {
+    float phi = 2.0 * PI * xi.x;
+    float cosTheta = sqrt((1.0 - xi.y) / (1.0 + (alpha * alpha - 1.0) * xi.y));
+    float sinTheta = sqrt(1.0 - cosTheta * cosTheta);
+    vec3 H = vec3(cos(phi) * sinTheta, sin(phi) * sinTheta, cosTheta);
+
+    vec3 up = abs(N.z) < 0.999 ? vec3(0.0, 0.0, 1.0) : vec3(1.0, 0.0, 0.0);
+    vec3 tangent = normalize(cross(up, N));
+    vec3 bitangent = cross(N, tangent);
+    return normalize(tangent * H.x + bitangent * H.y + N * H.z);
+}
+
+void main() {
+    vec3 N = normalize(faceDirection(face, fragTexCoord * 2.0 - 1.0));
+
+    // The sharpest level is the capture itself
+    if (roughness == 0.0) {
+        outColor = vec4(textureLod(source_map, N, 0.0).rgb, 1.0);
+        return;
+    }
+
+    float alpha = roughness * roughness; // Same remapping as pbr.frag
+    float texelSolidAngle = 4.0 * PI / (6.0 * source_resolution * source_resolution);
+    vec3 color = vec3(0.0);
+    float weight = 0.0;
+    for (uint i = 0u; i < SAMPLE_COUNT; i++) {
+        vec3 H = sampleGGX(hammersley(i), N, alpha);
+        vec3 L = reflect(-N, H);
+        float NdotL = dot(N, L);
+        if (NdotL <= 0.0) {
+            continue;
+        }
+
+        // Read from a blurrier level where the samples are further apart, so bright spots between
+        // them don't turn into speckles
+        float NdotH = max(dot(N, H), 0.0);
+        float d = NdotH * NdotH * (alpha * alpha - 1.0) + 1.0;
+        float pdf = alpha * alpha / (PI * d * d) / 4.0; // D * NdotH / (4 * HdotV), with V = N
+        float sampleSolidAngle = 1.0 / (float(SAMPLE_COUNT) * pdf + 1e-4);
+        float lod = max(0.5 * log2(sampleSolidAngle / texelSolidAngle), 0.0);
+
+        color += textureLod(source_map, L, lod).rgb * NdotL;
+        weight += NdotL;
+    }
+    outColor = vec4(color / max(weight, 1e-4), 1.0);
+}
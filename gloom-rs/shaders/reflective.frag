@@ -0,0 +1,78 @@
+#version 430 core
+
+// Flat reflective surface facing +Y, see reflection.rs
+
+in vec3 fragWorldPosition;
+
+uniform vec3 camera_position;
+uniform vec3 tint;
+uniform vec4 view_rect;       // Region of the view in pixels, (x, y, width, height)
+uniform vec2 texture_size;    // Size of the reflection (and depth) texture in pixels
+uniform int reflection_kind;  // 0 planar, 1 screen space, 2 none
+uniform mat4 view_projection; // For screen space reflections
+uniform int reversed_z;       // Non-zero if the depth buffer is reversed
+
+uniform sampler2D reflection_texture; // Mirrored scene (planar) or the scene itself (screen space)
+uniform sampler2D depth_texture;      // Depth of the scene, screen space only
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec4 outMotion; // Not written, the motion comes from the camera alone (see motion.rs)
+
+// Screen space reflections: march along the reflected ray in world space and stop where it goes
+// behind something in the depth buffer
+bool traceScreenSpace(vec3 origin, vec3 direction, out vec2 hitUV) {
+    const int steps = 48;
+    float stepLength = 4.0;
+    vec3 position = origin;
+    for (int i = 0; i < steps; i++) {
+        position += direction * stepLength;
+        stepLength *= 1.06; // Longer steps further out, where precision matters less
+
+        vec4 clip = view_projection * vec4(position, 1.0);
+        if (clip.w <= 0.0) {
+            return false;
+        }
+        vec3 ndc = clip.xyz / clip.w;
+        vec2 viewUV = ndc.xy * 0.5 + 0.5;
+        if (any(lessThan(viewUV, vec2(0.0))) || any(greaterThan(viewUV, vec2(1.0)))) {
+            return false; // Left the view, nothing to reflect
+        }
+
+        vec2 pixelUV = (view_rect.xy + viewUV * view_rect.zw) / texture_size;
+        float sceneDepth = texture(depth_texture, pixelUV).r;
+        bool behind = reversed_z != 0 ? ndc.z < sceneDepth && sceneDepth > 0.0 : ndc.z * 0.5 + 0.5 > sceneDepth && sceneDepth < 1.0;
+        if (behind) {
+            hitUV = pixelUV;
+            return true;
+        }
+    }
+    return false;
+}
+
+void main() {
+    vec3 viewDirection = normalize(fragWorldPosition - camera_position);
+    vec3 normal = vec3(0.0, 1.0, 0.0);
+
+    // Schlick's Fresnel with the reflectance of ice (about 2% head on)
+    float cosTheta = clamp(dot(-viewDirection, normal), 0.0, 1.0);
+    float fresnel = 0.02 + 0.98 * pow(1.0 - cosTheta, 5.0);
+    fresnel = max(fresnel, 0.25); // Polished ice, keep some reflection even looking straight down
+
+    vec3 reflection;
+    if (reflection_kind == 1) {
+        vec2 hitUV;
+        if (traceScreenSpace(fragWorldPosition, reflect(viewDirection, normal), hitUV)) {
+            reflection = texture(reflection_texture, hitUV).rgb;
+        } else {
+            reflection = tint * 0.3; // Missed everything on screen
+        }
+    } else if (reflection_kind == 2) {
+        reflection = tint * 0.3;
+    } else {
+        // The mirrored scene was drawn with the same view projection, so it lines up pixel for pixel
+        reflection = texture(reflection_texture, gl_FragCoord.xy / texture_size).rgb;
+    }
+
+    outColor = vec4(mix(tint * 0.5, reflection, fresnel), 0.9);
+    outMotion = vec4(0.0, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,13 @@
+#version 430 core
+
+layout(location = 0) in vec3 inPosition;
+
+uniform mat4 mvp_matrix;
+uniform mat4 model_matrix;
+
+out vec3 fragWorldPosition;
+
+void main() {
+    fragWorldPosition = (model_matrix * vec4(inPosition, 1.0)).xyz;
+    gl_Position = mvp_matrix * vec4(inPosition, 1.0);
+}
@@ -0,0 +1,16 @@
+#version 430 core
+
+// Draws the shadow casters from the sun into a shadow map, see shadows.rs. Only the depth is kept.
+
+layout(location = 0) in vec3 inPosition;
+
+// Streamed for every draw, see TransformStream in ring_buffer.rs. The MVP matrix is the sun's.
+layout(std140) uniform DrawTransforms {
+    mat4 mvp_matrix;
+    mat4 model_matrix;
+    mat4 previous_model_matrix;
+};
+
+void main() {
+    gl_Position = mvp_matrix * vec4(inPosition, 1.0);
+}
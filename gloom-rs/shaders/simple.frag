@@ -2,18 +2,181 @@
 
 in vec4 fragColor;  // Color passed from the vertex shader
 in vec3 fragNormal; // Normal passed from the vertex shader
+in vec3 fragWorldPosition; // World space position passed from the vertex shader
+in vec3 fragPreviousWorldPosition; // Where it was last frame
 
-out vec4 outColor;  // Final color to be written to the screen
+// Motion vectors, see motion.rs. Without the TAA jitter.
+uniform mat4 motion_view_projection;
+uniform mat4 previous_view_projection;
+
+// Sun and sky, see time_of_day.rs
+uniform vec3 sun_direction; // Direction the light travels in
+uniform vec3 sun_color;
+uniform vec3 ambient_color;
+
+// Distance fog, see FogSettings in settings.rs
+uniform vec3 camera_position;
+uniform int fog_enabled;
+uniform int fog_mode; // 0 linear, 1 exponential, 2 exponential squared
+uniform vec3 fog_color;
+uniform float fog_start;
+uniform float fog_end;
+uniform float fog_density;
+
+// Dynamic point and spot lights, sorted into clusters of the view, see lights.rs and clusters.rs
+#define MAX_LIGHTS 256
+#define LIGHT_INDEX_WIDTH 1024u
+struct Light {
+    vec3 position;
+    vec3 direction; // Spot lights only
+    vec3 color;     // Already multiplied by the intensity
+    float range;
+    float cos_inner;
+    float cos_outer;
+};
+uniform sampler2D light_data;      // Three texels per light, one row each
+uniform usampler2D cluster_lights; // Start and length of every cluster's list in light_indices
+uniform usampler2D light_indices;  // The lists one after the other, LIGHT_INDEX_WIDTH to a row
+#pragma uniform_block LightClusters // cluster_view_projection, cluster_grid and cluster_depth
+
+// Sun shadows, see shadows.rs
+#define MAX_CASCADES 4
+#pragma uniform_block ShadowCascades // cascade_matrices, cascade_count and cascade_blend
+uniform int shadows_enabled;
+uniform sampler2DArrayShadow shadow_map; // One layer per cascade
+
+// Shadows of one point light, see point_shadows.rs
+uniform int point_shadow_light; // Index of the light, -1 for none
+uniform samplerCubeShadow point_shadow_map; // Distance from the light over its range
+
+layout(location = 0) out vec4 outColor;  // Final color to be written to the screen
+layout(location = 1) out vec4 outMotion; // How far it moved on the screen since last frame, see motion.rs
+
+// How much of the fog color to use, 0 is no fog and 1 is only fog
+float fogFactor(float distance) {
+    if (fog_mode == 0) {
+        return clamp((distance - fog_start) / (fog_end - fog_start), 0.0, 1.0);
+    } else if (fog_mode == 1) {
+        return 1.0 - exp(-fog_density * distance);
+    } else {
+        float d = fog_density * distance;
+        return 1.0 - exp(-d * d);
+    }
+}
+
+Light fetchLight(int i) {
+    vec4 positionRange = texelFetch(light_data, ivec2(0, i), 0);
+    vec4 directionInner = texelFetch(light_data, ivec2(1, i), 0);
+    vec4 colorOuter = texelFetch(light_data, ivec2(2, i), 0);
+    return Light(positionRange.xyz, directionInner.xyz, colorOuter.rgb, positionRange.w, directionInner.w, colorOuter.w);
+}
+
+// Start and length of the light list of the cluster `position` is in, found the same way as in
+// LightClusters::assign
+uvec2 clusterOf(vec3 position) {
+    vec4 clip = cluster_view_projection * vec4(position, 1.0);
+    ivec2 tile = clamp(ivec2((clip.xy / clip.w * 0.5 + 0.5) * vec2(cluster_grid.xy)), ivec2(0), cluster_grid.xy - 1);
+    float slices = log(max(clip.w, cluster_depth.x) / cluster_depth.x) / log(cluster_depth.y / cluster_depth.x);
+    int slice = min(int(slices * float(cluster_grid.z)), cluster_grid.z - 1);
+    return texelFetch(cluster_lights, ivec2(tile.y * cluster_grid.x + tile.x, slice), 0).xy;
+}
+
+// Index of light number `n` in a cluster's list starting at `start`
+int clusterLight(uint start, uint n) {
+    uint index = start + n;
+    return int(texelFetch(light_indices, ivec2(index % LIGHT_INDEX_WIDTH, index / LIGHT_INDEX_WIDTH), 0).r);
+}
+
+// Light arriving from light i, after distance and cone falloff. Also gives the direction towards it.
+vec3 lightRadiance(int i, vec3 position, out vec3 towardsLight) {
+    Light light = fetchLight(i);
+    vec3 offset = light.position - position;
+    float distance = length(offset);
+    towardsLight = offset / max(distance, 1e-4);
+
+    // Inverse square falloff, windowed so it reaches exactly zero at the range
+    float window = clamp(1.0 - pow(distance / light.range, 4.0), 0.0, 1.0);
+    float attenuation = window * window / (distance * distance + 1.0);
+
+    // Cone, points lights have one that covers everything
+    float spot = smoothstep(light.cos_outer, light.cos_inner, dot(-towardsLight, light.direction));
+
+    return light.color * attenuation * spot;
+}
+
+// How much of the sun reaches `position` in one cascade, averaged over the texels around it so the
+// shadow edges are soft
+float cascadeShadow(int cascade, vec3 position) {
+    vec3 shadowPosition = (cascade_matrices[cascade] * vec4(position, 1.0)).xyz;
+    vec2 texel = 1.0 / vec2(textureSize(shadow_map, 0).xy);
+    float lit = 0.0;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            lit += texture(shadow_map, vec4(shadowPosition.xy + vec2(x, y) * texel, float(cascade), shadowPosition.z));
+        }
+    }
+    return lit / 9.0;
+}
+
+// 1 where the sun reaches the fragment, 0 where something is in the way. Uses the first cascade the
+// fragment is in, and fades over to the next one near its edge.
+float sunShadow(vec3 position, vec3 normal) {
+    if (shadows_enabled == 0) {
+        return 1.0;
+    }
+    position += normal * 0.2; // Off the surface a little, so it doesn't shadow itself
+    for (int i = 0; i < cascade_count; i++) {
+        vec3 shadowPosition = (cascade_matrices[i] * vec4(position, 1.0)).xyz;
+        if (any(lessThan(shadowPosition, vec3(0.0))) || any(greaterThan(shadowPosition, vec3(1.0)))) {
+            continue;
+        }
+        float shadow = cascadeShadow(i, position);
+        float edge = min(min(shadowPosition.x, 1.0 - shadowPosition.x), min(shadowPosition.y, 1.0 - shadowPosition.y));
+        if (edge < cascade_blend && i + 1 < cascade_count) {
+            shadow = mix(cascadeShadow(i + 1, position), shadow, edge / cascade_blend);
+        }
+        return shadow;
+    }
+    return 1.0; // Further away than the shadows reach
+}
+
+// 1 where the point light with the shadow cube map reaches the fragment, 0 where something is in the way
+float pointShadow(vec3 position, vec3 normal) {
+    Light light = fetchLight(point_shadow_light);
+    vec3 fromLight = position + normal * 0.05 - light.position;
+    float distance = length(fromLight) / light.range;
+    return texture(point_shadow_map, vec4(fromLight, distance - 0.002));
+}
 
 void main() {
-    // The defined light direction
-    vec3 lightDirection = normalize(vec3(0.8, -0.5, 0.6));
-    
     // Normalize the fragment normal vector
     vec3 normal = fragNormal;
     
     // Light intensity using Lambertian model
-    float lightIntensity = max(dot(normal, -lightDirection), 0.0);
+    float lightIntensity = max(dot(normal, -sun_direction), 0.0);
+
+    vec3 color = fragColor.rgb * (sun_color * lightIntensity * sunShadow(fragWorldPosition, normal) + ambient_color);
+
+    // Add the dynamic lights of this fragment's cluster, same Lambertian model
+    uvec2 cluster = clusterOf(fragWorldPosition);
+    for (uint n = 0u; n < cluster.y; n++) {
+        int i = clusterLight(cluster.x, n);
+        vec3 towardsLight;
+        vec3 radiance = lightRadiance(i, fragWorldPosition, towardsLight);
+        if (i == point_shadow_light) {
+            radiance *= pointShadow(fragWorldPosition, normal);
+        }
+        color += fragColor.rgb * radiance * max(dot(normal, towardsLight), 0.0);
+    }
+
+    // Fade into the fog with distance from the camera
+    if (fog_enabled != 0) {
+        float distance = length(fragWorldPosition - camera_position);
+        color = mix(color, fog_color, fogFactor(distance));
+    }
     
-    outColor = vec4(fragColor.rgb * lightIntensity, 1.0);
+    outColor = vec4(color, fragColor.a); // Only the rotor discs aren't opaque
+    vec4 clip = motion_view_projection * vec4(fragWorldPosition, 1.0);
+    vec4 previousClip = previous_view_projection * vec4(fragPreviousWorldPosition, 1.0);
+    outMotion = vec4((clip.xy / clip.w - previousClip.xy / previousClip.w) * 0.5, 1.0, 1.0);
 }
@@ -4,11 +4,18 @@ layout(location = 0) in vec3 inPosition;
 layout(location = 1) in vec4 color;
 layout(location = 2) in vec3 inNormal;
 
-uniform mat4 mvp_matrix; // MVP matrix
-uniform mat4 model_matrix; // Model matrix (used for normals)     
+// Streamed for every draw, see TransformStream in ring_buffer.rs
+layout(std140) uniform DrawTransforms {
+    mat4 mvp_matrix; // MVP matrix
+    mat4 model_matrix; // Model matrix (used for normals)
+    mat4 previous_model_matrix; // Where the model was last frame, for the motion vectors (see motion.rs)
+};
+uniform vec4 clip_plane;   // Only used while GL_CLIP_DISTANCE0 is on, for reflections
 
 out vec4 fragColor;
 out vec3 fragNormal;
+out vec3 fragPreviousWorldPosition;
+out vec3 fragWorldPosition; // Used for the fog distance
 
 void main() {
     fragColor = color;
@@ -20,5 +27,10 @@ void main() {
     fragNormal = normalize(normal_matrix * inNormal);
     
     vec4 vertex_pre_tf = vec4(inPosition, 1.0);
+    fragWorldPosition = (model_matrix * vertex_pre_tf).xyz;
+    fragPreviousWorldPosition = (previous_model_matrix * vertex_pre_tf).xyz;
+#ifndef GL_ES
+    gl_ClipDistance[0] = dot(vec4(fragWorldPosition, 1.0), clip_plane); // Not in ES 3.0, see gl_caps.rs
+#endif
     gl_Position = mvp_matrix * vertex_pre_tf;
 }
@@ -0,0 +1,13 @@
+#version 430 core
+
+in vec2 fragTexCoord;
+in vec4 fragColor;
+
+uniform sampler2D sprite_atlas; // The sprites are white where they take the color, glyph outlines black
+
+out vec4 outColor;
+
+void main() {
+    vec4 sprite = texture(sprite_atlas, fragTexCoord);
+    outColor = vec4(sprite.rgb * fragColor.rgb, sprite.a * fragColor.a);
+}
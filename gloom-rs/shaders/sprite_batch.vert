@@ -0,0 +1,18 @@
+#version 430 core
+
+// The quads of a sprite batch, glyphs and HUD sprites, already placed on the view (see text.rs)
+
+layout(location = 0) in vec2 inPosition; // In pixels from the lower left corner of the view
+layout(location = 1) in vec2 inTexCoord; // In the overlay atlas
+layout(location = 2) in vec4 inColor;
+
+uniform vec2 view_size; // In pixels
+
+out vec2 fragTexCoord;
+out vec4 fragColor;
+
+void main() {
+    fragTexCoord = inTexCoord;
+    fragColor = inColor;
+    gl_Position = vec4(inPosition / view_size * 2.0 - 1.0, 0.0, 1.0);
+}
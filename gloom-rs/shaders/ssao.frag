@@ -0,0 +1,65 @@
+#version 430 core
+
+// Screen space ambient occlusion for one view, run as a fullscreen pass over its region.
+// Positions are rebuilt from the depth buffer, normals from how those positions change across pixels.
+
+uniform sampler2D depth_texture;
+uniform sampler2D noise_texture;  // 4x4 random rotations, tiled over the screen
+uniform mat4 projection;
+uniform mat4 inverse_projection;
+uniform vec4 view_rect;           // Region of the view in pixels, (x, y, width, height)
+uniform vec2 texture_size;        // Size of the depth texture in pixels
+uniform int reversed_z;           // Non-zero if the depth buffer is reversed
+
+#define KERNEL_SIZE 16
+uniform vec3 kernel[KERNEL_SIZE]; // Sample offsets in a unit hemisphere around +Z
+uniform float radius;             // In world units
+uniform float bias;
+uniform float strength;
+
+out vec4 outColor;
+
+// View space position at a point of the view, (0, 0) is its bottom left corner and (1, 1) its top right
+vec3 viewPosition(vec2 viewUV) {
+    vec2 pixel = view_rect.xy + clamp(viewUV, 0.0, 1.0) * view_rect.zw;
+    float depth = texture(depth_texture, pixel / texture_size).r;
+    float ndcDepth = reversed_z != 0 ? depth : depth * 2.0 - 1.0;
+    vec4 position = inverse_projection * vec4(viewUV * 2.0 - 1.0, ndcDepth, 1.0);
+    return position.xyz / position.w;
+}
+
+void main() {
+    vec2 viewUV = (gl_FragCoord.xy - view_rect.xy) / view_rect.zw;
+
+    // Nothing to occlude in the sky
+    float depth = texture(depth_texture, gl_FragCoord.xy / texture_size).r;
+    if (reversed_z != 0 ? depth <= 0.0 : depth >= 1.0) {
+        outColor = vec4(1.0);
+        return;
+    }
+
+    vec3 position = viewPosition(viewUV);
+    vec3 normal = normalize(cross(dFdx(position), dFdy(position)));
+
+    // Rotate the kernel randomly per pixel, the blur pass smooths out the resulting noise
+    vec3 randomVector = vec3(texture(noise_texture, gl_FragCoord.xy / 4.0).xy, 0.0);
+    vec3 tangent = normalize(randomVector - normal * dot(randomVector, normal));
+    vec3 bitangent = cross(normal, tangent);
+    mat3 tbn = mat3(tangent, bitangent, normal);
+
+    float occlusion = 0.0;
+    for (int i = 0; i < KERNEL_SIZE; i++) {
+        vec3 samplePosition = position + tbn * kernel[i] * radius;
+
+        vec4 sampleClip = projection * vec4(samplePosition, 1.0);
+        vec2 sampleUV = sampleClip.xy / sampleClip.w * 0.5 + 0.5;
+        float sceneDepth = viewPosition(sampleUV).z;
+
+        // Only count geometry close by, not something far in front of it
+        float rangeCheck = smoothstep(0.0, 1.0, radius / abs(position.z - sceneDepth));
+        occlusion += (sceneDepth >= samplePosition.z + bias ? 1.0 : 0.0) * rangeCheck;
+    }
+
+    float ao = 1.0 - strength * occlusion / float(KERNEL_SIZE);
+    outColor = vec4(vec3(clamp(ao, 0.0, 1.0)), 1.0);
+}
@@ -0,0 +1,18 @@
+#version 430 core
+
+// 4x4 box blur, the same size as the noise texture, so the noise pattern averages out
+
+uniform sampler2D ao_texture;
+uniform vec2 texture_size;
+
+out vec4 outColor;
+
+void main() {
+    float sum = 0.0;
+    for (int y = -2; y < 2; y++) {
+        for (int x = -2; x < 2; x++) {
+            sum += texture(ao_texture, (gl_FragCoord.xy + vec2(x, y)) / texture_size).r;
+        }
+    }
+    outColor = vec4(vec3(sum / 16.0), 1.0);
+}
@@ -0,0 +1,11 @@
+#version 430 core
+
+in vec3 fragColor;
+
+out vec4 outColor;
+
+void main() {
+    // Round and soft at the edge, added to the sky
+    float falloff = max(1.0 - length(gl_PointCoord * 2.0 - 1.0), 0.0);
+    outColor = vec4(fragColor * falloff * falloff, 1.0);
+}
@@ -0,0 +1,26 @@
+#version 430 core
+
+// One star as a point sprite, infinitely far away in its direction (see celestial.rs)
+
+layout(location = 0) in vec4 star;      // Direction on the celestial sphere, brightness
+layout(location = 1) in vec4 starColor; // Color, twinkle phase from 0 to 1
+
+uniform mat4 view_projection; // Of the camera at the origin
+uniform mat4 sky_rotation;    // How far the sky has turned with the time of day
+uniform float time;
+uniform float twinkle;
+uniform float visibility;
+
+out vec3 fragColor;
+
+void main() {
+    vec3 direction = (sky_rotation * vec4(star.xyz, 0.0)).xyz;
+    gl_Position = view_projection * vec4(direction, 0.0);
+    gl_Position.z = 0.0; // Nothing to test against, in the clip volume either way round
+
+    float phase = starColor.a * 6.2832;
+    float flicker = 1.0 + twinkle * sin(time * (2.0 + 3.0 * starColor.a) + phase) * sin(time * 1.3 + phase * 2.0);
+    float brightness = star.w * flicker * visibility;
+    fragColor = starColor.rgb * brightness;
+    gl_PointSize = 1.5 + 2.0 * min(star.w, 1.5);
+}
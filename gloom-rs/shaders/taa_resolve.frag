@@ -0,0 +1,70 @@
+#version 430 core
+
+// Blends the new frame into the reprojected history, for one view (see taa.rs)
+
+in vec2 fragTexCoord; // Inside the view
+
+uniform sampler2D scene_texture;   // This frame, jittered
+uniform sampler2D depth_texture;
+uniform sampler2D motion_texture;  // How far the pixel moved on the screen since last frame, see motion.rs
+uniform sampler2D history_texture; // Every frame before, blended together
+uniform int history_valid;
+uniform float blend;               // How much of the new frame goes in
+uniform mat4 inverse_view_projection;  // This frame's, with the jitter the depth was drawn with
+uniform mat4 previous_view_projection; // Last frame's, without the jitter, for where there is no motion
+uniform int reversed_z;                // Non-zero if the depth buffer is reversed
+uniform vec4 view_pixels; // The view in the textures, (x, y, width, height) in pixels
+uniform vec4 view_rect;   // The same, as a fraction of the textures
+
+out vec4 outColor;
+
+float luminance(vec3 color) {
+    return dot(color, vec3(0.2126, 0.7152, 0.0722));
+}
+
+void main() {
+    ivec2 pixel = ivec2(gl_FragCoord.xy);
+    ivec2 view_min = ivec2(view_pixels.xy);
+    ivec2 view_max = ivec2(view_pixels.xy + view_pixels.zw) - 1;
+    vec3 current = texelFetch(scene_texture, pixel, 0).rgb;
+
+    // The colors around the pixel in the new frame, the history has to stay between them
+    vec3 low = current;
+    vec3 high = current;
+    for (int y = -1; y <= 1; y++) {
+        for (int x = -1; x <= 1; x++) {
+            vec3 neighbor = texelFetch(scene_texture, clamp(pixel + ivec2(x, y), view_min, view_max), 0).rgb;
+            low = min(low, neighbor);
+            high = max(high, neighbor);
+        }
+    }
+
+    if (history_valid == 0) {
+        outColor = vec4(current, 1.0);
+        return;
+    }
+
+    // Where this pixel was on the screen last frame. Where the scene shaders didn't write the motion,
+    // from where the camera was: the pixel's world position from the depth, seen from there.
+    vec4 motion = texelFetch(motion_texture, pixel, 0);
+    vec2 previous_coord = fragTexCoord - motion.rg;
+    if (motion.b == 0.0) {
+        float depth = texelFetch(depth_texture, pixel, 0).r;
+        float ndcDepth = reversed_z != 0 ? depth : depth * 2.0 - 1.0;
+        vec4 world = inverse_view_projection * vec4(fragTexCoord * 2.0 - 1.0, ndcDepth, 1.0);
+        vec4 previous_clip = previous_view_projection * world;
+        previous_coord = previous_clip.w > 0.0 ? previous_clip.xy / previous_clip.w * 0.5 + 0.5 : vec2(-1.0);
+    }
+    if (any(lessThan(previous_coord, vec2(0.0))) || any(greaterThan(previous_coord, vec2(1.0)))) {
+        outColor = vec4(current, 1.0); // Wasn't on screen
+        return;
+    }
+
+    vec3 history = texture(history_texture, view_rect.xy + previous_coord * view_rect.zw).rgb;
+    history = clamp(history, low, high);
+
+    // Weighted by how bright they are, so a single very bright sample doesn't flicker through
+    float current_weight = blend / (1.0 + luminance(current));
+    float history_weight = (1.0 - blend) / (1.0 + luminance(history));
+    outColor = vec4((current * current_weight + history * history_weight) / (current_weight + history_weight), 1.0);
+}
@@ -0,0 +1,11 @@
+#version 430 core
+
+// The corners of one terrain patch, in world space on the y = 0 plane
+
+layout(location = 0) in vec3 inPosition;
+
+out vec3 controlPosition;
+
+void main() {
+    controlPosition = inPosition;
+}
@@ -0,0 +1,18 @@
+#version 430 core
+
+in vec2 fragTexCoord; // Texture coordinate passed from the vertex shader
+
+uniform sampler2D screen_texture; // What to show on the quad
+
+layout(location = 0) out vec4 outColor;  // Final color to be written to the screen
+layout(location = 1) out vec4 outMotion; // Not written, the motion comes from the camera alone (see motion.rs)
+
+void main() {
+    vec3 color = texture(screen_texture, fragTexCoord).rgb;
+
+    // Faint scanlines so it reads as a monitor and not a hole in the world
+    float scanline = 0.9 + 0.1 * sin(fragTexCoord.y * 400.0);
+
+    outColor = vec4(color * scanline, 1.0);
+    outMotion = vec4(0.0, 0.0, 0.0, 1.0);
+}
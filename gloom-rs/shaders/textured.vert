@@ -0,0 +1,13 @@
+#version 430 core
+
+layout(location = 0) in vec3 inPosition;
+layout(location = 3) in vec2 inTexCoord;
+
+uniform mat4 mvp_matrix; // MVP matrix
+
+out vec2 fragTexCoord;
+
+void main() {
+    fragTexCoord = inTexCoord;
+    gl_Position = mvp_matrix * vec4(inPosition, 1.0);
+}
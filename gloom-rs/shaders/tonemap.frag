@@ -0,0 +1,52 @@
+#version 430 core
+
+in vec2 fragTexCoord;
+
+uniform sampler2D hdr_texture; // The scene, in linear HDR
+uniform float exposure;
+uniform sampler2D exposure_texture; // 1x1, the auto exposure in G (see auto_exposure.rs)
+uniform int auto_exposure;
+uniform sampler2D ao_texture;  // Ambient occlusion, 1 is not occluded
+uniform int ao_enabled;
+uniform int encode_srgb;       // The window doesn't encode to sRGB itself, see color.rs
+uniform sampler3D lut_texture; // Color grading, display colors in and out (see color_grading.rs)
+uniform int lut_enabled;
+uniform float lut_intensity;
+uniform float lut_size;
+
+out vec4 outColor;
+
+// The exact sRGB curve, both ways
+vec3 encodeSrgb(vec3 linear) {
+    return mix(linear * 12.92, 1.055 * pow(linear, vec3(1.0 / 2.4)) - 0.055, step(0.0031308, linear));
+}
+
+vec3 decodeSrgb(vec3 srgb) {
+    return mix(srgb / 12.92, pow((srgb + 0.055) / 1.055, vec3(2.4)), step(0.04045, srgb));
+}
+
+void main() {
+    vec3 hdr = texture(hdr_texture, fragTexCoord).rgb;
+    if (ao_enabled != 0) {
+        hdr *= texture(ao_texture, fragTexCoord).r;
+    }
+
+    // Exposure tone mapping, brightness rolls off smoothly instead of clipping at 1
+    float e = auto_exposure != 0 ? texelFetch(exposure_texture, ivec2(0), 0).g : exposure;
+    vec3 mapped = vec3(1.0) - exp(-hdr * e);
+
+    // The grading tables work on what the monitor shows, so it is done in sRGB. Looked up between the
+    // centers of the first and last texels, that is where 0 and 1 are in the table.
+    if (lut_enabled != 0) {
+        vec3 display = encodeSrgb(clamp(mapped, 0.0, 1.0));
+        vec3 graded = texture(lut_texture, display * (lut_size - 1.0) / lut_size + 0.5 / lut_size).rgb;
+        mapped = decodeSrgb(mix(display, graded, lut_intensity));
+    }
+
+    // From linear light to what the monitor expects
+    if (encode_srgb != 0) {
+        mapped = encodeSrgb(mapped);
+    }
+
+    outColor = vec4(mapped, 1.0);
+}
@@ -0,0 +1,12 @@
+#version 430 core
+
+in vec4 fragColor;
+in float across;
+
+out vec4 outColor;
+
+void main() {
+    // Soft at the edges, like smoke or a vortex rather than a ribbon
+    float edge = 1.0 - across * across;
+    outColor = vec4(fragColor.rgb, fragColor.a * edge);
+}
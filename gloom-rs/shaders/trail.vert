@@ -0,0 +1,25 @@
+#version 430 core
+
+// One side of a point along a trail, pushed out sideways to face the camera (see trails.rs)
+
+layout(location = 0) in vec4 point;      // World position, -1 or 1 for the side
+layout(location = 1) in vec4 tangent;    // Along the trail, width
+layout(location = 2) in vec4 pointColor; // Faded with the age of the point
+
+uniform mat4 view_projection;
+uniform vec3 camera_position;
+
+out vec4 fragColor;
+out float across; // -1 to 1 from one edge to the other
+
+void main() {
+    // Across the trail and across the line of sight, so it is seen flat on from anywhere but straight along it
+    vec3 side = cross(tangent.xyz, camera_position - point.xyz);
+    float side_length = length(side);
+    side = side_length > 1e-5 ? side / side_length : vec3(0.0);
+    vec3 position = point.xyz + side * point.w * tangent.w * 0.5;
+
+    gl_Position = view_projection * vec4(position, 1.0);
+    fragColor = pointColor;
+    across = point.w;
+}
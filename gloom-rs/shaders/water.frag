@@ -0,0 +1,58 @@
+#version 430 core
+
+// Open water, the waves worked out in water.vert, see water.rs
+
+in vec3 fragWorldPosition;
+in vec3 fragNormal;
+in float fragCrest;
+
+uniform vec3 camera_position;
+uniform vec3 deep_color;
+uniform vec3 sun_direction; // Towards the sun
+uniform vec3 sun_color;
+uniform vec3 sky_color;
+uniform int planar_reflection; // 1 if the mirrored scene is in reflection_texture
+uniform vec2 texture_size;     // Of the reflection texture in pixels
+
+uniform sampler2D reflection_texture;
+
+layout(location = 0) out vec4 outColor;
+layout(location = 1) out vec4 outMotion; // Not written, the motion comes from the camera alone (see motion.rs)
+
+void main() {
+    vec3 viewDirection = normalize(fragWorldPosition - camera_position);
+    vec3 normal = normalize(fragNormal);
+    if (!gl_FrontFacing) {
+        normal = -normal; // Seen from under the water
+    }
+
+    // Schlick's Fresnel with the reflectance of water (about 2% head on)
+    float cosTheta = clamp(dot(-viewDirection, normal), 0.0, 1.0);
+    float fresnel = 0.02 + 0.98 * pow(1.0 - cosTheta, 5.0);
+
+    vec3 reflection;
+    if (planar_reflection != 0) {
+        // The mirrored scene is of the still water, the waves push the look up aside by how they tilt it
+        vec2 uv = gl_FragCoord.xy / texture_size + normal.xz * 0.04;
+        reflection = texture(reflection_texture, clamp(uv, vec2(0.0), vec2(1.0))).rgb;
+    } else {
+        reflection = sky_color;
+    }
+
+    // Light scattered back up from under the surface, a little greener through the thin crests
+    float daylight = clamp(sun_direction.y * 2.0 + 0.2, 0.05, 1.0);
+    vec3 body = mix(deep_color, deep_color * vec3(0.8, 1.6, 1.3), clamp(fragCrest, 0.0, 1.0)) * daylight;
+
+    // The sun glinting off the slopes facing it
+    vec3 halfway = normalize(sun_direction - viewDirection);
+    float glint = pow(max(dot(normal, halfway), 0.0), 400.0) * step(0.0, sun_direction.y);
+
+    vec3 color = mix(body, reflection, fresnel) + sun_color * glint * 8.0;
+
+    // Foam where the crests are highest
+    float foam = smoothstep(0.55, 0.85, fragCrest);
+    color = mix(color, vec3(0.9) * (daylight * sun_color + sky_color * 0.3), foam * 0.6);
+
+    outColor = vec4(color, mix(0.85, 1.0, fresnel));
+    outMotion = vec4(0.0, 0.0, 0.0, 1.0);
+}
@@ -0,0 +1,50 @@
+#version 430 core
+
+// Open water moved by a sum of Gerstner waves, see water.rs
+
+#define MAX_WAVES 8
+
+layout(location = 0) in vec3 inPosition;
+
+uniform mat4 view_projection;
+uniform vec3 origin; // Center of the still water
+uniform float time;
+uniform int wave_count;
+uniform vec4 waves[MAX_WAVES];       // Direction over XZ, wave number (radians per unit) and amplitude
+uniform vec2 wave_motion[MAX_WAVES]; // How far the points go along the wave for their height, and frequency
+
+out vec3 fragWorldPosition;
+out vec3 fragNormal;
+out float fragCrest; // How high the wave is here, 1 at the tallest crests
+
+void main() {
+    vec3 still = origin + inPosition;
+    vec3 position = still;
+    vec3 normal = vec3(0.0, 1.0, 0.0);
+    float highest = 0.0;
+
+    for (int i = 0; i < wave_count; i++) {
+        vec2 direction = waves[i].xy;
+        float number = waves[i].z;
+        float amplitude = waves[i].w;
+        float sharpness = wave_motion[i].x;
+        float phase = number * dot(direction, still.xz) - wave_motion[i].y * time;
+        float s = sin(phase);
+        float c = cos(phase);
+
+        // Every point goes around in a circle, forwards on the crests and back in the troughs
+        position.xz += direction * (sharpness * amplitude * c);
+        position.y += amplitude * s;
+
+        // The tilt of the surface from the same wave, see GPU Gems 1, chapter 1
+        float slope = number * amplitude;
+        normal.xz -= direction * (slope * c);
+        normal.y -= sharpness * slope * s;
+        highest += amplitude;
+    }
+
+    fragWorldPosition = position;
+    fragNormal = normalize(normal);
+    fragCrest = highest > 0.0 ? (position.y - origin.y) / highest : 0.0;
+    gl_Position = view_projection * vec4(position, 1.0);
+}
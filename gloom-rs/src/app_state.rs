@@ -0,0 +1,73 @@
+// * Application states
+// What the program as a whole is doing, which decides whether the simulation moves on and what the
+// input goes to. The loading screen is up until the assets are in, then the scene runs. Escape opens
+// the pause menu (see menu.rs) over the scene, which stands still behind it. Pause stops it without
+// the menu, and so does the window losing the focus, which starts it again when the focus comes back.
+//
+// The scene keeps being drawn in every state but Loading, only the time doesn't go on in it.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AppState {
+    Loading, // The loading screen, until the assets are in
+    Running,
+    Paused,  // Standing still, without the menu
+    Menu,    // Standing still, with the pause menu open
+}
+
+pub struct AppStateMachine {
+    state           : AppState,
+    paused_by_focus : bool, // Runs again when the focus comes back, a pause asked for doesn't
+}
+
+impl AppStateMachine {
+    pub fn new() -> AppStateMachine {
+        AppStateMachine { state: AppState::Loading, paused_by_focus: false }
+    }
+
+    pub fn state(&self) -> AppState {
+        self.state
+    }
+
+    // Whether time goes on in the scene
+    pub fn is_running(&self) -> bool {
+        self.state == AppState::Running
+    }
+
+    pub fn finish_loading(&mut self) {
+        if self.state == AppState::Loading {
+            self.set(AppState::Running);
+        }
+    }
+
+    // Open the menu from the scene, or close it and go back to running
+    pub fn toggle_menu(&mut self) {
+        match self.state {
+            AppState::Running | AppState::Paused => self.set(AppState::Menu),
+            AppState::Menu => self.set(AppState::Running),
+            AppState::Loading => {}
+        }
+    }
+
+    pub fn toggle_pause(&mut self) {
+        match self.state {
+            AppState::Running => self.set(AppState::Paused),
+            AppState::Paused => self.set(AppState::Running),
+            AppState::Menu | AppState::Loading => {}
+        }
+    }
+
+    pub fn focus_changed(&mut self, focused: bool) {
+        if !focused && self.state == AppState::Running {
+            self.set(AppState::Paused);
+            self.paused_by_focus = true;
+        } else if focused && self.state == AppState::Paused && self.paused_by_focus {
+            self.set(AppState::Running);
+        }
+    }
+
+    fn set(&mut self, state: AppState) {
+        println!("State: {:?} -> {:?}", self.state, state);
+        self.state = state;
+        self.paused_by_focus = false;
+    }
+}
@@ -0,0 +1,414 @@
+use std::collections::HashMap;
+
+use crate::compressed_texture::{self, CompressedImage, FormatSupport};
+use crate::gfx;
+use crate::gl_caps::RendererProfile;
+use crate::mesh::{generate_color_vec, Mesh, MultiPartModel};
+use crate::program_cache::ProgramCache;
+use crate::shader::{Shader, ShaderBuilder};
+use crate::util;
+
+// * Asset manager
+// Keeps every loaded mesh, texture and shader in one place, keyed by the path(s) they came from.
+// Asking for the same file twice gives back the same handle instead of parsing the file again.
+// Handles are just indices into the storage vectors, so they are Copy and cheap to pass around.
+//
+// The same file in different colors is parsed and uploaded once. Its parts go up without colors, and
+// every color only gets VAOs of its own that read the shared buffers, next to a buffer of its colors.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct MeshHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct TextureHandle(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ShaderHandle(usize);
+
+// One uploaded part of a model
+pub struct GpuMesh {
+    pub name        : String,
+    pub vao_id      : u32,
+    pub vbo_id      : u32,   // The positions of the geometry, the colors of a color of it
+    pub index_count : i32,
+    pub gpu_bytes   : usize, // Only what this one uploaded, not what it shares
+}
+
+pub struct MeshAsset {
+    pub path  : String,
+    pub color : [f32; 4],
+    pub parts : Vec<GpuMesh>,
+}
+
+pub struct TextureAsset {
+    pub path      : String,
+    pub texture   : gfx::Texture,
+    pub gpu_bytes : usize,
+}
+
+pub struct ShaderAsset {
+    pub paths  : Vec<String>,
+    pub shader : Shader,
+}
+
+pub struct AssetManager {
+    meshes   : Vec<MeshAsset>,
+    textures : Vec<TextureAsset>,
+    shaders  : Vec<ShaderAsset>,
+
+    // Parsed OBJ files and their geometry on the GPU, shared by every color of them
+    parsed_models  : HashMap<String, MultiPartModel>,
+    geometry       : HashMap<String, Vec<GpuMesh>>,
+    mesh_lookup    : HashMap<(String, [u32; 4]), MeshHandle>,
+    texture_lookup : HashMap<(String, gfx::ColorSpace), TextureHandle>,
+    shader_lookup  : HashMap<Vec<String>, ShaderHandle>,
+
+    pub shader_profile  : RendererProfile, // What the shaders are compiled for
+    pub program_cache   : Option<ProgramCache>,
+    pub texture_support : FormatSupport,   // Which compressed textures go to the GPU as they are
+}
+
+// Bytes a mesh takes up in RAM (and, since we upload everything, roughly the same on the GPU)
+pub fn mesh_bytes(mesh: &Mesh) -> usize {
+    util::byte_size_of_array(&mesh.vertices) as usize
+        + util::byte_size_of_array(&mesh.normals) as usize
+        + util::byte_size_of_array(&mesh.colors) as usize
+        + util::byte_size_of_array(&mesh.indices) as usize
+}
+
+// The shared geometry of a part is uploaded without its colors
+fn geometry_bytes(part: &Mesh) -> usize {
+    mesh_bytes(part) - util::byte_size_of_array(&part.colors) as usize
+}
+
+// A color of a part takes up one RGBA color per vertex
+fn color_bytes(part: &Mesh) -> usize {
+    part.vertices.len() / 3 * std::mem::size_of::<[f32; 4]>()
+}
+
+// Names for frame captures, "models/helicopter.obj body VAO"
+unsafe fn label_part(path: &str, part_name: &str, vao_id: u32, vbo_id: u32) {
+    gfx::label_object(gfx::ObjectKind::VertexArray, vao_id, &format!("{} {} geometry", path, part_name));
+    gfx::label_object(gfx::ObjectKind::Buffer, vbo_id, &format!("{} {} vertices", path, part_name));
+}
+
+unsafe fn label_colored_part(path: &str, part_name: &str, color: [f32; 4], vao_id: u32, color_buffer: u32) {
+    gfx::label_object(gfx::ObjectKind::VertexArray, vao_id, &format!("{} {} VAO {:?}", path, part_name, color));
+    gfx::label_object(gfx::ObjectKind::Buffer, color_buffer, &format!("{} {} colors {:?}", path, part_name, color));
+}
+
+// The geometry VAO of a part for a color of it, see util::share_geometry
+unsafe fn colored_part(path: &str, geometry: &GpuMesh, part: &Mesh, color: [f32; 4]) -> GpuMesh {
+    let mut vao_id = 0;
+    gl::GenVertexArrays(1, &mut vao_id);
+    let vbo_id = util::share_geometry(vao_id, geometry.vao_id, &generate_color_vec(color, part.vertices.len() / 3));
+    label_colored_part(path, &geometry.name, color, vao_id, vbo_id);
+    GpuMesh { name: geometry.name.clone(), vao_id, vbo_id, index_count: part.index_count, gpu_bytes: color_bytes(part) }
+}
+
+impl AssetManager {
+    pub fn new() -> AssetManager {
+        AssetManager {
+            meshes: vec![],
+            textures: vec![],
+            shaders: vec![],
+            parsed_models: HashMap::new(),
+            geometry: HashMap::new(),
+            mesh_lookup: HashMap::new(),
+            texture_lookup: HashMap::new(),
+            shader_lookup: HashMap::new(),
+            shader_profile: RendererProfile::Desktop,
+            program_cache: None,
+            texture_support: FormatSupport::default(),
+        }
+    }
+
+    // * Meshes
+    /// Loads (or fetches from the cache) an OBJ, STL or PLY file, uploads it, and paints every part in `color`.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, as the parts are uploaded with `create_vao`.
+    pub unsafe fn load_mesh(&mut self, path: &str, color: [f32; 4]) -> MeshHandle {
+        let key = (path.to_string(), color.map(f32::to_bits));
+        if let Some(&handle) = self.mesh_lookup.get(&key) {
+            return handle;
+        }
+
+        if !self.parsed_models.contains_key(path) {
+            let model = MultiPartModel::load(path, path, [1.0, 1.0, 1.0, 1.0]);
+            self.parsed_models.insert(path.to_string(), model);
+        }
+        let model = &self.parsed_models[path];
+        let geometry = self.geometry.entry(path.to_string()).or_insert_with(|| {
+            model.parts.iter()
+                .map(|(name, part)| {
+                    let (vao_id, vbo_id) = util::create_vao(&part.vertices, &part.indices, &vec![], &part.normals);
+                    label_part(path, name, vao_id, vbo_id);
+                    GpuMesh {
+                        name: name.clone(),
+                        vao_id,
+                        vbo_id,
+                        index_count: part.index_count,
+                        gpu_bytes: geometry_bytes(part),
+                    }
+                })
+                .collect()
+        });
+
+        let parts = geometry.iter().zip(&model.parts)
+            .map(|(geometry, (_, part))| colored_part(path, geometry, part, color))
+            .collect();
+
+        let handle = MeshHandle(self.meshes.len());
+        self.meshes.push(MeshAsset { path: path.to_string(), color, parts });
+        self.mesh_lookup.insert(key, handle);
+        handle
+    }
+
+    // The parsed file a mesh was made from, in white. Loaded meshes always have one.
+    pub fn model(&self, path: &str) -> &MultiPartModel {
+        &self.parsed_models[path]
+    }
+
+    // Hand over a model that was already parsed somewhere else (e.g. on a loader thread),
+    // so load_mesh doesn't read the file again
+    pub fn insert_parsed_model(&mut self, path: &str, model: MultiPartModel) {
+        self.parsed_models.entry(path.to_string()).or_insert(model);
+    }
+
+    pub fn mesh(&self, handle: MeshHandle) -> &MeshAsset {
+        &self.meshes[handle.0]
+    }
+
+    pub fn meshes(&self) -> &[MeshAsset] {
+        &self.meshes
+    }
+
+    // * Textures
+    /// Loads (or fetches from the cache) an image file as an RGBA8 texture with mipmaps. Color
+    /// textures (albedo, emissive) are `Srgb`, data textures `Linear`. DDS and KTX2 files keep their
+    /// block compression and the mipmaps in them (see compressed_texture.rs).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn load_texture(&mut self, path: &str, color_space: gfx::ColorSpace) -> TextureHandle {
+        let key = (path.to_string(), color_space);
+        if let Some(&handle) = self.texture_lookup.get(&key) {
+            return handle;
+        }
+
+        let (texture, gpu_bytes) = if compressed_texture::is_compressed_container(path) {
+            let mut id: u32 = 0;
+            gl::GenTextures(1, &mut id);
+            let image = CompressedImage::load(path).expect("Failed to load texture.");
+            let gpu_bytes = image.upload(id, &self.texture_support, color_space).unwrap_or_else(|e| panic!("Failed to upload texture {}: {}", path, e));
+            (gfx::Texture { id, width: image.width, height: image.height, color_space }, gpu_bytes)
+        } else {
+            let image = image::open(path)
+                .unwrap_or_else(|error| panic!("Failed to load texture. {}: {:?}", path, error))
+                .flipv() // OpenGL wants the first row at the bottom
+                .into_rgba8();
+            let (width, height) = image.dimensions();
+            // With a full mip chain, about 4/3 of the base level
+            (gfx::Texture::from_rgba8(width, height, image.as_raw(), color_space), (width * height * 4) as usize * 4 / 3)
+        };
+        texture.label(path);
+
+        let handle = TextureHandle(self.textures.len());
+        self.textures.push(TextureAsset { path: path.to_string(), texture, gpu_bytes });
+        self.texture_lookup.insert(key, handle);
+        handle
+    }
+
+    pub fn texture(&self, handle: TextureHandle) -> &TextureAsset {
+        &self.textures[handle.0]
+    }
+
+    // * Shaders
+    /// Compiles and links (or fetches from the cache) a shader program from the given files.
+    /// The same files in a different order count as the same program.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn load_shader(&mut self, paths: &[&str]) -> ShaderHandle {
+        let mut key: Vec<String> = paths.iter().map(|p| p.to_string()).collect();
+        key.sort();
+        if let Some(&handle) = self.shader_lookup.get(&key) {
+            return handle;
+        }
+
+        let mut builder = ShaderBuilder::new().with_profile(self.shader_profile).with_cache(self.program_cache.clone());
+        for path in paths {
+            builder = builder.attach_file(path);
+        }
+        let shader = builder.link();
+        gfx::label_object(gfx::ObjectKind::Program, shader.program_id, &key.join(" + "));
+
+        let handle = ShaderHandle(self.shaders.len());
+        self.shaders.push(ShaderAsset { paths: key.clone(), shader });
+        self.shader_lookup.insert(key, handle);
+        handle
+    }
+
+    pub fn shader(&self, handle: ShaderHandle) -> &Shader {
+        &self.shaders[handle.0].shader
+    }
+
+    // * Hot reloading
+    pub fn watched_paths(&self) -> Vec<String> {
+        let mut paths: Vec<String> = self.meshes.iter().map(|m| m.path.clone())
+            .chain(self.textures.iter().map(|t| t.path.clone()))
+            .chain(self.shaders.iter().flat_map(|s| s.paths.iter().cloned()))
+            .collect();
+        paths.sort();
+        paths.dedup();
+        paths
+    }
+
+    /// Reads a changed file again and re-uploads every mesh or texture made from it, and compiles
+    /// every shader program made from it again.
+    /// VAO, texture IDs and shader handles stay the same, so scene nodes keep pointing at the right thing.
+    /// A shader that doesn't compile panics like it does when it is first loaded, and the old program
+    /// is kept.
+    /// Returns (vao id, new index count) for every mesh part that was updated.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn reload(&mut self, path: &str) -> Vec<(u32, i32)> {
+        let mut updated_parts = vec![];
+
+        if let Some(geometry) = self.geometry.get_mut(path) {
+            let model = MultiPartModel::load(path, path, [1.0, 1.0, 1.0, 1.0]);
+
+            // Parts are matched by name, new parts need new scene nodes so they are skipped
+            for geometry_part in geometry.iter_mut() {
+                if let Some(part) = model.part(&geometry_part.name) {
+                    geometry_part.vbo_id = util::refill_vao(geometry_part.vao_id, &part.vertices, &part.indices, &vec![], &part.normals);
+                    label_part(path, &geometry_part.name, geometry_part.vao_id, geometry_part.vbo_id);
+                    geometry_part.index_count = part.index_count;
+                    geometry_part.gpu_bytes = geometry_bytes(part);
+                } else {
+                    println!("Part '{}' is gone from {}, keeping the old one", geometry_part.name, path);
+                }
+            }
+
+            // The colors point at the new buffers, and get as many colors as there are vertices now
+            for asset in self.meshes.iter_mut().filter(|m| m.path == path) {
+                for (gpu_part, geometry_part) in asset.parts.iter_mut().zip(geometry.iter()) {
+                    if let Some(part) = model.part(&gpu_part.name) {
+                        let colors = generate_color_vec(asset.color, part.vertices.len() / 3);
+                        gpu_part.vbo_id = util::share_geometry(gpu_part.vao_id, geometry_part.vao_id, &colors);
+                        label_colored_part(path, &gpu_part.name, asset.color, gpu_part.vao_id, gpu_part.vbo_id);
+                        gpu_part.index_count = part.index_count;
+                        gpu_part.gpu_bytes = color_bytes(part);
+                        updated_parts.push((gpu_part.vao_id, part.index_count));
+                    }
+                }
+            }
+            self.parsed_models.insert(path.to_string(), model);
+        }
+
+        let texture_support = self.texture_support;
+        for texture in self.textures.iter_mut().filter(|t| t.path == path) {
+            if compressed_texture::is_compressed_container(path) {
+                let uploaded = CompressedImage::load(path).and_then(|image| {
+                    let gpu_bytes = image.upload(texture.texture.id, &texture_support, texture.texture.color_space)?;
+                    texture.texture.width = image.width;
+                    texture.texture.height = image.height;
+                    Ok(gpu_bytes)
+                });
+                match uploaded {
+                    Ok(gpu_bytes) => texture.gpu_bytes = gpu_bytes,
+                    Err(e) => println!("Failed to reload texture {}", e),
+                }
+                continue;
+            }
+            let image = match image::open(path) {
+                Ok(image) => image.flipv().into_rgba8(),
+                Err(e) => {
+                    println!("Failed to reload texture {}: {}", path, e);
+                    continue;
+                }
+            };
+            let (width, height) = image.dimensions();
+
+            texture.texture.upload_rgba8(width, height, image.as_raw());
+            texture.gpu_bytes = (width * height * 4) as usize * 4 / 3;
+        }
+
+        let (shader_profile, program_cache) = (self.shader_profile, self.program_cache.clone());
+        for asset in self.shaders.iter_mut().filter(|s| s.paths.iter().any(|p| p == path)) {
+            let mut builder = ShaderBuilder::new().with_profile(shader_profile).with_cache(program_cache.clone());
+            for shader_path in &asset.paths {
+                builder = builder.attach_file(shader_path);
+            }
+            let shader = builder.link();
+            gfx::label_object(gfx::ObjectKind::Program, shader.program_id, &asset.paths.join(" + "));
+            asset.shader.delete();
+            asset.shader = shader;
+        }
+
+        updated_parts
+    }
+
+    // * Cleanup
+    /// Deletes every GL object the manager owns and forgets about them.
+    /// All handles handed out before this are invalid afterwards.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete_all(&mut self) {
+        for asset in self.meshes.drain(..) {
+            for part in asset.parts {
+                util::delete_shared_vao(part.vao_id);
+            }
+        }
+        for (_, geometry) in self.geometry.drain() {
+            for part in geometry {
+                util::delete_vao(part.vao_id);
+            }
+        }
+        for texture in self.textures.drain(..) {
+            texture.texture.delete();
+        }
+        for asset in self.shaders.drain(..) {
+            asset.shader.delete();
+        }
+        self.parsed_models.clear();
+        self.mesh_lookup.clear();
+        self.texture_lookup.clear();
+        self.shader_lookup.clear();
+    }
+
+    // * Memory usage
+    // Returns (cpu bytes, gpu bytes) per asset type
+    pub fn mesh_memory(&self) -> (usize, usize) {
+        let cpu = self.parsed_models.values()
+            .flat_map(|model| model.parts.iter())
+            .map(|(_, part)| mesh_bytes(part))
+            .sum();
+        let gpu = self.geometry.values()
+            .chain(self.meshes.iter().map(|m| &m.parts))
+            .flatten()
+            .map(|part| part.gpu_bytes)
+            .sum();
+        (cpu, gpu)
+    }
+
+    pub fn texture_memory(&self) -> (usize, usize) {
+        // The image is dropped after upload
+        let gpu = self.textures.iter().map(|t| t.gpu_bytes).sum();
+        (0, gpu)
+    }
+
+    pub fn print_memory_report(&self) {
+        let (mesh_cpu, mesh_gpu) = self.mesh_memory();
+        let (texture_cpu, texture_gpu) = self.texture_memory();
+        let kib = |bytes: usize| bytes as f32 / 1024.0;
+
+        println!("Assets:");
+        println!("  Meshes   : {:3} loaded, CPU {:10.1} KiB, GPU {:10.1} KiB", self.meshes.len(), kib(mesh_cpu), kib(mesh_gpu));
+        println!("  Textures : {:3} loaded, CPU {:10.1} KiB, GPU {:10.1} KiB", self.textures.len(), kib(texture_cpu), kib(texture_gpu));
+        println!("  Shaders  : {:3} loaded", self.shaders.len());
+    }
+}
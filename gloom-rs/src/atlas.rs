@@ -0,0 +1,169 @@
+use std::collections::HashMap;
+
+use crate::gfx;
+
+// * Texture atlas
+// Overlays made of many small textures (flare elements, minimap markers, icons) would bind a
+// texture for every quad they draw. Instead their images are collected at load time, packed into one
+// texture side by side, and every pass draws from that one texture with the rectangle of the sprite
+// it wants, bound once for the whole pass.
+//
+// The packing is a simple shelf packer: the images go in rows from the tallest to the flattest, and
+// the atlas is made wider until the rows fit in a square-ish power of two texture. Every sprite gets
+// a border of its own edge pixels around it, so filtering (and the first mipmap levels, which is as
+// far down as the border keeps them apart) doesn't bleed its neighbours in.
+//
+// Like every texture here the first row is at the bottom, so a sprite's v goes up.
+
+// A packed image, in pixels and in texture coordinates
+#[derive(Clone, Copy, Debug)]
+pub struct Sprite {
+    pub x       : u32,
+    pub y       : u32,
+    pub width   : u32,
+    pub height  : u32,
+    pub uv_rect : [f32; 4], // Lower left corner (u, v) and size, what the shaders take
+}
+
+impl Sprite {
+    // Where (u, v), from 0 to 1 across the sprite, is in the atlas
+    pub fn uv(&self, u: f32, v: f32) -> [f32; 2] {
+        [self.uv_rect[0] + u * self.uv_rect[2], self.uv_rect[1] + v * self.uv_rect[3]]
+    }
+}
+
+struct SpriteImage {
+    name   : String,
+    width  : u32,
+    height : u32,
+    pixels : Vec<u8>, // RGBA8, first row at the bottom
+}
+
+pub struct AtlasBuilder {
+    padding : u32, // Pixels of border around every sprite
+    images  : Vec<SpriteImage>,
+}
+
+impl AtlasBuilder {
+    pub fn new(padding: u32) -> AtlasBuilder {
+        AtlasBuilder { padding, images: vec![] }
+    }
+
+    /// Tightly packed RGBA8 pixels, first row at the bottom. A name that is already in the atlas
+    /// replaces the image it had.
+    pub fn add_pixels(&mut self, name: &str, width: u32, height: u32, pixels: Vec<u8>) {
+        assert_eq!(pixels.len(), (width * height * 4) as usize, "Sprite {} is {}x{}", name, width, height);
+        self.images.retain(|image| image.name != name);
+        self.images.push(SpriteImage { name: name.to_string(), width, height, pixels });
+    }
+
+    /// A `size` x `size` sprite with the color `pixel(u, v)` at every pixel, (u, v) going from -1 to 1
+    /// across it with v up, from 0 to 1 per channel.
+    pub fn add_generated<F: Fn(f32, f32) -> [f32; 4]>(&mut self, name: &str, size: u32, pixel: F) {
+        let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+        for y in 0..size {
+            for x in 0..size {
+                let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+                let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+                pixels.extend(pixel(u, v).iter().map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8));
+            }
+        }
+        self.add_pixels(name, size, size, pixels);
+    }
+
+    /// Pack everything added so far into one texture.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn build(self, color_space: gfx::ColorSpace) -> TextureAtlas {
+        let padded: Vec<(u32, u32)> = self.images.iter().map(|image| (image.width + 2 * self.padding, image.height + 2 * self.padding)).collect();
+        let (width, height, corners) = pack(&padded);
+
+        let mut pixels = vec![0u8; (width * height * 4) as usize];
+        let mut sprites = HashMap::new();
+        for (image, &(corner_x, corner_y)) in self.images.iter().zip(&corners) {
+            let (x, y) = (corner_x + self.padding, corner_y + self.padding);
+            // The border repeats the nearest edge pixel of the image
+            for row in 0..image.height + 2 * self.padding {
+                let source_row = (row as i64 - self.padding as i64).clamp(0, image.height as i64 - 1) as u32;
+                for column in 0..image.width + 2 * self.padding {
+                    let source_column = (column as i64 - self.padding as i64).clamp(0, image.width as i64 - 1) as u32;
+                    let source = ((source_row * image.width + source_column) * 4) as usize;
+                    let target = (((corner_y + row) * width + corner_x + column) * 4) as usize;
+                    pixels[target..target + 4].copy_from_slice(&image.pixels[source..source + 4]);
+                }
+            }
+            let uv_rect = [
+                x as f32 / width as f32,
+                y as f32 / height as f32,
+                image.width as f32 / width as f32,
+                image.height as f32 / height as f32,
+            ];
+            sprites.insert(image.name.clone(), Sprite { x, y, width: image.width, height: image.height, uv_rect });
+        }
+
+        let texture = gfx::Texture::from_rgba8(width, height, &pixels, color_space);
+        gl::BindTexture(gl::TEXTURE_2D, texture.id);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        // Each level down halves the border, below the last level with some left the sprites run together
+        let max_level = 32 - self.padding.max(1).leading_zeros() - 1;
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, max_level as i32);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        TextureAtlas { texture, sprites }
+    }
+}
+
+pub struct TextureAtlas {
+    pub texture : gfx::Texture,
+    sprites     : HashMap<String, Sprite>,
+}
+
+impl TextureAtlas {
+    pub fn sprite(&self, name: &str) -> Option<&Sprite> {
+        self.sprites.get(name)
+    }
+
+    // The texture coordinate rectangle of a sprite, panics if there is no such sprite like a missing asset does
+    pub fn uv_rect(&self, name: &str) -> [f32; 4] {
+        self.sprite(name).unwrap_or_else(|| panic!("No sprite {} in the texture atlas", name)).uv_rect
+    }
+
+    pub fn sprite_count(&self) -> usize {
+        self.sprites.len()
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.texture.delete();
+    }
+}
+
+// Lower left corner of every rectangle on shelves, and the power of two size of the texture they fit in
+fn pack(sizes: &[(u32, u32)]) -> (u32, u32, Vec<(u32, u32)>) {
+    let mut order: Vec<usize> = (0..sizes.len()).collect();
+    order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1));
+    let mut width = sizes.iter().map(|size| size.0).max().unwrap_or(1).next_power_of_two();
+    loop {
+        let mut corners = vec![(0, 0); sizes.len()];
+        let (mut x, mut shelf_y, mut shelf_height) = (0, 0, 0);
+        for &i in &order {
+            let (sprite_width, sprite_height) = sizes[i];
+            if x + sprite_width > width {
+                shelf_y += shelf_height;
+                x = 0;
+                shelf_height = 0;
+            }
+            corners[i] = (x, shelf_y);
+            x += sprite_width;
+            shelf_height = shelf_height.max(sprite_height);
+        }
+        let height = (shelf_y + shelf_height).max(1).next_power_of_two();
+        if height <= width {
+            return (width, height, corners);
+        }
+        width *= 2;
+    }
+}
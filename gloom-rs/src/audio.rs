@@ -0,0 +1,287 @@
+extern crate nalgebra_glm as glm;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::camera::Camera;
+use crate::scene_graph::NodeId;
+use crate::snapshot::FrameSnapshot;
+use crate::spatial::SpatialIndex;
+
+// * Positional audio
+// Sounds are attached to SceneNodes by ID and follow them around: every frame the node's world
+// position is looked up in the frame snapshot, and the volume of each ear, the pitch (doppler) and
+// the overall loudness are worked out from where it is relative to the listener (the fly-cam).
+//
+// There are no sound files, the sounds are synthesized: a rotor thump for the helicopters and some
+// gusty wind that is everywhere. The output goes through rodio, which is behind the `audio` feature
+// (`cargo run --features audio`), because it needs the ALSA development files on Linux.
+// Without the feature, or without a sound device, everything here still runs but nothing is heard.
+
+pub const SAMPLE_RATE: u32 = 44100;
+const SPEED_OF_SOUND: f32 = 343.0; // World units are meters
+const INAUDIBLE_GAIN: f32 = 0.01;  // Sources far enough away to be quieter than this are silenced
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SoundKind {
+    RotorThump { blades_per_second: f32 }, // A low thump every time a blade passes
+    Wind,                                  // Noise with slow gusts
+}
+
+// What the render thread tells a playing sound, read by the audio thread.
+// The f32s are stored as their bits, there are no atomic floats.
+struct SoundParameters {
+    left_gain  : AtomicU32,
+    right_gain : AtomicU32,
+    pitch      : AtomicU32,
+}
+
+impl SoundParameters {
+    fn new(gain: f32) -> SoundParameters {
+        SoundParameters {
+            left_gain  : AtomicU32::new(gain.to_bits()),
+            right_gain : AtomicU32::new(gain.to_bits()),
+            pitch      : AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+
+    fn set(&self, left_gain: f32, right_gain: f32, pitch: f32) {
+        self.left_gain.store(left_gain.to_bits(), Ordering::Relaxed);
+        self.right_gain.store(right_gain.to_bits(), Ordering::Relaxed);
+        self.pitch.store(pitch.to_bits(), Ordering::Relaxed);
+    }
+
+    fn get(&self) -> (f32, f32, f32) {
+        (
+            f32::from_bits(self.left_gain.load(Ordering::Relaxed)),
+            f32::from_bits(self.right_gain.load(Ordering::Relaxed)),
+            f32::from_bits(self.pitch.load(Ordering::Relaxed)),
+        )
+    }
+}
+
+// * Synthesizer
+// An endless interleaved stereo stream. The gains are eased towards their targets so a sound
+// moving across the listener doesn't click.
+struct Synth {
+    kind       : SoundKind,
+    parameters : Arc<SoundParameters>,
+    phase      : f32, // Position within the current thump, in thumps
+    time       : f32, // Seconds played, scaled by the pitch
+    noise      : u32, // xorshift state
+    lowpass    : f32,
+    gains      : (f32, f32),
+    right_next : Option<f32>, // The right sample of the frame whose left sample was just handed out
+}
+
+impl Synth {
+    fn new(kind: SoundKind, parameters: Arc<SoundParameters>, seed: u32) -> Synth {
+        Synth { kind, parameters, phase: 0.0, time: 0.0, noise: seed | 1, lowpass: 0.0, gains: (0.0, 0.0), right_next: None }
+    }
+
+    fn white_noise(&mut self) -> f32 {
+        self.noise ^= self.noise << 13;
+        self.noise ^= self.noise >> 17;
+        self.noise ^= self.noise << 5;
+        self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0
+    }
+
+    // One mono sample, before the gains
+    fn next_mono(&mut self, pitch: f32) -> f32 {
+        let step = pitch / SAMPLE_RATE as f32;
+        self.time += step;
+
+        match self.kind {
+            SoundKind::RotorThump { blades_per_second } => {
+                self.phase = (self.phase + blades_per_second * step).fract();
+                // A 70 Hz knock with some chop on top, dying out quickly after each blade
+                let seconds_into_thump = self.phase / blades_per_second;
+                let envelope = (-self.phase * 9.0).exp();
+                let knock = (2.0 * std::f32::consts::PI * 70.0 * seconds_into_thump).sin();
+                let noise = self.white_noise();
+                self.lowpass += (noise - self.lowpass) * 0.15;
+                envelope * (0.8 * knock + 0.5 * self.lowpass)
+            }
+            SoundKind::Wind => {
+                // Heavily filtered noise, getting louder and softer in slow gusts
+                let noise = self.white_noise();
+                self.lowpass += (noise - self.lowpass) * 0.02;
+                let gusts = 0.6 + 0.4 * (self.time * 0.31).sin() * (self.time * 0.13 + 1.0).sin();
+                self.lowpass * gusts * 4.0
+            }
+        }
+    }
+}
+
+impl Iterator for Synth {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if let Some(right) = self.right_next.take() {
+            return Some(right);
+        }
+        let (left_gain, right_gain, pitch) = self.parameters.get();
+        self.gains.0 += (left_gain - self.gains.0) * 0.002;
+        self.gains.1 += (right_gain - self.gains.1) * 0.002;
+
+        let sample = self.next_mono(pitch);
+        self.right_next = Some(sample * self.gains.1);
+        Some(sample * self.gains.0)
+    }
+}
+
+#[cfg(feature = "audio")]
+impl rodio::Source for Synth {
+    fn current_frame_len(&self) -> Option<usize> { None }
+    fn channels(&self) -> u16 { 2 }
+    fn sample_rate(&self) -> u32 { SAMPLE_RATE }
+    fn total_duration(&self) -> Option<std::time::Duration> { None }
+}
+
+// * Sources
+pub struct AudioSource {
+    pub node_id            : NodeId,
+    pub kind               : SoundKind,
+    pub volume             : f32,
+    pub reference_distance : f32, // Full volume this close, quieter with distance past it
+    parameters             : Arc<SoundParameters>,
+    last_position          : Option<glm::Vec3>,
+}
+
+pub struct AudioSystem {
+    pub master_volume      : f32,
+    pub muted              : bool,
+    sources                : Vec<AudioSource>,
+    ambient                : Vec<(Arc<SoundParameters>, f32)>, // Not positional, with their volume
+    listener_last_position : Option<glm::Vec3>,
+    #[cfg(feature = "audio")]
+    output                 : Option<(rodio::OutputStream, rodio::OutputStreamHandle)>,
+}
+
+impl AudioSystem {
+    // Opens the default sound device, if there is one
+    pub fn new() -> AudioSystem {
+        #[cfg(feature = "audio")]
+        let output = match rodio::OutputStream::try_default() {
+            Ok(output) => Some(output),
+            Err(e) => {
+                println!("No sound: {}", e);
+                None
+            }
+        };
+        #[cfg(not(feature = "audio"))]
+        {
+            println!("No sound: built without the audio feature");
+        }
+
+        AudioSystem {
+            master_volume: 0.8,
+            muted: false,
+            sources: vec![],
+            ambient: vec![],
+            listener_last_position: None,
+            #[cfg(feature = "audio")]
+            output,
+        }
+    }
+
+    fn play(&self, kind: SoundKind, parameters: &Arc<SoundParameters>) {
+        let synth = Synth::new(kind, Arc::clone(parameters), 0x9E37_79B9 ^ (self.sources.len() + self.ambient.len()) as u32);
+        #[cfg(feature = "audio")]
+        {
+            if let Some((_, handle)) = &self.output {
+                if let Err(e) = handle.play_raw(synth) {
+                    println!("Failed to play {:?}: {}", kind, e);
+                }
+            }
+        }
+        #[cfg(not(feature = "audio"))]
+        {
+            drop((kind, synth));
+        }
+    }
+
+    // Play a sound at a node until the program ends. It is silent while the node is hidden.
+    pub fn attach(&mut self, node_id: NodeId, kind: SoundKind, volume: f32, reference_distance: f32) {
+        let parameters = Arc::new(SoundParameters::new(0.0));
+        self.play(kind, &parameters);
+        self.sources.push(AudioSource { node_id, kind, volume, reference_distance, parameters, last_position: None });
+    }
+
+    // Play a sound that is the same everywhere, like the wind. Returns its index for set_ambient_volume.
+    pub fn play_ambient(&mut self, kind: SoundKind, volume: f32) -> usize {
+        let parameters = Arc::new(SoundParameters::new(0.0));
+        self.play(kind, &parameters);
+        self.ambient.push((parameters, volume));
+        self.ambient.len() - 1
+    }
+
+    pub fn set_ambient_volume(&mut self, ambient: usize, volume: f32) {
+        self.ambient[ambient].1 = volume;
+    }
+
+    // * Follow the nodes
+    // Call once per frame, after the snapshot of the frame has been captured
+    pub fn update(&mut self, snapshot: &FrameSnapshot, spatial: &SpatialIndex, listener: &Camera, delta_time: f32) {
+        let master = if self.muted { 0.0 } else { self.master_volume };
+        let delta_time = delta_time.max(1e-4);
+
+        let listener_position = listener.position_f32();
+        let listener_velocity = self.listener_last_position
+            .map(|last| (listener_position - last) / delta_time)
+            .unwrap_or_else(glm::zero);
+        self.listener_last_position = Some(listener_position);
+        let screen_right = -listener.right(); // Camera::right points left on screen, see the A/D keys in main.rs
+
+        // Only the sources within earshot are looked up in the snapshot
+        let audible_distance = self.sources.iter()
+            .map(|source| source.volume * source.reference_distance / INAUDIBLE_GAIN)
+            .fold(0.0, f32::max);
+        let audible = spatial.nodes_near(&listener_position, audible_distance);
+
+        for source in &mut self.sources {
+            let out_of_earshot = spatial.bounds(source.node_id).is_some() && !audible.contains(&source.node_id);
+            let position = if out_of_earshot {
+                None
+            } else {
+                snapshot.draws.iter()
+                    .find(|draw| draw.node_id == source.node_id)
+                    .map(|draw| (draw.model_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz())
+            };
+            let position = match position {
+                Some(position) => position,
+                None => {
+                    source.parameters.set(0.0, 0.0, 1.0);
+                    source.last_position = None;
+                    continue;
+                }
+            };
+            let source_velocity = source.last_position
+                .map(|last| (position - last) / delta_time)
+                .unwrap_or_else(glm::zero);
+            source.last_position = Some(position);
+
+            let to_source = position - listener_position;
+            let distance = glm::length(&to_source);
+            let direction = if distance > 1e-4 { to_source / distance } else { glm::zero() };
+
+            // Loudness falls off with distance past the reference distance
+            let gain = source.volume * master * source.reference_distance / distance.max(source.reference_distance);
+
+            // Equal power panning from how far to the side the source is
+            let pan = glm::dot(&direction, &screen_right).clamp(-1.0, 1.0);
+            let angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+
+            // Doppler, with both velocities along the line between them (positive = moving apart)
+            let source_away = glm::dot(&source_velocity, &direction);
+            let listener_away = -glm::dot(&listener_velocity, &direction);
+            let pitch = ((SPEED_OF_SOUND - listener_away) / (SPEED_OF_SOUND + source_away)).clamp(0.5, 2.0);
+
+            source.parameters.set(gain * angle.cos(), gain * angle.sin(), pitch);
+        }
+
+        for (parameters, volume) in &self.ambient {
+            parameters.set(volume * master, volume * master, 1.0);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::post::PostChain;
+
+// * Auto exposure
+// The scene goes from a pitch black night sky to a searchlight pointed at the camera, no one exposure
+// fits both. So the exposure follows the brightness of the scene, the way a camera (or an eye) does:
+// the HDR target is metered every frame and the exposure is picked so its average ends up at
+// `key_value` (middle grey), eased towards over a moment instead of jumping.
+//
+// The metering draws the log of every pixel's luminance into a small texture and averages it by
+// building its mipmaps, the 1x1 level is the average (a geometric mean, so one bright lamp doesn't
+// darken everything). Pixels near the middle of the window count more, that is what is looked at.
+// The easing runs on the GPU too, between two 1x1 textures, so nothing is ever read back: the tone
+// mapping reads the exposure straight from the texture (see PostChain::end).
+
+const METER_SIZE: i32 = 256; // Of the metering texture, the window is scaled down to it
+
+pub struct AutoExposure {
+    pub key_value        : f32, // Average luminance the exposure aims for
+    pub adaptation_speed : f32, // How quickly it follows, per second. Around 1 takes a couple of seconds.
+    pub min_exposure     : f32, // So the night sky doesn't get brightened into grey noise
+    pub max_exposure     : f32,
+    meter_target         : Framebuffer,      // Weighted log luminance in R, the weight in G
+    adapted_targets      : [Framebuffer; 2], // Adapted log luminance in R, the exposure it gives in G
+    current              : usize,            // Which of the adapted targets has this frame's exposure
+    reset                : bool,             // Take the metered value as is, nothing to ease from
+    meter_shader         : ShaderHandle,
+    adapt_shader         : ShaderHandle,
+}
+
+impl AutoExposure {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager) -> AutoExposure {
+        let meter_target = Framebuffer::with_color_format(METER_SIZE, METER_SIZE, gl::RG16F, gl::FLOAT).with_label("exposure meter");
+        gl::BindTexture(gl::TEXTURE_2D, meter_target.color_texture);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_NEAREST as i32);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        AutoExposure {
+            key_value: 0.18,
+            adaptation_speed: 1.5,
+            min_exposure: 0.25,
+            max_exposure: 12.0,
+            meter_target,
+            adapted_targets: [
+                Framebuffer::with_color_format(1, 1, gl::RG32F, gl::FLOAT).with_label("adapted exposure 0"),
+                Framebuffer::with_color_format(1, 1, gl::RG32F, gl::FLOAT).with_label("adapted exposure 1"),
+            ],
+            current: 0,
+            reset: true,
+            meter_shader: assets.load_shader(&["shaders/fullscreen.vert", "shaders/exposure_meter.frag"]),
+            adapt_shader: assets.load_shader(&["shaders/fullscreen.vert", "shaders/exposure_adapt.frag"]),
+        }
+    }
+
+    // Jump straight to the exposure of the next frame, after a cut or after it was off for a while
+    pub fn reset(&mut self) {
+        self.reset = true;
+    }
+
+    /// Meter the post chain's HDR target and ease the exposure towards it, `delta_time` seconds after
+    /// the last update. Returns the texture with the exposure, for PostChain::end.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn update(&mut self, assets: &AssetManager, post_chain: &PostChain, delta_time: f32) -> u32 {
+        gfx::RenderState { depth_test: gfx::DepthTest::Off, ..Default::default() }.apply();
+
+        // * Metering, the mipmaps do the averaging
+        self.meter_target.bind();
+        let meter_shader = assets.shader(self.meter_shader);
+        meter_shader.activate();
+        meter_shader.set_uniform_int("hdr_texture", 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, post_chain.hdr_target.color_texture);
+        post_chain.draw_fullscreen_triangle();
+        gl::BindTexture(gl::TEXTURE_2D, self.meter_target.color_texture);
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+
+        // * Easing, from last frame's target into the other one
+        let previous = self.current;
+        self.current = 1 - self.current;
+        self.adapted_targets[self.current].bind();
+        let adapt_shader = assets.shader(self.adapt_shader);
+        adapt_shader.activate();
+        adapt_shader.set_uniform_int("meter_texture", 0);
+        adapt_shader.set_uniform_int("meter_level", (METER_SIZE as f32).log2() as i32);
+        adapt_shader.set_uniform_int("previous_texture", 1);
+        // Frame rate independent, the same share of the way is covered every second
+        let blend = if self.reset { 1.0 } else { 1.0 - (-delta_time * self.adaptation_speed).exp() };
+        adapt_shader.set_uniform_float("blend", blend);
+        adapt_shader.set_uniform_float("key_value", self.key_value);
+        adapt_shader.set_uniform_float("min_exposure", self.min_exposure);
+        adapt_shader.set_uniform_float("max_exposure", self.max_exposure);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, self.adapted_targets[previous].color_texture);
+        post_chain.draw_fullscreen_triangle();
+        self.reset = false;
+
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gfx::RenderState::default().apply();
+
+        self.adapted_targets[self.current].color_texture
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.meter_target.delete();
+        for target in &self.adapted_targets {
+            target.delete();
+        }
+    }
+}
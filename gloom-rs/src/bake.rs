@@ -0,0 +1,231 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use rayon::prelude::*;
+
+use crate::bvh::ray_triangle;
+use crate::mesh::Mesh;
+
+// * Vertex color baking
+// Preprocessing for meshes drawn without textures: work something out once per vertex and multiply
+// it into the vertex colors that create_vao uploads. Multiplying means bakes can be stacked, e.g. a
+// texture first and then ambient occlusion on top. Nothing here touches GL, so it can run on the
+// loader threads, but the ambient occlusion of a big mesh takes seconds even in a release build.
+// Bake once and keep the result with mesh::export_obj, the colors go into the OBJ.
+
+// Where on the texture each vertex is
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    Texcoords,                    // The mesh's own texture coordinates
+    TopDown { tiling: f32 },      // Straight down onto the XZ plane, the texture covering the mesh `tiling` times
+}
+
+// Multiply the texture color at every vertex into its color. Falls back to TopDown if the mesh has no
+// texture coordinates.
+pub fn bake_texture(mesh: &mut Mesh, image: &image::RgbaImage, projection: Projection) {
+    let vertex_count = mesh.vertices.len() / 3;
+    let projection = match projection {
+        Projection::Texcoords if mesh.texcoords.len() / 2 != vertex_count => {
+            println!("Warning: the mesh has no texture coordinates, baking the texture from above");
+            Projection::TopDown { tiling: 1.0 }
+        }
+        projection => projection,
+    };
+
+    let (min, max) = mesh.bounds();
+    let size = glm::vec2((max.x - min.x).max(1e-6), (max.z - min.z).max(1e-6));
+
+    for i in 0..vertex_count {
+        let (u, v) = match projection {
+            Projection::Texcoords => (mesh.texcoords[2 * i], mesh.texcoords[2 * i + 1]),
+            Projection::TopDown { tiling } => (
+                (mesh.vertices[3 * i] - min.x) / size.x * tiling,
+                (mesh.vertices[3 * i + 2] - min.z) / size.y * tiling,
+            ),
+        };
+        let sample = sample_bilinear(image, u, v);
+        for (color, sample) in mesh.colors[4 * i..4 * i + 4].iter_mut().zip(&sample) {
+            *color *= sample;
+        }
+    }
+}
+
+// Texture lookup that wraps around like GL_REPEAT, with v going up like in OpenGL
+fn sample_bilinear(image: &image::RgbaImage, u: f32, v: f32) -> [f32; 4] {
+    let (width, height) = image.dimensions();
+    let x = u.rem_euclid(1.0) * width as f32 - 0.5;
+    let y = (1.0 - v.rem_euclid(1.0)) * height as f32 - 0.5;
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let texel = |x: f32, y: f32| {
+        let pixel = image.get_pixel((x as i64).rem_euclid(width as i64) as u32, (y as i64).rem_euclid(height as i64) as u32);
+        pixel.0.map(|channel| channel as f32 / 255.0)
+    };
+    let (a, b, c, d) = (texel(x0, y0), texel(x0 + 1.0, y0), texel(x0, y0 + 1.0), texel(x0 + 1.0, y0 + 1.0));
+    [0, 1, 2, 3].map(|channel| {
+        let top = a[channel] + (b[channel] - a[channel]) * fx;
+        let bottom = c[channel] + (d[channel] - c[channel]) * fx;
+        top + (bottom - top) * fy
+    })
+}
+
+// * Ambient occlusion
+// Rays are shot from every vertex into the hemisphere around its normal. Whatever they hit within
+// `max_distance` darkens the vertex, nearer hits more. Only the triangles near the vertex can be hit,
+// so the triangles are sorted into a grid of `max_distance` sized cells and each vertex only looks at
+// its own cell and the ones around it.
+#[derive(Clone, Copy, Debug)]
+pub struct AmbientOcclusion {
+    pub rays         : usize,
+    pub max_distance : f32,
+    pub strength     : f32, // 0 bakes nothing, 1 turns fully covered vertices black
+}
+
+impl Default for AmbientOcclusion {
+    fn default() -> AmbientOcclusion {
+        AmbientOcclusion { rays: 16, max_distance: 20.0, strength: 0.8 }
+    }
+}
+
+pub fn bake_ambient_occlusion(mesh: &mut Mesh, settings: &AmbientOcclusion) {
+    let before = std::time::Instant::now();
+    let vertex = |i: u32| glm::vec3(mesh.vertices[3 * i as usize], mesh.vertices[3 * i as usize + 1], mesh.vertices[3 * i as usize + 2]);
+    let triangles: Vec<[u32; 3]> = mesh.indices.chunks(3)
+        .filter(|triangle| triangle.len() == 3)
+        .map(|triangle| [triangle[0], triangle[1], triangle[2]])
+        .collect();
+
+    let cell_size = settings.max_distance.max(1e-3);
+    let cell = |point: &glm::Vec3| ((point.x / cell_size).floor() as i32, (point.y / cell_size).floor() as i32, (point.z / cell_size).floor() as i32);
+    let mut grid: HashMap<(i32, i32, i32), Vec<usize>> = HashMap::new();
+    for (t, triangle) in triangles.iter().enumerate() {
+        let corners = triangle.map(vertex);
+        let low = cell(&glm::min2(&glm::min2(&corners[0], &corners[1]), &corners[2]));
+        let high = cell(&glm::max2(&glm::max2(&corners[0], &corners[1]), &corners[2]));
+        for x in low.0..=high.0 {
+            for y in low.1..=high.1 {
+                for z in low.2..=high.2 {
+                    grid.entry((x, y, z)).or_default().push(t);
+                }
+            }
+        }
+    }
+
+    // Cosine weighted directions around +Z on a golden angle spiral, turned a little differently
+    // for every vertex so neighbours don't all miss the same gaps
+    let directions: Vec<glm::Vec3> = (0..settings.rays.max(1)).map(|i| {
+        let r = ((i as f32 + 0.5) / settings.rays.max(1) as f32).sqrt();
+        let angle = i as f32 * 2.399_963;
+        glm::vec3(r * angle.cos(), r * angle.sin(), (1.0 - r * r).max(0.0).sqrt())
+    }).collect();
+
+    let vertex_count = mesh.vertices.len() / 3;
+    let normals = &mesh.normals;
+    let occlusion: Vec<f32> = (0..vertex_count as u32).into_par_iter().map(|i| {
+        let position = vertex(i);
+        let normal = glm::vec3(normals[3 * i as usize], normals[3 * i as usize + 1], normals[3 * i as usize + 2]);
+        if glm::length(&normal) < 1e-6 {
+            return 0.0;
+        }
+        let normal = glm::normalize(&normal);
+        let origin = position + normal * (settings.max_distance * 1e-3);
+
+        let home = cell(&position);
+        let mut nearby = vec![];
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    if let Some(cell_triangles) = grid.get(&(home.0 + x, home.1 + y, home.2 + z)) {
+                        nearby.extend_from_slice(cell_triangles);
+                    }
+                }
+            }
+        }
+        nearby.sort_unstable();
+        nearby.dedup();
+        nearby.retain(|&t| !triangles[t].contains(&i));
+
+        let helper = if normal.x.abs() < 0.9 { glm::vec3(1.0, 0.0, 0.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+        let tangent = glm::normalize(&glm::cross(&helper, &normal));
+        let bitangent = glm::cross(&normal, &tangent);
+        let twist = (i as f32 * 0.618_034).fract() * 2.0 * std::f32::consts::PI;
+        let (twist_sin, twist_cos) = twist.sin_cos();
+
+        let mut occluded = 0.0;
+        for direction in &directions {
+            let (x, y) = (direction.x * twist_cos - direction.y * twist_sin, direction.x * twist_sin + direction.y * twist_cos);
+            let ray = tangent * x + bitangent * y + normal * direction.z;
+            let nearest = nearby.iter()
+                .filter_map(|&t| ray_triangle(&origin, &ray, &triangles[t].map(vertex)))
+                .filter(|&distance| distance < settings.max_distance)
+                .fold(None, |nearest: Option<f32>, distance| Some(nearest.map_or(distance, |nearest| nearest.min(distance))));
+            if let Some(distance) = nearest {
+                occluded += 1.0 - distance / settings.max_distance;
+            }
+        }
+        occluded / directions.len() as f32
+    }).collect();
+
+    for (i, occlusion) in occlusion.iter().enumerate() {
+        let light = 1.0 - occlusion * settings.strength;
+        for channel in 0..3 {
+            mesh.colors[4 * i + channel] *= light;
+        }
+    }
+    println!("Baked ambient occlusion for {} vertices in {:.3}ms.", vertex_count, before.elapsed().as_micros() as f32 / 1e3);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The corners and triangles of a box around the origin, `half_size` out to every side
+    fn closed_box(half_size: f32) -> (Vec<f32>, Vec<u32>) {
+        let mut vertices = vec![];
+        for corner in 0..8 {
+            for axis in 0..3 {
+                vertices.push(if corner >> axis & 1 == 1 { half_size } else { -half_size });
+            }
+        }
+        let indices = vec![
+            0, 1, 3, 0, 3, 2,  4, 5, 7, 4, 7, 6, // -Z, +Z
+            0, 1, 5, 0, 5, 4,  2, 3, 7, 2, 7, 6, // -Y, +Y
+            0, 2, 6, 0, 6, 4,  1, 3, 7, 1, 7, 5, // -X, +X
+        ];
+        (vertices, indices)
+    }
+
+    #[test]
+    fn bakes_a_plain_texture_as_its_color() {
+        let color = image::Rgba([255, 51, 0, 255]);
+        let image = image::RgbaImage::from_pixel(4, 4, color);
+        let (vertices, indices) = closed_box(1.0);
+        let mut mesh = Mesh::from_arrays(vertices, vec![], vec![], indices);
+        bake_texture(&mut mesh, &image, Projection::TopDown { tiling: 3.0 });
+        for vertex_color in mesh.colors.chunks(4) {
+            for (baked, expected) in vertex_color.iter().zip(&color.0) {
+                assert!((baked - *expected as f32 / 255.0).abs() < 1e-5, "{:?}", vertex_color);
+            }
+        }
+    }
+
+    #[test]
+    fn a_vertex_inside_a_box_is_more_occluded_than_one_outside() {
+        let (mut vertices, mut indices) = closed_box(1.0);
+        // A small triangle in the middle of the box and one well above it
+        for y in [0.0, 5.0] {
+            let first = vertices.len() as u32 / 3;
+            vertices.extend_from_slice(&[0.0, y, 0.0,  0.1, y, 0.0,  0.0, y, 0.1]);
+            indices.extend_from_slice(&[first, first + 1, first + 2]);
+        }
+        let normals = [0.0, 1.0, 0.0].repeat(vertices.len() / 3);
+        let mut mesh = Mesh::from_arrays(vertices, normals, vec![], indices);
+        bake_ambient_occlusion(&mut mesh, &AmbientOcclusion::default());
+
+        let (inside, outside) = (mesh.colors[4 * 8], mesh.colors[4 * 11]);
+        assert!(inside < outside, "inside {}, outside {}", inside, outside);
+        assert_eq!(outside, 1.0);
+    }
+}
@@ -0,0 +1,365 @@
+extern crate nalgebra_glm as glm;
+
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::time::Instant;
+
+use crate::camera::Camera;
+use crate::gfx;
+use crate::gl_caps::GlCaps;
+use crate::path::Path;
+use crate::scene_graph;
+
+// * Benchmark mode
+// `--benchmark [seconds]` takes the camera away from the controls and flies it along a fixed loop
+// over the scene, recording every frame: how long it took, how long the GPU was busy with it and how
+// many draw calls it made. At the end a report is written, every frame as CSV and the percentiles as
+// JSON, and the program exits. Two commits run on the same machine can then be compared by numbers
+// instead of by watching the frame rate.
+//
+// The camera goes around the loop once in the given time whatever the frame rate, so every run sees
+// the same views. Vsync and the frame cap are turned off for it (see main.rs), and the first seconds
+// are a warm up that isn't recorded: the shaders compiling, the textures going up, the caches filling.
+//
+//   cargo run --release -- --benchmark 60 --benchmark-output results/before
+
+pub struct BenchmarkOptions {
+    pub duration : f32,     // Seconds recorded, after the warm up
+    pub warmup   : f32,     // Seconds at the start of the loop before recording
+    pub output   : PathBuf, // The report goes into this with .csv and .json
+}
+
+impl BenchmarkOptions {
+    /// The options on the command line, None without `--benchmark`.
+    /// - `--benchmark [seconds]`: Run it, for 30 seconds unless given.
+    /// - `--benchmark-warmup <seconds>`: Not recorded at the start, 3 by default.
+    /// - `--benchmark-output <path>`: Where the report goes, `benchmark` by default.
+    pub fn from_args<I: Iterator<Item = String>>(args: I) -> Option<BenchmarkOptions> {
+        let mut options = BenchmarkOptions { duration: 30.0, warmup: 3.0, output: PathBuf::from("benchmark") };
+        let mut enabled = false;
+        let mut args = args.peekable();
+        while let Some(arg) = args.next() {
+            let seconds = |value: Option<&String>| value.and_then(|value| value.parse::<f32>().ok()).filter(|seconds| *seconds >= 0.0);
+            match arg.as_str() {
+                "--benchmark" => {
+                    enabled = true;
+                    if let Some(duration) = seconds(args.peek()) {
+                        options.duration = duration.max(1.0);
+                        args.next();
+                    }
+                }
+                "--benchmark-warmup" => match seconds(args.next().as_ref()) {
+                    Some(warmup) => options.warmup = warmup,
+                    None => println!("--benchmark-warmup needs a number of seconds"),
+                },
+                "--benchmark-output" => match args.next() {
+                    Some(output) => options.output = PathBuf::from(output),
+                    None => println!("--benchmark-output needs a path"),
+                },
+                _ => println!("Unknown argument {}", arg),
+            }
+        }
+        if enabled { Some(options) } else { None }
+    }
+}
+
+// What was recorded of one frame
+#[derive(Clone, Copy, Debug)]
+struct FrameSample {
+    time       : f32,         // Seconds since recording started, at the end of the frame
+    frame_ms   : f32,         // Since the end of the frame before
+    gpu_ms     : Option<f32>, // Without timer queries, or if the result never came back
+    draw_calls : u32,
+}
+
+// * GPU timings
+// A TIME_ELAPSED query around every frame. Its result is only there a few frames later, so there is
+// a ring of them and each is read back right before it is used again, by when the GPU is long done.
+const QUERY_FRAMES: usize = 4;
+
+struct GpuTimer {
+    queries : [u32; QUERY_FRAMES],
+    samples : [Option<usize>; QUERY_FRAMES], // The sample each query in flight is for
+    next    : usize,
+    running : bool,                          // Between begin() and end()
+}
+
+impl GpuTimer {
+    unsafe fn new() -> GpuTimer {
+        let mut queries = [0; QUERY_FRAMES];
+        gl::GenQueries(QUERY_FRAMES as i32, queries.as_mut_ptr());
+        GpuTimer { queries, samples: [None; QUERY_FRAMES], next: 0, running: false }
+    }
+
+    unsafe fn begin(&mut self, sample: usize, recorded: &mut [FrameSample]) {
+        self.read(self.next, recorded);
+        gl::BeginQuery(gl::TIME_ELAPSED, self.queries[self.next]);
+        self.samples[self.next] = Some(sample);
+        self.running = true;
+    }
+
+    unsafe fn end(&mut self) {
+        gl::EndQuery(gl::TIME_ELAPSED);
+        self.next = (self.next + 1) % QUERY_FRAMES;
+        self.running = false;
+    }
+
+    // Ends the query of a frame that never got to end(), its timing is of half a frame and dropped.
+    // Left running, the next begin() would be an INVALID_OPERATION.
+    unsafe fn abandon(&mut self) {
+        if self.running {
+            gl::EndQuery(gl::TIME_ELAPSED);
+            self.samples[self.next] = None;
+            self.running = false;
+        }
+    }
+
+    // Waits for the result if it isn't in yet
+    unsafe fn read(&mut self, slot: usize, recorded: &mut [FrameSample]) {
+        if let Some(sample) = self.samples[slot].take() {
+            let mut nanoseconds: u64 = 0;
+            gl::GetQueryObjectui64v(self.queries[slot], gl::QUERY_RESULT, &mut nanoseconds);
+            if let Some(frame) = recorded.get_mut(sample) {
+                frame.gpu_ms = Some(nanoseconds as f32 / 1.0e6);
+            }
+        }
+    }
+
+    unsafe fn finish(&mut self, recorded: &mut [FrameSample]) {
+        for slot in 0..QUERY_FRAMES {
+            self.read(slot, recorded);
+        }
+        gl::DeleteQueries(QUERY_FRAMES as i32, self.queries.as_ptr());
+    }
+}
+
+pub struct Benchmark {
+    pub options      : BenchmarkOptions,
+    camera_path      : Path,
+    started          : Option<Instant>, // The first frame
+    last_frame_end   : Option<Instant>,
+    recording        : bool,            // The frame in progress is recorded
+    frame_draw_calls : u64,             // gfx::draw_call_count at its start
+    samples          : Vec<FrameSample>,
+    timer            : Option<GpuTimer>,
+}
+
+impl Benchmark {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(options: BenchmarkOptions, gl_caps: &GlCaps) -> Benchmark {
+        // Across the flight area, low past the landing pad and the base, then far out where the
+        // impostors take over, and back. Speeds don't matter, the loop is timed by the duration.
+        let camera_path = Path::new(1.0, true)
+            .with_waypoint(glm::vec3(   0.0,  60.0,  150.0), None)
+            .with_waypoint(glm::vec3( -70.0,  20.0,   70.0), None)
+            .with_waypoint(glm::vec3( -40.0,  12.0,   10.0), None)
+            .with_waypoint(glm::vec3(-110.0,  35.0,  -70.0), None)
+            .with_waypoint(glm::vec3(  60.0,  30.0, -100.0), None)
+            .with_waypoint(glm::vec3( 260.0, 110.0,    0.0), None)
+            .with_waypoint(glm::vec3(  90.0,  40.0,  110.0), None);
+        if !gl_caps.timer_queries {
+            println!("Benchmark: no timer queries on this driver, the report has no GPU timings");
+        }
+        Benchmark {
+            options,
+            camera_path,
+            started: None,
+            last_frame_end: None,
+            recording: false,
+            frame_draw_calls: 0,
+            samples: vec![],
+            timer: if gl_caps.timer_queries { Some(GpuTimer::new()) } else { None },
+        }
+    }
+
+    fn elapsed(&self) -> f32 {
+        self.started.map_or(0.0, |started| started.elapsed().as_secs_f32())
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.started.is_some() && self.elapsed() >= self.options.warmup + self.options.duration
+    }
+
+    // Where the camera is, going around the loop once over the recorded time (and holding still at
+    // the start of it during the warm up), always looking at the middle of the flight area
+    pub fn camera(&self) -> Camera {
+        let progress = ((self.elapsed() - self.options.warmup) / self.options.duration).clamp(0.0, 1.0);
+        let position = self.camera_path.position_at(progress * self.camera_path.total_length());
+        let mut camera = Camera::new(scene_graph::position_from_f32(&position));
+        camera.look_at(glm::vec3(0.0, 10.0, 0.0));
+        camera
+    }
+
+    /// Call at the start of every frame, before anything is drawn.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn begin_frame(&mut self) {
+        if self.started.is_none() {
+            println!("Benchmark: {} s warm up, then {} s recorded", self.options.warmup, self.options.duration);
+            self.started = Some(Instant::now());
+        }
+        // Only what this frame draws is counted
+        self.frame_draw_calls = gfx::draw_call_count();
+        self.recording = self.elapsed() >= self.options.warmup && self.last_frame_end.is_some();
+        if let (true, Some(timer)) = (self.recording, self.timer.as_mut()) {
+            timer.begin(self.samples.len(), &mut self.samples);
+        }
+    }
+
+    /// Call at the end of every frame, right before the buffers are swapped.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn end_frame(&mut self) {
+        let now = Instant::now();
+        if self.recording {
+            if let Some(timer) = self.timer.as_mut() {
+                timer.end();
+            }
+            let frame_start = self.last_frame_end.unwrap_or(now);
+            self.samples.push(FrameSample {
+                time: self.elapsed() - self.options.warmup,
+                frame_ms: now.duration_since(frame_start).as_secs_f32() * 1000.0,
+                gpu_ms: None,
+                draw_calls: (gfx::draw_call_count() - self.frame_draw_calls) as u32,
+            });
+        }
+        self.last_frame_end = Some(now);
+    }
+
+    /// Call when a frame panicked between begin_frame() and end_frame(), instead of end_frame().
+    /// The frame isn't recorded.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn reset_after_panic(&mut self) {
+        if let Some(timer) = self.timer.as_mut() {
+            timer.abandon();
+        }
+        self.recording = false;
+    }
+
+    /// Collect the last GPU timings, then write the report and print a summary of it.
+    /// - `window_size`: What was drawn at, in pixels.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn finish(mut self, gl_caps: &GlCaps, window_size: (u32, u32)) {
+        if let Some(mut timer) = self.timer.take() {
+            timer.finish(&mut self.samples);
+        }
+
+        let frame_ms = Summary::of(self.samples.iter().map(|sample| sample.frame_ms).collect());
+        let gpu_ms = Summary::of(self.samples.iter().filter_map(|sample| sample.gpu_ms).collect());
+        let draw_calls = Summary::of(self.samples.iter().map(|sample| sample.draw_calls as f32).collect());
+        let frame_count = self.samples.len();
+        let average_fps = frame_ms.map_or(0.0, |summary| 1000.0 / summary.mean.max(1e-6));
+
+        println!("Benchmark: {} frames in {} s, {:.1} fps on average", frame_count, self.options.duration, average_fps);
+        for (name, summary) in [("frame time", frame_ms), ("GPU time", gpu_ms)] {
+            if let Some(summary) = summary {
+                println!("  {} (ms): mean {:.2}, p50 {:.2}, p95 {:.2}, p99 {:.2}, max {:.2}",
+                    name, summary.mean, summary.p50, summary.p95, summary.p99, summary.max);
+            }
+        }
+        if let Some(summary) = draw_calls {
+            println!("  draw calls: mean {:.0}, max {:.0}", summary.mean, summary.max);
+        }
+
+        // Every frame
+        let mut csv = String::from("frame,time_s,frame_ms,gpu_ms,draw_calls\n");
+        for (i, sample) in self.samples.iter().enumerate() {
+            let gpu_ms = sample.gpu_ms.map_or(String::new(), |gpu_ms| format!("{:.4}", gpu_ms));
+            let _ = writeln!(csv, "{},{:.4},{:.4},{},{}", i, sample.time, sample.frame_ms, gpu_ms, sample.draw_calls);
+        }
+
+        // The summary, written out by hand to not need a JSON crate for it
+        let mut json = String::from("{\n");
+        let _ = writeln!(json, "  \"commit\": \"{}\",", git_commit());
+        let _ = writeln!(json, "  \"timestamp\": {},", std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH).map_or(0, |since| since.as_secs()));
+        let _ = writeln!(json, "  \"renderer\": \"{}\",", json_escape(&gl_caps.renderer));
+        let _ = writeln!(json, "  \"vendor\": \"{}\",", json_escape(&gl_caps.vendor));
+        let _ = writeln!(json, "  \"opengl\": \"{}.{}\",", gl_caps.version.0, gl_caps.version.1);
+        let _ = writeln!(json, "  \"resolution\": [{}, {}],", window_size.0, window_size.1);
+        let _ = writeln!(json, "  \"duration_s\": {},", self.options.duration);
+        let _ = writeln!(json, "  \"warmup_s\": {},", self.options.warmup);
+        let _ = writeln!(json, "  \"frames\": {},", frame_count);
+        let _ = writeln!(json, "  \"average_fps\": {:.2},", average_fps);
+        let _ = writeln!(json, "  \"frame_ms\": {},", Summary::to_json(frame_ms));
+        let _ = writeln!(json, "  \"gpu_ms\": {},", Summary::to_json(gpu_ms));
+        let _ = writeln!(json, "  \"draw_calls\": {}", Summary::to_json(draw_calls));
+        json.push_str("}\n");
+
+        let csv_path = self.options.output.with_extension("csv");
+        let json_path = self.options.output.with_extension("json");
+        if let Some(directory) = csv_path.parent().filter(|directory| !directory.as_os_str().is_empty()) {
+            let _ = std::fs::create_dir_all(directory);
+        }
+        for (path, contents) in [(&csv_path, csv), (&json_path, json)] {
+            match std::fs::write(path, contents) {
+                Ok(()) => println!("Benchmark report written to {}", path.display()),
+                Err(error) => println!("Could not write the benchmark report to {}: {}", path.display(), error),
+            }
+        }
+    }
+}
+
+// * Percentiles
+#[derive(Clone, Copy, Debug)]
+struct Summary {
+    mean : f32,
+    p50  : f32,
+    p90  : f32,
+    p95  : f32,
+    p99  : f32,
+    max  : f32,
+}
+
+impl Summary {
+    // None for no values
+    fn of(mut values: Vec<f32>) -> Option<Summary> {
+        if values.is_empty() {
+            return None;
+        }
+        values.sort_by(|a, b| a.total_cmp(b));
+        // Nearest rank: the smallest value with at least `percent` of them at or below it
+        let percentile = |percent: f32| {
+            let rank = (percent / 100.0 * values.len() as f32).ceil() as usize;
+            values[rank.clamp(1, values.len()) - 1]
+        };
+        Some(Summary {
+            mean: values.iter().sum::<f32>() / values.len() as f32,
+            p50: percentile(50.0),
+            p90: percentile(90.0),
+            p95: percentile(95.0),
+            p99: percentile(99.0),
+            max: values[values.len() - 1],
+        })
+    }
+
+    fn to_json(summary: Option<Summary>) -> String {
+        match summary {
+            Some(s) => format!("{{ \"mean\": {:.4}, \"p50\": {:.4}, \"p90\": {:.4}, \"p95\": {:.4}, \"p99\": {:.4}, \"max\": {:.4} }}",
+                s.mean, s.p50, s.p90, s.p95, s.p99, s.max),
+            None => String::from("null"),
+        }
+    }
+}
+
+// The commit the program was run from, so reports can be told apart. "unknown" outside of a checkout.
+fn git_commit() -> String {
+    std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .map(|output| String::from_utf8_lossy(&output.stdout).trim().to_string())
+        .unwrap_or_else(|| String::from("unknown"))
+}
+
+fn json_escape(text: &str) -> String {
+    text.replace('\\', "\\\\").replace('"', "\\\"")
+}
@@ -0,0 +1,214 @@
+extern crate nalgebra_glm as glm;
+
+use crate::keyframes::Track;
+use crate::scene_graph::{self, SceneNode};
+
+// * Animation blending
+// An AnimationClip is a set of keyframe Tracks over a node subtree, one per node it moves, each node
+// found by its path of child indices from the subtree's root (the way get_child counts them). Unlike
+// the KeyframePlayer, a clip doesn't set the pose of its nodes, it is added on top of whatever else
+// put them there this frame (a path, HelicopterPose): its tracks are offsets from that pose.
+//
+// The AnimationBlender plays any number of clips on one subtree, each with a weight, and fades the
+// weights over time. Every node gets the weighted sum of the offsets of the clips that move it; past a
+// total weight of 1 it is the weighted average instead, so two clips at full weight meet halfway.
+// Rotations are blended per Euler angle like the Tracks are, which is fine for the small tilts clips
+// are for. A Mask keeps a clip off the parts of the subtree that something else is moving.
+//
+// A node nobody else sets every frame would pile the offsets up, so the blender remembers where it
+// left every node. A node that is still there goes back to its old base before the new offset goes
+// on, one that has been moved since takes where it is now as its new base.
+
+// The nodes of a subtree a clip may move, everything but the excluded subtrees
+#[derive(Clone, Debug, Default)]
+pub struct Mask {
+    excluded : Vec<Vec<usize>>,
+}
+
+impl Mask {
+    pub fn all() -> Mask {
+        Mask::default()
+    }
+
+    /// Leaves out the node at `path` and everything under it, builder style.
+    pub fn without(mut self, path: &[usize]) -> Mask {
+        self.excluded.push(path.to_vec());
+        self
+    }
+
+    pub fn includes(&self, path: &[usize]) -> bool {
+        !self.excluded.iter().any(|excluded| path.starts_with(excluded))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AnimationClip {
+    pub channels : Vec<(Vec<usize>, Track)>, // The path of a node from the root, and its offsets
+    pub looping  : bool,                     // Otherwise it holds the last keyframe
+}
+
+impl AnimationClip {
+    pub fn new(looping: bool) -> AnimationClip {
+        AnimationClip { channels: vec![], looping }
+    }
+
+    /// Moves the node at `path` (empty for the root), builder style like Track::with_keyframe.
+    pub fn with_channel(mut self, path: &[usize], track: Track) -> AnimationClip {
+        self.channels.push((path.to_vec(), track));
+        self
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.channels.iter().map(|(_, track)| track.duration()).fold(0.0, f32::max)
+    }
+}
+
+struct Layer {
+    name          : &'static str,
+    clip          : AnimationClip,
+    mask          : Mask,
+    time          : f32,
+    weight        : f32,
+    target_weight : f32,
+    fade_rate     : f32, // Weight per second towards the target
+}
+
+// Where the blender left a node, see the top of the file
+struct Applied {
+    path            : Vec<usize>,
+    base_position   : scene_graph::Position,
+    base_rotation   : glm::Vec3,
+    result_position : scene_graph::Position,
+    result_rotation : glm::Vec3,
+}
+
+pub struct AnimationBlender {
+    layers  : Vec<Layer>,
+    applied : Vec<Applied>,
+}
+
+impl AnimationBlender {
+    pub fn new() -> AnimationBlender {
+        AnimationBlender { layers: vec![], applied: vec![] }
+    }
+
+    /// Adds `clip` as the layer `name`, at a weight of 0 until it is faded in. A layer that is
+    /// already there gets the new clip and mask, and keeps its weight.
+    pub fn add(&mut self, name: &'static str, clip: AnimationClip, mask: Mask) {
+        match self.layers.iter_mut().find(|layer| layer.name == name) {
+            Some(layer) => {
+                layer.clip = clip;
+                layer.mask = mask;
+            }
+            None => self.layers.push(Layer { name, clip, mask, time: 0.0, weight: 0.0, target_weight: 0.0, fade_rate: 0.0 }),
+        }
+    }
+
+    /// Moves the weight of the layer `name` to `weight` over `duration` seconds.
+    pub fn fade(&mut self, name: &str, weight: f32, duration: f32) {
+        if let Some(layer) = self.layers.iter_mut().find(|layer| layer.name == name) {
+            layer.target_weight = weight;
+            layer.fade_rate = if duration > 0.0 { (weight - layer.weight).abs() / duration } else { f32::INFINITY };
+        }
+    }
+
+    /// Fades the layer `name` in to full weight and every other layer out, over `duration` seconds.
+    pub fn crossfade(&mut self, name: &str, duration: f32) {
+        let others: Vec<&'static str> = self.layers.iter().map(|layer| layer.name).filter(|other| *other != name).collect();
+        for other in others {
+            self.fade(other, 0.0, duration);
+        }
+        self.fade(name, 1.0, duration);
+    }
+
+    // The layer whose weight is headed highest, None before anything was faded in
+    pub fn leading(&self) -> Option<&'static str> {
+        self.layers.iter()
+            .filter(|layer| layer.target_weight > 0.0)
+            .max_by(|a, b| a.target_weight.total_cmp(&b.target_weight))
+            .map(|layer| layer.name)
+    }
+
+    // Move the clips and the fades along, and pose the subtree under `root`
+    pub fn update(&mut self, root: &mut SceneNode, delta_time: f32) {
+        for layer in &mut self.layers {
+            let step = layer.fade_rate * delta_time;
+            layer.weight = if layer.weight < layer.target_weight {
+                (layer.weight + step).min(layer.target_weight)
+            } else {
+                (layer.weight - step).max(layer.target_weight)
+            };
+            layer.time += delta_time;
+            let duration = layer.clip.duration();
+            if layer.clip.looping && duration > 0.0 && layer.time >= duration {
+                layer.time %= duration;
+            }
+        }
+
+        // Every node any clip moves, even when its layer is faded out, so it goes back to its base
+        let mut paths: Vec<Vec<usize>> = self.applied.iter().map(|applied| applied.path.clone()).collect();
+        for layer in &self.layers {
+            for (path, _) in &layer.clip.channels {
+                if !paths.contains(path) {
+                    paths.push(path.clone());
+                }
+            }
+        }
+
+        for path in paths {
+            let node = match find_path(root, &path) {
+                Some(node) => node,
+                None => continue,
+            };
+
+            let (mut position_offset, mut rotation_offset, mut total_weight) = (glm::Vec3::zeros(), glm::Vec3::zeros(), 0.0);
+            for layer in self.layers.iter().filter(|layer| layer.weight > 0.0 && layer.mask.includes(&path)) {
+                for (_, track) in layer.clip.channels.iter().filter(|(channel_path, _)| *channel_path == path) {
+                    let (position, rotation) = track.sample(layer.time);
+                    position_offset += position * layer.weight;
+                    rotation_offset += rotation * layer.weight;
+                }
+                total_weight += layer.weight;
+            }
+            if total_weight > 1.0 {
+                position_offset /= total_weight;
+                rotation_offset /= total_weight;
+            }
+
+            let index = match self.applied.iter().position(|applied| applied.path == path) {
+                Some(index) => index,
+                None => {
+                    self.applied.push(Applied {
+                        path,
+                        base_position: node.position,
+                        base_rotation: node.rotation,
+                        result_position: node.position,
+                        result_rotation: node.rotation,
+                    });
+                    self.applied.len() - 1
+                }
+            };
+            let applied = &mut self.applied[index];
+            if node.position != applied.result_position || node.rotation != applied.result_rotation {
+                applied.base_position = node.position;
+                applied.base_rotation = node.rotation;
+            }
+            node.position = applied.base_position + scene_graph::position_from_f32(&position_offset);
+            node.rotation = applied.base_rotation + rotation_offset;
+            applied.result_position = node.position;
+            applied.result_rotation = node.rotation;
+        }
+    }
+}
+
+// The node at `path` of child indices under `root`, if the subtree has one there
+fn find_path<'a>(root: &'a mut SceneNode, path: &[usize]) -> Option<&'a mut SceneNode> {
+    let mut node = root;
+    for &index in path {
+        if index >= node.children.len() {
+            return None;
+        }
+        node = node.get_child(index);
+    }
+    Some(node)
+}
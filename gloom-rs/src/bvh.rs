@@ -0,0 +1,210 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::Mesh;
+
+// * Bounding volume hierarchy over the triangles of a mesh
+// Testing a ray against every triangle of the terrain is hundreds of thousands of tests, too many to do
+// every frame. The BVH is a binary tree of boxes: each node's box holds its triangles, split in two
+// halves along the longest side of the box until only a few triangles are left in each leaf. A ray only
+// visits the boxes it goes through, nearest first, and stops looking once the nearest hit is closer
+// than the next box. Built once per mesh (in mesh space) and rebuilt if the mesh changes.
+
+const LEAF_SIZE: usize = 4;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub origin    : glm::Vec3,
+    pub direction : glm::Vec3, // Doesn't have to be normalized, distances are in lengths of it
+}
+
+impl Ray {
+    pub fn new(origin: glm::Vec3, direction: glm::Vec3) -> Ray {
+        Ray { origin, direction }
+    }
+
+    pub fn at(&self, distance: f32) -> glm::Vec3 {
+        self.origin + self.direction * distance
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub distance : f32,
+    pub position : glm::Vec3,
+    pub normal   : glm::Vec3, // Of the triangle, facing the way its corners wind counterclockwise
+    pub triangle : usize,     // Index of the triangle in the mesh, its corners are indices[3 * triangle..]
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Aabb {
+    min : glm::Vec3,
+    max : glm::Vec3,
+}
+
+impl Aabb {
+    fn empty() -> Aabb {
+        Aabb { min: glm::vec3(f32::MAX, f32::MAX, f32::MAX), max: glm::vec3(f32::MIN, f32::MIN, f32::MIN) }
+    }
+
+    fn grow(&mut self, point: &glm::Vec3) {
+        self.min = glm::min2(&self.min, point);
+        self.max = glm::max2(&self.max, point);
+    }
+
+    // Slab test, gives the distance where the ray goes in (0 if it starts inside)
+    fn hit(&self, origin: &glm::Vec3, inverse_direction: &glm::Vec3, max_distance: f32) -> Option<f32> {
+        let mut near: f32 = 0.0;
+        let mut far = max_distance;
+        for axis in 0..3 {
+            let t1 = (self.min[axis] - origin[axis]) * inverse_direction[axis];
+            let t2 = (self.max[axis] - origin[axis]) * inverse_direction[axis];
+            near = near.max(t1.min(t2));
+            far = far.min(t1.max(t2));
+        }
+        if near <= far { Some(near) } else { None }
+    }
+}
+
+// Leaves have `count` triangles starting at `first` in Bvh::order, inner nodes have count 0 and
+// their children at `first` and `first + 1`
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    bounds : Aabb,
+    first  : usize,
+    count  : usize,
+}
+
+pub struct Bvh {
+    nodes     : Vec<BvhNode>,
+    triangles : Vec<[glm::Vec3; 3]>,
+    order     : Vec<usize>, // Triangle indices, grouped so every leaf's triangles are next to each other
+}
+
+impl Bvh {
+    pub fn from_mesh(mesh: &Mesh) -> Bvh {
+        let vertex = |i: u32| glm::vec3(mesh.vertices[3 * i as usize], mesh.vertices[3 * i as usize + 1], mesh.vertices[3 * i as usize + 2]);
+        let triangles: Vec<[glm::Vec3; 3]> = mesh.indices.chunks(3)
+            .filter(|triangle| triangle.len() == 3)
+            .map(|triangle| [vertex(triangle[0]), vertex(triangle[1]), vertex(triangle[2])])
+            .collect();
+        Bvh::new(triangles)
+    }
+
+    pub fn new(triangles: Vec<[glm::Vec3; 3]>) -> Bvh {
+        let centroids: Vec<glm::Vec3> = triangles.iter().map(|[a, b, c]| (a + b + c) / 3.0).collect();
+        let mut bvh = Bvh { nodes: vec![], triangles, order: (0..centroids.len()).collect() };
+        bvh.nodes.push(BvhNode { bounds: Aabb::empty(), first: 0, count: centroids.len() });
+        bvh.subdivide(0, &centroids);
+        bvh
+    }
+
+    fn subdivide(&mut self, node_index: usize, centroids: &[glm::Vec3]) {
+        let BvhNode { first, count, .. } = self.nodes[node_index];
+        let mut bounds = Aabb::empty();
+        let mut centroid_bounds = Aabb::empty();
+        for &triangle in &self.order[first..first + count] {
+            for corner in &self.triangles[triangle] {
+                bounds.grow(corner);
+            }
+            centroid_bounds.grow(&centroids[triangle]);
+        }
+        self.nodes[node_index].bounds = bounds;
+        if count <= LEAF_SIZE {
+            return;
+        }
+
+        // Split at the middle of the longest side, by triangle count so both halves always get some
+        let extent = centroid_bounds.max - centroid_bounds.min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z { 0 } else if extent.y >= extent.z { 1 } else { 2 };
+        if extent[axis] <= 0.0 {
+            return; // All the centroids are in the same place, no way to split them
+        }
+        let half = count / 2;
+        self.order[first..first + count].select_nth_unstable_by(half, |&a, &b| centroids[a][axis].total_cmp(&centroids[b][axis]));
+
+        let children = self.nodes.len();
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first, count: half });
+        self.nodes.push(BvhNode { bounds: Aabb::empty(), first: first + half, count: count - half });
+        self.nodes[node_index] = BvhNode { bounds, first: children, count: 0 };
+        self.subdivide(children, centroids);
+        self.subdivide(children + 1, centroids);
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+
+    // The nearest triangle the ray goes through, no further than `max_distance`
+    pub fn closest_hit(&self, ray: &Ray, max_distance: f32) -> Option<RayHit> {
+        let inverse_direction = glm::vec3(1.0 / ray.direction.x, 1.0 / ray.direction.y, 1.0 / ray.direction.z);
+        let mut closest: Option<(f32, usize)> = None;
+        let mut stack = vec![0];
+
+        while let Some(node_index) = stack.pop() {
+            let node = &self.nodes[node_index];
+            let limit = closest.map_or(max_distance, |(distance, _)| distance);
+            if node.bounds.hit(&ray.origin, &inverse_direction, limit).is_none() {
+                continue;
+            }
+            if node.count > 0 {
+                for &triangle in &self.order[node.first..node.first + node.count] {
+                    if let Some(distance) = ray_triangle(&ray.origin, &ray.direction, &self.triangles[triangle]) {
+                        if distance <= closest.map_or(max_distance, |(closest, _)| closest) {
+                            closest = Some((distance, triangle));
+                        }
+                    }
+                }
+                continue;
+            }
+
+            // Push the far child first so the near one is looked at first
+            let (left, right) = (node.first, node.first + 1);
+            let left_distance = self.nodes[left].bounds.hit(&ray.origin, &inverse_direction, limit);
+            let right_distance = self.nodes[right].bounds.hit(&ray.origin, &inverse_direction, limit);
+            match (left_distance, right_distance) {
+                (Some(l), Some(r)) if l <= r => stack.extend_from_slice(&[right, left]),
+                (Some(_), Some(_)) => stack.extend_from_slice(&[left, right]),
+                (Some(_), None) => stack.push(left),
+                (None, Some(_)) => stack.push(right),
+                (None, None) => {}
+            }
+        }
+
+        closest.map(|(distance, triangle)| {
+            let [a, b, c] = self.triangles[triangle];
+            RayHit {
+                distance,
+                position: ray.at(distance),
+                normal: glm::normalize(&glm::cross(&(b - a), &(c - a))),
+                triangle,
+            }
+        })
+    }
+}
+
+// Möller–Trumbore, distance along the ray to where it goes through the triangle
+pub fn ray_triangle(origin: &glm::Vec3, direction: &glm::Vec3, corners: &[glm::Vec3; 3]) -> Option<f32> {
+    let edge1 = corners[1] - corners[0];
+    let edge2 = corners[2] - corners[0];
+    let p = glm::cross(direction, &edge2);
+    let determinant = glm::dot(&edge1, &p);
+    if determinant.abs() < 1e-8 {
+        return None;
+    }
+    let to_origin = origin - corners[0];
+    let u = glm::dot(&to_origin, &p) / determinant;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = glm::cross(&to_origin, &edge1);
+    let v = glm::dot(direction, &q) / determinant;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let distance = glm::dot(&edge2, &q) / determinant;
+    if distance > 0.0 { Some(distance) } else { None }
+}
@@ -0,0 +1,122 @@
+extern crate nalgebra_glm as glm;
+
+use crate::math;
+use crate::scene_graph::{self, Position};
+use crate::settings::CameraProjection;
+
+// * Camera
+// Position plus yaw/pitch, the same way the fly-cam has always worked (see math::calculate_direction).
+// The position is as precise as the scene's (see scene_graph::Real).
+// Pulled out into a struct so several views (split screen, chase cams...) can each have their own.
+
+#[derive(Clone, Copy)]
+pub struct Camera {
+    pub position : Position,
+    pub yaw      : f32, // radians
+    pub pitch    : f32, // radians, clamped to straight up/down
+}
+
+impl Camera {
+    pub fn new(position: Position) -> Camera {
+        Camera {
+            position,
+            yaw: 0.0,
+            pitch: 0.0,
+        }
+    }
+
+    // For what doesn't need the position precise: the shaders, the sound, the culling
+    pub fn position_f32(&self) -> glm::Vec3 {
+        scene_graph::position_to_f32(&self.position)
+    }
+
+    pub fn forward(&self) -> glm::Vec3 {
+        math::calculate_direction(self.yaw, self.pitch)
+    }
+
+    pub fn right(&self) -> glm::Vec3 {
+        math::camera_basis(self.yaw, self.pitch).1
+    }
+
+    pub fn up(&self) -> glm::Vec3 {
+        math::camera_basis(self.yaw, self.pitch).2
+    }
+
+    // Turn by a mouse movement (in pixels)
+    pub fn rotate(&mut self, delta_x: f32, delta_y: f32, sensitivity: f32) {
+        self.pitch -= delta_y * sensitivity; // Update pitch (vertical)
+        self.yaw += delta_x * sensitivity;   // Update yaw (horizontal)
+
+        // Clamp the pitch value to avoid excessive rotation
+        self.pitch = self.pitch.clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
+    }
+
+    // Turn the camera so it looks at a point
+    pub fn look_at(&mut self, target: glm::Vec3) {
+        if let Some((yaw, pitch)) = math::yaw_pitch_towards(&(target - self.position_f32())) {
+            self.yaw = yaw;
+            self.pitch = pitch;
+        }
+    }
+
+    // * Chase cam
+    // Put the camera behind and above something flying along with the given heading yaw.
+    // The helicopters' yaw is PI + atan2(dx, dz) of their movement (see simple_heading_animation).
+    pub fn chase(&mut self, target: glm::Vec3, target_yaw: f32, distance: f32, height: f32) {
+        let moving_direction = glm::vec3(-target_yaw.sin(), 0.0, -target_yaw.cos());
+        self.position = scene_graph::position_from_f32(&(target - moving_direction * distance + glm::vec3(0.0, height, 0.0)));
+        self.look_at(target);
+    }
+
+    pub fn view_projection(&self, aspect_ratio: f32, projection: &CameraProjection) -> glm::Mat4 {
+        math::calculate_transformation_from_camera_to_world_view(
+            aspect_ratio,
+            projection.near,
+            projection.far,
+            projection.reversed_z,
+            self.position_f32(),
+            self.forward(),
+            self.up(),
+        )
+    }
+
+    // The same, but of the camera moved to the origin, see CameraOrigin
+    pub fn relative_view_projection(&self, aspect_ratio: f32, projection: &CameraProjection) -> glm::Mat4 {
+        math::calculate_transformation_from_camera_to_world_view(
+            aspect_ratio,
+            projection.near,
+            projection.far,
+            projection.reversed_z,
+            glm::zero(),
+            self.forward(),
+            self.up(),
+        )
+    }
+}
+
+
+// * Camera relative rendering
+// Far out on a big terrain the positions are large numbers, and in the view projection times the
+// model matrix their translations cancel out, with only the last few bits of an f32 left over. Then
+// everything shakes as the camera moves. Instead the world is moved so the camera sits at the origin:
+// the camera's position is taken out of the model matrix first, while both are still exact, and
+// what is left goes through the view projection of the camera at the origin.
+
+#[derive(Clone, Copy)]
+pub struct CameraOrigin {
+    pub position                 : Position,
+    pub view_projection          : glm::Mat4, // The usual one, that this stands in for
+    pub relative_view_projection : glm::Mat4, // With the camera at the origin
+}
+
+impl CameraOrigin {
+    // `position` is where `model_matrix` puts the origin of the model, as precise as the scene has it
+    pub fn model_view_projection(&self, model_matrix: &glm::Mat4, position: &Position) -> glm::Mat4 {
+        let relative_position = scene_graph::position_to_f32(&(position - self.position));
+        let mut relative_model_matrix = *model_matrix;
+        for row in 0..3 {
+            relative_model_matrix[(row, 3)] = relative_position[row];
+        }
+        self.relative_view_projection * relative_model_matrix
+    }
+}
@@ -0,0 +1,223 @@
+extern crate nalgebra_glm as glm;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::gfx;
+use crate::time_of_day::TimeOfDay;
+use crate::toolbox;
+
+// * Stars and the Earth
+// What is behind the sky: a few thousand stars as point sprites, and the Earth hanging over the horizon.
+// They are drawn first in every view, right after it is cleared to the sky color, infinitely far away
+// and without depth, so everything in the scene goes in front of them.
+//
+// The stars are scattered over the celestial sphere from a seed, thicker along a band like the Milky
+// Way, mostly faint with a few bright ones, and colored from orange to blue. The sphere turns with the
+// time of day (see TimeOfDay::sky_rotation), so the stars rise and set with the sun. They fade out as
+// the sky gets light and behind the haze of the weather. There is no air on the moon to make them
+// twinkle, they do anyway because a still starfield looks like a texture.
+//
+// The moon always shows the Earth the same side, so from the surface the Earth stays in the same
+// place in the sky while the stars turn behind it. It is a quad facing the camera with a lit sphere
+// worked out in the fragment shader, its continents, ice and clouds are in a texture made from noise
+// when the program starts. The sun lights the half facing it, so it goes through its phases over the
+// day, and it spins around about 29.5 times for every day of the moon.
+
+// Radians across on the sky, the real Earth is about 2 degrees wide seen from the moon
+const EARTH_ANGULAR_RADIUS : f32 = 0.03;
+const EARTH_TEXTURE_SIZE   : (u32, u32) = (512, 256);
+const EARTH_DAYS_PER_DAY   : f32 = 29.5;
+const EARTH_AXIAL_TILT     : f32 = 0.41; // Radians
+
+// fBm swings further from its middle than the value noise the map was first tuned on did, it's squeezed
+// back to that spread around 0.5, and about a third of the Earth is above sea level
+const NOISE_SPREAD : f32 = 0.72;
+const SEA_LEVEL    : f32 = 0.56;
+
+pub struct CelestialSphere {
+    pub enabled         : bool,
+    pub twinkle         : f32,       // How much the brightness of a star goes up and down, 0 for not at all
+    pub earth_direction : glm::Vec3, // From the ground, fixed in the sky
+    star_count          : usize,
+    star_vao_id         : u32,
+    star_data           : gfx::Buffer, // Direction and brightness of every star
+    star_colors         : gfx::Buffer, // Color and twinkle phase
+    star_shader         : ShaderHandle,
+    earth_shader        : ShaderHandle,
+    earth_texture       : gfx::Texture,
+    empty_vao_id        : u32,
+}
+
+impl CelestialSphere {
+    /// `star_count` stars scattered from `seed`.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, star_count: usize, seed: u64) -> CelestialSphere {
+        let star_shader = assets.load_shader(&["shaders/stars.vert", "shaders/stars.frag"]);
+        let earth_shader = assets.load_shader(&["shaders/earth.vert", "shaders/earth.frag"]);
+
+        let (data, colors) = scatter_stars(star_count, seed);
+        let mut star_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut star_vao_id);
+        gl::BindVertexArray(star_vao_id);
+        let star_data = gfx::Buffer::new(gfx::BufferKind::Vertex, &data);
+        star_data.vertex_attribute(0, 4);
+        let star_colors = gfx::Buffer::new(gfx::BufferKind::Vertex, &colors);
+        star_colors.vertex_attribute(1, 4);
+        gl::BindVertexArray(0);
+        gfx::label_object(gfx::ObjectKind::VertexArray, star_vao_id, "stars VAO");
+        star_data.label("star directions");
+        star_colors.label("star colors");
+
+        let (width, height) = EARTH_TEXTURE_SIZE;
+        let earth_texture = gfx::Texture::from_rgba8(width, height, &earth_pixels(width, height, seed), gfx::ColorSpace::Srgb);
+        earth_texture.label("earth");
+
+        let mut empty_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut empty_vao_id);
+
+        CelestialSphere {
+            enabled: true,
+            twinkle: 0.3,
+            earth_direction: glm::normalize(&glm::vec3(0.45, 0.35, -0.8)),
+            star_count,
+            star_vao_id,
+            star_data,
+            star_colors,
+            star_shader,
+            earth_shader,
+            earth_texture,
+            empty_vao_id,
+        }
+    }
+
+    /// Draw the stars and the Earth into the active view, with the view projection of a camera at the
+    /// origin (see Camera::relative_view_projection). `visibility` fades the stars out, from 0 to 1.
+    /// Leaves the default render state, keeping the scissor.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, relative_view_projection: &glm::Mat4, time_of_day: &TimeOfDay, elapsed: f32, visibility: f32) {
+        if !self.enabled {
+            return;
+        }
+        let scissor = gfx::applied_render_state().scissor;
+        gfx::RenderState {
+            depth_test: gfx::DepthTest::Off,
+            depth_write: false,
+            cull: gfx::CullMode::Off,
+            blend: gfx::Blend::Additive,
+            scissor,
+            ..Default::default()
+        }.apply();
+
+        if visibility > 0.0 {
+            let shader = assets.shader(self.star_shader);
+            shader.activate();
+            shader.set_uniform_mat4("view_projection", relative_view_projection);
+            shader.set_uniform_mat4("sky_rotation", &time_of_day.sky_rotation());
+            shader.set_uniform_float("time", elapsed);
+            shader.set_uniform_float("twinkle", self.twinkle);
+            shader.set_uniform_float("visibility", visibility);
+            gl::Enable(gl::PROGRAM_POINT_SIZE);
+            gl::BindVertexArray(self.star_vao_id);
+            gfx::count_draw_call();
+            gl::DrawArrays(gl::POINTS, 0, self.star_count as i32);
+            gl::Disable(gl::PROGRAM_POINT_SIZE);
+        }
+
+        // The Earth covers the stars behind it
+        gfx::RenderState { blend: gfx::Blend::Alpha, ..gfx::applied_render_state() }.apply();
+        let spin = time_of_day.hours / 24.0 * EARTH_DAYS_PER_DAY * 2.0 * std::f32::consts::PI;
+        let earth_rotation = glm::rotation(EARTH_AXIAL_TILT, &glm::vec3(0.0, 0.0, 1.0)) * glm::rotation(spin, &glm::vec3(0.0, 1.0, 0.0));
+        let shader = assets.shader(self.earth_shader);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", relative_view_projection);
+        shader.set_uniform_vec3("earth_direction", self.earth_direction.as_ref());
+        shader.set_uniform_float("earth_radius", EARTH_ANGULAR_RADIUS.tan());
+        shader.set_uniform_mat4("earth_rotation", &earth_rotation);
+        shader.set_uniform_vec3("sun_direction", time_of_day.sun_direction().as_ref());
+        shader.set_uniform_float("visibility", visibility);
+        shader.set_uniform_int("earth_texture", 0);
+        gfx::bind_texture(0, Some(self.earth_texture.id));
+        gl::BindVertexArray(self.empty_vao_id);
+        gfx::count_draw_call();
+        gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+
+        gfx::bind_texture(0, None);
+        gfx::RenderState { scissor, ..Default::default() }.apply();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteVertexArrays(1, &self.star_vao_id);
+        gl::DeleteVertexArrays(1, &self.empty_vao_id);
+        self.star_data.delete();
+        self.star_colors.delete();
+        self.earth_texture.delete();
+    }
+}
+
+// (direction, brightness) and (color, twinkle phase) of every star
+fn scatter_stars(count: usize, seed: u64) -> (Vec<[f32; 4]>, Vec<[f32; 4]>) {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let band_normal = glm::normalize(&glm::vec3(0.3, 1.0, 0.5));
+    let mut data = Vec::with_capacity(count);
+    let mut colors = Vec::with_capacity(count);
+    for _ in 0..count {
+        // Uniform over the sphere, then a third of them squashed towards the plane of the band
+        let z: f32 = rng.gen_range(-1.0..1.0);
+        let angle: f32 = rng.gen_range(0.0..2.0 * std::f32::consts::PI);
+        let mut direction = glm::vec3((1.0 - z * z).sqrt() * angle.cos(), z, (1.0 - z * z).sqrt() * angle.sin());
+        if rng.gen_bool(0.35) {
+            let height = glm::dot(&direction, &band_normal);
+            direction = glm::normalize(&(direction - band_normal * height * 0.85));
+        }
+        // Many faint ones and a few bright ones
+        let brightness = 0.15 + 2.5 * rng.gen::<f32>().powi(6);
+        data.push([direction.x, direction.y, direction.z, brightness]);
+
+        let temperature: f32 = rng.gen();
+        let color = if temperature < 0.5 {
+            glm::lerp(&glm::vec3(1.0, 0.7, 0.45), &glm::vec3(1.0, 1.0, 1.0), temperature * 2.0)
+        } else {
+            glm::lerp(&glm::vec3(1.0, 1.0, 1.0), &glm::vec3(0.65, 0.75, 1.0), temperature * 2.0 - 1.0)
+        };
+        colors.push([color.x, color.y, color.z, rng.gen_range(0.0..1.0)]);
+    }
+    (data, colors)
+}
+
+// An equirectangular map of a made up Earth: oceans, continents, ice at the poles and clouds over it
+fn earth_pixels(width: u32, height: u32, seed: u64) -> Vec<u8> {
+    let land_noise = toolbox::Noise::new(seed);
+    let cloud_noise = toolbox::Noise::new(seed.wrapping_add(1));
+    let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+    for y in 0..height {
+        // The first row is at the bottom, the south pole
+        let latitude = (y as f32 + 0.5) / height as f32 * std::f32::consts::PI - std::f32::consts::FRAC_PI_2;
+        for x in 0..width {
+            let longitude = (x as f32 + 0.5) / width as f32 * 2.0 * std::f32::consts::PI;
+            let point = glm::vec3(latitude.cos() * longitude.cos(), latitude.sin(), latitude.cos() * longitude.sin());
+
+            let land = 0.5 + land_noise.fbm3(&(point * 2.5), 5) * NOISE_SPREAD;
+            let mut color = if land > SEA_LEVEL {
+                glm::lerp(&glm::vec3(0.25, 0.45, 0.15), &glm::vec3(0.6, 0.5, 0.3), ((land - SEA_LEVEL) * 5.0).min(1.0))
+            } else {
+                glm::lerp(&glm::vec3(0.02, 0.07, 0.3), &glm::vec3(0.05, 0.2, 0.45), (land * 1.8).min(1.0))
+            };
+            if latitude.abs() > 1.2 + (land - 0.5) * 0.3 {
+                color = glm::vec3(0.9, 0.93, 0.95);
+            }
+            let clouds = (cloud_noise.fbm3(&point.component_mul(&glm::vec3(3.0, 6.0, 3.0)), 4) * NOISE_SPREAD * 3.0).clamp(0.0, 1.0);
+            color = glm::lerp(&color, &glm::vec3(1.0, 1.0, 1.0), clouds * 0.8);
+
+            pixels.extend_from_slice(&[(color.x * 255.0) as u8, (color.y * 255.0) as u8, (color.z * 255.0) as u8, 255]);
+        }
+    }
+    pixels
+}
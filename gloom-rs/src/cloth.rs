@@ -0,0 +1,217 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::{self, Mesh};
+use crate::toolbox;
+use crate::util;
+
+// * Cloth
+// A flag planted on the surface, the cloth of it a grid of masses held together by springs: along the
+// rows and columns to keep it from stretching, across the diagonals against shearing, and over every
+// second mass against folding up too sharply. The column next to the pole is pinned to it.
+//
+// Every fixed step the masses move on by Verlet integration (how far they went the step before is their
+// velocity), pulled down by the moon's gravity and pushed by the wind of the weather, then the springs
+// are relaxed a few times by moving both ends towards their rest length. The wind pushes every mass
+// along the normal of the cloth there, by how much the wind blows into it, so the cloth turns side on
+// to it and flaps. A little noise on top keeps a steady wind from letting it settle.
+//
+// The cloth is a mesh like any other, drawn through a SceneNode. Every frame its positions and
+// normals are worked out again and uploaded into the buffers of its VAO with glBufferSubData. The back
+// is a second copy of the grid facing the other way, so it is seen from both sides with back faces
+// culled.
+
+const STEP       : f32 = 1.0 / 120.0; // Seconds, the simulation runs at a fixed rate
+const MAX_STEPS  : usize = 8;         // Per frame, after a long frame it falls behind instead
+const ITERATIONS : usize = 6;         // Spring relaxations per step
+const GRAVITY    : glm::Vec3 = glm::Vec3::new(0.0, -1.6, 0.0); // The moon's
+const DAMPING    : f32 = 0.995;       // Of the velocity, per step
+const WIND_FORCE : f32 = 0.6;
+const FLUTTER    : f32 = 0.35;        // How much the noise varies the wind, 0 for not at all
+
+#[derive(Clone, Copy, Debug)]
+struct Spring {
+    a           : usize,
+    b           : usize,
+    rest_length : f32,
+}
+
+pub struct Cloth {
+    pub enabled : bool,
+    positions   : Vec<glm::Vec3>, // In the space of the node, row by row from the top
+    previous    : Vec<glm::Vec3>,
+    pinned      : Vec<bool>,
+    springs     : Vec<Spring>,
+    pub mesh    : Mesh,           // Front and back, the front's vertices first
+    pub vao_id  : u32,
+    time        : f32,
+    leftover    : f32,            // Seconds not yet stepped
+    noise       : toolbox::Noise,
+}
+
+impl Cloth {
+    /// A flag `width` by `height` with `columns` by `rows` masses, hanging from the top of a pole at
+    /// the origin with its left edge pinned to it, in the XY plane.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn flag(width: f32, height: f32, columns: usize, rows: usize, pole_height: f32, color: [f32; 4]) -> Cloth {
+        let (columns, rows) = (columns.max(2), rows.max(2));
+        let spacing = glm::vec2(width / (columns - 1) as f32, height / (rows - 1) as f32);
+        let mut positions = Vec::with_capacity(columns * rows);
+        let mut pinned = Vec::with_capacity(columns * rows);
+        for row in 0..rows {
+            for column in 0..columns {
+                positions.push(glm::vec3(column as f32 * spacing.x, pole_height - row as f32 * spacing.y, 0.0));
+                pinned.push(column == 0);
+            }
+        }
+
+        let index = |column: usize, row: usize| row * columns + column;
+        let mut springs = vec![];
+        let mut connect = |a: usize, b: usize| springs.push(Spring { a, b, rest_length: glm::distance(&positions[a], &positions[b]) });
+        for row in 0..rows {
+            for column in 0..columns {
+                let here = index(column, row);
+                if column + 1 < columns { connect(here, index(column + 1, row)); }
+                if row + 1 < rows { connect(here, index(column, row + 1)); }
+                if column + 1 < columns && row + 1 < rows {
+                    connect(here, index(column + 1, row + 1));
+                    connect(index(column + 1, row), index(column, row + 1));
+                }
+                if column + 2 < columns { connect(here, index(column + 2, row)); }
+                if row + 2 < rows { connect(here, index(column, row + 2)); }
+            }
+        }
+
+        // Two triangles per square of the grid, wound the other way round on the back
+        let count = (columns * rows) as u32;
+        let mut indices = vec![];
+        for row in 0..rows - 1 {
+            for column in 0..columns - 1 {
+                let [a, b, c, d] = [index(column, row), index(column + 1, row), index(column, row + 1), index(column + 1, row + 1)].map(|i| i as u32);
+                indices.extend_from_slice(&[a, c, b, b, c, d]);
+                indices.extend_from_slice(&[a + count, b + count, c + count, b + count, d + count, c + count]);
+            }
+        }
+        let vertices: Vec<f32> = positions.iter().chain(positions.iter()).flat_map(|p| [p.x, p.y, p.z]).collect();
+        let colors: Vec<f32> = (0..2 * count).flat_map(|_| color).collect();
+        let mesh = Mesh::from_arrays(vertices, vec![], colors, indices);
+        let (vao_id, _) = util::create_vao(&mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals);
+        gl::BindVertexArray(0);
+
+        let mut cloth = Cloth {
+            enabled: true,
+            previous: positions.clone(),
+            positions,
+            pinned,
+            springs,
+            mesh,
+            vao_id,
+            time: 0.0,
+            leftover: 0.0,
+            noise: toolbox::Noise::new(23),
+        };
+        cloth.upload();
+        cloth
+    }
+
+    /// Step the cloth along by `delta_time` in `wind` (world space, the node isn't turned), and upload it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn update(&mut self, wind: glm::Vec3, delta_time: f32) {
+        if !self.enabled || delta_time <= 0.0 {
+            return;
+        }
+        self.leftover = (self.leftover + delta_time).min(STEP * MAX_STEPS as f32);
+        while self.leftover >= STEP {
+            self.leftover -= STEP;
+            self.time += STEP;
+            self.step(wind);
+        }
+        self.upload();
+    }
+
+    fn step(&mut self, wind: glm::Vec3) {
+        let normals = self.normals();
+        for (i, normal) in normals.iter().enumerate() {
+            if self.pinned[i] {
+                continue;
+            }
+            let position = self.positions[i];
+            let velocity = (position - self.previous[i]) / STEP * DAMPING;
+            // The wind gusts a little differently over every part of the cloth
+            let flutter = 1.0 + FLUTTER * self.noise.perlin2(self.time * 3.0 + position.x * 0.8, position.y * 0.8);
+            let relative_wind = wind * flutter - velocity;
+            let force = normal * glm::dot(normal, &relative_wind) * WIND_FORCE;
+            self.previous[i] = position;
+            self.positions[i] = position + velocity * STEP + (GRAVITY + force) * STEP * STEP;
+        }
+
+        for _ in 0..ITERATIONS {
+            for spring in &self.springs {
+                let (a, b) = (self.positions[spring.a], self.positions[spring.b]);
+                let distance = glm::distance(&a, &b);
+                if distance < 1e-6 {
+                    continue;
+                }
+                let correction = (b - a) * ((distance - spring.rest_length) / distance);
+                match (self.pinned[spring.a], self.pinned[spring.b]) {
+                    (true, true)   => {}
+                    (true, false)  => self.positions[spring.b] -= correction,
+                    (false, true)  => self.positions[spring.a] += correction,
+                    (false, false) => {
+                        self.positions[spring.a] += correction * 0.5;
+                        self.positions[spring.b] -= correction * 0.5;
+                    }
+                }
+            }
+        }
+    }
+
+    // The smooth normals of the front, from the triangles around every mass
+    fn normals(&self) -> Vec<glm::Vec3> {
+        let vertices: Vec<f32> = self.positions.iter().flat_map(|p| [p.x, p.y, p.z]).collect();
+        let front_indices = self.mesh.indices.chunks(6).step_by(2).flatten().copied().collect::<Vec<u32>>();
+        mesh::generate_normals(&vertices, &front_indices).chunks(3).map(glm::make_vec3).collect()
+    }
+
+    // New positions and normals into the mesh and its VAO, the back facing the other way
+    unsafe fn upload(&mut self) {
+        let normals = self.normals();
+        let count = self.positions.len();
+        for (i, (position, normal)) in self.positions.iter().zip(&normals).enumerate() {
+            for (vertex, sign) in [(i, 1.0), (i + count, -1.0)] {
+                self.mesh.vertices[3 * vertex..3 * vertex + 3].copy_from_slice(position.as_slice());
+                self.mesh.normals[3 * vertex..3 * vertex + 3].copy_from_slice((normal * sign).as_slice());
+            }
+        }
+        if let Some(positions) = util::vertex_buffer(self.vao_id, 0) {
+            positions.update_range(0, &self.mesh.vertices);
+        }
+        if let Some(normals) = util::vertex_buffer(self.vao_id, 2) {
+            normals.update_range(0, &self.mesh.normals);
+        }
+    }
+}
+
+// A square pole `height` tall standing on the origin, for the flag to hang from
+pub fn pole_mesh(height: f32, thickness: f32, color: [f32; 4]) -> Mesh {
+    let h = thickness / 2.0;
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut indices = vec![];
+    // Every side its own four corners, so the edges stay sharp
+    for (normal, right) in [(glm::vec3(1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, -1.0)), (glm::vec3(-1.0, 0.0, 0.0), glm::vec3(0.0, 0.0, 1.0)),
+                            (glm::vec3(0.0, 0.0, 1.0), glm::vec3(1.0, 0.0, 0.0)), (glm::vec3(0.0, 0.0, -1.0), glm::vec3(-1.0, 0.0, 0.0))] {
+        let first = (vertices.len() / 3) as u32;
+        for (across, up) in [(-1.0, 0.0), (1.0, 0.0), (1.0, height), (-1.0, height)] {
+            let corner: glm::Vec3 = normal * h + right * (across * h) + glm::vec3(0.0, up, 0.0);
+            vertices.extend_from_slice(corner.as_slice());
+            normals.extend_from_slice(normal.as_slice());
+        }
+        indices.extend_from_slice(&[first, first + 1, first + 2, first, first + 2, first + 3]);
+    }
+    let colors = (0..vertices.len() / 3).flat_map(|_| color).collect();
+    Mesh::from_arrays(vertices, normals, colors, indices)
+}
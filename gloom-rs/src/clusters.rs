@@ -0,0 +1,253 @@
+extern crate nalgebra_glm as glm;
+
+use crate::gfx;
+use crate::lights::{LightKind, WorldLight, MAX_LIGHTS};
+use crate::shader::Shader;
+use crate::uniform_block::UniformBlock;
+
+// * Clustered forward lighting
+// Looping over every light for every fragment gets slow with dozens of searchlights and lamps, while
+// most of them only reach a small part of the screen. So the view frustum is cut into a grid of
+// clusters, tiles across the screen and slices by depth (thinner close to the camera), and every light
+// is added to the clusters its range touches. A fragment finds its cluster from where it is on screen
+// and how far away it is, and only loops over the lights in it.
+//
+// The lights are uploaded once a frame into a float texture, three texels each. The clusters are
+// worked out on the CPU for every view that is drawn (the split screen regions, their reflections in
+// the lake, the security feed), which only needs the view projection matrix of the view: every camera
+// uses the same kind of perspective projection, and its rows give back the view space axes.
+//
+// Textures instead of storage buffers keep this inside what ES 3.0 can do.
+
+const GRID_X: usize = 16;
+const GRID_Y: usize = 9;
+const GRID_Z: usize = 24;
+const CLUSTER_COUNT: usize = GRID_X * GRID_Y * GRID_Z;
+
+// Depth the slices start at and go to (the last one reaches on past it), spaced logarithmically
+const SLICE_NEAR: f32 = 1.0;
+const SLICE_FAR: f32 = 1500.0;
+
+// The light index list is a 2D texture this wide, with as many rows as needed for MAX_LIGHT_INDICES
+const INDEX_TEXTURE_WIDTH: usize = 1024;
+const MAX_LIGHT_INDICES: usize = 16 * INDEX_TEXTURE_WIDTH;
+
+// Texture units, after the shadow maps
+pub const LIGHT_DATA_UNIT: u32 = 8;
+pub const CLUSTER_UNIT: u32 = 9;
+pub const LIGHT_INDEX_UNIT: u32 = 10;
+pub const LIGHT_CLUSTERS_BINDING: u32 = 2; // Uniform block binding, after the draw transforms and the cascades
+
+uniform_block! {
+    pub struct ClusterBlock as LightClusters {
+        cluster_view_projection : glm::Mat4,  // Of the view the clusters are for
+        cluster_grid            : glm::IVec4, // Clusters across, up and in depth
+        cluster_depth           : glm::Vec4,  // Where the depth slices start and end
+    }
+}
+
+impl ClusterBlock {
+    fn new(view_projection: glm::Mat4) -> ClusterBlock {
+        ClusterBlock {
+            cluster_view_projection: view_projection,
+            cluster_grid: glm::vec4(GRID_X as i32, GRID_Y as i32, GRID_Z as i32, 0),
+            cluster_depth: glm::vec4(SLICE_NEAR, SLICE_FAR, 0.0, 0.0),
+        }
+    }
+}
+
+pub struct LightClusters {
+    light_data_texture  : u32,
+    cluster_texture     : u32,
+    index_texture       : u32,
+    uniform_buffer      : gfx::Buffer,
+    lights              : Vec<(glm::Vec3, f32)>, // Where the lights uploaded this frame are and how far they reach
+    overflow_warned     : bool,
+}
+
+impl LightClusters {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new() -> LightClusters {
+        // Integer textures are never filtered, and the light data is only ever read with texelFetch
+        let create_texture = |internal_format: gl::types::GLenum, format: gl::types::GLenum, data_type: gl::types::GLenum, width: usize, height: usize| {
+            let mut texture_id: u32 = 0;
+            gl::GenTextures(1, &mut texture_id);
+            gl::BindTexture(gl::TEXTURE_2D, texture_id);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, width as i32, height as i32, 0, format, data_type, std::ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+            texture_id
+        };
+        let light_data_texture = create_texture(gl::RGBA32F, gl::RGBA, gl::FLOAT, 3, MAX_LIGHTS);
+        let cluster_texture = create_texture(gl::RG32UI, gl::RG_INTEGER, gl::UNSIGNED_INT, GRID_X * GRID_Y, GRID_Z);
+        let index_texture = create_texture(gl::R32UI, gl::RED_INTEGER, gl::UNSIGNED_INT, INDEX_TEXTURE_WIDTH, MAX_LIGHT_INDICES / INDEX_TEXTURE_WIDTH);
+
+        let uniform_buffer = gfx::Buffer::new(gfx::BufferKind::Uniform, &ClusterBlock::new(glm::zero()).to_bytes());
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        gfx::label_object(gfx::ObjectKind::Texture, light_data_texture, "cluster light data");
+        gfx::label_object(gfx::ObjectKind::Texture, cluster_texture, "light clusters");
+        gfx::label_object(gfx::ObjectKind::Texture, index_texture, "cluster light indices");
+        uniform_buffer.label("LightClusters block");
+
+        LightClusters { light_data_texture, cluster_texture, index_texture, uniform_buffer, lights: vec![], overflow_warned: false }
+    }
+
+    /// Upload the lights of this frame. Lights past MAX_LIGHTS are left out.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_lights(&mut self, lights: &[WorldLight]) {
+        if lights.len() > MAX_LIGHTS {
+            println!("Warning: {} lights in the scene, only the first {} are used", lights.len(), MAX_LIGHTS);
+        }
+
+        let mut texels: Vec<f32> = Vec::with_capacity(lights.len().min(MAX_LIGHTS) * 12);
+        self.lights.clear();
+        for world_light in lights.iter().take(MAX_LIGHTS) {
+            let light = &world_light.light;
+            let color = light.color.map(|channel| channel * light.intensity);
+
+            // A point light is a spot light with a cone that covers everything
+            let (cos_inner, cos_outer) = match light.kind {
+                LightKind::Point => (-2.0, -3.0),
+                LightKind::Spot { inner_angle, outer_angle } => (inner_angle.cos(), outer_angle.cos()),
+            };
+
+            let (position, direction) = (world_light.position, world_light.direction);
+            texels.extend_from_slice(&[position.x, position.y, position.z, light.range]);
+            texels.extend_from_slice(&[direction.x, direction.y, direction.z, cos_inner]);
+            texels.extend_from_slice(&[color[0], color[1], color[2], cos_outer]);
+            self.lights.push((position, light.range));
+        }
+
+        if !self.lights.is_empty() {
+            gl::BindTexture(gl::TEXTURE_2D, self.light_data_texture);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, 3, self.lights.len() as i32, gl::RGBA, gl::FLOAT, texels.as_ptr() as *const std::ffi::c_void);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+    }
+
+    /// Sort the lights into the clusters of the view drawn with `view_projection_matrix`, for
+    /// everything drawn until the next call.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn assign(&mut self, view_projection_matrix: &glm::Mat4) {
+        // A perspective projection only scales the view's X and Y axes, and its W is the depth
+        let row = |i: usize| view_projection_matrix.row(i).transpose();
+        let (scale_x, scale_y) = (row(0).xyz().norm(), row(1).xyz().norm());
+        let (axis_x, axis_y, axis_depth) = (row(0) / scale_x, row(1) / scale_y, row(3));
+        let to_view = |position: &glm::Vec3| {
+            let position = glm::vec4(position.x, position.y, position.z, 1.0);
+            glm::vec3(axis_x.dot(&position), axis_y.dot(&position), axis_depth.dot(&position))
+        };
+
+        // Which lights touch every cluster, as a list per cluster
+        let mut cluster_lights: Vec<Vec<u32>> = vec![vec![]; CLUSTER_COUNT];
+        for (light_index, (position, range)) in self.lights.iter().enumerate() {
+            let center = to_view(position);
+            if center.z + range < SLICE_NEAR {
+                continue; // All of it behind the camera
+            }
+            let first_slice = slice_of(center.z - range);
+            let last_slice = slice_of(center.z + range);
+            for z in first_slice..=last_slice {
+                let (near, far) = (slice_depth(z), slice_depth(z + 1));
+                for y in 0..GRID_Y {
+                    let (bottom, top) = (tile_edge(y, GRID_Y), tile_edge(y + 1, GRID_Y));
+                    for x in 0..GRID_X {
+                        let (left, right) = (tile_edge(x, GRID_X), tile_edge(x + 1, GRID_X));
+                        // The box around the cluster, the frustum widens with depth
+                        let min = glm::vec3((left * near).min(left * far) / scale_x, (bottom * near).min(bottom * far) / scale_y, near);
+                        let max = glm::vec3((right * near).max(right * far) / scale_x, (top * near).max(top * far) / scale_y, far);
+                        let closest = glm::clamp_vec(&center, &min, &max);
+                        if glm::distance2(&closest, &center) <= range * range {
+                            cluster_lights[(z * GRID_Y + y) * GRID_X + x].push(light_index as u32);
+                        }
+                    }
+                }
+            }
+        }
+
+        // One list after the other, and where each one starts and how long it is
+        let mut clusters: Vec<u32> = Vec::with_capacity(CLUSTER_COUNT * 2);
+        let mut indices: Vec<u32> = vec![];
+        for lights in &cluster_lights {
+            let room = MAX_LIGHT_INDICES - indices.len();
+            if lights.len() > room && !self.overflow_warned {
+                println!("Warning: more than {} light indices in the clusters, some lights are left out", MAX_LIGHT_INDICES);
+                self.overflow_warned = true;
+            }
+            let count = lights.len().min(room);
+            clusters.extend_from_slice(&[indices.len() as u32, count as u32]);
+            indices.extend_from_slice(&lights[..count]);
+        }
+
+        gl::BindTexture(gl::TEXTURE_2D, self.cluster_texture);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+        gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, (GRID_X * GRID_Y) as i32, GRID_Z as i32, gl::RG_INTEGER, gl::UNSIGNED_INT, clusters.as_ptr() as *const std::ffi::c_void);
+        if !indices.is_empty() {
+            // Whole rows, the last one padded out
+            let rows = indices.len().div_ceil(INDEX_TEXTURE_WIDTH);
+            indices.resize(rows * INDEX_TEXTURE_WIDTH, 0);
+            gl::BindTexture(gl::TEXTURE_2D, self.index_texture);
+            gl::TexSubImage2D(gl::TEXTURE_2D, 0, 0, 0, INDEX_TEXTURE_WIDTH as i32, rows as i32, gl::RED_INTEGER, gl::UNSIGNED_INT, indices.as_ptr() as *const std::ffi::c_void);
+        }
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        self.uniform_buffer.update(&ClusterBlock::new(*view_projection_matrix).to_bytes());
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+    }
+
+    /// Point a lit shader (simple.frag or pbr.frag) at the lights and the clusters, it has to be active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_uniforms(&self, shader: &Shader) {
+        for (name, unit, texture_id) in [
+            ("light_data", LIGHT_DATA_UNIT, self.light_data_texture),
+            ("cluster_lights", CLUSTER_UNIT, self.cluster_texture),
+            ("light_indices", LIGHT_INDEX_UNIT, self.index_texture),
+        ] {
+            shader.set_uniform_int(name, unit as i32);
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        if shader.get_uniform_block_index("LightClusters") != gl::INVALID_INDEX {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, LIGHT_CLUSTERS_BINDING, self.uniform_buffer.id);
+        }
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteTextures(1, &self.light_data_texture);
+        gl::DeleteTextures(1, &self.cluster_texture);
+        gl::DeleteTextures(1, &self.index_texture);
+        self.uniform_buffer.delete();
+    }
+}
+
+// Slice a depth is in, the same way clusterLights in simple.frag finds it
+fn slice_of(depth: f32) -> usize {
+    let t = (depth.max(SLICE_NEAR) / SLICE_NEAR).ln() / (SLICE_FAR / SLICE_NEAR).ln();
+    ((t * GRID_Z as f32) as usize).min(GRID_Z - 1)
+}
+
+// Depth slice `z` starts at. The last slice goes on forever.
+fn slice_depth(z: usize) -> f32 {
+    if z >= GRID_Z {
+        return f32::MAX;
+    }
+    SLICE_NEAR * (SLICE_FAR / SLICE_NEAR).powf(z as f32 / GRID_Z as f32)
+}
+
+// Edge `i` of `count` tiles across normalized device coordinates
+fn tile_edge(i: usize, count: usize) -> f32 {
+    i as f32 / count as f32 * 2.0 - 1.0
+}
@@ -0,0 +1,32 @@
+// * Color management
+// Light adds up linearly, so the lighting, the blending and the filtering are all done with linear
+// values. Colors picked by eye (vertex colors, the sky, textures painted in an image editor) are sRGB
+// though, the way a monitor shows them, so they are decoded to linear before they are used: textures
+// by the GPU, with an sRGB internal format, everything else here on the CPU when it is uploaded. At
+// the very end the window's sRGB framebuffer encodes the linear result again for the monitor (or the
+// tone mapping does it, on drivers without one, see post.rs).
+//
+// Only what is a color gets decoded. Light colors and intensities, normal maps, roughness and the like
+// are linear quantities already.
+
+// One channel, with the exact sRGB curve (a straight bit near black, a 2.4 power above it)
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+pub fn decode_srgb(color: [f32; 3]) -> [f32; 3] {
+    color.map(srgb_to_linear)
+}
+
+// Vertex colors, RGB or RGBA packed one after the other. Alpha is coverage and stays as it is.
+pub fn decode_srgb_vertex_colors(colors: &[f32]) -> Vec<f32> {
+    let components = if colors.len().is_multiple_of(4) { 4 } else { 3 };
+    colors.iter()
+        .enumerate()
+        .map(|(i, &value)| if i % components < 3 { srgb_to_linear(value) } else { value })
+        .collect()
+}
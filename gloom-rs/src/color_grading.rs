@@ -0,0 +1,229 @@
+use std::convert::TryInto;
+use std::path::Path;
+
+use crate::shader::Shader;
+
+// * Color grading
+// The look of the final image (a cold lunar night, a warm film) as a 3D lookup table: every display
+// color, after the tone mapping, is looked up in a cube of colors and replaced by what is there. The
+// tables are made in an image editor or a grading tool, so changing the mood doesn't touch a shader.
+//
+// Two file formats are read, from resources/luts/ at startup:
+// - .cube (Adobe / Resolve), text with LUT_3D_SIZE and one "r g b" line per entry, red fastest
+// - strip images (.png and the like), N tiles of NxN side by side, the Unreal layout: red goes right
+//   inside a tile, green down from the top and blue picks the tile. The top left pixel is black.
+// Both are in display (sRGB) space, the way the grading tools make them.
+
+const LUT_DIRECTORY: &str = "resources/luts";
+const MAX_CUBE_SIZE: i32 = 256; // Bigger than any grading tool makes, and still a texture GL takes
+pub const LUT_UNIT: u32 = 3; // Texture unit in the tone mapping pass
+
+pub struct Lut {
+    pub name   : String,
+    pub size   : i32, // Entries along each side of the cube
+    texture_id : u32,
+}
+
+pub struct ColorGrading {
+    pub luts      : Vec<Lut>,
+    pub current   : Option<usize>, // Index into `luts`, None for no grading
+    pub intensity : f32,           // 0 is the image as it was, 1 fully graded
+}
+
+impl ColorGrading {
+    /// Load every table in resources/luts/, in file name order. Broken ones are left out with a warning.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new() -> ColorGrading {
+        let mut paths: Vec<_> = std::fs::read_dir(LUT_DIRECTORY)
+            .map(|entries| entries.filter_map(|entry| entry.ok()).map(|entry| entry.path()).collect())
+            .unwrap_or_default();
+        paths.sort();
+
+        let luts = paths.iter()
+            .filter_map(|path| match Lut::load(path) {
+                Ok(lut) => Some(lut),
+                Err(message) => {
+                    println!("Warning: skipping color grading table {}: {}", path.display(), message);
+                    None
+                }
+            })
+            .collect();
+
+        ColorGrading { luts, current: None, intensity: 1.0 }
+    }
+
+    // No grading, then every table in turn
+    pub fn next(&mut self) {
+        self.current = match self.current {
+            None if !self.luts.is_empty()              => Some(0),
+            Some(index) if index + 1 < self.luts.len() => Some(index + 1),
+            _                                          => None,
+        };
+    }
+
+    pub fn current_name(&self) -> &str {
+        self.current.map_or("off", |index| &self.luts[index].name)
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_uniforms(&self, shader: &Shader) {
+        let lut = self.current.map(|index| &self.luts[index]);
+        shader.set_uniform_int("lut_enabled", lut.is_some() as i32);
+        shader.set_uniform_int("lut_texture", LUT_UNIT as i32);
+        shader.set_uniform_float("lut_intensity", self.intensity);
+        shader.set_uniform_float("lut_size", lut.map_or(1, |lut| lut.size) as f32);
+        gl::ActiveTexture(gl::TEXTURE0 + LUT_UNIT);
+        gl::BindTexture(gl::TEXTURE_3D, lut.map_or(0, |lut| lut.texture_id));
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn unbind(&self) {
+        gl::ActiveTexture(gl::TEXTURE0 + LUT_UNIT);
+        gl::BindTexture(gl::TEXTURE_3D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        for lut in &self.luts {
+            gl::DeleteTextures(1, &lut.texture_id);
+        }
+    }
+}
+
+impl Lut {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn load(path: &Path) -> Result<Lut, String> {
+        let extension = path.extension().and_then(|extension| extension.to_str()).unwrap_or("").to_lowercase();
+        let (size, colors) = if extension == "cube" {
+            let text = std::fs::read_to_string(path).map_err(|error| error.to_string())?;
+            parse_cube(&text)?
+        } else {
+            let image = image::open(path).map_err(|error| error.to_string())?.into_rgb8();
+            read_strip(image.width(), image.height(), image.as_raw())?
+        };
+
+        let name = path.file_stem().map_or(String::new(), |stem| stem.to_string_lossy().replace('_', " "));
+        Ok(Lut { name, size, texture_id: create_lut_texture(size, &colors) })
+    }
+}
+
+// Size and the colors in the order of a 3D texture (red fastest, then green, then blue)
+fn parse_cube(text: &str) -> Result<(i32, Vec<f32>), String> {
+    let mut size = 0;
+    let mut domain_min = [0.0; 3];
+    let mut domain_max = [1.0; 3];
+    let mut colors = vec![];
+
+    for line in text.lines().map(|line| line.trim()) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let numbers = |words: &[&str]| -> Result<[f32; 3], String> {
+            let parsed: Vec<f32> = words.iter().map(|word| word.parse::<f32>()).collect::<Result<_, _>>()
+                .map_err(|_| format!("not a number in \"{}\"", line))?;
+            parsed.try_into().map_err(|_| format!("expected three numbers in \"{}\"", line))
+        };
+        match words[0] {
+            "LUT_3D_SIZE" => size = words.get(1).and_then(|word| word.parse().ok()).ok_or("bad LUT_3D_SIZE")?,
+            "LUT_1D_SIZE" => return Err("1D tables aren't supported".to_string()),
+            "DOMAIN_MIN" => domain_min = numbers(&words[1..])?,
+            "DOMAIN_MAX" => domain_max = numbers(&words[1..])?,
+            // TITLE, LUT_3D_INPUT_RANGE, LUT_1D_INPUT_RANGE and whatever else a tool writes don't change the table
+            keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {}
+            _ => {
+                let color = numbers(&words)?;
+                // Brought into 0 to 1, which is what the texture lookup covers
+                colors.extend((0..3).map(|i| (color[i] - domain_min[i]) / (domain_max[i] - domain_min[i])));
+            }
+        }
+    }
+
+    if size < 2 {
+        return Err("no LUT_3D_SIZE".to_string());
+    }
+    if size > MAX_CUBE_SIZE {
+        return Err(format!("a LUT_3D_SIZE of {} is more than {}", size, MAX_CUBE_SIZE));
+    }
+    if colors.len() != (size * size * size * 3) as usize {
+        return Err(format!("{} entries for a size of {}", colors.len() / 3, size));
+    }
+    Ok((size, colors))
+}
+
+// A strip image with the top row first, into the same order as parse_cube
+fn read_strip(width: u32, height: u32, pixels: &[u8]) -> Result<(i32, Vec<f32>), String> {
+    let size = height;
+    if size < 2 || width != size * size {
+        return Err(format!("a {}x{} image isn't a strip of square tiles, one per row", width, height));
+    }
+
+    let mut colors = Vec::with_capacity((size * size * size * 3) as usize);
+    for blue in 0..size {
+        for green in 0..size {
+            for red in 0..size {
+                let pixel = ((green * width + blue * size + red) * 3) as usize;
+                colors.extend(pixels[pixel..pixel + 3].iter().map(|&channel| channel as f32 / 255.0));
+            }
+        }
+    }
+    Ok((size as i32, colors))
+}
+
+unsafe fn create_lut_texture(size: i32, colors: &[f32]) -> u32 {
+    let mut texture_id: u32 = 0;
+    gl::GenTextures(1, &mut texture_id);
+    gl::BindTexture(gl::TEXTURE_3D, texture_id);
+    gl::TexImage3D(gl::TEXTURE_3D, 0, gl::RGB16F as i32, size, size, size, 0, gl::RGB, gl::FLOAT, colors.as_ptr() as *const std::ffi::c_void);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_3D, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+    gl::BindTexture(gl::TEXTURE_3D, 0);
+    texture_id
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn identity_cube(header: &str) -> String {
+        let mut text = header.to_string();
+        for blue in 0..2 {
+            for green in 0..2 {
+                for red in 0..2 {
+                    text += &format!("{} {} {}\n", red, green, blue);
+                }
+            }
+        }
+        text
+    }
+
+    #[test]
+    fn skips_unknown_keywords() {
+        let text = identity_cube("TITLE \"night\"\nLUT_3D_SIZE 2\nLUT_3D_INPUT_RANGE 0.0 1.0\nLUT_1D_INPUT_RANGE 0.0 1.0\n");
+        let (size, colors) = parse_cube(&text).unwrap();
+        assert_eq!(size, 2);
+        assert_eq!(&colors[..6], &[0.0, 0.0, 0.0, 1.0, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn rejects_huge_sizes() {
+        assert!(parse_cube("LUT_3D_SIZE 100000\n0 0 0\n").is_err());
+        assert!(parse_cube("LUT_3D_SIZE 2000000000\n").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_numbers() {
+        assert!(parse_cube(&identity_cube("LUT_3D_SIZE 2\n0 x 0\n")).is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use glutin::event::MouseButton;
+
+use crate::events::InputState;
+use crate::gfx;
+use crate::hud::{Anchor, Hud, HudElement, HudElementId};
+use crate::shader::Shader;
+use crate::strings::StringTable;
+use crate::viewport::Viewport;
+
+// * A/B comparison
+// For the pictures in the assignment reports: the scene drawn two ways at once, one on each side of a
+// split line across the window. Left of it is A and right of it is B, and the one thing they differ in
+// is what is being compared: flat shading against PBR, shadows on against off, or fog on against off.
+// Everything else is the way the settings have it. 4 cycles through what is compared and off again.
+//
+// Both sides are drawn from the same cameras in the same frame, into the same views, each restricted to
+// its side with the scissor, so the split goes through every view of the split screen at the same
+// place. The split line can be dragged with the left mouse button, a click on it doesn't pick what is
+// under it.
+
+// How close to the split line a click grabs it, in pixels
+const GRAB_DISTANCE : f32 = 6.0;
+const LINE_WIDTH    : i32 = 2;
+const LINE_COLOR    : [f32; 3] = [1.0, 1.0, 1.0];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ComparisonMode {
+    Off,
+    Shading, // Flat | PBR
+    Shadows, // On | off
+    Fog,     // On | off
+}
+
+impl ComparisonMode {
+    pub fn next(self) -> ComparisonMode {
+        match self {
+            ComparisonMode::Off     => ComparisonMode::Shading,
+            ComparisonMode::Shading => ComparisonMode::Shadows,
+            ComparisonMode::Shadows => ComparisonMode::Fog,
+            ComparisonMode::Fog     => ComparisonMode::Off,
+        }
+    }
+}
+
+// What a side is drawn with, of the settings that can differ between them
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Look {
+    pub pbr     : bool,
+    pub shadows : bool,
+    pub fog     : bool,
+}
+
+impl Look {
+    /// Turn the fog on or off in `shader` and its shadows off if this look has them off, on top of the
+    /// lighting uniforms of the settings. `shader` has to be active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_uniforms(&self, shader: &Shader) {
+        shader.set_uniform_int("fog_enabled", self.fog as i32);
+        if !self.shadows {
+            shader.set_uniform_int("shadows_enabled", 0);
+            shader.set_uniform_int("point_shadow_light", -1);
+        }
+    }
+
+    // The string key of what this look is in `mode`
+    fn name_key(&self, mode: ComparisonMode) -> &'static str {
+        match mode {
+            ComparisonMode::Shading => if self.pbr { "comparison.pbr" } else { "comparison.flat" },
+            ComparisonMode::Shadows => if self.shadows { "comparison.shadows" } else { "comparison.no_shadows" },
+            ComparisonMode::Fog     => if self.fog { "comparison.fog" } else { "comparison.no_fog" },
+            ComparisonMode::Off     => "",
+        }
+    }
+}
+
+pub struct Comparison {
+    pub mode  : ComparisonMode,
+    pub split : f32, // Across the window, from 0 to 1
+    dragging  : bool,
+    label     : HudElementId,
+}
+
+impl Comparison {
+    /// Adds the label of the sides to `hud`, hidden while nothing is compared.
+    pub fn new(hud: &mut Hud) -> Comparison {
+        let label = hud.add(HudElement::text(Anchor::Top, "-").with_margin((0.0, 4.0)).with_visible(false));
+        Comparison { mode: ComparisonMode::Off, split: 0.5, dragging: false, label }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.mode != ComparisonMode::Off
+    }
+
+    // A and B, from the look the settings have. Only that one without a comparison.
+    pub fn looks(&self, current: Look) -> Vec<Look> {
+        match self.mode {
+            ComparisonMode::Off     => vec![current],
+            ComparisonMode::Shading => vec![Look { pbr: false, ..current }, Look { pbr: true, ..current }],
+            ComparisonMode::Shadows => vec![Look { shadows: true, ..current }, Look { shadows: false, ..current }],
+            ComparisonMode::Fog     => vec![Look { fog: true, ..current }, Look { fog: false, ..current }],
+        }
+    }
+
+    // The scissor rectangle of every side within `view`, with its look. The sides that miss the view are left out.
+    pub fn sides(&self, view: &Viewport, window_width: u32, current: Look) -> Vec<((i32, i32, i32, i32), Look)> {
+        let looks = self.looks(current);
+        if looks.len() == 1 {
+            return vec![((view.x, view.y, view.width, view.height), looks[0])];
+        }
+        let split_x = self.split_x(window_width).clamp(view.x, view.x + view.width);
+        let left = ((view.x, view.y, split_x - view.x, view.height), looks[0]);
+        let right = ((split_x, view.y, view.x + view.width - split_x, view.height), looks[1]);
+        vec![left, right].into_iter().filter(|((_, _, width, _), _)| *width > 0).collect()
+    }
+
+    fn split_x(&self, window_width: u32) -> i32 {
+        (self.split * window_width as f32).round() as i32
+    }
+
+    /// Drags the split line with the left mouse button, and takes the click that grabbed it so nothing
+    /// is picked with it. Shows which side is which on the HUD.
+    pub fn update(&mut self, hud: &mut Hud, input: &mut InputState, strings: &StringTable, current: Look) {
+        hud.set_visible(self.label, self.enabled());
+        if !self.enabled() {
+            self.dragging = false;
+            return;
+        }
+        let looks = self.looks(current);
+        hud.set_text(self.label, &strings.format("comparison.sides", &[
+            ("a", strings.get(looks[0].name_key(self.mode))),
+            ("b", strings.get(looks[1].name_key(self.mode))),
+        ]));
+
+        let window_width = input.window_size.0.max(1) as f32;
+        let cursor_x = input.cursor_position.0;
+        if input.was_just_clicked(MouseButton::Left) && (cursor_x - self.split * window_width).abs() <= GRAB_DISTANCE {
+            self.dragging = true;
+            input.just_clicked_buttons.retain(|&button| button != MouseButton::Left);
+        }
+        if !input.left_button_pressed {
+            self.dragging = false;
+        }
+        if self.dragging {
+            self.split = (cursor_x / window_width).clamp(0.0, 1.0);
+        }
+    }
+
+    /// Draw the split line over the window.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw_split_line(&self, window_width: u32, window_height: u32) {
+        let x = self.split_x(window_width) - LINE_WIDTH / 2;
+        gfx::set_scissor(Some((x, 0, LINE_WIDTH, window_height as i32)));
+        gl::ClearColor(LINE_COLOR[0], LINE_COLOR[1], LINE_COLOR[2], 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT);
+        gfx::set_scissor(None);
+    }
+}
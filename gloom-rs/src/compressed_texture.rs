@@ -0,0 +1,546 @@
+use std::path::Path;
+
+use crate::gfx;
+use crate::gl_caps::GlCaps;
+
+// * Block compressed textures
+// A 4K RGBA8 texture with its mipmaps takes up 85 MiB of video memory. Stored block compressed (BCn)
+// it is a quarter of that (BC3, BC5, BC7) or an eighth (BC1, BC4), and the GPU samples it as it is.
+// The compression is slow and lossy, so it is done offline (texconv, compressonator, toktx) into a
+// DDS or a KTX2 file, and loading one is just reading the blocks of every mip level and handing them
+// to glCompressedTexImage2D. AssetManager::load_texture does that for any path ending in .dds or .ktx2.
+//
+// Not every driver takes every format: BC1 to BC3 (S3TC) are an extension, though nearly universal on
+// desktops, BC4 and BC5 are core since 3.0 and BC7 since 4.2. Where a format is missing, the blocks
+// are decoded on the CPU and uploaded uncompressed, which looks the same and only costs the memory
+// back. There is no decoder for BC7, every driver able to run this has it anyway.
+//
+// Both containers store the top row first, OpenGL wants the bottom row first. Like the images that
+// load_texture flips, the blocks are flipped while loading: the rows of blocks are reversed, and the
+// rows inside every block, which BC1 to BC5 keep in separate bits. BC7 mixes its rows, so a BC7 file
+// has to be written bottom up (KTXorientation "ru" in a KTX2 file), or it is used upside down.
+//
+// Only plain 2D textures: no cube maps, arrays or 3D textures, and no supercompressed KTX2 (Basis, zstd).
+
+// S3TC is an extension (EXT_texture_compression_s3tc, and EXT_texture_sRGB for the sRGB variants),
+// the gl crate only has core enums
+const COMPRESSED_RGBA_S3TC_DXT1: gl::types::GLenum = 0x83F1;
+const COMPRESSED_RGBA_S3TC_DXT3: gl::types::GLenum = 0x83F2;
+const COMPRESSED_RGBA_S3TC_DXT5: gl::types::GLenum = 0x83F3;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: gl::types::GLenum = 0x8C4D;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT3: gl::types::GLenum = 0x8C4E;
+const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: gl::types::GLenum = 0x8C4F;
+
+const KTX2_IDENTIFIER: [u8; 12] = [0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BlockFormat {
+    Bc1, // RGB with 1 bit alpha, 8 bytes per 4x4 block
+    Bc2, // RGB with 4 bit alpha, 16 bytes
+    Bc3, // RGB with interpolated alpha, 16 bytes
+    Bc4, // One channel, 8 bytes
+    Bc5, // Two channels (normal maps), 16 bytes
+    Bc7, // RGBA in better quality, 16 bytes
+}
+
+impl BlockFormat {
+    pub fn block_bytes(self) -> usize {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc4 => 8,
+            _ => 16,
+        }
+    }
+
+    // BC4 and BC5 are data, they have no sRGB variants
+    fn gl_format(self, color_space: gfx::ColorSpace) -> gl::types::GLenum {
+        let srgb = color_space == gfx::ColorSpace::Srgb;
+        match self {
+            BlockFormat::Bc1 => if srgb { COMPRESSED_SRGB_ALPHA_S3TC_DXT1 } else { COMPRESSED_RGBA_S3TC_DXT1 },
+            BlockFormat::Bc2 => if srgb { COMPRESSED_SRGB_ALPHA_S3TC_DXT3 } else { COMPRESSED_RGBA_S3TC_DXT3 },
+            BlockFormat::Bc3 => if srgb { COMPRESSED_SRGB_ALPHA_S3TC_DXT5 } else { COMPRESSED_RGBA_S3TC_DXT5 },
+            BlockFormat::Bc4 => gl::COMPRESSED_RED_RGTC1,
+            BlockFormat::Bc5 => gl::COMPRESSED_RG_RGTC2,
+            BlockFormat::Bc7 => if srgb { gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM } else { gl::COMPRESSED_RGBA_BPTC_UNORM },
+        }
+    }
+
+    fn supported(self, support: &FormatSupport, color_space: gfx::ColorSpace) -> bool {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc2 | BlockFormat::Bc3 => support.s3tc && (color_space == gfx::ColorSpace::Linear || support.s3tc_srgb),
+            BlockFormat::Bc4 | BlockFormat::Bc5 => support.rgtc,
+            BlockFormat::Bc7 => support.bptc,
+        }
+    }
+}
+
+// Which block formats the driver takes, what AssetManager uploads compressed
+#[derive(Clone, Copy, Debug, Default)]
+pub struct FormatSupport {
+    pub s3tc      : bool, // BC1 to BC3
+    pub s3tc_srgb : bool, // Their sRGB variants
+    pub rgtc      : bool, // BC4 and BC5
+    pub bptc      : bool, // BC7
+}
+
+impl FormatSupport {
+    pub fn from_caps(caps: &GlCaps) -> FormatSupport {
+        FormatSupport {
+            s3tc: caps.s3tc_compression,
+            s3tc_srgb: caps.s3tc_srgb,
+            rgtc: caps.rgtc_compression,
+            bptc: caps.bptc_compression,
+        }
+    }
+}
+
+pub fn is_compressed_container(path: &str) -> bool {
+    let extension = Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+    matches!(extension.as_deref(), Some("dds") | Some("ktx2"))
+}
+
+pub struct CompressedImage {
+    pub format    : BlockFormat,
+    pub width     : u32,
+    pub height    : u32,
+    pub levels    : Vec<Vec<u8>>, // The blocks of every mip level, the largest first
+    pub bottom_up : bool,         // The first row is the bottom one already
+}
+
+impl CompressedImage {
+    /// A DDS or KTX2 file, by its extension.
+    pub fn load(path: &str) -> Result<CompressedImage, String> {
+        let bytes = std::fs::read(path).map_err(|error| format!("{}: {}", path, error))?;
+        let image = if path.to_lowercase().ends_with(".ktx2") { Self::parse_ktx2(&bytes) } else { Self::parse_dds(&bytes) };
+        image.map_err(|error| format!("{}: {}", path, error))
+    }
+
+    pub fn parse_dds(bytes: &[u8]) -> Result<CompressedImage, String> {
+        if bytes.get(0..4) != Some(b"DDS ") || read_u32(bytes, 4)? != 124 {
+            return Err("not a DDS file".to_string());
+        }
+        let flags = read_u32(bytes, 8)?;
+        let height = read_u32(bytes, 12)?;
+        let width = read_u32(bytes, 16)?;
+        let level_count = if flags & 0x20000 != 0 { read_u32(bytes, 28)?.max(1) } else { 1 };
+        check_level_count(level_count, width, height)?;
+        let caps2 = read_u32(bytes, 112)?;
+        if caps2 & 0x200 != 0 || caps2 & 0x200000 != 0 {
+            return Err("cube maps and volume textures aren't supported".to_string());
+        }
+
+        let (format, data_offset) = match bytes.get(84..88) {
+            Some(b"DXT1") => (BlockFormat::Bc1, 128),
+            Some(b"DXT2") | Some(b"DXT3") => (BlockFormat::Bc2, 128),
+            Some(b"DXT4") | Some(b"DXT5") => (BlockFormat::Bc3, 128),
+            Some(b"ATI1") | Some(b"BC4U") => (BlockFormat::Bc4, 128),
+            Some(b"ATI2") | Some(b"BC5U") => (BlockFormat::Bc5, 128),
+            Some(b"DX10") => {
+                if read_u32(bytes, 140)? > 1 {
+                    return Err("texture arrays aren't supported".to_string());
+                }
+                let format = match read_u32(bytes, 128)? {
+                    70..=72 => BlockFormat::Bc1,
+                    73..=75 => BlockFormat::Bc2,
+                    76..=78 => BlockFormat::Bc3,
+                    79 | 80 => BlockFormat::Bc4,
+                    82 | 83 => BlockFormat::Bc5,
+                    97..=99 => BlockFormat::Bc7,
+                    dxgi_format => return Err(format!("DXGI format {} isn't a supported block format", dxgi_format)),
+                };
+                (format, 148)
+            }
+            _ => return Err("only block compressed DDS files (BC1 to BC5 and BC7) are supported".to_string()),
+        };
+
+        let mut levels = vec![];
+        let mut offset = data_offset;
+        for level in 0..level_count {
+            let size = level_bytes(format, mip_size(width, level), mip_size(height, level)).ok_or("the texture is too large")?;
+            levels.push(read_bytes(bytes, offset, size).ok_or("the file is cut off")?.to_vec());
+            offset += size;
+        }
+        Ok(CompressedImage { format, width, height, levels, bottom_up: false })
+    }
+
+    pub fn parse_ktx2(bytes: &[u8]) -> Result<CompressedImage, String> {
+        if bytes.get(0..12) != Some(&KTX2_IDENTIFIER[..]) {
+            return Err("not a KTX2 file".to_string());
+        }
+        let format = match read_u32(bytes, 12)? {
+            131..=134 => BlockFormat::Bc1,
+            135 | 136 => BlockFormat::Bc2,
+            137 | 138 => BlockFormat::Bc3,
+            139 => BlockFormat::Bc4,
+            141 => BlockFormat::Bc5,
+            145 | 146 => BlockFormat::Bc7,
+            vk_format => return Err(format!("Vulkan format {} isn't a supported block format", vk_format)),
+        };
+        let width = read_u32(bytes, 20)?;
+        let height = read_u32(bytes, 24)?;
+        if read_u32(bytes, 28)? > 0 || read_u32(bytes, 32)? > 1 || read_u32(bytes, 36)? > 1 {
+            return Err("only plain 2D textures are supported, no volumes, arrays or cube maps".to_string());
+        }
+        let level_count = read_u32(bytes, 40)?.max(1);
+        check_level_count(level_count, width, height)?;
+        if read_u32(bytes, 44)? != 0 {
+            return Err("supercompressed KTX2 files (Basis, zstd) aren't supported".to_string());
+        }
+
+        // Key/value pairs, each its length, the key, a zero, the value, padded to 4 bytes
+        let (key_values_offset, key_values_length) = (read_u32(bytes, 56)? as usize, read_u32(bytes, 60)? as usize);
+        let key_values = read_bytes(bytes, key_values_offset, key_values_length).ok_or("the file is cut off")?;
+        let mut bottom_up = false;
+        let mut offset = 0;
+        while offset + 4 <= key_values.len() {
+            let length = read_u32(key_values, offset)? as usize;
+            let pair = read_bytes(key_values, offset + 4, length).ok_or("the key/value data is cut off")?;
+            if let Some(value) = pair.strip_prefix(b"KTXorientation\0") {
+                bottom_up = value.get(1) == Some(&b'u');
+            }
+            offset += 4 + length.div_ceil(4) * 4;
+        }
+
+        let mut levels = vec![];
+        for level in 0..level_count {
+            let index = 80 + 24 * level as usize;
+            let offset = read_u64(bytes, index)? as usize;
+            let length = read_u64(bytes, index + 8)? as usize;
+            if Some(length) != level_bytes(format, mip_size(width, level), mip_size(height, level)) {
+                return Err(format!("mip level {} is {} bytes, not the size of its blocks", level, length));
+            }
+            levels.push(read_bytes(bytes, offset, length).ok_or("the file is cut off")?.to_vec());
+        }
+        Ok(CompressedImage { format, width, height, levels, bottom_up })
+    }
+
+    // Video memory the blocks take up
+    pub fn gpu_bytes(&self) -> usize {
+        self.levels.iter().map(|level| level.len()).sum()
+    }
+
+    /// Upload into `texture_id` compressed if the driver takes the format, decoded otherwise.
+    /// Gives the bytes of video memory it takes up.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn upload(&self, texture_id: u32, support: &FormatSupport, color_space: gfx::ColorSpace) -> Result<usize, String> {
+        let compressed = self.format.supported(support, color_space);
+        if !compressed && self.format == BlockFormat::Bc7 {
+            return Err("BC7 needs OpenGL 4.2 or ARB_texture_compression_bptc".to_string());
+        }
+        if !self.bottom_up && self.format == BlockFormat::Bc7 {
+            println!("A BC7 texture can't be flipped while loading, it is upside down unless it is written bottom up");
+        }
+
+        // Every level has to be in the same format, so if any of them can't be flipped they are all decoded
+        let compressed_levels: Option<Vec<Vec<u8>>> = if !compressed {
+            None
+        } else if self.bottom_up || self.format == BlockFormat::Bc7 {
+            Some(self.levels.clone())
+        } else {
+            (0..self.levels.len()).map(|level| self.flipped_level(level)).collect()
+        };
+
+        gl::BindTexture(gl::TEXTURE_2D, texture_id);
+        let mut gpu_bytes = 0;
+        for level in 0..self.levels.len() {
+            let (width, height) = (mip_size(self.width, level as u32) as i32, mip_size(self.height, level as u32) as i32);
+            if let Some(compressed_levels) = &compressed_levels {
+                let blocks = &compressed_levels[level];
+                gl::CompressedTexImage2D(
+                    gl::TEXTURE_2D,
+                    level as i32,
+                    self.format.gl_format(color_space),
+                    width,
+                    height,
+                    0,
+                    blocks.len() as i32,
+                    blocks.as_ptr() as *const std::ffi::c_void,
+                );
+                gpu_bytes += blocks.len();
+            } else {
+                let pixels = self.decode_level(level);
+                let internal_format = match color_space {
+                    gfx::ColorSpace::Linear => gl::RGBA8,
+                    gfx::ColorSpace::Srgb   => gl::SRGB8_ALPHA8,
+                };
+                gl::TexImage2D(
+                    gl::TEXTURE_2D,
+                    level as i32,
+                    internal_format as i32,
+                    width,
+                    height,
+                    0,
+                    gl::RGBA,
+                    gl::UNSIGNED_BYTE,
+                    pixels.as_ptr() as *const std::ffi::c_void,
+                );
+                gpu_bytes += pixels.len();
+            }
+        }
+
+        let mipmapped = self.levels.len() > 1;
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, self.levels.len() as i32 - 1);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, if mipmapped { gl::LINEAR_MIPMAP_LINEAR } else { gl::LINEAR } as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        Ok(gpu_bytes)
+    }
+
+    // The blocks of a level with the rows in the opposite order. None for BC7, and for heights the
+    // blocks can't be flipped at (not a whole number of blocks, but more than one row of them).
+    fn flipped_level(&self, level: usize) -> Option<Vec<u8>> {
+        let (width, height) = (mip_size(self.width, level as u32), mip_size(self.height, level as u32));
+        if self.format == BlockFormat::Bc7 || (height % 4 != 0 && height > 4) {
+            return None;
+        }
+        let rows = height.min(4) as usize;
+        let row_bytes = blocks_across(width) * self.format.block_bytes();
+        let mut flipped = Vec::with_capacity(self.levels[level].len());
+        for block_row in self.levels[level].chunks_exact(row_bytes).rev() {
+            for block in block_row.chunks_exact(self.format.block_bytes()) {
+                let mut block = block.to_vec();
+                match self.format {
+                    BlockFormat::Bc1 => flip_color_block(&mut block, rows),
+                    BlockFormat::Bc2 => {
+                        flip_explicit_alpha_block(&mut block[0..8], rows);
+                        flip_color_block(&mut block[8..16], rows);
+                    }
+                    BlockFormat::Bc3 => {
+                        flip_interpolated_block(&mut block[0..8], rows);
+                        flip_color_block(&mut block[8..16], rows);
+                    }
+                    BlockFormat::Bc4 => flip_interpolated_block(&mut block, rows),
+                    BlockFormat::Bc5 => {
+                        flip_interpolated_block(&mut block[0..8], rows);
+                        flip_interpolated_block(&mut block[8..16], rows);
+                    }
+                    BlockFormat::Bc7 => unreachable!(),
+                }
+                flipped.extend(block);
+            }
+        }
+        Some(flipped)
+    }
+
+    // RGBA8 pixels of a level, first row at the bottom. BC4 is red only and BC5 red and green,
+    // like the GPU samples them.
+    fn decode_level(&self, level: usize) -> Vec<u8> {
+        let (width, height) = (mip_size(self.width, level as u32) as usize, mip_size(self.height, level as u32) as usize);
+        let across = blocks_across(width as u32);
+        let mut pixels = vec![0u8; width * height * 4];
+        for (i, block) in self.levels[level].chunks_exact(self.format.block_bytes()).enumerate() {
+            let texels = decode_block(self.format, block);
+            let (block_x, block_y) = (i % across * 4, i / across * 4);
+            for (j, texel) in texels.iter().enumerate() {
+                let (x, y) = (block_x + j % 4, block_y + j / 4);
+                if x < width && y < height {
+                    let row = if self.bottom_up { y } else { height - 1 - y };
+                    pixels[(row * width + x) * 4..(row * width + x) * 4 + 4].copy_from_slice(texel);
+                }
+            }
+        }
+        pixels
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Result<u32, String> {
+    let field = bytes.get(offset..offset + 4).ok_or("the file is cut off")?;
+    Ok(u32::from_le_bytes([field[0], field[1], field[2], field[3]]))
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> Result<u64, String> {
+    Ok(read_u32(bytes, offset)? as u64 | (read_u32(bytes, offset + 4)? as u64) << 32)
+}
+
+// `length` bytes from `offset` on, None if they aren't all in `bytes` (offsets from the file can be anything)
+fn read_bytes(bytes: &[u8], offset: usize, length: usize) -> Option<&[u8]> {
+    offset.checked_add(length).and_then(|end| bytes.get(offset..end))
+}
+
+fn mip_size(size: u32, level: u32) -> u32 {
+    size.checked_shr(level).unwrap_or(0).max(1)
+}
+
+// A full mip chain goes down to 1 by 1, there can't be more levels than halvings of the larger side
+fn check_level_count(level_count: u32, width: u32, height: u32) -> Result<(), String> {
+    let full_chain = u32::BITS - width.max(height).max(1).leading_zeros();
+    if level_count > full_chain {
+        return Err(format!("{} mip levels, a {}x{} texture has at most {}", level_count, width, height, full_chain));
+    }
+    Ok(())
+}
+
+fn blocks_across(size: u32) -> usize {
+    size.div_ceil(4) as usize
+}
+
+// None if it doesn't fit in a usize, which a file can claim with a large enough width and height
+fn level_bytes(format: BlockFormat, width: u32, height: u32) -> Option<usize> {
+    blocks_across(width).checked_mul(blocks_across(height))?.checked_mul(format.block_bytes())
+}
+
+// * Flipping blocks
+// Every block keeps its rows of indices apart, so the first `rows` rows (all 4, or the height of a
+// level less than a block tall) are put in the opposite order.
+
+// BC1 colors: two endpoints, then a byte of 2 bit indices per row
+fn flip_color_block(block: &mut [u8], rows: usize) {
+    block[4..4 + rows].reverse();
+}
+
+// BC2 alpha: two bytes of 4 bit alphas per row
+fn flip_explicit_alpha_block(block: &mut [u8], rows: usize) {
+    let mut row_values: Vec<[u8; 2]> = block.chunks_exact(2).map(|row| [row[0], row[1]]).collect();
+    row_values[..rows].reverse();
+    for (row, values) in block.chunks_exact_mut(2).zip(row_values) {
+        row.copy_from_slice(&values);
+    }
+}
+
+// BC4 (and the alpha of BC3): two endpoints, then 48 bits of 3 bit indices, 12 bits per row
+fn flip_interpolated_block(block: &mut [u8], rows: usize) {
+    let bits = block[2..8].iter().rev().fold(0u64, |bits, &byte| bits << 8 | byte as u64);
+    let mut row_bits: Vec<u64> = (0..4).map(|row| bits >> (12 * row) & 0xFFF).collect();
+    row_bits[..rows].reverse();
+    let bits = row_bits.iter().enumerate().fold(0u64, |bits, (row, &row_bits)| bits | row_bits << (12 * row));
+    for (i, byte) in block[2..8].iter_mut().enumerate() {
+        *byte = (bits >> (8 * i)) as u8;
+    }
+}
+
+// * Decoding blocks
+// The fallback for drivers without the format. Gives the 16 texels of a block row by row, top first.
+
+fn decode_block(format: BlockFormat, block: &[u8]) -> [[u8; 4]; 16] {
+    match format {
+        BlockFormat::Bc1 => decode_color_block(block, true),
+        BlockFormat::Bc2 => {
+            let mut texels = decode_color_block(&block[8..16], false);
+            let alphas = block[0..8].iter().rev().fold(0u64, |bits, &byte| bits << 8 | byte as u64);
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[3] = (alphas >> (4 * i) & 0xF) as u8 * 17;
+            }
+            texels
+        }
+        BlockFormat::Bc3 => {
+            let mut texels = decode_color_block(&block[8..16], false);
+            for (texel, alpha) in texels.iter_mut().zip(decode_interpolated_block(&block[0..8])) {
+                texel[3] = alpha;
+            }
+            texels
+        }
+        BlockFormat::Bc4 => decode_interpolated_block(block).map(|red| [red, 0, 0, 255]),
+        BlockFormat::Bc5 => {
+            let (red, green) = (decode_interpolated_block(&block[0..8]), decode_interpolated_block(&block[8..16]));
+            let mut texels = [[0, 0, 0, 255]; 16];
+            for (i, texel) in texels.iter_mut().enumerate() {
+                texel[0] = red[i];
+                texel[1] = green[i];
+            }
+            texels
+        }
+        BlockFormat::Bc7 => unreachable!("BC7 is never decoded on the CPU"),
+    }
+}
+
+// BC1 colors. Only BC1 itself has the mode with 3 colors and a transparent black, picked by the
+// endpoints being in increasing order.
+fn decode_color_block(block: &[u8], allow_transparent: bool) -> [[u8; 4]; 16] {
+    let endpoint = |offset: usize| u16::from_le_bytes([block[offset], block[offset + 1]]);
+    let (color0, color1) = (endpoint(0), endpoint(2));
+    let expand = |color: u16| {
+        let (r, g, b) = ((color >> 11 & 31) as u32, (color >> 5 & 63) as u32, (color & 31) as u32);
+        [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2]
+    };
+    let (a, b) = (expand(color0), expand(color1));
+    let mix = |weight_a: u32, weight_b: u32, divisor: u32| {
+        [0, 1, 2].map(|channel| ((a[channel] * weight_a + b[channel] * weight_b) / divisor) as u8)
+    };
+    let palette: [[u8; 4]; 4] = if color0 > color1 || !allow_transparent {
+        let (c2, c3) = (mix(2, 1, 3), mix(1, 2, 3));
+        [mix(1, 0, 1), mix(0, 1, 1), c2, c3].map(|[r, g, b]| [r, g, b, 255])
+    } else {
+        let c2 = mix(1, 1, 2);
+        [[a[0] as u8, a[1] as u8, a[2] as u8, 255], [b[0] as u8, b[1] as u8, b[2] as u8, 255], [c2[0], c2[1], c2[2], 255], [0, 0, 0, 0]]
+    };
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let mut texels = [[0; 4]; 16];
+    for (i, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (2 * i) & 3) as usize];
+    }
+    texels
+}
+
+// BC4 values: 8 steps between the endpoints, or 6 steps and 0 and 255 when they are in increasing order
+fn decode_interpolated_block(block: &[u8]) -> [u8; 16] {
+    let (value0, value1) = (block[0] as u32, block[1] as u32);
+    let mut palette = [0u8; 8];
+    palette[0] = value0 as u8;
+    palette[1] = value1 as u8;
+    if value0 > value1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * value0 + i as u32 * value1) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * value0 + i as u32 * value1) / 5) as u8;
+        }
+        palette[6] = 0;
+        palette[7] = 255;
+    }
+    let bits = block[2..8].iter().rev().fold(0u64, |bits, &byte| bits << 8 | byte as u64);
+    let mut values = [0; 16];
+    for (i, value) in values.iter_mut().enumerate() {
+        *value = palette[(bits >> (3 * i) & 7) as usize];
+    }
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A BC1 KTX2 header for a 4x4 texture with one level, at `offset` and `length` bytes long
+    fn ktx2_header(width: u32, offset: u64, length: u64) -> Vec<u8> {
+        let mut bytes = KTX2_IDENTIFIER.to_vec();
+        for field in [131, 1, width, 4, 0, 0, 1, 1, 0, 0, 0, 0, 0] {
+            bytes.extend_from_slice(&u32::to_le_bytes(field));
+        }
+        bytes.extend_from_slice(&[0; 16]); // No supercompression global data
+        for field in [offset, length, length] {
+            bytes.extend_from_slice(&u64::to_le_bytes(field));
+        }
+        bytes
+    }
+
+    #[test]
+    fn reads_a_well_formed_ktx2_file() {
+        let mut bytes = ktx2_header(4, 104, 8);
+        bytes.extend_from_slice(&[0xAA; 8]);
+        let image = CompressedImage::parse_ktx2(&bytes).unwrap();
+        assert_eq!(image.format, BlockFormat::Bc1);
+        assert_eq!(image.levels, vec![vec![0xAA; 8]]);
+    }
+
+    #[test]
+    fn rejects_a_level_past_the_end_of_the_address_space() {
+        let bytes = ktx2_header(4, u64::MAX - 3, 8);
+        assert!(CompressedImage::parse_ktx2(&bytes).is_err());
+    }
+
+    #[test]
+    fn rejects_a_size_too_large_to_count() {
+        assert!(CompressedImage::parse_ktx2(&ktx2_header(u32::MAX, 104, 8)).is_err());
+
+        let mut dds = b"DDS ".to_vec();
+        dds.resize(128, 0);
+        dds[4..8].copy_from_slice(&124u32.to_le_bytes());
+        dds[12..16].copy_from_slice(&u32::MAX.to_le_bytes());
+        dds[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+        dds[84..88].copy_from_slice(b"DXT5");
+        assert!(CompressedImage::parse_dds(&dds).is_err());
+    }
+}
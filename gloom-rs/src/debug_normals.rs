@@ -0,0 +1,82 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::scene_graph::{NodeId, SceneNode, WorldMatrix};
+use crate::shader::Shader;
+
+// * Normal debugging
+// Draws the normals of a node (and everything below it) as lines, made by a geometry shader from
+// the same triangles the node is drawn with. Vertex normals are yellow, face normals (from the
+// winding order) are cyan. When the lighting looks wrong, this shows whether the normals are
+// missing, flipped or not smoothed the way they should be.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum NormalDebugMode {
+    Off,
+    Vertex,
+    Face,
+    Both,
+}
+
+impl NormalDebugMode {
+    pub fn next(self) -> NormalDebugMode {
+        match self {
+            NormalDebugMode::Off    => NormalDebugMode::Vertex,
+            NormalDebugMode::Vertex => NormalDebugMode::Face,
+            NormalDebugMode::Face   => NormalDebugMode::Both,
+            NormalDebugMode::Both   => NormalDebugMode::Off,
+        }
+    }
+}
+
+pub struct NormalDebug {
+    pub mode          : NormalDebugMode,
+    pub normal_length : f32, // In world units
+    shader            : ShaderHandle,
+}
+
+impl NormalDebug {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager) -> NormalDebug {
+        NormalDebug {
+            mode: NormalDebugMode::Off,
+            normal_length: 1.5,
+            shader: assets.load_shader(&["shaders/debug_normals.vert", "shaders/debug_normals.geom", "shaders/debug_normals.frag"]),
+        }
+    }
+
+    /// Draw the normals of the node with `node_id`. `draw` has to draw a node and its children with
+    /// the given shader, like draw_scene does, given the transformation of the node's parent.
+    /// Leaves `scene_shader` active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw<F: FnMut(&SceneNode, &WorldMatrix, &Shader)>(
+        &self,
+        assets: &AssetManager,
+        scene_graph: &SceneNode,
+        node_id: NodeId,
+        view_projection_matrix: &glm::Mat4,
+        scene_shader: &Shader,
+        mut draw: F,
+    ) {
+        if self.mode == NormalDebugMode::Off {
+            return;
+        }
+        let (node, parent_transform) = match scene_graph.find(node_id, &glm::identity()) {
+            Some(found) => found,
+            None => return,
+        };
+
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", view_projection_matrix);
+        shader.set_uniform_float("normal_length", self.normal_length);
+        shader.set_uniform_int("show_vertex_normals", matches!(self.mode, NormalDebugMode::Vertex | NormalDebugMode::Both) as i32);
+        shader.set_uniform_int("show_face_normals", matches!(self.mode, NormalDebugMode::Face | NormalDebugMode::Both) as i32);
+        draw(node, &parent_transform, shader);
+
+        scene_shader.activate();
+    }
+}
@@ -0,0 +1,194 @@
+use glutin::window::{CursorGrabMode, Fullscreen, Window};
+
+// * Window modes
+// F11 (or Alt+Enter) cycles windowed -> borderless fullscreen -> exclusive fullscreen -> windowed.
+// Switching mode makes the window send a normal Resized event, so the viewport and the aspect ratio
+// are updated by the same code that handles the user dragging the window edges.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WindowMode {
+    Windowed,
+    Borderless,
+    Exclusive,
+}
+
+impl WindowMode {
+    pub fn next(self) -> WindowMode {
+        match self {
+            WindowMode::Windowed   => WindowMode::Borderless,
+            WindowMode::Borderless => WindowMode::Exclusive,
+            WindowMode::Exclusive  => WindowMode::Windowed,
+        }
+    }
+
+    // Switch the window over to this mode. Returns the mode that was actually applied,
+    // exclusive fullscreen falls back to borderless if the monitor doesn't report any video modes.
+    pub fn apply(self, window: &Window) -> WindowMode {
+        match self {
+            WindowMode::Windowed => {
+                window.set_fullscreen(None);
+                WindowMode::Windowed
+            }
+            WindowMode::Borderless => {
+                window.set_fullscreen(Some(Fullscreen::Borderless(window.current_monitor())));
+                WindowMode::Borderless
+            }
+            WindowMode::Exclusive => {
+                // Pick the biggest resolution, and the highest refresh rate of those
+                let video_mode = window.current_monitor().and_then(|monitor| {
+                    monitor.video_modes().max_by_key(|mode| {
+                        let size = mode.size();
+                        (size.width * size.height, mode.refresh_rate_millihertz())
+                    })
+                });
+                match video_mode {
+                    Some(mode) => {
+                        window.set_fullscreen(Some(Fullscreen::Exclusive(mode)));
+                        WindowMode::Exclusive
+                    }
+                    None => {
+                        println!("No video modes available for exclusive fullscreen, using borderless");
+                        WindowMode::Borderless.apply(window)
+                    }
+                }
+            }
+        }
+    }
+}
+
+
+// * Mouse capture
+// For flying around with the mouse like in a first person game, instead of holding the right button.
+// The cursor is hidden and kept in the window, and the camera turns with the raw mouse motion, which
+// keeps coming when the cursor can't move any further. Wayland (and macOS) can lock the cursor where
+// it is, X11 and Windows can only confine it to the window, so locking is tried first.
+//
+// A grab has to go when the window loses the focus: X11 would keep the cursor in a window the user
+// alt-tabbed away from, and Wayland drops the lock by itself but the cursor would stay hidden. The
+// render thread releases it on focus loss and captures again once the focus is back (see main.rs).
+
+// Capture or release the cursor. False if the window system wouldn't grab it, the cursor is shown then.
+pub fn set_mouse_captured(window: &Window, captured: bool) -> bool {
+    if !captured {
+        let _ = window.set_cursor_grab(CursorGrabMode::None);
+        window.set_cursor_visible(true);
+        return true;
+    }
+    let grabbed = window.set_cursor_grab(CursorGrabMode::Locked)
+        .or_else(|_| window.set_cursor_grab(CursorGrabMode::Confined));
+    match grabbed {
+        Ok(()) => {
+            window.set_cursor_visible(false);
+            true
+        }
+        Err(error) => {
+            println!("Could not capture the mouse: {}", error);
+            false
+        }
+    }
+}
+
+
+// * Vsync at runtime
+// glutin only lets us pick vsync when the context is built, so to change it later we look up the
+// platform's swap interval extension ourselves. Not every driver has one, in which case vsync
+// just stays the way the context was built. They don't agree on what they return either: the WGL one
+// returns TRUE when it worked, the GLX ones 0 (and an error code when not).
+
+type SwapIntervalFn = extern "system" fn(i32) -> i32;
+
+// Which of them was loaded, for what it returns
+#[derive(Clone, Copy, Debug)]
+enum SwapIntervalApi {
+    Wgl,
+    Glx,
+}
+
+impl SwapIntervalApi {
+    fn succeeded(self, result: i32) -> bool {
+        match self {
+            SwapIntervalApi::Wgl => result != 0,
+            SwapIntervalApi::Glx => result == 0,
+        }
+    }
+}
+
+pub struct SwapControl {
+    set_interval: Option<(SwapIntervalFn, SwapIntervalApi)>,
+}
+
+impl SwapControl {
+    // Has to be called on the render thread, with the context current
+    pub fn load<F: Fn(&str) -> *const std::ffi::c_void>(get_proc_address: F) -> SwapControl {
+        // (eglSwapInterval also wants the display, so EGL isn't supported here)
+        let names = [
+            ("wglSwapIntervalEXT",  SwapIntervalApi::Wgl), // Windows
+            ("glXSwapIntervalMESA", SwapIntervalApi::Glx), // Linux, Mesa drivers
+            ("glXSwapIntervalSGI",  SwapIntervalApi::Glx), // Linux, most others (some refuse an interval of 0)
+        ];
+        let set_interval = names.iter()
+            .map(|&(name, api)| (get_proc_address(name), api))
+            .find(|(ptr, _)| !ptr.is_null())
+            .map(|(ptr, api)| (unsafe { std::mem::transmute::<*const std::ffi::c_void, SwapIntervalFn>(ptr) }, api));
+
+        if set_interval.is_none() {
+            println!("No swap interval extension found, vsync can't be changed at runtime");
+        }
+        SwapControl { set_interval }
+    }
+
+    // Returns whether the driver accepted the change
+    pub fn set_vsync(&self, enabled: bool) -> bool {
+        match self.set_interval {
+            Some((set_interval, api)) => api.succeeded(set_interval(if enabled { 1 } else { 0 })),
+            None => false,
+        }
+    }
+}
+
+
+// * Frame limiter
+// Sleeps away whatever is left of the frame budget. thread::sleep tends to oversleep by a
+// millisecond or so, so we sleep until shortly before the deadline and spin for the rest.
+
+pub struct FrameLimiter {
+    pub max_fps : Option<u32>,
+    next_frame  : std::time::Instant,
+}
+
+impl FrameLimiter {
+    pub fn new(max_fps: Option<u32>) -> FrameLimiter {
+        FrameLimiter {
+            max_fps,
+            next_frame: std::time::Instant::now(),
+        }
+    }
+
+    // Call once per frame, right before swapping buffers
+    pub fn wait(&mut self) {
+        let max_fps = match self.max_fps {
+            Some(fps) if fps > 0 => fps,
+            _ => {
+                self.next_frame = std::time::Instant::now();
+                return;
+            }
+        };
+        let frame_time = std::time::Duration::from_secs_f64(1.0 / max_fps as f64);
+        let spin_margin = std::time::Duration::from_millis(2);
+
+        let now = std::time::Instant::now();
+        if self.next_frame > now {
+            let remaining = self.next_frame - now;
+            if remaining > spin_margin {
+                std::thread::sleep(remaining - spin_margin);
+            }
+            while std::time::Instant::now() < self.next_frame {
+                std::hint::spin_loop();
+            }
+            self.next_frame += frame_time;
+        } else {
+            // We're already late, don't try to catch up by rushing the next frames
+            self.next_frame = now + frame_time;
+        }
+    }
+}
@@ -0,0 +1,405 @@
+extern crate nalgebra_glm as glm;
+
+use std::rc::Rc;
+
+use rand::Rng;
+use rayon::prelude::*;
+
+use crate::lights::{Light, WorldLight};
+use crate::material::Material;
+use crate::path::PathFollower;
+use crate::scene_graph;
+use crate::snapshot::{DrawItem, FrameSnapshot};
+
+// * Entities and components
+// A second way to put things in the scene, next to the scene graph. An entity is only an ID, what it
+// is comes from the components stored for it: a Transform to be somewhere, a MeshRenderer to be drawn,
+// a Light, a ParticleEmitter, an Animator. The systems at the bottom each run over the entities with the
+// components they care about, so a new kind of object is a new mix of components rather than another
+// hand-written list of node pointers in main.rs.
+//
+// Once a frame, in this order:
+//
+//     ecs::animate(&mut world, delta_time);              // Animators move their Transforms
+//     ecs::update_transforms(&mut world);                // World matrices, parents before children
+//     ecs::update_particles(&mut world, delta_time);     // Emit from where the emitters are now
+//     ecs::extract(&world, snapshot);                    // Into the frame snapshot, after capture()
+//
+// Everything is drawn from the frame snapshot like the scene graph is, so entities show up in every
+// pass. Their draws have node ID 0, they can't be picked or followed by sounds and triggers.
+
+// Entity IDs are reused after an entity is despawned, the generation tells the old and the new apart
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Entity {
+    index      : u32,
+    generation : u32,
+}
+
+// One component type for every entity, indexed by the entity's index
+pub struct Storage<T> {
+    slots: Vec<Option<(u32, T)>>, // With the generation of the entity that owns it
+}
+
+impl<T> Storage<T> {
+    fn new() -> Storage<T> {
+        Storage { slots: vec![] }
+    }
+
+    pub fn insert(&mut self, entity: Entity, component: T) {
+        let index = entity.index as usize;
+        if self.slots.len() <= index {
+            self.slots.resize_with(index + 1, || None);
+        }
+        self.slots[index] = Some((entity.generation, component));
+    }
+
+    pub fn remove(&mut self, entity: Entity) -> Option<T> {
+        self.get(entity)?;
+        self.slots[entity.index as usize].take().map(|(_, component)| component)
+    }
+
+    pub fn get(&self, entity: Entity) -> Option<&T> {
+        match self.slots.get(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => Some(component),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, entity: Entity) -> Option<&mut T> {
+        match self.slots.get_mut(entity.index as usize) {
+            Some(Some((generation, component))) if *generation == entity.generation => Some(component),
+            _ => None,
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (Entity, &T)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            slot.as_ref().map(|(generation, component)| (Entity { index: index as u32, generation: *generation }, component))
+        })
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (Entity, &mut T)> {
+        self.slots.iter_mut().enumerate().filter_map(|(index, slot)| {
+            slot.as_mut().map(|(generation, component)| (Entity { index: index as u32, generation: *generation }, component))
+        })
+    }
+}
+
+// * Components
+#[derive(Clone, Debug)]
+pub struct Transform {
+    pub position     : glm::Vec3,      // Relative to the parent, like a SceneNode
+    pub rotation     : glm::Vec3,      // x = pitch, y = yaw, z = roll
+    pub scale        : glm::Vec3,
+    pub parent       : Option<Entity>, // Moves along with its parent, if it has one that is still alive
+    pub world_matrix : glm::Mat4,      // Filled in by update_transforms
+}
+
+impl Transform {
+    pub fn at(position: glm::Vec3) -> Transform {
+        Transform {
+            position,
+            rotation     : glm::zero(),
+            scale        : glm::vec3(1.0, 1.0, 1.0),
+            parent       : None,
+            world_matrix : glm::identity(),
+        }
+    }
+
+    pub fn with_parent(mut self, parent: Entity) -> Transform {
+        self.parent = Some(parent);
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Transform {
+        self.scale = glm::vec3(scale, scale, scale);
+        self
+    }
+
+    // Same order as SceneNode::local_transform, without the reference point
+    pub fn local_transform(&self) -> glm::Mat4 {
+        let rotation_matrix = glm::rotation(self.rotation.x, &glm::vec3(1.0, 0.0, 0.0))
+            * glm::rotation(self.rotation.y, &glm::vec3(0.0, 1.0, 0.0))
+            * glm::rotation(self.rotation.z, &glm::vec3(0.0, 0.0, 1.0));
+        glm::translation(&self.position) * rotation_matrix * glm::scaling(&self.scale)
+    }
+
+    pub fn world_position(&self) -> glm::Vec3 {
+        (self.world_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz()
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct MeshRenderer {
+    pub vao_id      : u32,
+    pub index_count : i32,
+    pub material    : Option<Rc<Material>>,
+    pub visible     : bool,
+}
+
+impl MeshRenderer {
+    pub fn new(vao_id: u32, index_count: i32, material: Option<Rc<Material>>) -> MeshRenderer {
+        MeshRenderer { vao_id, index_count, material, visible: true }
+    }
+}
+
+pub enum Animation {
+    Spin { speed: glm::Vec3 },                                   // Radians per second around each axis
+    Bob { base: glm::Vec3, amplitude: f32, frequency: f32 },     // Up and down around `base`
+    FollowPath(PathFollower),                                    // Position and heading from the path
+}
+
+pub struct Animator {
+    pub animation : Animation,
+    pub speed     : f32, // Multiplies the time, 0 pauses it
+    time          : f32,
+}
+
+impl Animator {
+    pub fn new(animation: Animation) -> Animator {
+        Animator { animation, speed: 1.0, time: 0.0 }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Particle {
+    pub position : glm::Vec3, // World space, a particle doesn't follow the emitter once it is out
+    pub velocity : glm::Vec3,
+    pub age      : f32,
+}
+
+// Spits out small copies of a mesh from where its entity is (or anywhere in a box around it), which
+// drift off and shrink away
+#[derive(Clone, Debug)]
+pub struct ParticleEmitter {
+    pub vao_id      : u32,
+    pub index_count : i32,
+    pub material    : Option<Rc<Material>>,
+    pub mesh_center : glm::Vec3, // Where the middle of the mesh is, in its own units
+    pub rate        : f32,       // Particles per second
+    pub lifetime    : f32,       // Seconds
+    pub velocity    : glm::Vec3, // Starting velocity, before the spread
+    pub spread      : f32,       // Random extra speed in every direction
+    pub area        : glm::Vec3, // Half size of the box around the entity that particles start in
+    pub gravity     : glm::Vec3,
+    pub size        : f32,       // Scale of the mesh for a new particle
+    pub max_count   : usize,
+    pub emitting    : bool,
+    pub particles   : Vec<Particle>,
+    to_emit         : f32,       // Fraction of a particle left over from the last frame
+}
+
+impl ParticleEmitter {
+    pub fn new(vao_id: u32, index_count: i32, material: Option<Rc<Material>>, rate: f32, lifetime: f32) -> ParticleEmitter {
+        ParticleEmitter {
+            vao_id,
+            index_count,
+            material,
+            mesh_center : glm::zero(),
+            rate,
+            lifetime,
+            velocity    : glm::vec3(0.0, 1.0, 0.0),
+            spread      : 0.5,
+            area        : glm::zero(),
+            gravity     : glm::zero(),
+            size        : 1.0,
+            max_count   : 256,
+            emitting    : true,
+            particles   : vec![],
+            to_emit     : 0.0,
+        }
+    }
+}
+
+// * World
+pub struct World {
+    generations           : Vec<u32>,
+    free                  : Vec<u32>,
+    pub transforms        : Storage<Transform>,
+    pub mesh_renderers    : Storage<MeshRenderer>,
+    pub lights            : Storage<Light>,
+    pub particle_emitters : Storage<ParticleEmitter>,
+    pub animators         : Storage<Animator>,
+}
+
+impl World {
+    pub fn new() -> World {
+        World {
+            generations       : vec![],
+            free              : vec![],
+            transforms        : Storage::new(),
+            mesh_renderers    : Storage::new(),
+            lights            : Storage::new(),
+            particle_emitters : Storage::new(),
+            animators         : Storage::new(),
+        }
+    }
+
+    // A new entity without any components
+    pub fn spawn(&mut self) -> Entity {
+        match self.free.pop() {
+            Some(index) => Entity { index, generation: self.generations[index as usize] },
+            None => {
+                self.generations.push(0);
+                Entity { index: self.generations.len() as u32 - 1, generation: 0 }
+            }
+        }
+    }
+
+    // Remove the entity and all its components. Its children stay, without a parent.
+    pub fn despawn(&mut self, entity: Entity) {
+        if !self.is_alive(entity) {
+            return;
+        }
+        self.transforms.remove(entity);
+        self.mesh_renderers.remove(entity);
+        self.lights.remove(entity);
+        self.particle_emitters.remove(entity);
+        self.animators.remove(entity);
+        self.generations[entity.index as usize] += 1;
+        self.free.push(entity.index);
+    }
+
+    pub fn is_alive(&self, entity: Entity) -> bool {
+        self.generations.get(entity.index as usize) == Some(&entity.generation)
+    }
+
+    pub fn entity_count(&self) -> usize {
+        self.generations.len() - self.free.len()
+    }
+}
+
+// * Systems
+
+// Move every entity with both an Animator and a Transform
+pub fn animate(world: &mut World, delta_time: f32) {
+    for (entity, animator) in world.animators.iter_mut() {
+        let transform = match world.transforms.get_mut(entity) {
+            Some(transform) => transform,
+            None => continue,
+        };
+        let delta_time = delta_time * animator.speed;
+        animator.time += delta_time;
+
+        match &mut animator.animation {
+            Animation::Spin { speed } => transform.rotation += *speed * delta_time,
+            Animation::Bob { base, amplitude, frequency } => {
+                // Offset by the entity, so things spawned on the same frame don't bob in step
+                let phase = animator.time * *frequency + entity.index as f32;
+                transform.position = *base + glm::vec3(0.0, phase.sin() * *amplitude, 0.0);
+            }
+            Animation::FollowPath(route) => {
+                route.update(delta_time);
+                let (position, heading) = route.heading();
+                transform.position = position;
+                transform.rotation = glm::vec3(heading.pitch, heading.yaw, heading.roll);
+            }
+        }
+    }
+}
+
+// Work out the world matrix of every Transform, from its parents up
+pub fn update_transforms(world: &mut World) {
+    let local: Vec<(Entity, glm::Mat4, Option<Entity>)> = world.transforms.iter()
+        .map(|(entity, transform)| (entity, transform.local_transform(), transform.parent))
+        .collect();
+
+    for &(entity, local_transform, parent) in &local {
+        let mut world_matrix = local_transform;
+        let mut parent = parent;
+        // The depth limit stops a parent loop from hanging the frame
+        for _ in 0..64 {
+            let (parent_transform, grandparent) = match parent.and_then(|parent| local.iter().find(|(other, _, _)| *other == parent)) {
+                Some(&(_, parent_transform, grandparent)) => (parent_transform, grandparent),
+                None => break,
+            };
+            world_matrix = parent_transform * world_matrix;
+            parent = grandparent;
+        }
+        if let Some(transform) = world.transforms.get_mut(entity) {
+            transform.world_matrix = world_matrix;
+        }
+    }
+}
+
+// Age and move the particles, and emit new ones from where the emitters are now
+pub fn update_particles(world: &mut World, delta_time: f32) {
+    let mut rng = rand::thread_rng();
+    for (entity, emitter) in world.particle_emitters.iter_mut() {
+        let lifetime = emitter.lifetime;
+        let gravity = emitter.gravity;
+        // Moving them is spread over the thread pool, only dropping the dead ones is serial
+        emitter.particles.par_iter_mut().with_min_len(1024).for_each(|particle| {
+            particle.age += delta_time;
+            particle.velocity += gravity * delta_time;
+            particle.position += particle.velocity * delta_time;
+        });
+        emitter.particles.retain(|particle| particle.age < lifetime);
+
+        let origin = match world.transforms.get(entity) {
+            Some(transform) if emitter.emitting => transform.world_position(),
+            _ => continue,
+        };
+        emitter.to_emit += emitter.rate * delta_time;
+        while emitter.to_emit >= 1.0 {
+            emitter.to_emit -= 1.0;
+            if emitter.particles.len() >= emitter.max_count {
+                continue;
+            }
+            let jitter = glm::vec3(rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0));
+            let offset = glm::vec3(rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0), rng.gen_range(-1.0..=1.0));
+            emitter.particles.push(Particle {
+                position : origin + emitter.area.component_mul(&offset),
+                velocity : emitter.velocity + jitter * emitter.spread,
+                age      : 0.0,
+            });
+        }
+    }
+}
+
+// Add the meshes, particles and lights of the world to a snapshot that has just been captured
+pub fn extract(world: &World, snapshot: &mut FrameSnapshot) {
+    for (entity, mesh_renderer) in world.mesh_renderers.iter() {
+        if !mesh_renderer.visible {
+            continue;
+        }
+        if let Some(transform) = world.transforms.get(entity) {
+            snapshot.draws.push(DrawItem {
+                node_id               : 0,
+                vao_id                : mesh_renderer.vao_id,
+                index_count           : mesh_renderer.index_count,
+                model_matrix          : transform.world_matrix,
+                previous_model_matrix : transform.world_matrix,
+                position              : scene_graph::position_from_f32(&transform.world_position()),
+                material              : mesh_renderer.material.clone(),
+            });
+        }
+    }
+
+    for (_, emitter) in world.particle_emitters.iter() {
+        for particle in &emitter.particles {
+            let size = emitter.size * (1.0 - particle.age / emitter.lifetime).max(0.0);
+            let model_matrix = glm::translation(&particle.position) * glm::scaling(&glm::vec3(size, size, size)) * glm::translation(&-emitter.mesh_center);
+            snapshot.draws.push(DrawItem {
+                node_id               : 0,
+                vao_id                : emitter.vao_id,
+                index_count           : emitter.index_count,
+                model_matrix,
+                previous_model_matrix : model_matrix,
+                position              : scene_graph::position_from_f32(&(model_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz()),
+                material              : emitter.material.clone(),
+            });
+        }
+    }
+
+    for (entity, light) in world.lights.iter() {
+        if let Some(transform) = world.transforms.get(entity) {
+            let direction = transform.world_matrix * glm::vec4(light.direction.x, light.direction.y, light.direction.z, 0.0);
+            snapshot.lights.push(WorldLight {
+                light     : *light,
+                position  : transform.world_position(),
+                direction : glm::normalize(&direction.xyz()),
+            });
+        }
+    }
+}
@@ -0,0 +1,103 @@
+extern crate nalgebra_glm as glm;
+
+// * Environment cube map
+// What the surroundings look like in every direction, used for image based lighting in the PBR
+// shader. Rough surfaces read from the blurry mip levels, shiny ones from the sharp ones.
+// There is no sky texture yet, so the cube map is generated: black sky above, a faint blue glow
+// at the horizon and grey moon dust below.
+
+pub struct EnvironmentMap {
+    pub cube_map_id : u32,
+    pub size        : i32, // Width and height of every face at mip level 0
+    pub mip_levels  : i32,
+}
+
+impl EnvironmentMap {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn night_sky(size: i32) -> EnvironmentMap {
+        let mut cube_map_id: u32 = 0;
+        gl::GenTextures(1, &mut cube_map_id);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map_id);
+
+        for face in 0..6 {
+            let mut pixels: Vec<u8> = Vec::with_capacity((size * size * 4) as usize);
+            for y in 0..size {
+                for x in 0..size {
+                    // Pixel center in -1..1 on the face
+                    let u = 2.0 * (x as f32 + 0.5) / size as f32 - 1.0;
+                    let v = 2.0 * (y as f32 + 0.5) / size as f32 - 1.0;
+                    let color = night_sky_color(&face_direction(face, u, v));
+                    pixels.extend_from_slice(&[
+                        (color.x.clamp(0.0, 1.0) * 255.0) as u8,
+                        (color.y.clamp(0.0, 1.0) * 255.0) as u8,
+                        (color.z.clamp(0.0, 1.0) * 255.0) as u8,
+                        255,
+                    ]);
+                }
+            }
+            gl::TexImage2D(
+                gl::TEXTURE_CUBE_MAP_POSITIVE_X + face,
+                0,
+                gl::SRGB8_ALPHA8 as i32, // Picked by eye, decoded to linear when sampled
+                size,
+                size,
+                0,
+                gl::RGBA,
+                gl::UNSIGNED_BYTE,
+                pixels.as_ptr() as *const std::ffi::c_void,
+            );
+        }
+
+        gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+        gl::Enable(gl::TEXTURE_CUBE_MAP_SEAMLESS); // Filter across face edges on the blurry levels
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+
+        let mip_levels = (size as f32).log2().floor() as i32 + 1;
+        EnvironmentMap { cube_map_id, size, mip_levels }
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn bind(&self, texture_unit: u32) {
+        gl::ActiveTexture(gl::TEXTURE0 + texture_unit);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.cube_map_id);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteTextures(1, &self.cube_map_id);
+    }
+}
+
+// Direction through a point (u, v) on a cube map face, following the OpenGL cube map layout
+fn face_direction(face: u32, u: f32, v: f32) -> glm::Vec3 {
+    let direction = match face {
+        0 => glm::vec3( 1.0,   -v,   -u), // +X
+        1 => glm::vec3(-1.0,   -v,    u), // -X
+        2 => glm::vec3(   u,  1.0,    v), // +Y
+        3 => glm::vec3(   u, -1.0,   -v), // -Y
+        4 => glm::vec3(   u,   -v,  1.0), // +Z
+        _ => glm::vec3(  -u,   -v, -1.0), // -Z
+    };
+    glm::normalize(&direction)
+}
+
+fn night_sky_color(direction: &glm::Vec3) -> glm::Vec3 {
+    let sky_zenith  = glm::vec3(0.0, 0.0, 0.01);
+    let sky_horizon = glm::vec3(0.07, 0.09, 0.16);
+    let ground      = glm::vec3(0.12, 0.12, 0.12);
+
+    if direction.y >= 0.0 {
+        glm::lerp(&sky_horizon, &sky_zenith, direction.y.sqrt())
+    } else {
+        glm::lerp(&sky_horizon, &ground, (-direction.y * 4.0).min(1.0))
+    }
+}
@@ -0,0 +1,132 @@
+use std::any::Any;
+
+use crate::assets::AssetManager;
+use crate::atlas::TextureAtlas;
+use crate::gfx;
+use crate::hud::Hud;
+use crate::strings::StringTable;
+use crate::text::{BitmapFont, PlacedGlyph, SpriteBatch, TextAlign, ADVANCE};
+use crate::viewport::Viewport;
+
+// * Error screen
+// What the window shows instead of the scene after a frame panicked. Every frame of the main loop runs
+// inside catch_unwind (see main.rs), so a bad shader, a broken model or a bug in a script's callback
+// doesn't take the whole program down with it: the panic message goes up on a plain screen, and the
+// render thread waits there until one of the watched files (models, textures, shaders, scripts) is
+// saved again, or R is pressed, and then tries the frame again. Escape quits.
+//
+// A frame that panicked can leave anything bound, so the screen unbinds what matters before it draws.
+// A panic before the main loop still ends the render thread, and the watchdog closes the window.
+
+const WRAP_COLUMNS : usize = 64; // Characters per line of the message
+const BACKGROUND   : [f32; 3] = [0.12, 0.02, 0.03];
+const TITLE_COLOR  : [f32; 4] = [1.0, 0.45, 0.35, 1.0];
+const TEXT_COLOR   : [f32; 4] = [0.9, 0.9, 0.92, 1.0];
+
+// The message of a panic, from what catch_unwind caught
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| String::from("(no message)"))
+}
+
+// Break the lines of `text` at spaces so none is longer than `columns`, words that are longer are cut
+fn wrap(text: &str, columns: usize) -> String {
+    let mut lines = vec![];
+    for paragraph in text.lines() {
+        let mut line = String::new();
+        for word in paragraph.split_whitespace() {
+            let mut word = word.to_string();
+            while word.chars().count() > columns {
+                if !line.is_empty() {
+                    lines.push(std::mem::take(&mut line));
+                }
+                let rest = word.split_off(word.char_indices().nth(columns).map_or(word.len(), |(index, _)| index));
+                lines.push(std::mem::replace(&mut word, rest));
+            }
+            if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > columns {
+                lines.push(std::mem::take(&mut line));
+            }
+            if !line.is_empty() {
+                line.push(' ');
+            }
+            line.push_str(&word);
+        }
+        lines.push(line);
+    }
+    lines.join("\n")
+}
+
+pub struct ErrorScreen {
+    title   : (Vec<PlacedGlyph>, (u32, u32)), // Laid out with the size in cells
+    message : Option<(Vec<PlacedGlyph>, (u32, u32))>,
+    font    : BitmapFont,
+    batch   : SpriteBatch,
+}
+
+impl ErrorScreen {
+    /// `atlas` has to have the sprites from `BitmapFont::add_sprites` in it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, atlas: &TextureAtlas) -> ErrorScreen {
+        ErrorScreen {
+            title: (vec![], (0, 0)),
+            message: None,
+            font: BitmapFont::new(atlas),
+            batch: SpriteBatch::new(assets, atlas),
+        }
+    }
+
+    pub fn is_shown(&self) -> bool {
+        self.message.is_some()
+    }
+
+    // Show the screen with the message of the panic
+    pub fn show(&mut self, message: &str, strings: &StringTable) {
+        let title = strings.get("error.title");
+        self.title = (self.font.layout(title, TextAlign::Center), BitmapFont::measure(title));
+        let text = format!("{}\n\n{}", wrap(message, WRAP_COLUMNS), wrap(strings.get("error.hint"), WRAP_COLUMNS));
+        self.message = Some((self.font.layout(&text, TextAlign::Center), BitmapFont::measure(&text)));
+    }
+
+    pub fn clear(&mut self) {
+        self.message = None;
+    }
+
+    /// Draw the screen over the whole window, as big as it fits.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&mut self, assets: &AssetManager, window_width: u32, window_height: u32) {
+        let (glyphs, (width, height)) = match &self.message {
+            Some(message) => message,
+            None => return,
+        };
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::BindVertexArray(0);
+        gl::UseProgram(0);
+        gfx::RenderState::default().apply();
+        gfx::set_viewport(0, 0, window_width, window_height);
+        gfx::clear(BACKGROUND);
+
+        // The HUD's cell size, smaller if the message would be wider than the window
+        let fits = (window_width as f32 / (WRAP_COLUMNS as u32 * ADVANCE) as f32).floor().max(1.0);
+        let cell = Hud::cell_size(window_height).min(fits);
+        let (title_glyphs, (title_width, title_height)) = &self.title;
+        let title_gap = 2.0 * *title_height as f32 * cell;
+        let total_height = (*title_height + *height) as f32 * cell + title_gap;
+        let top = ((window_height as f32 + total_height) / 2.0).round();
+        let centered = |text_width: u32| ((window_width as f32 - text_width as f32 * cell) / 2.0).round();
+        self.batch.add_text(title_glyphs, centered(*title_width), top, cell, TITLE_COLOR);
+        self.batch.add_text(glyphs, centered(*width), top - *title_height as f32 * cell - title_gap, cell, TEXT_COLOR);
+        let window = Viewport { x: 0, y: 0, width: window_width as i32, height: window_height as i32 };
+        self.batch.draw(assets, &window);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.batch.delete();
+    }
+}
@@ -0,0 +1,223 @@
+use std::sync::mpsc::Receiver;
+
+use glutin::event::{ElementState, MouseButton, VirtualKeyCode};
+
+// * Events from the window to the render thread
+// The event loop owns the window and the render thread owns the GL context, so everything the
+// renderer needs to know about input goes through one channel as AppEvents. The render thread
+// drains the channel once per frame and keeps the resulting input state in an InputState.
+
+#[derive(Clone, Copy, Debug)]
+pub enum AppEvent {
+    Resize(u32, u32),
+    Key(VirtualKeyCode, ElementState),
+    MouseDelta(f32, f32),
+    CursorMoved(f32, f32),
+    MouseButton(MouseButton, ElementState),
+    ToggleFullscreen,
+    Focused(bool),
+    CloseRequested,
+}
+
+pub struct InputState {
+    pub pressed_keys           : Vec<VirtualKeyCode>,
+    pub just_pressed_keys      : Vec<VirtualKeyCode>, // Went down since the last end_frame(), for toggles
+    pub mouse_delta            : (f32, f32), // Mouse movement while looking around, since last reset
+    pub right_button_pressed   : bool,
+    pub left_button_pressed    : bool,
+    pub mouse_captured         : bool,       // Looking around with the mouse without holding the right button
+    pub cursor_position        : (f32, f32), // In window pixels, y going down
+    pub just_clicked_buttons   : Vec<MouseButton>, // Went down since the last end_frame()
+    pub window_size            : (u32, u32),
+    pub resized                : bool,       // Window size changed since the last time this was cleared
+    pub toggle_fullscreen      : bool,       // Fullscreen toggle requested since the last time this was cleared
+    pub focused                : bool,       // The window has the keyboard focus
+    pub focus_changed          : bool,       // Gained or lost it since the last time this was cleared
+    pub close_requested        : bool,
+}
+
+impl InputState {
+    pub fn new(window_width: u32, window_height: u32) -> InputState {
+        InputState {
+            pressed_keys: Vec::with_capacity(10),
+            just_pressed_keys: Vec::with_capacity(10),
+            mouse_delta: (0.0, 0.0),
+            right_button_pressed: false,
+            left_button_pressed: false,
+            mouse_captured: false,
+            cursor_position: (0.0, 0.0),
+            just_clicked_buttons: Vec::with_capacity(3),
+            window_size: (window_width, window_height),
+            resized: false,
+            toggle_fullscreen: false,
+            focused: true,
+            focus_changed: false,
+            close_requested: false,
+        }
+    }
+
+    pub fn apply(&mut self, event: AppEvent) {
+        match event {
+            AppEvent::Resize(width, height) => {
+                self.window_size = (width, height);
+                self.resized = true;
+            }
+            AppEvent::Key(keycode, ElementState::Pressed) => {
+                // Key repeat sends more Pressed events, only the first one counts as "just pressed"
+                if !self.pressed_keys.contains(&keycode) {
+                    self.pressed_keys.push(keycode);
+                    self.just_pressed_keys.push(keycode);
+                }
+            }
+            AppEvent::Key(keycode, ElementState::Released) => {
+                self.pressed_keys.retain(|&k| k != keycode);
+            }
+            AppEvent::MouseDelta(dx, dy) => {
+                // Only accumulate movement when right mouse button is pressed, or the mouse is captured
+                if self.right_button_pressed || self.mouse_captured {
+                    self.mouse_delta.0 += dx;
+                    self.mouse_delta.1 += dy;
+                }
+            }
+            AppEvent::CursorMoved(x, y) => {
+                self.cursor_position = (x, y);
+            }
+            AppEvent::MouseButton(button, state) => {
+                match button {
+                    MouseButton::Right => self.right_button_pressed = state == ElementState::Pressed,
+                    MouseButton::Left => self.left_button_pressed = state == ElementState::Pressed,
+                    _ => {}
+                }
+                if state == ElementState::Pressed {
+                    self.just_clicked_buttons.push(button);
+                }
+            }
+            AppEvent::ToggleFullscreen => {
+                self.toggle_fullscreen = true;
+            }
+            AppEvent::Focused(focused) => {
+                // The releases of whatever was held when the focus went elsewhere never arrive
+                if !focused {
+                    self.pressed_keys.clear();
+                    self.right_button_pressed = false;
+                    self.left_button_pressed = false;
+                    self.mouse_delta = (0.0, 0.0);
+                }
+                self.focused = focused;
+                self.focus_changed = true;
+            }
+            AppEvent::CloseRequested => {
+                self.close_requested = true;
+            }
+        }
+    }
+
+    // Apply everything that arrived since last frame, never blocks
+    pub fn drain(&mut self, receiver: &Receiver<AppEvent>) {
+        for event in receiver.try_iter() {
+            self.apply(event);
+        }
+    }
+
+    pub fn is_pressed(&self, keycode: VirtualKeyCode) -> bool {
+        self.pressed_keys.contains(&keycode)
+    }
+
+    pub fn was_just_pressed(&self, keycode: VirtualKeyCode) -> bool {
+        self.just_pressed_keys.contains(&keycode)
+    }
+
+    pub fn was_just_clicked(&self, button: MouseButton) -> bool {
+        self.just_clicked_buttons.contains(&button)
+    }
+
+    // Drop the presses, clicks and mouse movement of this frame, once something (the pause menu) has
+    // taken them so nothing else reacts to them as well
+    pub fn consume_frame_input(&mut self) {
+        self.just_pressed_keys.clear();
+        self.just_clicked_buttons.clear();
+        self.mouse_delta = (0.0, 0.0);
+    }
+
+    // Call at the end of every frame
+    pub fn end_frame(&mut self) {
+        self.just_pressed_keys.clear();
+        self.just_clicked_buttons.clear();
+    }
+}
+
+// * Actions
+// Gameplay input goes through named actions instead of raw keys, so the key can be changed in one place
+// and whatever reacts to it (a subscriber on the event bus) doesn't need to know which key it was.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    ToggleDoor,   // Open or close the door of the selected helicopter
+    ToggleEngine, // Land the selected helicopter, or take it off again
+}
+
+impl Action {
+    pub const ALL: [Action; 2] = [Action::ToggleDoor, Action::ToggleEngine];
+
+    // The name it has in the settings file, "ToggleDoor"
+    pub fn from_name(name: &str) -> Option<Action> {
+        Action::ALL.iter().copied().find(|action| format!("{:?}", action) == name)
+    }
+}
+
+// Keys an action can be bound to from the settings file (see user_settings.rs), by the names glutin
+// gives them: "E", "F1", "Key4", "Space"
+const BINDABLE_KEYS: &[VirtualKeyCode] = &[
+    VirtualKeyCode::A, VirtualKeyCode::B, VirtualKeyCode::C, VirtualKeyCode::D, VirtualKeyCode::E,
+    VirtualKeyCode::F, VirtualKeyCode::G, VirtualKeyCode::H, VirtualKeyCode::I, VirtualKeyCode::J,
+    VirtualKeyCode::K, VirtualKeyCode::L, VirtualKeyCode::M, VirtualKeyCode::N, VirtualKeyCode::O,
+    VirtualKeyCode::P, VirtualKeyCode::Q, VirtualKeyCode::R, VirtualKeyCode::S, VirtualKeyCode::T,
+    VirtualKeyCode::U, VirtualKeyCode::V, VirtualKeyCode::W, VirtualKeyCode::X, VirtualKeyCode::Y,
+    VirtualKeyCode::Z,
+    VirtualKeyCode::Key0, VirtualKeyCode::Key1, VirtualKeyCode::Key2, VirtualKeyCode::Key3, VirtualKeyCode::Key4,
+    VirtualKeyCode::Key5, VirtualKeyCode::Key6, VirtualKeyCode::Key7, VirtualKeyCode::Key8, VirtualKeyCode::Key9,
+    VirtualKeyCode::F1, VirtualKeyCode::F2, VirtualKeyCode::F3, VirtualKeyCode::F4, VirtualKeyCode::F5,
+    VirtualKeyCode::F6, VirtualKeyCode::F7, VirtualKeyCode::F8, VirtualKeyCode::F9, VirtualKeyCode::F10,
+    VirtualKeyCode::F12,
+    VirtualKeyCode::Space, VirtualKeyCode::Tab, VirtualKeyCode::Return, VirtualKeyCode::Back,
+    VirtualKeyCode::Insert, VirtualKeyCode::Delete, VirtualKeyCode::Home, VirtualKeyCode::End,
+    VirtualKeyCode::PageUp, VirtualKeyCode::PageDown,
+    VirtualKeyCode::Left, VirtualKeyCode::Right, VirtualKeyCode::Up, VirtualKeyCode::Down,
+];
+
+pub fn key_from_name(name: &str) -> Option<VirtualKeyCode> {
+    BINDABLE_KEYS.iter().copied().find(|key| format!("{:?}", key) == name)
+}
+
+pub struct ActionMap {
+    bindings : Vec<(VirtualKeyCode, Action)>,
+}
+
+impl ActionMap {
+    pub fn new() -> ActionMap {
+        ActionMap { bindings: vec![] }
+    }
+
+    // Bind a key to an action, a key can only do one thing so an earlier binding of it is replaced
+    pub fn bind(&mut self, key: VirtualKeyCode, action: Action) {
+        self.bindings.retain(|&(bound_key, _)| bound_key != key);
+        self.bindings.push((key, action));
+    }
+
+    // Bind a key to an action instead of the keys it had
+    pub fn rebind(&mut self, action: Action, key: VirtualKeyCode) {
+        self.bindings.retain(|&(_, bound_action)| bound_action != action);
+        self.bind(key, action);
+    }
+
+    pub fn key_for(&self, action: Action) -> Option<VirtualKeyCode> {
+        self.bindings.iter().find(|&&(_, bound_action)| bound_action == action).map(|&(key, _)| key)
+    }
+
+    // The actions whose key went down this frame
+    pub fn just_triggered(&self, input: &InputState) -> Vec<Action> {
+        self.bindings.iter()
+            .filter(|&&(key, _)| input.was_just_pressed(key))
+            .map(|&(_, action)| action)
+            .collect()
+    }
+}
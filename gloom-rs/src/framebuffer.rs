@@ -0,0 +1,131 @@
+use crate::gfx::{self, ObjectKind};
+
+// * Framebuffer
+// Something to render into that isn't the window: a color texture plus a depth/stencil texture.
+// Both can be sampled like any other texture afterwards (the depth part, for the depth/stencil one).
+
+pub struct Framebuffer {
+    pub fbo_id          : u32,
+    pub color_texture   : u32,
+    pub depth_texture   : u32,
+    pub width           : i32,
+    pub height          : i32,
+    pub internal_format : gl::types::GLenum,
+    pub data_type       : gl::types::GLenum,
+    pub label           : &'static str, // For frame captures, kept over a resize
+}
+
+impl Framebuffer {
+    /// Creates a framebuffer with an RGBA8 color texture and a 24 bit depth + 8 bit stencil texture.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(width: i32, height: i32) -> Framebuffer {
+        Framebuffer::with_color_format(width, height, gl::RGBA8, gl::UNSIGNED_BYTE)
+    }
+
+    /// Same as `new`, but with any sized internal format for the color texture (e.g. RGBA16F for HDR).
+    /// R32UI gives an integer texture, which is read back as is and never filtered.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn with_color_format(width: i32, height: i32, internal_format: gl::types::GLenum, data_type: gl::types::GLenum) -> Framebuffer {
+        let mut fbo_id: u32 = 0;
+        gl::GenFramebuffers(1, &mut fbo_id);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo_id);
+
+        // Color goes into a texture so we can sample it later
+        // The format has to match the channels of the internal format, ES doesn't let that slide
+        let (format, filter) = match internal_format {
+            gl::R32UI                       => (gl::RED_INTEGER, gl::NEAREST),
+            gl::R8 | gl::R16F | gl::R32F    => (gl::RED, gl::LINEAR),
+            gl::RG8 | gl::RG16F | gl::RG32F => (gl::RG, gl::LINEAR),
+            _                               => (gl::RGBA, gl::LINEAR),
+        };
+        let mut color_texture: u32 = 0;
+        gl::GenTextures(1, &mut color_texture);
+        gl::BindTexture(gl::TEXTURE_2D, color_texture);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, internal_format as i32, width, height, 0, format, data_type, std::ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, filter as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_2D, color_texture, 0);
+
+        // Depth goes into a texture too, effects like the lens flare occlusion test read it.
+        // The stencil goes along with it, the selection outline needs one. Float depth, which is what
+        // makes reversed depth worth it (see gfx::DepthMode).
+        let mut depth_texture: u32 = 0;
+        gl::GenTextures(1, &mut depth_texture);
+        gl::BindTexture(gl::TEXTURE_2D, depth_texture);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::DEPTH32F_STENCIL8 as i32, width, height, 0, gl::DEPTH_STENCIL, gl::FLOAT_32_UNSIGNED_INT_24_8_REV, std::ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_STENCIL_ATTACHMENT, gl::TEXTURE_2D, depth_texture, 0);
+
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Framebuffer {}x{} is not complete!", width, height);
+        }
+
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        Framebuffer { fbo_id, color_texture, depth_texture, width, height, internal_format, data_type, label: "" }
+    }
+
+    /// Name the framebuffer and its textures for frame captures, "hdr target" gives "hdr target color"
+    /// and "hdr target depth" for the textures.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn with_label(mut self, label: &'static str) -> Framebuffer {
+        self.label = label;
+        if label.is_empty() {
+            return self;
+        }
+        gfx::label_object(ObjectKind::Framebuffer, self.fbo_id, label);
+        gfx::label_object(ObjectKind::Texture, self.color_texture, &format!("{} color", label));
+        gfx::label_object(ObjectKind::Texture, self.depth_texture, &format!("{} depth", label));
+        self
+    }
+
+    /// Recreate the attachments at a new size, if it changed. The old contents are lost.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn resize(&mut self, width: i32, height: i32) {
+        if width == self.width && height == self.height {
+            return;
+        }
+        self.delete();
+        *self = Framebuffer::with_color_format(width.max(1), height.max(1), self.internal_format, self.data_type).with_label(self.label);
+    }
+
+    /// Draw into this framebuffer from now on, covering all of it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn bind(&self) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo_id);
+        gl::Viewport(0, 0, self.width, self.height);
+    }
+
+    /// Go back to drawing into the window.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn unbind(&self, window_width: u32, window_height: u32) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(0, 0, window_width as i32, window_height as i32);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteFramebuffers(1, &self.fbo_id);
+        gl::DeleteTextures(1, &self.color_texture);
+        gl::DeleteTextures(1, &self.depth_texture);
+    }
+}
@@ -0,0 +1,116 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::camera::Camera;
+use crate::gfx;
+use crate::math;
+use crate::scene_graph;
+use crate::util;
+
+// * Frustum debug view
+// Culling bugs are hard to see from the camera that does the culling: whatever it throws away is off
+// screen anyway. So the fly-cam's view can be watched from an observer camera instead, which stays
+// where it was put while the fly-cam keeps flying. The scene is still culled against the fly-cam (see
+// FrameSnapshot::draw_visible_from), so nodes popping in and out at the edges of its frustum show up,
+// and the frustum itself is drawn as a wireframe box around what the fly-cam sees.
+//
+// The far plane is 10000 units away, far past the fog, so only the first `shown_distance` of the
+// frustum is drawn. Shadow maps will split the frustum into cascades by distance: put the distances in
+// `cascade_splits` and every cascade gets a box of its own, so the splits can be lined up with the
+// shadow artifacts. `practical_splits` gives the usual choice of distances.
+
+// Colors of the cascades, repeating if there are more
+const CASCADE_COLORS: [[f32; 3]; 4] = [[1.0, 0.3, 0.3], [0.3, 1.0, 0.3], [0.3, 0.5, 1.0], [1.0, 1.0, 0.3]];
+const FRUSTUM_COLOR: [f32; 3] = [1.0, 1.0, 1.0];
+
+pub struct FrustumDebug {
+    pub observer       : Option<Camera>, // Where the fly-cam's view is seen from, None when not observing
+    pub shown_distance : f32,            // How much of the frustum is drawn
+    pub cascade_splits : Vec<f32>,       // Far end of every cascade, in increasing order
+    vao_id             : u32,
+    shader             : ShaderHandle,
+}
+
+impl FrustumDebug {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager) -> FrustumDebug {
+        // The corners of the cube normalized device coordinates go from -1 to 1 in, and its 12 edges
+        let vertices: Vec<f32> = (0..8)
+            .flat_map(|corner| [corner & 1, (corner >> 1) & 1, (corner >> 2) & 1].map(|bit| bit as f32 * 2.0 - 1.0))
+            .collect();
+        let colors = vec![1.0; 8 * 4];
+        let normals = vec![0.0; vertices.len()];
+        let indices = vec![
+            0, 1, 2, 3, 4, 5, 6, 7, // Along X
+            0, 2, 1, 3, 4, 6, 5, 7, // Along Y
+            0, 4, 1, 5, 2, 6, 3, 7, // Along Z
+        ];
+        let (vao_id, _) = util::create_vao(&vertices, &indices, &colors, &normals);
+
+        FrustumDebug {
+            observer: None,
+            shown_distance: 800.0,
+            cascade_splits: vec![],
+            vao_id,
+            shader: assets.load_shader(&["shaders/frustum_debug.vert", "shaders/frustum_debug.frag"]),
+        }
+    }
+
+    // Start watching `camera` from a bit behind and above it, or go back to looking through it
+    pub fn toggle_observer(&mut self, camera: &Camera) {
+        self.observer = match self.observer {
+            Some(_) => None,
+            None => {
+                let mut observer = Camera::new(camera.position - scene_graph::position_from_f32(&(camera.forward() * 60.0 - glm::vec3(0.0, 25.0, 0.0))));
+                observer.look_at(camera.position_f32() + camera.forward() * 20.0);
+                Some(observer)
+            }
+        };
+    }
+
+    /// Draw the frustum of `camera` (with views `aspect_ratio` wide, from `near_plane` on) and its
+    /// cascades, as seen with `view_projection_matrix`. Leaves the shader of the frustum active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, view_projection_matrix: &glm::Mat4, camera: &Camera, aspect_ratio: f32, near_plane: f32) {
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        let view = glm::look_at(&camera.position_f32(), &(camera.position_f32() + camera.forward()), &camera.up());
+
+        // The inverse of the part of the projection between two distances takes the cube onto it
+        let draw_slice = |near: f32, far: f32, color: &[f32; 3]| {
+            let slice_view_projection = math::camera_perspective_between(aspect_ratio, near, far) * view;
+            shader.set_uniform_mat4("mvp_matrix", &(view_projection_matrix * glm::inverse(&slice_view_projection)));
+            shader.set_uniform_vec3("line_color", color);
+            gfx::DrawCall { primitive: gfx::Primitive::Lines, ..gfx::DrawCall::indexed(self.vao_id, 24) }.submit();
+        };
+
+        let mut near = near_plane;
+        for (&split, color) in self.cascade_splits.iter().zip(CASCADE_COLORS.iter().cycle()) {
+            draw_slice(near, split, color);
+            near = split;
+        }
+        draw_slice(near_plane, self.shown_distance, &FRUSTUM_COLOR);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        util::delete_vao(self.vao_id);
+    }
+}
+
+// Distances for `count` cascades between `near` and `far`: `lambda` 0 spaces them evenly, 1 so every
+// cascade reaches the same number of times further than the one before, in between mixes the two.
+pub fn practical_splits(near: f32, far: f32, count: usize, lambda: f32) -> Vec<f32> {
+    (1..=count)
+        .map(|i| {
+            let t = i as f32 / count as f32;
+            let logarithmic = near * (far / near).powf(t);
+            let uniform = near + (far - near) * t;
+            lambda * logarithmic + (1.0 - lambda) * uniform
+        })
+        .collect()
+}
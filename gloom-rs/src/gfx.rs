@@ -0,0 +1,581 @@
+// * Graphics backend
+// A thin layer the scene renderer talks to instead of calling gl:: directly.
+// Only OpenGL sits behind it for now, but nothing in these types is GL specific
+// (no GLenums in the public fields), so a wgpu/Vulkan backend can implement the same thing later.
+// Effects with their own special GL setup (terrain, instancing, post) still call gl:: themselves.
+
+use std::cell::{Cell, RefCell};
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// * Buffers
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BufferKind {
+    Vertex,
+    Index,
+    Uniform,
+    Storage,
+}
+
+impl BufferKind {
+    fn target(self) -> gl::types::GLenum {
+        match self {
+            BufferKind::Vertex  => gl::ARRAY_BUFFER,
+            BufferKind::Index   => gl::ELEMENT_ARRAY_BUFFER,
+            BufferKind::Uniform => gl::UNIFORM_BUFFER,
+            BufferKind::Storage => gl::SHADER_STORAGE_BUFFER,
+        }
+    }
+}
+
+pub struct Buffer {
+    pub id   : u32,
+    pub kind : BufferKind,
+    pub size : usize,
+}
+
+impl Buffer {
+    /// Creates a buffer filled with `data`. It is left bound, so a vertex buffer
+    /// goes into the currently bound VAO (and an index buffer becomes its index buffer).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new<T: Copy>(kind: BufferKind, data: &[T]) -> Buffer {
+        let mut id: u32 = 0;
+        gl::GenBuffers(1, &mut id);
+        let mut buffer = Buffer { id, kind, size: 0 };
+        buffer.update(data);
+        buffer
+    }
+
+    /// Replaces the whole contents, the buffer grows or shrinks to fit. Leaves it bound.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn update<T: Copy>(&mut self, data: &[T]) {
+        if self.size != std::mem::size_of_val(data) && self.size != 0 {
+            note_event(format!("buffer {} reallocated from {} to {} bytes", self.id, self.size, std::mem::size_of_val(data)));
+        }
+        self.size = std::mem::size_of_val(data);
+        self.bind();
+        gl::BufferData(self.kind.target(), self.size as isize, data.as_ptr() as *const c_void, gl::STATIC_DRAW);
+    }
+
+    /// Replaces the elements from `first` on with `data`, which has to fit in the buffer. Leaves it bound.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn update_range<T: Copy>(&self, first: usize, data: &[T]) {
+        let offset = first * std::mem::size_of::<T>();
+        assert!(offset + std::mem::size_of_val(data) <= self.size, "Buffer update past the end of the buffer");
+        self.bind();
+        gl::BufferSubData(self.kind.target(), offset as isize, std::mem::size_of_val(data) as isize, data.as_ptr() as *const c_void);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn bind(&self) {
+        gl::BindBuffer(self.kind.target(), self.id);
+    }
+
+    /// Feed vertex attribute `location` of the bound VAO from this buffer,
+    /// as tightly packed floats with `components` per vertex.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context and a bound VAO.
+    pub unsafe fn vertex_attribute(&self, location: u32, components: i32) {
+        self.bind();
+        gl::VertexAttribPointer(location, components, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
+        gl::EnableVertexAttribArray(location);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteBuffers(1, &self.id);
+    }
+
+    /// Name it for frame captures (see `label_object`).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn label(&self, name: &str) {
+        label_object(ObjectKind::Buffer, self.id, name);
+    }
+}
+
+// * Textures
+// What the values in a texture are. Colors are sRGB and get decoded to linear by the GPU when sampled,
+// data (normals, roughness, masks) is used as it is. See color.rs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    Linear,
+    Srgb,
+}
+
+pub struct Texture {
+    pub id          : u32,
+    pub width       : u32,
+    pub height      : u32,
+    pub color_space : ColorSpace,
+}
+
+impl Texture {
+    /// Creates a repeating, mipmapped RGBA8 texture from tightly packed pixels (first row at the bottom).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn from_rgba8(width: u32, height: u32, pixels: &[u8], color_space: ColorSpace) -> Texture {
+        let mut id: u32 = 0;
+        gl::GenTextures(1, &mut id);
+        let mut texture = Texture { id, width, height, color_space };
+        texture.upload_rgba8(width, height, pixels);
+
+        gl::BindTexture(gl::TEXTURE_2D, id);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        texture
+    }
+
+    /// Replaces the pixels (the size may change) and rebuilds the mipmaps.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn upload_rgba8(&mut self, width: u32, height: u32, pixels: &[u8]) {
+        let internal_format = match self.color_space {
+            ColorSpace::Linear => gl::RGBA8,
+            ColorSpace::Srgb   => gl::SRGB8_ALPHA8, // Alpha stays linear
+        };
+        gl::BindTexture(gl::TEXTURE_2D, self.id);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            internal_format as i32,
+            width as i32,
+            height as i32,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            pixels.as_ptr() as *const c_void,
+        );
+        gl::GenerateMipmap(gl::TEXTURE_2D);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        note_event(format!("texture {} uploaded, {}x{}", self.id, width, height));
+        self.width = width;
+        self.height = height;
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteTextures(1, &self.id);
+    }
+
+    /// Name it for frame captures (see `label_object`).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn label(&self, name: &str) {
+        label_object(ObjectKind::Texture, self.id, name);
+    }
+}
+
+/// Binds a 2D texture (or nothing, with `None`) to a texture unit. Unit 0 is active afterwards.
+///
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn bind_texture(unit: u32, texture_id: Option<u32>) {
+    gl::ActiveTexture(gl::TEXTURE0 + unit);
+    gl::BindTexture(gl::TEXTURE_2D, texture_id.unwrap_or(0));
+    gl::ActiveTexture(gl::TEXTURE0);
+}
+
+// * Render state
+// The fixed function part of drawing, shaders still go through shader.rs. Every pass declares the
+// whole state it draws with and applies it, instead of switching single things off and back on and
+// leaking whatever it forgot to switch back. The state applied last is remembered, and applying
+// only makes the GL calls for what is different from it, so it is cheap enough to do for every draw.
+//
+// For that to hold nothing else may change this state with gl:: directly. Whatever has to (another
+// library drawing into the same context) calls forget_render_state after.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthTest {
+    Off,
+    Less,
+    LessEqual,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Blend {
+    Off,
+    Alpha,
+    Additive,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CullMode {
+    Off,
+    Back,
+    Front,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PolygonMode {
+    Fill,
+    Line, // Wireframe
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RenderState {
+    pub depth_test  : DepthTest,
+    pub depth_write : bool,
+    pub cull        : CullMode,
+    pub blend       : Blend,
+    pub polygon     : PolygonMode,
+    pub scissor     : Option<(i32, i32, i32, i32)>, // (x, y, width, height) in pixels, None draws everywhere
+}
+
+impl Default for RenderState {
+    // What the scene is drawn with
+    fn default() -> RenderState {
+        RenderState {
+            depth_test  : DepthTest::Less,
+            depth_write : true,
+            cull        : CullMode::Back,
+            blend       : Blend::Alpha,
+            polygon     : PolygonMode::Fill,
+            scissor     : None,
+        }
+    }
+}
+
+impl RenderState {
+    // For fullscreen passes: no depth, no culling, no blending
+    pub fn fullscreen() -> RenderState {
+        RenderState { depth_test: DepthTest::Off, depth_write: false, cull: CullMode::Off, blend: Blend::Off, ..Default::default() }
+    }
+
+    /// Makes this the state, with GL calls for what isn't already set that way.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn apply(&self) {
+        let applied = APPLIED_STATE.with(|applied| applied.replace(Some(*self)));
+        let changed = |unchanged: fn(&RenderState, &RenderState) -> bool| applied.is_none_or(|applied| !unchanged(&applied, self));
+
+        if changed(|a, b| a.depth_test == b.depth_test) {
+            apply_depth_test(self.depth_test);
+        }
+        if changed(|a, b| a.depth_write == b.depth_write) {
+            gl::DepthMask(if self.depth_write { gl::TRUE } else { gl::FALSE });
+        }
+        if changed(|a, b| a.cull == b.cull) {
+            match self.cull {
+                CullMode::Off   => gl::Disable(gl::CULL_FACE),
+                CullMode::Back  => { gl::Enable(gl::CULL_FACE); gl::CullFace(gl::BACK); },
+                CullMode::Front => { gl::Enable(gl::CULL_FACE); gl::CullFace(gl::FRONT); },
+            }
+        }
+        if changed(|a, b| a.blend == b.blend) {
+            match self.blend {
+                Blend::Off      => gl::Disable(gl::BLEND),
+                Blend::Alpha    => { gl::Enable(gl::BLEND); gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA); },
+                Blend::Additive => { gl::Enable(gl::BLEND); gl::BlendFunc(gl::SRC_ALPHA, gl::ONE); },
+            }
+        }
+        if changed(|a, b| a.polygon == b.polygon) {
+            // ES 3.0 has no glPolygonMode, only fill
+            if gl::PolygonMode::is_loaded() {
+                gl::PolygonMode(gl::FRONT_AND_BACK, match self.polygon { PolygonMode::Fill => gl::FILL, PolygonMode::Line => gl::LINE });
+            }
+        }
+        if changed(|a, b| a.scissor == b.scissor) {
+            match self.scissor {
+                None => gl::Disable(gl::SCISSOR_TEST),
+                Some((x, y, width, height)) => { gl::Enable(gl::SCISSOR_TEST); gl::Scissor(x, y, width, height); },
+            }
+        }
+    }
+}
+
+thread_local! {
+    // The GL context is only ever current on the main thread
+    static APPLIED_STATE: Cell<Option<RenderState>> = const { Cell::new(None) };
+}
+
+// The state applied last, the default one if none was yet
+pub fn applied_render_state() -> RenderState {
+    APPLIED_STATE.with(|applied| applied.get()).unwrap_or_default()
+}
+
+// Make the next RenderState::apply set everything, after the state was changed behind its back
+pub fn forget_render_state() {
+    APPLIED_STATE.with(|applied| applied.set(None));
+}
+
+/// Only the scissor rectangle, the rest of the state stays as it is.
+///
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn set_scissor(scissor: Option<(i32, i32, i32, i32)>) {
+    RenderState { scissor, ..applied_render_state() }.apply();
+}
+
+unsafe fn apply_depth_test(depth_test: DepthTest) {
+    // "Less" is closer to the camera, which is the larger depth with reversed depth
+    let reversed = depth_mode() == DepthMode::Reversed;
+    match depth_test {
+        DepthTest::Off       => gl::Disable(gl::DEPTH_TEST),
+        DepthTest::Less      => { gl::Enable(gl::DEPTH_TEST); gl::DepthFunc(if reversed { gl::GREATER } else { gl::LESS }); },
+        DepthTest::LessEqual => { gl::Enable(gl::DEPTH_TEST); gl::DepthFunc(if reversed { gl::GEQUAL } else { gl::LEQUAL }); },
+    }
+}
+
+// * Depth convention
+// Standard is OpenGL's own: clip space z from -1 to 1, and depth from 0 at the near plane to 1 at
+// the far one. Reversed has clip space z from 0 to 1 (glClipControl) and depth from 1 at the near
+// plane to 0 at the far one. A float depth buffer has most of its precision close to 0, which that
+// puts far away, where the perspective divide leaves the least, so distant surfaces stop z-fighting.
+// The projection has to be made for it (see CameraProjection in settings.rs).
+//
+// Only the camera views are drawn reversed, the shadow maps and the probes keep the standard one.
+// The depth tests of the render state and the depth clears follow whichever is set. The shaders
+// that read a depth texture get a `reversed_z` uniform to tell them which one it was written with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DepthMode {
+    Standard,
+    Reversed,
+}
+
+static REVERSED_DEPTH: AtomicBool = AtomicBool::new(false);
+
+pub fn depth_mode() -> DepthMode {
+    if REVERSED_DEPTH.load(Ordering::Relaxed) { DepthMode::Reversed } else { DepthMode::Standard }
+}
+
+/// Switch the depth convention for what is drawn next. The depth test that is applied is set again
+/// for it.
+/// Reversed needs glClipControl (GlCaps::clip_control).
+///
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn set_depth_mode(mode: DepthMode) {
+    if mode == depth_mode() {
+        return;
+    }
+    let reversed = mode == DepthMode::Reversed;
+    REVERSED_DEPTH.store(reversed, Ordering::Relaxed);
+    gl::ClipControl(gl::LOWER_LEFT, if reversed { gl::ZERO_TO_ONE } else { gl::NEGATIVE_ONE_TO_ONE });
+    gl::ClearDepth(if reversed { 0.0 } else { 1.0 });
+    apply_depth_test(applied_render_state().depth_test);
+}
+
+// * Draw calls
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Primitive {
+    Triangles,
+    Lines,
+}
+
+impl Primitive {
+    fn mode(self) -> gl::types::GLenum {
+        match self {
+            Primitive::Triangles => gl::TRIANGLES,
+            Primitive::Lines     => gl::LINES,
+        }
+    }
+}
+
+// One indexed draw of a VAO (32 bit indices) with whatever shader and render state are active
+#[derive(Clone, Copy, Debug)]
+pub struct DrawCall {
+    pub vao_id      : u32,
+    pub index_count : i32,
+    pub primitive   : Primitive,
+    pub instances   : i32,
+}
+
+impl DrawCall {
+    pub fn indexed(vao_id: u32, index_count: i32) -> DrawCall {
+        DrawCall { vao_id, index_count, primitive: Primitive::Triangles, instances: 1 }
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn submit(&self) {
+        count_draw_call();
+        gl::BindVertexArray(self.vao_id);
+        if self.instances == 1 {
+            gl::DrawElements(self.primitive.mode(), self.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        } else {
+            gl::DrawElementsInstanced(self.primitive.mode(), self.index_count, gl::UNSIGNED_INT, std::ptr::null(), self.instances);
+        }
+    }
+}
+
+// * Frame statistics
+// Draw calls made so far, for the benchmark and the spike capture (see benchmark.rs and spikes.rs),
+// which take the difference over whatever they measure. DrawCall counts itself, the passes that call
+// gl::Draw* directly count theirs with `count_draw_call`.
+//
+// And the slow things that happened, like a shader compiling or a buffer growing. They are kept
+// until the spike capture takes them at the end of the frame, so a hitch can be put down to them.
+thread_local! {
+    static DRAW_CALLS: Cell<u64> = const { Cell::new(0) };
+    static EVENTS: RefCell<Vec<String>> = const { RefCell::new(vec![]) };
+}
+
+// Events kept per frame, something going wrong every draw would otherwise fill the memory
+const MAX_EVENTS: usize = 64;
+
+pub fn count_draw_call() {
+    DRAW_CALLS.with(|count| count.set(count.get() + 1));
+}
+
+pub fn draw_call_count() -> u64 {
+    DRAW_CALLS.with(|count| count.get())
+}
+
+pub fn note_event(event: String) {
+    EVENTS.with(|events| {
+        let mut events = events.borrow_mut();
+        if events.len() < MAX_EVENTS {
+            events.push(event);
+        }
+    });
+}
+
+// The events since the last call
+pub fn take_events() -> Vec<String> {
+    EVENTS.with(|events| std::mem::take(&mut *events.borrow_mut()))
+}
+
+// * Frame
+/// Clears color, depth and stencil of whatever is being drawn into (within the scissor, if it is on).
+///
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn clear(color: [f32; 3]) {
+    gl::ClearColor(color[0], color[1], color[2], 1.0);
+    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT | gl::STENCIL_BUFFER_BIT);
+}
+
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn set_viewport(x: i32, y: i32, width: u32, height: u32) {
+    gl::Viewport(x, y, width as i32, height as i32);
+}
+
+// * Debug labels
+// Names for GL objects and groups around stretches of the frame, for frame captures: RenderDoc and
+// apitrace show "terrain VAO" and a "shadows" group instead of "VAO 17" and a flat list of draw calls.
+// Needs KHR_debug (or GL 4.3), which is what the debug callback needs too, and is switched on with
+// it (see main.rs), so none of this costs anything in a normal run.
+//
+// Code that wants a group of its own uses debug_scope!, which pushes a group that lasts until the end
+// of the enclosing block:
+//
+//     debug_scope!("shadow cascade {}", cascade);
+//
+// The render graph puts every pass in a group named after it.
+static DEBUG_LABELS: AtomicBool = AtomicBool::new(false);
+
+// Longer labels are cut, GL_MAX_LABEL_LENGTH is at least 256 with the terminator
+const MAX_LABEL_LENGTH: usize = 255;
+
+pub fn enable_debug_labels(enabled: bool) {
+    let supported = gl::ObjectLabel::is_loaded() && gl::PushDebugGroup::is_loaded() && gl::PopDebugGroup::is_loaded();
+    DEBUG_LABELS.store(enabled && supported, Ordering::Relaxed);
+}
+
+pub fn debug_labels_enabled() -> bool {
+    DEBUG_LABELS.load(Ordering::Relaxed)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ObjectKind {
+    Buffer,
+    Texture,
+    VertexArray,
+    Program,
+    Framebuffer,
+}
+
+impl ObjectKind {
+    fn identifier(self) -> gl::types::GLenum {
+        match self {
+            ObjectKind::Buffer      => gl::BUFFER,
+            ObjectKind::Texture     => gl::TEXTURE,
+            ObjectKind::VertexArray => gl::VERTEX_ARRAY,
+            ObjectKind::Program     => gl::PROGRAM,
+            ObjectKind::Framebuffer => gl::FRAMEBUFFER,
+        }
+    }
+}
+
+/// Name an object for frame captures. Does nothing without debug labels.
+///
+/// # Safety
+/// Needs a current OpenGL context, and `id` has to be an object of that kind that was bound at least once.
+pub unsafe fn label_object(kind: ObjectKind, id: u32, name: &str) {
+    if !debug_labels_enabled() || id == 0 {
+        return;
+    }
+    let length = name.len().min(MAX_LABEL_LENGTH);
+    gl::ObjectLabel(kind.identifier(), id, length as i32, name.as_ptr() as *const gl::types::GLchar);
+}
+
+/// # Safety
+/// Needs a current OpenGL context, and a `pop_debug_group` for every push.
+pub unsafe fn push_debug_group(name: &str) {
+    if !debug_labels_enabled() {
+        return;
+    }
+    let length = name.len().min(MAX_LABEL_LENGTH);
+    gl::PushDebugGroup(gl::DEBUG_SOURCE_APPLICATION, 0, length as i32, name.as_ptr() as *const gl::types::GLchar);
+}
+
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn pop_debug_group() {
+    if debug_labels_enabled() {
+        gl::PopDebugGroup();
+    }
+}
+
+// A debug group that ends when this is dropped, made by debug_scope!
+pub struct DebugScope {
+    pushed: bool, // Labels can be switched off while the group is open
+}
+
+impl DebugScope {
+    /// # Safety
+    /// Needs a current OpenGL context, until it is dropped.
+    pub unsafe fn new(name: &str) -> DebugScope {
+        push_debug_group(name);
+        DebugScope { pushed: debug_labels_enabled() }
+    }
+}
+
+impl Drop for DebugScope {
+    fn drop(&mut self) {
+        if self.pushed {
+            unsafe { gl::PopDebugGroup() };
+        }
+    }
+}
+
+// A debug group from here to the end of the block, named like format! would. The name isn't even
+// formatted without debug labels.
+macro_rules! debug_scope {
+    ($($name:tt)+) => {
+        let _debug_scope = if $crate::gfx::debug_labels_enabled() {
+            Some(unsafe { $crate::gfx::DebugScope::new(&format!($($name)+)) })
+        } else {
+            None
+        };
+    };
+}
@@ -0,0 +1,208 @@
+use std::collections::HashSet;
+use std::ffi::CStr;
+
+// * OpenGL capabilities
+// What the driver we got can do, looked up once at startup so the renderer can pick its code paths
+// from it instead of assuming a recent desktop driver. Every shader is `#version 430`, so 4.3 is the
+// floor; everything newer (or optional, like the debug output) is checked before it is used.
+
+// * Renderer profiles
+// Desktop uses everything the driver has. Es3 keeps the renderer inside what OpenGL ES 3.0 (and so
+// WebGL 2) can do, to find out what breaks before it runs on one: the shaders are compiled as
+// `#version 300 es`, and the features ES 3.0 doesn't have are turned off in the capabilities.
+// It still runs on the desktop context (4.3 drivers take ES 3.0 shaders). Picked with GLOOM_PROFILE=es3.
+//
+// The browser build is not part of this, it is its own piece of work still to be done: glutin, the gl
+// crate's loader and the loading threads have no wasm32 version, so every GL call has to go through
+// glow first, with winit's canvas in place of the glutin window. Until then a wasm32 build stops with
+// a compile error (see main.rs), and there is no `webgl` profile name, as nothing here runs on WebGL.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RendererProfile {
+    Desktop,
+    Es3,
+}
+
+impl RendererProfile {
+    pub fn from_env() -> RendererProfile {
+        match std::env::var("GLOOM_PROFILE").as_deref() {
+            Ok("es3") | Ok("es") => RendererProfile::Es3,
+            _ => RendererProfile::Desktop,
+        }
+    }
+
+    // What replaces the `#version` line of every shader
+    pub fn shader_header(self) -> &'static str {
+        match self {
+            RendererProfile::Desktop => "#version 430 core\n",
+            RendererProfile::Es3     => "#version 300 es\nprecision highp float;\nprecision highp int;\nprecision highp sampler2D;\nprecision highp samplerCube;\nprecision highp sampler2DArrayShadow;\nprecision highp samplerCubeShadow;\nprecision highp usampler2D;\nprecision highp sampler3D;\n",
+        }
+    }
+}
+
+// GL_MAX_TEXTURE_MAX_ANISOTROPY and GL_TEXTURE_MAX_ANISOTROPY (EXT_texture_filter_anisotropic, core in 4.6),
+// the gl crate only goes up to 4.5
+pub const MAX_TEXTURE_MAX_ANISOTROPY: gl::types::GLenum = 0x84FF;
+pub const TEXTURE_MAX_ANISOTROPY: gl::types::GLenum = 0x84FE;
+
+pub struct GlCaps {
+    pub version                 : (i32, i32),
+    pub vendor                  : String,
+    pub renderer                : String,
+    pub extensions              : HashSet<String>,
+
+    // Features
+    pub debug_output            : bool, // glDebugMessageCallback (4.3 or KHR_debug)
+    pub compute_shaders         : bool, // 4.3 or ARB_compute_shader
+    pub multi_draw_indirect     : bool, // 4.3 or ARB_multi_draw_indirect
+    pub tessellation            : bool, // 4.0 or ARB_tessellation_shader
+    pub geometry_shaders        : bool, // 3.2
+    pub buffer_storage          : bool, // Persistent mapping, 4.4 or ARB_buffer_storage
+    pub direct_state_access     : bool, // 4.5 or ARB_direct_state_access
+    pub anisotropic_filtering   : bool, // 4.6 or EXT_texture_filter_anisotropic
+    pub shader_storage          : bool, // Storage buffers in shaders, 4.3
+    pub clip_distance           : bool, // gl_ClipDistance, not in ES 3.0
+    pub float_render_targets    : bool, // Rendering into RGBA16F, ES 3.0 needs EXT_color_buffer_float
+    pub srgb_framebuffer        : bool, // The window encodes linear colors to sRGB itself (with FRAMEBUFFER_SRGB on)
+    pub clip_control            : bool, // glClipControl, for reversed depth. 4.5 or ARB_clip_control
+    pub program_binaries        : bool, // glGetProgramBinary, for the shader cache. 4.1 or ARB_get_program_binary
+    pub s3tc_compression        : bool, // BC1 to BC3 textures, EXT_texture_compression_s3tc
+    pub s3tc_srgb               : bool, // Their sRGB variants, EXT_texture_sRGB
+    pub rgtc_compression        : bool, // BC4 and BC5 textures, 3.0
+    pub bptc_compression        : bool, // BC7 textures, 4.2 or ARB_texture_compression_bptc
+    pub timer_queries           : bool, // GPU timings, 3.3 or ARB_timer_query
+
+    // Limits
+    pub max_texture_size        : i32,
+    pub max_samples             : i32,
+    pub max_texture_units       : i32, // Combined, over all shader stages
+    pub max_uniform_block_size  : i32,
+    pub max_compute_invocations : i32, // Per work group
+    pub max_anisotropy          : f32,
+}
+
+impl GlCaps {
+    /// # Safety
+    /// Needs a current OpenGL context, with the functions loaded.
+    pub unsafe fn detect() -> GlCaps {
+        let mut major = 0;
+        let mut minor = 0;
+        gl::GetIntegerv(gl::MAJOR_VERSION, &mut major);
+        gl::GetIntegerv(gl::MINOR_VERSION, &mut minor);
+        let version = (major, minor);
+        let at_least = |wanted: (i32, i32)| version >= wanted;
+
+        let mut extension_count = 0;
+        gl::GetIntegerv(gl::NUM_EXTENSIONS, &mut extension_count);
+        let extensions: HashSet<String> = (0..extension_count as u32)
+            .map(|i| CStr::from_ptr(gl::GetStringi(gl::EXTENSIONS, i) as *const libc::c_char).to_string_lossy().to_string())
+            .collect();
+        let has = |name: &str| extensions.contains(name);
+
+        let debug_output = (at_least((4, 3)) || has("GL_KHR_debug")) && gl::DebugMessageCallback::is_loaded();
+        let compute_shaders = (at_least((4, 3)) || has("GL_ARB_compute_shader")) && gl::DispatchCompute::is_loaded();
+        let multi_draw_indirect = (at_least((4, 3)) || has("GL_ARB_multi_draw_indirect")) && gl::MultiDrawElementsIndirect::is_loaded();
+        let tessellation = at_least((4, 0)) || has("GL_ARB_tessellation_shader");
+        let geometry_shaders = at_least((3, 2));
+        let buffer_storage = (at_least((4, 4)) || has("GL_ARB_buffer_storage")) && gl::BufferStorage::is_loaded();
+        let direct_state_access = (at_least((4, 5)) || has("GL_ARB_direct_state_access")) && gl::CreateBuffers::is_loaded();
+        let clip_control = (at_least((4, 5)) || has("GL_ARB_clip_control")) && gl::ClipControl::is_loaded();
+        let program_binaries = (at_least((4, 1)) || has("GL_ARB_get_program_binary")) && gl::ProgramBinary::is_loaded();
+        let s3tc_compression = has("GL_EXT_texture_compression_s3tc");
+        let s3tc_srgb = has("GL_EXT_texture_sRGB") || has("GL_EXT_texture_compression_s3tc_srgb");
+        let bptc_compression = at_least((4, 2)) || has("GL_ARB_texture_compression_bptc");
+        let timer_queries = (at_least((3, 3)) || has("GL_ARB_timer_query")) && gl::GetQueryObjectui64v::is_loaded();
+        let anisotropic_filtering = at_least((4, 6)) || has("GL_EXT_texture_filter_anisotropic") || has("GL_ARB_texture_filter_anisotropic");
+
+        let get = |name: gl::types::GLenum| {
+            let mut value = 0;
+            gl::GetIntegerv(name, &mut value);
+            value
+        };
+        // What the window's color buffer is, the window system doesn't always give us what we ask for
+        let mut window_encoding = 0;
+        gl::GetFramebufferAttachmentParameteriv(gl::FRAMEBUFFER, gl::BACK_LEFT, gl::FRAMEBUFFER_ATTACHMENT_COLOR_ENCODING, &mut window_encoding);
+
+        let mut max_anisotropy = 1.0;
+        if anisotropic_filtering {
+            gl::GetFloatv(MAX_TEXTURE_MAX_ANISOTROPY, &mut max_anisotropy);
+        }
+
+        GlCaps {
+            version,
+            vendor: crate::util::get_gl_string(gl::VENDOR),
+            renderer: crate::util::get_gl_string(gl::RENDERER),
+            extensions,
+            debug_output,
+            compute_shaders,
+            multi_draw_indirect,
+            tessellation,
+            geometry_shaders,
+            buffer_storage,
+            direct_state_access,
+            anisotropic_filtering,
+            shader_storage: at_least((4, 3)),
+            clip_distance: true,
+            float_render_targets: true,
+            srgb_framebuffer: window_encoding == gl::SRGB as i32,
+            clip_control,
+            program_binaries,
+            s3tc_compression,
+            s3tc_srgb,
+            rgtc_compression: true,
+            bptc_compression,
+            timer_queries,
+            max_texture_size: get(gl::MAX_TEXTURE_SIZE),
+            max_samples: get(gl::MAX_SAMPLES),
+            max_texture_units: get(gl::MAX_COMBINED_TEXTURE_IMAGE_UNITS),
+            max_uniform_block_size: get(gl::MAX_UNIFORM_BLOCK_SIZE),
+            max_compute_invocations: if compute_shaders { get(gl::MAX_COMPUTE_WORK_GROUP_INVOCATIONS) } else { 0 },
+            max_anisotropy,
+        }
+    }
+
+    /// Turn off everything `profile` doesn't have.
+    pub fn restrict_to(&mut self, profile: RendererProfile) {
+        if profile == RendererProfile::Es3 {
+            self.debug_output = false; // ES 3.2
+            self.compute_shaders = false; // ES 3.1
+            self.multi_draw_indirect = false;
+            self.tessellation = false; // ES 3.2
+            self.geometry_shaders = false; // ES 3.2
+            self.buffer_storage = false;
+            self.direct_state_access = false;
+            self.shader_storage = false; // ES 3.1
+            self.clip_distance = false;
+            self.clip_control = false; // EXT_clip_control, which the gl crate doesn't load
+            self.float_render_targets = self.has_extension("GL_EXT_color_buffer_float");
+            self.rgtc_compression = self.has_extension("GL_EXT_texture_compression_rgtc");
+            self.bptc_compression = self.has_extension("GL_EXT_texture_compression_bptc");
+            self.timer_queries = false; // EXT_disjoint_timer_query, which the gl crate doesn't load
+        }
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        self.extensions.contains(name)
+    }
+
+    // Mesa drivers (llvmpipe, radeonsi, iris...) put "Mesa" in the version string
+    pub fn is_mesa(&self) -> bool {
+        unsafe { crate::util::get_gl_string(gl::VERSION).contains("Mesa") }
+    }
+
+    pub fn print(&self) {
+        let yes_no = |supported: bool| if supported { "yes" } else { "no" };
+        println!("OpenGL {}.{} with {} extensions", self.version.0, self.version.1, self.extensions.len());
+        println!("  debug output {}, compute {}, multi draw indirect {}, tessellation {}, geometry shaders {}",
+            yes_no(self.debug_output), yes_no(self.compute_shaders), yes_no(self.multi_draw_indirect),
+            yes_no(self.tessellation), yes_no(self.geometry_shaders));
+        println!("  buffer storage {}, direct state access {}, anisotropic filtering {} (up to {}x), sRGB window {}, clip control {}, program binaries {}",
+            yes_no(self.buffer_storage), yes_no(self.direct_state_access), yes_no(self.anisotropic_filtering), self.max_anisotropy,
+            yes_no(self.srgb_framebuffer), yes_no(self.clip_control), yes_no(self.program_binaries));
+        println!("  max texture size {}, max samples {}, texture units {}, uniform block size {}, compute invocations {}",
+            self.max_texture_size, self.max_samples, self.max_texture_units, self.max_uniform_block_size, self.max_compute_invocations);
+        println!("  compressed textures BC1-BC3 {} (sRGB {}), BC4-BC5 {}, BC7 {}, timer queries {}",
+            yes_no(self.s3tc_compression), yes_no(self.s3tc_srgb), yes_no(self.rgtc_compression), yes_no(self.bptc_compression),
+            yes_no(self.timer_queries));
+    }
+}
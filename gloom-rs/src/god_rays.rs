@@ -0,0 +1,133 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::math;
+use crate::post::PostChain;
+use crate::viewport::Viewport;
+
+// * God rays
+// Shafts of sunlight fanning out past the terrain when the sun is low and just behind something
+// (crepuscular rays), done in screen space. First a mask is drawn at half resolution: the sun disc
+// where the sky is visible, black where anything is in front of it. Then every pixel of the view
+// walks from itself towards the sun on the screen, adding up the mask along the way, which smears
+// the visible bits of the disc out into rays. The rays are added to the HDR target before the tone
+// mapping, so they are exposed like the rest of the scene.
+
+const SAMPLES: i32 = 64; // Along the way to the sun, per pixel
+
+pub struct GodRays {
+    pub enabled    : bool,
+    pub sun_radius : f32, // Of the disc in the mask, as a fraction of the view height
+    pub density    : f32, // How much of the way to the sun the samples cover, 1 goes all the way
+    pub decay      : f32, // Every sample further along counts this much less than the one before
+    pub strength   : f32,
+    mask_target    : Framebuffer,
+    mask_shader    : ShaderHandle,
+    rays_shader    : ShaderHandle,
+}
+
+impl GodRays {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, width: u32, height: u32) -> GodRays {
+        GodRays {
+            enabled: true,
+            sun_radius: 0.06,
+            density: 0.9,
+            decay: 0.97,
+            strength: 2.5,
+            mask_target: Framebuffer::with_color_format((width / 2) as i32, (height / 2) as i32, gl::RGBA16F, gl::FLOAT).with_label("god rays mask"),
+            mask_shader: assets.load_shader(&["shaders/fullscreen.vert", "shaders/god_rays_mask.frag"]),
+            rays_shader: assets.load_shader(&["shaders/fullscreen.vert", "shaders/god_rays.frag"]),
+        }
+    }
+
+    /// Add the rays of a sun with light traveling in `sun_direction` to every view drawn into the post
+    /// chain's HDR target, given with the view projection it was drawn with.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn render(&mut self, assets: &AssetManager, post_chain: &PostChain, views: &[(Viewport, glm::Mat4)], sun_direction: glm::Vec3, sun_color: [f32; 3]) {
+        if !self.enabled {
+            return;
+        }
+
+        // Where the sun is in every view, leaving out the ones it is too far out of to cast rays into
+        let suns: Vec<(Viewport, glm::Vec2, f32)> = views.iter()
+            .filter_map(|(view, view_projection)| {
+                // Infinitely far away in the direction of the sun, so w = 0
+                let towards_sun = -sun_direction;
+                let sun_ndc = math::project_to_ndc(view_projection, &glm::vec4(towards_sun.x, towards_sun.y, towards_sun.z, 0.0))?;
+                // The rays still reach into the view from a sun just past its edge, fading out further off
+                let fade = (2.0 - sun_ndc.x.abs().max(sun_ndc.y.abs())).clamp(0.0, 1.0);
+                if fade <= 0.0 {
+                    return None;
+                }
+                Some((*view, sun_ndc * 0.5 + glm::vec2(0.5, 0.5), fade))
+            })
+            .collect();
+        if suns.is_empty() {
+            return;
+        }
+
+        let scene = &post_chain.hdr_target;
+        self.mask_target.resize((scene.width / 2).max(1), (scene.height / 2).max(1));
+        let view_rect = |view: &Viewport| [
+            view.x as f32 / scene.width as f32,
+            view.y as f32 / scene.height as f32,
+            view.width as f32 / scene.width as f32,
+            view.height as f32 / scene.height as f32,
+        ];
+
+        gfx::RenderState::fullscreen().apply();
+
+        // * Mask, the sun disc where nothing is in front of it
+        self.mask_target.bind();
+        gl::ClearColor(0.0, 0.0, 0.0, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT);
+        let mask_shader = assets.shader(self.mask_shader);
+        mask_shader.activate();
+        mask_shader.set_uniform_int("depth_texture", 0);
+        mask_shader.set_uniform_float("sun_radius", self.sun_radius);
+        mask_shader.set_uniform_vec3("sun_color", &sun_color);
+        mask_shader.set_uniform_int("reversed_z", (gfx::depth_mode() == gfx::DepthMode::Reversed) as i32);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, scene.depth_texture);
+        for (view, sun_position, _) in &suns {
+            mask_shader.set_uniform_vec2("sun_position", &[sun_position.x, sun_position.y]);
+            mask_shader.set_uniform_float("aspect_ratio", view.aspect_ratio());
+            mask_shader.set_uniform_vec4("view_rect", &view_rect(view));
+            gl::Viewport(view.x / 2, view.y / 2, view.width / 2, view.height / 2);
+            post_chain.draw_fullscreen_triangle();
+        }
+
+        // * Rays, smeared out from the mask towards the sun and added to the scene
+        scene.bind();
+        gfx::RenderState { blend: gfx::Blend::Additive, ..gfx::RenderState::fullscreen() }.apply();
+        let rays_shader = assets.shader(self.rays_shader);
+        rays_shader.activate();
+        rays_shader.set_uniform_int("mask_texture", 0);
+        rays_shader.set_uniform_int("samples", SAMPLES);
+        rays_shader.set_uniform_float("density", self.density);
+        rays_shader.set_uniform_float("decay", self.decay);
+        gl::BindTexture(gl::TEXTURE_2D, self.mask_target.color_texture);
+        for (view, sun_position, fade) in &suns {
+            rays_shader.set_uniform_vec2("sun_position", &[sun_position.x, sun_position.y]);
+            rays_shader.set_uniform_float("strength", self.strength * fade);
+            rays_shader.set_uniform_vec4("view_rect", &view_rect(view));
+            gl::Viewport(view.x, view.y, view.width, view.height);
+            post_chain.draw_fullscreen_triangle();
+        }
+
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gfx::RenderState::default().apply();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.mask_target.delete();
+    }
+}
@@ -0,0 +1,110 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::gfx;
+use crate::settings::FogSettings;
+use crate::time_of_day::TimeOfDay;
+
+// * Infinite ground
+// A floor for prototype scenes that goes on to the horizon wherever the camera is, without the terrain
+// model. The asset manifest puts one in with `ground` or `checker-ground`, and then the terrain model
+// isn't loaded at all: a flat stand-in mesh (see Terrain::flat) takes its place for collisions and the
+// like, hidden, and this is drawn instead.
+//
+// There is no mesh. A single triangle covers the view, and the fragment shader follows the line of
+// sight through every pixel until it meets the plane of the ground, so it is locked to the frustum and
+// has no edge. Where it meets it the depth is worked out again and written, so the scene stands on the
+// ground like on any other surface, and pixels looking above the horizon are thrown away. It is done
+// with the view projection of a camera at the origin (see Camera::relative_view_projection), so it
+// stays precise far away from the origin.
+//
+// The pattern is made in the shader from where the line of sight meets the ground: a checkerboard to
+// judge distances and scale by, or grey regolith from noise with small craters in it. Both are filtered
+// by how much ground a pixel covers, so they blur out towards the horizon instead of flickering, and
+// then fade into the fog. It is lit by the sun and the ambient light, without shadows.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GroundPattern {
+    Regolith,
+    Checker,
+}
+
+impl GroundPattern {
+    fn shader_value(self) -> i32 {
+        match self {
+            GroundPattern::Regolith => 0,
+            GroundPattern::Checker  => 1,
+        }
+    }
+}
+
+pub struct InfiniteGround {
+    pub enabled       : bool,
+    pub height        : f32,
+    pub color         : [f32; 3], // Linear
+    pub pattern       : GroundPattern,
+    pub tile_size     : f32,      // Side length of a square of the checkerboard
+    pub fade_distance : f32,      // Gone into the fog color by here, whatever the fog settings
+    shader            : ShaderHandle,
+    empty_vao_id      : u32,
+}
+
+impl InfiniteGround {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, height: f32, color: [f32; 3], pattern: GroundPattern) -> InfiniteGround {
+        let mut empty_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut empty_vao_id);
+        InfiniteGround {
+            enabled: true,
+            height,
+            color,
+            pattern,
+            tile_size: 4.0,
+            fade_distance: 1500.0,
+            shader: assets.load_shader(&["shaders/fullscreen.vert", "shaders/infinite_ground.frag"]),
+            empty_vao_id,
+        }
+    }
+
+    /// Draw the ground into the active view, with the view projection of a camera at the origin.
+    /// `sun_color` is linear, like the scene shader's. Leaves the default render state, keeping the scissor.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, relative_view_projection: &glm::Mat4, camera_position: glm::Vec3, time_of_day: &TimeOfDay, sun_color: [f32; 3], fog: &FogSettings) {
+        if !self.enabled {
+            return;
+        }
+        let scissor = gfx::applied_render_state().scissor;
+        gfx::RenderState { cull: gfx::CullMode::Off, scissor, ..Default::default() }.apply();
+
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_mat4("relative_view_projection", relative_view_projection);
+        shader.set_uniform_mat4("inverse_view_projection", &glm::inverse(relative_view_projection));
+        shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+        shader.set_uniform_float("ground_height", self.height);
+        shader.set_uniform_vec3("ground_color", &self.color);
+        shader.set_uniform_int("pattern", self.pattern.shader_value());
+        shader.set_uniform_float("tile_size", self.tile_size);
+        shader.set_uniform_float("fade_distance", self.fade_distance);
+        shader.set_uniform_vec3("sun_direction", time_of_day.sun_direction().as_ref());
+        shader.set_uniform_vec3("sun_color", &sun_color);
+        shader.set_uniform_vec3("ambient_color", &time_of_day.ambient_color());
+        shader.set_uniform_int("reversed_z", (gfx::depth_mode() == gfx::DepthMode::Reversed) as i32);
+        fog.set_uniforms(shader);
+
+        gl::BindVertexArray(self.empty_vao_id);
+        gfx::count_draw_call();
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        gl::BindVertexArray(0);
+        gfx::RenderState { scissor, ..Default::default() }.apply();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteVertexArrays(1, &self.empty_vao_id);
+    }
+}
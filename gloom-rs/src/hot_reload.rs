@@ -0,0 +1,51 @@
+use std::time::{Duration, Instant, SystemTime};
+
+// * File watcher for hot reloading
+// No fancy OS file notifications, we just look at the modification time of every watched file
+// a couple of times per second. That is plenty for "save in Blender, look at the window".
+
+pub struct FileWatcher {
+    files         : Vec<(String, Option<SystemTime>)>,
+    poll_interval : Duration,
+    last_poll     : Instant,
+}
+
+fn modified_time(path: &str) -> Option<SystemTime> {
+    std::fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl FileWatcher {
+    pub fn new(poll_interval: Duration) -> FileWatcher {
+        FileWatcher {
+            files: vec![],
+            poll_interval,
+            last_poll: Instant::now(),
+        }
+    }
+
+    pub fn watch(&mut self, path: &str) {
+        if self.files.iter().any(|(p, _)| p == path) {
+            return;
+        }
+        self.files.push((path.to_string(), modified_time(path)));
+    }
+
+    /// Paths that were modified since the last call. Only actually looks at the disk once per poll interval.
+    pub fn changed_files(&mut self) -> Vec<String> {
+        if self.last_poll.elapsed() < self.poll_interval {
+            return vec![];
+        }
+        self.last_poll = Instant::now();
+
+        let mut changed = vec![];
+        for (path, last_modified) in self.files.iter_mut() {
+            let modified = modified_time(path);
+            // A file that is missing right now is probably being written, try again next poll
+            if modified.is_some() && modified != *last_modified {
+                *last_modified = modified;
+                changed.push(path.clone());
+            }
+        }
+        changed
+    }
+}
@@ -0,0 +1,292 @@
+use crate::assets::AssetManager;
+use crate::atlas::{AtlasBuilder, TextureAtlas};
+use crate::text::{BitmapFont, PlacedGlyph, SpriteBatch, TextAlign};
+use crate::viewport::Viewport;
+
+// * HUD
+// What is drawn flat over the whole window: the frame rate, the controls, a crosshair. Elements are
+// added once and kept; each says which corner or edge of the window it is anchored to and how far in
+// from it, and where that puts it is worked out again every frame from the window size, so everything
+// stays in its place when the window is resized and nothing has to deal with pixel coordinates.
+//
+// Sizes are in cells of the font (see text.rs), and a cell is a whole number of pixels that grows with
+// the window height, so the HUD is as big on a 4K screen as on a 1080p one. It draws through one sprite
+// batch after everything else.
+//
+// Elements that move, like the artificial horizon of the flight instruments (see instruments.rs), are
+// anchored where they rest and moved from there with an offset and a rotation.
+
+// The window height a cell is this many pixels per, rounded
+const PIXELS_PER_CELL_HEIGHT: f32 = 540.0;
+
+pub type HudElementId = usize;
+
+// Where in the window an element goes, and which of its corners or edges goes there
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    BottomRight,
+}
+
+impl Anchor {
+    // Across and up the window from the lower left corner, from 0 to 1
+    fn fraction(self) -> (f32, f32) {
+        match self {
+            Anchor::TopLeft     => (0.0, 1.0),
+            Anchor::Top         => (0.5, 1.0),
+            Anchor::TopRight    => (1.0, 1.0),
+            Anchor::Left        => (0.0, 0.5),
+            Anchor::Center      => (0.5, 0.5),
+            Anchor::Right       => (1.0, 0.5),
+            Anchor::BottomLeft  => (0.0, 0.0),
+            Anchor::Bottom      => (0.5, 0.0),
+            Anchor::BottomRight => (1.0, 0.0),
+        }
+    }
+
+    // Lines of text line up on the side they are anchored to
+    fn text_align(self) -> TextAlign {
+        match self.fraction().0 {
+            x if x < 0.5 => TextAlign::Left,
+            x if x > 0.5 => TextAlign::Right,
+            _            => TextAlign::Center,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+enum HudContent {
+    Text { text: String, glyphs: Vec<PlacedGlyph> }, // Laid out when it changes
+    Sprite { uv_rect: [f32; 4] },
+}
+
+#[derive(Clone, Debug)]
+pub struct HudElement {
+    pub anchor   : Anchor,
+    pub margin   : (f32, f32), // Cells in from the edges it is anchored to, nothing along a centered axis
+    pub offset   : (f32, f32), // Cells moved from where the anchor puts it, x right and y up
+    pub rotation : f32,        // Radians counterclockwise about its middle, sprites only
+    pub color    : [f32; 4],
+    pub visible  : bool,
+    size         : (u32, u32), // In cells
+    content      : HudContent,
+}
+
+impl HudElement {
+    // Text, laid out when it is added to the HUD
+    pub fn text(anchor: Anchor, text: &str) -> HudElement {
+        HudElement {
+            anchor,
+            margin: (4.0, 4.0),
+            offset: (0.0, 0.0),
+            rotation: 0.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            visible: true,
+            size: BitmapFont::measure(text),
+            content: HudContent::Text { text: text.to_string(), glyphs: vec![] },
+        }
+    }
+
+    // A sprite of the overlay atlas, `size` cells wide and high
+    pub fn sprite(anchor: Anchor, atlas: &TextureAtlas, name: &str, size: (u32, u32)) -> HudElement {
+        HudElement {
+            anchor,
+            margin: (4.0, 4.0),
+            offset: (0.0, 0.0),
+            rotation: 0.0,
+            color: [1.0, 1.0, 1.0, 1.0],
+            visible: true,
+            size,
+            content: HudContent::Sprite { uv_rect: atlas.uv_rect(name) },
+        }
+    }
+
+    pub fn with_margin(mut self, margin: (f32, f32)) -> HudElement {
+        self.margin = margin;
+        self
+    }
+
+    pub fn with_offset(mut self, offset: (f32, f32)) -> HudElement {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> HudElement {
+        self.color = color;
+        self
+    }
+
+    pub fn with_visible(mut self, visible: bool) -> HudElement {
+        self.visible = visible;
+        self
+    }
+
+    /// Where the element goes in a window of `window_width` x `window_height` pixels with `cell_size`
+    /// pixels to a cell, as (x, y, width, height) in pixels from the lower left corner.
+    pub fn rect(&self, window_width: u32, window_height: u32, cell_size: f32) -> [f32; 4] {
+        let (fraction_x, fraction_y) = self.anchor.fraction();
+        let (width, height) = (self.size.0 as f32 * cell_size, self.size.1 as f32 * cell_size);
+        // Pushed in from the edge it is anchored to: right from the left edge, left from the right one
+        let inwards = |fraction: f32| if fraction < 0.5 { 1.0 } else if fraction > 0.5 { -1.0 } else { 0.0 };
+        let x = fraction_x * (window_width as f32 - width) + (inwards(fraction_x) * self.margin.0 + self.offset.0) * cell_size;
+        let y = fraction_y * (window_height as f32 - height) + (inwards(fraction_y) * self.margin.1 + self.offset.1) * cell_size;
+        [x.round(), y.round(), width, height]
+    }
+}
+
+pub struct Hud {
+    pub enabled : bool,
+    elements    : Vec<HudElement>,
+    font        : BitmapFont,
+    batch       : SpriteBatch,
+}
+
+impl Hud {
+    // A crosshair, white with a dark edge so it shows on anything, and a plain square for panels
+    pub fn add_sprites(atlas: &mut AtlasBuilder) {
+        atlas.add_generated("hud_panel", 8, |_, _| [1.0, 1.0, 1.0, 1.0]);
+        atlas.add_generated("hud_crosshair", 32, |u, v| {
+            let (u, v) = (u.abs(), v.abs());
+            let arm = |along: f32, across: f32| (0.3..=0.95).contains(&along) && across <= 0.07;
+            let edge = |along: f32, across: f32| (0.22..=1.0).contains(&along) && across <= 0.15;
+            if arm(u, v) || arm(v, u) {
+                [1.0, 1.0, 1.0, 1.0]
+            } else if edge(u, v) || edge(v, u) {
+                [0.0, 0.0, 0.0, 0.6]
+            } else {
+                [0.0, 0.0, 0.0, 0.0]
+            }
+        });
+    }
+
+    /// `atlas` has to have the sprites from `add_sprites` and `BitmapFont::add_sprites` in it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, atlas: &TextureAtlas) -> Hud {
+        Hud {
+            enabled: true,
+            elements: vec![],
+            font: BitmapFont::new(atlas),
+            batch: SpriteBatch::new(assets, atlas),
+        }
+    }
+
+    pub fn add(&mut self, mut element: HudElement) -> HudElementId {
+        if let HudContent::Text { text, glyphs } = &mut element.content {
+            *glyphs = self.font.layout(text, element.anchor.text_align());
+        }
+        self.elements.push(element);
+        self.elements.len() - 1
+    }
+
+    pub fn element(&self, id: HudElementId) -> &HudElement {
+        &self.elements[id]
+    }
+
+    // Where an element is in a window of this size, for what the mouse is over
+    pub fn element_rect(&self, id: HudElementId, window_width: u32, window_height: u32) -> [f32; 4] {
+        self.elements[id].rect(window_width, window_height, Self::cell_size(window_height))
+    }
+
+    pub fn set_color(&mut self, id: HudElementId, color: [f32; 4]) {
+        self.elements[id].color = color;
+    }
+
+    // Lays the text out again only if it changed, so it can be set every frame. Ignored for sprites.
+    pub fn set_text(&mut self, id: HudElementId, new_text: &str) {
+        let element = &mut self.elements[id];
+        if let HudContent::Text { text, glyphs } = &mut element.content {
+            if text != new_text {
+                *text = new_text.to_string();
+                *glyphs = self.font.layout(new_text, element.anchor.text_align());
+                element.size = BitmapFont::measure(new_text);
+            }
+        }
+    }
+
+    pub fn set_visible(&mut self, id: HudElementId, visible: bool) {
+        self.elements[id].visible = visible;
+    }
+
+    pub fn set_offset(&mut self, id: HudElementId, offset: (f32, f32)) {
+        self.elements[id].offset = offset;
+    }
+
+    pub fn set_rotation(&mut self, id: HudElementId, rotation: f32) {
+        self.elements[id].rotation = rotation;
+    }
+
+    // Pixels to a cell in a window this high
+    pub fn cell_size(window_height: u32) -> f32 {
+        (window_height as f32 / PIXELS_PER_CELL_HEIGHT).round().max(1.0)
+    }
+
+    /// Lay the elements out for the window size and draw them over the whole window.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, with the window bound as the framebuffer.
+    pub unsafe fn draw(&mut self, assets: &AssetManager, window_width: u32, window_height: u32) {
+        if !self.enabled {
+            return;
+        }
+        let cell_size = Self::cell_size(window_height);
+        for element in self.elements.iter().filter(|element| element.visible) {
+            let [x, y, width, height] = element.rect(window_width, window_height, cell_size);
+            let added = match &element.content {
+                HudContent::Text { glyphs, .. } => self.batch.add_text(glyphs, x, y + height, cell_size, element.color),
+                HudContent::Sprite { uv_rect } if element.rotation != 0.0 => {
+                    self.batch.add_rotated_quad([x, y, width, height], element.rotation, *uv_rect, element.color)
+                }
+                HudContent::Sprite { uv_rect } => self.batch.add_quad([x, y, width, height], *uv_rect, element.color),
+            };
+            if !added {
+                break;
+            }
+        }
+        let window = Viewport { x: 0, y: 0, width: window_width as i32, height: window_height as i32 };
+        self.batch.draw(assets, &window);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.batch.delete();
+    }
+}
+
+// * Frame rate
+// Frames counted over half a second at a time, so the number on the HUD can be read
+pub struct FrameRateCounter {
+    frames  : u32,
+    elapsed : f32,
+    pub fps : f32,
+    pub ms  : f32, // Average frame time over the last count
+}
+
+impl FrameRateCounter {
+    pub fn new() -> FrameRateCounter {
+        FrameRateCounter { frames: 0, elapsed: 0.0, fps: 0.0, ms: 0.0 }
+    }
+
+    // Count a frame that took `delta_time` seconds, true when there is a new count
+    pub fn update(&mut self, delta_time: f32) -> bool {
+        self.frames += 1;
+        self.elapsed += delta_time;
+        if self.elapsed < 0.5 {
+            return false;
+        }
+        self.fps = self.frames as f32 / self.elapsed;
+        self.ms = self.elapsed * 1000.0 / self.frames as f32;
+        self.frames = 0;
+        self.elapsed = 0.0;
+        true
+    }
+}
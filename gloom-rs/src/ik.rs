@@ -0,0 +1,188 @@
+extern crate nalgebra_glm as glm;
+
+use crate::scene_graph::{NodeId, SceneNode};
+
+// * Inverse kinematics
+// An IkChain is a line of SceneNodes, every one a child of the one before, like the segments of a
+// crane arm. Given a point in world space, solve turns the joints so the tip of the last one gets to
+// it, with cyclic coordinate descent: from the last joint back to the first, each joint turns about
+// its pivot (see SceneNode::pivot) so the line from there to the tip points at the target, and that
+// goes round again until the tip is close enough. Two joints (a shoulder and an elbow) get there in a
+// couple of rounds, longer chains take a few more.
+//
+// The joints keep their rotation from one solve to the next, so solving every frame for a moving
+// target only has to make up for how far it moved. A joint can be held between limits on each of its
+// Euler angles, a hinge is a joint whose limits only leave one axis free. The joints and what they
+// hang from should be scaled the same along every axis, or a turn in the world isn't one for them.
+//
+// Nothing is drawn or changed other than the rotations of the joints. The chain only knows them by
+// their IDs and looks them up below the root it is given, like the rest of the update (see
+// SceneNode::find_mut), so a joint that is no longer in the scene graph just stops the solve.
+
+#[derive(Clone, Copy, Debug)]
+pub struct Joint {
+    pub node : NodeId,
+    pub min  : glm::Vec3, // Lowest rotation around the X, the Y and the Z axes
+    pub max  : glm::Vec3,
+}
+
+pub struct IkChain {
+    pub joints     : Vec<Joint>, // From the base of the chain out to the tip
+    pub tip        : glm::Vec3,  // The end of the chain, in the last joint's space
+    pub iterations : usize,      // Rounds over every joint at most
+    pub tolerance  : f32,        // How close the tip has to get, in world units
+}
+
+impl IkChain {
+    /// The joints go in the order they hang off each other, each one a child of the one before.
+    pub fn new(nodes: &[NodeId], tip: glm::Vec3) -> IkChain {
+        let unlimited = glm::vec3(f32::INFINITY, f32::INFINITY, f32::INFINITY);
+        IkChain {
+            joints: nodes.iter().map(|&node| Joint { node, min: -unlimited, max: unlimited }).collect(),
+            tip,
+            iterations: 10,
+            tolerance: 0.01,
+        }
+    }
+
+    /// Holds the `index`th joint between `min` and `max`, builder style.
+    pub fn with_limits(mut self, index: usize, min: glm::Vec3, max: glm::Vec3) -> IkChain {
+        self.joints[index].min = min;
+        self.joints[index].max = max;
+        self
+    }
+
+    // Where the parent of every joint is in the world, and the world matrix of the last joint.
+    // None if a joint isn't below `root`.
+    fn parent_matrices(&self, root: &SceneNode) -> Option<(Vec<glm::Mat4>, glm::Mat4)> {
+        let (_, parent_matrix) = root.find(self.joints.first()?.node, &glm::identity())?;
+        let mut matrix: glm::Mat4 = glm::convert(parent_matrix);
+        let mut parent_matrices = Vec::with_capacity(self.joints.len());
+        for joint in &self.joints {
+            parent_matrices.push(matrix);
+            let (node, _) = root.find(joint.node, &glm::identity())?;
+            matrix *= node.local_transform();
+        }
+        Some((parent_matrices, matrix))
+    }
+
+    // The tip of the chain in world space, None if a joint isn't below `root`
+    pub fn tip_position(&self, root: &SceneNode) -> Option<glm::Vec3> {
+        let (_, last_matrix) = self.parent_matrices(root)?;
+        Some((last_matrix * self.tip.push(1.0)).xyz())
+    }
+
+    // Turn the joints, found below `root`, towards putting the tip at `target` in world space.
+    // True if the tip got there.
+    pub fn solve(&mut self, root: &mut SceneNode, target: glm::Vec3) -> bool {
+        let reached = |chain: &IkChain, root: &SceneNode| {
+            chain.tip_position(root).is_some_and(|tip| glm::distance(&tip, &target) <= chain.tolerance)
+        };
+        for _ in 0..self.iterations {
+            if reached(self, root) {
+                return true;
+            }
+            for index in (0..self.joints.len()).rev() {
+                let (parent_matrices, last_matrix) = match self.parent_matrices(root) {
+                    Some(matrices) => matrices,
+                    None => return false,
+                };
+                let tip = (last_matrix * self.tip.push(1.0)).xyz();
+                let joint = self.joints[index];
+                let node = match root.find_mut(joint.node) {
+                    Some(node) => node,
+                    None => return false,
+                };
+                let parent_matrix = parent_matrices[index];
+                let pivot = (parent_matrix * node.pivot().push(1.0)).xyz();
+
+                // The turn that points the tip at the target, seen from the pivot
+                let (to_tip, to_target) = (tip - pivot, target - pivot);
+                if glm::length(&to_tip) < 1e-5 || glm::length(&to_target) < 1e-5 {
+                    continue;
+                }
+                let (to_tip, to_target) = (glm::normalize(&to_tip), glm::normalize(&to_target));
+                let axis = glm::cross(&to_tip, &to_target);
+                if glm::length(&axis) < 1e-6 {
+                    continue; // Already pointing at it, or straight away from it with no way to pick a side
+                }
+                let angle = glm::dot(&to_tip, &to_target).clamp(-1.0, 1.0).acos();
+                let world_turn = glm::rotation(angle, &glm::normalize(&axis));
+
+                // The same turn in the parent's space, before the joint's own rotation
+                let parent_rotation = glm::mat4_to_mat3(&parent_matrix);
+                let parent_rotation_inverse = glm::inverse(&parent_rotation);
+                let local_turn = parent_rotation_inverse * glm::mat4_to_mat3(&world_turn) * parent_rotation;
+                let rotation = local_turn * glm::mat4_to_mat3(&node.rotation_matrix());
+
+                node.rotation = glm::clamp_vec(&euler_xyz(&rotation), &joint.min, &joint.max);
+            }
+        }
+        reached(self, root)
+    }
+}
+
+// The angles of a rotation matrix made the way SceneNode::rotation_matrix makes it, X * Y * Z
+fn euler_xyz(rotation: &glm::Mat3) -> glm::Vec3 {
+    let sin_y = rotation[(0, 2)].clamp(-1.0, 1.0);
+    let y = sin_y.asin();
+    if sin_y.abs() < 0.9999 {
+        glm::vec3((-rotation[(1, 2)]).atan2(rotation[(2, 2)]), y, (-rotation[(0, 1)]).atan2(rotation[(0, 0)]))
+    } else {
+        // Looking straight along Y, X and Z turn about the same axis, so Z gets none of it
+        glm::vec3(rotation[(2, 1)].atan2(rotation[(1, 1)]), y, 0.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A root with a shoulder and an elbow hanging off it, each segment 1 long along -Y
+    fn two_joint_arm() -> (crate::scene_graph::Node, NodeId, NodeId) {
+        let mut root = SceneNode::new();
+        let mut shoulder = SceneNode::new();
+        let mut elbow = SceneNode::new();
+        elbow.position = glm::vec3(0.0, -1.0, 0.0);
+        let (shoulder_id, elbow_id) = (shoulder.id, elbow.id);
+        shoulder.add_child(&elbow);
+        root.add_child(&shoulder);
+        (root, shoulder_id, elbow_id)
+    }
+
+    #[test]
+    fn reaches_a_reachable_target() {
+        let (mut root, shoulder, elbow) = two_joint_arm();
+        let mut chain = IkChain::new(&[shoulder, elbow], glm::vec3(0.0, -1.0, 0.0));
+        chain.iterations = 50;
+        let target = glm::vec3(1.0, -1.0, 0.5);
+        assert!(chain.solve(&mut root, target));
+        let tip = chain.tip_position(&root).unwrap();
+        assert!(glm::distance(&tip, &target) <= chain.tolerance, "tip at {:?}", tip);
+    }
+
+    #[test]
+    fn keeps_to_its_limits() {
+        let (mut root, shoulder, elbow) = two_joint_arm();
+        let (min, max) = (glm::vec3(-0.5, 0.0, -0.5), glm::vec3(0.5, 0.0, 0.5));
+        let mut chain = IkChain::new(&[shoulder, elbow], glm::vec3(0.0, -1.0, 0.0))
+            .with_limits(0, min, max)
+            .with_limits(1, min, max);
+        // Straight up is out of reach with these limits, the tip only gets as close as they allow
+        assert!(!chain.solve(&mut root, glm::vec3(0.3, 2.0, 0.0)));
+        for id in [shoulder, elbow] {
+            let (node, _) = root.find(id, &glm::identity()).unwrap();
+            for axis in 0..3 {
+                assert!(node.rotation[axis] >= min[axis] - 1e-6 && node.rotation[axis] <= max[axis] + 1e-6, "{:?}", node.rotation);
+            }
+        }
+    }
+
+    #[test]
+    fn stops_when_a_joint_is_gone() {
+        let (mut root, shoulder, _) = two_joint_arm();
+        let mut chain = IkChain::new(&[shoulder, 0], glm::vec3(0.0, -1.0, 0.0));
+        assert!(!chain.solve(&mut root, glm::vec3(1.0, -1.0, 0.0)));
+        assert!(chain.tip_position(&root).is_none());
+    }
+}
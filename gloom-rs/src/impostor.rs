@@ -0,0 +1,239 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::material;
+use crate::shader::Shader;
+use crate::util;
+
+// * Impostors
+// A helicopter is 74 000 triangles and every rock over a thousand, which is a lot to spend on
+// something a few pixels tall. An ImpostorAtlas captures its subjects once, from YAW_FRAMES directions
+// around them at each of PITCH_FRAMES heights, into a texture atlas of small frames: one atlas for
+// their colors and one for their normals, so they are still lit by the sun as it moves. Far away, a
+// subject is drawn as a single quad facing the camera (made up from gl_VertexID, like the lens flare
+// elements) with the frame captured from closest to where the camera is.
+//
+// The quad is turned towards the camera in the subject's own space and then put in the world with the
+// subject's model matrix, so a squashed rock or a leaning helicopter stays squashed and leaning.
+// Nothing animates in an impostor (the rotors stand still where they were captured), and they throw
+// no shadows of their own, which is why they are only for what is far away.
+//
+// Atlas rows go subject by subject, each subject's rows from level up to the highest pitch.
+
+pub const MAX_SUBJECTS : usize = 8;    // MAX_SUBJECTS in impostor.vert
+const YAW_FRAMES       : usize = 8;    // Around each subject, YAW_FRAMES in impostor.vert
+const PITCH_FRAMES     : usize = 3;    // From level up, PITCH_FRAMES in impostor.vert
+const PITCH_STEP       : f32   = 30.0; // Degrees between the heights, PITCH_STEP in impostor.vert
+const FRAME_SIZE       : i32   = 128;  // Pixels on a side of one frame
+const MIP_LEVELS       : i32   = 4;    // Below that, neighbouring frames bleed into each other
+
+// Same layout as Instance in instanced.vert and impostor.vert, so an instance batch's own instances
+// can be drawn as impostors (see draw_listed)
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuImpostor {
+    model   : [f32; 16],
+    bounds  : [f32; 4], // Unused by the impostors
+    subject : [u32; 4], // Index of the subject in x, the rest is padding
+}
+
+// How many impostors draw_listed draws
+#[derive(Clone, Copy, Debug)]
+pub enum ImpostorCount {
+    Known(u32),
+    Indirect(u32), // A buffer holding a glDrawArraysIndirect command, filled in on the GPU
+}
+
+// Somebody else's instances to draw as impostors, see draw_listed
+#[derive(Clone, Copy, Debug)]
+pub struct ImpostorList {
+    pub instance_ssbo  : u32, // Laid out like Instance in instanced.vert, mesh.x is the subject
+    pub visible_buffer : u32, // Indices into the instances
+    pub first_visible  : u32, // Where the ones to draw start in it
+    pub count          : ImpostorCount,
+}
+
+pub struct ImpostorAtlas {
+    albedo          : Framebuffer,    // Color in RGB, how much of the texel is covered in A
+    normals         : Framebuffer,    // In the subject's own space, packed from -1..1 into 0..1
+    subjects        : Vec<[f32; 4]>,  // Bounding sphere (center, radius) in each subject's own space
+    instance_buffer : u32,            // For what draw is given, draw_listed uses the caller's
+    empty_vao_id    : u32,
+    capture_shader  : ShaderHandle,
+    shader          : ShaderHandle,
+}
+
+impl ImpostorAtlas {
+    /// An empty atlas with room for `subject_count` subjects, up to MAX_SUBJECTS. Draws nothing until
+    /// they are captured.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, subject_count: usize) -> ImpostorAtlas {
+        assert!(subject_count <= MAX_SUBJECTS, "An impostor atlas has room for {} subjects", MAX_SUBJECTS);
+        let width = YAW_FRAMES as i32 * FRAME_SIZE;
+        let height = (PITCH_FRAMES * subject_count) as i32 * FRAME_SIZE;
+
+        let mut instance_buffer: u32 = 0;
+        gl::GenBuffers(1, &mut instance_buffer);
+        let mut empty_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut empty_vao_id);
+
+        ImpostorAtlas {
+            albedo: Framebuffer::new(width, height).with_label("impostor albedo"),
+            normals: Framebuffer::new(width, height).with_label("impostor normals"),
+            subjects: vec![[0.0; 4]; subject_count],
+            instance_buffer,
+            empty_vao_id,
+            capture_shader: assets.load_shader(&["shaders/simple.vert", "shaders/impostor_capture.frag"]),
+            shader: assets.load_shader(&["shaders/impostor.vert", "shaders/impostor.frag"]),
+        }
+    }
+
+    /// Captures the subject `subject`, which lies inside the box from `min` to `max` in its own space.
+    /// `draw` draws all of it with the view projection and the shader it is given, which takes the same
+    /// transforms as simple.vert and a material albedo if there is one.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, with standard depth (see gfx::DepthMode).
+    pub unsafe fn capture<F: FnMut(&glm::Mat4, &Shader)>(&mut self, assets: &AssetManager, subject: usize, min: glm::Vec3, max: glm::Vec3, mut draw: F) {
+        let center = (min + max) * 0.5;
+        let radius = (glm::distance(&min, &max) * 0.5).max(1e-3);
+        self.subjects[subject] = [center.x, center.y, center.z, radius];
+
+        let shader = assets.shader(self.capture_shader);
+        shader.activate();
+        shader.set_uniform_vec4("clip_plane", &[0.0; 4]);
+
+        for (framebuffer, capture_normals) in [(&self.albedo, false), (&self.normals, true)] {
+            gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer.fbo_id);
+            shader.set_uniform_int("capture_normals", capture_normals as i32);
+
+            for row in 0..PITCH_FRAMES {
+                for column in 0..YAW_FRAMES {
+                    let (x, y) = (column as i32 * FRAME_SIZE, (subject * PITCH_FRAMES + row) as i32 * FRAME_SIZE);
+                    gl::Viewport(x, y, FRAME_SIZE, FRAME_SIZE);
+                    gfx::RenderState { blend: gfx::Blend::Off, scissor: Some((x, y, FRAME_SIZE, FRAME_SIZE)), ..Default::default() }.apply();
+                    gl::ClearColor(0.0, 0.0, 0.0, 0.0);
+                    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+                    // Straight at the center, from the direction impostor.vert picks this frame for
+                    let (yaw, pitch) = (column as f32 * std::f32::consts::TAU / YAW_FRAMES as f32, (row as f32 * PITCH_STEP).to_radians());
+                    let towards_camera = glm::vec3(pitch.cos() * yaw.sin(), pitch.sin(), pitch.cos() * yaw.cos());
+                    let view = glm::look_at(&(center + towards_camera * 2.0 * radius), &center, &glm::vec3(0.0, 1.0, 0.0));
+                    let projection = glm::ortho(-radius, radius, -radius, radius, radius, 3.0 * radius);
+                    draw(&(projection * view), shader);
+                }
+            }
+        }
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gfx::RenderState::default().apply();
+
+        for texture in [self.albedo.color_texture, self.normals.color_texture] {
+            gl::BindTexture(gl::TEXTURE_2D, texture);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAX_LEVEL, MIP_LEVELS);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+            gl::GenerateMipmap(gl::TEXTURE_2D);
+        }
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+    }
+
+    /// The program the impostors are drawn with, for the lighting uniforms that are set once per frame.
+    pub fn shader<'a>(&self, assets: &'a AssetManager) -> &'a Shader {
+        assets.shader(self.shader)
+    }
+
+    /// Draws an impostor for every (model matrix, subject). Leaves the impostor program active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, instances: &[(glm::Mat4, usize)], view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3, clip_plane: [f32; 4]) {
+        if instances.is_empty() {
+            return;
+        }
+        let impostors: Vec<GpuImpostor> = instances.iter().map(|(model, subject)| {
+            let mut matrix = [0.0; 16];
+            matrix.copy_from_slice(model.as_slice());
+            GpuImpostor { model: matrix, bounds: [0.0; 4], subject: [*subject as u32, 0, 0, 0] }
+        }).collect();
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.instance_buffer);
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, util::byte_size_of_array(&impostors), util::pointer_to_array(&impostors), gl::STREAM_DRAW);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+
+        let shader = self.activate(assets, view_projection_matrix, camera_position, clip_plane);
+        shader.set_uniform_int("listed", 0);
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, self.instance_buffer);
+        gl::BindVertexArray(self.empty_vao_id);
+        gfx::count_draw_call();
+        gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, impostors.len() as i32);
+        self.finish();
+    }
+
+    /// Draws the instances of an instance batch as impostors, the ones in `list`. Leaves the impostor
+    /// program active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw_listed(&self, assets: &AssetManager, list: ImpostorList, view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3, clip_plane: [f32; 4]) {
+        if let ImpostorCount::Known(0) = list.count {
+            return;
+        }
+        let shader = self.activate(assets, view_projection_matrix, camera_position, clip_plane);
+        shader.set_uniform_int("listed", 1);
+        shader.set_uniform_uint("first_visible", list.first_visible);
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, list.instance_ssbo);
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, list.visible_buffer);
+        gl::BindVertexArray(self.empty_vao_id);
+        match list.count {
+            ImpostorCount::Known(count) => {
+                gfx::count_draw_call();
+                gl::DrawArraysInstanced(gl::TRIANGLE_STRIP, 0, 4, count as i32);
+            }
+            ImpostorCount::Indirect(command_buffer) => {
+                gfx::count_draw_call();
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, command_buffer);
+                gl::DrawArraysIndirect(gl::TRIANGLE_STRIP, std::ptr::null());
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+            }
+        }
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, 0);
+        self.finish();
+    }
+
+    unsafe fn activate<'a>(&self, assets: &'a AssetManager, view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3, clip_plane: [f32; 4]) -> &'a Shader {
+        let shader = self.shader(assets);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", view_projection_matrix);
+        shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+        shader.set_uniform_vec4("clip_plane", &clip_plane);
+        for (i, bounds) in self.subjects.iter().enumerate() {
+            shader.set_uniform_vec4(&format!("subject_bounds[{}]", i), bounds);
+        }
+        shader.set_uniform_int("subject_count", self.subjects.len() as i32);
+        // The material units, every material binds its own again
+        shader.set_uniform_int("albedo_atlas", material::ALBEDO_UNIT as i32);
+        shader.set_uniform_int("normal_atlas", material::NORMAL_UNIT as i32);
+        gfx::bind_texture(material::ALBEDO_UNIT, Some(self.albedo.color_texture));
+        gfx::bind_texture(material::NORMAL_UNIT, Some(self.normals.color_texture));
+        shader
+    }
+
+    unsafe fn finish(&self) {
+        gl::BindVertexArray(0);
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, 0);
+        gfx::bind_texture(material::ALBEDO_UNIT, None);
+        gfx::bind_texture(material::NORMAL_UNIT, None);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.albedo.delete();
+        self.normals.delete();
+        gl::DeleteBuffers(1, &self.instance_buffer);
+        gl::DeleteVertexArrays(1, &self.empty_vao_id);
+    }
+}
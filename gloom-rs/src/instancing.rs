@@ -0,0 +1,371 @@
+extern crate nalgebra_glm as glm;
+
+use std::ops::Range;
+
+use rayon::prelude::*;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::gfx;
+use crate::impostor::{ImpostorAtlas, ImpostorCount, ImpostorList};
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::shader::Shader;
+use crate::util;
+
+// * Instance batches
+// Lots of copies of a few meshes (boulders, debris...) drawn without a scene node each. All the meshes
+// of a batch share one VAO, and every instance is a model matrix plus a bounding sphere in a storage
+// buffer. Per view, the instances inside the frustum are put in a visible list and drawn with one
+// instanced draw per mesh. There are two ways to do the culling:
+//  - CpuCulled: the CPU tests every sphere and uploads the visible list, one draw call per mesh
+//  - GpuIndirect: a compute shader tests them and fills in the draw commands itself, which are then
+//    drawn with a single glMultiDrawElementsIndirect. Nothing goes back to the CPU.
+// A batch spread out over a big area can be split into chunks of instances next to each other (see
+// with_chunks), each with a sphere around all of them. A chunk outside the frustum is skipped as a
+// whole in either mode, on the GPU by not dispatching it at all.
+// With impostors (see with_impostors), the instances further from the camera than a distance are drawn
+// as camera facing quads instead of their meshes. They go into a second visible list after the first,
+// counted up by the CPU or by the compute shader like the mesh draws are.
+
+const WORKGROUP_SIZE: u32 = 64; // local_size_x in instance_cull.comp
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InstancingMode {
+    CpuCulled,
+    GpuIndirect,
+}
+
+impl InstancingMode {
+    pub fn next(self) -> InstancingMode {
+        match self {
+            InstancingMode::CpuCulled   => InstancingMode::GpuIndirect,
+            InstancingMode::GpuIndirect => InstancingMode::CpuCulled,
+        }
+    }
+}
+
+// Same layout as Instance in the shaders (std430)
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct GpuInstance {
+    model  : [f32; 16],
+    bounds : [f32; 4], // (center, radius) in world space
+    mesh   : [u32; 4], // Index of the mesh in x, the rest is padding
+}
+
+// glDrawElementsIndirect's command layout
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct DrawCommand {
+    count          : u32,
+    instance_count : u32,
+    first_index    : u32,
+    base_vertex    : i32,
+    base_instance  : u32, // Where this mesh's part of the visible list starts
+}
+
+// glDrawArraysIndirect's command layout, for the impostors
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct ArraysCommand {
+    count          : u32,
+    instance_count : u32,
+    first          : u32,
+    base_instance  : u32,
+}
+
+struct Chunk {
+    first  : usize,
+    count  : usize,
+    bounds : [f32; 4], // Around every instance of the chunk, (center, radius) in world space
+}
+
+pub struct InstanceBatch {
+    pub mode                : InstancingMode,
+    pub use_impostors       : bool,
+    instances               : Vec<GpuInstance>,
+    commands                : Vec<DrawCommand>, // With the instance counts at 0, reset to this before every cull
+    chunks                  : Vec<Chunk>,
+    impostors               : Option<(ImpostorAtlas, f32)>, // A subject per mesh, and from how far away
+    vao_id                  : u32,
+    instance_ssbo           : u32,
+    visible_buffer          : u32, // Indices into the instances, read per instance by the vertex shader, then the impostors'
+    command_buffer          : u32,
+    impostor_command_buffer : u32,
+    cull_shader             : ShaderHandle,
+    simple_shader           : ShaderHandle,
+    pbr_shader              : ShaderHandle,
+}
+
+impl InstanceBatch {
+    /// `instances` are (index into `meshes`, model matrix, bounding sphere radius in world units).
+    /// The bounding sphere is centered on the middle of the instance's mesh.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, meshes: &[&Mesh], instances: &[(usize, glm::Mat4, f32)]) -> InstanceBatch {
+        // One vertex buffer for all the meshes, each draw command picks out its own range
+        let mut merged = Mesh { vertices: vec![], normals: vec![], colors: vec![], texcoords: vec![], indices: vec![], index_count: 0 };
+        let mut commands = vec![];
+        for mesh in meshes {
+            commands.push(DrawCommand {
+                count: mesh.indices.len() as u32,
+                instance_count: 0,
+                first_index: merged.indices.len() as u32,
+                base_vertex: (merged.vertices.len() / 3) as i32,
+                base_instance: 0,
+            });
+            merged.vertices.extend_from_slice(&mesh.vertices);
+            merged.normals.extend_from_slice(&mesh.normals);
+            merged.colors.extend_from_slice(&mesh.colors);
+            merged.indices.extend_from_slice(&mesh.indices);
+        }
+        merged.index_count = merged.indices.len() as i32;
+
+        // Every mesh gets room in the visible list for all of its instances
+        let mut base_instance = 0;
+        for (mesh_index, command) in commands.iter_mut().enumerate() {
+            command.base_instance = base_instance;
+            base_instance += instances.iter().filter(|(mesh, _, _)| *mesh == mesh_index).count() as u32;
+        }
+
+        let mesh_centers: Vec<glm::Vec3> = meshes.iter().map(|mesh| {
+            let (min, max) = mesh.bounds();
+            (min + max) * 0.5
+        }).collect();
+        let instances: Vec<GpuInstance> = instances.iter().map(|&(mesh, model, radius)| {
+            let center = model * mesh_centers[mesh].push(1.0);
+            let mut matrix = [0.0; 16];
+            matrix.copy_from_slice(model.as_slice());
+            GpuInstance { model: matrix, bounds: [center.x, center.y, center.z, radius], mesh: [mesh as u32, 0, 0, 0] }
+        }).collect();
+
+        let (vao_id, _) = util::create_vao(&merged.vertices, &merged.indices, &merged.colors, &merged.normals);
+
+        let mut instance_ssbo: u32 = 0;
+        gl::GenBuffers(1, &mut instance_ssbo);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, instance_ssbo);
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, util::byte_size_of_array(&instances), util::pointer_to_array(&instances), gl::STATIC_DRAW);
+
+        let mut command_buffer: u32 = 0;
+        gl::GenBuffers(1, &mut command_buffer);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, command_buffer);
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, util::byte_size_of_array(&commands), util::pointer_to_array(&commands), gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+
+        let mut impostor_command_buffer: u32 = 0;
+        gl::GenBuffers(1, &mut impostor_command_buffer);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, impostor_command_buffer);
+        gl::BufferData(gl::SHADER_STORAGE_BUFFER, std::mem::size_of::<ArraysCommand>() as isize, std::ptr::null(), gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+
+        // The visible list is written as a storage buffer and read as an instanced vertex attribute.
+        // Twice as long as there are instances, the impostors' list comes after the meshes'.
+        let mut visible_buffer: u32 = 0;
+        gl::BindVertexArray(vao_id);
+        gl::GenBuffers(1, &mut visible_buffer);
+        gl::BindBuffer(gl::ARRAY_BUFFER, visible_buffer);
+        gl::BufferData(gl::ARRAY_BUFFER, (2 * instances.len().max(1) * std::mem::size_of::<u32>()) as isize, std::ptr::null(), gl::DYNAMIC_DRAW);
+        gl::VertexAttribIPointer(4, 1, gl::UNSIGNED_INT, 0, std::ptr::null());
+        gl::VertexAttribDivisor(4, 1);
+        gl::EnableVertexAttribArray(4);
+        gl::BindVertexArray(0);
+        gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+        InstanceBatch {
+            mode: InstancingMode::CpuCulled,
+            use_impostors: false,
+            impostors: None,
+            impostor_command_buffer,
+            chunks: vec![Chunk { first: 0, count: instances.len(), bounds: [0.0, 0.0, 0.0, f32::INFINITY] }],
+            instances,
+            commands,
+            vao_id,
+            instance_ssbo,
+            visible_buffer,
+            command_buffer,
+            cull_shader: assets.load_shader(&["shaders/instance_cull.comp"]),
+            simple_shader: assets.load_shader(&["shaders/instanced.vert", "shaders/simple.frag"]),
+            pbr_shader: assets.load_shader(&["shaders/instanced.vert", "shaders/pbr.frag"]),
+        }
+    }
+
+    /// Splits the instances into chunks, (range of instances, bounding sphere (center, radius)), builder
+    /// style. Instances that aren't in any chunk aren't drawn.
+    pub fn with_chunks(mut self, chunks: &[(Range<usize>, [f32; 4])]) -> InstanceBatch {
+        self.chunks = chunks.iter()
+            .map(|(range, bounds)| Chunk { first: range.start, count: range.len(), bounds: *bounds })
+            .collect();
+        self
+    }
+
+    /// Draws the instances further than `distance` from the camera as impostors from `atlas`, which has
+    /// captured every mesh of the batch as the subject with the same index. Builder style, turns them on.
+    pub fn with_impostors(mut self, atlas: ImpostorAtlas, distance: f32) -> InstanceBatch {
+        self.impostors = Some((atlas, distance));
+        self.use_impostors = true;
+        self
+    }
+
+    /// The program to draw with, for the lighting uniforms that are set once per frame.
+    pub fn shader<'a>(&self, assets: &'a AssetManager, pbr: bool) -> &'a Shader {
+        assets.shader(if pbr { self.pbr_shader } else { self.simple_shader })
+    }
+
+    /// The program the impostors are drawn with, if the batch has them.
+    pub fn impostor_shader<'a>(&self, assets: &'a AssetManager) -> Option<&'a Shader> {
+        self.impostors.as_ref().map(|(atlas, _)| atlas.shader(assets))
+    }
+
+    /// Cull the instances against `view_projection_matrix` and draw what is left.
+    /// `clip_plane` is used while GL_CLIP_DISTANCE0 is on. Leaves the instancing or the impostor program active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, pbr: bool, view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3, clip_plane: [f32; 4]) {
+        if self.instances.is_empty() {
+            return;
+        }
+        let planes = frustum_planes(view_projection_matrix);
+        let chunks: Vec<&Chunk> = self.chunks.iter().filter(|chunk| sphere_in_frustum(&planes, &chunk.bounds)).collect();
+        let impostor_distance = match &self.impostors {
+            Some((_, distance)) if self.use_impostors => *distance,
+            _ => f32::INFINITY,
+        };
+        let first_impostor = self.instances.len() as u32; // Where the impostors' list starts
+        let mut impostor_count = ImpostorCount::Indirect(self.impostor_command_buffer);
+
+        match self.mode {
+            InstancingMode::CpuCulled => {
+                // The sphere tests are spread over the thread pool, only the compaction is serial
+                let candidates: Vec<usize> = chunks.iter().flat_map(|chunk| chunk.first..chunk.first + chunk.count).collect();
+                let inside: Vec<bool> = candidates.par_iter()
+                    .with_min_len(1024)
+                    .map(|&i| sphere_in_frustum(&planes, &self.instances[i].bounds))
+                    .collect();
+
+                let mut commands = self.commands.clone();
+                let mut visible = vec![0u32; 2 * self.instances.len()];
+                let mut impostors = 0;
+                for (&i, _) in candidates.iter().zip(&inside).filter(|(_, &inside)| inside) {
+                    let instance = &self.instances[i];
+                    let center = glm::vec3(instance.bounds[0], instance.bounds[1], instance.bounds[2]);
+                    if glm::distance(&center, &camera_position) > impostor_distance {
+                        visible[(first_impostor + impostors) as usize] = i as u32;
+                        impostors += 1;
+                        continue;
+                    }
+                    let command = &mut commands[instance.mesh[0] as usize];
+                    visible[(command.base_instance + command.instance_count) as usize] = i as u32;
+                    command.instance_count += 1;
+                }
+                impostor_count = ImpostorCount::Known(impostors);
+                gl::BindBuffer(gl::ARRAY_BUFFER, self.visible_buffer);
+                gl::BufferSubData(gl::ARRAY_BUFFER, 0, util::byte_size_of_array(&visible), util::pointer_to_array(&visible));
+                gl::BindBuffer(gl::ARRAY_BUFFER, 0);
+
+                self.activate_draw_shader(assets, pbr, view_projection_matrix, camera_position, clip_plane);
+                gl::BindVertexArray(self.vao_id);
+                for command in commands.iter().filter(|command| command.instance_count > 0) {
+                    gfx::count_draw_call();
+                    gl::DrawElementsInstancedBaseVertexBaseInstance(
+                        gl::TRIANGLES,
+                        command.count as i32,
+                        gl::UNSIGNED_INT,
+                        (command.first_index as usize * std::mem::size_of::<u32>()) as *const std::ffi::c_void,
+                        command.instance_count as i32,
+                        command.base_vertex,
+                        command.base_instance,
+                    );
+                }
+            }
+            InstancingMode::GpuIndirect => {
+                // Start from zero instances for every mesh, the compute shader counts them up
+                gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.command_buffer);
+                gl::BufferSubData(gl::SHADER_STORAGE_BUFFER, 0, util::byte_size_of_array(&self.commands), util::pointer_to_array(&self.commands));
+                let impostor_command = [ArraysCommand { count: 4, instance_count: 0, first: 0, base_instance: 0 }];
+                gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, self.impostor_command_buffer);
+                gl::BufferSubData(gl::SHADER_STORAGE_BUFFER, 0, util::byte_size_of_array(&impostor_command), util::pointer_to_array(&impostor_command));
+                gl::BindBuffer(gl::SHADER_STORAGE_BUFFER, 0);
+
+                let cull_shader = assets.shader(self.cull_shader);
+                cull_shader.activate();
+                for (i, plane) in planes.iter().enumerate() {
+                    cull_shader.set_uniform_vec4(&format!("frustum_planes[{}]", i), &[plane.x, plane.y, plane.z, plane.w]);
+                }
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, self.instance_ssbo);
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 1, self.visible_buffer);
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 2, self.command_buffer);
+                gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 3, self.impostor_command_buffer);
+                cull_shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+                cull_shader.set_uniform_float("impostor_distance", impostor_distance);
+                cull_shader.set_uniform_uint("first_impostor", first_impostor);
+                // One dispatch per chunk in view, they all append to the same lists
+                for chunk in &chunks {
+                    cull_shader.set_uniform_uint("first_instance", chunk.first as u32);
+                    cull_shader.set_uniform_uint("instance_count", chunk.count as u32);
+                    let workgroups = (chunk.count as u32).div_ceil(WORKGROUP_SIZE);
+                    gl::DispatchCompute(workgroups, 1, 1);
+                }
+                gl::MemoryBarrier(gl::COMMAND_BARRIER_BIT | gl::VERTEX_ATTRIB_ARRAY_BARRIER_BIT | gl::SHADER_STORAGE_BARRIER_BIT);
+
+                self.activate_draw_shader(assets, pbr, view_projection_matrix, camera_position, clip_plane);
+                gl::BindVertexArray(self.vao_id);
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, self.command_buffer);
+                gfx::count_draw_call();
+                gl::MultiDrawElementsIndirect(gl::TRIANGLES, gl::UNSIGNED_INT, std::ptr::null(), self.commands.len() as i32, 0);
+                gl::BindBuffer(gl::DRAW_INDIRECT_BUFFER, 0);
+            }
+        }
+
+        gl::BindVertexArray(0);
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, 0);
+
+        if let (Some((atlas, _)), true) = (&self.impostors, impostor_distance.is_finite()) {
+            let list = ImpostorList { instance_ssbo: self.instance_ssbo, visible_buffer: self.visible_buffer, first_visible: first_impostor, count: impostor_count };
+            atlas.draw_listed(assets, list, view_projection_matrix, camera_position, clip_plane);
+        }
+    }
+
+    unsafe fn activate_draw_shader(&self, assets: &AssetManager, pbr: bool, view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3, clip_plane: [f32; 4]) {
+        let shader = self.shader(assets, pbr);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", view_projection_matrix);
+        shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+        shader.set_uniform_vec4("clip_plane", &clip_plane);
+        if pbr {
+            Material::default().bind(shader);
+        }
+        gl::BindBufferBase(gl::SHADER_STORAGE_BUFFER, 0, self.instance_ssbo);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        util::delete_vao(self.vao_id);
+        gl::DeleteBuffers(1, &self.visible_buffer);
+        gl::DeleteBuffers(1, &self.instance_ssbo);
+        gl::DeleteBuffers(1, &self.command_buffer);
+        gl::DeleteBuffers(1, &self.impostor_command_buffer);
+        if let Some((atlas, _)) = &self.impostors {
+            atlas.delete();
+        }
+    }
+}
+
+// The six planes of the view frustum (left, right, bottom, top, near, far), from the rows of the
+// view projection matrix. Normalized, with the normals pointing inwards. With reversed depth (clip
+// space z from 0 to w, see gfx::DepthMode) the far plane doesn't cut anything off, which only culls less.
+pub fn frustum_planes(view_projection_matrix: &glm::Mat4) -> [glm::Vec4; 6] {
+    let row = |i: usize| glm::vec4(view_projection_matrix[(i, 0)], view_projection_matrix[(i, 1)], view_projection_matrix[(i, 2)], view_projection_matrix[(i, 3)]);
+    let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+    let mut planes = [w + x, w - x, w + y, w - y, w + z, w - z];
+    for plane in planes.iter_mut() {
+        *plane /= glm::length(&glm::vec3(plane.x, plane.y, plane.z));
+    }
+    planes
+}
+
+pub fn sphere_in_frustum(planes: &[glm::Vec4; 6], sphere: &[f32; 4]) -> bool {
+    planes.iter().all(|plane| plane.x * sphere[0] + plane.y * sphere[1] + plane.z * sphere[2] + plane.w >= -sphere[3])
+}
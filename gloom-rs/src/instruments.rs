@@ -0,0 +1,153 @@
+extern crate nalgebra_glm as glm;
+
+use crate::atlas::{AtlasBuilder, TextureAtlas};
+use crate::hud::{Anchor, Hud, HudElement, HudElementId};
+use crate::strings::StringTable;
+
+// * Flight instruments
+// HUD widgets for the helicopter that is selected (Tab or a click): its height above the ground, its
+// speed over the ground and how fast it climbs or sinks, and an artificial horizon. They go away when
+// nothing is selected.
+//
+// The horizon is three sprites on top of each other in the lower left corner: the dial, the horizon
+// line, turned against the roll and moved down as the nose comes up, and the fixed aircraft symbol in
+// the middle. The roll and pitch are the helicopter root node's rotation (see update.rs and path.rs
+// for what sets it). The speeds come from how far the node moved since the last frame, smoothed so
+// they can be read, since nothing keeps the velocity of a node.
+
+const DIAL_SIZE        : u32 = 40;   // Cells across
+const PITCH_FULL_SCALE : f32 = std::f32::consts::FRAC_PI_4; // The horizon is furthest from the middle at this pitch
+const MAX_PITCH_SHIFT  : f32 = 0.3;  // As a fraction of the dial, so the line stays inside it
+const SPEED_SMOOTHING  : f32 = 4.0;  // Per second, higher follows changes faster
+
+// What the instruments show, of the helicopter they are bound to
+#[derive(Clone, Copy, Debug)]
+pub struct AircraftState {
+    pub index               : usize,       // Which helicopter, for the title
+    pub position            : glm::Vec3,   // In the world
+    pub rotation            : glm::Vec3,   // Of the root node: pitch, yaw and roll, in radians
+    pub height_above_ground : Option<f32>, // None past the edge of the terrain
+}
+
+pub struct FlightInstruments {
+    pub enabled   : bool,
+    readout       : HudElementId,
+    dial          : HudElementId,
+    horizon       : HudElementId,
+    aircraft      : HudElementId,
+    followed      : Option<usize>,     // The helicopter the speeds are of
+    last_position : Option<glm::Vec3>,
+    velocity      : glm::Vec3,         // Smoothed, in units per second
+}
+
+impl FlightInstruments {
+    pub fn add_sprites(atlas: &mut AtlasBuilder) {
+        // A dark disc with a ring around it and ticks every 30 degrees of roll over the top
+        atlas.add_generated("instrument_dial", 128, |u, v| {
+            let r = (u * u + v * v).sqrt();
+            let angle = u.atan2(v).to_degrees(); // From straight up
+            let tick = angle.abs() <= 60.0 && (angle / 30.0 - (angle / 30.0).round()).abs() < 0.04;
+            if (0.9..=0.97).contains(&r) || (tick && (0.78..0.9).contains(&r)) {
+                [1.0, 1.0, 1.0, 0.9]
+            } else if r < 0.9 {
+                [0.0, 0.0, 0.0, 0.35]
+            } else {
+                [0.0, 0.0, 0.0, 0.0]
+            }
+        });
+        // A line across the middle with a dark edge
+        atlas.add_generated("instrument_horizon", 128, |u, v| {
+            if u.abs() <= 0.6 && v.abs() <= 0.025 {
+                [1.0, 1.0, 1.0, 1.0]
+            } else if u.abs() <= 0.62 && v.abs() <= 0.05 {
+                [0.0, 0.0, 0.0, 0.6]
+            } else {
+                [0.0, 0.0, 0.0, 0.0]
+            }
+        });
+        // Two wings and a dot, the helicopter seen from behind
+        atlas.add_generated("instrument_aircraft", 128, |u, v| {
+            let wing = (0.18..=0.42).contains(&u.abs()) && v.abs() <= 0.03;
+            let wing_tip = (0.18..=0.21).contains(&u.abs()) && (-0.1..=0.0).contains(&v);
+            let dot = (u * u + v * v).sqrt() <= 0.05;
+            let inside = wing || wing_tip || dot;
+            [1.0, 1.0, 1.0, inside as i32 as f32]
+        });
+    }
+
+    /// Adds the widgets to `hud`, hidden until there is a helicopter to show. `atlas` has to have the
+    /// sprites from `add_sprites` in it.
+    pub fn new(hud: &mut Hud, atlas: &TextureAtlas) -> FlightInstruments {
+        let margin = (4.0, 4.0);
+        let dial_element = |name: &str| HudElement::sprite(Anchor::BottomLeft, atlas, name, (DIAL_SIZE, DIAL_SIZE))
+            .with_margin(margin)
+            .with_visible(false);
+        let dial = hud.add(dial_element("instrument_dial"));
+        let horizon = hud.add(dial_element("instrument_horizon").with_color([0.55, 0.85, 1.0, 1.0]));
+        let aircraft = hud.add(dial_element("instrument_aircraft").with_color([1.0, 0.75, 0.1, 1.0]));
+        // The readout beside the dial, its bottom line level with the bottom of the dial
+        let readout = hud.add(HudElement::text(Anchor::BottomLeft, "-")
+            .with_margin((margin.0 + DIAL_SIZE as f32 + 4.0, margin.1 + 2.0))
+            .with_visible(false));
+
+        FlightInstruments {
+            enabled: true,
+            readout,
+            dial,
+            horizon,
+            aircraft,
+            followed: None,
+            last_position: None,
+            velocity: glm::zero(),
+        }
+    }
+
+    /// Show `aircraft`, or hide the instruments with None. Once every frame.
+    pub fn update(&mut self, hud: &mut Hud, strings: &StringTable, aircraft: Option<AircraftState>, delta_time: f32) {
+        let shown = self.enabled && aircraft.is_some();
+        for element in [self.readout, self.dial, self.horizon, self.aircraft] {
+            hud.set_visible(element, shown);
+        }
+        let aircraft = match aircraft {
+            Some(aircraft) => aircraft,
+            None => {
+                self.followed = None;
+                return;
+            }
+        };
+
+        // Another helicopter, the speeds start over
+        if self.followed != Some(aircraft.index) {
+            self.followed = Some(aircraft.index);
+            self.last_position = None;
+            self.velocity = glm::zero();
+        }
+        if let (Some(last_position), true) = (self.last_position, delta_time > 0.0) {
+            let velocity = (aircraft.position - last_position) / delta_time;
+            let blend = 1.0 - (-SPEED_SMOOTHING * delta_time).exp();
+            self.velocity += (velocity - self.velocity) * blend;
+        }
+        self.last_position = Some(aircraft.position);
+        if !shown {
+            return;
+        }
+
+        let ground_speed = glm::length(&glm::vec2(self.velocity.x, self.velocity.z));
+        let altitude = aircraft.height_above_ground.map_or(strings.get("units.unknown").to_string(), |height| {
+            strings.format("units.meters", &[("value", &format!("{:.0}", height))])
+        });
+        hud.set_text(self.readout, &strings.format("instruments.readout", &[
+            ("index", &aircraft.index.to_string()),
+            ("altitude", &altitude),
+            ("speed", &format!("{:.1}", ground_speed)),
+            ("vertical_speed", &format!("{:+.1}", self.velocity.y)),
+        ]));
+
+        // The horizon turns against the roll, and moves across the dial away from where the nose points
+        let (pitch, roll) = (aircraft.rotation.x, aircraft.rotation.z);
+        let shift = (pitch / PITCH_FULL_SCALE).clamp(-1.0, 1.0) * MAX_PITCH_SHIFT * DIAL_SIZE as f32;
+        let (sin, cos) = (-roll).sin_cos();
+        hud.set_rotation(self.horizon, -roll);
+        hud.set_offset(self.horizon, (shift * sin, -shift * cos));
+    }
+}
@@ -0,0 +1,117 @@
+extern crate nalgebra_glm as glm;
+
+use crate::scene_graph::{self, NodeId, SceneNode};
+
+// * Keyframe animation
+// A Track is a list of poses (position and rotation, relative to the node's parent) at points in
+// time, eased in and out between each other. The KeyframePlayer plays tracks on nodes: every node has
+// at most one track playing, forwards or backwards. Playing a node that is already moving only turns
+// it around, so a door that is told to close halfway through opening goes back from where it is.
+
+#[derive(Clone, Copy, Debug)]
+pub struct Keyframe {
+    pub time     : f32, // Seconds from the start of the track
+    pub position : glm::Vec3,
+    pub rotation : glm::Vec3,
+}
+
+#[derive(Clone, Debug)]
+pub struct Track {
+    pub keyframes : Vec<Keyframe>, // Sorted by time
+}
+
+impl Track {
+    pub fn new() -> Track {
+        Track { keyframes: vec![] }
+    }
+
+    /// Adds a keyframe, builder style like Path::with_waypoint. They can come in any order.
+    pub fn with_keyframe(mut self, time: f32, position: glm::Vec3, rotation: glm::Vec3) -> Track {
+        let index = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+        self.keyframes.insert(index, Keyframe { time, position, rotation });
+        self
+    }
+
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |keyframe| keyframe.time)
+    }
+
+    // The pose at `time`, held at the first and last keyframes outside of the track
+    pub fn sample(&self, time: f32) -> (glm::Vec3, glm::Vec3) {
+        let next = self.keyframes.partition_point(|keyframe| keyframe.time <= time);
+        match (next.checked_sub(1).map(|previous| self.keyframes[previous]), self.keyframes.get(next).copied()) {
+            (Some(from), Some(to)) => {
+                let t = (time - from.time) / (to.time - from.time).max(1e-6);
+                let t = t * t * (3.0 - 2.0 * t); // Smoothstep, starts and stops gently
+                (glm::lerp(&from.position, &to.position, t), glm::lerp(&from.rotation, &to.rotation, t))
+            }
+            (Some(only), None) | (None, Some(only)) => (only.position, only.rotation),
+            (None, None) => (glm::zero(), glm::zero()),
+        }
+    }
+}
+
+struct Playing {
+    node_id : NodeId,
+    track   : Track,
+    time    : f32,
+    forward : bool,
+}
+
+pub struct KeyframePlayer {
+    playing : Vec<Playing>,
+}
+
+impl KeyframePlayer {
+    pub fn new() -> KeyframePlayer {
+        KeyframePlayer { playing: vec![] }
+    }
+
+    /// Play `track` on the node, towards its end if `forward` and towards its start otherwise.
+    /// A node that isn't moving starts from the other end.
+    pub fn play(&mut self, node_id: NodeId, track: &Track, forward: bool) {
+        match self.playing.iter_mut().find(|playing| playing.node_id == node_id) {
+            Some(playing) => {
+                playing.track = track.clone();
+                playing.forward = forward;
+            }
+            None => {
+                let time = if forward { 0.0 } else { track.duration() };
+                self.playing.push(Playing { node_id, track: track.clone(), time, forward });
+            }
+        }
+    }
+
+    pub fn is_playing(&self, node_id: NodeId) -> bool {
+        self.playing.iter().any(|playing| playing.node_id == node_id)
+    }
+
+    // Move every animation along and pose its node, found below `root`. Gives the nodes whose animation
+    // ended this frame, a node that is no longer in the scene graph ends right away.
+    pub fn update(&mut self, root: &mut SceneNode, delta_time: f32) -> Vec<NodeId> {
+        let mut finished = vec![];
+        for playing in &mut self.playing {
+            let duration = playing.track.duration();
+            let step = if playing.forward { delta_time } else { -delta_time };
+            playing.time = (playing.time + step).clamp(0.0, duration);
+
+            let (position, rotation) = playing.track.sample(playing.time);
+            let node = match root.find_mut(playing.node_id) {
+                Some(node) => node,
+                None => {
+                    finished.push(playing.node_id);
+                    continue;
+                }
+            };
+            node.position = scene_graph::position_from_f32(&position);
+            node.rotation = rotation;
+
+            let end = if playing.forward { duration } else { 0.0 };
+            if playing.time == end {
+                finished.push(playing.node_id);
+            }
+        }
+        self.playing.retain(|playing| !finished.contains(&playing.node_id));
+        finished
+    }
+}
@@ -0,0 +1,181 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::AssetManager;
+use crate::atlas::TextureAtlas;
+use crate::math;
+use crate::scene_graph::NodeId;
+use crate::snapshot::FrameSnapshot;
+use crate::text::{BitmapFont, PlacedGlyph, SpriteBatch, TextAlign};
+use crate::viewport::Viewport;
+
+// * World-space labels
+// Text that hangs over a node of the scene graph and follows it around, "HELICOPTER 3" with its
+// height above the ground under it. Each label is a billboard: the point it hangs at is projected
+// into every view and the text is drawn flat on the screen there, over everything, on top of the
+// finished image like the other overlays.
+//
+// The text is the same size on screen at any distance, a whole number of pixels per cell of the font
+// (see text.rs) so it stays sharp. Labels further away than their `max_distance` aren't drawn, and
+// fade out over the last fifth of it instead of popping. Labels behind the camera or off the view are
+// skipped, and the far ones are drawn first so the near ones are on top where they overlap.
+//
+// The glyphs of all the labels of a view go into one sprite batch and draw in one call, with the
+// font's sprites in the overlay atlas.
+
+const FADE_START : f32 = 0.8; // Of max_distance
+
+pub type LabelId = usize;
+
+#[derive(Clone, Debug)]
+pub struct Label {
+    pub node_id      : NodeId,    // Follows this node, a label of a node that is gone isn't drawn
+    pub offset       : glm::Vec3, // From the node's origin, in world space so "above" stays above when it tilts
+    pub color        : [f32; 4],  // Of the letters, the outline stays dark
+    pub max_distance : f32,       // From the camera, in world units
+    pub visible      : bool,
+    text             : String,    // Lines split at '\n', set through LabelRenderer::set_text
+}
+
+impl Label {
+    pub fn new(node_id: NodeId, text: &str) -> Label {
+        Label {
+            node_id,
+            offset: glm::zero(),
+            color: [1.0, 1.0, 1.0, 1.0],
+            max_distance: 400.0,
+            visible: true,
+            text: text.to_string(),
+        }
+    }
+
+    pub fn with_offset(mut self, offset: glm::Vec3) -> Label {
+        self.offset = offset;
+        self
+    }
+
+    pub fn with_color(mut self, color: [f32; 4]) -> Label {
+        self.color = color;
+        self
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+}
+
+// A label with its text laid out, and where it is this frame
+struct PlacedLabel {
+    label  : Label,
+    glyphs : Vec<PlacedGlyph>,
+    size   : (u32, u32),        // In cells
+    anchor : Option<glm::Vec3>, // World position, None when its node isn't in the scene
+}
+
+pub struct LabelRenderer {
+    pub enabled   : bool,
+    pub cell_size : u32, // Screen pixels per cell of the font
+    labels        : Vec<PlacedLabel>,
+    font          : BitmapFont,
+    batch         : SpriteBatch,
+}
+
+impl LabelRenderer {
+    /// `atlas` has to have the sprites from `BitmapFont::add_sprites` in it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, atlas: &TextureAtlas) -> LabelRenderer {
+        LabelRenderer {
+            enabled: true,
+            cell_size: 2,
+            labels: vec![],
+            font: BitmapFont::new(atlas),
+            batch: SpriteBatch::new(assets, atlas),
+        }
+    }
+
+    pub fn add(&mut self, label: Label) -> LabelId {
+        let glyphs = self.font.layout(&label.text, TextAlign::Center);
+        let size = BitmapFont::measure(&label.text);
+        self.labels.push(PlacedLabel { label, glyphs, size, anchor: None });
+        self.labels.len() - 1
+    }
+
+    pub fn label(&self, id: LabelId) -> &Label {
+        &self.labels[id].label
+    }
+
+    // Lays the text out again only if it changed, so a readout can be set every frame
+    pub fn set_text(&mut self, id: LabelId, text: &str) {
+        let placed = &mut self.labels[id];
+        if placed.label.text != text {
+            placed.label.text = text.to_string();
+            placed.glyphs = self.font.layout(text, TextAlign::Center);
+            placed.size = BitmapFont::measure(text);
+        }
+    }
+
+    pub fn set_visible(&mut self, id: LabelId, visible: bool) {
+        self.labels[id].label.visible = visible;
+    }
+
+    /// Find where the labels' nodes are in this frame's snapshot, once before the views are drawn.
+    pub fn update(&mut self, snapshot: &FrameSnapshot) {
+        for placed in &mut self.labels {
+            placed.anchor = snapshot.node(placed.label.node_id)
+                .map(|node| node.model_matrix.column(3).xyz() + placed.label.offset);
+        }
+    }
+
+    /// Draw the labels seen by a camera at `camera_position` with `view_projection_matrix`, inside `view`.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, with the framebuffer the view is in bound.
+    pub unsafe fn draw(&mut self, assets: &AssetManager, view: &Viewport, view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3) {
+        if !self.enabled || self.labels.is_empty() {
+            return;
+        }
+        let (view_width, view_height) = (view.width.max(1) as f32, view.height.max(1) as f32);
+
+        // The labels in front of the camera and close enough, with where they go on the view in pixels
+        let mut shown: Vec<(&PlacedLabel, glm::Vec2, f32)> = self.labels.iter()
+            .filter(|placed| placed.label.visible && !placed.glyphs.is_empty())
+            .filter_map(|placed| {
+                let anchor = placed.anchor?;
+                let distance = glm::distance(&anchor, &camera_position);
+                if distance > placed.label.max_distance {
+                    return None;
+                }
+                let ndc = math::project_to_ndc(view_projection_matrix, &glm::vec4(anchor.x, anchor.y, anchor.z, 1.0))?;
+                let pixel = math::ndc_to_pixel(&ndc, view_width, view_height);
+                Some((placed, pixel, distance))
+            })
+            .collect();
+        shown.sort_by(|(_, _, a), (_, _, b)| b.total_cmp(a));
+
+        let cell = self.cell_size.max(1) as f32;
+        for (placed, pixel, distance) in shown {
+            // Centered over the anchor with the bottom line on it, on whole pixels
+            let (width, height) = (placed.size.0 as f32 * cell, placed.size.1 as f32 * cell);
+            let left = (pixel.x - width / 2.0).round();
+            let top = pixel.y.round() + height;
+            if left > view_width || left + width < 0.0 || top - height > view_height || top < 0.0 {
+                continue; // Off the view
+            }
+
+            let fade_start = placed.label.max_distance * FADE_START;
+            let fade = 1.0 - ((distance - fade_start) / (placed.label.max_distance - fade_start).max(1e-3)).clamp(0.0, 1.0);
+            let color = placed.label.color;
+            if !self.batch.add_text(&placed.glyphs, left, top, cell, [color[0], color[1], color[2], color[3] * fade]) {
+                break;
+            }
+        }
+        self.batch.draw(assets, view);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.batch.delete();
+    }
+}
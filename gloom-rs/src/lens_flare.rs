@@ -0,0 +1,166 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::atlas::{AtlasBuilder, TextureAtlas};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::math;
+use crate::viewport::Viewport;
+
+// * Lens flare
+// Glare from the sun when it is on screen. The sun is projected to the screen and the depth buffer
+// around it is sampled (in the vertex shader) to see how much of it is hidden behind the terrain.
+// The flare is then a row of additive billboards along the line from the sun through the center of
+// the view, drawn on top of the finished image. The shapes are sprites in the overlay atlas (see
+// atlas.rs), so all of the elements draw with the same texture bound.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlareShape {
+    Glow, // Soft round blob
+    Ring, // Thin halo
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct FlareElement {
+    pub position : f32,      // Along the flare axis, 0 is the sun, 1 the center of the view, 2 the opposite side
+    pub size     : f32,      // Radius, as a fraction of the view height
+    pub color    : [f32; 3],
+    pub shape    : FlareShape,
+}
+
+pub struct LensFlare {
+    pub enabled   : bool,
+    pub elements  : Vec<FlareElement>,
+    shader        : ShaderHandle,
+    atlas_texture : u32,      // Owned by the atlas
+    glow_sprite   : [f32; 4], // Where the shapes are in it
+    ring_sprite   : [f32; 4],
+    empty_vao_id  : u32,
+}
+
+impl LensFlare {
+    // The shapes of the elements, brightness in the color channels
+    pub fn add_sprites(atlas: &mut AtlasBuilder) {
+        add_flare_sprite(atlas, "flare_glow", |r| (1.0 - r).max(0.0).powf(2.5));
+        add_flare_sprite(atlas, "flare_ring", |r| (1.0 - ((r - 0.85) / 0.1).powi(2)).max(0.0));
+    }
+
+    /// `atlas` has to have the sprites from `add_sprites` in it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, atlas: &TextureAtlas) -> LensFlare {
+        let shader = assets.load_shader(&["shaders/lens_flare.vert", "shaders/lens_flare.frag"]);
+
+        let mut empty_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut empty_vao_id);
+
+        let elements = vec![
+            FlareElement { position: 0.0, size: 0.35, color: [1.0, 0.9, 0.7], shape: FlareShape::Glow },
+            FlareElement { position: 0.0, size: 0.12, color: [1.0, 1.0, 1.0], shape: FlareShape::Glow },
+            FlareElement { position: 0.4, size: 0.05, color: [0.6, 0.7, 1.0], shape: FlareShape::Glow },
+            FlareElement { position: 0.7, size: 0.09, color: [0.4, 1.0, 0.5], shape: FlareShape::Ring },
+            FlareElement { position: 1.1, size: 0.03, color: [1.0, 0.6, 0.3], shape: FlareShape::Glow },
+            FlareElement { position: 1.4, size: 0.14, color: [0.5, 0.4, 1.0], shape: FlareShape::Ring },
+            FlareElement { position: 1.8, size: 0.07, color: [1.0, 0.5, 0.6], shape: FlareShape::Glow },
+            FlareElement { position: 2.1, size: 0.25, color: [0.3, 0.5, 0.9], shape: FlareShape::Ring },
+        ];
+
+        LensFlare {
+            enabled: true,
+            elements,
+            shader,
+            atlas_texture: atlas.texture.id,
+            glow_sprite: atlas.uv_rect("flare_glow"),
+            ring_sprite: atlas.uv_rect("flare_ring"),
+            empty_vao_id,
+        }
+    }
+
+    /// Draw the flare for a light traveling in `sun_direction`, seen by a camera with
+    /// `view_projection_matrix`, inside `view`. `scene` is the framebuffer the view was drawn into,
+    /// its depth texture is used for the occlusion test.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, with the window bound as the framebuffer.
+    pub unsafe fn draw(&self, assets: &AssetManager, scene: &Framebuffer, view: &Viewport, view_projection_matrix: &glm::Mat4, sun_direction: glm::Vec3) {
+        if !self.enabled {
+            return;
+        }
+
+        // Infinitely far away in the direction of the sun, so w = 0
+        let towards_sun = -sun_direction;
+        let sun_ndc = match math::project_to_ndc(view_projection_matrix, &glm::vec4(towards_sun.x, towards_sun.y, towards_sun.z, 0.0)) {
+            Some(sun_ndc) => sun_ndc,
+            None => return, // Behind the camera
+        };
+        if sun_ndc.x.abs() > 1.5 || sun_ndc.y.abs() > 1.5 {
+            return; // Too far off screen to show anything
+        }
+
+        gl::Viewport(view.x, view.y, view.width, view.height);
+        gfx::RenderState {
+            depth_test: gfx::DepthTest::Off,
+            cull: gfx::CullMode::Off,
+            blend: gfx::Blend::Additive, // The flares are opaque, so this adds their color
+            scissor: Some((view.x, view.y, view.width, view.height)),
+            ..Default::default()
+        }.apply();
+
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_vec3("sun_position", &[sun_ndc.x, sun_ndc.y, 0.0]);
+        shader.set_uniform_float("aspect_ratio", view.aspect_ratio());
+        // Where the view is inside the depth texture, as (x, y, width, height) in texture coordinates
+        shader.set_uniform_vec4("view_rect", &[
+            view.x as f32 / scene.width as f32,
+            view.y as f32 / scene.height as f32,
+            view.width as f32 / scene.width as f32,
+            view.height as f32 / scene.height as f32,
+        ]);
+        shader.set_uniform_int("depth_texture", 0);
+        shader.set_uniform_int("sprite_atlas", 1);
+        shader.set_uniform_int("reversed_z", (gfx::depth_mode() == gfx::DepthMode::Reversed) as i32);
+
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, scene.depth_texture);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, self.atlas_texture);
+        gl::BindVertexArray(self.empty_vao_id);
+
+        for element in &self.elements {
+            shader.set_uniform_vec4("sprite_rect", match element.shape {
+                FlareShape::Glow => &self.glow_sprite,
+                FlareShape::Ring => &self.ring_sprite,
+            });
+            shader.set_uniform_float("element_position", element.position);
+            shader.set_uniform_float("element_size", element.size);
+            shader.set_uniform_vec3("element_color", &element.color);
+            gfx::count_draw_call();
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        }
+
+        gl::BindVertexArray(0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        gfx::RenderState::default().apply();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteVertexArrays(1, &self.empty_vao_id);
+    }
+}
+
+// Gray sprite with the brightness given by `falloff(distance from the center)`, where the distance
+// is 0 in the middle and 1 at the edge
+fn add_flare_sprite<F: Fn(f32) -> f32>(atlas: &mut AtlasBuilder, name: &str, falloff: F) {
+    atlas.add_generated(name, 64, |u, v| {
+        let r = (u * u + v * v).sqrt();
+        let brightness = if r < 1.0 { falloff(r).clamp(0.0, 1.0) } else { 0.0 };
+        [brightness, brightness, brightness, 1.0]
+    });
+}
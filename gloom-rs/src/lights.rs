@@ -0,0 +1,88 @@
+extern crate nalgebra_glm as glm;
+
+use crate::scene_graph::SceneNode;
+
+// * Dynamic lights
+// Lights live on SceneNodes and move with them, like the searchlight under each helicopter.
+// Every frame the scene graph is walked once with the same transforms draw_scene uses, which turns
+// every node light into a world space light, and those go to the shaders through clusters.rs.
+
+// Must match MAX_LIGHTS in simple.frag and pbr.frag
+pub const MAX_LIGHTS: usize = 256;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LightKind {
+    Point,
+    // Cone around the light's direction. Full brightness inside `inner_angle`, fading out until
+    // `outer_angle` (both radians, from the center of the cone).
+    Spot { inner_angle: f32, outer_angle: f32 },
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Light {
+    pub kind      : LightKind,
+    pub color     : [f32; 3],
+    pub intensity : f32,
+    pub range     : f32,       // No light at all past this distance
+    pub direction : glm::Vec3, // Spot lights only, in the node's own space
+    pub shadows   : bool,      // Point lights only, see point_shadows.rs
+}
+
+impl Light {
+    pub fn point(color: [f32; 3], intensity: f32, range: f32) -> Light {
+        Light {
+            kind: LightKind::Point,
+            color,
+            intensity,
+            range,
+            direction: glm::vec3(0.0, -1.0, 0.0),
+            shadows: false,
+        }
+    }
+
+    pub fn spot(color: [f32; 3], intensity: f32, range: f32, direction: glm::Vec3, inner_angle: f32, outer_angle: f32) -> Light {
+        Light {
+            kind: LightKind::Spot { inner_angle, outer_angle },
+            color,
+            intensity,
+            range,
+            direction: glm::normalize(&direction),
+            shadows: false,
+        }
+    }
+
+    // Throw shadows in every direction. Only one light gets them at a time, see point_shadows.rs.
+    pub fn with_shadows(mut self) -> Light {
+        self.shadows = true;
+        self
+    }
+}
+
+// A light after the scene graph transforms have been applied
+#[derive(Clone, Copy, Debug)]
+pub struct WorldLight {
+    pub light     : Light,
+    pub position  : glm::Vec3,
+    pub direction : glm::Vec3,
+}
+
+// Walk the scene graph and gather every light, in world space
+pub fn collect_lights(node: &SceneNode, transformation_so_far: &glm::Mat4, lights: &mut Vec<WorldLight>) {
+    let transformation_matrix = transformation_so_far * node.local_transform();
+
+    if let Some(light) = node.light {
+        let position = transformation_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        let direction = transformation_matrix * glm::vec4(light.direction.x, light.direction.y, light.direction.z, 0.0);
+        lights.push(WorldLight {
+            light,
+            position: position.xyz(),
+            direction: glm::normalize(&direction.xyz()),
+        });
+    }
+
+    for &child_ptr in &node.children {
+        if let Some(child) = unsafe { child_ptr.as_ref() } {
+            collect_lights(child, &transformation_matrix, lights);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+
+use crate::error_screen;
+use crate::gfx;
+use crate::mesh::{Helicopter, ManifestEntry, Mesh, MultiPartModel};
+
+// * Background asset loading
+// Parsing the big OBJ files takes a good while, and doing it on the render thread means the window
+// shows nothing until everything is done. Instead every file is parsed on its own worker thread and
+// sent back over a channel. The render thread only does the GPU upload (which has to happen on the
+// thread that owns the GL context) and draws a loading bar in the meantime.
+// A job that panics sends back its panic message instead, so loading stops with it rather than
+// waiting for an asset that never comes.
+
+pub enum LoadedAsset {
+    Terrain(Mesh),
+    Helicopter(Box<Helicopter>),
+    Model(usize, ManifestEntry, MultiPartModel), // Index in the manifest, they finish in any order
+}
+
+pub struct BackgroundLoader {
+    sender   : Sender<Result<LoadedAsset, String>>,
+    receiver : Receiver<Result<LoadedAsset, String>>,
+    pub total    : usize, // Jobs started
+    pub received : usize, // Jobs that have sent their result back
+}
+
+impl BackgroundLoader {
+    pub fn new() -> BackgroundLoader {
+        let (sender, receiver) = channel();
+        BackgroundLoader {
+            sender,
+            receiver,
+            total: 0,
+            received: 0,
+        }
+    }
+
+    // Run a loading job on a worker thread, the result shows up in poll() when it is done
+    pub fn spawn<F>(&mut self, job: F)
+    where
+        F: FnOnce() -> LoadedAsset + Send + 'static,
+    {
+        let sender = self.sender.clone();
+        self.total += 1;
+        thread::spawn(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(job)).map_err(|payload| error_screen::panic_message(&*payload));
+            // The receiver only goes away if the render thread died, nothing to do about it then
+            let _ = sender.send(result);
+        });
+    }
+
+    // Everything that finished since last time, never blocks. Err is the message of a job that panicked.
+    pub fn poll(&mut self) -> Vec<Result<LoadedAsset, String>> {
+        let finished: Vec<_> = self.receiver.try_iter().collect();
+        self.received += finished.len();
+        finished
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.total == 0 { 1.0 } else { self.received as f32 / self.total as f32 }
+    }
+
+    pub fn done(&self) -> bool {
+        self.received >= self.total
+    }
+}
+
+// * Loading screen
+// Just a progress bar in the middle of the screen, drawn with scissored clears so it doesn't
+// need any shaders or geometry (those are what we're waiting for after all)
+pub unsafe fn draw_loading_screen(progress: f32, screen_width: u32, screen_height: u32) {
+    let bar_width  = (screen_width as f32 * 0.6) as i32;
+    let bar_height = 20;
+    let bar_x      = (screen_width as i32 - bar_width) / 2;
+    let bar_y      = (screen_height as i32 - bar_height) / 2;
+
+    gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky, same as the scene
+    gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+    // Bar background
+    gfx::set_scissor(Some((bar_x, bar_y, bar_width, bar_height)));
+    gl::ClearColor(0.15, 0.15, 0.2, 1.0);
+    gl::Clear(gl::COLOR_BUFFER_BIT);
+
+    // Filled part
+    let filled_width = (bar_width as f32 * progress.clamp(0.0, 1.0)) as i32;
+    if filled_width > 0 {
+        gfx::set_scissor(Some((bar_x, bar_y, filled_width, bar_height)));
+        gl::ClearColor(0.8, 0.8, 0.85, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT);
+    }
+
+    gfx::set_scissor(None);
+}
@@ -7,108 +7,233 @@
 #![allow(unused_unsafe)]
 #![allow(unused_variables)]
 
+// There is no browser build (see gl_caps.rs), say so instead of failing somewhere inside glutin
+#[cfg(target_arch = "wasm32")]
+compile_error!("gloom-rs has no wasm32 build yet, the renderer needs to move onto glow and winit first");
+
 extern crate nalgebra_glm as glm;
 use std::thread;
-use std::sync::{Mutex, Arc, RwLock};
-
+use std::sync::{Arc, RwLock};
+use std::sync::mpsc;
+use std::rc::Rc;
+use std::cell::{Cell, RefCell};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[macro_use]
+mod gfx; // First, so debug_scope! can be used everywhere
 mod shader;
+mod program_cache;
 mod util;
+mod math;
 mod mesh;
 mod scene_graph;
 mod toolbox;
-
-use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, ElementState::{Pressed, Released}, VirtualKeyCode::{self, *}};
+mod path;
+mod assets;
+mod loader;
+mod hot_reload;
+mod events;
+mod display;
+mod settings;
+mod minimap;
+mod camera;
+mod viewport;
+mod framebuffer;
+mod monitor;
+mod material;
+mod environment;
+mod post;
+mod lights;
+mod lens_flare;
+mod atlas;
+mod compressed_texture;
+mod sampler;
+mod ssao;
+mod time_of_day;
+mod reflection;
+mod outline;
+mod picking;
+mod debug_normals;
+mod terrain;
+mod impostor;
+mod instancing;
+mod scatter;
+mod static_batch;
+mod ring_buffer;
+mod gl_caps;
+mod quirks;
+#[macro_use]
+mod uniform_block;
+mod update;
+mod rotor;
+mod snapshot;
+mod audio;
+mod triggers;
+mod scripting;
+mod ecs;
+mod pool;
+mod bake;
+mod bvh;
+mod spatial;
+mod pivot_gizmo;
+mod keyframes;
+mod blending;
+mod ik;
+mod rotor_blur;
+mod weather;
+mod frustum_debug;
+mod shadows;
+mod point_shadows;
+mod clusters;
+mod color;
+mod probes;
+mod auto_exposure;
+mod god_rays;
+mod color_grading;
+mod taa;
+mod motion;
+mod render_graph;
+mod benchmark;
+mod spikes;
+mod text;
+mod labels;
+mod hud;
+mod instruments;
+mod app_state;
+mod menu;
+mod user_settings;
+mod strings;
+mod error_screen;
+mod comparison;
+mod celestial;
+mod shake;
+mod projectiles;
+mod trails;
+mod cloth;
+mod water;
+mod ground;
+
+use glutin::event::{Event, WindowEvent, DeviceEvent, KeyboardInput, MouseButton, VirtualKeyCode::{self, *}};
 use glutin::event_loop::ControlFlow;
 use scene_graph::SceneNode;
+use render_graph::{Pass, RenderGraph, Resource, Target};
+use events::AppEvent;
 
 
 // initial window size
 const INITIAL_SCREEN_W: u32 = 800;
 const INITIAL_SCREEN_H: u32 = 600;
 
+// model files, also watched for hot reloading
+const TERRAIN_PATH: &str = "resources/lunarsurface.obj";
+const HELICOPTER_PATH: &str = "resources/helicopter.obj";
+// Where 5 writes the terrain as it is, craters and all (see mesh::export_obj)
+const TERRAIN_EXPORT_PATH: &str = "terrain_export.obj";
+
+// Fraction of the terrain's triangles to keep when it is loaded, 1.0 keeps the OBJ as it is (see mesh::simplify)
+const TERRAIN_DETAIL: f32 = 1.0;
+// Baked into the terrain's vertex colors when it is loaded (see bake.rs): an image, by its texture
+// coordinates or from above, and ambient occlusion, which takes a few seconds on the lunar surface
+const TERRAIN_BAKE_TEXTURE: Option<&str> = None;
+const TERRAIN_BAKE_AMBIENT_OCCLUSION: bool = false;
+// Of the flat terrain mesh made when an infinite ground stands in for the terrain model, about as big as it
+const FLAT_TERRAIN_SIZE: f32 = 1760.0;
+
+fn load_terrain() -> mesh::Mesh {
+    let mut terrain = mesh::Terrain::load(TERRAIN_PATH);
+    if TERRAIN_DETAIL < 1.0 {
+        let simplified = mesh::simplify(&terrain, TERRAIN_DETAIL);
+        println!("Simplified the terrain from {} to {} triangles.", terrain.indices.len() / 3, simplified.indices.len() / 3);
+        terrain = simplified;
+    }
+    if let Some(path) = TERRAIN_BAKE_TEXTURE {
+        match image::open(path) {
+            Ok(image) => bake::bake_texture(&mut terrain, &image.to_rgba8(), bake::Projection::Texcoords),
+            Err(error) => println!("Couldn't bake {} into the terrain: {}", path, error),
+        }
+    }
+    if TERRAIN_BAKE_AMBIENT_OCCLUSION {
+        bake::bake_ambient_occlusion(&mut terrain, &bake::AmbientOcclusion::default());
+    }
+    terrain
+}
+
 // Draw Scene
 unsafe fn draw_scene(
     node: &scene_graph::SceneNode,
     view_projection_matrix: &glm::Mat4,
-    transformation_so_far: &glm::Mat4,
+    transformation_so_far: &scene_graph::WorldMatrix,
     shader: &shader::Shader,
+    transforms: &ring_buffer::TransformStream,
 ) {
-    // Translation matrix to move the object to the reference point (move to origin of rotation)
-    let translate_to_reference = glm::translation(&(-node.reference_point));
-    
-    // Regular translation for the node's position
-    let translation_matrix = glm::translation(&node.position);
-
-    // Scaling matrix
-    let scale_matrix = glm::scaling(&node.scale);
-
-    let rotation_matrix_z = glm::rotation(node.rotation.z, &glm::vec3(0.0, 0.0, 1.0)); // yaw
-    let rotation_matrix_y = glm::rotation(node.rotation.y, &glm::vec3(0.0, 1.0, 0.0)); // pitch
-    let rotation_matrix_x = glm::rotation(node.rotation.x, &glm::vec3(1.0, 0.0, 0.0)); // roll
-
-    // Intrinsic rotation order: Z (yaw), Y (pitch), X (roll)
-    let rotation_matrix = rotation_matrix_x * rotation_matrix_y * rotation_matrix_z;
+    if !node.visible {
+        return;
+    }
 
-    // Calculate the final transformation matrix:
-    let transformation_matrix = transformation_so_far
-        * translation_matrix            // Translate to the node's position
-        * glm::translation(&node.reference_point)  // Translate back after rotation
-        * rotation_matrix                // Apply rotation at the reference point
-        * translate_to_reference         // Move to reference point 
-        * scale_matrix;                  // Apply scaling               // Apply scaling
+    // Calculate the final transformation matrix, as precise as the scene is:
+    let transformation_matrix = transformation_so_far * node.precise_local_transform();
 
-    let mvp_matrix = view_projection_matrix * transformation_matrix;
+    let model_matrix: glm::Mat4 = glm::convert(transformation_matrix);
 
-    let model_matrix = transformation_matrix;
+    let mvp_matrix = transforms.model_view_projection(view_projection_matrix, &model_matrix, &transformation_matrix.column(3).xyz());
 
     // If the node has a VAO, draw it
     if node.vao_id != 0 {
-        shader.set_uniform_mat4("mvp_matrix", &mvp_matrix);
-        shader.set_uniform_mat4("model_matrix", &model_matrix);
+        let previous_model_matrix = node.previous_world_matrix.get().unwrap_or(model_matrix);
+        transforms.set_moving(shader, &mvp_matrix, &model_matrix, &previous_model_matrix);
+
+        // Only the PBR shader has materials
+        if shader.get_uniform_location("material_albedo") != -1 {
+            match &node.material {
+                Some(material) => material.bind(shader),
+                None => material::Material::default().bind(shader),
+            }
+        }
 
         
         // Draw the VAO
-        gl::BindVertexArray(node.vao_id);
-        gl::DrawElements(gl::TRIANGLES, node.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        gfx::DrawCall::indexed(node.vao_id, node.index_count).submit();
     }
 
     // Recursively draw the children
     for &child_ptr in &node.children {
         if let Some(child) = child_ptr.as_ref() {
-            draw_scene(child, view_projection_matrix, &transformation_matrix, shader);
+            draw_scene(child, view_projection_matrix, &transformation_matrix, shader, transforms);
         }
     }
 }
 
 
 fn main() {
+    // What the user changed the last time, saved on exit (see user_settings.rs). The window is made
+    // the size it was.
+    let user_settings = user_settings::UserSettings::load();
+
     // Set up the necessary objects to deal with windows and event handling
     let el = glutin::event_loop::EventLoop::new();
     let wb = glutin::window::WindowBuilder::new()
         .with_title("Gloom-rs")
-        .with_resizable(true)
-        .with_inner_size(glutin::dpi::LogicalSize::new(INITIAL_SCREEN_W, INITIAL_SCREEN_H));
+        .with_resizable(true);
+    let wb = match user_settings.window_size {
+        Some((width, height)) => wb.with_inner_size(glutin::dpi::PhysicalSize::new(width, height)),
+        None => wb.with_inner_size(glutin::dpi::LogicalSize::new(INITIAL_SCREEN_W, INITIAL_SCREEN_H)),
+    };
     let cb = glutin::ContextBuilder::new()
-        .with_vsync(true);
+        .with_vsync(user_settings.vsync.unwrap_or(true))
+        .with_srgb(true) // Linear colors go in, the window encodes them for the monitor (see color.rs)
+        .with_stencil_buffer(8); // For the selection outline
     let windowed_context = cb.build_windowed(wb, &el).unwrap();
-    // Uncomment these if you want to use the mouse for controls, but want it to be confined to the screen and/or invisible.
-    //windowed_context.window().set_cursor_grab(true).expect("failed to grab cursor");
-    //windowed_context.window().set_cursor_visible(false);
+    // The cursor is captured for mouse look with the ` key (see display::set_mouse_captured)
 
-    // Set up a shared vector for keeping track of currently pressed keys
-    let arc_pressed_keys = Arc::new(Mutex::new(Vec::<VirtualKeyCode>::with_capacity(10)));
-    // Make a reference of this vector to send to the render thread
-    let pressed_keys = Arc::clone(&arc_pressed_keys);
+    // --benchmark flies a fixed camera path and writes a performance report (see benchmark.rs)
+    let benchmark_options = benchmark::BenchmarkOptions::from_args(std::env::args().skip(1));
 
-    // Set up shared tuple for tracking mouse movement between frames
-    let arc_mouse_delta = Arc::new(Mutex::new((0f32, 0f32)));
-    // Make a reference of this tuple to send to the render thread
-    let mouse_delta = Arc::clone(&arc_mouse_delta);
+    // Set up a channel for sending window events (keys, mouse, resizes) to the render thread
+    let (event_sender, event_receiver) = mpsc::channel::<AppEvent>();
 
-    // Set up shared tuple for tracking changes to the window size
-    let arc_window_size = Arc::new(Mutex::new((INITIAL_SCREEN_W, INITIAL_SCREEN_H, false)));
-    // Make a reference of this tuple to send to the render thread
-    let window_size = Arc::clone(&arc_window_size);
+    // Set when the event loop is exiting, the render thread checks it every frame and cleans up
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let render_shutdown = Arc::clone(&shutdown);
 
     // * Camera variables used in 3D scene to move camera around
     
@@ -117,8 +242,7 @@ fn main() {
     
     let mut camera_yaw: f32 = 0.0;
     let mut camera_pitch: f32 = 0.0;
-    let mouse_sensitivity: f32 = 0.005; // Mouse sensitivity for rotation
-    let mut mouse_right_button_pressed = false;
+    let mut mouse_sensitivity: f32 = user_settings.mouse_sensitivity.unwrap_or(0.005); // Mouse sensitivity for rotation, changed in the pause menu
 
 
 
@@ -135,36 +259,205 @@ fn main() {
             c
         };
 
-        let mut window_aspect_ratio = INITIAL_SCREEN_W as f32 / INITIAL_SCREEN_H as f32;
+        // The size the window was made with, the restored one if there was one. Everything sized to the
+        // window starts out at it, the resize events take over from there.
+        let initial_size = context.window().inner_size();
+        let (initial_width, initial_height) = (initial_size.width.max(1), initial_size.height.max(1));
+        let mut window_aspect_ratio = initial_width as f32 / initial_height as f32;
+
+        // Input state built up from the events the window sends us
+        let mut input = events::InputState::new(initial_width, initial_height);
+
+        // Loading until the main loop starts, then running, paused or in the pause menu (see app_state.rs)
+        let mut app_state = app_state::AppStateMachine::new();
+
+        // Windowed, borderless or exclusive fullscreen, toggled with F11 / Alt+Enter
+        let mut window_mode = match user_settings.window_mode {
+            Some(display::WindowMode::Windowed) | None => display::WindowMode::Windowed,
+            Some(saved_mode) => saved_mode.apply(context.window()),
+        };
+        let mut mouse_capture_wanted = false;
+
+        // What can be changed while running:
+        //  - V        vsync
+        //  - L        frame cap
+        //  - F, G     fog
+        //  - P        PBR shading
+        //  - H        HDR
+        //  - Y        auto exposure, or a manual one with + and -
+        //  - O        SSAO
+        //  - Tab      select a helicopter (or a left click on it) and show its flight instruments
+        //  - N        its normals
+        //  - X        tessellated terrain
+        //  - I        boulder culling
+        //  - U        mute the sound
+        //  - B        fire flares
+        //  - C        show the pivots
+        //  - E        open and close a door (or a click on it)
+        //  - Z        speed up the rotors
+        //  - Q        change the weather
+        //  - J        shadows on and off
+        //  - `        capture the mouse for looking around
+        //  - 1        texture filtering quality
+        //  - 2        helicopter labels
+        //  - 3        the HUD
+        //  - 4        compare two looks side by side
+        //  - F3       watch the fly-cam's frustum from outside
+        //  - F4, F5   color grading, and how strong it is
+        //  - F6       temporal anti-aliasing on and off
+        //  - F7       reverse the depth
+        //  - F8       move the near plane
+        //  - F9, F10  move the far plane
+        //  - F12      impostors for far away helicopters and rocks
+        //  - Escape   pause menu
+        //  - Pause    stop the scene
+        let mut settings = settings::RendererSettings::new();
+        user_settings.apply_to(&mut settings);
+        let swap_control = display::SwapControl::load(|symbol| context.get_proc_address(symbol) as *const _);
+        let mut frame_limiter = display::FrameLimiter::new(settings.frame_cap);
 
         // Set up openGL
         unsafe {
-            gl::Enable(gl::DEPTH_TEST);
-            gl::DepthFunc(gl::LESS);
-            gl::Enable(gl::CULL_FACE);
+            gfx::RenderState::default().apply();
             gl::Disable(gl::MULTISAMPLE);
-            gl::Enable(gl::BLEND);
-            gl::BlendFunc(gl::SRC_ALPHA, gl::ONE_MINUS_SRC_ALPHA);
-            gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
-            gl::DebugMessageCallback(Some(util::debug_callback), util::null());
 
             // Print some diagnostics
             println!("{}: {}", util::get_gl_string(gl::VENDOR), util::get_gl_string(gl::RENDERER));
             println!("OpenGL\t: {}", util::get_gl_string(gl::VERSION));
             println!("GLSL\t: {}", util::get_gl_string(gl::SHADING_LANGUAGE_VERSION));
+            println!("Update\t: {} threads", rayon::current_num_threads());
+        }
+
+        // * What the driver can do, the optional code paths below are picked from this
+        let mut gl_caps = unsafe { gl_caps::GlCaps::detect() };
+        gl_caps.print();
+        if gl_caps.version < (4, 3) {
+            panic!("OpenGL 4.3 is needed for the shaders, but the driver only has {}.{}", gl_caps.version.0, gl_caps.version.1);
+        }
+
+        // GLOOM_PROFILE=es3 keeps to what OpenGL ES 3.0 / WebGL 2 can do
+        let renderer_profile = gl_caps::RendererProfile::from_env();
+        gl_caps.restrict_to(renderer_profile);
+        if renderer_profile != gl_caps::RendererProfile::Desktop {
+            println!("Renderer profile: {:?}", renderer_profile);
+        }
+        // Workarounds for the drivers that need them, GLOOM_QUIRKS=off turns them off
+        let driver_quirks = quirks::DriverQuirks::detect(&gl_caps);
+        driver_quirks.apply_to(&mut gl_caps);
+        driver_quirks.print();
+        if !gl_caps.float_render_targets {
+            settings.hdr = false;
+        }
+        if !gl_caps.clip_control {
+            settings.projection.reversed_z = false;
+        }
+        // Anisotropic filtering for the materials, as much as the driver has. 1 cycles the quality.
+        unsafe { sampler::set_quality(settings.texture_quality, gl_caps.max_anisotropy) };
+
+        // Everything is lit in linear space and the window encodes it to sRGB (see color.rs). Without
+        // an sRGB window only the tone mapping can do that.
+        if gl_caps.srgb_framebuffer {
+            unsafe { gl::Enable(gl::FRAMEBUFFER_SRGB) };
+        } else {
+            println!("Warning: the window isn't sRGB, colors are only gamma corrected with HDR on (H)");
         }
 
+        // A GL error fails the frame it happened in (see util::debug_callback). Some drivers don't have it, and GLOOM_GL_DEBUG=0 turns it off.
+        let debug_output_wanted = std::env::var("GLOOM_GL_DEBUG").map(|value| value != "0").unwrap_or(true);
+        if gl_caps.debug_output && debug_output_wanted {
+            unsafe {
+                gl::Enable(gl::DEBUG_OUTPUT_SYNCHRONOUS);
+                gl::DebugMessageCallback(Some(util::debug_callback), util::null());
+            }
+        } else {
+            println!("OpenGL debug output is off");
+        }
+        // Names for the GL objects and a group per pass for frame captures (see gfx.rs), along with the debug output
+        gfx::enable_debug_labels(gl_caps.debug_output && debug_output_wanted);
+
+        // * Asset manager, caches everything we load by path
+        let mut assets = assets::AssetManager::new();
+        assets.shader_profile = renderer_profile;
+        assets.texture_support = compressed_texture::FormatSupport::from_caps(&gl_caps);
+        // Linked programs are kept in shader_cache for the next run, GLOOM_SHADER_CACHE=off turns it off
+        assets.program_cache = unsafe { program_cache::ProgramCache::new(gl_caps.program_binaries) };
+
         // * Load, Compile and Link the shader pair
-        let shader = unsafe {
-            shader::ShaderBuilder::new()
-                .attach_file("shaders/simple.vert")
-                .attach_file("shaders/simple.frag")
-                .link()
+        let shader_handle = unsafe {
+            assets.load_shader(&["shaders/simple.vert", "shaders/simple.frag"])
         };
+        let pbr_shader_handle = unsafe {
+            assets.load_shader(&["shaders/pbr.vert", "shaders/pbr.frag"])
+        };
+        let textured_shader_handle = unsafe {
+            assets.load_shader(&["shaders/textured.vert", "shaders/textured.frag"])
+        };
+
+        // * Parse the models on worker threads and show a loading screen until they are all in
+        let mut background_loader = loader::BackgroundLoader::new();
+        // An infinite ground in the manifest stands in for the terrain model, so only a flat mesh is made
+        let manifest = mesh::read_manifest("resources/models.manifest");
+        let flat_ground_height = manifest.iter()
+            .find(|entry| matches!(entry.kind, mesh::ManifestKind::Ground { .. }))
+            .map(|entry| entry.position.y);
+        match flat_ground_height {
+            Some(height) => background_loader.spawn(move || loader::LoadedAsset::Terrain(mesh::Terrain::flat(FLAT_TERRAIN_SIZE, 64, height))),
+            None => background_loader.spawn(|| loader::LoadedAsset::Terrain(load_terrain())),
+        }
+        background_loader.spawn(|| loader::LoadedAsset::Helicopter(Box::new(mesh::Helicopter::load(HELICOPTER_PATH))));
+        // In manifest order, the models are filled in as they come back
+        let mut manifest_entries: Vec<Option<mesh::ManifestEntry>> = vec![];
+        for (index, entry) in manifest.into_iter().enumerate() {
+            // Water and the ground are made here, there is nothing to load
+            if entry.kind != mesh::ManifestKind::Model {
+                manifest_entries.push(Some(entry));
+                continue;
+            }
+            manifest_entries.push(None);
+            background_loader.spawn(move || {
+                let model = mesh::MultiPartModel::load(&entry.name, &entry.path, [1.0, 1.0, 1.0, 1.0]);
+                loader::LoadedAsset::Model(index, entry, model)
+            });
+        }
 
-        let lunar_surface = mesh::Terrain::load("resources/lunarsurface.obj");
+        let mut loaded_terrain = None;
+        let mut loaded_helicopter = None;
 
-        let helicopter = mesh::Helicopter::load("resources/helicopter.obj");
+        while !background_loader.done() {
+            // Closed before anything was uploaded, so there is nothing to clean up yet
+            if render_shutdown.load(Ordering::Relaxed) {
+                return;
+            }
+
+            for asset in background_loader.poll() {
+                match asset {
+                    Ok(loader::LoadedAsset::Terrain(terrain)) => loaded_terrain = Some(terrain),
+                    Ok(loader::LoadedAsset::Helicopter(helicopter)) => loaded_helicopter = Some(*helicopter),
+                    Ok(loader::LoadedAsset::Model(index, entry, model)) => {
+                        assets.insert_parsed_model(&entry.path, model);
+                        manifest_entries[index] = Some(entry);
+                    }
+                    Err(message) => panic!("Loading failed: {}", message),
+                }
+            }
+
+            input.drain(&event_receiver);
+            let (width, height) = input.window_size;
+            if input.resized {
+                context.resize(glutin::dpi::PhysicalSize::new(width, height));
+                unsafe { driver_quirks.after_resize() };
+                input.resized = false;
+            }
+            unsafe {
+                gfx::set_viewport(0, 0, width, height);
+                loader::draw_loading_screen(background_loader.progress(), width, height);
+            }
+            context.swap_buffers().unwrap();
+        }
+
+        let manifest_entries: Vec<mesh::ManifestEntry> = manifest_entries.into_iter().flatten().collect();
+        let lunar_surface = loaded_terrain.expect("Terrain failed to load");
+        let helicopter = loaded_helicopter.expect("Helicopter failed to load");
 
         let (vao_id_terrain, vbo_id_terrain): (u32, u32) = unsafe { 
             util::create_vao(&lunar_surface.vertices, &lunar_surface.indices, &lunar_surface.colors, &lunar_surface.normals)          
@@ -186,14 +479,61 @@ fn main() {
             util::create_vao(&helicopter.tail_rotor.vertices, &helicopter.tail_rotor.indices, &helicopter.tail_rotor.colors, &helicopter.tail_rotor.normals)          
         };
 
+        // * See-through discs shown instead of the rotor blades when they spin fast, Z cycles the rotor speed
+        let main_rotor_disc = rotor_blur::rotor_disc(&helicopter.main_rotor, 1, 48);
+        let tail_rotor_disc = rotor_blur::rotor_disc(&helicopter.tail_rotor, 0, 24);
+        let (vao_id_main_rotor_disc, _) = unsafe {
+            util::create_vao(&main_rotor_disc.vertices, &main_rotor_disc.indices, &main_rotor_disc.colors, &main_rotor_disc.normals)
+        };
+        let (vao_id_tail_rotor_disc, _) = unsafe {
+            util::create_vao(&tail_rotor_disc.vertices, &tail_rotor_disc.indices, &tail_rotor_disc.colors, &tail_rotor_disc.normals)
+        };
+        unsafe {
+            let vaos = [
+                (vao_id_terrain, "terrain"),
+                (vao_id_helicopter_body, "helicopter body"),
+                (vao_id_helicopter_door, "helicopter door"),
+                (vao_id_helicopter_main_rotor, "helicopter main rotor"),
+                (vao_id_helicopter_tail_rotor, "helicopter tail rotor"),
+                (vao_id_main_rotor_disc, "main rotor disc"),
+                (vao_id_tail_rotor_disc, "tail rotor disc"),
+            ];
+            for &(vao_id, name) in &vaos {
+                gfx::label_object(gfx::ObjectKind::VertexArray, vao_id, &format!("{} VAO", name));
+            }
+        }
+        let rotor_speed_scales = [1.0, 3.0, 8.0];
+        let mut rotor_speed_scale_index = 0;
+
+        // * PBR materials for the helicopter parts, and the environment they reflect
+        let helicopter_materials: Vec<Rc<material::Material>> = unsafe { material::load_gltf_materials("resources/helicopter_materials.gltf", &mut assets) }
+            .into_iter().map(Rc::new).collect();
+        let helicopter_material = |name: &str| helicopter_materials.iter().find(|material| material.name == name).cloned();
+        let environment_map = unsafe { environment::EnvironmentMap::night_sky(64) };
+
+        // Navigation lights, red on the left and green on the right like real aircraft, white on the tail.
+        // The tail light also lights up its surroundings a little.
+        // sphere.obj is 20 units wide with its center at (10, 0, 10), so scale it down and center it.
+        let nav_light_scale = 0.02;
+        let nav_light_center_offset = glm::vec3(10.0, 0.0, 10.0) * nav_light_scale;
+        let nav_lights = unsafe {
+            [
+                (assets.load_mesh("resources/sphere.obj", [1.0, 0.1, 0.1, 1.0]), glm::vec3(-1.3, 0.8, 0.0), Rc::new(material::Material::emissive("nav_light_red", [1.0, 0.1, 0.1], 8.0)), None),
+                (assets.load_mesh("resources/sphere.obj", [0.1, 1.0, 0.2, 1.0]), glm::vec3( 1.3, 0.8, 0.0), Rc::new(material::Material::emissive("nav_light_green", [0.1, 1.0, 0.2], 8.0)), None),
+                (assets.load_mesh("resources/sphere.obj", [1.0, 1.0, 1.0, 1.0]), glm::vec3( 0.0, 2.6, 11.6), Rc::new(material::Material::emissive("nav_light_white", [1.0, 1.0, 1.0], 6.0)), Some(lights::Light::point([1.0, 1.0, 1.0], 20.0, 15.0))),
+            ]
+        };
+
         // Create a vector to store the root nodes of the helicopters
         let mut helicopters: Vec<*mut SceneNode> = Vec::new();
+        // And the IDs of their searchlights, which sweep from side to side
+        let mut searchlights: Vec<scene_graph::NodeId> = Vec::new();
 
         // * Set up the scene graph
         let mut scene_graph = SceneNode::new();
         let mut terrain_node = SceneNode::from_vao(vao_id_terrain, lunar_surface.index_count);
 
-        scene_graph.add_child(&mut terrain_node);
+        scene_graph.add_child(&terrain_node);
 
         // Set up the root node for each helicopter
         for i in 0..5 {
@@ -203,14 +543,50 @@ fn main() {
             let mut helicopter_main_rotor_node = SceneNode::from_vao(vao_id_helicopter_main_rotor, helicopter.main_rotor.index_count);
             let mut helicopter_tail_rotor_node = SceneNode::from_vao(vao_id_helicopter_tail_rotor, helicopter.tail_rotor.index_count);
 
-            // Set the reference point for the tail rotor
-            helicopter_tail_rotor_node.reference_point = glm::vec3(0.35, 2.3, 10.4);
+            helicopter_body_node.material = helicopter_material("body");
+            helicopter_door_node.material = helicopter_material("door");
+            helicopter_main_rotor_node.material = helicopter_material("main_rotor");
+            helicopter_tail_rotor_node.material = helicopter_material("tail_rotor");
+
+            // The rotors spin about their hubs
+            helicopter_main_rotor_node.set_pivot_from_mesh(&helicopter.main_rotor);
+            helicopter_tail_rotor_node.set_pivot_from_mesh(&helicopter.tail_rotor);
 
             // Build the scene graph for each helicopter
-            helicopter_root_node.add_child(&mut helicopter_body_node);
-            helicopter_root_node.add_child(&mut helicopter_door_node);
-            helicopter_root_node.add_child(&mut helicopter_main_rotor_node);
-            helicopter_root_node.add_child(&mut helicopter_tail_rotor_node);
+            helicopter_root_node.add_child(&helicopter_body_node);
+            helicopter_root_node.add_child(&helicopter_door_node);
+            helicopter_root_node.add_child(&helicopter_main_rotor_node);
+            helicopter_root_node.add_child(&helicopter_tail_rotor_node);
+
+            let mut main_rotor_disc_node = SceneNode::from_vao(vao_id_main_rotor_disc, main_rotor_disc.index_count);
+            let mut tail_rotor_disc_node = SceneNode::from_vao(vao_id_tail_rotor_disc, tail_rotor_disc.index_count);
+            main_rotor_disc_node.material = helicopter_material("main_rotor");
+            tail_rotor_disc_node.material = helicopter_material("tail_rotor");
+            main_rotor_disc_node.visible = false;
+            tail_rotor_disc_node.visible = false;
+            helicopter_root_node.add_child(&main_rotor_disc_node);
+            helicopter_root_node.add_child(&tail_rotor_disc_node);
+
+            for (mesh_handle, position, nav_light_material, nav_light) in &nav_lights {
+                let part = &assets.mesh(*mesh_handle).parts[0];
+                let mut nav_light_node = SceneNode::from_vao(part.vao_id, part.index_count);
+                nav_light_node.position = scene_graph::position_from_f32(&(position - nav_light_center_offset));
+                nav_light_node.scale = glm::vec3(nav_light_scale, nav_light_scale, nav_light_scale);
+                nav_light_node.material = Some(nav_light_material.clone());
+                nav_light_node.light = *nav_light;
+                helicopter_root_node.add_child(&nav_light_node);
+            }
+
+            // Searchlight under the body, pointing down and a bit forward
+            let mut searchlight_node = SceneNode::new();
+            searchlight_node.position = glm::vec3(0.0, -1.6, 0.0);
+            searchlight_node.light = Some(lights::Light::spot(
+                [1.0, 0.95, 0.8], 4000.0, 250.0,
+                glm::vec3(0.0, -1.0, -0.5),
+                10.0_f32.to_radians(), 14.0_f32.to_radians(),
+            ));
+            searchlights.push(searchlight_node.id);
+            helicopter_root_node.add_child(&searchlight_node);
 
             // Push each helicopter's root node into the vector (as raw pointers)
             unsafe {
@@ -218,82 +594,1180 @@ fn main() {
             }
 
             // Add the helicopter to the scene graph
-            scene_graph.add_child(&mut helicopter_root_node);
+            scene_graph.add_child(&helicopter_root_node);
         }
+
+        // * Crane arm under helicopter 0, reaching for the middle of the landing pad (see ik.rs). The two
+        // joints are empty nodes, each with a stretched sphere hanging off it as the segment, so the
+        // joints themselves stay evenly scaled.
+        let crane_segment_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [0.9, 0.75, 0.2, 1.0]) };
+        let crane_segment_length: f32 = 1.5;
+        let mut crane_joints = vec![];
+        let mut crane_parent = helicopters[0];
+        for joint_position in [glm::vec3(0.0, -1.4, -2.0), glm::vec3(0.0, -crane_segment_length, 0.0)] {
+            let mut joint_node = SceneNode::new();
+            joint_node.position = scene_graph::position_from_f32(&joint_position);
+            let part = &assets.mesh(crane_segment_mesh).parts[0];
+            let mut segment_node = SceneNode::from_vao(part.vao_id, part.index_count);
+            // sphere.obj is 20 units wide with its center at (10, 0, 10)
+            segment_node.scale = glm::vec3(0.01, crane_segment_length / 20.0, 0.01);
+            segment_node.position = scene_graph::position_from_f32(&(glm::vec3(0.0, -crane_segment_length / 2.0, 0.0) - glm::vec3(10.0, 0.0, 10.0).component_mul(&segment_node.scale)));
+            joint_node.add_child(&segment_node);
+            crane_joints.push(joint_node.id);
+            unsafe {
+                (*crane_parent).add_child(&joint_node);
+                crane_parent = joint_node.as_mut().get_unchecked_mut();
+            }
+        }
+        // A shoulder that swings forward, back and to the sides, and an elbow that only bends
+        let mut crane_arm = ik::IkChain::new(&crane_joints, glm::vec3(0.0, -crane_segment_length, 0.0))
+            .with_limits(0, glm::vec3(-1.2, 0.0, -1.2), glm::vec3(1.2, 0.0, 1.2))
+            .with_limits(1, glm::vec3(-2.4, 0.0, 0.0), glm::vec3(2.4, 0.0, 0.0));
+
+        // * Extra models listed in the asset manifest
+        // Every part of a model gets its own node under a root node placed at the manifest position,
+        // water is drawn by itself in a pass of its own, and the ground first in every view
+        let mut named_nodes = vec![];
+        let mut water_surfaces = vec![];
+        let mut infinite_ground = None;
+        for entry in manifest_entries {
+            if let mesh::ManifestKind::Ground { checker } = entry.kind {
+                let [r, g, b, _] = entry.color;
+                let pattern = if checker { ground::GroundPattern::Checker } else { ground::GroundPattern::Regolith };
+                infinite_ground = Some(unsafe { ground::InfiniteGround::new(&mut assets, entry.position.y, [r, g, b], pattern) });
+                continue;
+            }
+            if let mesh::ManifestKind::Water { size, reflections } = entry.kind {
+                let [r, g, b, _] = entry.color;
+                water_surfaces.push(unsafe {
+                    water::WaterSurface::new(&mut assets, entry.position, size, [r, g, b], reflections && gl_caps.clip_distance, initial_width, initial_height)
+                });
+                continue;
+            }
+            let mut model_root_node = SceneNode::new();
+            model_root_node.position = scene_graph::position_from_f32(&entry.position);
+
+            let model_handle = unsafe { assets.load_mesh(&entry.path, entry.color) };
+            for part in &assets.mesh(model_handle).parts {
+                let mut part_node = SceneNode::from_vao(part.vao_id, part.index_count);
+                model_root_node.add_child(&part_node);
+            }
+
+            named_nodes.push((entry.name.clone(), model_root_node.id));
+            scene_graph.add_child(&model_root_node);
+        }
+        // The flat mesh is still there for collisions and the like, the ground is drawn in its place
+        if infinite_ground.is_some() {
+            terrain_node.visible = false;
+        }
+
+        // * Lamp at the lunar base, in front of the security monitor. It throws shadows every way (see point_shadows.rs).
+        let lamp_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [1.0, 0.9, 0.7, 1.0]) };
+        let lamp_scale = 0.03;
+        let mut lamp_node = SceneNode::new();
+        lamp_node.position = scene_graph::position_from_f32(&glm::vec3(-24.0, 8.0, 8.0));
+        lamp_node.light = Some(lights::Light::point([1.0, 0.85, 0.6], 300.0, 40.0).with_shadows());
+        let lamp_part = &assets.mesh(lamp_mesh).parts[0];
+        let mut lamp_bulb_node = SceneNode::from_vao(lamp_part.vao_id, lamp_part.index_count);
+        lamp_bulb_node.position = scene_graph::position_from_f32(&(-glm::vec3(10.0, 0.0, 10.0) * lamp_scale));
+        lamp_bulb_node.scale = glm::vec3(lamp_scale, lamp_scale, lamp_scale);
+        lamp_bulb_node.material = Some(Rc::new(material::Material::emissive("lamp", [1.0, 0.85, 0.6], 8.0)));
+        lamp_node.add_child(&lamp_bulb_node);
+        scene_graph.add_child(&lamp_node);
+        assets.print_memory_report();
+
+        // * Custom flight routes
+        // Helicopters with a route follow their path, the rest keep using simple_heading_animation
+        let mut helicopter_routes: Vec<Option<path::PathFollower>> = (0..helicopters.len()).map(|_| None).collect();
+        helicopter_routes[0] = Some(path::PathFollower::new(
+            path::Path::new(20.0, true)
+                .with_waypoint(glm::vec3(-40.0, 10.0, -40.0), None)
+                .with_waypoint(glm::vec3( 40.0, 20.0, -40.0), Some(30.0))
+                .with_waypoint(glm::vec3( 40.0, 15.0,  40.0), None)
+                .with_waypoint(glm::vec3(-40.0,  5.0,  40.0), Some(12.0))
+        ));
+
+        // * Attitude of the helicopters, blended on top of their flight
+        // Cruising they dip the nose a little more, slowed down below hover_speed they bob and sway
+        // with the nose up, crossfading between the two. The rotors are spun by HelicopterPose, the
+        // mask keeps the clips off them and their discs.
+        let hover_speed = 15.0;
+        let cruise_clip = blending::AnimationClip::new(false)
+            .with_channel(&[], keyframes::Track::new().with_keyframe(0.0, glm::zero(), glm::vec3(-0.08, 0.0, 0.0)));
+        let hover_clip = blending::AnimationClip::new(true)
+            .with_channel(&[], keyframes::Track::new()
+                .with_keyframe(0.0, glm::zero(), glm::vec3(0.06, 0.0, 0.0))
+                .with_keyframe(1.0, glm::vec3(0.0, 0.4, 0.0), glm::vec3(0.06, 0.0, 0.04))
+                .with_keyframe(2.0, glm::zero(), glm::vec3(0.06, 0.0, 0.0))
+                .with_keyframe(3.0, glm::vec3(0.0, 0.4, 0.0), glm::vec3(0.06, 0.0, -0.04))
+                .with_keyframe(4.0, glm::zero(), glm::vec3(0.06, 0.0, 0.0)));
+        let rotor_mask = blending::Mask::all().without(&[2]).without(&[3]).without(&[4]).without(&[5]);
+        let mut helicopter_blenders: Vec<blending::AnimationBlender> = helicopters.iter().map(|_| {
+            let mut blender = blending::AnimationBlender::new();
+            blender.add("cruise", cruise_clip.clone(), rotor_mask.clone());
+            blender.add("hover", hover_clip.clone(), rotor_mask.clone());
+            blender
+        }).collect();
+
+        // * Entities, for things that aren't helicopters
+        // A beacon bobbing over the landing pad, with balls circling it, lighting the pad and throwing sparks
+        let mut ecs_world = ecs::World::new();
+        let beacon_center = glm::vec3(-40.0, 8.0, 40.0);
+        let beacon_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [1.0, 0.5, 0.1, 1.0]) };
+        let (beacon_vao_id, beacon_index_count) = (assets.mesh(beacon_mesh).parts[0].vao_id, assets.mesh(beacon_mesh).parts[0].index_count);
+        let beacon_material = Rc::new(material::Material::emissive("beacon", [1.0, 0.5, 0.1], 10.0));
+        let sphere_center = glm::vec3(10.0, 0.0, 10.0); // Same sphere.obj as the navigation lights
+        let ball = |ecs_world: &mut ecs::World, parent: ecs::Entity, position: glm::Vec3, scale: f32| {
+            let ball = ecs_world.spawn();
+            ecs_world.transforms.insert(ball, ecs::Transform::at(position - sphere_center * scale).with_scale(scale).with_parent(parent));
+            ecs_world.mesh_renderers.insert(ball, ecs::MeshRenderer::new(beacon_vao_id, beacon_index_count, Some(beacon_material.clone())));
+            ball
+        };
+
+        let beacon = ecs_world.spawn();
+        ecs_world.transforms.insert(beacon, ecs::Transform::at(beacon_center));
+        ecs_world.animators.insert(beacon, ecs::Animator::new(ecs::Animation::Bob { base: beacon_center, amplitude: 0.6, frequency: 1.5 }));
+        ecs_world.lights.insert(beacon, lights::Light::point([1.0, 0.5, 0.1], 60.0, 25.0));
+        ball(&mut ecs_world, beacon, glm::zero(), 0.05);
+
+        let beacon_orbit = ecs_world.spawn();
+        ecs_world.transforms.insert(beacon_orbit, ecs::Transform::at(glm::zero()).with_parent(beacon));
+        ecs_world.animators.insert(beacon_orbit, ecs::Animator::new(ecs::Animation::Spin { speed: glm::vec3(0.0, 2.0, 0.0) }));
+        for i in 0..3 {
+            let angle = i as f32 * 2.0 * std::f32::consts::PI / 3.0;
+            ball(&mut ecs_world, beacon_orbit, glm::vec3(angle.cos(), 0.0, angle.sin()) * 1.8, 0.015);
+        }
+
+        let mut sparks = ecs::ParticleEmitter::new(beacon_vao_id, beacon_index_count, Some(beacon_material.clone()), 25.0, 1.5);
+        sparks.mesh_center = sphere_center;
+        sparks.velocity = glm::vec3(0.0, 3.0, 0.0);
+        sparks.spread = 1.5;
+        sparks.gravity = glm::vec3(0.0, -4.0, 0.0);
+        sparks.size = 0.008;
+        ecs_world.particle_emitters.insert(beacon, sparks);
+
+        // * Weather, Q cycles clear, dust storm and snow. Its dust and snow are entities too.
+        let dust_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [0.6, 0.45, 0.3, 1.0]) };
+        let snow_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [0.95, 0.95, 1.0, 1.0]) };
+        let (dust_part, snow_part) = (&assets.mesh(dust_mesh).parts[0], &assets.mesh(snow_mesh).parts[0]);
+        let mut weather = weather::Weather::new(
+            &mut ecs_world,
+            (dust_part.vao_id, dust_part.index_count, sphere_center),
+            (snow_part.vao_id, snow_part.index_count, sphere_center),
+        );
+        println!("Entities\t: {}", ecs_world.entity_count());
+
+        // * Trails off the tips of the main rotors, and behind the flares (see trails.rs)
+        let mut trail_renderer = unsafe { trails::TrailRenderer::new(&mut assets) };
+        let rotor_center = helicopter.main_rotor.center();
+        let rotor_radius = |vertex: &glm::Vec3| glm::vec2(vertex.x - rotor_center.x, vertex.z - rotor_center.z).norm();
+        let rotor_tip = helicopter.main_rotor.vertices.chunks(3)
+            .map(|v| glm::vec3(v[0], v[1], v[2]))
+            .max_by(|a, b| rotor_radius(a).partial_cmp(&rotor_radius(b)).unwrap())
+            .unwrap_or(rotor_center);
+        for &helicopter_root_node in &helicopters {
+            trail_renderer.follow(unsafe { &(&*helicopter_root_node)[2] }, rotor_tip, trails::TrailStyle::vortex());
+        }
+
+        // * Flares, B fires one from every helicopter. They light up where they land, see projectiles.rs.
+        let flare_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [1.0, 0.3, 0.2, 1.0]) };
+        let flare_part = &assets.mesh(flare_mesh).parts[0];
+        let mut flares = projectiles::ProjectileSystem::new(
+            &mut scene_graph,
+            (flare_part.vao_id, flare_part.index_count, sphere_center),
+            (beacon_vao_id, beacon_index_count, sphere_center),
+            0.015,
+        );
+
+        // * Sound, a rotor thump following every helicopter plus the wind, U mutes it
+        let mut audio = audio::AudioSystem::new();
+        for (i, &helicopter_root_node) in helicopters.iter().enumerate() {
+            let body_id = unsafe { (&*helicopter_root_node)[0].id }; // The body is the first child
+            audio.attach(body_id, audio::SoundKind::RotorThump { blades_per_second: 11.0 + i as f32 * 0.7 }, 1.0, 15.0);
+        }
+        let wind_sound = audio.play_ambient(audio::SoundKind::Wind, 0.15);
+
+        // * Gameplay triggers, the subscribers below react to what happens in the scene
+        let mut event_bus = triggers::EventBus::new();
+        let mut trigger_watcher = triggers::TriggerWatcher::new();
+        // The low, slow corner of helicopter 0's route
+        let landing_pad = trigger_watcher.add_box("landing pad", glm::vec3(-50.0, 0.0, 30.0), glm::vec3(-30.0, 12.0, 50.0));
+        // Around the security monitor
+        let lunar_base = trigger_watcher.add_sphere("lunar base", glm::vec3(-35.0, 12.0, 0.0), 30.0);
+        trigger_watcher.track_camera();
+        let helicopter_body_ids: Vec<scene_graph::NodeId> = helicopters.iter()
+            .map(|&helicopter_root_node| unsafe { (&*helicopter_root_node)[0].id })
+            .collect();
+        for &body_id in &helicopter_body_ids {
+            trigger_watcher.watch(body_id, 4.0);
+        }
+        let mut routes_finished: Vec<bool> = helicopter_routes.iter().map(|route| route.as_ref().is_some_and(|route| route.finished)).collect();
+
+        // * Helicopter doors, they pop out and slide back along the body
+        // E (the ToggleDoor action) opens or closes the door of the selected helicopter, or helicopter 0's
+        // if none is selected, and clicking a door does the same for its own helicopter. Helicopter 0 also
+        // opens its door while it is over the landing pad.
+        let mut action_map = events::ActionMap::new();
+        action_map.bind(VirtualKeyCode::E, events::Action::ToggleDoor);
+        let keyframe_player = Rc::new(RefCell::new(keyframes::KeyframePlayer::new()));
+        let door_track = keyframes::Track::new()
+            .with_keyframe(0.0, glm::zero(), glm::zero())
+            .with_keyframe(0.3, glm::vec3(0.15, 0.0, 0.0), glm::zero())
+            .with_keyframe(1.2, glm::vec3(0.15, 0.0, 1.5), glm::zero());
+        // (helicopter root ID, door ID, open)
+        let mut doors: Vec<(scene_graph::NodeId, scene_graph::NodeId, bool)> = helicopters.iter()
+            .map(|&helicopter_root_node| unsafe { ((*helicopter_root_node).id, (*helicopter_root_node).get_child(update::DOOR_CHILD).id, false) })
+            .collect();
+        let route_helicopter_body = helicopter_body_ids[0];
+        let door_player = Rc::clone(&keyframe_player);
+        event_bus.subscribe(move |event| {
+            let (helicopter, open) = match *event {
+                triggers::GameEvent::Action { action: events::Action::ToggleDoor, target } => {
+                    (target.map_or(Some(0), |target| doors.iter().position(|&(helicopter_id, _, _)| helicopter_id == target)), None)
+                }
+                triggers::GameEvent::NodeClicked { node_id } => {
+                    (doors.iter().position(|&(_, door_id, _)| door_id == node_id), None)
+                }
+                triggers::GameEvent::NodeEnteredRegion { node_id, region } if node_id == route_helicopter_body && region == landing_pad => (Some(0), Some(true)),
+                triggers::GameEvent::NodeLeftRegion { node_id, region } if node_id == route_helicopter_body && region == landing_pad => (Some(0), Some(false)),
+                _ => return,
+            };
+            if let Some(i) = helicopter {
+                let (_, door_id, is_open) = &mut doors[i];
+                let open = open.unwrap_or(!*is_open);
+                if open != *is_open {
+                    *is_open = open;
+                    door_player.borrow_mut().play(*door_id, &door_track, open);
+                    println!("Helicopter {} door: {}", i, if open { "opening" } else { "closing" });
+                }
+            }
+        });
+        event_bus.subscribe(|event| match event {
+            triggers::GameEvent::Collision { .. } | triggers::GameEvent::AnimationFinished { .. } => println!("{:?}", event),
+            _ => {}
+        });
+
+        // The wind dies down while the camera is close to the base
+        let wind_volume = Rc::new(Cell::new(0.15));
+        let wind_volume_target = Rc::clone(&wind_volume);
+        event_bus.subscribe(move |event| match *event {
+            triggers::GameEvent::CameraEnteredRegion { region } if region == lunar_base => wind_volume_target.set(0.04),
+            triggers::GameEvent::CameraLeftRegion { region } if region == lunar_base => wind_volume_target.set(0.15),
+            _ => {}
+        });
+
+        // * Camera shake, from collisions and from helicopters flying close past the fly-cam (see shake.rs)
+        let mut camera_shake = shake::CameraShake::new();
+        let collision_trauma = Rc::new(Cell::new(0.0));
+        let collision_trauma_sum = Rc::clone(&collision_trauma);
+        event_bus.subscribe(move |event| {
+            if let triggers::GameEvent::Collision { .. } = event {
+                collision_trauma_sum.set(collision_trauma_sum.get() + 0.6);
+            }
+        });
     
+        // * Split screen, F2 cycles between 1, 2 and 4 views
+        // View 0 is the fly-cam, the others chase helicopters and are updated every frame
+        let mut viewports = viewport::ViewportManager::new(
+            viewport::SplitLayout::Single,
+            vec![camera::Camera::new(glm::zero()); 4],
+        );
+
+        // * HDR target and tone mapping, toggled with H
+        let mut post_chain = unsafe { post::PostChain::new(&mut assets, initial_width, initial_height) };
+        post_chain.encode_srgb = !gl_caps.srgb_framebuffer;
+
+        // * Exposure that follows the brightness of the scene, Y switches between it and the manual one
+        let mut auto_exposure = unsafe { auto_exposure::AutoExposure::new(&mut assets) };
+
+        // * Temporal anti-aliasing, toggled with F6 (only with HDR on)
+        let mut taa = unsafe { taa::Taa::new(&mut assets, initial_width, initial_height) };
+        // * How far every pixel moved since the last frame, written with the scene for the TAA
+        let mut motion_vectors = unsafe { motion::MotionVectors::new() };
+
+        // * Ambient occlusion, toggled with O (only with HDR on)
+        let mut ssao = unsafe { ssao::Ssao::new(&mut assets, initial_width, initial_height) };
+
+        // * Ray queries against the terrain, so the camera can't fly through it
+        let before = std::time::Instant::now();
+        let mut terrain_bvh = bvh::Bvh::from_mesh(&lunar_surface);
+        println!("Terrain BVH\t: {} triangles, {} nodes, built in {:.3}ms", terrain_bvh.triangle_count(), terrain_bvh.node_count(), before.elapsed().as_micros() as f32 / 1e3);
+
+        // * Rotors that spin up and coast down, and the flight state of every helicopter (see rotor.rs)
+        // The helicopters start on the ground under where their flights start. F1 (the ToggleEngine
+        // action) lands the selected helicopter, or helicopter 0's if none is selected, or starts it again.
+        let height_above_ground = |terrain_bvh: &bvh::Bvh, position: glm::Vec3| {
+            terrain_bvh.closest_hit(&bvh::Ray::new(position, glm::vec3(0.0, -1.0, 0.0)), 1000.0).map(|hit| hit.distance)
+        };
+        // Where one touches down its exhaust carves a small crater into the terrain, and it settles into it
+        let mut deformable_terrain = terrain::Terrain::new(lunar_surface.clone(), vao_id_terrain);
+        let (crater_radius, crater_depth) = (5.0, 0.6);
+        let mut helicopter_engines = vec![rotor::Engine::new(); helicopters.len()];
+        let start_poses = update::helicopter_poses(&mut helicopter_routes, &helicopter_engines, 0.0, 0.0);
+        for (engine, pose) in helicopter_engines.iter_mut().zip(&start_poses) {
+            engine.start_on_ground(height_above_ground(&terrain_bvh, pose.position));
+        }
+        let helicopter_engines = Rc::new(RefCell::new(helicopter_engines));
+        action_map.bind(VirtualKeyCode::F1, events::Action::ToggleEngine);
+        user_settings.apply_key_bindings(&mut action_map);
+        let engine_toggles = Rc::clone(&helicopter_engines);
+        let helicopter_ids: Vec<scene_graph::NodeId> = helicopters.iter().map(|&helicopter_root_node| unsafe { (*helicopter_root_node).id }).collect();
+        let engine_helicopter_ids = helicopter_ids.clone();
+        event_bus.subscribe(move |event| {
+            if let triggers::GameEvent::Action { action: events::Action::ToggleEngine, target } = *event {
+                let helicopter = target.map_or(Some(0), |target| engine_helicopter_ids.iter().position(|&id| id == target));
+                if let Some(i) = helicopter {
+                    let mut engines = engine_toggles.borrow_mut();
+                    engines[i].toggle();
+                    println!("Helicopter {} engine: {:?}", i, engines[i].state);
+                }
+            }
+        });
+
+        // * Frozen lake in the lowest part of the terrain, R switches between planar and screen space reflections
+        let lowest_terrain_point = lunar_surface.vertices.chunks(3)
+            .min_by(|a, b| a[1].partial_cmp(&b[1]).unwrap())
+            .map(|v| glm::vec3(v[0], v[1], v[2]))
+            .unwrap_or(glm::zero());
+        let mut frozen_lake = unsafe {
+            reflection::ReflectivePlane::new(
+                &mut assets,
+                lowest_terrain_point + glm::vec3(0.0, if infinite_ground.is_some() { 0.1 } else { 8.0 }, 0.0),
+                160.0,
+                [0.75, 0.85, 0.95],
+                initial_width,
+                initial_height,
+                gl_caps.clip_distance,
+            )
+        };
+
+        // * Tessellated terrain as a GPU side alternative to the terrain mesh, toggled with X
+        let mut tessellated_terrain = if gl_caps.tessellation && infinite_ground.is_none() {
+            Some(unsafe { terrain::TessellatedTerrain::new(&mut assets, &lunar_surface, vao_id_terrain) })
+        } else {
+            None
+        };
+
+        // * Per draw transforms go through a persistently mapped ring buffer
+        let mut transforms = unsafe { ring_buffer::TransformStream::new(4096, gl_caps.buffer_storage) };
+
+        // * Impostors stand in for what is far away, F12 turns them on and off. They are drawn from
+        // storage buffers like the instance batches.
+        let mut use_impostors = true;
+        let (helicopter_impostor_distance, rock_impostor_distance) = (300.0, 150.0);
+        let helicopter_subtree_ids: Vec<Vec<scene_graph::NodeId>> = helicopters.iter()
+            .map(|&helicopter_root_node| unsafe { (*helicopter_root_node).subtree_ids() })
+            .collect();
+        let helicopter_impostors = if !gl_caps.shader_storage { None } else { Some(unsafe {
+            let mut atlas = impostor::ImpostorAtlas::new(&mut assets, 1);
+            let (mut min, mut max) = helicopter.body.bounds();
+            for part in [&helicopter.door, &helicopter.main_rotor, &helicopter.tail_rotor] {
+                let (part_min, part_max) = part.bounds();
+                min = glm::min2(&min, &part_min);
+                max = glm::max2(&max, &part_max);
+            }
+            // In the helicopter's own space, everything under its root the way it is standing now.
+            // The captures go through the transforms like the draws of a frame.
+            transforms.begin_frame();
+            atlas.capture(&assets, 0, min, max, |capture_view_projection, capture_shader| {
+                for &child in &(*helicopters[0]).children {
+                    draw_scene(&*child, capture_view_projection, &glm::identity(), capture_shader, &transforms);
+                }
+                material::Material::unbind_samplers();
+            });
+            transforms.end_frame();
+            atlas
+        }) };
+
+        // * Rocks and boulders scattered over the terrain, drawn as one instance batch culled per chunk.
+        // Small flat rocks everywhere it isn't too steep, boulders in fields on the flatter ground.
+        // I switches between culling them on the CPU and on the GPU with indirect draws.
+        let mut boulders = if !gl_caps.shader_storage { None } else { Some(unsafe {
+            let light_rock = mesh::MultiPartModel::load("boulder", "resources/sphere.obj", [0.55, 0.53, 0.5, 1.0]);
+            let dark_rock = mesh::MultiPartModel::load("boulder", "resources/sphere.obj", [0.3, 0.29, 0.28, 1.0]);
+            let (light_rock, dark_rock) = (&light_rock.parts[0].1, &dark_rock.parts[0].1);
+            let boulder_fields = scatter::DensityMap::noise(256, 12, 7);
+            let rules = vec![
+                scatter::ScatterRule::new(0, light_rock, 0.25).with_sizes(0.03, 0.1, 0.5).with_max_slope(35f32.to_radians()).with_sink(0.3),
+                scatter::ScatterRule::new(1, dark_rock, 0.15).with_sizes(0.03, 0.1, 0.5).with_max_slope(35f32.to_radians()).with_sink(0.3),
+                scatter::ScatterRule::new(0, light_rock, 0.2).with_sizes(0.12, 0.3, 0.6).with_max_slope(15f32.to_radians())
+                    .with_sink(0.2).with_density_map(boulder_fields.clone()),
+                scatter::ScatterRule::new(1, dark_rock, 0.1).with_sizes(0.12, 0.3, 0.6).with_max_slope(15f32.to_radians())
+                    .with_sink(0.2).with_density_map(boulder_fields),
+            ];
+            let field = scatter::ScatterField::new(&lunar_surface, rules, 1);
+            println!("Scattered {} rocks and boulders ({} KiB packed)", field.instance_count(), field.packed_size() / 1024);
+            let (instances, chunks) = field.instances();
+
+            let mut atlas = impostor::ImpostorAtlas::new(&mut assets, 2);
+            transforms.begin_frame();
+            for (subject, rock) in [light_rock, dark_rock].iter().enumerate() {
+                let (min, max) = rock.bounds();
+                let (rock_vao_id, _) = util::create_vao(&rock.vertices, &rock.indices, &rock.colors, &rock.normals);
+                atlas.capture(&assets, subject, min, max, |capture_view_projection, capture_shader| {
+                    transforms.set(capture_shader, capture_view_projection, &glm::identity());
+                    material::Material::default().bind(capture_shader);
+                    gfx::DrawCall::indexed(rock_vao_id, rock.index_count).submit();
+                });
+                util::delete_vao(rock_vao_id);
+            }
+            transforms.end_frame();
+
+            instancing::InstanceBatch::new(&mut assets, &[light_rock, dark_rock], &instances)
+                .with_chunks(&chunks)
+                .with_impostors(atlas, rock_impostor_distance)
+        }) };
+
+        // * Selection outline, Tab cycles the selected helicopter and left click picks what is under the cursor
+        let mut outline_renderer = unsafe { outline::OutlineRenderer::new(&mut assets) };
+        let mut selected_helicopter: Option<usize> = None;
+        let mut picking_buffer = unsafe { picking::PickingBuffer::new(&mut assets, initial_width, initial_height) };
+
+        // * Marker lights along the edge of the landing pad, red on the corners. They never move, so they
+        // are merged into one batch per color (see static_batch.rs) instead of taking a draw call each.
+        let corner_marker_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [1.0, 0.15, 0.1, 1.0]) };
+        let edge_marker_mesh = unsafe { assets.load_mesh("resources/sphere.obj", [1.0, 0.8, 0.4, 1.0]) };
+        let corner_marker_material = Rc::new(material::Material::emissive("pad_marker_corner", [1.0, 0.15, 0.1], 6.0));
+        let edge_marker_material = Rc::new(material::Material::emissive("pad_marker_edge", [1.0, 0.8, 0.4], 4.0));
+        let marker_scale = 0.015;
+        let mut static_batcher = static_batch::StaticBatcher::new();
+        let mut pad_markers_node = SceneNode::new();
+        for i in 0..16 {
+            // Four to a side, going around from the corner at (-50, 30)
+            let along = (i % 4) as f32 * 5.0;
+            let (x, z) = match i / 4 {
+                0 => (-50.0 + along, 30.0),
+                1 => (-30.0, 30.0 + along),
+                2 => (-30.0 - along, 50.0),
+                _ => (-50.0, 50.0 - along),
+            };
+            let ground = terrain_bvh.closest_hit(&bvh::Ray::new(glm::vec3(x, 200.0, z), glm::vec3(0.0, -1.0, 0.0)), 1000.0)
+                .map_or(0.0, |hit| 200.0 - hit.distance);
+            let corner = i % 4 == 0;
+            let marker_mesh = assets.mesh(if corner { corner_marker_mesh } else { edge_marker_mesh });
+            let mut marker_node = SceneNode::from_vao(marker_mesh.parts[0].vao_id, marker_mesh.parts[0].index_count);
+            marker_node.position = scene_graph::position_from_f32(&(glm::vec3(x, ground + 0.05, z) - glm::vec3(10.0, 0.0, 10.0) * marker_scale));
+            marker_node.scale = glm::vec3(marker_scale, marker_scale, marker_scale);
+            marker_node.material = Some(Rc::clone(if corner { &corner_marker_material } else { &edge_marker_material }));
+            let world_matrix = marker_node.local_transform();
+            let mut marker_part = assets.model(&marker_mesh.path).parts[0].1.clone();
+            marker_part.set_color(marker_mesh.color);
+            static_batcher.add(&mut marker_node, &marker_part, &world_matrix);
+            pad_markers_node.add_child(&marker_node);
+        }
+        scene_graph.add_child(&pad_markers_node);
+        let batched_node_count = static_batcher.node_count();
+        let static_batches = unsafe { static_batcher.build(&mut scene_graph) };
+        println!("Static batches\t: {} nodes drawn in {} draw calls", batched_node_count, static_batches.len());
+        // Where the crane arm reaches for, the ground in the middle of the landing pad
+        let crane_target = terrain_bvh.closest_hit(&bvh::Ray::new(glm::vec3(-40.0, 200.0, 40.0), glm::vec3(0.0, -1.0, 0.0)), 1000.0)
+            .map_or(glm::vec3(-40.0, 0.0, 40.0), |hit| hit.position);
+
+        // * A flag planted next to the base, its cloth blowing in the wind of the weather (see cloth.rs)
+        let flag_ground = terrain_bvh.closest_hit(&bvh::Ray::new(glm::vec3(-24.0, 200.0, 10.0), glm::vec3(0.0, -1.0, 0.0)), 1000.0)
+            .map_or(glm::vec3(-24.0, 0.0, 10.0), |hit| hit.position);
+        let flag_pole_height = 6.0;
+        let mut flag_cloth = unsafe { cloth::Cloth::flag(4.0, 2.5, 20, 12, flag_pole_height, [0.75, 0.12, 0.1, 1.0]) };
+        let flag_pole = cloth::pole_mesh(flag_pole_height + 0.2, 0.12, [0.8, 0.8, 0.8, 1.0]);
+        let (vao_id_flag_pole, _) = unsafe { util::create_vao(&flag_pole.vertices, &flag_pole.indices, &flag_pole.colors, &flag_pole.normals) };
+        let mut flag_node = SceneNode::new();
+        flag_node.position = scene_graph::position_from_f32(&flag_ground);
+        let mut flag_pole_node = SceneNode::from_vao(vao_id_flag_pole, flag_pole.index_count);
+        flag_pole_node.material = Some(Rc::new(material::Material::new("flag_pole", [0.8, 0.8, 0.8, 1.0], 0.9, 0.3)));
+        let mut flag_cloth_node = SceneNode::from_vao(flag_cloth.vao_id, flag_cloth.mesh.index_count);
+        flag_cloth_node.material = Some(Rc::new(material::Material::new("flag", [0.75, 0.12, 0.1, 1.0], 0.0, 0.85)));
+        flag_node.add_child(&flag_pole_node);
+        flag_node.add_child(&flag_cloth_node);
+        scene_graph.add_child(&flag_node);
+        picking_buffer.add_batches(&static_batches);
+
+        // * Normals of the selected node drawn as lines, N cycles off/vertex/face/both
+        let mut normal_debug = if gl_caps.geometry_shaders { Some(unsafe { debug_normals::NormalDebug::new(&mut assets) }) } else { None };
+
+        // * Every node's pivot drawn as a small set of axes, C toggles it
+        let mut pivot_gizmo = unsafe { pivot_gizmo::PivotGizmo::new(&mut assets) };
+
+        // * The fly-cam's frustum seen from a detached observer camera, F3 toggles it
+        let mut frustum_debug = unsafe { frustum_debug::FrustumDebug::new(&mut assets) };
+
+        // * Shadows from the sun, cascaded over the fly-cam's view, J toggles them
+        let mut shadows = unsafe { shadows::CascadedShadows::new(&mut assets, 2048) };
+
+        // * Shadows of the point light that asks for them, the lamp at the base. Toggled with J too.
+        let mut point_shadows = unsafe { point_shadows::PointShadows::new(&mut assets, 512) };
+
+        // * The dynamic lights, sorted into clusters of every view so each fragment only loops over the ones near it
+        let mut light_clusters = unsafe { clusters::LightClusters::new() };
+
+        // * Reflection probes for the PBR materials, over where the helicopters fly and at the landing pad
+        let mut probes = unsafe { probes::ReflectionProbes::new(&mut assets, 128, gl_caps.float_render_targets) };
+        unsafe {
+            probes.add(glm::vec3(0.0, 15.0, 0.0), 100.0);
+            probes.add(glm::vec3(-40.0, 10.0, 40.0), 35.0);
+        }
+
+        // * Day and night, the sun moves across the sky
+        let mut time_of_day = time_of_day::TimeOfDay::default_morning();
+
+        // * The stars and the Earth behind the sky, the stars turn with the sun
+        let celestial_sphere = unsafe { celestial::CelestialSphere::new(&mut assets, 4000, 11) };
+
+        // * The small textures of the overlays, packed into one atlas so each overlay binds a single texture
+        let mut overlay_sprites = atlas::AtlasBuilder::new(4);
+        lens_flare::LensFlare::add_sprites(&mut overlay_sprites);
+        minimap::Minimap::add_sprites(&mut overlay_sprites);
+        text::BitmapFont::add_sprites(&mut overlay_sprites);
+        hud::Hud::add_sprites(&mut overlay_sprites);
+        instruments::FlightInstruments::add_sprites(&mut overlay_sprites);
+        let overlay_atlas = unsafe { overlay_sprites.build(gfx::ColorSpace::Linear) };
+        println!("Overlay atlas\t: {} sprites in {}x{}", overlay_atlas.sprite_count(), overlay_atlas.texture.width, overlay_atlas.texture.height);
+
+        // * Lens flare and god rays from the sun, toggled together with K (only drawn with HDR on)
+        let mut lens_flare = unsafe { lens_flare::LensFlare::new(&mut assets, &overlay_atlas) };
+        let mut god_rays = unsafe { god_rays::GodRays::new(&mut assets, initial_width, initial_height) };
+
+        // * Minimap in the top right corner, toggled with M. The terrain on it is drawn once, up front.
+        let mut minimap = unsafe { minimap::Minimap::new(&mut assets, 1024, &overlay_atlas) };
+        unsafe { minimap.bake(&assets, &lunar_surface, vao_id_terrain) };
+
+        // * What the overlays say, in the language from the user settings (see strings.rs)
+        let mut strings = strings::StringTable::load(user_settings.language.as_deref().unwrap_or(strings::DEFAULT_LANGUAGE));
+
+        // * Labels over the helicopters with how high above the ground they are, toggled with 2
+        let mut labels = unsafe { labels::LabelRenderer::new(&mut assets, &overlay_atlas) };
+        let helicopter_labels: Vec<labels::LabelId> = helicopters.iter().enumerate()
+            .map(|(i, &helicopter_root_node)| labels.add(labels::Label::new(unsafe { (*helicopter_root_node).id },
+                    &strings.format("labels.helicopter", &[("index", &i.to_string())]))
+                .with_offset(glm::vec3(0.0, 6.0, 0.0))
+                .with_color([1.0, 0.9, 0.6, 1.0])))
+            .collect();
+
+        // * The HUD over the whole window: the frame rate, the controls and a crosshair while the mouse
+        // is captured. Toggled with 3.
+        let mut hud = unsafe { hud::Hud::new(&mut assets, &overlay_atlas) };
+        let fps_element = hud.add(hud::HudElement::text(hud::Anchor::TopLeft, strings.get("hud.frame_rate_waiting")));
+        let help_element = hud.add(hud::HudElement::text(hud::Anchor::Bottom, strings.get("hud.help"))
+            .with_color([0.8, 0.85, 0.9, 0.8]));
+        let crosshair_element = hud.add(hud::HudElement::sprite(hud::Anchor::Center, &overlay_atlas, "hud_crosshair", (8, 8))
+            .with_visible(false));
+        let mut frame_rate = hud::FrameRateCounter::new();
+
+        // * Altitude, speed and an artificial horizon of the selected helicopter, on the HUD
+        let mut flight_instruments = instruments::FlightInstruments::new(&mut hud, &overlay_atlas);
+
+        // * The scene drawn two ways on either side of a line that can be dragged, 4 picks what is compared
+        let mut comparison = comparison::Comparison::new(&mut hud);
+
+        // * The pause menu Escape opens, on the HUD too so it is added last and goes on top
+        let mut pause_menu = menu::PauseMenu::new(&mut hud, &overlay_atlas);
+
+        // * What is shown instead of the scene after a frame panicked
+        let mut error_screen = unsafe { error_screen::ErrorScreen::new(&mut assets, &overlay_atlas) };
+
+        // * Security monitor at the lunar base, showing a feed of helicopter 3 on its way in
+        let mut security_monitor = unsafe { monitor::Monitor::new(glm::vec3(-35.0, 12.0, 0.0), std::f32::consts::FRAC_PI_2, 16.0, 9.0, 512) };
+        security_monitor.camera.position = glm::vec3(-30.0, 25.0, 20.0);
+
+        // * Watch the model, texture and shader files so they can be edited while the program runs
+        let mut file_watcher = hot_reload::FileWatcher::new(std::time::Duration::from_millis(500));
+        let mut pending_reloads: Vec<String> = vec![];
+        if infinite_ground.is_none() {
+            file_watcher.watch(TERRAIN_PATH);
+        }
+        file_watcher.watch(HELICOPTER_PATH);
+        for path in assets.watched_paths() {
+            file_watcher.watch(&path);
+        }
+
+        // * Scripted behaviors, every script in resources/scripts is loaded and hot reloaded
+        // Scripts find nodes by the names given here: the manifest models, "scene", "terrain" and "helicopter0" and up
+        let mut script_host = scripting::ScriptHost::new(&mut scene_graph);
+        script_host.expose("scene", scene_graph.id);
+        script_host.expose("terrain", terrain_node.id);
+        for (i, &helicopter_root_node) in helicopters.iter().enumerate() {
+            script_host.expose(&format!("helicopter{}", i), unsafe { (*helicopter_root_node).id });
+        }
+        for (name, id) in &named_nodes {
+            script_host.expose(name, *id);
+        }
+        for path in script_host.load_directory("resources/scripts") {
+            file_watcher.watch(&path);
+        }
+
+        // * Benchmark mode, the camera follows its path and the frames are recorded until it is done.
+        // Vsync and the frame cap would only measure the monitor.
+        let mut benchmark = benchmark_options.map(|options| unsafe { benchmark::Benchmark::new(options, &gl_caps) });
+        if benchmark.is_some() {
+            if swap_control.set_vsync(false) {
+                settings.vsync = false;
+            }
+            settings.frame_cap = None;
+            frame_limiter.max_fps = None;
+        }
+
+        // * Frames that take much longer than the ones around them are written to frame_spikes.log,
+        // with what every pass took and what happened in them (see spikes.rs)
+        let mut spike_capture = spikes::SpikeCapture::new(&gl_caps);
+
         // The main rendering loop
-        let first_frame_time = std::time::Instant::now();
-        let mut previous_frame_time = first_frame_time;
+        let mut previous_frame_time = std::time::Instant::now();
+        let mut simulation_time: f32 = 0.0;
 
         // Keep track of the last time rotation was updated
         let mut last_rotation_update = 0.0;
 
-        loop {
-            // Compute time passed since the previous frame and since the start of the program
+        // * What gets drawn, captured from the scene graph once the update of a frame is done
+        let mut frame_snapshots = snapshot::SnapshotBuffer::new();
+        let mut frame_number: u64 = 0;
+
+        // The order the render graph ran the passes in last, printed when it changes
+        let mut last_pass_order: Vec<&str> = vec![];
+
+        // * Where the drawn nodes are, for culling the views and the nearby queries of the triggers and sounds
+        let mut spatial_index = spatial::SpatialIndex::new(32.0);
+        spatial_index.register_mesh(vao_id_terrain, &lunar_surface);
+        spatial_index.register_mesh(vao_id_helicopter_body, &helicopter.body);
+        spatial_index.register_mesh(vao_id_helicopter_door, &helicopter.door);
+        spatial_index.register_mesh(vao_id_helicopter_main_rotor, &helicopter.main_rotor);
+        spatial_index.register_mesh(vao_id_helicopter_tail_rotor, &helicopter.tail_rotor);
+        spatial_index.register_mesh(vao_id_main_rotor_disc, &main_rotor_disc);
+        spatial_index.register_mesh(vao_id_tail_rotor_disc, &tail_rotor_disc);
+        for batch in &static_batches {
+            spatial_index.register_mesh(batch.vao_id, &batch.mesh);
+        }
+        spatial_index.register_assets(&assets);
+
+        app_state.finish_loading();
+        while !render_shutdown.load(Ordering::Relaxed) && !input.close_requested {
+            // Compute time passed since the previous frame, and the time in the scene, which stands
+            // still while it is paused
             let now = std::time::Instant::now();
-            let elapsed = now.duration_since(first_frame_time).as_secs_f32();
-            let delta_time = now.duration_since(previous_frame_time).as_secs_f32();
+            let frame_time = now.duration_since(previous_frame_time).as_secs_f32();
             previous_frame_time = now;
+            let delta_time = if app_state.is_running() { frame_time } else { 0.0 };
+            simulation_time += delta_time;
+            let elapsed = simulation_time;
+
+            // * After a frame panicked the error screen is up instead (see error_screen.rs), until a watched
+            // file is saved again or R is pressed. The changed files are reloaded by the next frame.
+            if error_screen.is_shown() {
+                input.drain(&event_receiver);
+                let (width, height) = input.window_size;
+                if input.resized {
+                    context.resize(glutin::dpi::PhysicalSize::new(width, height));
+                    unsafe { driver_quirks.after_resize() };
+                    input.resized = false;
+                }
+                if input.was_just_pressed(VirtualKeyCode::Escape) {
+                    input.close_requested = true;
+                }
+                let changed_files = file_watcher.changed_files();
+                let retry = !changed_files.is_empty() || input.was_just_pressed(VirtualKeyCode::R);
+                pending_reloads.extend(changed_files);
+                input.end_frame();
+                if !retry {
+                    unsafe { error_screen.draw(&assets, width, height); }
+                    context.swap_buffers().unwrap();
+                    thread::sleep(std::time::Duration::from_millis(16));
+                    continue;
+                }
+                println!("Trying the frame again");
+                error_screen.clear();
+            }
 
-            // Calculate the camera direction based on the yaw and pitch
-            let camera_forward = util::calculate_direction(camera_yaw, camera_pitch);
-            let camera_right = glm::normalize(&glm::cross(&glm::vec3(0.0, 1.0, 0.0), &camera_forward));
-            let camera_up = glm::normalize(&glm::cross(&camera_forward, &camera_right));
+            // The whole frame, true when a benchmark is done. A panic in it is caught and put up on the
+            // error screen, the state is left as far as the frame got.
+            let frame = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                if let Some(benchmark) = benchmark.as_mut() {
+                    unsafe { benchmark.begin_frame(); }
+                }
 
-            // Update each helicopter's position and rotation
-            for (i, helicopter_root_node) in helicopters.iter_mut().enumerate() {
-                let heading_animation = toolbox::simple_heading_animation(elapsed + (i as f32) * 0.8); // Offset for each helicopter
+                // * Hot reload changed model, texture and shader files, and the ones that changed while
+                // the error screen was up. The data is put into the same VAOs, only the index counts of
+                // the nodes need updating.
+                unsafe { spike_capture.mark("hot reload"); }
+                let changed_files: Vec<String> = pending_reloads.drain(..).chain(file_watcher.changed_files()).collect();
+                for path in changed_files {
+                    println!("{} changed on disk, reloading", path);
+                    gfx::note_event(format!("{} reloaded", path));
+                    let updated_parts: Vec<(u32, i32)> = unsafe {
+                        if path == TERRAIN_PATH {
+                            let terrain = load_terrain();
+                            terrain_bvh = bvh::Bvh::from_mesh(&terrain);
+                            spatial_index.register_mesh(vao_id_terrain, &terrain);
+                            util::refill_vao(vao_id_terrain, &terrain.vertices, &terrain.indices, &terrain.colors, &terrain.normals);
+                            if let Some(tessellated_terrain) = tessellated_terrain.as_mut() {
+                                tessellated_terrain.bake(&assets, &terrain, vao_id_terrain);
+                            }
+                            minimap.bake(&assets, &terrain, vao_id_terrain);
+                            let index_count = terrain.index_count;
+                            deformable_terrain = terrain::Terrain::new(terrain, vao_id_terrain);
+                            vec![(vao_id_terrain, index_count)]
+                        } else if path == HELICOPTER_PATH {
+                            let helicopter = mesh::Helicopter::load(HELICOPTER_PATH);
+                            let main_rotor_disc = rotor_blur::rotor_disc(&helicopter.main_rotor, 1, 48);
+                            let tail_rotor_disc = rotor_blur::rotor_disc(&helicopter.tail_rotor, 0, 24);
+                            let parts = [
+                                (vao_id_helicopter_body, &helicopter.body),
+                                (vao_id_helicopter_door, &helicopter.door),
+                                (vao_id_helicopter_main_rotor, &helicopter.main_rotor),
+                                (vao_id_helicopter_tail_rotor, &helicopter.tail_rotor),
+                                (vao_id_main_rotor_disc, &main_rotor_disc),
+                                (vao_id_tail_rotor_disc, &tail_rotor_disc),
+                            ];
+                            parts.iter().map(|&(vao_id, part)| {
+                                util::refill_vao(vao_id, &part.vertices, &part.indices, &part.colors, &part.normals);
+                                spatial_index.register_mesh(vao_id, part);
+                                (vao_id, part.index_count)
+                            }).collect()
+                        } else if script_host.is_loaded(&path) {
+                            script_host.load(&path);
+                            vec![]
+                        } else {
+                            let updated_parts = assets.reload(&path);
+                            spatial_index.register_assets(&assets);
+                            updated_parts
+                        }
+                    };
 
-                // Dereference the pointer to access the fields
-                unsafe {
-                    // Dereference twice to get access to the fields
-                    (*(*helicopter_root_node)).position = glm::vec3(heading_animation.x, 0.0, heading_animation.z);
-                    (*(*helicopter_root_node)).rotation.x = heading_animation.pitch;
-                    (*(*helicopter_root_node)).rotation.y = heading_animation.yaw;
-                    (*(*helicopter_root_node)).rotation.z = heading_animation.roll;
+                    for (vao_id, index_count) in updated_parts {
+                        scene_graph.update_index_count(vao_id, index_count);
+                    }
+                }
+
+                unsafe { spike_capture.mark("update"); }
+                time_of_day.update(delta_time);
+
+                // Calculate the camera direction based on the yaw and pitch
+                let (camera_forward, camera_right, camera_up) = math::camera_basis(camera_yaw, camera_pitch);
+
+                // Update each helicopter's position and rotation
+                // The poses are worked out from plain data first, then written into the scene graph here
+                // The engines go first, the rotors spin and the helicopters only fly while theirs is Flying
+                let rotor_speed_scale = rotor_speed_scales[rotor_speed_scale_index];
+                let mut engines = helicopter_engines.borrow_mut();
+                let mut terrain_deformed = false;
+                for (i, engine) in engines.iter_mut().enumerate() {
+                    let position = scene_graph.find(helicopter_ids[i], &glm::identity())
+                        .map_or(glm::zero(), |(helicopter_root_node, _)| scene_graph::position_to_f32(&helicopter_root_node.position));
+                    if let Some(state) = engine.update(delta_time, rotor_speed_scale, height_above_ground(&terrain_bvh, position)) {
+                        println!("Helicopter {} engine: {:?}", i, state);
+                        if state == rotor::FlightState::Parked && unsafe { deformable_terrain.deform(position, crater_radius, -crater_depth) } {
+                            engine.height_offset -= crater_depth;
+                            terrain_deformed = true;
+                        }
+                    }
+                }
+                // Everything made from the terrain mesh, like after a hot reload
+                if terrain_deformed {
+                    terrain_bvh = bvh::Bvh::from_mesh(&deformable_terrain.mesh);
+                    spatial_index.register_mesh(vao_id_terrain, &deformable_terrain.mesh);
+                    unsafe {
+                        if let Some(tessellated_terrain) = tessellated_terrain.as_mut() {
+                            tessellated_terrain.bake(&assets, &deformable_terrain.mesh, vao_id_terrain);
+                        }
+                        minimap.bake(&assets, &deformable_terrain.mesh, vao_id_terrain);
+                    }
+                }
+                let helicopter_poses = update::helicopter_poses(&mut helicopter_routes, &engines, elapsed, delta_time);
+                for (i, pose) in helicopter_poses.iter().enumerate() {
+                    if let Some(helicopter_root_node) = scene_graph.find_mut(helicopter_ids[i]) {
+                        pose.apply_to(helicopter_root_node, searchlights[i]);
+                        rotor_blur::blur_rotor(helicopter_root_node, update::MAIN_ROTOR_CHILD, update::MAIN_ROTOR_DISC_CHILD, engines[i].main_rotor.speed);
+                        rotor_blur::blur_rotor(helicopter_root_node, update::TAIL_ROTOR_CHILD, update::TAIL_ROTOR_DISC_CHILD, engines[i].tail_rotor.speed);
+                    }
+                }
+                // Only flying helicopters lean into cruising or hover, on the ground they sit still
+                for (i, blender) in helicopter_blenders.iter_mut().enumerate() {
+                    let hovering = helicopter_routes[i].as_ref().is_some_and(|route| route.speed() < hover_speed);
+                    let layer = match engines[i].state {
+                        rotor::FlightState::Flying => Some(if hovering { "hover" } else { "cruise" }),
+                        _ => None,
+                    };
+                    if blender.leading() != layer {
+                        match layer {
+                            Some(layer) => blender.crossfade(layer, 1.5),
+                            None => {
+                                blender.fade("cruise", 0.0, 1.5);
+                                blender.fade("hover", 0.0, 1.5);
+                            }
+                        }
+                    }
+                    if let Some(helicopter_root_node) = scene_graph.find_mut(helicopter_ids[i]) {
+                        blender.update(helicopter_root_node, delta_time);
+                    }
+                }
+                drop(engines);
+                // After the helicopter has moved, so the arm reaches from where it is this frame
+                crane_arm.solve(&mut scene_graph, crane_target);
+                for (i, route) in helicopter_routes.iter().enumerate() {
+                    let finished = route.as_ref().is_some_and(|route| route.finished);
+                    if finished && !routes_finished[i] {
+                        event_bus.publish(triggers::GameEvent::AnimationFinished { node_id: helicopter_ids[i] });
+                    }
+                    routes_finished[i] = finished;
+                }
+                for node_id in keyframe_player.borrow_mut().update(&mut scene_graph, delta_time) {
+                    event_bus.publish(triggers::GameEvent::AnimationFinished { node_id });
+                }
 
-                    // Update the rotors
-                    let helicopter_main_rotor_node = (*(*helicopter_root_node)).get_child(2); // Assuming rotor is the 3rd child
-                    let helicopter_tail_rotor_node = (*(*helicopter_root_node)).get_child(3); // Assuming tail rotor is the 4th child
 
-                    // Update the rotation of the helicopter's rotors based on the elapsed time
-                    (*helicopter_main_rotor_node).rotation.y = elapsed * 5.0; // Main rotor spinning continuously
-                    (*helicopter_tail_rotor_node).rotation.x = elapsed * 8.0; // Tail rotor spinning continuously
+                // Apply every event the window sent since last frame
+                input.drain(&event_receiver);
+
+                // * Escape opens the pause menu, which takes all of the input while it is open. Pause stops
+                // the scene without it, and so does the window losing the focus (not while benchmarking).
+                if input.focus_changed && benchmark.is_none() {
+                    app_state.focus_changed(input.focused);
+                }
+                if input.was_just_pressed(VirtualKeyCode::Escape) && app_state.state() != app_state::AppState::Menu {
+                    app_state.toggle_menu();
+                    pause_menu.reset();
+                    input.consume_frame_input();
+                    input.focus_changed = true; // Lets go of the mouse for the menu
+                } else if input.was_just_pressed(VirtualKeyCode::Pause) {
+                    app_state.toggle_pause();
+                }
+                let menu_values = menu::MenuValues { vsync: settings.vsync, mouse_sensitivity };
+                match pause_menu.update(&mut hud, &input, app_state.state(), &menu_values, &strings) {
+                    Some(menu::MenuCommand::Resume) => {
+                        app_state.toggle_menu();
+                        input.focus_changed = true;
+                    }
+                    Some(menu::MenuCommand::Quit) => input.close_requested = true,
+                    Some(menu::MenuCommand::SetVsync(vsync)) => {
+                        if swap_control.set_vsync(vsync) {
+                            settings.vsync = vsync;
+                            println!("Vsync: {}", if settings.vsync { "on" } else { "off" });
+                        } else {
+                            println!("Vsync can't be changed on this driver");
+                        }
+                    }
+                    Some(menu::MenuCommand::SetMouseSensitivity(sensitivity)) => mouse_sensitivity = sensitivity,
+                    // The readouts are set again every frame, only the help text has to be set here
+                    Some(menu::MenuCommand::NextLanguage) => {
+                        strings.next_language();
+                        hud.set_text(help_element, strings.get("hud.help"));
+                    }
+                    None => {}
+                }
+                let in_menu = app_state.state() == app_state::AppState::Menu;
+                if in_menu {
+                    input.consume_frame_input();
                 }
-            }
 
+                for &key in &input.just_pressed_keys {
+                    event_bus.publish(triggers::GameEvent::KeyAction(key));
+                }
+                for action in action_map.just_triggered(&input) {
+                    let target = selected_helicopter.map(|i| helicopter_ids[i]);
+                    event_bus.publish(triggers::GameEvent::Action { action, target });
+                }
 
-            // Handle resize events
-            if let Ok(mut new_size) = window_size.lock() {
-                if new_size.2 {
-                    context.resize(glutin::dpi::PhysicalSize::new(new_size.0, new_size.1));
-                    // ! window_aspect_ratio = new_size.0 as f32 / new_size.1 as f32;
-                    (*new_size).2 = false;
-                    println!("Window was resized to {}x{}", new_size.0, new_size.1);
-                    unsafe { gl::Viewport(0, 0, new_size.0 as i32, new_size.1 as i32); }
+                // Handle fullscreen toggling, the window sends a resize event afterwards
+                if input.toggle_fullscreen {
+                    window_mode = window_mode.next().apply(context.window());
+                    input.toggle_fullscreen = false;
+                    println!("Window mode: {:?}", window_mode);
+                }
+
+                // Mouse look without holding the right button, ` captures and releases the cursor. It is let
+                // go while the window is out of focus and captured again when it comes back.
+                if input.was_just_pressed(VirtualKeyCode::Grave) {
+                    mouse_capture_wanted = !mouse_capture_wanted;
+                    input.focus_changed = true;
+                    println!("Mouse look: {}", if mouse_capture_wanted { "captured" } else { "released" });
+                }
+                if input.focus_changed {
+                    let capture = mouse_capture_wanted && input.focused && !in_menu;
+                    input.mouse_captured = display::set_mouse_captured(context.window(), capture) && capture;
+                    input.focus_changed = false;
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::F2) {
+                    viewports.layout = viewports.layout.next();
+                    println!("Split screen: {:?}", viewports.layout);
+                }
+                if input.was_just_pressed(VirtualKeyCode::F3) {
+                    frustum_debug.toggle_observer(&viewports.cameras[0]);
+                    println!("Frustum observer: {}", if frustum_debug.observer.is_some() { "on" } else { "off" });
+                }
+                // Color grading table (F4) and how much of it is applied (F5)
+                if input.was_just_pressed(VirtualKeyCode::F4) {
+                    post_chain.color_grading.next();
+                    println!("Color grading: {}", post_chain.color_grading.current_name());
+                }
+                if input.was_just_pressed(VirtualKeyCode::F5) {
+                    let grading = &mut post_chain.color_grading;
+                    grading.intensity = if grading.intensity >= 1.0 { 0.25 } else { grading.intensity + 0.25 };
+                    println!("Color grading intensity: {:.2}", grading.intensity);
+                }
+                if input.was_just_pressed(VirtualKeyCode::F6) {
+                    taa.enabled = !taa.enabled;
+                    taa.reset();
+                    println!("TAA: {}", if taa.enabled { "on" } else { "off" });
+                }
+                // Reversed depth (F7), near plane (F8) and far plane closer (F9) or further out (F10)
+                if input.was_just_pressed(VirtualKeyCode::F7) && gl_caps.clip_control {
+                    settings.projection.reversed_z = !settings.projection.reversed_z;
+                    taa.reset();
+                    println!("Reversed depth: {}", if settings.projection.reversed_z { "on" } else { "off" });
+                }
+                if input.was_just_pressed(VirtualKeyCode::F8) {
+                    settings.projection.next_near_plane();
+                    println!("Near plane: {}", settings.projection.near);
+                }
+                if input.was_just_pressed(VirtualKeyCode::F9) {
+                    settings.projection.scale_far_plane(0.5);
+                    println!("Far plane: {}", settings.projection.far);
+                }
+                if input.was_just_pressed(VirtualKeyCode::F10) {
+                    settings.projection.scale_far_plane(2.0);
+                    println!("Far plane: {}", settings.projection.far);
+                }
+                if input.was_just_pressed(VirtualKeyCode::Key1) {
+                    settings.texture_quality = settings.texture_quality.next();
+                    unsafe { sampler::set_quality(settings.texture_quality, gl_caps.max_anisotropy) };
+                    println!("Texture filtering: {:?}", settings.texture_quality);
+                }
+                if input.was_just_pressed(VirtualKeyCode::J) {
+                    shadows.enabled = !shadows.enabled;
+                    point_shadows.enabled = shadows.enabled;
+                    println!("Shadows: {}", if shadows.enabled { "on" } else { "off" });
+                }
+                if input.was_just_pressed(VirtualKeyCode::M) {
+                    minimap.enabled = !minimap.enabled;
+                }
+                if input.was_just_pressed(VirtualKeyCode::Key2) {
+                    labels.enabled = !labels.enabled;
+                    println!("Helicopter labels: {}", if labels.enabled { "on" } else { "off" });
+                }
+                if input.was_just_pressed(VirtualKeyCode::Key3) {
+                    hud.enabled = !hud.enabled;
+                }
+                // The shadow maps are drawn for the side that has them even if J turned them off
+                if input.was_just_pressed(VirtualKeyCode::Key4) {
+                    comparison.mode = comparison.mode.next();
+                    if comparison.mode == comparison::ComparisonMode::Shadows {
+                        shadows.enabled = true;
+                        point_shadows.enabled = true;
+                    }
+                    println!("A/B comparison: {:?}", comparison.mode);
+                }
+                if input.was_just_pressed(VirtualKeyCode::Key5) {
+                    match mesh::export_obj(TERRAIN_EXPORT_PATH, &deformable_terrain.mesh) {
+                        Ok(()) => println!("Terrain written to {}", TERRAIN_EXPORT_PATH),
+                        Err(error) => println!("Couldn't write the terrain to {}: {}", TERRAIN_EXPORT_PATH, error),
+                    }
+                }
+                let current_look = comparison::Look { pbr: settings.pbr, shadows: shadows.enabled, fog: settings.fog.enabled };
+                comparison.update(&mut hud, &mut input, &strings, current_look);
+                if input.was_just_pressed(VirtualKeyCode::K) {
+                    lens_flare.enabled = !lens_flare.enabled;
+                    god_rays.enabled = lens_flare.enabled;
+                }
+                if input.was_just_pressed(VirtualKeyCode::U) {
+                    audio.muted = !audio.muted;
+                    println!("Sound: {}", if audio.muted { "muted" } else { "on" });
+                }
+
+                // Flat vertex color shading or PBR materials
+                if input.was_just_pressed(VirtualKeyCode::P) {
+                    settings.pbr = !settings.pbr;
+                    println!("Shading: {}", if settings.pbr { "PBR" } else { "flat" });
+                }
+
+                // HDR on/off (H), auto exposure on/off (Y) and manual exposure (+/-, turns the auto exposure off)
+                if input.was_just_pressed(VirtualKeyCode::H) && gl_caps.float_render_targets {
+                    settings.hdr = !settings.hdr;
+                    auto_exposure.reset();
+                    taa.reset();
+                    println!("HDR: {}", if settings.hdr { "on" } else { "off" });
+                }
+                if input.was_just_pressed(VirtualKeyCode::Y) {
+                    settings.auto_exposure = !settings.auto_exposure;
+                    auto_exposure.reset();
+                    println!("Auto exposure: {}", if settings.auto_exposure { "on" } else { "off" });
+                }
+                if input.was_just_pressed(VirtualKeyCode::Equals) || input.was_just_pressed(VirtualKeyCode::NumpadAdd) {
+                    settings.auto_exposure = false;
+                    settings.exposure *= 1.25;
+                    println!("Exposure: {:.2}", settings.exposure);
+                }
+                if input.was_just_pressed(VirtualKeyCode::Minus) || input.was_just_pressed(VirtualKeyCode::NumpadSubtract) {
+                    settings.auto_exposure = false;
+                    settings.exposure /= 1.25;
+                    println!("Exposure: {:.2}", settings.exposure);
+                }
+
+                // Time of day: pause (T), slower/faster ([ and ]), one hour back/forward (, and .)
+                if input.was_just_pressed(VirtualKeyCode::T) {
+                    time_of_day.paused = !time_of_day.paused;
+                }
+                if input.was_just_pressed(VirtualKeyCode::LBracket) {
+                    time_of_day.speed /= 2.0;
+                }
+                if input.was_just_pressed(VirtualKeyCode::RBracket) {
+                    time_of_day.speed *= 2.0;
+                }
+                if input.was_just_pressed(VirtualKeyCode::Comma) {
+                    time_of_day.set_hours(time_of_day.hours - 1.0);
+                }
+                if input.was_just_pressed(VirtualKeyCode::Period) {
+                    time_of_day.set_hours(time_of_day.hours + 1.0);
+                }
+                if [VirtualKeyCode::T, VirtualKeyCode::LBracket, VirtualKeyCode::RBracket, VirtualKeyCode::Comma, VirtualKeyCode::Period]
+                    .iter().any(|&key| input.was_just_pressed(key)) {
+                    println!("Time of day: {:02}:{:02}, {} hours per second{}",
+                        time_of_day.hours as u32, (time_of_day.hours.fract() * 60.0) as u32,
+                        time_of_day.speed, if time_of_day.paused { " (paused)" } else { "" });
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::Tab) {
+                    selected_helicopter = match selected_helicopter {
+                        None => Some(0),
+                        Some(i) if i + 1 < helicopters.len() => Some(i + 1),
+                        Some(_) => None,
+                    };
+                    match selected_helicopter {
+                        Some(i) => outline_renderer.set_highlighted(helicopter_ids[i], [1.0, 0.75, 0.1, 1.0]),
+                        None => outline_renderer.clear_highlighted(),
+                    }
+                }
+
+                if let (true, Some(tessellated_terrain)) = (input.was_just_pressed(VirtualKeyCode::X), tessellated_terrain.as_mut()) {
+                    tessellated_terrain.enabled = !tessellated_terrain.enabled;
+                    terrain_node.visible = !tessellated_terrain.enabled;
+                    println!("Terrain: {}", if tessellated_terrain.enabled { "tessellated" } else { "mesh" });
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::B) {
+                    for &helicopter_id in &helicopter_ids {
+                        if let Some((helicopter_root_node, _)) = scene_graph.find(helicopter_id, &glm::identity()) {
+                            flares.launch_from(helicopter_root_node, &mut trail_renderer, elapsed);
+                        }
+                    }
+                    println!("Flares: {} burning, {} nodes made, {} of {} fired hit the ground", flares.live_count(), flares.allocated_count(), flares.hits, flares.fired);
+                }
+                flares.update(&mut ecs_world, &mut trail_renderer, &terrain_bvh, elapsed, delta_time);
+
+                if let (true, Some(boulders)) = (input.was_just_pressed(VirtualKeyCode::I), boulders.as_mut()) {
+                    if gl_caps.compute_shaders && gl_caps.multi_draw_indirect {
+                        boulders.mode = boulders.mode.next();
+                        println!("Instancing: {:?}", boulders.mode);
+                    }
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::F12) {
+                    use_impostors = !use_impostors;
+                    if let Some(boulders) = boulders.as_mut() {
+                        boulders.use_impostors = use_impostors;
+                    }
+                    println!("Impostors: {}", if use_impostors { "on" } else { "off" });
+                }
+
+                if let (true, Some(normal_debug)) = (input.was_just_pressed(VirtualKeyCode::N), normal_debug.as_mut()) {
+                    normal_debug.mode = normal_debug.mode.next();
+                    println!("Normal debug: {:?}", normal_debug.mode);
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::Q) {
+                    weather.preset = weather.preset.next();
+                    println!("Weather: {:?}", weather.preset);
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::Z) {
+                    rotor_speed_scale_index = (rotor_speed_scale_index + 1) % rotor_speed_scales.len();
+                    println!("Rotor speed: {}x", rotor_speed_scales[rotor_speed_scale_index]);
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::C) {
+                    pivot_gizmo.enabled = !pivot_gizmo.enabled;
+                    println!("Pivot gizmo: {}", if pivot_gizmo.enabled { "on" } else { "off" });
+                }
+
+                if input.was_just_pressed(VirtualKeyCode::R) && gl_caps.clip_distance {
+                    frozen_lake.mode = frozen_lake.mode.next();
+                    println!("Reflections: {:?}", frozen_lake.mode);
                 }
-            }
 
-            // Handle keyboard input
-            if let Ok(keys) = pressed_keys.lock() {
-                for key in keys.iter() {
+                if input.was_just_pressed(VirtualKeyCode::O) {
+                    settings.ssao = !settings.ssao;
+                    println!("SSAO: {}", if settings.ssao { "on" } else { "off" });
+                }
+
+                // Fog on/off (F) and fog falloff (G)
+                if input.was_just_pressed(VirtualKeyCode::F) {
+                    settings.fog.enabled = !settings.fog.enabled;
+                    println!("Fog: {}", if settings.fog.enabled { "on" } else { "off" });
+                }
+                if input.was_just_pressed(VirtualKeyCode::G) {
+                    settings.fog.mode = settings.fog.mode.next();
+                    println!("Fog mode: {:?}", settings.fog.mode);
+                }
+
+                // Handle vsync and frame cap toggles
+                if input.was_just_pressed(VirtualKeyCode::V) {
+                    if swap_control.set_vsync(!settings.vsync) {
+                        settings.vsync = !settings.vsync;
+                        println!("Vsync: {}", if settings.vsync { "on" } else { "off" });
+                    } else {
+                        println!("Vsync can't be changed on this driver");
+                    }
+                }
+                if input.was_just_pressed(VirtualKeyCode::L) {
+                    settings.next_frame_cap();
+                    frame_limiter.max_fps = settings.frame_cap;
+                    match settings.frame_cap {
+                        Some(fps) => println!("Frame cap: {} fps", fps),
+                        None => println!("Frame cap: off"),
+                    }
+                }
+
+                // Handle resize events
+                if input.resized {
+                    let (width, height) = input.window_size;
+                    context.resize(glutin::dpi::PhysicalSize::new(width, height));
+                    unsafe { driver_quirks.after_resize() };
+                    if width > 0 && height > 0 {
+                        window_aspect_ratio = width as f32 / height as f32;
+                    }
+                    input.resized = false;
+                    println!("Window was resized to {}x{}", width, height);
+                    gfx::note_event(format!("window resized to {}x{}", width, height));
+                    unsafe { gfx::set_viewport(0, 0, width, height); }
+                }
+
+                // Handle keyboard input. The camera can still fly around the paused scene, but not in the menu.
+                let mut camera_movement: glm::Vec3 = glm::zero();
+                for key in input.pressed_keys.iter().filter(|_| !in_menu) {
                     let movement_vector: glm::Vec3 = match key {
-                        VirtualKeyCode::W => camera_forward * camera_speed * delta_time,     // Move forward
-                        VirtualKeyCode::S => -camera_forward * camera_speed * delta_time,    // Move backward
-                        VirtualKeyCode::A => camera_right * camera_speed * delta_time,       // Move left
-                        VirtualKeyCode::D => -camera_right * camera_speed * delta_time,      // Move right
-                        VirtualKeyCode::Space => camera_up * camera_speed * delta_time,      // Move up
-                        VirtualKeyCode::LShift => -camera_up * camera_speed * delta_time,    // Move down
+                        VirtualKeyCode::W => camera_forward * camera_speed * frame_time,     // Move forward
+                        VirtualKeyCode::S => -camera_forward * camera_speed * frame_time,    // Move backward
+                        VirtualKeyCode::A => camera_right * camera_speed * frame_time,       // Move left
+                        VirtualKeyCode::D => -camera_right * camera_speed * frame_time,      // Move right
+                        VirtualKeyCode::Space => camera_up * camera_speed * frame_time,      // Move up
+                        VirtualKeyCode::LShift => -camera_up * camera_speed * frame_time,    // Move down
                         _ => glm::vec3(0.0, 0.0, 0.0)
                     };
-    
-                    // Update camera position based on movement
-                    camera_position += movement_vector;
+
+                    camera_movement += movement_vector;
                 }
-            }
 
-            // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
-            if let Ok(mut delta) = mouse_delta.lock() {
+                // Update camera position based on movement, stopping in front of the terrain instead of going
+                // through it. The movement is the ray, so a hit within 1.0 of it is on the way.
+                if camera_movement != glm::Vec3::zeros() {
+                    match terrain_bvh.closest_hit(&bvh::Ray::new(camera_position, camera_movement), 1.0) {
+                        Some(hit) => {
+                            let towards_camera = if glm::dot(&hit.normal, &camera_movement) > 0.0 { -hit.normal } else { hit.normal };
+                            camera_position = hit.position + towards_camera * 0.5;
+                        }
+                        None => camera_position += camera_movement,
+                    }
+                }
+
+                // Handle mouse movement. delta contains the x and y movement of the mouse since last frame in pixels
+                let delta = input.mouse_delta;
                 camera_pitch -= delta.1 * mouse_sensitivity; // Update pitch (vertical)
                 camera_yaw += delta.0 * mouse_sensitivity; // Update yaw (horizontal)
 
@@ -301,35 +1775,654 @@ fn main() {
                 camera_pitch = camera_pitch.clamp(-std::f32::consts::FRAC_PI_2, std::f32::consts::FRAC_PI_2);
 
                 // Reset the mouse delta after applying it
-                *delta = (0.0, 0.0);
-            }
+                input.mouse_delta = (0.0, 0.0);
+
+                // The benchmark has the camera
+                if let Some(benchmark) = benchmark.as_ref() {
+                    let benchmark_camera = benchmark.camera();
+                    camera_position = benchmark_camera.position_f32();
+                    camera_yaw = benchmark_camera.yaw;
+                    camera_pitch = benchmark_camera.pitch;
+                }
 
 
-            // * Apply transformations to the world from camera view
-            let view_projection_matrix: glm::Mat4 = util::calculate_transformation_from_camera_to_world_view(
-                window_aspect_ratio,
-                camera_position,
-                camera_forward,
-                camera_up
-            );
+                // * Apply transformations to the world from camera view
+                let view_projection_matrix: glm::Mat4 = math::calculate_transformation_from_camera_to_world_view(
+                    window_aspect_ratio,
+                    settings.projection.near,
+                    settings.projection.far,
+                    settings.projection.reversed_z,
+                    camera_position,
+                    camera_forward,
+                    camera_up
+                );
+
+                // The scripts get the last say about where things are this frame
+                script_host.update(&input, elapsed, delta_time);
+
+                // Then the entities, they don't depend on the scene graph
+                weather.update(&mut ecs_world, camera_position, delta_time);
+                unsafe { flag_cloth.update(weather.wind(), delta_time) };
+                for water_surface in &mut water_surfaces {
+                    water_surface.update(delta_time);
+                }
+                ecs::animate(&mut ecs_world, delta_time);
+                ecs::update_transforms(&mut ecs_world);
+                ecs::update_particles(&mut ecs_world, delta_time);
+
+                // * Snapshot the scene graph, the full-scene passes below only draw from the snapshot
+                unsafe { spike_capture.mark("snapshot"); }
+                frame_number += 1;
+                let back_snapshot = frame_snapshots.back_mut();
+                back_snapshot.capture(&scene_graph, frame_number);
+                ecs::extract(&ecs_world, back_snapshot);
+                frame_snapshots.publish();
+                trail_renderer.update(elapsed);
+                let frame_snapshot = frame_snapshots.front();
+                spatial_index.update(frame_snapshot);
+
+                // * Update the split screen cameras, following the helicopters where the snapshot has them
+                viewports.cameras[0] = camera::Camera { position: scene_graph::position_from_f32(&camera_position), yaw: camera_yaw, pitch: camera_pitch };
+                // (camera, helicopter it chases)
+                for (camera_index, helicopter_index) in [(1, 0), (2, 1)] {
+                    if let Some(helicopter) = frame_snapshot.node(helicopter_ids[helicopter_index]) {
+                        viewports.cameras[camera_index].chase(scene_graph::position_to_f32(&helicopter.position), helicopter.rotation.y, 30.0, 10.0);
+                    }
+                }
+                // Overview camera high above the middle of the flight area, following helicopter 2
+                viewports.cameras[3].position = scene_graph::position_from_f32(&glm::vec3(0.0, 150.0, 120.0));
+                if let Some(helicopter) = frame_snapshot.node(helicopter_ids[2]) {
+                    viewports.cameras[3].look_at(scene_graph::position_to_f32(&helicopter.position));
+                }
+                if let Some(helicopter) = frame_snapshot.node(helicopter_ids[3]) {
+                    security_monitor.camera.look_at(scene_graph::position_to_f32(&helicopter.position));
+                }
 
-            // * Render Objects
-            unsafe {
-                // Clear the color and depth buffers
-                gl::ClearColor(0.035, 0.046, 0.078, 1.0); // night sky
-                gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT); // Clear the screen
+                // The sounds follow the nodes they are attached to, heard from the fly-cam
+                audio.update(frame_snapshot, &spatial_index, &viewports.cameras[0], delta_time);
+
+                // Region and collision triggers, then everything published this frame goes to the subscribers
+                trigger_watcher.update(frame_snapshot, &spatial_index, camera_position, &mut event_bus);
+                event_bus.dispatch();
+                audio.set_ambient_volume(wind_sound, wind_volume.get());
+
+                camera_shake.add_trauma(collision_trauma.take());
+                for helicopter in helicopter_ids.iter().filter_map(|&id| frame_snapshot.node(id)) {
+                    let helicopter_position = scene_graph::position_to_f32(&helicopter.position);
+                    camera_shake.flyby(glm::distance(&helicopter_position, &camera_position), delta_time);
+                }
+                camera_shake.update(delta_time);
+
+                // * Render Objects
+                unsafe {
+                    // Waits if the GPU is still drawing the frame from three frames ago
+                    transforms.begin_frame();
+
+                    // The flat and the PBR scene shader, indexed by whether it is PBR
+                    let scene_shaders = [assets.shader(shader_handle), assets.shader(pbr_shader_handle)];
+                    let shader = scene_shaders[settings.pbr as usize];
+                    let textured_shader = assets.shader(textured_shader_handle);
+                    // Programs that draw what isn't in the scene graph, for the features the driver has, flat and PBR too
+                    let extra_shaders: [Vec<&shader::Shader>; 2] = [false, true].map(|pbr| [
+                        tessellated_terrain.as_ref().map(|terrain| terrain.shader(&assets, pbr)),
+                        boulders.as_ref().map(|boulders| boulders.shader(&assets, pbr)),
+                        boulders.as_ref().and_then(|boulders| boulders.impostor_shader(&assets)),
+                        helicopter_impostors.as_ref().map(|impostors| impostors.shader(&assets)),
+                    ].iter().flatten().copied().collect());
+                    // The looks of the A/B comparison, and which of the scene shaders they need lit
+                    let looks = comparison.looks(current_look);
+                    let mut shading_modes: Vec<bool> = looks.iter().map(|look| look.pbr).collect();
+                    shading_modes.dedup();
+
+                    // The fog fades into the sky, both seen through the weather. Picked by eye, so decoded to linear.
+                    let sky_color = color::decode_srgb(weather.sky_color(time_of_day.sky_color()));
+                    settings.fog.color = sky_color;
+                    weather.apply_fog(&mut settings.fog);
+
+                    // Draws what isn't in the scene graph: the boulders, and the tessellated terrain when it
+                    // stands in for the terrain mesh. Then goes back to the scene shader, flat or PBR.
+                    let draw_extras = |view_projection: &glm::Mat4, camera_position: glm::Vec3, clip_plane: [f32; 4], pbr: bool| {
+                        if let Some(tessellated_terrain) = tessellated_terrain.as_ref().filter(|terrain| terrain.enabled) {
+                            tessellated_terrain.draw(&assets, pbr, view_projection, camera_position, clip_plane);
+                        }
+                        if let Some(boulders) = &boulders {
+                            boulders.draw(&assets, pbr, view_projection, camera_position, clip_plane);
+                        }
+                        scene_shaders[pbr as usize].activate();
+                    };
+
+                    let (width, height) = input.window_size;
+
+                    // Every view of this frame, with its camera and view projection
+                    let mut views: Vec<(viewport::Viewport, camera::Camera, glm::Mat4)> = viewports.viewports(width, height).iter()
+                        .zip(viewports.cameras.iter())
+                        .map(|(view, view_camera)| {
+                            // The fly-cam keeps using the window's aspect ratio when it has the whole window
+                            let region_view_projection = if viewports.layout == viewport::SplitLayout::Single {
+                                view_projection_matrix
+                            } else {
+                                view_camera.view_projection(view.aspect_ratio(), &settings.projection)
+                            };
+                            (*view, *view_camera, region_view_projection)
+                        })
+                        .collect();
+
+                    // While observing, the fly-cam's view is seen from the observer, but still culled as the fly-cam
+                    let (fly_cam_view, fly_cam, fly_cam_view_projection) = views[0];
+                    if let Some(observer) = frustum_debug.observer {
+                        views[0] = (fly_cam_view, observer, observer.view_projection(fly_cam_view.aspect_ratio(), &settings.projection));
+                    }
+
+                    // The same views with their cameras at the origin, for the camera relative transforms
+                    let mut relative_view_projections: Vec<glm::Mat4> = views.iter()
+                        .map(|(view, view_camera, _)| view_camera.relative_view_projection(view.aspect_ratio(), &settings.projection))
+                        .collect();
+
+                    // The fly-cam shakes, after it has been moved for this frame
+                    if frustum_debug.observer.is_none() {
+                        views[0].2 = camera_shake.apply(&views[0].2, views[0].0.aspect_ratio(), &settings.projection);
+                        relative_view_projections[0] = camera_shake.apply(&relative_view_projections[0], views[0].0.aspect_ratio(), &settings.projection);
+                    }
+
+                    // Every view is moved by a fraction of a pixel for the temporal anti-aliasing, a different one every frame
+                    let taa_active = settings.hdr && taa.enabled;
+                    let motion_view_projections: Vec<glm::Mat4> = views.iter().map(|(_, _, vp)| *vp).collect();
+                    if taa_active {
+                        for ((view, _, view_projection), relative_view_projection) in views.iter_mut().zip(relative_view_projections.iter_mut()) {
+                            let jitter = taa.jitter(view);
+                            *view_projection = jitter * *view_projection;
+                            *relative_view_projection = jitter * *relative_view_projection;
+                        }
+                    }
+                    let rendered_views: Vec<(viewport::Viewport, glm::Mat4)> = views.iter().map(|(view, _, vp)| (*view, *vp)).collect();
+
+                    // Left click selects whatever is under the cursor. A helicopter part selects the whole
+                    // helicopter, anything else (the terrain) clears the selection.
+                    let camera_depth_mode = settings.projection.depth_mode();
+                    if input.was_just_clicked(MouseButton::Left) {
+                        gfx::set_depth_mode(camera_depth_mode);
+                        let picked = picking_buffer.pick(&assets, frame_snapshot, &rendered_views, input.cursor_position, width, height);
+                        gfx::set_depth_mode(gfx::DepthMode::Standard);
+                        if let Some(node_id) = picked {
+                            event_bus.publish(triggers::GameEvent::NodeClicked { node_id });
+                        }
+                        selected_helicopter = picked.and_then(|node_id| {
+                            helicopter_subtree_ids.iter().position(|subtree_ids| subtree_ids.contains(&node_id))
+                        });
+                        match selected_helicopter {
+                            Some(i) => outline_renderer.set_highlighted(helicopter_ids[i], [1.0, 0.75, 0.1, 1.0]),
+                            None => outline_renderer.clear_highlighted(),
+                        }
+                        shader.activate();
+                    }
+
+                    // Where the helicopters are in the world in this frame, for the minimap and the impostors
+                    let helicopter_matrices: Vec<glm::Mat4> = helicopter_ids.iter()
+                        .filter_map(|&id| frame_snapshot.node(id))
+                        .map(|helicopter| helicopter.model_matrix)
+                        .collect();
+                    let helicopter_positions: Vec<glm::Vec3> = helicopter_matrices.iter().map(|matrix| matrix.column(3).xyz()).collect();
+
+                    // The height readouts under the names, then where the labels hang this frame
+                    if labels.enabled {
+                        for (i, &label) in helicopter_labels.iter().enumerate() {
+                            let position = frame_snapshot.node(helicopter_ids[i]).map(|helicopter| scene_graph::position_to_f32(&helicopter.position));
+                            let altitude = position.and_then(|position| height_above_ground(&terrain_bvh, position)).map_or(strings.get("units.unknown").to_string(), |height| {
+                                strings.format("units.meters", &[("value", &format!("{:.0}", height))])
+                            });
+                            labels.set_text(label, &strings.format("labels.helicopter_altitude", &[("index", &i.to_string()), ("altitude", &altitude)]));
+                        }
+                        labels.update(frame_snapshot);
+                    }
+
+                    if frame_rate.update(frame_time) {
+                        hud.set_text(fps_element, &strings.format("hud.frame_rate", &[
+                            ("fps", &format!("{:.0}", frame_rate.fps)),
+                            ("ms", &format!("{:.1}", frame_rate.ms)),
+                        ]));
+                    }
+                    hud.set_visible(crosshair_element, input.mouse_captured);
+                    let selected_aircraft = selected_helicopter.and_then(|i| {
+                        let helicopter = frame_snapshot.node(helicopter_ids[i])?;
+                        let position = scene_graph::position_to_f32(&helicopter.position);
+                        Some(instruments::AircraftState {
+                            index: i,
+                            position,
+                            rotation: helicopter.rotation,
+                            height_above_ground: height_above_ground(&terrain_bvh, position),
+                        })
+                    });
+                    flight_instruments.update(&mut hud, &strings, selected_aircraft, delta_time);
+
+                    // The helicopters far enough from `camera_position` to be drawn as impostors: the nodes
+                    // that aren't drawn for them, and where the impostors go
+                    let far_helicopters = |camera_position: glm::Vec3| -> (Vec<scene_graph::NodeId>, Vec<(glm::Mat4, usize)>) {
+                        if !use_impostors || helicopter_impostors.is_none() {
+                            return (vec![], vec![]);
+                        }
+                        let far: Vec<usize> = (0..helicopter_matrices.len())
+                            .filter(|&i| glm::distance(&helicopter_positions[i], &camera_position) > helicopter_impostor_distance)
+                            .collect();
+                        let skipped = far.iter().flat_map(|&i| helicopter_subtree_ids[i].iter().copied()).collect();
+                        (skipped, far.iter().map(|&i| (helicopter_matrices[i], 0)).collect())
+                    };
+
+                    // The scene is drawn into the HDR target if it is on, otherwise straight into the window
+                    let planar_reflections = frozen_lake.needs_planar_pass(settings.hdr);
+                    let water_reflections = water_surfaces.iter().any(|water_surface| water_surface.needs_planar_pass());
+                    if settings.hdr {
+                        post_chain.resize(width, height);
+                    }
+                    let scene_target = if settings.hdr { Target::Framebuffer(&post_chain.hdr_target) } else { Target::Window };
+                    let scene_writes: &[Resource] = if settings.hdr { &[Resource::Scene] } else { &[Resource::Scene, Resource::Window] };
+                    let sun_is_up = time_of_day.sun_height() > 0.0;
+                    let sun_color = weather.light_color(time_of_day.sun_color());
+                    let star_visibility = time_of_day.star_visibility() * weather.sky_clearness();
+                    let world_lights = &frame_snapshot.lights;
+                    let feed_view_projection = security_monitor.feed_view_projection(&settings.projection);
+
+                    // What more than one of the passes changes
+                    let shadows = RefCell::new(&mut shadows);
+                    let point_shadows = RefCell::new(&mut point_shadows);
+                    let light_clusters = RefCell::new(&mut light_clusters);
+                    let probes = RefCell::new(&mut probes);
+                    let frustum_debug = RefCell::new(&mut frustum_debug);
+                    let motion_vectors = RefCell::new(&mut motion_vectors);
+                    let frozen_lake = RefCell::new(&mut frozen_lake);
+                    let water_surfaces = RefCell::new(&mut water_surfaces);
+                    // Made by the passes before the tone mapping, for it
+                    let ao_texture = Cell::new(None);
+                    let exposure_texture = Cell::new(None);
+
+                    let mut render_graph = RenderGraph::new(width, height);
+
+                    // * Shadow maps, with the cascades fitted to the fly-cam
+                    render_graph.add(Pass::new("shadow maps", || {
+                        let mut shadows = shadows.borrow_mut();
+                        shadows.render(&assets, &viewports.cameras[0], fly_cam_view.aspect_ratio(), settings.projection.near, time_of_day.sun_direction(), |depth_shader, light_view_projection| {
+                            frame_snapshot.draw_visible(light_view_projection, depth_shader, &transforms, &spatial_index);
+                            // The terrain node is hidden while the tessellated terrain stands in for it, its mesh still throws the shadows
+                            if !terrain_node.visible {
+                                transforms.set(depth_shader, light_view_projection, &glm::identity());
+                                gfx::DrawCall::indexed(vao_id_terrain, terrain_node.index_count).submit();
+                            }
+                        });
+                        frustum_debug.borrow_mut().cascade_splits = shadows.splits().to_vec();
+
+                        // Lights attached to nodes, in world space now that everything has moved for this frame
+                        point_shadows.borrow_mut().render(&assets, world_lights, |depth_shader, face_view_projection| {
+                            frame_snapshot.draw_visible(face_view_projection, depth_shader, &transforms, &spatial_index);
+                        });
+                    }).writes(&[Resource::ShadowMaps]));
+
+                    // * The lights, the shadow maps and the environment of the lit shaders
+                    render_graph.add(Pass::new("lighting", || {
+                        light_clusters.borrow_mut().set_lights(world_lights);
+                        if shading_modes.contains(&true) {
+                            environment_map.bind(material::ENVIRONMENT_UNIT);
+                        }
+
+                        // The tessellated terrain and the boulders are lit the same way as everything else
+                        let lit_shaders = shading_modes.iter().flat_map(|&pbr| extra_shaders[pbr as usize].iter().copied().chain(std::iter::once(scene_shaders[pbr as usize])).map(move |lit_shader| (lit_shader, pbr)));
+                        for (lit_shader, pbr) in lit_shaders {
+                            lit_shader.activate();
+                            settings.fog.set_uniforms(lit_shader);
+
+                            lit_shader.set_uniform_vec3("sun_direction", time_of_day.sun_direction().as_ref());
+                            lit_shader.set_uniform_vec3("sun_color", &weather.light_color(time_of_day.sun_color()));
+                            // The impostors are lit the simple way with PBR on too, the PBR shader has no ambient color
+                            lit_shader.set_uniform_vec3("ambient_color", &weather.light_color(time_of_day.ambient_color()));
+                            if pbr {
+                                lit_shader.set_uniform_float("environment_intensity", time_of_day.ambient_intensity());
+                                lit_shader.set_uniform_int("environment_map", material::ENVIRONMENT_UNIT as i32);
+                                lit_shader.set_uniform_float("environment_mip_levels", environment_map.mip_levels as f32);
+                                probes.borrow().set_uniforms(lit_shader, &environment_map);
+                            }
+
+                            light_clusters.borrow().set_uniforms(lit_shader);
+                            shadows.borrow().set_uniforms(lit_shader);
+                            point_shadows.borrow().set_uniforms(lit_shader);
+                        }
+                    }).reads(&[Resource::ShadowMaps]).writes(&[Resource::Lighting]));
+
+                    // * The next faces of the reflection probes, with only what stays put in them
+                    // Also for the PBR side of a shading comparison
+                    render_graph.add(Pass::new("reflection probes", || {
+                        let shader = scene_shaders[1];
+                        shader.activate();
+                        probes.borrow_mut().update(&assets, sky_color, |probe_view_projection, probe_position| {
+                            light_clusters.borrow_mut().assign(probe_view_projection);
+                            shader.set_uniform_vec3("camera_position", probe_position.as_ref());
+                            draw_scene(&terrain_node, probe_view_projection, &glm::identity(), shader, &transforms);
+                            for (_, node_id) in &named_nodes {
+                                if let Some((node, parent_matrix)) = scene_graph.find(*node_id, &glm::identity()) {
+                                    draw_scene(node, probe_view_projection, &parent_matrix, shader, &transforms);
+                                }
+                            }
+                            draw_extras(probe_view_projection, probe_position, [0.0; 4], true);
+                            material::Material::unbind_samplers();
+                        });
+                    }).reads(&[Resource::Lighting]).writes(&[Resource::Probes]).only_if(shading_modes.contains(&true)));
+
+                    // * The security camera feed, it is shown in every view
+                    render_graph.add(Pass::new("security feed", || {
+                        gfx::clear(sky_color);
+                        shader.activate();
+                        light_clusters.borrow_mut().assign(&feed_view_projection);
+                        shader.set_uniform_vec3("camera_position", security_monitor.camera.position_f32().as_ref());
+                        frame_snapshot.draw_visible(&feed_view_projection, shader, &transforms, &spatial_index);
+                        draw_extras(&feed_view_projection, security_monitor.camera.position_f32(), [0.0; 4], settings.pbr);
+                    }).reads(&[Resource::Lighting]).writes(&[Resource::MonitorFeed])
+                        .draws_into(Target::Framebuffer(&security_monitor.framebuffer)).with_depth_mode(camera_depth_mode));
+
+                    // * The mirrored scene for the planar reflections of the lake and the water
+                    let draw_mirrored = |mirrored_view_projection: &glm::Mat4, mirrored_camera_position: glm::Vec3, clip_plane: [f32; 4]| {
+                        shader.set_uniform_vec3("camera_position", mirrored_camera_position.as_ref());
+                        shader.set_uniform_vec4("clip_plane", &clip_plane);
+                        light_clusters.borrow_mut().assign(mirrored_view_projection);
+                        frame_snapshot.draw_visible(mirrored_view_projection, shader, &transforms, &spatial_index);
+                        draw_extras(mirrored_view_projection, mirrored_camera_position, clip_plane, settings.pbr);
+                    };
+                    render_graph.add(Pass::new("lake reflections", || {
+                        shader.activate();
+                        frozen_lake.borrow_mut().render_reflections(width, height, &views, sky_color, draw_mirrored);
+                    }).reads(&[Resource::Lighting]).writes(&[Resource::Reflections]).with_depth_mode(camera_depth_mode).only_if(planar_reflections));
+                    render_graph.add(Pass::new("water reflections", || {
+                        shader.activate();
+                        for water_surface in water_surfaces.borrow_mut().iter_mut() {
+                            water_surface.render_reflections(width, height, &views, sky_color, draw_mirrored);
+                        }
+                    }).reads(&[Resource::Lighting]).writes(&[Resource::Reflections]).with_depth_mode(camera_depth_mode).only_if(water_reflections));
+
+                    // * The scene graph, once per view, and once per side of an A/B comparison within it
+                    render_graph.add(Pass::new("scene", || {
+                        let mut motion_vectors = motion_vectors.borrow_mut();
+                        if taa_active {
+                            motion_vectors.begin(&post_chain);
+                        }
+                        let lit_shaders: Vec<&shader::Shader> = shading_modes.iter()
+                            .flat_map(|&pbr| extra_shaders[pbr as usize].iter().copied().chain(std::iter::once(scene_shaders[pbr as usize])))
+                            .collect();
+
+                        for (view_index, (view, view_camera, region_view_projection)) in views.iter().enumerate() {
+                            view.activate();
+
+                            // Clear the color and depth buffers
+                            gfx::clear(sky_color); // Clear the screen
+                            if taa_active {
+                                motion_vectors.clear_view();
+                                motion_vectors.begin_view(&lit_shaders, &motion_view_projections[view_index]);
+                            }
+                            celestial_sphere.draw(&assets, &relative_view_projections[view_index], &time_of_day, elapsed, star_visibility);
+                            if let Some(infinite_ground) = &infinite_ground {
+                                infinite_ground.draw(&assets, &relative_view_projections[view_index], view_camera.position_f32(), &time_of_day, sun_color, &settings.fog);
+                            }
+
+                            transforms.set_camera_origin(Some(camera::CameraOrigin {
+                                position: view_camera.position,
+                                view_projection: *region_view_projection,
+                                relative_view_projection: relative_view_projections[view_index],
+                            }));
+
+                            let observing = view_index == 0 && frustum_debug.borrow().observer.is_some();
+                            let culling_view_projection = if observing { &fly_cam_view_projection } else { region_view_projection };
+                            light_clusters.borrow_mut().assign(region_view_projection);
+                            let (skipped, impostors) = far_helicopters(view_camera.position_f32());
+
+                            // What the looks change is drawn once per side, kept to it with the scissor. None of
+                            // it changes the scissor.
+                            for (side_scissor, look) in comparison.sides(view, width, current_look) {
+                                gfx::set_scissor(Some(side_scissor));
+                                let shader = scene_shaders[look.pbr as usize];
+                                // Only a comparison draws with something the lighting didn't set up
+                                if comparison.enabled() {
+                                    for &lit_shader in extra_shaders[look.pbr as usize].iter().chain(std::iter::once(&shader)) {
+                                        lit_shader.activate();
+                                        settings.fog.set_uniforms(lit_shader);
+                                        shadows.borrow().set_uniforms(lit_shader);
+                                        point_shadows.borrow().set_uniforms(lit_shader);
+                                        look.set_uniforms(lit_shader);
+                                    }
+                                }
+                                shader.activate();
+                                shader.set_uniform_vec3("camera_position", view_camera.position_f32().as_ref());
+                                frame_snapshot.draw_visible_except(region_view_projection, culling_view_projection, shader, &transforms, &spatial_index, &skipped);
+                                if let Some(helicopter_impostors) = &helicopter_impostors {
+                                    helicopter_impostors.draw(&assets, &impostors, region_view_projection, view_camera.position_f32(), [0.0; 4]);
+                                }
+                                draw_extras(region_view_projection, view_camera.position_f32(), [0.0; 4], look.pbr);
+                            }
+                            view.activate();
+
+                            outline_renderer.draw(&assets, &scene_graph, view, region_view_projection, shader, |node, parent_transform, outline_pass_shader| {
+                                draw_scene(node, region_view_projection, parent_transform, outline_pass_shader, &transforms);
+                            });
+                            if let (Some(selected), Some(normal_debug)) = (outline_renderer.highlighted(), &normal_debug) {
+                                normal_debug.draw(&assets, &scene_graph, selected, region_view_projection, shader, |node, parent_transform, normals_shader| {
+                                    draw_scene(node, region_view_projection, parent_transform, normals_shader, &transforms);
+                                });
+                            }
+                            pivot_gizmo.draw(&assets, &scene_graph, region_view_projection);
+                            if observing {
+                                frustum_debug.borrow().draw(&assets, region_view_projection, &fly_cam, fly_cam_view.aspect_ratio(), settings.projection.near);
+                            }
+                            shader.activate();
+
+                            security_monitor.draw_screen(region_view_projection, textured_shader);
+                            shader.activate();
+                        }
+                        transforms.set_camera_origin(None);
+                        viewports.reset(width, height);
+                    }).reads(&[Resource::Lighting, Resource::ShadowMaps, Resource::Probes, Resource::MonitorFeed, Resource::Reflections]).writes(scene_writes)
+                        .draws_into(scene_target).with_depth_mode(camera_depth_mode));
+
+                    // * The water goes on top of the views, it is see-through at the shore
+                    render_graph.add(Pass::new("water", || {
+                        for (view, view_camera, region_view_projection) in &views {
+                            view.activate();
+                            for water_surface in water_surfaces.borrow().iter() {
+                                water_surface.draw(&assets, region_view_projection, view_camera.position_f32(), time_of_day.sun_direction(), sun_color, sky_color);
+                            }
+                        }
+                        shader.activate();
+                        viewports.reset(width, height);
+                    }).reads(&[Resource::Scene, Resource::Reflections]).writes(scene_writes)
+                        .draws_into(scene_target).with_depth_mode(camera_depth_mode).only_if(!water_surfaces.borrow().is_empty()));
+
+                    // * The lake goes on top of the views, it is see-through and screen space reflections read what is under it
+                    render_graph.add(Pass::new("lake", || {
+                        let scene_framebuffer = if settings.hdr { Some(&post_chain.hdr_target) } else { None };
+                        for (view, view_camera, region_view_projection) in &views {
+                            view.activate();
+                            frozen_lake.borrow_mut().draw(&assets, view, region_view_projection, view_camera.position_f32(), scene_framebuffer);
+                        }
+                        shader.activate();
+                        viewports.reset(width, height);
+                    }).reads(&[Resource::Scene, Resource::Reflections]).writes(scene_writes)
+                        .draws_into(scene_target).with_depth_mode(camera_depth_mode));
+
+                    // * The trails go on top too, see-through as well
+                    trail_renderer.upload(elapsed);
+                    render_graph.add(Pass::new("trails", || {
+                        for (view, view_camera, region_view_projection) in &views {
+                            view.activate();
+                            trail_renderer.draw(&assets, region_view_projection, view_camera.position_f32());
+                        }
+                        shader.activate();
+                        viewports.reset(width, height);
+                    }).reads(&[Resource::Scene]).writes(scene_writes)
+                        .draws_into(scene_target).with_depth_mode(camera_depth_mode).only_if(trail_renderer.enabled && !trail_renderer.is_empty()));
+
+                    // * Post processing in the HDR target, then tone mapped into the window
+                    render_graph.add(Pass::new("temporal anti-aliasing", || {
+                        let motion_vectors = motion_vectors.borrow();
+                        motion_vectors.end(&post_chain);
+                        taa.resolve(&assets, &post_chain, &motion_vectors, &rendered_views);
+                    }).reads(&[Resource::Scene]).writes(&[Resource::Scene]).with_depth_mode(camera_depth_mode).only_if(taa_active));
+
+                    render_graph.add(Pass::new("god rays", || {
+                        god_rays.render(&assets, &post_chain, &rendered_views, time_of_day.sun_direction(), time_of_day.sun_color());
+                    }).reads(&[Resource::Scene]).writes(&[Resource::Scene]).with_depth_mode(camera_depth_mode).only_if(settings.hdr && sun_is_up));
+
+                    render_graph.add(Pass::new("ambient occlusion", || {
+                        let views: Vec<viewport::Viewport> = rendered_views.iter().map(|(view, _)| *view).collect();
+                        ao_texture.set(Some(ssao.render(&assets, &post_chain, &views, &settings.projection, None)));
+                    }).reads(&[Resource::Scene]).writes(&[Resource::AmbientOcclusion]).with_depth_mode(camera_depth_mode).only_if(settings.hdr && settings.ssao));
+
+                    render_graph.add(Pass::new("auto exposure", || {
+                        exposure_texture.set(Some(auto_exposure.update(&assets, &post_chain, delta_time)));
+                    }).reads(&[Resource::Scene]).writes(&[Resource::Exposure]).only_if(settings.hdr && settings.auto_exposure));
+
+                    render_graph.add(Pass::new("tone mapping", || {
+                        post_chain.end(&assets, settings.exposure, exposure_texture.get(), ao_texture.get(), width, height);
+                    }).reads(&[Resource::Scene, Resource::AmbientOcclusion, Resource::Exposure]).writes(&[Resource::Window])
+                        .draws_into(Target::Window).only_if(settings.hdr));
+
+                    // The flares need the depth of the HDR target for the occlusion test
+                    render_graph.add(Pass::new("lens flares", || {
+                        for (view, region_view_projection) in &rendered_views {
+                            lens_flare.draw(&assets, &post_chain.hdr_target, view, region_view_projection, time_of_day.sun_direction());
+                        }
+                    }).reads(&[Resource::Scene]).writes(&[Resource::Window]).draws_into(Target::Window)
+                        .with_depth_mode(camera_depth_mode).only_if(settings.hdr && sun_is_up));
 
-                shader.activate();
+                    render_graph.add(Pass::new("labels", || {
+                        for (view, view_camera, region_view_projection) in &views {
+                            labels.draw(&assets, view, region_view_projection, view_camera.position_f32());
+                        }
+                    }).writes(&[Resource::Window]).draws_into(Target::Window));
 
-                // Render the scene graph
-                draw_scene(&*scene_graph, &view_projection_matrix, &glm::identity(), &shader);
+                    // * The minimap goes on top of everything
+                    render_graph.add(Pass::new("minimap", || {
+                        minimap.draw(&assets, width, height, camera_position, camera_yaw, &helicopter_positions);
+                    }).writes(&[Resource::Window]).draws_into(Target::Window));
 
+                    render_graph.add(Pass::new("comparison split", || {
+                        comparison.draw_split_line(width, height);
+                    }).writes(&[Resource::Window]).draws_into(Target::Window).only_if(comparison.enabled()));
+
+                    render_graph.add(Pass::new("hud", || {
+                        hud.draw(&assets, width, height);
+                    }).writes(&[Resource::Window]).draws_into(Target::Window));
+
+                    let pass_order = render_graph.execute(&mut spike_capture);
+                    if pass_order != last_pass_order {
+                        println!("Render passes: {}", pass_order.join(", "));
+                        last_pass_order = pass_order;
+                    }
+
+                    transforms.end_frame();
+                }
 
-              
+                input.end_frame();
+
+                // Wait out the rest of the frame if a frame cap is set
+                unsafe { spike_capture.mark("frame cap and swap"); }
+                frame_limiter.wait();
+
+                if let Some(benchmark) = benchmark.as_mut() {
+                    unsafe { benchmark.end_frame(); }
+                }
+
+                // An OpenGL error in this frame (or while setting up) fails the frame here
+                util::raise_recorded_gl_error();
+
+                // Display the new color buffer on the display
+                context.swap_buffers().unwrap(); // we use "double buffering" to avoid artifacts
+
+                unsafe {
+                    spike_capture.end_frame(frame_number, spikes::SceneStats {
+                        draw_items: frame_snapshots.front().draws.len(),
+                        lights: frame_snapshots.front().lights.len(),
+                        indexed_nodes: spatial_index.len(),
+                        texture_bytes: assets.texture_memory().1,
+                        window_size: input.window_size,
+                    });
+                }
+
+                benchmark.as_ref().is_some_and(|benchmark| benchmark.is_finished())
+            }));
+            match frame {
+                Ok(true) => break,
+                Ok(false) => {}
+                Err(payload) => {
+                    let message = error_screen::panic_message(&*payload);
+                    println!("Frame {} panicked: {}", frame_number, message);
+                    error_screen.show(&message, &strings);
+                    input.end_frame();
+                    // Whatever the frame left open is closed, or the retry trips over it
+                    unsafe {
+                        transforms.reset_after_panic();
+                        if let Some(benchmark) = benchmark.as_mut() {
+                            benchmark.reset_after_panic();
+                        }
+                    }
+                }
             }
+        }
 
-            // Display the new color buffer on the display
-            context.swap_buffers().unwrap(); // we use "double buffering" to avoid artifacts
+        // The report, also of a benchmark that was stopped early. A benchmark turns vsync and the frame
+        // cap off, so it doesn't save the settings either.
+        if let Some(benchmark) = benchmark.take() {
+            unsafe { benchmark.finish(&gl_caps, input.window_size); }
+        } else {
+            // A fullscreen window is the size of the monitor, so the windowed size from before is kept
+            let window_size = if window_mode == display::WindowMode::Windowed { Some(input.window_size) } else { user_settings.window_size };
+            user_settings::UserSettings::capture(&settings, mouse_sensitivity, window_size, window_mode, &action_map, strings.language()).save();
+        }
+
+        // * Shutdown
+        // Free everything we put on the GPU while the context is still alive
+        println!("Render thread shutting down");
+        unsafe {
+            spike_capture.delete();
+            for vao_id in [vao_id_terrain, vao_id_helicopter_body, vao_id_helicopter_door, vao_id_helicopter_main_rotor, vao_id_helicopter_tail_rotor, vao_id_main_rotor_disc, vao_id_tail_rotor_disc] {
+                util::delete_vao(vao_id);
+            }
+            for batch in &static_batches {
+                util::delete_vao(batch.vao_id);
+            }
+            security_monitor.delete();
+            environment_map.delete();
+            celestial_sphere.delete();
+            trail_renderer.delete();
+            post_chain.delete();
+            auto_exposure.delete();
+            taa.delete();
+            motion_vectors.delete();
+            lens_flare.delete();
+            god_rays.delete();
+            minimap.delete();
+            labels.delete();
+            hud.delete();
+            error_screen.delete();
+            overlay_atlas.delete();
+            sampler::delete_all();
+            frustum_debug.delete();
+            shadows.delete();
+            point_shadows.delete();
+            light_clusters.delete();
+            probes.delete();
+            ssao.delete();
+            frozen_lake.delete();
+            for water_surface in &water_surfaces {
+                water_surface.delete();
+            }
+            if let Some(infinite_ground) = &infinite_ground {
+                infinite_ground.delete();
+            }
+            picking_buffer.delete();
+            if let Some(tessellated_terrain) = &tessellated_terrain {
+                tessellated_terrain.delete();
+            }
+            if let Some(boulders) = &boulders {
+                boulders.delete();
+            }
+            if let Some(helicopter_impostors) = &helicopter_impostors {
+                helicopter_impostors.delete();
+            }
+            transforms.delete();
+            assets.delete_all();
         }
     });
 
@@ -339,9 +2432,14 @@ fn main() {
     // == //
 
 
-    // Keep track of the health of the rendering thread
+    // Keep track of the health of the rendering thread. A panic in a frame is caught by the render
+    // thread itself (see error_screen.rs), this is for the ones while it loads and sets up.
     let render_thread_healthy = Arc::new(RwLock::new(true));
     let render_thread_watchdog = Arc::clone(&render_thread_healthy);
+    // Lets the event loop wait for the render thread to finish cleaning up before the process exits
+    let (render_done_sender, render_done_receiver) = mpsc::channel::<()>();
+    // And wakes it up when the render thread stopped by itself, after a benchmark or a panic
+    let event_loop_proxy = el.create_proxy();
     thread::spawn(move || {
         if !render_thread.join().is_ok() {
             if let Ok(mut health) = render_thread_watchdog.write() {
@@ -349,8 +2447,13 @@ fn main() {
                 *health = false;
             }
         }
+        let _ = render_done_sender.send(());
+        let _ = event_loop_proxy.send_event(());
     });
 
+    // Whether Alt is held, only the event loop gets modifier updates
+    let mut alt_held = false;
+
     // Start the event loop -- This is where window events are initially handled
     el.run(move |event, _, control_flow| {
         *control_flow = ControlFlow::Wait;
@@ -363,67 +2466,61 @@ fn main() {
         }
 
         match event {
+            // The process ends right after this event, so stop the render thread and wait for it
+            Event::LoopDestroyed => {
+                shutdown.store(true, Ordering::Relaxed);
+                if render_done_receiver.recv_timeout(std::time::Duration::from_secs(2)).is_err() {
+                    println!("Render thread did not shut down in time");
+                }
+            }
+            // The render thread is done, so is the program
+            Event::UserEvent(()) => {
+                *control_flow = ControlFlow::Exit;
+            }
+            // Everything the render thread needs goes through the event channel.
+            // A failed send just means the render thread is gone, and the watchdog handles that.
             Event::WindowEvent { event: WindowEvent::Resized(physical_size), .. } => {
                 println!("New window size received: {}x{}", physical_size.width, physical_size.height);
-                if let Ok(mut new_size) = arc_window_size.lock() {
-                    *new_size = (physical_size.width, physical_size.height, true);
-                }
+                let _ = event_sender.send(AppEvent::Resize(physical_size.width, physical_size.height));
             }
             Event::WindowEvent { event: WindowEvent::CloseRequested, .. } => {
+                let _ = event_sender.send(AppEvent::CloseRequested);
                 *control_flow = ControlFlow::Exit;
             }
             // Keep track of currently pressed keys to send to the rendering thread
             Event::WindowEvent { event: WindowEvent::KeyboardInput {
                     input: KeyboardInput { state: key_state, virtual_keycode: Some(keycode), .. }, .. }, .. } => {
 
-                if let Ok(mut keys) = arc_pressed_keys.lock() {
-                    match key_state {
-                        Released => {
-                            if keys.contains(&keycode) {
-                                let i = keys.iter().position(|&k| k == keycode).unwrap();
-                                keys.remove(i);
-                            }
-                        },
-                        Pressed => {
-                            if !keys.contains(&keycode) {
-                                keys.push(keycode);
-                            }
-                        }
-                    }
-                }
+                let _ = event_sender.send(AppEvent::Key(keycode, key_state));
 
-                // Handle Escape and Q keys separately
-                match keycode {
-                    Escape => { *control_flow = ControlFlow::Exit; }
-                    Q      => { *control_flow = ControlFlow::Exit; }
-                    _      => { }
+                // F11 or Alt+Enter cycles through the window modes
+                if key_state == glutin::event::ElementState::Pressed && (keycode == F11 || (keycode == Return && alt_held)) {
+                    let _ = event_sender.send(AppEvent::ToggleFullscreen);
                 }
+
+                // Escape opens the pause menu in the render thread, quitting goes through it
+            }
+            // Keep track of the Alt key for Alt+Enter
+            Event::WindowEvent { event: WindowEvent::ModifiersChanged(modifiers), .. } => {
+                alt_held = modifiers.alt();
             }
-            // Handle mouse button events (right click for rotation)
+            // Handle mouse button events (left click picks, holding the right button looks around)
             Event::WindowEvent { event: WindowEvent::MouseInput { button, state, .. }, .. } => {
-                if button == glutin::event::MouseButton::Right {
-                    if state == Pressed {
-                        mouse_right_button_pressed = true;
-                    } else {
-                        mouse_right_button_pressed = false;
-                    }
-                }
+                let _ = event_sender.send(AppEvent::MouseButton(button, state));
             }
             // Handle mouse movement events
+            // The render thread only accumulates them while the cursor is captured (` toggles it, and it
+            // is let go while the window is out of focus) or the right mouse button is held
             Event::DeviceEvent { event: DeviceEvent::MouseMotion { delta }, .. } => {
-                // Accumulate mouse movement
-                // if let Ok(mut position) = arc_mouse_delta.lock() {
-                //     *position = (position.0 + delta.0 as f32, position.1 + delta.1 as f32);
-                // }
-
-                // Only accumulate movement when right mouse button is pressed
-                if mouse_right_button_pressed {  
-                    if let Ok(mut mouse_delta) = arc_mouse_delta.lock() {
-                        // Accumulate mouse movement for pitch and yaw
-                        mouse_delta.0 += delta.0 as f32;
-                        mouse_delta.1 += delta.1 as f32;
-                    }
-                }
+                let _ = event_sender.send(AppEvent::MouseDelta(delta.0 as f32, delta.1 as f32));
+            }
+            // The cursor position in the window, for picking
+            Event::WindowEvent { event: WindowEvent::CursorMoved { position, .. }, .. } => {
+                let _ = event_sender.send(AppEvent::CursorMoved(position.x as f32, position.y as f32));
+            }
+            // The mouse capture lets go while another window has the focus
+            Event::WindowEvent { event: WindowEvent::Focused(focused), .. } => {
+                let _ = event_sender.send(AppEvent::Focused(focused));
             }
             _ => { }
         }
@@ -0,0 +1,177 @@
+use std::path::Path;
+
+use crate::assets::AssetManager;
+use crate::gfx;
+use crate::sampler::{self, Filter, SamplerDesc, Wrap};
+use crate::shader::Shader;
+
+// * PBR materials
+// Metallic/roughness materials, the same model glTF uses, so its materials can be taken as they are.
+// A SceneNode can have one, nodes without one are drawn with `Material::default()`, which just
+// takes the vertex colors as albedo. Only the PBR shader reads materials, the simple shader ignores them.
+
+// Texture units the maps are bound to, matching pbr.frag
+pub const ALBEDO_UNIT             : u32 = 0;
+pub const METALLIC_ROUGHNESS_UNIT : u32 = 1;
+pub const OCCLUSION_UNIT          : u32 = 2;
+pub const NORMAL_UNIT             : u32 = 3;
+pub const ENVIRONMENT_UNIT        : u32 = 4;
+pub const EMISSIVE_UNIT           : u32 = 5;
+
+#[derive(Clone, Debug)]
+pub struct Material {
+    pub name      : String,
+    pub albedo    : [f32; 4], // Multiplied with the vertex color and the albedo map
+    pub metallic  : f32,
+    pub roughness : f32,
+    pub ao        : f32,      // Ambient occlusion, 1 is not occluded
+    pub emissive  : [f32; 3], // Light given off by the surface itself, can go above 1 (HDR)
+
+    // OpenGL texture IDs. Textures need texture coordinates at location 3, which only some meshes have.
+    pub albedo_map             : Option<u32>,
+    pub metallic_roughness_map : Option<u32>, // glTF layout: roughness in G, metallic in B
+    pub occlusion_map          : Option<u32>, // Occlusion in R
+    pub normal_map             : Option<u32>, // Tangent space
+    pub emissive_map           : Option<u32>, // Multiplied with `emissive`
+    pub sampler                : SamplerDesc, // How all of the maps are filtered, see sampler.rs
+}
+
+impl Default for Material {
+    fn default() -> Material {
+        Material {
+            name: String::from("default"),
+            albedo: [1.0, 1.0, 1.0, 1.0],
+            metallic: 0.0,
+            roughness: 0.8,
+            ao: 1.0,
+            emissive: [0.0, 0.0, 0.0],
+            albedo_map: None,
+            metallic_roughness_map: None,
+            occlusion_map: None,
+            normal_map: None,
+            emissive_map: None,
+            sampler: SamplerDesc::default(),
+        }
+    }
+}
+
+impl Material {
+    pub fn new(name: &str, albedo: [f32; 4], metallic: f32, roughness: f32) -> Material {
+        Material {
+            name: name.to_string(),
+            albedo,
+            metallic,
+            roughness,
+            ..Default::default()
+        }
+    }
+
+    // Something that glows with the given color and doesn't care about the lights, like a lamp
+    pub fn emissive(name: &str, color: [f32; 3], strength: f32) -> Material {
+        Material {
+            name: name.to_string(),
+            albedo: [color[0], color[1], color[2], 1.0],
+            emissive: [color[0] * strength, color[1] * strength, color[2] * strength],
+            ..Default::default()
+        }
+    }
+
+    /// Set the material uniforms and bind the maps with the material's sampler, the shader has to be active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn bind(&self, shader: &Shader) {
+        shader.set_uniform_vec4("material_albedo", &self.albedo);
+        shader.set_uniform_float("material_metallic", self.metallic);
+        shader.set_uniform_float("material_roughness", self.roughness);
+        shader.set_uniform_float("material_ao", self.ao);
+        shader.set_uniform_vec3("material_emissive", &self.emissive);
+
+        let maps = [
+            ("albedo_map", ALBEDO_UNIT, self.albedo_map),
+            ("metallic_roughness_map", METALLIC_ROUGHNESS_UNIT, self.metallic_roughness_map),
+            ("occlusion_map", OCCLUSION_UNIT, self.occlusion_map),
+            ("normal_map", NORMAL_UNIT, self.normal_map),
+            ("emissive_map", EMISSIVE_UNIT, self.emissive_map),
+        ];
+        for (name, unit, map) in maps {
+            shader.set_uniform_int(&format!("has_{}", name), map.is_some() as i32);
+            shader.set_uniform_int(name, unit as i32);
+            gfx::bind_texture(unit, map);
+            sampler::bind(unit, map.and(Some(&self.sampler)));
+        }
+    }
+
+    /// Unbind the samplers `bind` left on the map units, once done drawing with materials.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn unbind_samplers() {
+        for unit in [ALBEDO_UNIT, METALLIC_ROUGHNESS_UNIT, OCCLUSION_UNIT, NORMAL_UNIT, EMISSIVE_UNIT] {
+            sampler::bind(unit, None);
+        }
+    }
+}
+
+// * glTF material import
+/// Reads the materials of a .gltf/.glb file. Only the materials are used, not the meshes.
+/// Textures referenced by URI are loaded through the asset manager, relative to the file.
+///
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn load_gltf_materials(path: &str, assets: &mut AssetManager) -> Vec<Material> {
+    let document = gltf::Gltf::open(path)
+        .unwrap_or_else(|e| panic!("Failed to load glTF file {}: {:?}", path, e));
+    let directory = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    // Embedded images (buffer views, data URIs) aren't supported, those maps are left out
+    // glTF has the base color and emissive textures in sRGB, the others are data
+    let mut load_texture = |texture: gltf::Texture, color_space: gfx::ColorSpace| -> Option<u32> {
+        match texture.source().source() {
+            gltf::image::Source::Uri { uri, .. } if !uri.starts_with("data:") => {
+                let texture_path = directory.join(uri);
+                let handle = assets.load_texture(&texture_path.to_string_lossy(), color_space);
+                Some(assets.texture(handle).texture.id)
+            }
+            _ => {
+                println!("Warning: {} has an embedded texture, which is not supported", path);
+                None
+            }
+        }
+    };
+
+    document.materials().enumerate().map(|(i, material)| {
+        let pbr = material.pbr_metallic_roughness();
+        Material {
+            name: material.name().map(|name| name.to_string()).unwrap_or(format!("material_{}", i)),
+            albedo: pbr.base_color_factor(),
+            metallic: pbr.metallic_factor(),
+            roughness: pbr.roughness_factor(),
+            ao: 1.0,
+            emissive: material.emissive_factor(),
+            albedo_map: pbr.base_color_texture().and_then(|info| load_texture(info.texture(), gfx::ColorSpace::Srgb)),
+            metallic_roughness_map: pbr.metallic_roughness_texture().and_then(|info| load_texture(info.texture(), gfx::ColorSpace::Linear)),
+            occlusion_map: material.occlusion_texture().and_then(|info| load_texture(info.texture(), gfx::ColorSpace::Linear)),
+            normal_map: material.normal_texture().and_then(|info| load_texture(info.texture(), gfx::ColorSpace::Linear)),
+            emissive_map: material.emissive_texture().and_then(|info| load_texture(info.texture(), gfx::ColorSpace::Srgb)),
+            // The maps share the sampler of the base color texture
+            sampler: pbr.base_color_texture().map_or(SamplerDesc::default(), |info| gltf_sampler(&info.texture().sampler())),
+        }
+    }).collect()
+}
+
+// glTF has filters and wrap modes but no anisotropy, that stays at the default
+fn gltf_sampler(sampler: &gltf::texture::Sampler) -> SamplerDesc {
+    use gltf::texture::{MinFilter, WrappingMode};
+    let filter = match sampler.min_filter() {
+        Some(MinFilter::Nearest) | Some(MinFilter::NearestMipmapNearest) => Filter::Nearest,
+        Some(MinFilter::Linear) | Some(MinFilter::LinearMipmapNearest) => Filter::Bilinear,
+        _ => Filter::Trilinear,
+    };
+    let wrap = match sampler.wrap_s() {
+        WrappingMode::Repeat         => Wrap::Repeat,
+        WrappingMode::MirroredRepeat => Wrap::MirroredRepeat,
+        WrappingMode::ClampToEdge    => Wrap::ClampToEdge,
+    };
+    SamplerDesc::default().with_filter(filter).with_wrap(wrap)
+}
@@ -0,0 +1,409 @@
+extern crate nalgebra_glm as glm;
+
+use rand::prelude::*;
+use rayon::prelude::*;
+
+// * Math
+// The transforms, the camera directions, the projections and where billboards land on the screen,
+// pulled out of util.rs so nothing in here touches OpenGL: every function only takes and gives back
+// numbers and glm types, and can be used (and checked, see the tests at the bottom) without a context. util.rs keeps the GL plumbing: the VAOs, the buffers, the
+// debug callback.
+
+// * Transforming vertex arrays in place
+// The vertex arrays are flat (x, y, z, x, y, z, ...), like the meshes keep them. A matrix is applied
+// to every vertex where it is, without a new array, and a big array like the terrain's (millions of
+// floats) is cut into blocks that go over the thread pool. Only the upper three rows of the matrix
+// are used: the w of every point is 1 and the w that comes out is dropped, like the GPU does for an
+// affine transformation.
+
+// Vertices per block, and the fewest worth splitting up at all
+const PARALLEL_BLOCK: usize = 4096;
+const PARALLEL_VERTICES: usize = 4 * PARALLEL_BLOCK;
+
+// Runs `apply` over the vertices, in blocks on the thread pool if there are enough of them
+fn for_vertex_blocks<F: Fn(&mut [f32]) + Send + Sync>(vertices: &mut [f32], apply: F) {
+    if vertices.len() / 3 >= PARALLEL_VERTICES {
+        vertices.par_chunks_mut(3 * PARALLEL_BLOCK).for_each(apply);
+    } else {
+        apply(vertices);
+    }
+}
+
+/// Moves every point by `matrix`, a trailing partial vertex is left alone.
+pub fn transform_points_in_place(vertices: &mut [f32], matrix: &glm::Mat4) {
+    let linear = glm::mat4_to_mat3(matrix);
+    let offset: glm::Vec3 = matrix.fixed_slice::<3, 1>(0, 3).into();
+    for_vertex_blocks(vertices, |block| {
+        for vertex in block.chunks_exact_mut(3) {
+            let moved = linear * glm::make_vec3(vertex) + offset;
+            vertex.copy_from_slice(moved.as_slice());
+        }
+    });
+}
+
+/// Turns every normal the way `matrix` turns the surface it belongs to (by its inverse transpose, so
+/// non-uniform scaling doesn't tilt them), and normalizes them again.
+pub fn transform_normals_in_place(normals: &mut [f32], matrix: &glm::Mat4) {
+    let normal_matrix = glm::mat4_to_mat3(&glm::inverse_transpose(*matrix));
+    for_vertex_blocks(normals, |block| {
+        for normal in block.chunks_exact_mut(3) {
+            let turned = glm::normalize(&(normal_matrix * glm::make_vec3(normal)));
+            normal.copy_from_slice(turned.as_slice());
+        }
+    });
+}
+
+pub fn scale_vertices_in_place(vertices: &mut [f32], scale_x: f32, scale_y: f32, scale_z: f32) {
+    for_vertex_blocks(vertices, |block| {
+        for vertex in block.chunks_exact_mut(3) {
+            vertex[0] *= scale_x;
+            vertex[1] *= scale_y;
+            vertex[2] *= scale_z;
+        }
+    });
+}
+
+pub fn rotate_vertices_in_place(vertices: &mut [f32], rotation_x: f32, rotation_y: f32, rotation_z: f32) {
+    transform_points_in_place(vertices, &(rotation_matrix_z(rotation_z) * rotation_matrix_y(rotation_y) * rotation_matrix_x(rotation_x)));
+}
+
+pub fn translate_vertices_in_place(vertices: &mut [f32], translate_x: f32, translate_y: f32, translate_z: f32) {
+    for_vertex_blocks(vertices, |block| {
+        for vertex in block.chunks_exact_mut(3) {
+            vertex[0] += translate_x;
+            vertex[1] += translate_y;
+            vertex[2] += translate_z;
+        }
+    });
+}
+
+// * Scaling Transform
+/// Scaled copy of `vertices`, see scale_vertices_in_place.
+pub fn scale_vertices(vertices: &[f32], scale_x: f32, scale_y: f32, scale_z: f32) -> Vec<f32> {
+    let mut scaled_vertices = vertices.to_vec();
+    scale_vertices_in_place(&mut scaled_vertices, scale_x, scale_y, scale_z);
+    scaled_vertices
+}
+
+// * Rotation Transfomations
+/// Function to calculate a rotation matrix around the X-axis
+pub fn rotation_matrix_x(angle: f32) -> glm::Mat4 {
+    glm::rotation(angle, &glm::vec3(1.0, 0.0, 0.0))
+}
+
+/// Function to calculate a rotation matrix around the Y-axis
+pub fn rotation_matrix_y(angle: f32) -> glm::Mat4 {
+    glm::rotation(angle, &glm::vec3(0.0, 1.0, 0.0))
+}
+
+/// Function to calculate a rotation matrix around the Z-axis
+pub fn rotation_matrix_z(angle: f32) -> glm::Mat4 {
+    glm::rotation(angle, &glm::vec3(0.0, 0.0, 1.0))
+}
+
+/// Function to apply a rotation to an array of vertices.
+/// - `vertices`: The input array of vertex positions.
+/// - `rotation_x`: Rotation around the X-axis in radians.
+/// - `rotation_y`: Rotation around the Y-axis in radians.
+/// - `rotation_z`: Rotation around the Z-axis in radians.
+/// Returns a new array of rotated vertices, rotated around X first, then Y, then Z.
+pub fn rotate_vertices(
+    vertices: &[f32],
+    rotation_x: f32,
+    rotation_y: f32,
+    rotation_z: f32
+) -> Vec<f32> {
+    let mut rotated_vertices = vertices.to_vec();
+    rotate_vertices_in_place(&mut rotated_vertices, rotation_x, rotation_y, rotation_z);
+    rotated_vertices
+}
+
+// * Translation Transformation
+/// Function to calculate a translation matrix
+pub fn translation_matrix(translate_x: f32, translate_y: f32, translate_z: f32) -> glm::Mat4 {
+    glm::translation(&glm::vec3(translate_x, translate_y, translate_z))
+}
+
+/// Function to apply a translation to an array of vertices.
+/// - `vertices`: The input array of vertex positions.
+/// - `translate_x`: Translation along the X-axis.
+/// - `translate_y`: Translation along the Y-axis.
+/// - `translate_z`: Translation along the Z-axis.
+/// Returns a new array of translated vertices.
+pub fn translate_vertices(
+    vertices: &[f32],
+    translate_x: f32,
+    translate_y: f32,
+    translate_z: f32
+) -> Vec<f32> {
+    let mut translated_vertices = vertices.to_vec();
+    translate_vertices_in_place(&mut translated_vertices, translate_x, translate_y, translate_z);
+    translated_vertices
+}
+
+
+// * For calculating direction of camera 
+// So that WASD responds to camera movement
+// This means even if we are 180*, ie backwards, when we press W (froward)
+// Without this function => We would go backwards
+// With this function, camera view knows we are back so it takes this into account when transforming 
+// Meaning we will move forward as intended
+pub fn calculate_direction(yaw: f32, pitch: f32) -> glm::Vec3 {
+    glm::vec3(
+        yaw.cos() * pitch.cos(),
+        pitch.sin(),
+        yaw.sin() * pitch.cos(),
+    )
+}
+
+pub fn calculate_right_vector(yaw: f32) -> glm::Vec3 {
+    glm::vec3(yaw.sin(), 0.0, -yaw.cos()).normalize()
+}
+
+pub fn calculate_up_vector(forward: glm::Vec3, right: glm::Vec3) -> glm::Vec3 {
+    glm::cross(&right, &forward).normalize()
+}
+
+// Forward, right and up of the fly-cam. "Right" is the one the cross product with the world up gives,
+// which points to the camera's left (A moves along it, D against it). Taken from the yaw alone, so it
+// doesn't fall apart looking straight up or down.
+pub fn camera_basis(yaw: f32, pitch: f32) -> (glm::Vec3, glm::Vec3, glm::Vec3) {
+    let forward = calculate_direction(yaw, pitch);
+    let right = calculate_right_vector(yaw);
+    let up = glm::normalize(&glm::cross(&forward, &right));
+    (forward, right, up)
+}
+
+// The other way around from calculate_direction: the yaw and pitch that look along `direction`,
+// None for a direction too short to have one
+pub fn yaw_pitch_towards(direction: &glm::Vec3) -> Option<(f32, f32)> {
+    if glm::length(direction) < 1e-6 {
+        return None;
+    }
+    let direction = glm::normalize(direction);
+    Some((direction.z.atan2(direction.x), direction.y.clamp(-1.0, 1.0).asin()))
+}
+
+
+
+// * Camera perspective projection
+// 45 degree vertical field of view. The near and far planes are in the renderer settings, these
+// are where they start out.
+pub const CAMERA_NEAR: f32 = 1.0;
+pub const CAMERA_FAR: f32 = 10000.0;
+const CAMERA_FIELD_OF_VIEW: f32 = 45.0;
+
+// With reversed depth the clip space z goes from 0 at the far plane to 1 at the near one, see gfx::DepthMode
+pub fn camera_perspective(aspect_ratio: f32, near: f32, far: f32, reversed_z: bool) -> glm::Mat4 {
+    if reversed_z {
+        glm::reversed_perspective_rh_zo(aspect_ratio, CAMERA_FIELD_OF_VIEW.to_radians(), near, far)
+    } else {
+        camera_perspective_between(aspect_ratio, near, far)
+    }
+}
+
+// The standard projection, but only for the part of the view between `near` and `far`
+pub fn camera_perspective_between(aspect_ratio: f32, near: f32, far: f32) -> glm::Mat4 {
+    glm::perspective(aspect_ratio, CAMERA_FIELD_OF_VIEW.to_radians(), near, far)
+}
+
+// * Apply transformations to the world from camera view
+pub fn calculate_transformation_from_camera_to_world_view(
+    window_aspect_ratio: f32,
+    near: f32,
+    far: f32,
+    reversed_z: bool,
+    camera_position: glm::Vec3,
+    camera_forward: glm::Vec3,
+    camera_up: glm::Vec3
+) -> glm::Mat4 {
+    // Calculate camera perspective
+    let camera_perspective_matrix: glm::Mat4 = camera_perspective(window_aspect_ratio, near, far, reversed_z);
+
+    // Calculate camera transformations
+    // Build the view matrix based on the camera position and orientation
+    let camera_rotation_matrix = glm::look_at(
+        &camera_position, 
+        &(camera_position + camera_forward), 
+        &camera_up
+    );
+
+    // Combine the matrices
+    let view_projection_matrix: glm::Mat4 = camera_perspective_matrix * camera_rotation_matrix;
+
+    // Return
+    return view_projection_matrix;
+}
+
+
+
+// * Generalized transformation function
+// This function calculates the full transformation matrix for an object, including its position, 
+// rotation, and scaling, and applies the view-projection matrix from the camera.
+// Parameters:
+// - `position`: The object's position in world coordinates.
+// - `rotation`: The object's rotation angles around each axis (x, y, z) in radians.
+// - `scale`: The scaling factor for the object in each direction (x, y, z).
+// - `view_projection_matrix`: The combined view-projection matrix from the camera.
+pub fn calculate_transformation_object(
+    position: glm::Vec3,
+    rotation: glm::Vec3,
+    scale: glm::Vec3,
+    view_projection_matrix: glm::Mat4,
+) -> glm::Mat4 {
+    // Compute rotation in Objects frame
+    let object_rotation_matrix_x = glm::rotation(rotation.x, &glm::vec3(1.0, 0.0, 0.0));
+    let object_rotation_matrix_y = glm::rotation(rotation.y, &glm::vec3(0.0, 1.0, 0.0));
+    let object_rotation_matrix_z = glm::rotation(rotation.z, &glm::vec3(0.0, 0.0, 1.0));
+
+    let object_rotation_matrix: glm::Mat4 = 
+        object_rotation_matrix_z * 
+        object_rotation_matrix_y *
+        object_rotation_matrix_x;
+
+    // Before we do anything else we scale, rotate and put object into start position
+    // 1. Scale
+    // 2. Rotate
+    // 3. Translate
+    let object_transform_matrix: glm::Mat4 = 
+        glm::translation(&position) *
+        object_rotation_matrix *
+        glm::scaling(&scale);
+
+    // Combine matrices to form view projection of rotating orca matrix
+    // 1. Apply start position of Orca
+    // 2. Translate Orca to the origin
+    // 3. Apply rotation animation
+    // 4. Apply translation animation
+    // 5. Translate Orca back to its original position
+    // 6. Apply view-projection transformation
+    let view_projection_matrix_object: glm::Mat4 = 
+        view_projection_matrix * 
+        object_transform_matrix;
+    
+    // Return
+    return view_projection_matrix_object;
+}
+
+
+// * Billboards
+// Where on the screen something drawn facing the camera goes: labels hang at a point, the lens flare
+// and the god rays at the sun, a direction infinitely far away.
+
+/// Where `point` lands in normalized device coordinates, from -1 to 1 across the view. `point` has a w
+/// of 1 for a position and 0 for a direction. None if it is behind the camera.
+pub fn project_to_ndc(view_projection: &glm::Mat4, point: &glm::Vec4) -> Option<glm::Vec2> {
+    let clip = view_projection * point;
+    if clip.w <= 1e-3 {
+        return None;
+    }
+    Some(glm::vec2(clip.x / clip.w, clip.y / clip.w))
+}
+
+// From normalized device coordinates to pixels in a view `width` by `height`, from its lower left corner
+pub fn ndc_to_pixel(ndc: &glm::Vec2, width: f32, height: f32) -> glm::Vec2 {
+    glm::vec2((ndc.x * 0.5 + 0.5) * width, (ndc.y * 0.5 + 0.5) * height)
+}
+
+
+// * Random float generator
+pub fn random_float_in_range(min: f32, max: f32) -> f32 {
+    let mut rng = rand::thread_rng();
+    let range = max - min;
+    rng.gen::<f32>() * range + min
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const TOLERANCE: f32 = 1e-3;
+
+    fn angle() -> impl Strategy<Value = f32> {
+        -10.0f32..10.0
+    }
+
+    fn vertex() -> impl Strategy<Value = Vec<f32>> {
+        prop::collection::vec(-100.0f32..100.0, 3)
+    }
+
+    proptest! {
+        #[test]
+        fn rotation_preserves_length(vertex in vertex(), x in angle(), y in angle(), z in angle()) {
+            let rotated = rotate_vertices(&vertex, x, y, z);
+            let (before, after) = (glm::length(&glm::make_vec3(&vertex)), glm::length(&glm::make_vec3(&rotated)));
+            prop_assert!((before - after).abs() <= TOLERANCE * before.max(1.0));
+        }
+
+        #[test]
+        fn translation_then_back_is_identity(vertex in vertex(), x in -50.0f32..50.0, y in -50.0f32..50.0, z in -50.0f32..50.0) {
+            let moved = translate_vertices(&translate_vertices(&vertex, x, y, z), -x, -y, -z);
+            for (a, b) in vertex.iter().zip(&moved) {
+                prop_assert!((a - b).abs() <= TOLERANCE);
+            }
+        }
+
+        #[test]
+        fn look_at_is_orthonormal(yaw in angle(), pitch in -1.5f32..1.5, x in -100.0f32..100.0, y in -100.0f32..100.0, z in -100.0f32..100.0) {
+            let (forward, _, up) = camera_basis(yaw, pitch);
+            let eye = glm::vec3(x, y, z);
+            let view = glm::look_at(&eye, &(eye + forward), &up);
+            let rotation = glm::mat4_to_mat3(&view);
+            let product = rotation * rotation.transpose();
+            for row in 0..3 {
+                for column in 0..3 {
+                    let expected = if row == column { 1.0 } else { 0.0 };
+                    prop_assert!((product[(row, column)] - expected).abs() <= TOLERANCE);
+                }
+            }
+        }
+
+        #[test]
+        fn camera_basis_is_orthonormal(yaw in angle(), pitch in -1.5f32..1.5) {
+            let (forward, right, up) = camera_basis(yaw, pitch);
+            for axis in [forward, right, up] {
+                prop_assert!((glm::length(&axis) - 1.0).abs() <= TOLERANCE);
+            }
+            prop_assert!(glm::dot(&forward, &right).abs() <= TOLERANCE);
+            prop_assert!(glm::dot(&forward, &up).abs() <= TOLERANCE);
+            prop_assert!(glm::dot(&right, &up).abs() <= TOLERANCE);
+        }
+
+        #[test]
+        fn yaw_pitch_towards_undoes_calculate_direction(yaw in -3.1f32..3.1, pitch in -1.5f32..1.5) {
+            let (found_yaw, found_pitch) = yaw_pitch_towards(&calculate_direction(yaw, pitch)).unwrap();
+            prop_assert!((found_yaw - yaw).abs() <= TOLERANCE);
+            prop_assert!((found_pitch - pitch).abs() <= TOLERANCE);
+        }
+
+        #[test]
+        fn point_ahead_projects_into_view(yaw in angle(), pitch in -1.5f32..1.5) {
+            let (forward, _, up) = camera_basis(yaw, pitch);
+            let view_projection = calculate_transformation_from_camera_to_world_view(1.5, CAMERA_NEAR, CAMERA_FAR, false, glm::zero(), forward, up);
+            let ahead = forward * 50.0;
+            let ndc = project_to_ndc(&view_projection, &glm::vec4(ahead.x, ahead.y, ahead.z, 1.0)).unwrap();
+            prop_assert!(glm::length(&ndc) <= TOLERANCE);
+            prop_assert!(project_to_ndc(&view_projection, &glm::vec4(-ahead.x, -ahead.y, -ahead.z, 1.0)).is_none());
+        }
+    }
+
+    #[test]
+    fn large_arrays_transform_like_small_ones() {
+        let vertices: Vec<f32> = (0..3 * PARALLEL_VERTICES + 2).map(|i| (i % 97) as f32 - 48.0).collect();
+        let matrix = translation_matrix(1.0, 2.0, 3.0) * rotation_matrix_y(0.7) * glm::scaling(&glm::vec3(2.0, 0.5, 1.0));
+        let mut in_blocks = vertices.clone();
+        transform_points_in_place(&mut in_blocks, &matrix);
+        for (vertex, moved) in vertices.chunks_exact(3).zip(in_blocks.chunks_exact(3)) {
+            let expected = matrix * glm::vec4(vertex[0], vertex[1], vertex[2], 1.0);
+            assert_close(&expected.xyz(), &glm::make_vec3(moved));
+        }
+        // The trailing partial vertex is left alone
+        assert_eq!(in_blocks[in_blocks.len() - 2..], vertices[vertices.len() - 2..]);
+    }
+
+    fn assert_close(a: &glm::Vec3, b: &glm::Vec3) {
+        assert!(glm::distance(a, b) <= TOLERANCE * glm::length(a).max(1.0), "{:?} != {:?}", a, b);
+    }
+}
@@ -0,0 +1,183 @@
+use glutin::event::{MouseButton, VirtualKeyCode};
+
+use crate::app_state::AppState;
+use crate::atlas::TextureAtlas;
+use crate::events::InputState;
+use crate::hud::{Anchor, Hud, HudElement, HudElementId};
+use crate::strings::StringTable;
+
+// * Pause menu
+// The menu Escape opens over the scene (see app_state.rs), drawn with the HUD: resume, the settings
+// and quit. The settings page changes what can be changed while running, vsync, the mouse sensitivity
+// and the language (see strings.rs), and they apply as soon as they are changed.
+//
+// The arrow keys (or W and S) move between the items and Enter or Space picks one, left and right
+// change a setting, Escape goes back. The mouse picks the item it is over with a click. The menu only
+// says what was picked, main.rs does it.
+
+const MAX_ITEMS        : usize = 4; // Of any page
+const ITEM_SPACING     : f32 = 14.0; // Cells from one item to the next
+const SENSITIVITY_STEP : f32 = 0.001;
+const SENSITIVITY_MAX  : f32 = 0.02;
+
+const SELECTED_COLOR : [f32; 4] = [1.0, 0.75, 0.1, 1.0];
+const ITEM_COLOR     : [f32; 4] = [0.85, 0.85, 0.9, 1.0];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum MenuPage {
+    Main,
+    Settings,
+}
+
+impl MenuPage {
+    fn item_count(self) -> usize {
+        match self {
+            MenuPage::Main     => 3, // Resume, settings, quit
+            MenuPage::Settings => 4, // Vsync, mouse sensitivity, language, back
+        }
+    }
+}
+
+// What was picked in the menu
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MenuCommand {
+    Resume,
+    Quit,
+    SetVsync(bool),
+    SetMouseSensitivity(f32),
+    NextLanguage,
+}
+
+// The settings as they are now, to show them and change them from
+#[derive(Clone, Copy, Debug)]
+pub struct MenuValues {
+    pub vsync             : bool,
+    pub mouse_sensitivity : f32, // Radians per pixel of mouse movement
+}
+
+pub struct PauseMenu {
+    page        : MenuPage,
+    selected    : usize,
+    last_cursor : (f32, f32),   // The mouse only selects what it is over when it moves
+    panel       : HudElementId,
+    title       : HudElementId,
+    items       : [HudElementId; MAX_ITEMS],
+    paused      : HudElementId, // Shown while paused without the menu
+}
+
+impl PauseMenu {
+    /// Adds the menu to `hud`, hidden until it is opened. `atlas` has to have the HUD sprites in it.
+    pub fn new(hud: &mut Hud, atlas: &TextureAtlas) -> PauseMenu {
+        let panel = hud.add(HudElement::sprite(Anchor::Center, atlas, "hud_panel", (160, 84))
+            .with_color([0.02, 0.03, 0.05, 0.75])
+            .with_visible(false));
+        let title = hud.add(HudElement::text(Anchor::Center, "-")
+            .with_offset((0.0, 2.0 * ITEM_SPACING))
+            .with_visible(false));
+        let items = [0, 1, 2, 3].map(|i| hud.add(HudElement::text(Anchor::Center, "-")
+            .with_offset((0.0, (1.0 - i as f32) * ITEM_SPACING))
+            .with_color(ITEM_COLOR)
+            .with_visible(false)));
+        let paused = hud.add(HudElement::text(Anchor::Top, "-").with_margin((0.0, 12.0)).with_visible(false));
+
+        PauseMenu { page: MenuPage::Main, selected: 0, last_cursor: (0.0, 0.0), panel, title, items, paused }
+    }
+
+    // Start over on the first item of the main page, when the menu opens
+    pub fn reset(&mut self) {
+        self.page = MenuPage::Main;
+        self.selected = 0;
+    }
+
+    /// Takes the input of the frame while the menu is open, and shows the menu as it is now (or hides
+    /// it in the other states). What was picked, if anything.
+    pub fn update(&mut self, hud: &mut Hud, input: &InputState, state: AppState, values: &MenuValues, strings: &StringTable) -> Option<MenuCommand> {
+        let open = state == AppState::Menu;
+        hud.set_visible(self.panel, open);
+        hud.set_visible(self.title, open);
+        hud.set_visible(self.paused, state == AppState::Paused);
+        hud.set_text(self.paused, strings.get("menu.paused"));
+        if !open {
+            for &item in &self.items {
+                hud.set_visible(item, false);
+            }
+            return None;
+        }
+
+        // The item the mouse is over, anywhere along its row of the panel
+        let (window_width, window_height) = input.window_size;
+        let cursor = (input.cursor_position.0, window_height as f32 - input.cursor_position.1);
+        let [panel_x, _, panel_width, _] = hud.element_rect(self.panel, window_width, window_height);
+        let row_height = ITEM_SPACING * Hud::cell_size(window_height);
+        let item_count = self.page.item_count();
+        let hovered = self.items[..item_count].iter().position(|&item| {
+            let [_, y, _, height] = hud.element_rect(item, window_width, window_height);
+            let middle = y + height / 2.0;
+            (panel_x..panel_x + panel_width).contains(&cursor.0) && (middle - row_height / 2.0..middle + row_height / 2.0).contains(&cursor.1)
+        });
+        let cursor_moved = input.cursor_position != self.last_cursor;
+        self.last_cursor = input.cursor_position;
+
+        let pressed = |keys: &[VirtualKeyCode]| keys.iter().any(|&key| input.was_just_pressed(key));
+        if pressed(&[VirtualKeyCode::Up, VirtualKeyCode::W]) {
+            self.selected = (self.selected + item_count - 1) % item_count;
+        }
+        if pressed(&[VirtualKeyCode::Down, VirtualKeyCode::S]) {
+            self.selected = (self.selected + 1) % item_count;
+        }
+        if let Some(hovered) = hovered.filter(|_| cursor_moved || input.was_just_clicked(MouseButton::Left)) {
+            self.selected = hovered;
+        }
+        let activated = pressed(&[VirtualKeyCode::Return, VirtualKeyCode::NumpadEnter, VirtualKeyCode::Space])
+            || (hovered.is_some() && input.was_just_clicked(MouseButton::Left));
+        let step = pressed(&[VirtualKeyCode::Right, VirtualKeyCode::D]) as i32 - pressed(&[VirtualKeyCode::Left, VirtualKeyCode::A]) as i32;
+
+        let command = match self.page {
+            MenuPage::Main if pressed(&[VirtualKeyCode::Escape]) => Some(MenuCommand::Resume),
+            MenuPage::Main if activated => match self.selected {
+                0 => Some(MenuCommand::Resume),
+                1 => {
+                    self.page = MenuPage::Settings;
+                    self.selected = 0;
+                    None
+                }
+                _ => Some(MenuCommand::Quit),
+            },
+            MenuPage::Main => None,
+            MenuPage::Settings if pressed(&[VirtualKeyCode::Escape]) || (activated && self.selected == 3) => {
+                self.page = MenuPage::Main;
+                self.selected = 1;
+                None
+            }
+            MenuPage::Settings => match self.selected {
+                0 if activated || step != 0 => Some(MenuCommand::SetVsync(!values.vsync)),
+                1 if step != 0 => Some(MenuCommand::SetMouseSensitivity(
+                    (values.mouse_sensitivity + step as f32 * SENSITIVITY_STEP).clamp(SENSITIVITY_STEP, SENSITIVITY_MAX))),
+                2 if activated || step != 0 => Some(MenuCommand::NextLanguage),
+                _ => None,
+            },
+        };
+
+        let item_count = self.page.item_count();
+        let labels = match self.page {
+            MenuPage::Main => vec![
+                strings.get("menu.resume").to_string(),
+                strings.get("menu.settings").to_string(),
+                strings.get("menu.quit").to_string(),
+            ],
+            MenuPage::Settings => vec![
+                strings.format("menu.vsync", &[("state", strings.get(if values.vsync { "menu.on" } else { "menu.off" }))]),
+                strings.format("menu.mouse_sensitivity", &[("value", &format!("{:.0}", values.mouse_sensitivity / SENSITIVITY_STEP))]),
+                strings.format("menu.language", &[("language", strings.get("language.name"))]),
+                strings.get("menu.back").to_string(),
+            ],
+        };
+        hud.set_text(self.title, strings.get(match self.page { MenuPage::Main => "menu.paused", MenuPage::Settings => "menu.settings" }));
+        for (i, &item) in self.items.iter().enumerate() {
+            hud.set_visible(item, i < item_count);
+            hud.set_text(item, labels.get(i).map_or("-", |label| label.as_str()));
+            hud.set_color(item, if i == self.selected { SELECTED_COLOR } else { ITEM_COLOR });
+        }
+        command
+    }
+}
@@ -1,16 +1,21 @@
+extern crate nalgebra_glm as glm;
+use std::collections::HashMap;
+use std::io::{BufWriter, Write};
 use tobj;
 
-// internal helper
-fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
+// `num` copies of `color`, one per vertex
+pub fn generate_color_vec(color: [f32; 4], num: usize) -> Vec<f32> {
     color.iter().cloned().cycle().take(num*4).collect()
 }
 
 // Mesh
 
+#[derive(Clone)]
 pub struct Mesh {
     pub vertices    : Vec<f32>,
     pub normals     : Vec<f32>,
     pub colors      : Vec<f32>,
+    pub texcoords   : Vec<f32>, // Two per vertex, empty if the file has none
     pub indices     : Vec<u32>,
     pub index_count : i32,
 }
@@ -19,14 +24,815 @@ impl Mesh {
     pub fn from(mesh: tobj::Mesh, color: [f32; 4]) -> Self {
         let num_verts = mesh.positions.len() / 3;
         let index_count = mesh.indices.len() as i32;
+        // Not every exporter writes normals (Tinkercad doesn't), and create_vao needs them
+        let normals = if mesh.normals.is_empty() {
+            generate_normals(&mesh.positions, &mesh.indices)
+        } else {
+            mesh.normals
+        };
+        // Vertex colors in the file (like the ones export_obj writes) are tinted by `color`
+        let colors = if mesh.vertex_color.len() == mesh.positions.len() {
+            mesh.vertex_color.chunks(3)
+                .flat_map(|rgb| [rgb[0] * color[0], rgb[1] * color[1], rgb[2] * color[2], color[3]])
+                .collect()
+        } else {
+            generate_color_vec(color, num_verts)
+        };
         Mesh {
             vertices: mesh.positions,
-            normals: mesh.normals,
+            normals,
             indices: mesh.indices,
-            colors: generate_color_vec(color, num_verts),
+            colors,
+            texcoords: mesh.texcoords,
             index_count,
         }
     }
+
+    // Paint the whole mesh in a single color
+    pub fn set_color(&mut self, color: [f32; 4]) {
+        self.colors = generate_color_vec(color, self.vertices.len() / 3);
+    }
+
+    // Corners of the axis aligned box around the vertices, both at the origin for an empty mesh
+    pub fn bounds(&self) -> (glm::Vec3, glm::Vec3) {
+        if self.vertices.len() < 3 {
+            return (glm::zero(), glm::zero());
+        }
+        let mut min = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+        let mut max = -min;
+        for vertex in self.vertices.chunks(3) {
+            let vertex = glm::vec3(vertex[0], vertex[1], vertex[2]);
+            min = glm::min2(&min, &vertex);
+            max = glm::max2(&max, &vertex);
+        }
+        (min, max)
+    }
+
+    // Middle of the bounding box. For a symmetric part like a rotor this is its hub, which makes it
+    // the point to rotate it about (see SceneNode::set_pivot_from_mesh).
+    pub fn center(&self) -> glm::Vec3 {
+        let (min, max) = self.bounds();
+        (min + max) * 0.5
+    }
+}
+
+// OBJ export
+// Write a mesh to an OBJ file, to look at generated or transformed geometry in Blender or to keep it
+// as a static asset. The vertex colors go after the positions (`v x y z r g b`), which Blender and
+// tobj both read, and every face refers to the normal of each of its vertices (`f 1//1 2//2 3//3`).
+pub fn export_obj(path: &str, mesh: &Mesh) -> std::io::Result<()> {
+    let mut file = BufWriter::new(std::fs::File::create(path)?);
+    writeln!(file, "# {} vertices, {} triangles", mesh.vertices.len() / 3, mesh.indices.len() / 3)?;
+    writeln!(file, "o {}", std::path::Path::new(path).file_stem().map_or("mesh".into(), |stem| stem.to_string_lossy()))?;
+
+    let has_colors = mesh.colors.len() / 4 == mesh.vertices.len() / 3;
+    for (i, position) in mesh.vertices.chunks(3).enumerate() {
+        if has_colors {
+            let color = &mesh.colors[4 * i..4 * i + 3]; // OBJ has no alpha
+            writeln!(file, "v {} {} {} {} {} {}", position[0], position[1], position[2], color[0], color[1], color[2])?;
+        } else {
+            writeln!(file, "v {} {} {}", position[0], position[1], position[2])?;
+        }
+    }
+
+    let has_normals = mesh.normals.len() == mesh.vertices.len();
+    if has_normals {
+        for normal in mesh.normals.chunks(3) {
+            writeln!(file, "vn {} {} {}", normal[0], normal[1], normal[2])?;
+        }
+    }
+
+    // OBJ counts from 1
+    for triangle in mesh.indices.chunks(3).filter(|triangle| triangle.len() == 3) {
+        if has_normals {
+            writeln!(file, "f {0}//{0} {1}//{1} {2}//{2}", triangle[0] + 1, triangle[1] + 1, triangle[2] + 1)?;
+        } else {
+            writeln!(file, "f {} {} {}", triangle[0] + 1, triangle[1] + 1, triangle[2] + 1)?;
+        }
+    }
+    file.flush()
+}
+
+// STL and PLY import
+// Both give a single Mesh. STL has no shared vertices, every triangle gets its own three with the
+// face normal, so printed parts look faceted like they do in slicers. PLY keeps its vertices, normals
+// and colors when the file has them. A PLY without faces (a point cloud scan) becomes a tiny
+// tetrahedron per point, since everything here is drawn as triangles.
+
+impl Mesh {
+    // A mesh from plain arrays, with normals worked out if there are none
+    pub fn from_arrays(vertices: Vec<f32>, normals: Vec<f32>, colors: Vec<f32>, indices: Vec<u32>) -> Self {
+        let normals = if normals.len() == vertices.len() { normals } else { generate_normals(&vertices, &indices) };
+        let colors = if colors.len() / 4 == vertices.len() / 3 { colors } else { generate_color_vec([1.0, 1.0, 1.0, 1.0], vertices.len() / 3) };
+        Mesh { index_count: indices.len() as i32, vertices, normals, colors, texcoords: vec![], indices }
+    }
+}
+
+pub fn load_stl(path: &str, color: [f32; 4]) -> Result<Mesh, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    // ASCII files start with "solid", but so do some binary ones, so go by the size a binary file would have
+    let binary_triangles = bytes.get(80..84).map(|count| u32::from_le_bytes([count[0], count[1], count[2], count[3]]) as usize);
+    let is_binary = binary_triangles.is_some_and(|count| bytes.len() == 84 + count * 50);
+
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut add_triangle = |normal: glm::Vec3, corners: [glm::Vec3; 3]| {
+        // Exporters are allowed to write a zero normal and let the reader work it out. A triangle
+        // without one that is also a line or a point covers nothing, so it is left out.
+        let normal = if glm::length(&normal) > 1e-6 {
+            glm::normalize(&normal)
+        } else {
+            let cross = glm::cross(&(corners[1] - corners[0]), &(corners[2] - corners[0]));
+            if glm::length(&cross) <= 1e-12 {
+                return;
+            }
+            glm::normalize(&cross)
+        };
+        for corner in &corners {
+            vertices.extend_from_slice(&[corner.x, corner.y, corner.z]);
+            normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+        }
+    };
+
+    if is_binary {
+        let float = |offset: usize| f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]);
+        let vector = |offset: usize| glm::vec3(float(offset), float(offset + 4), float(offset + 8));
+        for triangle in 0..binary_triangles.unwrap_or(0) {
+            let offset = 84 + triangle * 50; // Normal, three corners, two unused bytes
+            add_triangle(vector(offset), [vector(offset + 12), vector(offset + 24), vector(offset + 36)]);
+        }
+    } else {
+        let text = String::from_utf8_lossy(&bytes);
+        let mut tokens = text.split_whitespace();
+        let numbers = |tokens: &mut std::str::SplitWhitespace, what: &str| -> Result<glm::Vec3, String> {
+            let mut number = || tokens.next()
+                .and_then(|token| token.parse::<f32>().ok())
+                .ok_or_else(|| format!("{}: expected three numbers after '{}'", path, what));
+            Ok(glm::vec3(number()?, number()?, number()?))
+        };
+        let mut normal = glm::zero();
+        let mut corners = vec![];
+        while let Some(token) = tokens.next() {
+            match token {
+                "normal" => normal = numbers(&mut tokens, "normal")?,
+                "vertex" => corners.push(numbers(&mut tokens, "vertex")?),
+                "endfacet" => {
+                    if corners.len() != 3 {
+                        return Err(format!("{}: a facet with {} vertices, expected 3", path, corners.len()));
+                    }
+                    add_triangle(normal, [corners[0], corners[1], corners[2]]);
+                    corners.clear();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let indices = (0..(vertices.len() / 3) as u32).collect();
+    let mut mesh = Mesh::from_arrays(vertices, normals, vec![], indices);
+    mesh.set_color(color);
+    Ok(mesh)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PlyType { I8, U8, I16, U16, I32, U32, F32, F64 }
+
+impl PlyType {
+    fn parse(name: &str) -> Option<PlyType> {
+        Some(match name {
+            "char" | "int8"     => PlyType::I8,
+            "uchar" | "uint8"   => PlyType::U8,
+            "short" | "int16"   => PlyType::I16,
+            "ushort" | "uint16" => PlyType::U16,
+            "int" | "int32"     => PlyType::I32,
+            "uint" | "uint32"   => PlyType::U32,
+            "float" | "float32" => PlyType::F32,
+            "double" | "float64" => PlyType::F64,
+            _ => return None,
+        })
+    }
+
+    fn size(self) -> usize {
+        match self {
+            PlyType::I8 | PlyType::U8 => 1,
+            PlyType::I16 | PlyType::U16 => 2,
+            PlyType::I32 | PlyType::U32 | PlyType::F32 => 4,
+            PlyType::F64 => 8,
+        }
+    }
+}
+
+struct PlyProperty {
+    name : String,
+    kind : PlyType,
+    list : Option<PlyType>, // Type of the item count, for list properties
+}
+
+struct PlyElement {
+    name       : String,
+    count      : usize,
+    properties : Vec<PlyProperty>,
+}
+
+// The data after the header, one number at a time
+enum PlyBody<'a> {
+    Ascii(std::str::SplitWhitespace<'a>),
+    Binary { bytes: &'a [u8], position: usize, big_endian: bool },
+}
+
+impl<'a> PlyBody<'a> {
+    fn read(&mut self, kind: PlyType) -> Result<f64, String> {
+        match self {
+            PlyBody::Ascii(tokens) => tokens.next()
+                .and_then(|token| token.parse::<f64>().ok())
+                .ok_or_else(|| "ran out of numbers".to_string()),
+            PlyBody::Binary { bytes, position, big_endian } => {
+                let size = kind.size();
+                let mut raw = [0u8; 8];
+                raw[..size].copy_from_slice(bytes.get(*position..*position + size).ok_or("ran out of data")?);
+                if *big_endian {
+                    raw[..size].reverse();
+                }
+                *position += size;
+                Ok(match kind {
+                    PlyType::I8  => raw[0] as i8 as f64,
+                    PlyType::U8  => raw[0] as f64,
+                    PlyType::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    PlyType::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+                    PlyType::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+                    PlyType::F64 => f64::from_le_bytes(raw),
+                })
+            }
+        }
+    }
+}
+
+pub fn load_ply(path: &str, color: [f32; 4]) -> Result<Mesh, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let error = |message: String| format!("{}: {}", path, message);
+
+    // * Header
+    let header_end = bytes.windows(10).position(|window| window == b"end_header")
+        .ok_or_else(|| error("no end_header, not a PLY file".to_string()))?;
+    let body_start = bytes[header_end..].iter().position(|&byte| byte == b'\n').map_or(bytes.len(), |newline| header_end + newline + 1);
+    let header = String::from_utf8_lossy(&bytes[..header_end]);
+
+    let mut format = None;
+    let mut elements: Vec<PlyElement> = vec![];
+    for line in header.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        match fields.as_slice() {
+            ["format", name, _version] => format = Some(name.to_string()),
+            ["element", name, count] => elements.push(PlyElement {
+                name: name.to_string(),
+                count: count.parse().map_err(|_| error(format!("bad element count '{}'", count)))?,
+                properties: vec![],
+            }),
+            ["property", "list", count_type, item_type, name] => {
+                let property = PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::parse(item_type).ok_or_else(|| error(format!("unknown type '{}'", item_type)))?,
+                    list: Some(PlyType::parse(count_type).ok_or_else(|| error(format!("unknown type '{}'", count_type)))?),
+                };
+                elements.last_mut().ok_or_else(|| error("property before any element".to_string()))?.properties.push(property);
+            }
+            ["property", kind, name] => {
+                let property = PlyProperty {
+                    name: name.to_string(),
+                    kind: PlyType::parse(kind).ok_or_else(|| error(format!("unknown type '{}'", kind)))?,
+                    list: None,
+                };
+                elements.last_mut().ok_or_else(|| error("property before any element".to_string()))?.properties.push(property);
+            }
+            _ => {} // ply, comment, obj_info
+        }
+    }
+
+    let text;
+    let mut body = match format.as_deref() {
+        Some("ascii") => {
+            text = String::from_utf8_lossy(&bytes[body_start..]);
+            PlyBody::Ascii(text.split_whitespace())
+        }
+        Some("binary_little_endian") => PlyBody::Binary { bytes: &bytes[body_start..], position: 0, big_endian: false },
+        Some("binary_big_endian") => PlyBody::Binary { bytes: &bytes[body_start..], position: 0, big_endian: true },
+        other => return Err(error(format!("unsupported format {:?}", other))),
+    };
+
+    // * Data, the elements come in the order of the header
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut colors = vec![];
+    let mut indices = vec![];
+    for element in &elements {
+        let has_normals = element.properties.iter().any(|property| property.name == "nx");
+        let has_colors = element.properties.iter().any(|property| property.name == "red");
+
+        for _ in 0..element.count {
+            let mut position = [0.0f32; 3];
+            let mut normal = [0.0f32; 3];
+            let mut vertex_color = [1.0f32; 4];
+            for property in &element.properties {
+                if let Some(count_type) = property.list {
+                    let count = body.read(count_type).map_err(&error)? as usize;
+                    let items = (0..count).map(|_| body.read(property.kind)).collect::<Result<Vec<f64>, String>>().map_err(&error)?;
+                    if element.name == "face" && (property.name == "vertex_indices" || property.name == "vertex_index") {
+                        // Polygons are split up into a fan of triangles
+                        for i in 1..items.len().saturating_sub(1) {
+                            indices.extend_from_slice(&[items[0] as u32, items[i] as u32, items[i + 1] as u32]);
+                        }
+                    }
+                    continue;
+                }
+
+                let value = body.read(property.kind).map_err(&error)? as f32;
+                // Integer colors go from 0 to 255, float colors from 0 to 1
+                let channel = if property.kind == PlyType::F32 || property.kind == PlyType::F64 { value } else { value / 255.0 };
+                match property.name.as_str() {
+                    "x" => position[0] = value,
+                    "y" => position[1] = value,
+                    "z" => position[2] = value,
+                    "nx" => normal[0] = value,
+                    "ny" => normal[1] = value,
+                    "nz" => normal[2] = value,
+                    "red" => vertex_color[0] = channel,
+                    "green" => vertex_color[1] = channel,
+                    "blue" => vertex_color[2] = channel,
+                    "alpha" => vertex_color[3] = channel,
+                    _ => {}
+                }
+            }
+
+            if element.name == "vertex" {
+                vertices.extend_from_slice(&position);
+                if has_normals {
+                    normals.extend_from_slice(&normal);
+                }
+                if has_colors {
+                    colors.extend_from_slice(&vertex_color);
+                } else {
+                    colors.extend_from_slice(&color);
+                }
+            }
+        }
+    }
+
+    if let Some(&index) = indices.iter().find(|&&index| index as usize >= vertices.len() / 3) {
+        return Err(error(format!("a face uses vertex {}, there are only {}", index, vertices.len() / 3)));
+    }
+    if indices.is_empty() {
+        return Ok(point_cloud(&vertices, &colors));
+    }
+    Ok(Mesh::from_arrays(vertices, normals, colors, indices))
+}
+
+// internal helper
+// A small tetrahedron at every point, sized from how spread out the points are
+fn point_cloud(points: &[f32], colors: &[f32]) -> Mesh {
+    let mut min = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+    let mut max = -min;
+    for point in points.chunks(3) {
+        let point = glm::vec3(point[0], point[1], point[2]);
+        min = glm::min2(&min, &point);
+        max = glm::max2(&max, &point);
+    }
+    let size = if points.is_empty() { 0.0 } else { glm::distance(&min, &max) * 0.002 };
+    let corners = [
+        glm::vec3( 1.0,  1.0,  1.0),
+        glm::vec3( 1.0, -1.0, -1.0),
+        glm::vec3(-1.0,  1.0, -1.0),
+        glm::vec3(-1.0, -1.0,  1.0),
+    ];
+    let faces = [[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]];
+
+    let mut mesh = Mesh::from_arrays(vec![], vec![], vec![], vec![]);
+    for (i, point) in points.chunks(3).enumerate() {
+        let first = (mesh.vertices.len() / 3) as u32;
+        for corner in &corners {
+            let normal = glm::normalize(corner);
+            mesh.vertices.extend_from_slice(&[point[0] + corner.x * size, point[1] + corner.y * size, point[2] + corner.z * size]);
+            mesh.normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+            mesh.colors.extend_from_slice(&colors[4 * i..4 * i + 4]);
+        }
+        for face in &faces {
+            mesh.indices.extend(face.iter().map(|&corner| first + corner));
+        }
+    }
+    mesh.index_count = mesh.indices.len() as i32;
+    mesh
+}
+
+// Mesh simplification
+// Quadric error metric edge collapses (Garland and Heckbert): every vertex keeps track of the planes of
+// the triangles around it, and the edge whose endpoints can be merged while staying closest to those
+// planes is collapsed first, over and over until few enough triangles are left. Flat areas go first and
+// sharp features last. Edges on the outline of the mesh get extra planes standing up along them, so the
+// outline stays where it is. Collapses that would flip a triangle over are skipped.
+
+// Upper triangle of a symmetric 4x4 matrix, in f64 since the sums get big
+#[derive(Clone, Copy, Default)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    // Squared distance to the plane a*x + b*y + c*z + d = 0 (with a unit normal), times `weight`
+    fn plane(normal: glm::DVec3, d: f64, weight: f64) -> Quadric {
+        let (a, b, c) = (normal.x, normal.y, normal.z);
+        Quadric([a * a, a * b, a * c, a * d, b * b, b * c, b * d, c * c, c * d, d * d].map(|q| q * weight))
+    }
+
+    fn add(&self, other: &Quadric) -> Quadric {
+        let mut sum = self.0;
+        for (q, other) in sum.iter_mut().zip(&other.0) {
+            *q += other;
+        }
+        Quadric(sum)
+    }
+
+    fn error(&self, p: &glm::DVec3) -> f64 {
+        let q = &self.0;
+        q[0] * p.x * p.x + 2.0 * q[1] * p.x * p.y + 2.0 * q[2] * p.x * p.z + 2.0 * q[3] * p.x
+            + q[4] * p.y * p.y + 2.0 * q[5] * p.y * p.z + 2.0 * q[6] * p.y
+            + q[7] * p.z * p.z + 2.0 * q[8] * p.z
+            + q[9]
+    }
+
+    // The point with the least error, if there is a single one
+    fn minimum(&self) -> Option<glm::DVec3> {
+        let q = &self.0;
+        let a = glm::DMat3::new(q[0], q[1], q[2], q[1], q[4], q[5], q[2], q[5], q[7]);
+        if a.determinant().abs() < 1e-12 {
+            return None;
+        }
+        a.try_inverse().map(|inverse| inverse * -glm::DVec3::new(q[3], q[6], q[8]))
+    }
+}
+
+// Where to merge the two ends of an edge to, and how far off that is. Also gives how far along the
+// edge the point is (0 = at `a`), for the colors and texture coordinates.
+fn collapse_target(quadric: &Quadric, a: &glm::DVec3, b: &glm::DVec3) -> (f64, glm::DVec3, f64) {
+    let mut candidates = vec![(*a, 0.0), (*b, 1.0), ((a + b) * 0.5, 0.5)];
+    if let Some(optimal) = quadric.minimum() {
+        let edge = b - a;
+        let t = (glm::dot(&(optimal - a), &edge) / glm::dot(&edge, &edge).max(1e-20)).clamp(0.0, 1.0);
+        // Far away from the edge means the quadric is nearly flat in some direction, don't trust it
+        if glm::distance(&optimal, &(a + edge * t)) < glm::length(&edge) * 2.0 {
+            candidates.push((optimal, t));
+        }
+    }
+    candidates.into_iter()
+        .map(|(point, t)| (quadric.error(&point), point, t))
+        .min_by(|x, y| x.0.total_cmp(&y.0))
+        .unwrap()
+}
+
+#[derive(PartialEq)]
+struct Collapse {
+    cost     : f64,
+    a        : u32,
+    b        : u32,
+    versions : (u32, u32), // Of a and b when this was worked out, the collapse is stale if either changed
+}
+
+impl Eq for Collapse {}
+
+impl Ord for Collapse {
+    // Cheapest first out of BinaryHeap, which gives the largest
+    fn cmp(&self, other: &Collapse) -> std::cmp::Ordering {
+        other.cost.total_cmp(&self.cost)
+    }
+}
+
+impl PartialOrd for Collapse {
+    fn partial_cmp(&self, other: &Collapse) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+// A copy of the mesh with about `target_ratio` of its triangles (0.25 keeps a quarter). The normals are
+// worked out again for the new shape.
+pub fn simplify(mesh: &Mesh, target_ratio: f32) -> Mesh {
+    let vertex_count = mesh.vertices.len() / 3;
+    let mut positions: Vec<glm::DVec3> = mesh.vertices.chunks(3)
+        .map(|p| glm::DVec3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+        .collect();
+    let mut colors = mesh.colors.clone();
+    let mut texcoords = mesh.texcoords.clone();
+    let has_colors = colors.len() == vertex_count * 4;
+    let has_texcoords = texcoords.len() == vertex_count * 2;
+
+    let mut faces: Vec<[u32; 3]> = mesh.indices.chunks(3)
+        .filter(|face| face.len() == 3)
+        .map(|face| [face[0], face[1], face[2]])
+        .collect();
+    let target = ((faces.len() as f32 * target_ratio.clamp(0.0, 1.0)) as usize).max(1);
+    let mut face_alive = vec![true; faces.len()];
+    let mut face_count = faces.len();
+    let mut vertex_faces: Vec<Vec<usize>> = vec![vec![]; vertex_count];
+    for (f, face) in faces.iter().enumerate() {
+        for &v in face {
+            vertex_faces[v as usize].push(f);
+        }
+    }
+
+    let face_normal = |positions: &[glm::DVec3], face: &[u32; 3]| {
+        let [a, b, c] = face.map(|v| positions[v as usize]);
+        glm::cross(&(b - a), &(c - a))
+    };
+
+    // * Quadrics from the planes of the triangles, weighted by area
+    let mut quadrics = vec![Quadric::default(); vertex_count];
+    let mut edge_faces: HashMap<(u32, u32), Vec<usize>> = HashMap::new();
+    for (f, face) in faces.iter().enumerate() {
+        let normal = face_normal(&positions, face);
+        let area = glm::length(&normal) * 0.5;
+        if area > 1e-20 {
+            let normal = glm::normalize(&normal);
+            let plane = Quadric::plane(normal, -glm::dot(&normal, &positions[face[0] as usize]), area);
+            for &v in face {
+                quadrics[v as usize] = quadrics[v as usize].add(&plane);
+            }
+        }
+        for i in 0..3 {
+            let (a, b) = (face[i], face[(i + 1) % 3]);
+            edge_faces.entry((a.min(b), a.max(b))).or_default().push(f);
+        }
+    }
+    // Edges with a triangle on one side only are the outline
+    for (&(a, b), edge_faces) in &edge_faces {
+        if let [f] = edge_faces.as_slice() {
+            let edge = positions[b as usize] - positions[a as usize];
+            let normal = face_normal(&positions, &faces[*f]);
+            let side = glm::cross(&edge, &normal);
+            if glm::length(&side) > 1e-20 {
+                let side = glm::normalize(&side);
+                let plane = Quadric::plane(side, -glm::dot(&side, &positions[a as usize]), glm::length2(&edge) * 1000.0);
+                quadrics[a as usize] = quadrics[a as usize].add(&plane);
+                quadrics[b as usize] = quadrics[b as usize].add(&plane);
+            }
+        }
+    }
+
+    // * Collapse the cheapest edge until there are few enough triangles
+    let mut versions = vec![0u32; vertex_count];
+    let mut vertex_alive = vec![true; vertex_count];
+    let mut heap = std::collections::BinaryHeap::new();
+    let collapse = |positions: &[glm::DVec3], quadrics: &[Quadric], versions: &[u32], a: u32, b: u32| {
+        let (cost, _, _) = collapse_target(&quadrics[a as usize].add(&quadrics[b as usize]), &positions[a as usize], &positions[b as usize]);
+        Collapse { cost, a, b, versions: (versions[a as usize], versions[b as usize]) }
+    };
+    for &(a, b) in edge_faces.keys() {
+        heap.push(collapse(&positions, &quadrics, &versions, a, b));
+    }
+
+    while face_count > target {
+        let Collapse { a, b, versions: edge_versions, .. } = match heap.pop() {
+            Some(collapse) => collapse,
+            None => break,
+        };
+        let (a_index, b_index) = (a as usize, b as usize);
+        if !vertex_alive[a_index] || !vertex_alive[b_index] || edge_versions != (versions[a_index], versions[b_index]) {
+            continue;
+        }
+        let quadric = quadrics[a_index].add(&quadrics[b_index]);
+        let (_, target_position, t) = collapse_target(&quadric, &positions[a_index], &positions[b_index]);
+
+        // The triangles that stay (they have only one of the two) must not turn over
+        let mut moved_positions = vec![];
+        let flips = vertex_faces[a_index].iter().chain(&vertex_faces[b_index])
+            .filter(|&&f| face_alive[f] && !(faces[f].contains(&a) && faces[f].contains(&b)))
+            .any(|&f| {
+                let before = face_normal(&positions, &faces[f]);
+                moved_positions.clear();
+                moved_positions.extend(faces[f].iter().map(|&v| if v == a || v == b { target_position } else { positions[v as usize] }));
+                let after = glm::cross(&(moved_positions[1] - moved_positions[0]), &(moved_positions[2] - moved_positions[0]));
+                glm::dot(&before, &after) <= 0.0
+            });
+        if flips {
+            continue;
+        }
+
+        // Merge b into a
+        positions[a_index] = target_position;
+        quadrics[a_index] = quadric;
+        if has_colors {
+            for channel in 0..4 {
+                colors[4 * a_index + channel] += (colors[4 * b_index + channel] - colors[4 * a_index + channel]) * t as f32;
+            }
+        }
+        if has_texcoords {
+            for axis in 0..2 {
+                texcoords[2 * a_index + axis] += (texcoords[2 * b_index + axis] - texcoords[2 * a_index + axis]) * t as f32;
+            }
+        }
+        vertex_alive[b_index] = false;
+        versions[a_index] += 1;
+
+        let b_faces = std::mem::take(&mut vertex_faces[b_index]);
+        for f in b_faces {
+            if !face_alive[f] {
+                continue;
+            }
+            if faces[f].contains(&a) {
+                face_alive[f] = false;
+                face_count -= 1;
+            } else {
+                for v in faces[f].iter_mut().filter(|v| **v == b) {
+                    *v = a;
+                }
+                vertex_faces[a_index].push(f);
+            }
+        }
+        vertex_faces[a_index].retain(|&f| face_alive[f]);
+
+        let mut neighbours: Vec<u32> = vertex_faces[a_index].iter().flat_map(|&f| faces[f]).filter(|&v| v != a).collect();
+        neighbours.sort_unstable();
+        neighbours.dedup();
+        for neighbour in neighbours {
+            heap.push(collapse(&positions, &quadrics, &versions, a, neighbour));
+        }
+    }
+
+    // * Pack what is left into a new mesh
+    let mut remap = vec![u32::MAX; vertex_count];
+    let mut simplified = Mesh::from_arrays(vec![], vec![], vec![], vec![]);
+    for face in faces.iter().zip(&face_alive).filter(|(_, &alive)| alive).map(|(face, _)| face) {
+        for &v in face {
+            let v = v as usize;
+            if remap[v] == u32::MAX {
+                remap[v] = (simplified.vertices.len() / 3) as u32;
+                simplified.vertices.extend_from_slice(&[positions[v].x as f32, positions[v].y as f32, positions[v].z as f32]);
+                if has_colors {
+                    simplified.colors.extend_from_slice(&colors[4 * v..4 * v + 4]);
+                }
+                if has_texcoords {
+                    simplified.texcoords.extend_from_slice(&texcoords[2 * v..2 * v + 2]);
+                }
+            }
+            simplified.indices.push(remap[v]);
+        }
+    }
+    simplified.normals = generate_normals(&simplified.vertices, &simplified.indices);
+    if !has_colors {
+        simplified.set_color([1.0, 1.0, 1.0, 1.0]);
+    }
+    simplified.index_count = simplified.indices.len() as i32;
+    simplified
+}
+
+// Smooth vertex normals by adding up the (area weighted) face normals of every triangle a vertex is part of
+pub fn generate_normals(positions: &[f32], indices: &[u32]) -> Vec<f32> {
+    let mut normals = vec![0.0f32; positions.len()];
+    let vertex = |i: u32| glm::vec3(positions[3 * i as usize], positions[3 * i as usize + 1], positions[3 * i as usize + 2]);
+
+    for triangle in indices.chunks(3) {
+        if triangle.len() < 3 { continue; }
+        let face_normal = glm::cross(&(vertex(triangle[1]) - vertex(triangle[0])), &(vertex(triangle[2]) - vertex(triangle[0])));
+        for &i in triangle {
+            for axis in 0..3 {
+                normals[3 * i as usize + axis] += face_normal[axis];
+            }
+        }
+    }
+
+    for normal in normals.chunks_mut(3) {
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        if length > 0.0 {
+            normal.iter_mut().for_each(|n| *n /= length);
+        }
+    }
+    normals
+}
+
+// Multi part model
+// Every named object/group in the OBJ file becomes its own Mesh, so any model can be
+// split into parts (rotors, doors, wheels...) without writing a new struct for it.
+
+#[derive(Clone)]
+pub struct MultiPartModel {
+    pub name  : String,
+    pub parts : Vec<(String, Mesh)>, // (OBJ object name, mesh), in file order
+}
+
+impl MultiPartModel {
+    pub fn load(name: &str, path: &str, color: [f32; 4]) -> Self {
+        println!("Loading {} model...", name);
+
+        // STL and PLY files have a single part, named after the file
+        let extension = std::path::Path::new(path).extension().map(|extension| extension.to_string_lossy().to_lowercase());
+        let single_part = match extension.as_deref() {
+            Some("stl") => Some(load_stl(path, color)),
+            Some("ply") => Some(load_ply(path, color)),
+            _ => None,
+        };
+        if let Some(mesh) = single_part {
+            let mesh = mesh.unwrap_or_else(|e| panic!("Failed to load {} model. {}", name, e));
+            let part_name = std::path::Path::new(path).file_stem().map_or(name.to_string(), |stem| stem.to_string_lossy().to_string());
+            println!("Loaded {} with {} points and {} triangles.", part_name, mesh.vertices.len() / 3, mesh.indices.len() / 3);
+            return MultiPartModel { name: name.to_string(), parts: vec![(part_name, mesh)] };
+        }
+
+        let before = std::time::Instant::now();
+        let (models, _materials)
+            = tobj::load_obj(path,
+                &tobj::LoadOptions{
+                    triangulate: true,
+                    single_index: true,
+                    ..Default::default()
+                }
+            ).unwrap_or_else(|error| panic!("Failed to load {} model: {:?}", name, error));
+        let after = std::time::Instant::now();
+        println!("Done in {:.3}ms.", after.duration_since(before).as_micros() as f32 / 1e3);
+
+        for model in &models {
+            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
+        }
+
+        MultiPartModel {
+            name: name.to_string(),
+            parts: models.into_iter().map(|m| (m.name, Mesh::from(m.mesh, color))).collect(),
+        }
+    }
+
+    pub fn part(&self, name: &str) -> Option<&Mesh> {
+        self.parts.iter().find(|(n, _)| n == name).map(|(_, mesh)| mesh)
+    }
+
+    // Takes a part out of the model, handy when the parts need to be owned by something else
+    pub fn take_part(&mut self, name: &str) -> Option<Mesh> {
+        let index = self.parts.iter().position(|(n, _)| n == name)?;
+        Some(self.parts.remove(index).1)
+    }
+}
+
+// Asset manifest
+// Plain text list of models to load, one per line:
+//     name  path/to/model.obj  [x y z]  [r g b a]
+// The model can be an OBJ, STL or PLY file. Empty lines and lines starting with '#' are ignored.
+// Instead of a path, `water` or `reflective-water` and a side length make a water surface:
+//     name  water  size  [x y z]  [r g b a]
+// and `ground` or `checker-ground` an InfiniteGround at the height of the position, which stands in
+// for the terrain model so it isn't loaded at all.
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ManifestKind {
+    Model,                                  // Loaded from the path
+    Water { size: f32, reflections: bool }, // A WaterSurface, the path is the keyword
+    Ground { checker: bool },               // An InfiniteGround, the path is the keyword
+}
+
+pub struct ManifestEntry {
+    pub name     : String,
+    pub path     : String,
+    pub kind     : ManifestKind,
+    pub position : glm::Vec3,
+    pub color    : [f32; 4],
+}
+
+pub fn read_manifest(path: &str) -> Vec<ManifestEntry> {
+    let source = std::fs::read_to_string(path)
+        .unwrap_or_else(|error| panic!("Failed to read asset manifest. {}: {:?}", path, error));
+
+    let mut entries = vec![];
+    for (line_number, line) in source.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.len() < 2 {
+            panic!("{}:{}: expected at least a name and a path", path, line_number + 1);
+        }
+        let mut numbers: Vec<f32> = fields[2..].iter()
+            .map(|f| f.parse::<f32>().unwrap_or_else(|_| panic!("{}:{}: '{}' is not a number", path, line_number + 1, f)))
+            .collect();
+
+        let kind = match fields[1] {
+            "water" | "reflective-water" => {
+                if numbers.is_empty() {
+                    panic!("{}:{}: expected the size of the water", path, line_number + 1);
+                }
+                ManifestKind::Water { size: numbers.remove(0), reflections: fields[1] == "reflective-water" }
+            }
+            "ground" | "checker-ground" => ManifestKind::Ground { checker: fields[1] == "checker-ground" },
+            _ => ManifestKind::Model,
+        };
+
+        let position = if numbers.len() >= 3 { glm::vec3(numbers[0], numbers[1], numbers[2]) } else { glm::zero() };
+        let color    = if numbers.len() >= 7 { [numbers[3], numbers[4], numbers[5], numbers[6]] } else { [1.0, 1.0, 1.0, 1.0] };
+
+        entries.push(ManifestEntry {
+            name: fields[0].to_string(),
+            path: fields[1].to_string(),
+            kind,
+            position,
+            color,
+        });
+    }
+    entries
 }
 
 // Lunar terrain
@@ -63,6 +869,28 @@ impl Terrain {
 
         Mesh::from(terrain.mesh, [1.0, 1.0, 1.0, 1.0])
     }
+
+    // A flat square `size` across at `height`, `cells` squares along a side, for when an InfiniteGround
+    // is drawn instead. Collisions, scattering and the minimap still go by a terrain mesh.
+    pub fn flat(size: f32, cells: usize, height: f32) -> Mesh {
+        let side = cells + 1;
+        let mut vertices = Vec::with_capacity(side * side * 3);
+        for row in 0..side {
+            for column in 0..side {
+                let (x, z) = (column as f32 / cells as f32 - 0.5, row as f32 / cells as f32 - 0.5);
+                vertices.extend_from_slice(&[x * size, height, z * size]);
+            }
+        }
+        let mut indices = Vec::with_capacity(cells * cells * 6);
+        for row in 0..cells {
+            for column in 0..cells {
+                let a = (row * side + column) as u32;
+                let (b, c, d) = (a + 1, a + side as u32, a + side as u32 + 1);
+                indices.extend_from_slice(&[a, c, b, b, c, d]);
+            }
+        }
+        Mesh::from_arrays(vertices, vec![], vec![], indices)
+    }
 }
 
 
@@ -92,33 +920,77 @@ impl Index<usize> for Helicopter {
 
 impl Helicopter {
     pub fn load(path: &str) -> Self {
-        println!("Loading helicopter model...");
-        let before = std::time::Instant::now();
-        let (models, _materials)
-            = tobj::load_obj(path,
-                &tobj::LoadOptions{
-                    triangulate: true,
-                    single_index: true,
-                    ..Default::default()
-                }
-            ).expect("Failed to load helicopter model");
-        let after = std::time::Instant::now();
-        println!("Done in {:.3}ms!", after.duration_since(before).as_micros() as f32 / 1e3);
-
-        for model in &models {
-            println!("Loaded {} with {} points and {} triangles.", model.name, model.mesh.positions.len() / 3, model.mesh.indices.len() / 3);
-        }
+        Helicopter::from_model(MultiPartModel::load("helicopter", path, [1.0, 1.0, 1.0, 1.0]))
+    }
 
-        let body_model = models.iter().find(|m| m.name == "Body_body").expect("Incorrect model file!").to_owned();
-        let door_model = models.iter().find(|m| m.name == "Door_door").expect("Incorrect model file!").to_owned();
-        let main_rotor_model = models.iter().find(|m| m.name == "Main_Rotor_main_rotor").expect("Incorrect model file!").to_owned();
-        let tail_rotor_model = models.iter().find(|m| m.name == "Tail_Rotor_tail_rotor").expect("Incorrect model file!").to_owned();
+    // Pick the four known parts out of a generic multi part model and paint them
+    pub fn from_model(mut model: MultiPartModel) -> Self {
+        let mut take = |name: &str, color: [f32; 4]| {
+            let mut mesh = model.take_part(name).expect("Incorrect model file!");
+            mesh.set_color(color);
+            mesh
+        };
 
         Helicopter {
-            body:       Mesh::from(body_model.mesh,         [0.3, 0.3, 0.3, 1.0]),
-            door:       Mesh::from(door_model.mesh,         [0.1, 0.1, 0.3, 1.0]),
-            main_rotor: Mesh::from(main_rotor_model.mesh,   [0.3, 0.1, 0.1, 1.0]),
-            tail_rotor: Mesh::from(tail_rotor_model.mesh,   [0.1, 0.3, 0.1, 1.0]),
+            body:       take("Body_body",             [0.3, 0.3, 0.3, 1.0]),
+            door:       take("Door_door",             [0.1, 0.1, 0.3, 1.0]),
+            main_rotor: take("Main_Rotor_main_rotor", [0.3, 0.1, 0.1, 1.0]),
+            tail_rotor: take("Tail_Rotor_tail_rotor", [0.1, 0.3, 0.1, 1.0]),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn export_obj_reads_back_the_same() {
+        // A bent quad, every vertex first used in order so tobj keeps the order
+        let mesh = Mesh::from_arrays(
+            vec![0.0, 0.0, 0.0,  1.5, 0.0, 0.0,  1.5, 0.25, 1.0,  0.0, 0.1, 1.0],
+            vec![0.0, 1.0, 0.0,  0.6, 0.8, 0.0,  0.0, 0.8, -0.6,  0.0, 0.0, 1.0],
+            vec![],
+            vec![0, 1, 2, 0, 2, 3],
+        );
+        let path = std::env::temp_dir().join(format!("gloom-rs-export-{}.obj", std::process::id()));
+        let path = path.to_str().unwrap();
+        export_obj(path, &mesh).unwrap();
+        let (models, _) = tobj::load_obj(path, &tobj::LoadOptions { triangulate: true, single_index: true, ..Default::default() }).unwrap();
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(models.len(), 1);
+        let read = &models[0].mesh;
+        assert_eq!(read.positions, mesh.vertices);
+        assert_eq!(read.normals, mesh.normals);
+        assert_eq!(read.indices, mesh.indices);
+    }
+
+    #[test]
+    fn load_stl_leaves_out_degenerate_facets_without_a_normal() {
+        let source = "solid test
+            facet normal 0 0 0
+                outer loop
+                    vertex 0 0 0
+                    vertex 1 0 0
+                    vertex 0 1 0
+                endloop
+            endfacet
+            facet normal 0 0 0
+                outer loop
+                    vertex 0 0 0
+                    vertex 1 1 1
+                    vertex 2 2 2
+                endloop
+            endfacet
+            endsolid test";
+        let path = std::env::temp_dir().join(format!("gloom-rs-degenerate-{}.stl", std::process::id()));
+        std::fs::write(&path, source).unwrap();
+        let mesh = load_stl(path.to_str().unwrap(), [1.0; 4]);
+        std::fs::remove_file(&path).unwrap();
+
+        let mesh = mesh.unwrap();
+        assert_eq!(mesh.vertices.len(), 9);
+        assert_eq!(mesh.normals, [0.0, 0.0, 1.0].repeat(3));
+    }
+}
@@ -0,0 +1,199 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::atlas::{AtlasBuilder, TextureAtlas};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::mesh::Mesh;
+
+// * Minimap
+// A radar in the top right corner of the window. Drawing the whole scene a second time from above
+// every frame is a lot of work for a few hundred pixels, and the terrain doesn't move, so it is drawn
+// once from straight above into a texture (like the height map in terrain.rs), shaded by its slopes and
+// with contour lines so the hills can be told apart. Every frame the part of that texture around the
+// fly-cam is put in the corner as a flat 2D overlay, with markers on top: an arrow for the camera,
+// pointing where it looks, and a dot per helicopter. Helicopters off the map stick to its edge, so the
+// direction to them can still be seen. -Z (forward for the fly-cam at yaw 0) is up on the map.
+// The markers are sprites in the overlay atlas (see atlas.rs).
+
+// What a quad draws, the `shape` uniform of minimap.frag
+const SHAPE_MAP: i32 = 0;
+const SHAPE_SPRITE: i32 = 1;
+
+pub struct Minimap {
+    pub enabled       : bool,
+    pub size_fraction : f32,      // Side length as a fraction of the window height
+    pub margin        : i32,      // Pixels between the minimap and the window corner
+    pub extent        : f32,      // Half of the width of the terrain area shown, in world units
+    pub marker_size   : f32,      // Radius of the markers, as a fraction of the minimap size
+    pub contour_step  : f32,      // World units of height between two contour lines
+    terrain_map       : Framebuffer,
+    bounds_min        : glm::Vec3, // Of the terrain, what the terrain map covers
+    bounds_size       : glm::Vec3,
+    bake_shader       : ShaderHandle,
+    overlay_shader    : ShaderHandle,
+    atlas_texture     : u32,      // Owned by the atlas
+    dot_sprite        : [f32; 4], // Where the markers are in it
+    arrow_sprite      : [f32; 4],
+    empty_vao_id      : u32,
+}
+
+impl Minimap {
+    // The marker shapes, white where they are and clear around them
+    pub fn add_sprites(atlas: &mut AtlasBuilder) {
+        atlas.add_generated("minimap_dot", 32, |u, v| {
+            let inside = (u * u + v * v).sqrt() <= 1.0;
+            [1.0, 1.0, 1.0, inside as i32 as f32]
+        });
+        // A triangle with its tip at +X and a notch at the back
+        atlas.add_generated("minimap_arrow", 32, |u, v| {
+            let in_triangle = u <= 1.0 && v.abs() <= (1.0 - u) * 0.6;
+            let in_notch = u < -1.0 + 0.6 * (1.0 - v.abs());
+            [1.0, 1.0, 1.0, (in_triangle && !in_notch) as i32 as f32]
+        });
+    }
+
+    /// The terrain map is `resolution` pixels on a side. It is empty until `bake` is called.
+    /// `atlas` has to have the sprites from `add_sprites` in it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, resolution: i32, atlas: &TextureAtlas) -> Minimap {
+        let mut empty_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut empty_vao_id);
+
+        Minimap {
+            enabled: true,
+            size_fraction: 0.3,
+            margin: 10,
+            extent: 300.0,
+            marker_size: 0.035,
+            contour_step: 10.0,
+            terrain_map: Framebuffer::new(resolution, resolution).with_label("minimap terrain"),
+            bounds_min: glm::zero(),
+            bounds_size: glm::vec3(1.0, 1.0, 1.0),
+            bake_shader: assets.load_shader(&["shaders/minimap_bake.vert", "shaders/minimap_bake.frag"]),
+            overlay_shader: assets.load_shader(&["shaders/minimap.vert", "shaders/minimap.frag"]),
+            atlas_texture: atlas.texture.id,
+            dot_sprite: atlas.uv_rect("minimap_dot"),
+            arrow_sprite: atlas.uv_rect("minimap_arrow"),
+            empty_vao_id,
+        }
+    }
+
+    /// Draw the terrain from above into the terrain map, again whenever the terrain mesh changed.
+    /// The mesh has to be the one in `vao_id`, drawn where it is (the terrain node isn't moved).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn bake(&mut self, assets: &AssetManager, terrain: &Mesh, vao_id: u32) {
+        let (bounds_min, bounds_max) = terrain.bounds();
+        self.bounds_min = bounds_min - glm::vec3(0.0, 1.0, 0.0); // Room above and below so nothing is clipped
+        self.bounds_size = bounds_max - bounds_min + glm::vec3(0.0, 2.0, 0.0);
+
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+        self.terrain_map.bind();
+        gl::ClearColor(0.02, 0.025, 0.04, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        gfx::RenderState { cull: gfx::CullMode::Off, ..Default::default() }.apply();
+
+        let shader = assets.shader(self.bake_shader);
+        shader.activate();
+        shader.set_uniform_vec3("bounds_min", self.bounds_min.as_ref());
+        shader.set_uniform_vec3("bounds_size", self.bounds_size.as_ref());
+        shader.set_uniform_float("contour_step", self.contour_step);
+        gl::BindVertexArray(vao_id);
+        gfx::count_draw_call();
+        gl::DrawElements(gl::TRIANGLES, terrain.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        gl::BindVertexArray(0);
+
+        gfx::RenderState::default().apply();
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    }
+
+    // Pixel rectangle (x, y, width, height) of the minimap, in the top right corner
+    pub fn viewport(&self, window_width: u32, window_height: u32) -> (i32, i32, i32, i32) {
+        let size = (window_height as f32 * self.size_fraction) as i32;
+        let x = window_width as i32 - size - self.margin;
+        let y = window_height as i32 - size - self.margin;
+        (x, y, size, size)
+    }
+
+    // Where a world position is on a map centered on `center`, -1 to 1 across it with Y up.
+    // Anything further away is pulled in to the edge.
+    pub fn map_position(&self, center: glm::Vec3, position: glm::Vec3) -> glm::Vec2 {
+        let offset = glm::vec2(position.x - center.x, center.z - position.z) / self.extent;
+        let furthest = offset.x.abs().max(offset.y.abs());
+        if furthest > 1.0 { offset / furthest } else { offset }
+    }
+
+    /// Draw the minimap on top of whatever is in the window, centered on `center` (the fly-cam).
+    /// `heading` is the yaw of the camera, `helicopters` the world positions of the helicopters.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, with the window bound as the framebuffer.
+    pub unsafe fn draw(&self, assets: &AssetManager, window_width: u32, window_height: u32, center: glm::Vec3, heading: f32, helicopters: &[glm::Vec3]) {
+        if !self.enabled {
+            return;
+        }
+        let (x, y, width, height) = self.viewport(window_width, window_height);
+        let srgb_output = gl::IsEnabled(gl::FRAMEBUFFER_SRGB) == gl::TRUE;
+
+        // A thin border first, then the map over all but the edge of it
+        gfx::set_scissor(Some((x - 2, y - 2, width + 4, height + 4)));
+        gl::ClearColor(0.6, 0.6, 0.65, 1.0);
+        gl::Clear(gl::COLOR_BUFFER_BIT);
+
+        gl::Viewport(x, y, width, height);
+        gfx::RenderState { depth_test: gfx::DepthTest::Off, cull: gfx::CullMode::Off, ..Default::default() }.apply();
+        gl::Disable(gl::FRAMEBUFFER_SRGB); // The map and the markers are in display colors already
+
+        let shader = assets.shader(self.overlay_shader);
+        shader.activate();
+        shader.set_uniform_int("terrain_map", 0);
+        shader.set_uniform_int("sprite_atlas", 1);
+        // The area shown, as (x, z) of its lower left corner and its size, in terrain map coordinates
+        let map_min = (glm::vec2(center.x, center.z) - glm::vec2(self.extent, self.extent) - self.bounds_min.xz()).component_div(&self.bounds_size.xz());
+        let map_size = glm::vec2(2.0 * self.extent, 2.0 * self.extent).component_div(&self.bounds_size.xz());
+        shader.set_uniform_vec4("map_rect", &[map_min.x, map_min.y, map_size.x, map_size.y]);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.terrain_map.color_texture);
+        gfx::bind_texture(1, Some(self.atlas_texture));
+        gl::BindVertexArray(self.empty_vao_id);
+
+        let draw_shape = |shape: i32, sprite: &[f32; 4], position: glm::Vec2, size: f32, rotation: f32, color: [f32; 3]| {
+            shader.set_uniform_int("shape", shape);
+            shader.set_uniform_vec4("sprite_rect", sprite);
+            shader.set_uniform_vec4("rect", &[position.x, position.y, size, size]);
+            shader.set_uniform_float("rotation", rotation);
+            shader.set_uniform_vec3("marker_color", &color);
+            gfx::count_draw_call();
+            gl::DrawArrays(gl::TRIANGLE_STRIP, 0, 4);
+        };
+        draw_shape(SHAPE_MAP, &[0.0; 4], glm::zero(), 1.0, 0.0, [1.0, 1.0, 1.0]);
+        for &helicopter in helicopters {
+            draw_shape(SHAPE_SPRITE, &self.dot_sprite, self.map_position(center, helicopter), self.marker_size, 0.0, [1.0, 0.2, 0.1]);
+        }
+        // Yaw turns from +X towards +Z, and +Z is down on the map, so the arrow turns clockwise
+        draw_shape(SHAPE_SPRITE, &self.arrow_sprite, glm::zero(), self.marker_size * 1.5, -heading, [1.0, 1.0, 1.0]);
+
+        gl::BindVertexArray(0);
+        gfx::bind_texture(1, None);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gfx::RenderState::default().apply();
+        if srgb_output {
+            gl::Enable(gl::FRAMEBUFFER_SRGB);
+        }
+        gl::Viewport(0, 0, window_width as i32, window_height as i32);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.terrain_map.delete();
+        gl::DeleteVertexArrays(1, &self.empty_vao_id);
+    }
+}
@@ -0,0 +1,75 @@
+extern crate nalgebra_glm as glm;
+
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::scene_graph;
+use crate::settings::CameraProjection;
+use crate::shader::Shader;
+use crate::util;
+
+// * Security monitor
+// A screen standing at the lunar base that shows what an auxiliary camera sees.
+// Every frame the scene is first drawn from that camera into a framebuffer, then the framebuffer's
+// color texture is put on a quad in the world with the textured shader.
+
+pub struct Monitor {
+    pub camera             : Camera,      // Where the feed is filmed from
+    pub framebuffer        : Framebuffer, // The feed, drawn into every frame
+    pub position           : glm::Vec3,   // Center of the screen
+    pub yaw                : f32,         // Rotation of the screen around Y, 0 faces +Z
+    screen_vao_id          : u32,
+    screen_index_count     : i32,
+}
+
+impl Monitor {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(position: glm::Vec3, yaw: f32, width: f32, height: f32, resolution: i32) -> Monitor {
+        let (screen_vao_id, screen_index_count) = util::create_quad_vao(width, height);
+        let feed_height = (resolution as f32 * height / width) as i32;
+        Monitor {
+            camera: Camera::new(scene_graph::position_from_f32(&position)),
+            framebuffer: Framebuffer::with_color_format(resolution, feed_height, gl::SRGB8_ALPHA8, gl::UNSIGNED_BYTE).with_label("monitor feed"), // sRGB so the dark parts don't band
+            position,
+            yaw,
+            screen_vao_id,
+            screen_index_count,
+        }
+    }
+
+    pub fn screen_transform(&self) -> glm::Mat4 {
+        glm::translation(&self.position) * glm::rotation(self.yaw, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    // View projection of the feed camera, with the aspect ratio of the screen
+    pub fn feed_view_projection(&self, projection: &CameraProjection) -> glm::Mat4 {
+        self.camera.view_projection(self.framebuffer.width as f32 / self.framebuffer.height as f32, projection)
+    }
+
+    /// Draw the screen quad with the feed on it. Leaves `shader` active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, and `shader` has to be the textured shader.
+    pub unsafe fn draw_screen(&self, view_projection_matrix: &glm::Mat4, shader: &Shader) {
+        shader.activate();
+        shader.set_uniform_mat4("mvp_matrix", &(view_projection_matrix * self.screen_transform()));
+        shader.set_uniform_int("screen_texture", 0);
+
+        gfx::bind_texture(0, Some(self.framebuffer.color_texture));
+
+        // Both sides should show the feed
+        gfx::RenderState { cull: gfx::CullMode::Off, ..Default::default() }.apply();
+        gfx::DrawCall::indexed(self.screen_vao_id, self.screen_index_count).submit();
+        gfx::RenderState::default().apply();
+
+        gfx::bind_texture(0, None);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        util::delete_vao(self.screen_vao_id);
+        self.framebuffer.delete();
+    }
+}
@@ -0,0 +1,112 @@
+extern crate nalgebra_glm as glm;
+
+use crate::post::PostChain;
+use crate::shader::Shader;
+
+// * Motion vectors
+// How far every pixel moved on the screen since the last frame, for the effects that blend frames
+// together (the temporal anti-aliasing in taa.rs, motion blur). While the scene is drawn, the motion
+// buffer is a second color target of the HDR target, and the scene shaders write into it next to
+// the color: the world position of the fragment now and last frame (from the model matrices of the
+// two snapshots, see SceneNode::previous_world_matrix), each through its frame's view projection.
+//
+// The motion is in texture coordinates of the view (now minus then) in RG. B says whether anything
+// was written: the shaders of what doesn't move (the monitor, the lake, the debug lines) and the sky
+// leave it 0, and for those pixels the motion comes from the camera alone, worked out from the depth
+// with `previous_view_projection`.
+//
+// The view projections given here are without the TAA jitter, so a still scene has no motion at all.
+
+pub struct MotionVectors {
+    pub texture               : u32,
+    size                      : (i32, i32),
+    view_projections          : Vec<glm::Mat4>, // Of every view this frame, in the order they are drawn
+    previous_view_projections : Vec<glm::Mat4>, // And last frame
+}
+
+impl MotionVectors {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new() -> MotionVectors {
+        let mut texture: u32 = 0;
+        gl::GenTextures(1, &mut texture);
+        MotionVectors { texture, size: (0, 0), view_projections: vec![], previous_view_projections: vec![] }
+    }
+
+    /// Attach the motion buffer to the post chain's HDR target, to be drawn into with the scene.
+    /// Once per frame, before the first view.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, with the HDR target bound.
+    pub unsafe fn begin(&mut self, post_chain: &PostChain) {
+        self.previous_view_projections = std::mem::take(&mut self.view_projections);
+
+        let scene = &post_chain.hdr_target;
+        if self.size != (scene.width, scene.height) {
+            self.size = (scene.width, scene.height);
+            gl::BindTexture(gl::TEXTURE_2D, self.texture);
+            gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RGBA16F as i32, scene.width, scene.height, 0, gl::RGBA, gl::FLOAT, std::ptr::null());
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+            gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+        // Attached every frame, the HDR target is made anew when the window changes size
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT1, gl::TEXTURE_2D, self.texture, 0);
+        gl::DrawBuffers(2, [gl::COLOR_ATTACHMENT0, gl::COLOR_ATTACHMENT1].as_ptr());
+    }
+
+    /// Set up `shaders` for drawing the next view, seen with `view_projection` (without jitter).
+    /// Leaves the last of them active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn begin_view(&mut self, shaders: &[&Shader], view_projection: &glm::Mat4) {
+        let index = self.view_projections.len();
+        self.view_projections.push(*view_projection);
+        let previous = self.previous_view_projection(index);
+        for shader in shaders {
+            shader.activate();
+            shader.set_uniform_mat4("motion_view_projection", view_projection);
+            shader.set_uniform_mat4("previous_view_projection", &previous);
+        }
+    }
+
+    /// Clear the motion of the view that is active. After the color is cleared, which clears this to
+    /// the sky color too.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn clear_view(&self) {
+        gl::ClearBufferfv(gl::COLOR, 1, [0.0f32; 4].as_ptr());
+    }
+
+    /// Stop writing motion, what is drawn into the HDR target after the scene has none.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn end(&self, post_chain: &PostChain) {
+        gl::BindFramebuffer(gl::FRAMEBUFFER, post_chain.hdr_target.fbo_id);
+        gl::DrawBuffers(1, [gl::COLOR_ATTACHMENT0].as_ptr());
+    }
+
+    // Last frame's view projection of the `index`th view. Its view projection of this frame if there
+    // was no such view, which makes it look like the camera stood still.
+    pub fn previous_view_projection(&self, index: usize) -> glm::Mat4 {
+        self.previous_view_projections.get(index)
+            .or_else(|| self.view_projections.get(index))
+            .copied()
+            .unwrap_or_else(glm::identity)
+    }
+
+    // Whether there are as many views as last frame, otherwise the layout changed and last frame's
+    // images don't line up with this one
+    pub fn views_unchanged(&self) -> bool {
+        self.view_projections.len() == self.previous_view_projections.len()
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteTextures(1, &self.texture);
+    }
+}
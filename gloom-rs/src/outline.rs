@@ -0,0 +1,99 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::gfx;
+use crate::scene_graph::{NodeId, SceneNode, WorldMatrix};
+use crate::shader::Shader;
+use crate::viewport::Viewport;
+
+// * Selection outline
+// Draws a colored outline around the highlighted node (and everything below it) with the stencil trick:
+//  1. Draw the node again, only marking its pixels in the stencil buffer.
+//  2. Draw it with every vertex pushed out along its normal in a flat color, only where the stencil
+//     isn't marked. What is left is a band around the node.
+// The outline is drawn without depth testing, so the selection can be seen through other things.
+
+pub struct OutlineRenderer {
+    highlighted : Option<(NodeId, [f32; 4])>,
+    pub width   : f32, // In pixels
+    shader      : ShaderHandle,
+}
+
+impl OutlineRenderer {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager) -> OutlineRenderer {
+        OutlineRenderer {
+            highlighted: None,
+            width: 3.0,
+            shader: assets.load_shader(&["shaders/outline.vert", "shaders/outline.frag"]),
+        }
+    }
+
+    pub fn set_highlighted(&mut self, node_id: NodeId, color: [f32; 4]) {
+        self.highlighted = Some((node_id, color));
+    }
+
+    pub fn clear_highlighted(&mut self) {
+        self.highlighted = None;
+    }
+
+    pub fn highlighted(&self) -> Option<NodeId> {
+        self.highlighted.map(|(node_id, _)| node_id)
+    }
+
+    /// Outline the highlighted node in `view`, right after the scene has been drawn into it.
+    /// `draw` has to draw a node and its children with the given shader, like draw_scene does,
+    /// given the transformation of the node's parent. Leaves `scene_shader` active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, and a stencil buffer that was cleared with the view.
+    pub unsafe fn draw<F: FnMut(&SceneNode, &WorldMatrix, &Shader)>(
+        &self,
+        assets: &AssetManager,
+        scene_graph: &SceneNode,
+        view: &Viewport,
+        view_projection_matrix: &glm::Mat4,
+        scene_shader: &Shader,
+        mut draw: F,
+    ) {
+        let (node_id, color) = match self.highlighted {
+            Some(highlighted) => highlighted,
+            None => return,
+        };
+        let (node, parent_transform) = match scene_graph.find(node_id, &glm::identity()) {
+            Some(found) => found,
+            None => return, // Not in this scene (anymore)
+        };
+
+        gl::Enable(gl::STENCIL_TEST);
+
+        // * 1. Mark the node's pixels
+        gl::StencilMask(0xFF);
+        gl::StencilFunc(gl::ALWAYS, 1, 0xFF);
+        gl::StencilOp(gl::KEEP, gl::KEEP, gl::REPLACE);
+        gl::ColorMask(gl::FALSE, gl::FALSE, gl::FALSE, gl::FALSE);
+        gfx::RenderState { depth_test: gfx::DepthTest::LessEqual, ..Default::default() }.apply();
+        scene_shader.activate();
+        draw(node, &parent_transform, scene_shader);
+        gl::ColorMask(gl::TRUE, gl::TRUE, gl::TRUE, gl::TRUE);
+
+
+        // * 2. Draw the pushed out version around them
+        gl::StencilMask(0x00);
+        gl::StencilFunc(gl::NOTEQUAL, 1, 0xFF);
+        gfx::RenderState { depth_test: gfx::DepthTest::Off, ..Default::default() }.apply();
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", view_projection_matrix);
+        shader.set_uniform_vec2("viewport_size", &[view.width as f32, view.height as f32]);
+        shader.set_uniform_float("outline_width", self.width);
+        shader.set_uniform_vec4("outline_color", &color);
+        draw(node, &parent_transform, shader);
+
+        gfx::RenderState::default().apply();
+        gl::StencilMask(0xFF);
+        gl::Disable(gl::STENCIL_TEST);
+        scene_shader.activate();
+    }
+}
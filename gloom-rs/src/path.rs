@@ -0,0 +1,245 @@
+extern crate nalgebra_glm as glm;
+use std::f32::consts::PI;
+
+use crate::scene_graph::{self, SceneNode};
+use crate::toolbox::Heading;
+
+// * Waypoint path following
+// A Path is just a list of 3D points that something should fly through, in order.
+// Every segment (waypoint i -> waypoint i+1) can have its own speed, otherwise the path default is used.
+// The PathFollower walks along the path over time and works out how the node should be banked,
+// the same way simple_heading_animation does it: look a little bit ahead and tilt into the turn.
+
+// How far ahead heading() looks, in seconds of flight, the same step simple_heading_animation takes
+const LOOKAHEAD_SECONDS: f32 = 0.05;
+
+pub struct Waypoint {
+    pub position : glm::Vec3,
+    pub speed    : Option<f32>, // Units per second for the segment starting at this waypoint
+}
+
+pub struct Path {
+    pub waypoints     : Vec<Waypoint>,
+    pub default_speed : f32,  // Used for segments without their own speed
+    pub looping       : bool, // Fly from the last waypoint back to the first one
+}
+
+impl Path {
+    pub fn new(default_speed: f32, looping: bool) -> Path {
+        Path {
+            waypoints: vec![],
+            default_speed,
+            looping,
+        }
+    }
+
+    /// Adds a waypoint to the end of the path, builder style so routes can be written in one go.
+    /// - `position`: Where the waypoint is.
+    /// - `speed`: Speed of the segment starting at this waypoint, `None` for the path default.
+    pub fn with_waypoint(mut self, position: glm::Vec3, speed: Option<f32>) -> Path {
+        self.waypoints.push(Waypoint { position, speed });
+        self
+    }
+
+    pub fn segment_count(&self) -> usize {
+        match self.waypoints.len() {
+            0 | 1 => 0,
+            n if self.looping => n,
+            n => n - 1,
+        }
+    }
+
+    // Start and end point of a segment, wrapping around for looping paths
+    fn segment(&self, index: usize) -> (glm::Vec3, glm::Vec3) {
+        let n = self.waypoints.len();
+        (self.waypoints[index % n].position, self.waypoints[(index + 1) % n].position)
+    }
+
+    fn segment_length(&self, index: usize) -> f32 {
+        let (start, end) = self.segment(index);
+        glm::distance(&start, &end)
+    }
+
+    fn segment_speed(&self, index: usize) -> f32 {
+        self.waypoints[index % self.waypoints.len()].speed.unwrap_or(self.default_speed)
+    }
+
+    pub fn total_length(&self) -> f32 {
+        (0..self.segment_count()).map(|i| self.segment_length(i)).sum()
+    }
+
+    /// Position on the path after travelling `distance` units from the first waypoint.
+    /// Looping paths wrap around, open paths stop at the last waypoint.
+    pub fn position_at(&self, distance: f32) -> glm::Vec3 {
+        if self.waypoints.is_empty() {
+            return glm::zero();
+        }
+        let total_length = self.total_length();
+        if total_length <= 0.0 {
+            return self.waypoints[0].position;
+        }
+
+        let mut remaining = if self.looping {
+            distance.rem_euclid(total_length)
+        } else {
+            distance.clamp(0.0, total_length)
+        };
+
+        for i in 0..self.segment_count() {
+            let length = self.segment_length(i);
+            if remaining <= length || i == self.segment_count() - 1 {
+                let (start, end) = self.segment(i);
+                let t = if length > 0.0 { (remaining / length).min(1.0) } else { 0.0 };
+                return glm::lerp(&start, &end, t);
+            }
+            remaining -= length;
+        }
+
+        self.waypoints[0].position
+    }
+}
+
+
+pub struct PathFollower {
+    pub path             : Path,
+    pub distance         : f32, // How far along the path we are
+    pub bank_factor      : f32, // How hard to roll into turns
+    pub max_bank         : f32, // Roll limit in radians
+    pub finished         : bool,
+
+    segment_index        : usize,
+    distance_in_segment  : f32,
+    current_speed        : f32,
+}
+
+impl PathFollower {
+    pub fn new(path: Path) -> PathFollower {
+        let current_speed = if path.waypoints.is_empty() { 0.0 } else { path.segment_speed(0) };
+        PathFollower {
+            path,
+            distance: 0.0,
+            bank_factor: 2.0,
+            max_bank: 0.6,
+            finished: false,
+            segment_index: 0,
+            distance_in_segment: 0.0,
+            current_speed,
+        }
+    }
+
+    // Units per second on the segment we are on
+    pub fn speed(&self) -> f32 {
+        self.current_speed
+    }
+
+    // * Move along the path
+    // The speed can change per segment, so we step segment by segment until the frame's travel is used up
+    pub fn update(&mut self, delta_time: f32) {
+        let segment_count = self.path.segment_count();
+        // All the waypoints on one spot, a looping path would go round it forever within one frame
+        if segment_count == 0 || self.finished || self.path.total_length() <= 0.0 {
+            return;
+        }
+
+        let mut time_left = delta_time;
+        while time_left > 0.0 {
+            let speed = self.path.segment_speed(self.segment_index);
+            let length = self.path.segment_length(self.segment_index);
+            self.current_speed = speed;
+
+            if speed <= 0.0 {
+                break;
+            }
+
+            let travel = speed * time_left;
+            let left_in_segment = length - self.distance_in_segment;
+
+            if travel < left_in_segment {
+                self.distance_in_segment += travel;
+                self.distance += travel;
+                break;
+            }
+
+            // Finish this segment and carry the remaining time into the next one
+            self.distance += left_in_segment;
+            time_left -= left_in_segment / speed;
+            self.distance_in_segment = 0.0;
+            self.segment_index += 1;
+
+            if self.segment_index >= segment_count {
+                if self.path.looping {
+                    self.segment_index = 0;
+                    self.distance = 0.0;
+                } else {
+                    self.segment_index = segment_count - 1;
+                    self.distance_in_segment = self.path.segment_length(self.segment_index);
+                    self.current_speed = 0.0; // Stopped at the last waypoint
+                    self.finished = true;
+                    break;
+                }
+            }
+        }
+    }
+
+    // * Heading at the current point of the path
+    // Same trick as simple_heading_animation, look a small step ahead to find the direction,
+    // then one more step to see how quickly the direction changes (curvature) and bank with it.
+    // At the end of an open path there is nothing ahead, the direction it arrived in is kept instead.
+    pub fn heading(&self) -> (glm::Vec3, Heading) {
+        let step = LOOKAHEAD_SECONDS * self.current_speed.max(1.0);
+
+        let position      = self.path.position_at(self.distance);
+        let position_next = self.path.position_at(self.distance + step);
+        let position_far  = self.path.position_at(self.distance + 2.0 * step);
+
+        let moved = |delta: &glm::Vec3| glm::length(delta) > 1e-6;
+        let delta_pos = match position_next - position {
+            delta if moved(&delta) => delta,
+            _ => position - self.path.position_at(self.distance - step),
+        };
+        let delta_pos_next = match position_far - position_next {
+            delta if moved(&delta) => delta,
+            _ => delta_pos, // Nothing to turn into
+        };
+
+        let yaw      = PI + delta_pos.x.atan2(delta_pos.z);
+        let yaw_next = PI + delta_pos_next.x.atan2(delta_pos_next.z);
+
+        // Wrap the yaw change into [-PI, PI] so crossing the +-PI line doesn't flip the helicopter
+        let turn = (yaw_next - yaw + PI).rem_euclid(2.0 * PI) - PI;
+
+        let horizontal = glm::length(&glm::vec2(delta_pos.x, delta_pos.z));
+        let climb      = delta_pos.y.atan2(horizontal.max(1e-6));
+
+        // Turn rate in radians per second decides how far we roll into the turn
+        let turn_rate = turn / step * self.current_speed;
+        let roll      = (turn_rate * self.bank_factor).clamp(-self.max_bank, self.max_bank);
+
+        // Tilt forward with horizontal speed like the simple animation, and nose up/down when climbing.
+        // The step ahead is shorter near the end of an open path, so the speed is taken from the path's
+        // own and only the share of it that is horizontal from the step.
+        let horizontal_share = if moved(&delta_pos) { horizontal / glm::length(&delta_pos) } else { 1.0 };
+        let pitch = -0.175 * self.current_speed * LOOKAHEAD_SECONDS * horizontal_share + climb;
+
+        (
+            position,
+            Heading {
+                x     : position.x,
+                z     : position.z,
+                roll,
+                pitch,
+                yaw,
+            },
+        )
+    }
+
+    // * Drive a SceneNode along the path
+    // Uses the same rotation layout as the helicopters in main.rs (x = pitch, y = yaw, z = roll)
+    pub fn apply_to(&self, node: &mut SceneNode) {
+        let (position, heading) = self.heading();
+        node.position = scene_graph::position_from_f32(&position);
+        node.rotation.x = heading.pitch;
+        node.rotation.y = heading.yaw;
+        node.rotation.z = heading.roll;
+    }
+}
@@ -0,0 +1,122 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::scene_graph::NodeId;
+use crate::shader::Shader;
+use crate::snapshot::FrameSnapshot;
+use crate::static_batch::{BatchPart, StaticBatch};
+use crate::viewport::Viewport;
+
+// * Picking with an ID buffer
+// Instead of casting a ray against the meshes, the frame's snapshot is drawn again into an integer texture
+// with every node writing its own ID, and the pixel under the cursor is read back. This is exact
+// for any shape (the helicopter is far from convex), the depth test picks the front-most node.
+// Only done on a click, and only the pixel under the cursor is drawn (scissor), so it is cheap.
+// A static batch is drawn one part at a time, each with the ID of the node it came from, so a
+// click on a batched prop picks the prop and not the batch.
+
+pub struct PickingBuffer {
+    target : Framebuffer, // R32UI, one node ID per pixel, 0 where there is nothing
+    shader : ShaderHandle,
+    // The parts of every static batch, by the ID of the node drawing it
+    batches : HashMap<NodeId, Vec<BatchPart>>,
+}
+
+impl PickingBuffer {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, width: u32, height: u32) -> PickingBuffer {
+        PickingBuffer {
+            target: Framebuffer::with_color_format(width as i32, height as i32, gl::R32UI, gl::UNSIGNED_INT).with_label("picking"),
+            shader: assets.load_shader(&["shaders/picking.vert", "shaders/picking.frag"]),
+            batches: HashMap::new(),
+        }
+    }
+
+    // Pick the nodes in these batches instead of the batches themselves
+    pub fn add_batches(&mut self, batches: &[StaticBatch]) {
+        for batch in batches {
+            self.batches.insert(batch.node_id, batch.parts.clone());
+        }
+    }
+
+    /// The ID of the node under `cursor` (window pixels, y going down like the window events),
+    /// seen from the view containing the cursor, as it is in `snapshot`. `views` are the views of this frame with their
+    /// view projections. None if the cursor is over the sky or outside every view.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context. Leaves the window bound as the framebuffer.
+    pub unsafe fn pick(
+        &mut self,
+        assets: &AssetManager,
+        snapshot: &FrameSnapshot,
+        views: &[(Viewport, glm::Mat4)],
+        cursor: (f32, f32),
+        window_width: u32,
+        window_height: u32,
+    ) -> Option<NodeId> {
+        // OpenGL has y going up
+        let x = cursor.0 as i32;
+        let y = window_height as i32 - 1 - cursor.1 as i32;
+        let (view, view_projection_matrix) = views.iter()
+            .find(|(view, _)| x >= view.x && x < view.x + view.width && y >= view.y && y < view.y + view.height)?;
+
+        self.target.resize(window_width as i32, window_height as i32);
+        self.target.bind();
+        gl::Viewport(view.x, view.y, view.width, view.height);
+        gfx::set_scissor(Some((x, y, 1, 1)));
+
+        let nothing: u32 = 0;
+        gl::ClearBufferuiv(gl::COLOR, 0, &nothing);
+        gl::Clear(gl::DEPTH_BUFFER_BIT);
+
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        draw_ids(snapshot, view_projection_matrix, shader, &self.batches);
+
+        let mut node_id: u32 = 0;
+        gl::ReadBuffer(gl::COLOR_ATTACHMENT0);
+        gl::ReadPixels(x, y, 1, 1, gl::RED_INTEGER, gl::UNSIGNED_INT, &mut node_id as *mut u32 as *mut std::ffi::c_void);
+
+        gfx::set_scissor(None);
+        self.target.unbind(window_width, window_height);
+
+        if node_id == 0 { None } else { Some(node_id) }
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.target.delete();
+    }
+}
+
+// Every draw of the snapshot, only writing the ID of its node. The entities have no node and are left out.
+unsafe fn draw_ids(
+    snapshot: &FrameSnapshot,
+    view_projection_matrix: &glm::Mat4,
+    shader: &Shader,
+    batches: &HashMap<NodeId, Vec<BatchPart>>,
+) {
+    for item in snapshot.draws.iter().filter(|item| item.node_id != 0) {
+        shader.set_uniform_mat4("mvp_matrix", &(view_projection_matrix * item.model_matrix));
+        gl::BindVertexArray(item.vao_id);
+        match batches.get(&item.node_id) {
+            Some(parts) => for part in parts {
+                shader.set_uniform_uint("node_id", part.node_id);
+                let offset = part.first_index as usize * std::mem::size_of::<u32>();
+                gfx::count_draw_call();
+                gl::DrawElements(gl::TRIANGLES, part.index_count, gl::UNSIGNED_INT, offset as *const std::ffi::c_void);
+            },
+            None => {
+                shader.set_uniform_uint("node_id", item.node_id);
+                gfx::count_draw_call();
+                gl::DrawElements(gl::TRIANGLES, item.index_count, gl::UNSIGNED_INT, std::ptr::null());
+            }
+        }
+    }
+}
@@ -0,0 +1,81 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::gfx;
+use crate::scene_graph::SceneNode;
+use crate::shader::Shader;
+use crate::util;
+
+// * Pivot gizmo
+// Draws three small axes at every node's pivot, the point it rotates and scales about (its position
+// plus its reference point), turned the way the node is turned: X red, Y green, Z blue. A part that
+// wobbles when it spins has its pivot somewhere off its axis, and this shows where. The axes are drawn
+// on top of everything, so pivots inside a mesh can be seen too.
+
+pub struct PivotGizmo {
+    pub enabled : bool,
+    pub size    : f32, // Length of the axes, in world units
+    vao_id      : u32,
+    shader      : ShaderHandle,
+}
+
+impl PivotGizmo {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager) -> PivotGizmo {
+        let vertices = vec![
+            0.0, 0.0, 0.0,  1.0, 0.0, 0.0,
+            0.0, 0.0, 0.0,  0.0, 1.0, 0.0,
+            0.0, 0.0, 0.0,  0.0, 0.0, 1.0,
+        ];
+        let colors = vec![
+            1.0, 0.2, 0.2, 1.0,  1.0, 0.2, 0.2, 1.0,
+            0.2, 1.0, 0.2, 1.0,  0.2, 1.0, 0.2, 1.0,
+            0.3, 0.5, 1.0, 1.0,  0.3, 0.5, 1.0, 1.0,
+        ];
+        let normals = vec![0.0; vertices.len()];
+        let indices = (0..6).collect();
+        let (vao_id, _) = util::create_vao(&vertices, &indices, &colors, &normals);
+
+        PivotGizmo {
+            enabled: false,
+            size: 0.6,
+            vao_id,
+            shader: assets.load_shader(&["shaders/pivot_gizmo.vert", "shaders/pivot_gizmo.frag"]),
+        }
+    }
+
+    /// Draw the pivots of every visible node in the scene graph. Leaves the shader of the gizmo active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, scene_graph: &SceneNode, view_projection_matrix: &glm::Mat4) {
+        if !self.enabled {
+            return;
+        }
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        gfx::RenderState { depth_test: gfx::DepthTest::Off, ..Default::default() }.apply();
+        self.draw_node(shader, scene_graph, view_projection_matrix, &glm::identity());
+        gfx::RenderState::default().apply();
+    }
+
+    unsafe fn draw_node(&self, shader: &Shader, node: &SceneNode, view_projection_matrix: &glm::Mat4, transformation_so_far: &glm::Mat4) {
+        if !node.visible {
+            return;
+        }
+        let gizmo_matrix = transformation_so_far
+            * glm::translation(&node.pivot())
+            * node.rotation_matrix()
+            * glm::scaling(&glm::vec3(self.size, self.size, self.size));
+        shader.set_uniform_mat4("mvp_matrix", &(view_projection_matrix * gizmo_matrix));
+        gfx::DrawCall { primitive: gfx::Primitive::Lines, ..gfx::DrawCall::indexed(self.vao_id, 6) }.submit();
+
+        let transformation_matrix = transformation_so_far * node.local_transform();
+        for &child in &node.children {
+            if let Some(child) = child.as_ref() {
+                self.draw_node(shader, child, view_projection_matrix, &transformation_matrix);
+            }
+        }
+    }
+}
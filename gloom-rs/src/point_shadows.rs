@@ -0,0 +1,146 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::gfx;
+use crate::lights::{LightKind, WorldLight, MAX_LIGHTS};
+use crate::shader::Shader;
+
+// * Point light shadows
+// A point light shines every way, so one shadow map looking in one direction isn't enough: its
+// shadows go into a cube map, drawn in six passes with a 90 degree camera looking out of each face.
+// Instead of the depth the projection gives, every texel gets the distance from the light (divided by
+// the light's range, to fit into a depth texture), and the lit shaders compare the distance of the
+// fragment with it, looking the cube map up in the direction from the light. No geometry shader
+// needed, so it works on ES 3.0 too.
+//
+// Six passes per light add up quickly, so only the first light that asks for shadows (Light::
+// with_shadows) gets them, like the lamp at the lunar base. The others keep lighting through walls.
+
+pub const POINT_SHADOW_UNIT: u32 = 7; // Texture unit of the cube map, after the cascades
+
+// The direction every face looks in, and which way is up on it, in the order GL numbers the faces
+// from TEXTURE_CUBE_MAP_POSITIVE_X. Up is upside down on the side faces, that is how cube maps are laid out.
+pub const CUBE_FACES: [([f32; 3], [f32; 3]); 6] = [
+    ([ 1.0,  0.0,  0.0], [0.0, -1.0,  0.0]),
+    ([-1.0,  0.0,  0.0], [0.0, -1.0,  0.0]),
+    ([ 0.0,  1.0,  0.0], [0.0,  0.0,  1.0]),
+    ([ 0.0, -1.0,  0.0], [0.0,  0.0, -1.0]),
+    ([ 0.0,  0.0,  1.0], [0.0, -1.0,  0.0]),
+    ([ 0.0,  0.0, -1.0], [0.0, -1.0,  0.0]),
+];
+
+pub struct PointShadows {
+    pub enabled  : bool,
+    resolution   : i32,
+    light_index  : Option<usize>, // Which of the lights uploaded this frame has the cube map
+    cube_texture : u32,
+    fbo_id       : u32,
+    depth_shader : ShaderHandle,
+}
+
+impl PointShadows {
+    /// A cube map with faces of `resolution` squared.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, resolution: i32) -> PointShadows {
+        let mut cube_texture: u32 = 0;
+        gl::GenTextures(1, &mut cube_texture);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_texture);
+        for face in 0..6 {
+            gl::TexImage2D(gl::TEXTURE_CUBE_MAP_POSITIVE_X + face, 0, gl::DEPTH_COMPONENT32F as i32, resolution, resolution, 0, gl::DEPTH_COMPONENT, gl::FLOAT, std::ptr::null());
+        }
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+
+        // Depth only, the face is attached before it is drawn
+        let mut fbo_id: u32 = 0;
+        gl::GenFramebuffers(1, &mut fbo_id);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo_id);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_CUBE_MAP_POSITIVE_X, cube_texture, 0);
+        let no_color = gl::NONE;
+        gl::DrawBuffers(1, &no_color);
+        gl::ReadBuffer(gl::NONE);
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Point shadow framebuffer {}x{} is not complete!", resolution, resolution);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        PointShadows {
+            enabled: true,
+            resolution,
+            light_index: None,
+            cube_texture,
+            fbo_id,
+            depth_shader: assets.load_shader(&["shaders/point_shadow_depth.vert", "shaders/point_shadow_depth.frag"]),
+        }
+    }
+
+    /// Draw the cube map of the first point light in `lights` that has shadows on. `lights` has to be
+    /// what goes to LightClusters::set_lights this frame. `draw_casters` draws everything that throws a
+    /// shadow with the shader and view projection it is given. Leaves the window bound, with the
+    /// viewport it had before.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn render<F: FnMut(&Shader, &glm::Mat4)>(&mut self, assets: &AssetManager, lights: &[WorldLight], mut draw_casters: F) {
+        self.light_index = lights.iter()
+            .take(MAX_LIGHTS)
+            .position(|world_light| world_light.light.shadows && world_light.light.kind == LightKind::Point)
+            .filter(|_| self.enabled);
+        let world_light = match self.light_index {
+            Some(index) => &lights[index],
+            None => return,
+        };
+
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo_id);
+        gl::Viewport(0, 0, self.resolution, self.resolution);
+        // Back faces stay culled, which also keeps a bulb around the light (seen from inside) out of it
+        gfx::RenderState::default().apply();
+
+        let shader = assets.shader(self.depth_shader);
+        shader.activate();
+        shader.set_uniform_vec3("light_position", world_light.position.as_ref());
+        shader.set_uniform_float("light_range", world_light.light.range);
+
+        let projection = glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 0.1, world_light.light.range);
+        for (face, (direction, up)) in CUBE_FACES.iter().enumerate() {
+            let (direction, up) = (glm::make_vec3(direction), glm::make_vec3(up));
+            let view = glm::look_at(&world_light.position, &(world_light.position + direction), &up);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32, self.cube_texture, 0);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            draw_casters(shader, &(projection * view));
+        }
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    }
+
+    /// Point a lit shader (simple.frag or pbr.frag) at the cube map, it has to be active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_uniforms(&self, shader: &Shader) {
+        shader.set_uniform_int("point_shadow_light", self.light_index.map_or(-1, |index| index as i32));
+        shader.set_uniform_int("point_shadow_map", POINT_SHADOW_UNIT as i32);
+        gl::ActiveTexture(gl::TEXTURE0 + POINT_SHADOW_UNIT);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.cube_texture);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteTextures(1, &self.cube_texture);
+        gl::DeleteFramebuffers(1, &self.fbo_id);
+    }
+}
@@ -0,0 +1,120 @@
+extern crate nalgebra_glm as glm;
+
+use std::rc::Rc;
+
+use crate::material::Material;
+use crate::scene_graph::{self, SceneNode};
+
+// * Pooled short-lived nodes
+// SceneNodes are never freed (see scene_graph.rs), so spawning a new one for every spark, piece of
+// debris or flare would leak a little more each time. A pool hands out nodes that all draw the same
+// VAO with the same material, and takes them back when their time is up: they are unhooked from the
+// parent and kept for the next spawn. Every spawned object also flies on its own with a velocity and
+// the pool's gravity, which is all short-lived things usually need.
+
+struct PooledObject {
+    node       : *mut SceneNode,
+    velocity   : glm::Vec3,
+    expires_at : f32, // Seconds, in the same clock as the `now` passed to spawn and update
+}
+
+pub struct NodePool {
+    parent      : *mut SceneNode,
+    vao_id      : u32,
+    index_count : i32,
+    material    : Option<Rc<Material>>,
+    pub scale   : glm::Vec3, // Given to every node when it is spawned
+    pub gravity : glm::Vec3,
+    pub limit   : usize,     // Most objects alive at the same time, spawning past it is ignored
+    live        : Vec<PooledObject>,
+    free        : Vec<*mut SceneNode>,
+    allocated   : usize,
+}
+
+impl NodePool {
+    // The spawned nodes are children of `parent`, which has to outlive the pool
+    pub fn new(parent: &mut SceneNode, vao_id: u32, index_count: i32, material: Option<Rc<Material>>) -> NodePool {
+        NodePool {
+            parent: parent as *mut SceneNode,
+            vao_id,
+            index_count,
+            material,
+            scale: glm::vec3(1.0, 1.0, 1.0),
+            gravity: glm::zero(),
+            limit: 256,
+            live: vec![],
+            free: vec![],
+            allocated: 0,
+        }
+    }
+
+    // Put an object in the scene until `now + time_to_live`. Gives its node, None if the pool is full.
+    pub fn spawn(&mut self, position: glm::Vec3, velocity: glm::Vec3, now: f32, time_to_live: f32) -> Option<*mut SceneNode> {
+        if self.live.len() >= self.limit {
+            return None;
+        }
+        let node = match self.free.pop() {
+            Some(node) => node,
+            None => {
+                let mut node = SceneNode::from_vao(self.vao_id, self.index_count);
+                node.material = self.material.clone();
+                self.allocated += 1;
+                unsafe { node.as_mut().get_unchecked_mut() as *mut SceneNode }
+            }
+        };
+        unsafe {
+            let node_ref = &mut *node;
+            node_ref.position = scene_graph::position_from_f32(&position);
+            node_ref.rotation = glm::zero();
+            node_ref.scale = self.scale;
+            node_ref.visible = true;
+            (&mut *self.parent).add_child(node_ref);
+        }
+        self.live.push(PooledObject { node, velocity, expires_at: now + time_to_live });
+        Some(node)
+    }
+
+    // Take an object out of the scene before its time is up
+    pub fn despawn(&mut self, node: *mut SceneNode) {
+        if let Some(index) = self.live.iter().position(|object| object.node == node) {
+            let object = self.live.swap_remove(index);
+            self.recycle(object.node);
+        }
+    }
+
+    fn recycle(&mut self, node: *mut SceneNode) {
+        unsafe { (&mut *self.parent).children.retain(|&child| child != node); }
+        self.free.push(node);
+    }
+
+    // * Move the live objects, and recycle the ones that have expired
+    pub fn update(&mut self, now: f32, delta_time: f32) {
+        let mut expired = vec![];
+        for object in &mut self.live {
+            if now >= object.expires_at {
+                expired.push(object.node);
+                continue;
+            }
+            object.velocity += self.gravity * delta_time;
+            unsafe { (&mut *object.node).position += scene_graph::position_from_f32(&(object.velocity * delta_time)); }
+        }
+        self.live.retain(|object| now < object.expires_at);
+        for node in expired {
+            self.recycle(node);
+        }
+    }
+
+    // The node and velocity of every live object, for whoever wants to check them before they move
+    pub fn live_objects(&self) -> Vec<(*mut SceneNode, glm::Vec3)> {
+        self.live.iter().map(|object| (object.node, object.velocity)).collect()
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.live.len()
+    }
+
+    // Nodes made so far, live or waiting in the pool
+    pub fn allocated_count(&self) -> usize {
+        self.allocated
+    }
+}
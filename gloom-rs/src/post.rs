@@ -0,0 +1,99 @@
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::color_grading::ColorGrading;
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+
+// * Post processing chain
+// The scene is drawn into a floating point framebuffer instead of the window, so colors can go
+// above 1 (emissive lights). The chain then runs fullscreen passes over it, ending with tone mapping
+// that brings everything back into what the screen can show, and the color grading after that.
+
+pub struct PostChain {
+    pub hdr_target    : Framebuffer,
+    pub encode_srgb   : bool, // Gamma correct in the tone mapping, for windows that can't do it themselves
+    pub color_grading : ColorGrading,
+    tonemap_shader    : ShaderHandle,
+    empty_vao_id      : u32, // Core profile wants a VAO bound even when the vertex shader makes up the vertices
+}
+
+impl PostChain {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, width: u32, height: u32) -> PostChain {
+        let tonemap_shader = assets.load_shader(&["shaders/fullscreen.vert", "shaders/tonemap.frag"]);
+
+        let mut empty_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut empty_vao_id);
+
+        PostChain {
+            hdr_target: Framebuffer::with_color_format(width as i32, height as i32, gl::RGBA16F, gl::FLOAT).with_label("hdr target"),
+            encode_srgb: false,
+            color_grading: ColorGrading::new(),
+            tonemap_shader,
+            empty_vao_id,
+        }
+    }
+
+    /// Keep the HDR target at the size of the window, before the scene is drawn into it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn resize(&mut self, window_width: u32, window_height: u32) {
+        self.hdr_target.resize(window_width as i32, window_height as i32);
+    }
+
+    /// Run the passes and put the result in the window. `ao_texture` is multiplied in if given (see ssao.rs).
+    /// The exposure is read from `exposure_texture` if given (see auto_exposure.rs), `exposure` otherwise.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn end(&self, assets: &AssetManager, exposure: f32, exposure_texture: Option<u32>, ao_texture: Option<u32>, window_width: u32, window_height: u32) {
+        self.hdr_target.unbind(window_width, window_height);
+
+        gfx::RenderState { depth_test: gfx::DepthTest::Off, ..Default::default() }.apply();
+
+        let shader = assets.shader(self.tonemap_shader);
+        shader.activate();
+        shader.set_uniform_int("hdr_texture", 0);
+        shader.set_uniform_float("exposure", exposure);
+        shader.set_uniform_int("exposure_texture", 2);
+        shader.set_uniform_int("auto_exposure", exposure_texture.is_some() as i32);
+        self.color_grading.set_uniforms(shader);
+        shader.set_uniform_int("encode_srgb", self.encode_srgb as i32);
+        shader.set_uniform_int("ao_texture", 1);
+        shader.set_uniform_int("ao_enabled", ao_texture.is_some() as i32);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, ao_texture.unwrap_or(0));
+        gl::ActiveTexture(gl::TEXTURE2);
+        gl::BindTexture(gl::TEXTURE_2D, exposure_texture.unwrap_or(0));
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.hdr_target.color_texture);
+        self.draw_fullscreen_triangle();
+
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE2);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+        self.color_grading.unbind();
+        gfx::RenderState::default().apply();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw_fullscreen_triangle(&self) {
+        gl::BindVertexArray(self.empty_vao_id);
+        gfx::count_draw_call();
+        gl::DrawArrays(gl::TRIANGLES, 0, 3);
+        gl::BindVertexArray(0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.hdr_target.delete();
+        self.color_grading.delete();
+        gl::DeleteVertexArrays(1, &self.empty_vao_id);
+    }
+}
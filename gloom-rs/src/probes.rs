@@ -0,0 +1,238 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::environment::EnvironmentMap;
+use crate::gfx;
+use crate::math;
+use crate::point_shadows::CUBE_FACES;
+use crate::shader::Shader;
+
+// * Reflection probes
+// The environment map only has the sky in it, so a shiny canopy flying over the terrain reflects
+// nothing of it. A probe is a point in the world the surroundings are captured at, into a cube map,
+// with the scene drawn six times with a 90 degree camera like the point light shadows. The capture is
+// then prefiltered for roughness: every mip level of the probe's cube map is the capture blurred the
+// way a rougher surface would see it (GGX, importance sampled), so pbr.frag can read it exactly like
+// the environment map, with rougher surfaces reading blurrier levels.
+//
+// pbr.frag uses the nearest probe whose sphere the fragment is in, fading into the environment map
+// towards the edge of it. The probes are no better than the point they were captured at, what is close
+// to the probe is reflected in the wrong place further away from it.
+//
+// The lighting changes over the day, so the probes are captured over and over, `faces_per_frame`
+// faces every frame, and each probe is prefiltered again once all six of its faces are new. Only what
+// stays put is captured (see main.rs), a helicopter would show up in the probes it flies through.
+
+pub const MAX_PROBES: usize = 4;      // Same as MAX_PROBES in pbr.frag
+pub const FIRST_PROBE_UNIT: u32 = 11; // Texture units of the probes' cube maps, after the light clusters
+
+struct ReflectionProbe {
+    position    : glm::Vec3,
+    radius      : f32,
+    cube_map_id : u32,  // Prefiltered, rougher on every mip level
+    ready       : bool, // Whether it has been captured all the way round yet
+}
+
+pub struct ReflectionProbes {
+    pub enabled         : bool,
+    pub faces_per_frame : usize,
+    probes              : Vec<ReflectionProbe>,
+    resolution          : i32,
+    mip_levels          : i32,
+    color_format        : gl::types::GLenum,
+    capture_cube        : u32, // Mipmapped, the prefilter reads blurrier levels for wider samples
+    depth_renderbuffer  : u32,
+    fbo_id              : u32,
+    next_face           : usize, // Probe times six plus the face captured next
+    prefilter_shader    : ShaderHandle,
+    empty_vao_id        : u32,
+}
+
+impl ReflectionProbes {
+    /// Cube maps with faces of `resolution` squared. `float_targets` keeps the light above 1 (needs
+    /// GlCaps::float_render_targets), without it the probes are clamped to 0..1.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, resolution: i32, float_targets: bool) -> ReflectionProbes {
+        let mip_levels = (resolution as f32).log2().floor() as i32 + 1;
+        let color_format = if float_targets { gl::RGBA16F } else { gl::RGBA8 };
+        let capture_cube = create_cube_map(resolution, mip_levels, color_format);
+
+        let mut depth_renderbuffer: u32 = 0;
+        gl::GenRenderbuffers(1, &mut depth_renderbuffer);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, depth_renderbuffer);
+        gl::RenderbufferStorage(gl::RENDERBUFFER, gl::DEPTH_COMPONENT24, resolution, resolution);
+        gl::BindRenderbuffer(gl::RENDERBUFFER, 0);
+
+        // The face (and level) drawn into is attached before it is drawn
+        let mut fbo_id: u32 = 0;
+        gl::GenFramebuffers(1, &mut fbo_id);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo_id);
+        gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_CUBE_MAP_POSITIVE_X, capture_cube, 0);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, depth_renderbuffer);
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Reflection probe framebuffer {}x{} is not complete!", resolution, resolution);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        let mut empty_vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut empty_vao_id);
+
+        ReflectionProbes {
+            enabled: true,
+            faces_per_frame: 1,
+            probes: vec![],
+            resolution,
+            mip_levels,
+            color_format,
+            capture_cube,
+            depth_renderbuffer,
+            fbo_id,
+            next_face: 0,
+            prefilter_shader: assets.load_shader(&["shaders/fullscreen.vert", "shaders/probe_prefilter.frag"]),
+            empty_vao_id,
+        }
+    }
+
+    /// Capture the surroundings at `position`, for everything within `radius` of it. Probes past
+    /// MAX_PROBES are left out.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn add(&mut self, position: glm::Vec3, radius: f32) {
+        if self.probes.len() >= MAX_PROBES {
+            println!("Warning: only {} reflection probes are supported, the one at {:?} is left out", MAX_PROBES, position);
+            return;
+        }
+        let cube_map_id = create_cube_map(self.resolution, self.mip_levels, self.color_format);
+        self.probes.push(ReflectionProbe { position, radius, cube_map_id, ready: false });
+    }
+
+    /// Capture the next faces, with the sky `sky_color` behind everything. `draw` draws what the
+    /// probes reflect with the view projection and camera position it is given, with the lit shaders
+    /// set up for this frame. Leaves the window bound, with the viewport it had before.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn update<F: FnMut(&glm::Mat4, glm::Vec3)>(&mut self, assets: &AssetManager, sky_color: [f32; 3], mut draw: F) {
+        if !self.enabled || self.probes.is_empty() {
+            return;
+        }
+
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo_id);
+        gl::Viewport(0, 0, self.resolution, self.resolution);
+        gfx::set_scissor(None);
+
+        let projection = glm::perspective(1.0, std::f32::consts::FRAC_PI_2, 0.5, math::CAMERA_FAR);
+        for _ in 0..self.faces_per_frame {
+            let (probe_index, face) = (self.next_face / 6, self.next_face % 6);
+            self.next_face = (self.next_face + 1) % (self.probes.len() * 6);
+
+            let position = self.probes[probe_index].position;
+            let (direction, up) = (glm::make_vec3(&CUBE_FACES[face].0), glm::make_vec3(&CUBE_FACES[face].1));
+            let view = glm::look_at(&position, &(position + direction), &up);
+            gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as u32, self.capture_cube, 0);
+            gl::ClearColor(sky_color[0], sky_color[1], sky_color[2], 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+            draw(&(projection * view), position);
+
+            if face == 5 {
+                self.prefilter(assets, probe_index);
+                gl::Viewport(0, 0, self.resolution, self.resolution);
+            }
+        }
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    }
+
+    // Blur the capture into every mip level of the probe's cube map, rougher for every level
+    unsafe fn prefilter(&mut self, assets: &AssetManager, probe_index: usize) {
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.capture_cube);
+        gl::GenerateMipmap(gl::TEXTURE_CUBE_MAP);
+
+        gfx::RenderState::fullscreen().apply();
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, 0);
+
+        let shader = assets.shader(self.prefilter_shader);
+        shader.activate();
+        shader.set_uniform_int("source_map", 0);
+        shader.set_uniform_float("source_resolution", self.resolution as f32);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, self.capture_cube);
+        gl::BindVertexArray(self.empty_vao_id);
+
+        let cube_map_id = self.probes[probe_index].cube_map_id;
+        for level in 0..self.mip_levels {
+            let size = (self.resolution >> level).max(1);
+            gl::Viewport(0, 0, size, size);
+            shader.set_uniform_float("roughness", level as f32 / (self.mip_levels - 1).max(1) as f32);
+            for face in 0..6 {
+                gl::FramebufferTexture2D(gl::FRAMEBUFFER, gl::COLOR_ATTACHMENT0, gl::TEXTURE_CUBE_MAP_POSITIVE_X + face, cube_map_id, level);
+                shader.set_uniform_int("face", face as i32);
+                gfx::count_draw_call();
+                gl::DrawArrays(gl::TRIANGLES, 0, 3);
+            }
+        }
+
+        gl::BindVertexArray(0);
+        gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+        gl::FramebufferRenderbuffer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, gl::RENDERBUFFER, self.depth_renderbuffer);
+        gfx::RenderState::default().apply();
+        self.probes[probe_index].ready = true;
+    }
+
+    /// Point pbr.frag at the probes that have been captured, it has to be active. The units of the
+    /// missing probes get `environment`, a sampler has to have something of its type bound.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_uniforms(&self, shader: &Shader, environment: &EnvironmentMap) {
+        let ready: Vec<&ReflectionProbe> = self.probes.iter().filter(|probe| self.enabled && probe.ready).collect();
+        shader.set_uniform_int("probe_count", ready.len() as i32);
+        shader.set_uniform_float("probe_mip_levels", self.mip_levels as f32);
+        for i in 0..MAX_PROBES {
+            let unit = FIRST_PROBE_UNIT + i as u32;
+            shader.set_uniform_int(&format!("probe_maps[{}]", i), unit as i32);
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            match ready.get(i) {
+                Some(probe) => {
+                    shader.set_uniform_vec4(&format!("probe_spheres[{}]", i), &[probe.position.x, probe.position.y, probe.position.z, probe.radius]);
+                    gl::BindTexture(gl::TEXTURE_CUBE_MAP, probe.cube_map_id);
+                }
+                None => gl::BindTexture(gl::TEXTURE_CUBE_MAP, environment.cube_map_id),
+            }
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        for probe in &self.probes {
+            gl::DeleteTextures(1, &probe.cube_map_id);
+        }
+        gl::DeleteTextures(1, &self.capture_cube);
+        gl::DeleteRenderbuffers(1, &self.depth_renderbuffer);
+        gl::DeleteFramebuffers(1, &self.fbo_id);
+        gl::DeleteVertexArrays(1, &self.empty_vao_id);
+    }
+}
+
+// An empty cube map with every mip level, filtered across the face edges like the environment map
+unsafe fn create_cube_map(resolution: i32, mip_levels: i32, color_format: gl::types::GLenum) -> u32 {
+    let mut cube_map_id: u32 = 0;
+    gl::GenTextures(1, &mut cube_map_id);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, cube_map_id);
+    gl::TexStorage2D(gl::TEXTURE_CUBE_MAP, mip_levels, color_format, resolution, resolution);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MIN_FILTER, gl::LINEAR_MIPMAP_LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+    gl::TexParameteri(gl::TEXTURE_CUBE_MAP, gl::TEXTURE_WRAP_R, gl::CLAMP_TO_EDGE as i32);
+    gl::BindTexture(gl::TEXTURE_CUBE_MAP, 0);
+    cube_map_id
+}
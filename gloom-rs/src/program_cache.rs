@@ -0,0 +1,119 @@
+use std::os::raw::c_void;
+use std::path::PathBuf;
+
+use crate::util;
+
+// * Program binary cache
+// Compiling and linking every shader at startup takes seconds on some drivers. After a program is
+// linked its binary is read back with glGetProgramBinary and written to the cache directory, and the
+// next run hands it straight to glProgramBinary instead of compiling anything.
+//
+// A binary is only good for the driver that made it, so the file is named after a hash of the
+// sources (after the #version swap and the uniform blocks are put in, see ShaderBuilder) together
+// with the vendor, renderer and version strings. A driver update gives every program a new name, and
+// the stale files just sit there. The driver can still turn a binary down (the program doesn't link
+// then), which falls back to compiling the sources like there was no cache.
+//
+// The directory is shader_cache, or GLOOM_SHADER_CACHE. GLOOM_SHADER_CACHE=off turns it off.
+
+#[derive(Clone)]
+pub struct ProgramCache {
+    directory : PathBuf,
+    driver    : String, // Vendor, renderer and version, a binary only loads on the same
+}
+
+impl ProgramCache {
+    /// None if the driver can't give out program binaries, or the cache was turned off.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(supported: bool) -> Option<ProgramCache> {
+        let directory = match std::env::var("GLOOM_SHADER_CACHE").as_deref() {
+            Ok("off") | Ok("0") => return None,
+            Ok(directory) => PathBuf::from(directory),
+            Err(_) => PathBuf::from("shader_cache"),
+        };
+        let mut format_count = 0;
+        if supported {
+            gl::GetIntegerv(gl::NUM_PROGRAM_BINARY_FORMATS, &mut format_count);
+        }
+        if format_count == 0 {
+            println!("Program binaries are not supported, shaders are compiled every time");
+            return None;
+        }
+        if let Err(error) = std::fs::create_dir_all(&directory) {
+            println!("Warning: couldn't make the shader cache {}: {}", directory.display(), error);
+            return None;
+        }
+
+        let driver = format!("{}\n{}\n{}", util::get_gl_string(gl::VENDOR), util::get_gl_string(gl::RENDERER), util::get_gl_string(gl::VERSION));
+        Some(ProgramCache { directory, driver })
+    }
+
+    fn path(&self, sources: &[(gl::types::GLenum, String)]) -> PathBuf {
+        let mut hash = fnv1a(FNV_OFFSET, self.driver.as_bytes());
+        for (shader_type, source) in sources {
+            // Where one source ends counts too
+            hash = fnv1a(hash, &shader_type.to_le_bytes());
+            hash = fnv1a(hash, source.as_bytes());
+            hash = fnv1a(hash, &[0]);
+        }
+        self.directory.join(format!("{:016x}.bin", hash))
+    }
+
+    /// Put the cached binary of the program made of `sources` into `program_id`. False if there is
+    /// none, or the driver didn't take it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn load(&self, program_id: u32, sources: &[(gl::types::GLenum, String)]) -> bool {
+        // The binary format first, then the binary
+        let bytes = match std::fs::read(self.path(sources)) {
+            Ok(bytes) if bytes.len() > 4 => bytes,
+            _ => return false,
+        };
+        let format = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        let binary = &bytes[4..];
+        gl::ProgramBinary(program_id, format, binary.as_ptr() as *const c_void, binary.len() as i32);
+
+        let mut success = i32::from(gl::FALSE);
+        gl::GetProgramiv(program_id, gl::LINK_STATUS, &mut success);
+        success == i32::from(gl::TRUE)
+    }
+
+    /// Write the binary of `program_id`, linked from `sources`, for the next run.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context, and `program_id` linked with PROGRAM_BINARY_RETRIEVABLE_HINT.
+    pub unsafe fn store(&self, program_id: u32, sources: &[(gl::types::GLenum, String)]) {
+        let mut length = 0;
+        gl::GetProgramiv(program_id, gl::PROGRAM_BINARY_LENGTH, &mut length);
+        if length <= 0 {
+            return;
+        }
+        let mut binary = vec![0u8; length as usize];
+        let mut format: u32 = 0;
+        let mut written = 0;
+        gl::GetProgramBinary(program_id, length, &mut written, &mut format, binary.as_mut_ptr() as *mut c_void);
+        binary.truncate(written.max(0) as usize);
+
+        let mut bytes = format.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&binary);
+        let path = self.path(sources);
+        if let Err(error) = std::fs::write(&path, bytes) {
+            println!("Warning: couldn't write the shader cache {}: {}", path.display(), error);
+        }
+    }
+}
+
+// FNV-1a, unlike std's hasher it is the same from one build to the next
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
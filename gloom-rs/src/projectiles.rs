@@ -0,0 +1,146 @@
+extern crate nalgebra_glm as glm;
+
+use std::rc::Rc;
+
+use rand::Rng;
+
+use crate::bvh::{Bvh, Ray};
+use crate::ecs::{self, Entity, World};
+use crate::lights::Light;
+use crate::material::Material;
+use crate::pool::NodePool;
+use crate::scene_graph::{self, SceneNode};
+use crate::trails::{TrailId, TrailRenderer, TrailStyle};
+
+// * Projectiles
+// Signal flares fired from the helicopters, B fires one from each. A flare is a small glowing sphere
+// from a NodePool with a point light on its node, so it lights up what it flies past. The pool moves it
+// along with the moon's gravity, and before every step the way it is about to go is checked against the
+// terrain with a ray through the BVH. If it hits, the flare goes back to the pool and where it hit gets
+// an impact: an entity with a burst of sparks and a bright point light, which fades out and is
+// despawned once the last spark is gone. Every flare leaves a trail of smoke behind it.
+
+const LAUNCH_SPEED  : f32 = 12.0;
+const LAUNCH_ANGLE  : f32 = 0.6;   // Radians up from the helicopter's heading
+const BURN_TIME     : f32 = 8.0;   // Seconds a flare flies before it goes out in the air
+const FLARE_COLOR   : [f32; 3] = [1.0, 0.3, 0.2];
+const IMPACT_FLASH  : f32 = 0.15;  // Seconds the sparks are coming out for
+const IMPACT_SPARKS : f32 = 400.0; // Per second while they are
+const IMPACT_LIFE   : f32 = 1.2;   // Seconds a spark lives, and the light fades over
+
+struct Impact {
+    entity : Entity,
+    age    : f32,
+}
+
+pub struct ProjectileSystem {
+    pool        : NodePool,
+    mesh_center : glm::Vec3,             // Where the middle of the flare is, scaled, from its node's origin
+    spark_mesh  : (u32, i32, glm::Vec3), // VAO, index count and center, like a ParticleEmitter's
+    impacts     : Vec<Impact>,
+    smoke       : Vec<(*mut SceneNode, TrailId)>, // The trail behind every flare in the air
+    pub fired   : usize,
+    pub hits    : usize,
+}
+
+impl ProjectileSystem {
+    // The flares are children of `parent`, drawn with the VAO, and `mesh_center` is the middle of the
+    // mesh in its own units. The sparks are drawn with `spark_mesh`, the same way.
+    pub fn new(parent: &mut SceneNode, flare_mesh: (u32, i32, glm::Vec3), spark_mesh: (u32, i32, glm::Vec3), scale: f32) -> ProjectileSystem {
+        let (vao_id, index_count, mesh_center) = flare_mesh;
+        let mut pool = NodePool::new(parent, vao_id, index_count, Some(Rc::new(Material::emissive("flare", FLARE_COLOR, 12.0))));
+        pool.scale = glm::vec3(scale, scale, scale);
+        pool.gravity = glm::vec3(0.0, -1.6, 0.0); // The moon's
+        pool.limit = 64;
+        ProjectileSystem { pool, mesh_center: mesh_center * scale, spark_mesh, impacts: vec![], smoke: vec![], fired: 0, hits: 0 }
+    }
+
+    // Fire a flare from the middle of `node`, up and ahead the way it is heading
+    pub fn launch_from(&mut self, node: &SceneNode, trails: &mut TrailRenderer, now: f32) {
+        let position = scene_graph::position_to_f32(&node.position);
+        let yaw = node.rotation.y;
+        // The helicopters fly towards -(sin yaw, cos yaw), see Camera::chase
+        let ahead = glm::vec3(-yaw.sin(), 0.0, -yaw.cos());
+        let spread = glm::vec3(rand::thread_rng().gen_range(-1.0..1.0), 0.0, rand::thread_rng().gen_range(-1.0..1.0));
+        let velocity = (ahead * LAUNCH_ANGLE.cos() + glm::vec3(0.0, LAUNCH_ANGLE.sin(), 0.0)) * LAUNCH_SPEED + spread;
+        if let Some(flare) = self.pool.spawn(position - self.mesh_center, velocity, now, BURN_TIME) {
+            unsafe { (*flare).light = Some(Light::point(FLARE_COLOR, 40.0, 12.0)); }
+            self.smoke.push((flare, trails.add(TrailStyle::smoke())));
+            self.fired += 1;
+        }
+    }
+
+    // * Move the flares, and set off an impact where one hits the terrain
+    // Call before ecs::update_transforms and ecs::update_particles, so the impacts start this frame.
+    pub fn update(&mut self, world: &mut World, trails: &mut TrailRenderer, terrain_bvh: &Bvh, now: f32, delta_time: f32) {
+        if delta_time > 0.0 {
+            for (flare, velocity) in self.pool.live_objects() {
+                let position = unsafe { scene_graph::position_to_f32(&(*flare).position) } + self.mesh_center;
+                // The step the pool is about to take, a hit within 1.0 of the ray is on the way
+                let step = (velocity + self.pool.gravity * delta_time) * delta_time;
+                if let Some(hit) = terrain_bvh.closest_hit(&Ray::new(position, step), 1.0) {
+                    self.pool.despawn(flare);
+                    self.impact(world, hit.position + hit.normal * 0.1);
+                }
+            }
+        }
+        self.pool.update(now, delta_time);
+
+        // The smoke follows the flares still in the air, and is left to fade behind the rest
+        let live: Vec<*mut SceneNode> = self.pool.live_objects().iter().map(|&(flare, _)| flare).collect();
+        let mesh_center = self.mesh_center;
+        self.smoke.retain(|&(flare, trail)| {
+            if live.contains(&flare) {
+                trails.push(trail, unsafe { scene_graph::position_to_f32(&(*flare).position) } + mesh_center, now);
+                true
+            } else {
+                trails.release(trail);
+                false
+            }
+        });
+
+        for impact in &mut self.impacts {
+            impact.age += delta_time;
+            let fade = (1.0 - impact.age / IMPACT_LIFE).max(0.0);
+            if let Some(light) = world.lights.get_mut(impact.entity) {
+                light.intensity = 300.0 * fade * fade;
+            }
+            if let Some(emitter) = world.particle_emitters.get_mut(impact.entity) {
+                emitter.emitting = impact.age < IMPACT_FLASH;
+            }
+        }
+        self.impacts.retain(|impact| {
+            let done = impact.age > IMPACT_FLASH + IMPACT_LIFE;
+            if done {
+                world.despawn(impact.entity);
+            }
+            !done
+        });
+    }
+
+    fn impact(&mut self, world: &mut World, position: glm::Vec3) {
+        let (vao_id, index_count, mesh_center) = self.spark_mesh;
+        let entity = world.spawn();
+        world.transforms.insert(entity, ecs::Transform::at(position));
+        let mut sparks = ecs::ParticleEmitter::new(vao_id, index_count, Some(Rc::new(Material::emissive("flare sparks", FLARE_COLOR, 8.0))), IMPACT_SPARKS, IMPACT_LIFE);
+        sparks.mesh_center = mesh_center;
+        sparks.velocity = glm::vec3(0.0, 4.0, 0.0);
+        sparks.spread = 4.0;
+        sparks.gravity = glm::vec3(0.0, -1.6, 0.0);
+        sparks.size = 0.006;
+        sparks.max_count = 64;
+        world.particle_emitters.insert(entity, sparks);
+        world.lights.insert(entity, Light::point(FLARE_COLOR, 300.0, 25.0));
+        self.impacts.push(Impact { entity, age: 0.0 });
+        self.hits += 1;
+    }
+
+    pub fn live_count(&self) -> usize {
+        self.pool.live_count()
+    }
+
+    // Nodes made so far, live or waiting in the pool
+    pub fn allocated_count(&self) -> usize {
+        self.pool.allocated_count()
+    }
+}
@@ -0,0 +1,116 @@
+use crate::gl_caps::GlCaps;
+
+// * Driver quirks
+// The renderer assumes the driver does what the spec says, but the lab machines this runs on range
+// from new desktop cards to old laptops and virtual machines, and some of their drivers don't. The
+// ones we know about are in QUIRKS, by the GL_VENDOR and GL_RENDERER strings printed at startup, with
+// what to do about them: mostly turning a feature off in the capabilities, so the code path that
+// doesn't need it is taken, and sometimes an extra step the driver needs (a glFinish after a resize).
+//
+// A rule matches when its vendor and renderer are in the driver's strings, ignoring case, an empty one
+// matches anything. Mesa names the GPUs the same way as their own drivers do, so a rule can say which
+// of the two it is for.
+//
+// GLOOM_QUIRKS=off turns the table off, to check whether a workaround is still needed, and
+// GLOOM_QUIRKS=FinishAfterResize,NoPersistentMapping turns those on as well, to try them on a driver
+// that isn't in the table yet. Only add a driver to QUIRKS with the bug report it works around, and
+// with a renderer narrow enough that the drivers without the bug don't lose the feature too. Until
+// one is known, the table is empty and the workarounds are only turned on with GLOOM_QUIRKS.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Workaround {
+    NoPersistentMapping, // Stream through glBufferSubData instead of a persistently mapped ring buffer
+    NoProgramBinaries,   // Don't keep linked programs in the shader cache
+    NoClipControl,       // Standard depth, without glClipControl
+    FinishAfterResize,   // Wait for the GPU after the window is resized, before drawing into it
+}
+
+const ALL_WORKAROUNDS: [Workaround; 4] = [
+    Workaround::NoPersistentMapping,
+    Workaround::NoProgramBinaries,
+    Workaround::NoClipControl,
+    Workaround::FinishAfterResize,
+];
+
+#[allow(dead_code)] // Not made while QUIRKS is empty
+struct Quirk {
+    vendor     : &'static str,
+    renderer   : &'static str,
+    mesa       : Option<bool>, // Only the Mesa drivers, or only the others, None for both
+    workaround : Workaround,
+    reason     : &'static str,
+    bug        : &'static str, // Where the driver bug is reported
+}
+
+const QUIRKS: &[Quirk] = &[];
+
+pub struct DriverQuirks {
+    active : Vec<(Workaround, &'static str)>, // With why
+}
+
+impl DriverQuirks {
+    /// The workarounds for the driver of `caps`, and those asked for with GLOOM_QUIRKS.
+    pub fn detect(caps: &GlCaps) -> DriverQuirks {
+        let setting = std::env::var("GLOOM_QUIRKS").unwrap_or_default();
+        if setting == "off" {
+            println!("Driver workarounds are off");
+            return DriverQuirks { active: vec![] };
+        }
+        let (vendor, renderer, mesa) = (caps.vendor.to_lowercase(), caps.renderer.to_lowercase(), caps.is_mesa());
+        let mut quirks = DriverQuirks { active: vec![] };
+        for quirk in QUIRKS {
+            if vendor.contains(&quirk.vendor.to_lowercase())
+                && renderer.contains(&quirk.renderer.to_lowercase())
+                && quirk.mesa.is_none_or(|only_mesa| only_mesa == mesa)
+            {
+                quirks.add(quirk.workaround, quirk.reason);
+            }
+        }
+        for name in setting.split(',').map(str::trim).filter(|name| !name.is_empty()) {
+            match ALL_WORKAROUNDS.iter().find(|workaround| format!("{:?}", workaround) == name) {
+                Some(&workaround) => quirks.add(workaround, "asked for with GLOOM_QUIRKS"),
+                None => println!("GLOOM_QUIRKS: there is no workaround called '{}'", name),
+            }
+        }
+        quirks
+    }
+
+    fn add(&mut self, workaround: Workaround, reason: &'static str) {
+        if !self.has(workaround) {
+            self.active.push((workaround, reason));
+        }
+    }
+
+    pub fn has(&self, workaround: Workaround) -> bool {
+        self.active.iter().any(|&(active, _)| active == workaround)
+    }
+
+    /// Turn off the features the driver can't be trusted with, before anything picks a code path from them.
+    pub fn apply_to(&self, caps: &mut GlCaps) {
+        if self.has(Workaround::NoPersistentMapping) {
+            caps.buffer_storage = false;
+        }
+        if self.has(Workaround::NoProgramBinaries) {
+            caps.program_binaries = false;
+        }
+        if self.has(Workaround::NoClipControl) {
+            caps.clip_control = false;
+        }
+    }
+
+    /// Call after the context was resized.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn after_resize(&self) {
+        if self.has(Workaround::FinishAfterResize) {
+            gl::Finish();
+        }
+    }
+
+    pub fn print(&self) {
+        for (workaround, reason) in &self.active {
+            println!("Driver workaround {:?}: {}", workaround, reason);
+        }
+    }
+}
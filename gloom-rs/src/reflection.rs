@@ -0,0 +1,238 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::camera::Camera;
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::util;
+use crate::viewport::Viewport;
+
+// * Reflective surfaces
+// A flat, horizontal surface (a frozen lake) that mirrors the scene. Two ways of getting the reflection:
+//  - Planar: the scene is drawn a second time, mirrored about the surface, into a texture the size of
+//    the window. Every view draws into its own region of it, so the surface shader can look up its
+//    reflection at the same pixel it is drawn at. Correct, but the scene is drawn twice.
+//  - Screen space: rays are marched through the depth buffer of the already drawn scene. Much
+//    cheaper, but only reflects what is on screen. Needs the scene in a framebuffer (HDR on).
+// Both are blended with the ice color by a Fresnel term, so the reflection is strongest at grazing angles.
+// The mirrored scene is cut off at the surface with gl_ClipDistance, without it (see GlCaps) there are
+// only screen space reflections, and none at all when the scene isn't in a framebuffer.
+//
+// The planar reflection is a PlanarReflection of its own, so other surfaces can mirror the scene the
+// same way (see WaterSurface).
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReflectionMode {
+    Planar,
+    ScreenSpace,
+}
+
+impl ReflectionMode {
+    pub fn next(self) -> ReflectionMode {
+        match self {
+            ReflectionMode::Planar      => ReflectionMode::ScreenSpace,
+            ReflectionMode::ScreenSpace => ReflectionMode::Planar,
+        }
+    }
+}
+
+// The scene mirrored about a horizontal plane, drawn into a texture the size of the window
+pub struct PlanarReflection {
+    pub height : f32,         // Of the mirror plane
+    target     : Framebuffer,
+}
+
+impl PlanarReflection {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(height: f32, window_width: u32, window_height: u32, label: &'static str) -> PlanarReflection {
+        PlanarReflection {
+            height,
+            target: Framebuffer::with_color_format(window_width as i32, window_height as i32, gl::RGBA16F, gl::FLOAT).with_label(label),
+        }
+    }
+
+    // Mirrors points about the plane
+    pub fn mirror_matrix(&self) -> glm::Mat4 {
+        glm::translation(&glm::vec3(0.0, self.height, 0.0))
+            * glm::scaling(&glm::vec3(1.0, -1.0, 1.0))
+            * glm::translation(&glm::vec3(0.0, -self.height, 0.0))
+    }
+
+    /// Draw the mirrored scene for every view. `draw` is called once per view with the mirrored
+    /// view projection, the mirrored camera position and the clip plane (as a, b, c, d with
+    /// a*x + b*y + c*z + d >= 0 kept) that cuts away everything below the plane.
+    /// Leaves the window bound as the framebuffer.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn render<F: FnMut(&glm::Mat4, glm::Vec3, [f32; 4])>(
+        &mut self,
+        window_width: u32,
+        window_height: u32,
+        views: &[(Viewport, Camera, glm::Mat4)],
+        sky_color: [f32; 3],
+        mut draw: F,
+    ) {
+        self.target.resize(window_width as i32, window_height as i32);
+        self.target.bind();
+
+        // Mirroring turns the triangles inside out
+        gl::FrontFace(gl::CW);
+        gl::Enable(gl::CLIP_DISTANCE0);
+
+        let mirror = self.mirror_matrix();
+        for (view, camera, view_projection) in views {
+            view.activate();
+            gl::ClearColor(sky_color[0], sky_color[1], sky_color[2], 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+
+            let camera_position = camera.position_f32();
+            let mirrored_camera = mirror * glm::vec4(camera_position.x, camera_position.y, camera_position.z, 1.0);
+            draw(&(view_projection * mirror), mirrored_camera.xyz(), [0.0, 1.0, 0.0, -self.height]);
+        }
+
+        gl::Disable(gl::CLIP_DISTANCE0);
+        gl::FrontFace(gl::CCW);
+        gfx::set_scissor(None);
+        self.target.unbind(window_width, window_height);
+    }
+
+    // The texture the mirrored scene is in, and its size in pixels
+    pub fn texture(&self) -> (u32, [f32; 2]) {
+        (self.target.color_texture, [self.target.width as f32, self.target.height as f32])
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.target.delete();
+    }
+}
+
+pub struct ReflectivePlane {
+    pub mode         : ReflectionMode,
+    pub center       : glm::Vec3, // Center of the surface, its height is the mirror plane
+    pub size         : f32,       // Side length
+    pub tint         : [f32; 3],  // Color of the surface itself
+    planar            : Option<PlanarReflection>, // None without clip distances
+    scene_copy        : Framebuffer, // Color and depth of the scene for screen space reflections
+    vao_id            : u32,
+    index_count       : i32,
+    shader            : ShaderHandle,
+}
+
+impl ReflectivePlane {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, center: glm::Vec3, size: f32, tint: [f32; 3], width: u32, height: u32, clip_distance: bool) -> ReflectivePlane {
+        let (vao_id, index_count) = util::create_quad_vao(size, size);
+        ReflectivePlane {
+            mode: if clip_distance { ReflectionMode::Planar } else { ReflectionMode::ScreenSpace },
+            center,
+            size,
+            tint,
+            planar: if clip_distance { Some(PlanarReflection::new(center.y, width, height, "reflection")) } else { None },
+            scene_copy: Framebuffer::with_color_format(width as i32, height as i32, gl::RGBA16F, gl::FLOAT).with_label("reflection scene copy"),
+            vao_id,
+            index_count,
+            shader: assets.load_shader(&["shaders/reflective.vert", "shaders/reflective.frag"]),
+        }
+    }
+
+    // The quad lies in the XY plane facing +Z, turn it to lie flat facing up
+    fn model_matrix(&self) -> glm::Mat4 {
+        glm::translation(&self.center) * glm::rotation(-std::f32::consts::FRAC_PI_2, &glm::vec3(1.0, 0.0, 0.0))
+    }
+
+    // Whether the planar reflections have to be drawn this frame
+    pub fn needs_planar_pass(&self, scene_in_framebuffer: bool) -> bool {
+        self.planar.is_some() && (self.mode == ReflectionMode::Planar || !scene_in_framebuffer)
+    }
+
+    /// Draw the scene mirrored about the surface for every view, see PlanarReflection::render.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn render_reflections<F: FnMut(&glm::Mat4, glm::Vec3, [f32; 4])>(
+        &mut self,
+        window_width: u32,
+        window_height: u32,
+        views: &[(Viewport, Camera, glm::Mat4)],
+        sky_color: [f32; 3],
+        draw: F,
+    ) {
+        if let Some(planar) = self.planar.as_mut() {
+            planar.height = self.center.y;
+            planar.render(window_width, window_height, views, sky_color, draw);
+        }
+    }
+
+    /// Draw the surface into `view`. `scene` is the framebuffer the scene is being drawn into, if any,
+    /// which screen space reflections read from. It stays bound afterwards.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&mut self, assets: &AssetManager, view: &Viewport, view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3, scene: Option<&Framebuffer>) {
+        let screen_space = (self.mode == ReflectionMode::ScreenSpace || self.planar.is_none()) && scene.is_some();
+
+        // Screen space reflections can't read the framebuffer they draw into, so take a copy of the view
+        if let (true, Some(scene)) = (screen_space, scene) {
+            self.scene_copy.resize(scene.width, scene.height);
+            gl::BindFramebuffer(gl::READ_FRAMEBUFFER, scene.fbo_id);
+            gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, self.scene_copy.fbo_id);
+            let (x0, y0, x1, y1) = (view.x, view.y, view.x + view.width, view.y + view.height);
+            gl::BlitFramebuffer(x0, y0, x1, y1, x0, y0, x1, y1, gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT, gl::NEAREST);
+            gl::BindFramebuffer(gl::FRAMEBUFFER, scene.fbo_id);
+        }
+
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        let model_matrix = self.model_matrix();
+        shader.set_uniform_mat4("mvp_matrix", &(view_projection_matrix * model_matrix));
+        shader.set_uniform_mat4("model_matrix", &model_matrix);
+        shader.set_uniform_mat4("view_projection", view_projection_matrix);
+        shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+        shader.set_uniform_vec3("tint", &self.tint);
+        shader.set_uniform_vec4("view_rect", &[view.x as f32, view.y as f32, view.width as f32, view.height as f32]);
+        let reflection_kind = match (screen_space, &self.planar) {
+            (true, _)        => 1,
+            (false, Some(_)) => 0,
+            (false, None)    => 2,
+        };
+        shader.set_uniform_int("reflection_kind", reflection_kind);
+        shader.set_uniform_int("reversed_z", (gfx::depth_mode() == gfx::DepthMode::Reversed) as i32);
+
+        let (reflection_texture, texture_size) = match (screen_space, &self.planar) {
+            (false, Some(planar)) => planar.texture(),
+            _ => (self.scene_copy.color_texture, [self.scene_copy.width as f32, self.scene_copy.height as f32]),
+        };
+        shader.set_uniform_vec2("texture_size", &texture_size);
+        shader.set_uniform_int("reflection_texture", 0);
+        shader.set_uniform_int("depth_texture", 1);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, reflection_texture);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, self.scene_copy.depth_texture);
+
+        gfx::RenderState { cull: gfx::CullMode::Off, ..Default::default() }.apply();
+        gl::BindVertexArray(self.vao_id);
+        gfx::count_draw_call();
+        gl::DrawElements(gl::TRIANGLES, self.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        gfx::RenderState::default().apply();
+
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        util::delete_vao(self.vao_id);
+        if let Some(planar) = &self.planar {
+            planar.delete();
+        }
+        self.scene_copy.delete();
+    }
+}
@@ -0,0 +1,174 @@
+use crate::framebuffer::Framebuffer;
+use crate::gfx::{self, DepthMode};
+use crate::spikes::SpikeCapture;
+
+// * Render graph
+// A frame is a list of passes: the shadow maps, the camera views, the post processing, the overlays.
+// Every pass says which resources it reads and writes, what it draws into and with which depth
+// convention. The graph puts the passes in an order where everything a pass reads is written before
+// it, binds what it draws into (with the viewport over all of it and no scissor), switches the depth
+// convention, and runs it. Passes that bind framebuffers of their own (the shadow maps, the probes)
+// draw into Target::Own, and leave the window bound when they are done.
+//
+// A resource can be written by more than one pass (the scene by the views, the lake, the anti-aliasing
+// and the god rays). Those run in the order they were added, each reading what the one before left.
+// A pass that only reads it gets what all of them left, wherever it was added.
+//
+// The graph is made anew every frame, with only the passes that are on this frame, which is cheap
+// next to drawing anything. Passes borrow what they draw with for as long as the graph lives; what
+// more than one of them changes goes in a RefCell for the frame.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Resource {
+    ShadowMaps,       // Of the sun and the point light
+    Lighting,         // The lit shaders set up with this frame's lights, shadows and environment
+    Probes,           // The reflection probes' cube maps
+    MonitorFeed,
+    Reflections,      // The planar reflections of the lake and the water
+    Scene,            // What the views are drawn into: color, depth and motion
+    AmbientOcclusion,
+    Exposure,
+    Window,
+}
+
+#[derive(Clone, Copy)]
+pub enum Target<'a> {
+    Own,                          // The pass binds what it draws into itself
+    Window,
+    Framebuffer(&'a Framebuffer),
+}
+
+pub struct Pass<'a> {
+    name       : &'static str,
+    reads      : Vec<Resource>,
+    writes     : Vec<Resource>,
+    target     : Target<'a>,
+    depth_mode : DepthMode,
+    enabled    : bool,
+    run        : Box<dyn FnMut() + 'a>,
+}
+
+impl<'a> Pass<'a> {
+    pub fn new(name: &'static str, run: impl FnMut() + 'a) -> Pass<'a> {
+        Pass {
+            name,
+            reads: vec![],
+            writes: vec![],
+            target: Target::Own,
+            depth_mode: DepthMode::Standard,
+            enabled: true,
+            run: Box::new(run),
+        }
+    }
+
+    pub fn reads(mut self, resources: &[Resource]) -> Pass<'a> {
+        self.reads.extend_from_slice(resources);
+        self
+    }
+
+    pub fn writes(mut self, resources: &[Resource]) -> Pass<'a> {
+        self.writes.extend_from_slice(resources);
+        self
+    }
+
+    pub fn draws_into(mut self, target: Target<'a>) -> Pass<'a> {
+        self.target = target;
+        self
+    }
+
+    // The camera views and what reads their depth, see gfx::DepthMode
+    pub fn with_depth_mode(mut self, depth_mode: DepthMode) -> Pass<'a> {
+        self.depth_mode = depth_mode;
+        self
+    }
+
+    // Left out of the frame unless `condition` holds
+    pub fn only_if(mut self, condition: bool) -> Pass<'a> {
+        self.enabled &= condition;
+        self
+    }
+}
+
+pub struct RenderGraph<'a> {
+    passes      : Vec<Pass<'a>>,
+    window_size : (u32, u32),
+}
+
+impl<'a> RenderGraph<'a> {
+    pub fn new(window_width: u32, window_height: u32) -> RenderGraph<'a> {
+        RenderGraph { passes: vec![], window_size: (window_width, window_height) }
+    }
+
+    pub fn add(&mut self, pass: Pass<'a>) {
+        if pass.enabled {
+            self.passes.push(pass);
+        }
+    }
+
+    // Whether the `after`th pass has to run after the `before`th
+    fn depends_on(&self, after: usize, before: usize) -> bool {
+        let (later, earlier) = (&self.passes[after], &self.passes[before]);
+        earlier.writes.iter().any(|resource| {
+            if later.writes.contains(resource) {
+                before < after
+            } else {
+                later.reads.contains(resource)
+            }
+        })
+    }
+
+    // Indices of the passes in the order they run: every pass after what it depends on, and otherwise
+    // in the order they were added
+    fn order(&self) -> Vec<usize> {
+        let mut order = vec![];
+        let mut done = vec![false; self.passes.len()];
+        while order.len() < self.passes.len() {
+            let next = (0..self.passes.len()).find(|&pass| {
+                !done[pass] && (0..self.passes.len()).all(|other| other == pass || done[other] || !self.depends_on(pass, other))
+            });
+            match next {
+                Some(pass) => {
+                    done[pass] = true;
+                    order.push(pass);
+                }
+                None => {
+                    let stuck: Vec<&str> = (0..self.passes.len()).filter(|&pass| !done[pass]).map(|pass| self.passes[pass].name).collect();
+                    panic!("The render passes {:?} wait on each other", stuck);
+                }
+            }
+        }
+        order
+    }
+
+    /// Run every pass, and go back to the standard depth convention after. Every pass is a section of
+    /// the frame for the spike capture, and a debug group for frame captures. Returns the names of the passes in the order they ran.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn execute(mut self, spikes: &mut SpikeCapture) -> Vec<&'static str> {
+        let order = self.order();
+        let (window_width, window_height) = self.window_size;
+        for &index in &order {
+            let pass = &mut self.passes[index];
+            spikes.mark(pass.name);
+            gfx::set_depth_mode(pass.depth_mode);
+            match pass.target {
+                Target::Own => {}
+                Target::Window => {
+                    gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+                    gl::Viewport(0, 0, window_width as i32, window_height as i32);
+                    gfx::set_scissor(None);
+                }
+                Target::Framebuffer(framebuffer) => {
+                    framebuffer.bind();
+                    gfx::set_scissor(None);
+                }
+            }
+            gfx::push_debug_group(pass.name);
+            (pass.run)();
+            gfx::pop_debug_group();
+        }
+        gfx::set_depth_mode(DepthMode::Standard);
+        order.iter().map(|&index| self.passes[index].name).collect()
+    }
+}
@@ -0,0 +1,269 @@
+extern crate nalgebra_glm as glm;
+
+use std::cell::Cell;
+
+use crate::camera::CameraOrigin;
+use crate::scene_graph::Position;
+use crate::shader::Shader;
+
+// * Persistently mapped ring buffer
+// Per frame data (like the transforms of every draw) is written straight into a buffer that stays
+// mapped for the whole run, instead of going through a glUniform* or glBufferSubData call per draw.
+// The buffer is split in three regions, one per frame in flight: while the GPU still reads the region
+// of an earlier frame, the CPU writes the next one. A fence at the end of every frame says when the
+// GPU is done with a region, and begin_frame() waits for it before the region is written again.
+// Anything Copy can go in, as long as the shader reading it agrees on the layout.
+
+const FRAMES_IN_FLIGHT: usize = 3;
+
+// Where the ring is bound for the draw transforms. Set from here instead of with `binding` in the
+// shaders, which ES 3.0 doesn't have.
+pub const DRAW_TRANSFORMS_BINDING: u32 = 0;
+
+pub struct RingBuffer {
+    pub buffer_id : u32,
+    mapped        : *mut u8,
+    frame_size    : usize,
+    alignment     : usize,    // Every push starts at a multiple of this
+    frame         : usize,    // Region written this frame
+    offset        : Cell<usize>, // Next free byte in the region, a Cell so any number of draws can share the ring
+    fences        : [gl::types::GLsync; FRAMES_IN_FLIGHT],
+}
+
+impl RingBuffer {
+    /// A ring with `frame_size` bytes per frame, meant to be bound as `target`
+    /// (e.g. UNIFORM_BUFFER, which decides the alignment of every push).
+    /// Without `buffer_storage` (see GlCaps) nothing gets mapped and every push returns None.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(target: gl::types::GLenum, frame_size: usize, buffer_storage: bool) -> RingBuffer {
+        let mut alignment: i32 = 1;
+        match target {
+            gl::UNIFORM_BUFFER => gl::GetIntegerv(gl::UNIFORM_BUFFER_OFFSET_ALIGNMENT, &mut alignment),
+            gl::SHADER_STORAGE_BUFFER => gl::GetIntegerv(gl::SHADER_STORAGE_BUFFER_OFFSET_ALIGNMENT, &mut alignment),
+            _ => { }
+        }
+        let alignment = alignment.max(1) as usize;
+        let frame_size = frame_size.div_ceil(alignment) * alignment;
+
+        let mut ring = RingBuffer {
+            buffer_id: 0,
+            mapped: std::ptr::null_mut(),
+            frame_size,
+            alignment,
+            frame: 0,
+            offset: Cell::new(0),
+            fences: [std::ptr::null(); FRAMES_IN_FLIGHT],
+        };
+        if !buffer_storage {
+            println!("Warning: glBufferStorage is not available, per draw data is uploaded the slow way");
+            return ring;
+        }
+
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+        let total_size = (frame_size * FRAMES_IN_FLIGHT) as isize;
+        gl::GenBuffers(1, &mut ring.buffer_id);
+        gl::BindBuffer(target, ring.buffer_id);
+        gl::BufferStorage(target, total_size, std::ptr::null(), flags);
+        ring.mapped = gl::MapBufferRange(target, 0, total_size, flags) as *mut u8;
+        gl::BindBuffer(target, 0);
+        ring
+    }
+
+    /// Move on to the next region, waiting for the GPU to finish reading it if it has to.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn begin_frame(&mut self) {
+        if self.mapped.is_null() {
+            return;
+        }
+        self.frame = (self.frame + 1) % FRAMES_IN_FLIGHT;
+        self.offset.set(0);
+
+        let fence = self.fences[self.frame];
+        if !fence.is_null() {
+            loop {
+                let result = gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 1_000_000_000);
+                if result == gl::ALREADY_SIGNALED || result == gl::CONDITION_SATISFIED || result == gl::WAIT_FAILED {
+                    break;
+                }
+            }
+            gl::DeleteSync(fence);
+            self.fences[self.frame] = std::ptr::null();
+        }
+    }
+
+    /// Done with this frame's region, the GPU is fenced off from it until it has drawn the frame.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn end_frame(&mut self) {
+        if !self.mapped.is_null() {
+            self.fences[self.frame] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+        }
+    }
+
+    /// Fence off the region of a frame that panicked before its end_frame(), so begin_frame() still
+    /// waits for whatever the GPU was given of it. Does nothing if the frame was ended.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn reset_after_panic(&mut self) {
+        if !self.mapped.is_null() && self.fences[self.frame].is_null() {
+            self.end_frame();
+        }
+    }
+
+    /// Copy `data` into this frame's region. Gives its byte offset in the buffer, for glBindBufferRange,
+    /// or None if the region is full (or nothing is mapped).
+    ///
+    /// # Safety
+    /// Must be between begin_frame() and end_frame().
+    pub unsafe fn push<T: Copy>(&self, data: &[T]) -> Option<usize> {
+        if self.mapped.is_null() {
+            return None;
+        }
+        let size = std::mem::size_of_val(data);
+        let offset = self.offset.get();
+        if offset + size > self.frame_size {
+            return None;
+        }
+        self.offset.set((offset + size).div_ceil(self.alignment) * self.alignment);
+
+        let buffer_offset = self.frame * self.frame_size + offset;
+        std::ptr::copy_nonoverlapping(data.as_ptr() as *const u8, self.mapped.add(buffer_offset), size);
+        Some(buffer_offset)
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        for fence in self.fences.iter().filter(|fence| !fence.is_null()) {
+            gl::DeleteSync(*fence);
+        }
+        if self.buffer_id != 0 {
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, self.buffer_id);
+            gl::UnmapBuffer(gl::COPY_WRITE_BUFFER);
+            gl::BindBuffer(gl::COPY_WRITE_BUFFER, 0);
+            gl::DeleteBuffers(1, &self.buffer_id);
+        }
+    }
+}
+
+// * Draw transforms
+// The model view projection and model matrices of every draw (and the model matrix of the frame
+// before, for the motion vectors of taa.rs), streamed through a ring buffer to
+// shaders that have the DrawTransforms uniform block (simple.vert and pbr.vert). Shaders without it
+// still get plain uniforms. If the ring fills up, the rest of the frame falls back to a single
+// uniform buffer that is written for every draw. While a camera origin is set, the model view
+// projections seen from that camera are made camera relative (see camera.rs).
+
+pub struct TransformStream {
+    pub ring        : RingBuffer,
+    fallback_buffer : u32,
+    camera_origin   : Cell<Option<CameraOrigin>>,
+}
+
+impl TransformStream {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(max_draws_per_frame: usize, buffer_storage: bool) -> TransformStream {
+        let draw_size = std::mem::size_of::<[glm::Mat4; 3]>();
+        let mut fallback_buffer: u32 = 0;
+        gl::GenBuffers(1, &mut fallback_buffer);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, fallback_buffer);
+        gl::BufferData(gl::UNIFORM_BUFFER, draw_size as isize, std::ptr::null(), gl::DYNAMIC_DRAW);
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+
+        let mut alignment: i32 = 1;
+        gl::GetIntegerv(gl::UNIFORM_BUFFER_OFFSET_ALIGNMENT, &mut alignment);
+        let aligned_draw_size = draw_size.div_ceil(alignment as usize) * alignment as usize;
+        TransformStream {
+            ring: RingBuffer::new(gl::UNIFORM_BUFFER, max_draws_per_frame * aligned_draw_size, buffer_storage),
+            fallback_buffer,
+            camera_origin: Cell::new(None),
+        }
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn begin_frame(&mut self) {
+        self.ring.begin_frame();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn end_frame(&mut self) {
+        self.ring.end_frame();
+    }
+
+    /// See RingBuffer::reset_after_panic. Also forgets the camera origin the frame left set.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn reset_after_panic(&mut self) {
+        self.ring.reset_after_panic();
+        self.camera_origin.set(None);
+    }
+
+    // Draws seen with `origin`'s view projection are camera relative from now on, None to stop
+    pub fn set_camera_origin(&self, origin: Option<CameraOrigin>) {
+        self.camera_origin.set(origin);
+    }
+
+    // The model view projection matrix of a draw at `position` (see CameraOrigin). Camera relative if
+    // it is seen from the camera origin, any other view projection (a light's, a mirrored one) is
+    // multiplied as it is.
+    pub fn model_view_projection(&self, view_projection_matrix: &glm::Mat4, model_matrix: &glm::Mat4, position: &Position) -> glm::Mat4 {
+        match self.camera_origin.get() {
+            Some(origin) if origin.view_projection == *view_projection_matrix => origin.model_view_projection(model_matrix, position),
+            _ => view_projection_matrix * model_matrix,
+        }
+    }
+
+    /// Set the transforms for the next draw with `shader`, which has to be active.
+    /// For something that doesn't move, see `set_moving` for what does.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set(&self, shader: &Shader, mvp_matrix: &glm::Mat4, model_matrix: &glm::Mat4) {
+        self.set_moving(shader, mvp_matrix, model_matrix, model_matrix);
+    }
+
+    /// Like `set`, for something that was at `previous_model_matrix` last frame.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_moving(&self, shader: &Shader, mvp_matrix: &glm::Mat4, model_matrix: &glm::Mat4, previous_model_matrix: &glm::Mat4) {
+        if !shader.draw_transforms {
+            shader.set_uniform_mat4("mvp_matrix", mvp_matrix);
+            shader.set_uniform_mat4("model_matrix", model_matrix);
+            if shader.get_uniform_location("previous_model_matrix") != -1 {
+                shader.set_uniform_mat4("previous_model_matrix", previous_model_matrix);
+            }
+            return;
+        }
+        let transforms = [*mvp_matrix, *model_matrix, *previous_model_matrix]; // Column major, same as std140
+        let size = std::mem::size_of_val(&transforms) as isize;
+        match self.ring.push(&transforms) {
+            Some(offset) => {
+                gl::BindBufferRange(gl::UNIFORM_BUFFER, DRAW_TRANSFORMS_BINDING, self.ring.buffer_id, offset as isize, size);
+            }
+            None => {
+                gl::BindBuffer(gl::UNIFORM_BUFFER, self.fallback_buffer);
+                gl::BufferSubData(gl::UNIFORM_BUFFER, 0, size, transforms.as_ptr() as *const std::ffi::c_void);
+                gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+                gl::BindBufferBase(gl::UNIFORM_BUFFER, DRAW_TRANSFORMS_BINDING, self.fallback_buffer);
+            }
+        }
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.ring.delete();
+        gl::DeleteBuffers(1, &self.fallback_buffer);
+    }
+}
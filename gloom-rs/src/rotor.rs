@@ -0,0 +1,133 @@
+use std::f32::consts::TAU;
+
+// * Rotor spin and flight states
+// Every rotor has an angular velocity that picks up towards its target speed while the engine runs,
+// and coasts down once it is off, instead of turning at a fixed rate from the first frame. The engine
+// of a helicopter goes through these states:
+//   Parked    On the ground with the engine off, the rotors coast to a stop
+//   Starting  Engine on, it stays on the ground until the main rotor is up to speed, then climbs back
+//             up to where it came down from
+//   Flying    Along its route (or the figure eight), the only state it moves along it in
+//   Landing   Straight down from where it stopped until it is standing on the ground
+// The helicopters start out on the ground (see Engine::start_on_ground), so they spin up and take
+// off when the program starts.
+//
+// Nothing in here knows where the ground is, the caller measures it (see Engine::update).
+
+// Rotor speeds in radians per second, at a rotor speed scale of 1
+pub const MAIN_ROTOR_SPEED: f32 = 5.0;
+pub const TAIL_ROTOR_SPEED: f32 = 8.0;
+
+const CLIMB_SPEED : f32 = 3.0; // Units per second, up after starting and down when landing
+const SKID_HEIGHT : f32 = 2.0; // How high the root of a helicopter is over the ground it stands on
+const TILT_RATE   : f32 = 0.5; // How quickly it levels out after stopping, and leans in again after starting
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rotor {
+    pub angle        : f32, // Radians, between 0 and a whole turn
+    pub speed        : f32, // Radians per second
+    pub target_speed : f32, // With the engine running, at a rotor speed scale of 1
+    pub acceleration : f32, // Radians per second, per second
+    pub deceleration : f32,
+}
+
+impl Rotor {
+    pub fn new(target_speed: f32, acceleration: f32, deceleration: f32) -> Rotor {
+        Rotor { angle: 0.0, speed: 0.0, target_speed, acceleration, deceleration }
+    }
+
+    // Spin up towards the target speed while `powered`, coast down to a stop otherwise. The speed
+    // scale multiplies the target and how quickly it changes, so spinning up takes as long at any scale.
+    pub fn update(&mut self, delta_time: f32, powered: bool, speed_scale: f32) {
+        let target = if powered { self.target_speed * speed_scale } else { 0.0 };
+        self.speed = if self.speed < target {
+            (self.speed + self.acceleration * speed_scale * delta_time).min(target)
+        } else {
+            (self.speed - self.deceleration * speed_scale * delta_time).max(target)
+        };
+        self.angle = (self.angle + self.speed * delta_time).rem_euclid(TAU);
+    }
+
+    pub fn up_to_speed(&self, speed_scale: f32) -> bool {
+        self.speed >= self.target_speed * speed_scale
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FlightState {
+    Parked,
+    Starting,
+    Flying,
+    Landing,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Engine {
+    pub state         : FlightState,
+    pub main_rotor    : Rotor,
+    pub tail_rotor    : Rotor,
+    pub flight_time   : f32, // Seconds spent Flying, how far along the figure eight it is
+    pub height_offset : f32, // How far under where it stopped flying it is, 0 or less
+    pub tilt          : f32, // How much of the flight's pitch and roll it shows, 0 standing on the ground
+}
+
+impl Engine {
+    pub fn new() -> Engine {
+        Engine {
+            state         : FlightState::Starting,
+            main_rotor    : Rotor::new(MAIN_ROTOR_SPEED, 1.0, 0.4),
+            tail_rotor    : Rotor::new(TAIL_ROTOR_SPEED, 1.6, 0.64),
+            flight_time   : 0.0,
+            height_offset : 0.0,
+            tilt          : 1.0,
+        }
+    }
+
+    // Put it on the ground `height_above_ground` under where it is, to take off from there
+    pub fn start_on_ground(&mut self, height_above_ground: Option<f32>) {
+        self.state = FlightState::Starting;
+        self.height_offset = -(height_above_ground.unwrap_or(SKID_HEIGHT) - SKID_HEIGHT).max(0.0);
+        self.tilt = 0.0;
+    }
+
+    // Land if it is in the air or about to be, start if it is on the ground or about to be
+    pub fn toggle(&mut self) {
+        self.state = match self.state {
+            FlightState::Flying | FlightState::Starting => FlightState::Landing,
+            FlightState::Parked | FlightState::Landing => FlightState::Starting,
+        };
+    }
+
+    /// Spin the rotors and move through the states. `height_above_ground` is how far the helicopter is
+    /// over the ground right now, None if there is no ground under it, where it can't land and goes
+    /// back up. Gives the state it went into, if it changed this frame.
+    pub fn update(&mut self, delta_time: f32, speed_scale: f32, height_above_ground: Option<f32>) -> Option<FlightState> {
+        let state = self.state;
+        let powered = self.state != FlightState::Parked;
+        self.main_rotor.update(delta_time, powered, speed_scale);
+        self.tail_rotor.update(delta_time, powered, speed_scale);
+
+        match self.state {
+            FlightState::Parked => {}
+            FlightState::Starting => {
+                if self.main_rotor.up_to_speed(speed_scale) {
+                    self.height_offset = (self.height_offset + CLIMB_SPEED * delta_time).min(0.0);
+                    if self.height_offset == 0.0 {
+                        self.state = FlightState::Flying;
+                    }
+                }
+            }
+            FlightState::Flying => self.flight_time += delta_time,
+            FlightState::Landing => match height_above_ground {
+                Some(height) if height <= SKID_HEIGHT => self.state = FlightState::Parked,
+                Some(_) => self.height_offset -= CLIMB_SPEED * delta_time,
+                None => self.state = FlightState::Starting,
+            },
+        }
+
+        let tilt = if self.state == FlightState::Flying { 1.0 } else { 0.0 };
+        self.tilt += (tilt - self.tilt).clamp(-TILT_RATE * delta_time, TILT_RATE * delta_time);
+
+        (self.state != state).then_some(self.state)
+    }
+}
@@ -0,0 +1,91 @@
+extern crate nalgebra_glm as glm;
+
+use crate::mesh::Mesh;
+use crate::scene_graph::SceneNode;
+
+// * Rotor blur
+// Rotor blades spinning faster than the frame rate can keep up with jump a big part of a turn between
+// frames, and look like they flicker or turn slowly backwards. A real camera (or eye) sees a blurred
+// disc instead, so past a certain speed the blades are hidden and a see-through disc is shown in their
+// place. The disc is generated from the rotor mesh: as wide as the blades reach, flat across the axis
+// they spin about, a little more solid at the hub where the blades are always close.
+//
+// The disc is see-through through its vertex alpha, and drawn with the rest of the helicopter. Things
+// drawn after it that are behind it don't show through, which is hard to notice on a moving helicopter.
+
+// Spin speed in radians per second past which the blades are swapped for the disc. About two turns a
+// second, where the blades start to strobe at 60 frames per second.
+pub const BLUR_SPEED: f32 = 12.0;
+
+// Distance from the hub (as a fraction of the radius) and alpha of the rings the disc is made of
+const RINGS: [(f32, f32); 5] = [(0.0, 0.6), (0.12, 0.5), (0.5, 0.3), (0.9, 0.25), (1.0, 0.08)];
+
+/// A disc covering everything the rotor sweeps when it spins about `axis` (0, 1 or 2 for X, Y or Z)
+/// through the middle of its bounds. Both sides face outwards, so it can be seen from above and below.
+pub fn rotor_disc(rotor: &Mesh, axis: usize, segments: usize) -> Mesh {
+    let center = rotor.center();
+    let axis_direction = glm::Vec3::ith(axis, 1.0);
+    let radius = rotor.vertices.chunks(3)
+        .map(|vertex| {
+            let offset = glm::vec3(vertex[0], vertex[1], vertex[2]) - center;
+            glm::length(&(offset - axis_direction * glm::dot(&offset, &axis_direction)))
+        })
+        .fold(0.0, f32::max);
+
+    // The disc takes the average color of the blades
+    let vertex_count = (rotor.colors.len() / 4).max(1) as f32;
+    let color = rotor.colors.chunks(4).fold([0.0; 3], |sum, c| [sum[0] + c[0], sum[1] + c[1], sum[2] + c[2]]).map(|channel| channel / vertex_count);
+
+    // Two directions across the axis to go around it with
+    let (u, v) = (glm::Vec3::ith((axis + 1) % 3, 1.0), glm::Vec3::ith((axis + 2) % 3, 1.0));
+    let segments = segments.max(3);
+
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut colors = vec![];
+    let mut indices = vec![];
+    for (side, normal) in [axis_direction, -axis_direction].iter().enumerate() {
+        let first = (vertices.len() / 3) as u32;
+        for &(distance, alpha) in RINGS.iter() {
+            for segment in 0..segments {
+                let angle = segment as f32 / segments as f32 * 2.0 * std::f32::consts::PI;
+                let position = center + (u * angle.cos() + v * angle.sin()) * distance * radius;
+                vertices.extend_from_slice(&[position.x, position.y, position.z]);
+                normals.extend_from_slice(&[normal.x, normal.y, normal.z]);
+                colors.extend_from_slice(&[color[0], color[1], color[2], alpha]);
+            }
+        }
+
+        // A quad between every two neighbouring rings, wound the other way round for the back side
+        for ring in 0..RINGS.len() as u32 - 1 {
+            for segment in 0..segments as u32 {
+                let next = (segment + 1) % segments as u32;
+                let inner = first + ring * segments as u32;
+                let outer = inner + segments as u32;
+                let (a, b, c, d) = (inner + segment, inner + next, outer + segment, outer + next);
+                if side == 0 {
+                    indices.extend_from_slice(&[a, c, b, b, c, d]);
+                } else {
+                    indices.extend_from_slice(&[a, b, c, b, d, c]);
+                }
+            }
+        }
+    }
+    Mesh::from_arrays(vertices, normals, colors, indices)
+}
+
+/// Show either the blades or the disc, depending on how fast the rotor spins. The disc follows the
+/// blades around, so it doesn't matter which one is looked at. Both are children of `parent`, at the
+/// indices `blades` and `disc`.
+pub fn blur_rotor(parent: &mut SceneNode, blades: usize, disc: usize, angular_speed: f32) {
+    let blurred = angular_speed.abs() > BLUR_SPEED;
+    let blades = parent.get_child(blades);
+    blades.visible = !blurred;
+    let (position, rotation, reference_point) = (blades.position, blades.rotation, blades.reference_point);
+
+    let disc = parent.get_child(disc);
+    disc.visible = blurred;
+    disc.position = position;
+    disc.rotation = rotation;
+    disc.reference_point = reference_point;
+}
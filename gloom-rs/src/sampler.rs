@@ -0,0 +1,182 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+// * Samplers
+// How a texture is filtered and wrapped is kept in GL sampler objects instead of the textures
+// themselves, so the same texture can be sampled differently by different materials, and all of
+// them can be changed at once. A material says what it wants in a SamplerDesc (see Material::sampler),
+// the sampler for it is made the first time it is bound and kept after that.
+//
+// On top of that there is one quality setting for the whole renderer, which caps the anisotropy and
+// trilinear filtering every material asks for (or takes it down to plain bilinear filtering at Low).
+// Anisotropic filtering is what keeps textures sharp and still at grazing angles, like the terrain far
+// off towards the horizon, where the mipmaps alone blur them or, with a negative LOD bias, shimmer.
+//
+// Like the render state in gfx.rs the samplers live with the GL context, on the main thread. A bound
+// sampler overrides the filtering of whatever texture is on its unit, so whoever binds one unbinds it
+// again when done (see Material::unbind_samplers), for the passes that set up their textures themselves.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,   // Blocky, for pixel art and lookup tables
+    Bilinear,  // Between the pixels, the nearest mipmap level
+    Trilinear, // And between two mipmap levels
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Wrap {
+    Repeat,
+    MirroredRepeat,
+    ClampToEdge,
+}
+
+impl Wrap {
+    fn gl_wrap(self) -> gl::types::GLenum {
+        match self {
+            Wrap::Repeat         => gl::REPEAT,
+            Wrap::MirroredRepeat => gl::MIRRORED_REPEAT,
+            Wrap::ClampToEdge    => gl::CLAMP_TO_EDGE,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SamplerDesc {
+    pub filter     : Filter,
+    pub wrap       : Wrap,
+    pub anisotropy : f32, // Samples along the direction a texture is stretched in, 1 is off
+    pub lod_bias   : f32, // Added to the mipmap level, below 0 is sharper and shimmers sooner
+}
+
+impl Default for SamplerDesc {
+    fn default() -> SamplerDesc {
+        SamplerDesc { filter: Filter::Trilinear, wrap: Wrap::Repeat, anisotropy: 16.0, lod_bias: 0.0 }
+    }
+}
+
+impl SamplerDesc {
+    /// Builder style, like Path::with_waypoint.
+    pub fn with_filter(mut self, filter: Filter) -> SamplerDesc {
+        self.filter = filter;
+        self
+    }
+
+    pub fn with_wrap(mut self, wrap: Wrap) -> SamplerDesc {
+        self.wrap = wrap;
+        self
+    }
+
+    // What is actually used at `quality`, with the driver's highest anisotropy
+    fn at_quality(&self, quality: TextureQuality, max_anisotropy: f32) -> SamplerDesc {
+        let filter = match (self.filter, quality) {
+            (Filter::Trilinear, TextureQuality::Low) => Filter::Bilinear,
+            (filter, _) => filter,
+        };
+        let anisotropy = self.anisotropy.min(quality.max_anisotropy()).min(max_anisotropy).max(1.0);
+        SamplerDesc { filter, anisotropy, ..*self }
+    }
+
+    // Floats can't be hashed, their bits can
+    fn key(&self) -> (Filter, Wrap, u32, u32) {
+        (self.filter, self.wrap, self.anisotropy.to_bits(), self.lod_bias.to_bits())
+    }
+}
+
+// The renderer wide setting, cycled with a key
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextureQuality {
+    Low,    // Bilinear, no anisotropy
+    Medium, // Trilinear, no anisotropy
+    High,   // Trilinear, up to 4x anisotropy
+    Ultra,  // Trilinear, up to 16x anisotropy
+}
+
+impl TextureQuality {
+    pub fn next(self) -> TextureQuality {
+        match self {
+            TextureQuality::Low    => TextureQuality::Medium,
+            TextureQuality::Medium => TextureQuality::High,
+            TextureQuality::High   => TextureQuality::Ultra,
+            TextureQuality::Ultra  => TextureQuality::Low,
+        }
+    }
+
+    fn max_anisotropy(self) -> f32 {
+        match self {
+            TextureQuality::Low | TextureQuality::Medium => 1.0,
+            TextureQuality::High  => 4.0,
+            TextureQuality::Ultra => 16.0,
+        }
+    }
+}
+
+struct SamplerCache {
+    quality        : TextureQuality,
+    max_anisotropy : f32, // Of the driver, 1 without anisotropic filtering
+    samplers       : HashMap<(Filter, Wrap, u32, u32), u32>,
+}
+
+thread_local! {
+    static SAMPLERS: RefCell<SamplerCache> = RefCell::new(SamplerCache {
+        quality: TextureQuality::Ultra,
+        max_anisotropy: 1.0,
+        samplers: HashMap::new(),
+    });
+}
+
+/// The quality every sampler is made at from now on, and the highest anisotropy the driver has
+/// (GlCaps::max_anisotropy). The samplers made so far are deleted and made again when they are next used.
+///
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn set_quality(quality: TextureQuality, max_anisotropy: f32) {
+    delete_all();
+    SAMPLERS.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        cache.quality = quality;
+        cache.max_anisotropy = max_anisotropy;
+    });
+}
+
+/// Bind the sampler for `desc` to a texture unit, or none with `None`, so the texture's own
+/// filtering is used again.
+///
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn bind(unit: u32, desc: Option<&SamplerDesc>) {
+    let sampler_id = desc.map_or(0, |desc| SAMPLERS.with(|cache| {
+        let mut cache = cache.borrow_mut();
+        let desc = desc.at_quality(cache.quality, cache.max_anisotropy);
+        *cache.samplers.entry(desc.key()).or_insert_with(|| create_sampler(&desc))
+    }));
+    gl::BindSampler(unit, sampler_id);
+}
+
+/// # Safety
+/// Needs a current OpenGL context.
+pub unsafe fn delete_all() {
+    SAMPLERS.with(|cache| {
+        for (_, sampler_id) in cache.borrow_mut().samplers.drain() {
+            gl::DeleteSamplers(1, &sampler_id);
+        }
+    });
+}
+
+unsafe fn create_sampler(desc: &SamplerDesc) -> u32 {
+    let mut sampler_id: u32 = 0;
+    gl::GenSamplers(1, &mut sampler_id);
+    let (min_filter, mag_filter) = match desc.filter {
+        Filter::Nearest   => (gl::NEAREST_MIPMAP_NEAREST, gl::NEAREST),
+        Filter::Bilinear  => (gl::LINEAR_MIPMAP_NEAREST, gl::LINEAR),
+        Filter::Trilinear => (gl::LINEAR_MIPMAP_LINEAR, gl::LINEAR),
+    };
+    gl::SamplerParameteri(sampler_id, gl::TEXTURE_MIN_FILTER, min_filter as i32);
+    gl::SamplerParameteri(sampler_id, gl::TEXTURE_MAG_FILTER, mag_filter as i32);
+    gl::SamplerParameteri(sampler_id, gl::TEXTURE_WRAP_S, desc.wrap.gl_wrap() as i32);
+    gl::SamplerParameteri(sampler_id, gl::TEXTURE_WRAP_T, desc.wrap.gl_wrap() as i32);
+    gl::SamplerParameterf(sampler_id, gl::TEXTURE_LOD_BIAS, desc.lod_bias);
+    if desc.anisotropy > 1.0 {
+        gl::SamplerParameterf(sampler_id, crate::gl_caps::TEXTURE_MAX_ANISOTROPY, desc.anisotropy);
+    }
+    sampler_id
+}
@@ -0,0 +1,257 @@
+extern crate nalgebra_glm as glm;
+
+use std::ops::Range;
+
+use rand::prelude::*;
+use rand::rngs::StdRng;
+
+use crate::mesh::Mesh;
+
+// * Surface scatter
+// Rocks and boulders spread over the terrain by rules instead of one at a time. A rule says how many
+// copies of a mesh go on every 100 square units of ground, how big they are, how steep the ground may
+// be under them (they would have rolled off anything steeper), and optionally a density map over the
+// terrain that thins them out, or gathers them into fields. Every triangle of the terrain gets its
+// share by area, so a big triangle gets as many as the small ones covering the same ground. The seed
+// puts everything in the same place every run.
+//
+// The terrain is cut into CHUNKS_PER_SIDE x CHUNKS_PER_SIDE chunks across, and every instance is kept
+// in its chunk in 8 bytes: where it is in the chunk's box in 16 bits per axis, and its turn and size
+// in 8 bits each. The instance batch gets the full matrices in chunk order, and the range and bounding
+// sphere of every chunk, so a chunk outside a view is skipped without looking at any of its instances
+// (see InstanceBatch::with_chunks).
+
+const CHUNKS_PER_SIDE: usize = 8;
+
+// What InstanceBatch::new and InstanceBatch::with_chunks take
+pub type Instances = Vec<(usize, glm::Mat4, f32)>;
+pub type ChunkRanges = Vec<(Range<usize>, [f32; 4])>;
+
+// How much of a rule's density goes on each part of the terrain, between 0 and 1, read from an image
+// or made up from noise. Covers the terrain from one side to the other.
+#[derive(Clone, Debug)]
+pub struct DensityMap {
+    size   : usize,
+    values : Vec<f32>, // Row by row, size x size
+}
+
+impl DensityMap {
+    /// From the brightness of an image, white is full density. Its top is the far (-Z) side of the terrain.
+    pub fn load(path: &str) -> Result<DensityMap, String> {
+        let image = image::open(path).map_err(|error| format!("{}: {}", path, error))?.into_luma8();
+        let size = image.width().min(image.height()) as usize;
+        let values = (0..size * size).map(|i| image.get_pixel((i % size) as u32, (i / size) as u32).0[0] as f32 / 255.0).collect();
+        Ok(DensityMap { size, values })
+    }
+
+    /// Patches `cells` across, some full and some empty with smooth edges in between.
+    pub fn noise(size: usize, cells: usize, seed: u64) -> DensityMap {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let lattice: Vec<f32> = (0..(cells + 1) * (cells + 1)).map(|_| rng.gen()).collect();
+        let values = (0..size * size).map(|i| {
+            let (x, y) = ((i % size) as f32 / size as f32 * cells as f32, (i / size) as f32 / size as f32 * cells as f32);
+            let (cell_x, cell_y) = (x.floor() as usize, y.floor() as usize);
+            let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+            let (tx, ty) = (smooth(x.fract()), smooth(y.fract()));
+            let corner = |dx: usize, dy: usize| lattice[(cell_y + dy) * (cells + 1) + cell_x + dx];
+            let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * tx;
+            let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * tx;
+            // Sharpened, so there are fields and bare ground rather than everything half full
+            ((top + (bottom - top) * ty) * 2.0 - 0.6).clamp(0.0, 1.0)
+        }).collect();
+        DensityMap { size, values }
+    }
+
+    // At (u, v) from 0 to 1 across the terrain, the nearest texel
+    pub fn sample(&self, u: f32, v: f32) -> f32 {
+        let x = ((u * self.size as f32) as usize).min(self.size - 1);
+        let y = ((v * self.size as f32) as usize).min(self.size - 1);
+        self.values[y * self.size + x]
+    }
+}
+
+pub struct ScatterRule {
+    pub mesh        : usize,      // Which mesh of the instance batch
+    pub density     : f32,        // Instances per 100 square units of ground, where the density map is full
+    pub sizes       : (f32, f32), // Smallest and biggest scale
+    pub flatten     : f32,        // Height scale next to the width, below 1 for flat rocks
+    pub max_slope   : f32,        // Radians from level
+    pub sink        : f32,        // How far into the ground, as a fraction of the height
+    pub density_map : Option<DensityMap>,
+    mesh_center     : glm::Vec3,  // Put at the instance's origin
+    mesh_radius     : f32,        // Around the center, at a scale of 1
+    mesh_height     : f32,
+}
+
+impl ScatterRule {
+    /// `mesh` is the `mesh_index`th mesh of the batch the instances go into.
+    pub fn new(mesh_index: usize, mesh: &Mesh, density: f32) -> ScatterRule {
+        let (min, max) = mesh.bounds();
+        ScatterRule {
+            mesh: mesh_index,
+            density,
+            sizes: (1.0, 1.0),
+            flatten: 1.0,
+            max_slope: std::f32::consts::FRAC_PI_2,
+            sink: 0.0,
+            density_map: None,
+            mesh_center: (min + max) * 0.5,
+            mesh_radius: glm::distance(&min, &max) * 0.5,
+            mesh_height: max.y - min.y,
+        }
+    }
+
+    /// Builder style, like Path::with_waypoint.
+    pub fn with_sizes(mut self, smallest: f32, biggest: f32, flatten: f32) -> ScatterRule {
+        self.sizes = (smallest, biggest);
+        self.flatten = flatten;
+        self
+    }
+
+    pub fn with_max_slope(mut self, max_slope: f32) -> ScatterRule {
+        self.max_slope = max_slope;
+        self
+    }
+
+    pub fn with_sink(mut self, sink: f32) -> ScatterRule {
+        self.sink = sink;
+        self
+    }
+
+    pub fn with_density_map(mut self, density_map: DensityMap) -> ScatterRule {
+        self.density_map = Some(density_map);
+        self
+    }
+
+    fn size(&self, packed: u8) -> f32 {
+        self.sizes.0 + (self.sizes.1 - self.sizes.0) * packed as f32 / u8::MAX as f32
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct PackedInstance {
+    position : [u16; 3], // In the chunk's box, from its lowest corner at 0 to its highest at u16::MAX
+    yaw      : u8,       // From 0 to a whole turn
+    size     : u8,       // From the rule's smallest size to its biggest
+}
+
+struct Chunk {
+    bounds_min  : glm::Vec3,
+    bounds_size : glm::Vec3,
+    instances   : Vec<Vec<PackedInstance>>, // One list per rule
+}
+
+impl Chunk {
+    fn pack(&self, position: glm::Vec3) -> [u16; 3] {
+        let t = glm::clamp(&(position - self.bounds_min).component_div(&self.bounds_size), 0.0, 1.0);
+        [0, 1, 2].map(|axis| (t[axis] * u16::MAX as f32).round() as u16)
+    }
+
+    fn unpack(&self, position: [u16; 3]) -> glm::Vec3 {
+        self.bounds_min + glm::vec3(position[0] as f32, position[1] as f32, position[2] as f32).component_mul(&self.bounds_size) / u16::MAX as f32
+    }
+}
+
+pub struct ScatterField {
+    rules  : Vec<ScatterRule>,
+    chunks : Vec<Chunk>,
+}
+
+impl ScatterField {
+    pub fn new(terrain: &Mesh, rules: Vec<ScatterRule>, seed: u64) -> ScatterField {
+        let (terrain_min, terrain_max) = terrain.bounds();
+        let terrain_size = terrain_max - terrain_min;
+        let chunk_size = glm::vec3(terrain_size.x / CHUNKS_PER_SIDE as f32, terrain_size.y.max(1e-3), terrain_size.z / CHUNKS_PER_SIDE as f32);
+        let mut chunks: Vec<Chunk> = (0..CHUNKS_PER_SIDE * CHUNKS_PER_SIDE).map(|i| Chunk {
+            bounds_min: terrain_min + glm::vec3((i % CHUNKS_PER_SIDE) as f32 * chunk_size.x, 0.0, (i / CHUNKS_PER_SIDE) as f32 * chunk_size.z),
+            bounds_size: chunk_size,
+            instances: rules.iter().map(|_| vec![]).collect(),
+        }).collect();
+
+        let mut rng = StdRng::seed_from_u64(seed);
+        let position = |index: u32| glm::make_vec3(&terrain.vertices[3 * index as usize..3 * index as usize + 3]);
+        for corners in terrain.indices.chunks_exact(3) {
+            let [a, b, c] = [0, 1, 2].map(|i| position(corners[i]));
+            let cross = glm::cross(&(b - a), &(c - a));
+            let area = glm::length(&cross) * 0.5;
+            if area <= 0.0 {
+                continue;
+            }
+            let slope = (cross.y.abs() / (2.0 * area)).clamp(-1.0, 1.0).acos();
+
+            for (rule_index, rule) in rules.iter().enumerate() {
+                if slope > rule.max_slope {
+                    continue;
+                }
+                // Whole instances, and one more as often as the fraction left over
+                let expected = area * rule.density / 100.0;
+                let count = expected.floor() as usize + (rng.gen::<f32>() < expected.fract()) as usize;
+                for _ in 0..count {
+                    // Uniformly over the triangle
+                    let (mut s, mut t) = (rng.gen::<f32>(), rng.gen::<f32>());
+                    if s + t > 1.0 {
+                        (s, t) = (1.0 - s, 1.0 - t);
+                    }
+                    let point = a + (b - a) * s + (c - a) * t;
+                    let across = (point - terrain_min).component_div(&terrain_size);
+                    if let Some(density_map) = &rule.density_map {
+                        if rng.gen::<f32>() >= density_map.sample(across.x, across.z) {
+                            continue;
+                        }
+                    }
+                    let chunk_x = ((across.x * CHUNKS_PER_SIDE as f32) as usize).min(CHUNKS_PER_SIDE - 1);
+                    let chunk_z = ((across.z * CHUNKS_PER_SIDE as f32) as usize).min(CHUNKS_PER_SIDE - 1);
+                    let chunk = &mut chunks[chunk_z * CHUNKS_PER_SIDE + chunk_x];
+                    let instance = PackedInstance { position: chunk.pack(point), yaw: rng.gen(), size: rng.gen() };
+                    chunk.instances[rule_index].push(instance);
+                }
+            }
+        }
+
+        ScatterField { rules, chunks }
+    }
+
+    pub fn instance_count(&self) -> usize {
+        self.chunks.iter().flat_map(|chunk| chunk.instances.iter()).map(|instances| instances.len()).sum()
+    }
+
+    // Bytes the placements take up, before they are unpacked for the GPU
+    pub fn packed_size(&self) -> usize {
+        self.instance_count() * std::mem::size_of::<PackedInstance>()
+    }
+
+    /// Every instance for an InstanceBatch, (mesh, model matrix, bounding sphere radius) in chunk order,
+    /// and the range and bounding sphere (center, radius) of every chunk that has any.
+    pub fn instances(&self) -> (Instances, ChunkRanges) {
+        let mut instances: Instances = vec![];
+        let mut chunk_ranges: ChunkRanges = vec![];
+        for chunk in &self.chunks {
+            let first = instances.len();
+            let (mut bounds_min, mut bounds_max) = (glm::vec3(f32::MAX, f32::MAX, f32::MAX), glm::vec3(f32::MIN, f32::MIN, f32::MIN));
+            for (rule, packed_instances) in self.rules.iter().zip(&chunk.instances) {
+                for packed in packed_instances {
+                    let size = rule.size(packed.size);
+                    let height = size * rule.flatten;
+                    // Standing on the ground, then sunk into it
+                    let raise = rule.mesh_height * height * (0.5 - rule.sink);
+                    let position = chunk.unpack(packed.position) + glm::vec3(0.0, raise, 0.0);
+                    let yaw = packed.yaw as f32 / 256.0 * std::f32::consts::TAU;
+                    let model = glm::translation(&position)
+                        * glm::rotation(yaw, &glm::vec3(0.0, 1.0, 0.0))
+                        * glm::scaling(&glm::vec3(size, height, size))
+                        * glm::translation(&-rule.mesh_center);
+                    let radius = rule.mesh_radius * size.max(height);
+                    bounds_min = glm::min2(&bounds_min, &position.add_scalar(-radius));
+                    bounds_max = glm::max2(&bounds_max, &position.add_scalar(radius));
+                    instances.push((rule.mesh, model, radius));
+                }
+            }
+            if instances.len() > first {
+                let center = (bounds_min + bounds_max) * 0.5;
+                let radius = glm::distance(&bounds_min, &bounds_max) * 0.5;
+                chunk_ranges.push((first..instances.len(), [center.x, center.y, center.z, radius]));
+            }
+        }
+        (instances, chunk_ranges)
+    }
+}
@@ -1,7 +1,14 @@
 extern crate nalgebra_glm as glm;
 
+use std::cell::Cell;
 use std::mem::ManuallyDrop;
 use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::lights::Light;
+use crate::material::Material;
+use crate::mesh::Mesh;
 
 // Used to create an unholy abomination upon which you should not cast your gaze. This ended up
 // being a necessity due to wanting to keep the code written by students as "straight forward" as
@@ -16,14 +23,56 @@ use std::pin::Pin;
 // having what I arbitrarily decided to be the required level of "simplicity of use".
 pub type Node = ManuallyDrop<Pin<Box<SceneNode>>>;
 
+// Every node gets its own ID, used to refer to a node without holding on to a pointer (selection, picking)
+pub type NodeId = u32;
+static NEXT_NODE_ID: AtomicU32 = AtomicU32::new(1); // 0 means "no node"
+
+fn next_node_id() -> NodeId {
+    NEXT_NODE_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+// * Scene coordinates
+// How precise the positions of the nodes and the cameras are. f32, unless the `f64-scene` feature is
+// on: then they are f64, and so are the world matrices the scene graph adds up for the snapshots.
+// They only become f32 once they are relative to the camera (see CameraOrigin), so a scene spread out
+// over planetary distances doesn't wobble. Rotations, scales and the pivots stay f32, they don't
+// grow with the distance. Everything else (sound, culling, the shaders' world positions) gets f32.
+#[cfg(not(feature = "f64-scene"))]
+pub type Real = f32;
+#[cfg(feature = "f64-scene")]
+pub type Real = f64;
+pub type Position = glm::TVec3<Real>;
+pub type WorldMatrix = glm::TMat4<Real>;
+
+pub fn position_from_f32(position: &glm::Vec3) -> Position {
+    glm::convert(*position)
+}
+
+pub fn position_to_f32(position: &Position) -> glm::Vec3 {
+    glm::convert(*position)
+}
+
+pub fn world_matrix_from_f32(matrix: &glm::Mat4) -> WorldMatrix {
+    glm::convert(*matrix)
+}
+
 pub struct SceneNode {
-    pub position        : glm::Vec3,   // Where I should be in relation to my parent
+    pub id              : NodeId,      // Who I am
+    pub position        : Position,    // Where I should be in relation to my parent
     pub rotation        : glm::Vec3,   // How I should be rotated, around the X, the Y and the Z axes
     pub scale           : glm::Vec3,   // How I should be scaled
     pub reference_point : glm::Vec3,   // The point I shall rotate and scale about
 
     pub vao_id      : u32,             // What I should draw
     pub index_count : i32,             // How much of it there is to draw
+    pub material    : Option<Rc<Material>>, // What it should look like with the PBR shader, shared with every node looking the same
+    pub light       : Option<Light>,        // Light I give off, moving along with me
+    pub visible     : bool,                 // Whether I (and those I command) get drawn
+
+    // Where I was in the world in the last two snapshots, for the motion vectors (see motion.rs).
+    // Kept up to date by FrameSnapshot::capture, None until I have been in one.
+    pub world_matrix          : Cell<Option<glm::Mat4>>,
+    pub previous_world_matrix : Cell<Option<glm::Mat4>>,
 
     pub children: Vec<*mut SceneNode>, // Those I command
 }
@@ -32,24 +81,36 @@ impl SceneNode {
 
     pub fn new() -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
-            position        : glm::zero(),
-            rotation        : glm::zero(),
-            scale           : glm::vec3(1.0, 1.0, 1.0),
-            reference_point : glm::zero(),
-            vao_id          : 0,
-            index_count     : -1,
-            children        : vec![],
+            id                    : next_node_id(),
+            position              : glm::zero(),
+            rotation              : glm::zero(),
+            scale                 : glm::vec3(1.0, 1.0, 1.0),
+            reference_point       : glm::zero(),
+            vao_id                : 0,
+            index_count           : -1,
+            material              : None,
+            light                 : None,
+            visible               : true,
+            world_matrix          : Cell::new(None),
+            previous_world_matrix : Cell::new(None),
+            children              : vec![],
         })))
     }
 
     pub fn from_vao(vao_id: u32, index_count: i32) -> Node {
         ManuallyDrop::new(Pin::new(Box::new(SceneNode {
+            id              : next_node_id(),
             position        : glm::zero(),
             rotation        : glm::zero(),
             scale           : glm::vec3(1.0, 1.0, 1.0),
             reference_point : glm::zero(),
             vao_id,
             index_count,
+            material: None,
+            light: None,
+            visible: true,
+            world_matrix: Cell::new(None),
+            previous_world_matrix: Cell::new(None),
             children: vec![],
         })))
     }
@@ -58,6 +119,73 @@ impl SceneNode {
         self.children.push(child as *const SceneNode as *mut SceneNode)
     }
 
+    // My transformation relative to my parent: scale, then rotate about the reference point, then move
+    pub fn local_transform(&self) -> glm::Mat4 {
+        glm::convert(self.precise_local_transform())
+    }
+
+    // The same, with my position as precise as the scene has it
+    pub fn precise_local_transform(&self) -> WorldMatrix {
+        let turn: glm::Mat4 = glm::translation(&self.reference_point)
+            * self.rotation_matrix()
+            * glm::translation(&(-self.reference_point))
+            * glm::scaling(&self.scale);
+        glm::translation(&self.position) * world_matrix_from_f32(&turn)
+    }
+
+    // My rotation on its own, about the Z axis first, then Y, then X
+    pub fn rotation_matrix(&self) -> glm::Mat4 {
+        let rotation_matrix_z = glm::rotation(self.rotation.z, &glm::vec3(0.0, 0.0, 1.0));
+        let rotation_matrix_y = glm::rotation(self.rotation.y, &glm::vec3(0.0, 1.0, 0.0));
+        let rotation_matrix_x = glm::rotation(self.rotation.x, &glm::vec3(1.0, 0.0, 0.0));
+        rotation_matrix_x * rotation_matrix_y * rotation_matrix_z
+    }
+
+    // Rotate and scale about the middle of the mesh I draw, instead of a point found by trial and error.
+    // Only the part of the point along the rotation axis can be off, and moving along the axis doesn't matter.
+    pub fn set_pivot_from_mesh(&mut self, mesh: &Mesh) {
+        self.reference_point = mesh.center();
+    }
+
+    // Where my reference point ends up in my parent's space, the point I turn about
+    pub fn pivot(&self) -> glm::Vec3 {
+        position_to_f32(&self.position) + self.reference_point
+    }
+
+    // Find a node by ID among this one and everything below it. Also gives the transformation of
+    // the node's parent, with `transformation_so_far` being the transformation of this node's parent.
+    // As precise as the scene is, like the snapshots, so what is drawn from it lines up with them.
+    pub fn find(&self, id: NodeId, transformation_so_far: &WorldMatrix) -> Option<(&SceneNode, WorldMatrix)> {
+        if self.id == id {
+            return Some((self, *transformation_so_far));
+        }
+        let transformation_matrix = transformation_so_far * self.precise_local_transform();
+        self.children.iter()
+            .filter_map(|&child| unsafe { child.as_ref() })
+            .find_map(|child| child.find(id, &transformation_matrix))
+    }
+
+    // The same, to change the node. Lets the update get at a node by its ID instead of holding on to a pointer.
+    pub fn find_mut(&mut self, id: NodeId) -> Option<&mut SceneNode> {
+        if self.id == id {
+            return Some(self);
+        }
+        self.children.iter()
+            .filter_map(|&child| unsafe { child.as_mut() })
+            .find_map(|child| child.find_mut(id))
+    }
+
+    // My ID and the IDs of everything below me
+    pub fn subtree_ids(&self) -> Vec<NodeId> {
+        let mut ids = vec![self.id];
+        for &child in &self.children {
+            if let Some(child) = unsafe { child.as_ref() } {
+                ids.extend(child.subtree_ids());
+            }
+        }
+        ids
+    }
+
     #[allow(dead_code)]
     pub fn get_child(& mut self, index: usize) -> & mut SceneNode {
         unsafe {
@@ -65,6 +193,18 @@ impl SceneNode {
         }
     }
 
+    // Update the index count of every node (this one and below) drawing the given VAO,
+    // used when a VAO gets new data with a different number of indices
+    #[allow(dead_code)]
+    pub fn update_index_count(&mut self, vao_id: u32, index_count: i32) {
+        if self.vao_id == vao_id {
+            self.index_count = index_count;
+        }
+        for &child in &self.children {
+            unsafe { (*child).update_index_count(vao_id, index_count); }
+        }
+    }
+
     #[allow(dead_code)]
     pub fn n_children(&self) -> usize {
         self.children.len()
@@ -74,6 +214,7 @@ impl SceneNode {
     pub fn print(&self) {
         println!(
 "SceneNode {{
+    ID:        {}
     VAO:       {}
     Indices:   {}
     Children:  {}
@@ -81,6 +222,7 @@ impl SceneNode {
     Rotation:  [{:.2}, {:.2}, {:.2}]
     Reference: [{:.2}, {:.2}, {:.2}]
 }}",
+            self.id,
             self.vao_id,
             self.index_count,
             self.children.len(),
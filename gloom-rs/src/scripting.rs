@@ -0,0 +1,241 @@
+extern crate nalgebra_glm as glm;
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use rhai::{Array, CallFnOptions, Dynamic, Engine, Map, Scope, AST};
+
+use crate::events::InputState;
+use crate::scene_graph::{self, Node, NodeId, SceneNode};
+
+// * Scripted behaviors
+// Rhai scripts that move nodes around, so a behavior or a demo sequence can be tweaked and hot
+// reloaded without recompiling. Every script can have these functions, both optional:
+//
+//     fn init()   { this.speed = 2.0; }              // After loading (and reloading) the script
+//     fn update() { let t = time() * this.speed; }   // Once per frame, before the scene is drawn
+//
+// `this` is an object map that belongs to the script and lives until the script is reloaded.
+// Scripts get at nodes through IDs: `node("beacon")` gives the ID of a node main.rs exposed by name.
+//
+//     time(), delta_time()                       Seconds since the start, and since the last frame
+//     is_pressed("J"), was_just_pressed("J")     Keys by their glutin name (Space, LShift, Key1...)
+//     node(name)                                 ID of a named node, -1 if there is none
+//     get_position(id), set_position(id, x, y, z)   The same goes for rotation and scale. The getters
+//     get_rotation(id), set_rotation(id, x, y, z)   give [x, y, z], relative to the parent like
+//     get_scale(id), set_scale(id, x, y, z)         everything else in the scene graph
+//     set_visible(id, visible)
+//     spawn_copy(template_id, parent_id)         Copy of the template and everything under it, gives its ID
+//
+// A script runs on the render thread, so every call it gets MAX_OPERATIONS steps of Rhai before it is
+// stopped with an error, an endless loop can't freeze the window.
+
+const MAX_OPERATIONS: u64 = 1_000_000;
+
+// What the script functions can see and change, shared by all of them
+struct ScriptWorld {
+    root              : *mut SceneNode,
+    named_nodes       : HashMap<String, NodeId>,
+    time              : f64,
+    delta_time        : f64,
+    pressed_keys      : Vec<String>,
+    just_pressed_keys : Vec<String>,
+}
+
+impl ScriptWorld {
+    // The nodes aren't owned by the world, they're behind the same raw pointers as everywhere else
+    #[allow(clippy::mut_from_ref)]
+    fn node(&self, id: i64) -> Option<&mut SceneNode> {
+        unsafe { find_node(self.root, id as NodeId).map(|node| &mut *node) }
+    }
+}
+
+// A new node with everything `template` has, and copies of its children under it
+fn copy_subtree(template: &SceneNode) -> Node {
+    let mut node = SceneNode::from_vao(template.vao_id, template.index_count);
+    node.position = template.position;
+    node.rotation = template.rotation;
+    node.scale = template.scale;
+    node.reference_point = template.reference_point;
+    node.material = template.material.clone();
+    node.light = template.light;
+    node.visible = template.visible;
+    for &child in &template.children {
+        node.add_child(&copy_subtree(unsafe { &*child }));
+    }
+    node
+}
+
+fn find_node(node: *mut SceneNode, id: NodeId) -> Option<*mut SceneNode> {
+    let node_ref = unsafe { node.as_ref()? };
+    if node_ref.id == id {
+        return Some(node);
+    }
+    node_ref.children.iter().find_map(|&child| find_node(child, id))
+}
+
+fn to_array(vector: &glm::Vec3) -> Array {
+    vec![Dynamic::from_float(vector.x as f64), Dynamic::from_float(vector.y as f64), Dynamic::from_float(vector.z as f64)]
+}
+
+struct Script {
+    path  : String,
+    ast   : Option<AST>, // None if it didn't compile, until it is fixed
+    state : Dynamic,
+}
+
+pub struct ScriptHost {
+    engine  : Engine,
+    world   : Rc<RefCell<ScriptWorld>>,
+    scripts : Vec<Script>,
+}
+
+impl ScriptHost {
+    // `root` is the scene graph the scripts work on, it has to outlive the host
+    pub fn new(root: &mut SceneNode) -> ScriptHost {
+        let world = Rc::new(RefCell::new(ScriptWorld {
+            root              : root as *mut SceneNode,
+            named_nodes       : HashMap::new(),
+            time              : 0.0,
+            delta_time        : 0.0,
+            pressed_keys      : vec![],
+            just_pressed_keys : vec![],
+        }));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+
+        let w = Rc::clone(&world);
+        engine.register_fn("time", move || w.borrow().time);
+        let w = Rc::clone(&world);
+        engine.register_fn("delta_time", move || w.borrow().delta_time);
+        let w = Rc::clone(&world);
+        engine.register_fn("is_pressed", move |key: &str| w.borrow().pressed_keys.iter().any(|pressed| pressed == key));
+        let w = Rc::clone(&world);
+        engine.register_fn("was_just_pressed", move |key: &str| w.borrow().just_pressed_keys.iter().any(|pressed| pressed == key));
+        let w = Rc::clone(&world);
+        engine.register_fn("node", move |name: &str| w.borrow().named_nodes.get(name).map_or(-1, |&id| id as i64));
+
+        let w = Rc::clone(&world);
+        engine.register_fn("get_position", move |id: i64| w.borrow().node(id).map_or_else(Array::new, |node| to_array(&scene_graph::position_to_f32(&node.position))));
+        let w = Rc::clone(&world);
+        engine.register_fn("get_rotation", move |id: i64| w.borrow().node(id).map_or_else(Array::new, |node| to_array(&node.rotation)));
+        let w = Rc::clone(&world);
+        engine.register_fn("get_scale", move |id: i64| w.borrow().node(id).map_or_else(Array::new, |node| to_array(&node.scale)));
+        let w = Rc::clone(&world);
+        engine.register_fn("set_position", move |id: i64, x: f64, y: f64, z: f64| {
+            if let Some(node) = w.borrow().node(id) { node.position = scene_graph::position_from_f32(&glm::vec3(x as f32, y as f32, z as f32)); }
+        });
+        let w = Rc::clone(&world);
+        engine.register_fn("set_rotation", move |id: i64, x: f64, y: f64, z: f64| {
+            if let Some(node) = w.borrow().node(id) { node.rotation = glm::vec3(x as f32, y as f32, z as f32); }
+        });
+        let w = Rc::clone(&world);
+        engine.register_fn("set_scale", move |id: i64, x: f64, y: f64, z: f64| {
+            if let Some(node) = w.borrow().node(id) { node.scale = glm::vec3(x as f32, y as f32, z as f32); }
+        });
+        let w = Rc::clone(&world);
+        engine.register_fn("set_visible", move |id: i64, visible: bool| {
+            if let Some(node) = w.borrow().node(id) { node.visible = visible; }
+        });
+
+        // The new nodes are leaked like every other SceneNode, spawning every frame is a bad idea.
+        // Not called "spawn", that is a reserved word in Rhai. The copy is finished before the parent
+        // is touched, so a parent inside the template doesn't get copied into itself.
+        let w = Rc::clone(&world);
+        engine.register_fn("spawn_copy", move |template_id: i64, parent_id: i64| {
+            let world = w.borrow();
+            let (template, parent) = match (find_node(world.root, template_id as NodeId), find_node(world.root, parent_id as NodeId)) {
+                (Some(template), Some(parent)) if template != parent => (template, parent),
+                _ => return -1,
+            };
+            let node = copy_subtree(unsafe { &*template });
+            unsafe { (*parent).add_child(&node) };
+            node.id as i64
+        });
+
+        ScriptHost { engine, world, scripts: vec![] }
+    }
+
+    // Let scripts find a node with node(name)
+    pub fn expose(&mut self, name: &str, id: NodeId) {
+        self.world.borrow_mut().named_nodes.insert(name.to_string(), id);
+    }
+
+    // Load every .rhai file in a directory, in alphabetical order. Gives the paths, for hot reloading.
+    pub fn load_directory(&mut self, directory: &str) -> Vec<String> {
+        let mut paths: Vec<String> = match std::fs::read_dir(directory) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|extension| extension == "rhai"))
+                .map(|path| path.to_string_lossy().to_string())
+                .collect(),
+            Err(e) => {
+                println!("No scripts loaded from {}: {}", directory, e);
+                vec![]
+            }
+        };
+        paths.sort();
+        for path in &paths {
+            self.load(path);
+        }
+        paths
+    }
+
+    // Load a script, or load it again if it is loaded already. Its state starts over.
+    pub fn load(&mut self, path: &str) {
+        let ast = match self.engine.compile_file(path.into()) {
+            Ok(ast) => Some(ast),
+            Err(e) => {
+                println!("Script {} failed to compile: {}", path, e);
+                None
+            }
+        };
+        let index = match self.scripts.iter().position(|script| script.path == path) {
+            Some(index) => index,
+            None => {
+                self.scripts.push(Script { path: path.to_string(), ast: None, state: Dynamic::UNIT });
+                self.scripts.len() - 1
+            }
+        };
+        let script = &mut self.scripts[index];
+        script.ast = ast;
+        script.state = Dynamic::from_map(Map::new());
+        Self::call(&self.engine, script, "init");
+    }
+
+    pub fn is_loaded(&self, path: &str) -> bool {
+        self.scripts.iter().any(|script| script.path == path)
+    }
+
+    // Call a function of the script with `this` bound to its state, if the script has that function.
+    // A script that fails keeps running, the error is printed every time.
+    fn call(engine: &Engine, script: &mut Script, function: &str) {
+        let ast = match &script.ast {
+            Some(ast) => ast,
+            None => return,
+        };
+        if !ast.iter_functions().any(|f| f.name == function && f.params.is_empty()) {
+            return;
+        }
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut script.state);
+        if let Err(e) = engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), ast, function, ()) {
+            println!("Script {} failed in {}(): {}", script.path, function, e);
+        }
+    }
+
+    // * Run the update of every script
+    pub fn update(&mut self, input: &InputState, time: f32, delta_time: f32) {
+        {
+            let mut world = self.world.borrow_mut();
+            world.time = time as f64;
+            world.delta_time = delta_time as f64;
+            world.pressed_keys = input.pressed_keys.iter().map(|key| format!("{:?}", key)).collect();
+            world.just_pressed_keys = input.just_pressed_keys.iter().map(|key| format!("{:?}", key)).collect();
+        }
+        for script in &mut self.scripts {
+            Self::call(&self.engine, script, "update");
+        }
+    }
+}
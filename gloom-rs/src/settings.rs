@@ -0,0 +1,156 @@
+use crate::gfx;
+use crate::math;
+use crate::sampler::TextureQuality;
+use crate::shader::Shader;
+
+// * Renderer settings
+// Everything about how the renderer behaves that can be changed while the program runs.
+// Owned by the render thread, changed through key bindings.
+
+pub struct RendererSettings {
+    pub vsync           : bool,
+    pub frame_cap       : Option<u32>, // Frames per second, None for no limit
+    pub fog             : FogSettings,
+    pub pbr             : bool,        // PBR materials instead of the flat vertex color shading
+    pub hdr             : bool,        // Draw into a floating point framebuffer and tone map it
+    pub exposure        : f32,         // Tone mapping exposure, higher is brighter. Only used with auto exposure off.
+    pub auto_exposure   : bool,        // Follow the brightness of the scene instead (see auto_exposure.rs)
+    pub ssao            : bool,        // Screen space ambient occlusion, needs HDR on
+    pub projection      : CameraProjection,
+    pub texture_quality : TextureQuality, // Caps the filtering of every material's sampler (see sampler.rs)
+}
+
+// Frame caps the L key cycles through
+pub const FRAME_CAPS: [Option<u32>; 4] = [None, Some(144), Some(60), Some(30)];
+
+impl RendererSettings {
+    pub fn new() -> RendererSettings {
+        RendererSettings {
+            vsync: true,
+            frame_cap: None,
+            fog: FogSettings::new(),
+            pbr: false,
+            hdr: true,
+            exposure: 1.5,
+            auto_exposure: true,
+            ssao: true,
+            projection: CameraProjection::new(),
+            texture_quality: TextureQuality::Ultra,
+        }
+    }
+
+    pub fn next_frame_cap(&mut self) {
+        let index = FRAME_CAPS.iter().position(|&cap| cap == self.frame_cap).unwrap_or(0);
+        self.frame_cap = FRAME_CAPS[(index + 1) % FRAME_CAPS.len()];
+    }
+}
+
+
+// * Camera projection
+// Where the near and far planes of the camera views are, and whether their depth is reversed (see
+// gfx::DepthMode). Reversed, the far plane can go out tens of thousands of units without the distant
+// terrain z-fighting. The shadow maps and the probes have projections of their own.
+
+#[derive(Clone, Copy, Debug)]
+pub struct CameraProjection {
+    pub near       : f32,
+    pub far        : f32,
+    pub reversed_z : bool, // Needs GlCaps::clip_control
+}
+
+// Near planes the F8 key cycles through
+pub const NEAR_PLANES: [f32; 4] = [1.0, 0.5, 0.1, 5.0];
+
+impl CameraProjection {
+    pub fn new() -> CameraProjection {
+        CameraProjection {
+            near: math::CAMERA_NEAR,
+            far: math::CAMERA_FAR,
+            reversed_z: true,
+        }
+    }
+
+    pub fn perspective(&self, aspect_ratio: f32) -> glm::Mat4 {
+        math::camera_perspective(aspect_ratio, self.near, self.far, self.reversed_z)
+    }
+
+    pub fn depth_mode(&self) -> gfx::DepthMode {
+        if self.reversed_z { gfx::DepthMode::Reversed } else { gfx::DepthMode::Standard }
+    }
+
+    pub fn next_near_plane(&mut self) {
+        let index = NEAR_PLANES.iter().position(|&near| near == self.near).unwrap_or(0);
+        self.near = NEAR_PLANES[(index + 1) % NEAR_PLANES.len()];
+    }
+
+    // Moves the far plane `factor` times further out, but never in front of the near plane
+    pub fn scale_far_plane(&mut self, factor: f32) {
+        self.far = (self.far * factor).max(self.near * 10.0);
+    }
+}
+
+
+// * Distance fog
+// Fades everything far away into the fog color, so the edge of the terrain doesn't just stop.
+// The Moon has no atmosphere, so this is a per-scene look rather than something physical (F toggles it).
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FogMode {
+    Linear,             // No fog before `start`, full fog from `end`
+    Exponential,        // 1 - e^(-density * distance)
+    ExponentialSquared, // 1 - e^(-(density * distance)^2), clearer up close and a sharper falloff
+}
+
+impl FogMode {
+    pub fn next(self) -> FogMode {
+        match self {
+            FogMode::Linear             => FogMode::Exponential,
+            FogMode::Exponential        => FogMode::ExponentialSquared,
+            FogMode::ExponentialSquared => FogMode::Linear,
+        }
+    }
+
+    // Matches the fog_mode values in simple.frag
+    fn shader_value(self) -> i32 {
+        match self {
+            FogMode::Linear             => 0,
+            FogMode::Exponential        => 1,
+            FogMode::ExponentialSquared => 2,
+        }
+    }
+}
+
+pub struct FogSettings {
+    pub enabled : bool,
+    pub mode    : FogMode,
+    pub color   : [f32; 3],
+    pub start   : f32, // Linear fog only, in world units from the camera
+    pub end     : f32, // Linear fog only
+    pub density : f32, // Exponential fog only
+}
+
+impl FogSettings {
+    pub fn new() -> FogSettings {
+        FogSettings {
+            enabled: true,
+            mode: FogMode::Linear,
+            color: [0.035, 0.046, 0.078], // Follows the sky color (see time_of_day.rs), so the terrain fades into it
+            start: 300.0,
+            end: 850.0,
+            density: 0.003,
+        }
+    }
+
+    /// Upload the fog uniforms, the shader has to be active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_uniforms(&self, shader: &Shader) {
+        shader.set_uniform_int("fog_enabled", self.enabled as i32);
+        shader.set_uniform_int("fog_mode", self.mode.shader_value());
+        shader.set_uniform_vec3("fog_color", &self.color);
+        shader.set_uniform_float("fog_start", self.start);
+        shader.set_uniform_float("fog_end", self.end);
+        shader.set_uniform_float("fog_density", self.density);
+    }
+}
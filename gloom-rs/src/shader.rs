@@ -6,13 +6,32 @@ use std::{
     path::Path,
 };
 
+use crate::clusters::LIGHT_CLUSTERS_BINDING;
+use crate::gfx;
+use crate::gl_caps::RendererProfile;
+use crate::program_cache::ProgramCache;
+use crate::ring_buffer::DRAW_TRANSFORMS_BINDING;
+use crate::shadows::SHADOW_CASCADES_BINDING;
+use crate::uniform_block;
+
 pub struct Shader {
     pub program_id: u32,
+    pub draw_transforms: bool, // Has the DrawTransforms block, see ring_buffer.rs
 }
 
+// Every uniform block a program can have and the binding point it always gets. A program keeps its
+// bindings, so they are set once when it is linked and the draws only bind buffers to the points.
+const UNIFORM_BLOCK_BINDINGS: [(&str, u32); 3] = [
+    ("DrawTransforms", DRAW_TRANSFORMS_BINDING),
+    ("ShadowCascades", SHADOW_CASCADES_BINDING),
+    ("LightClusters",  LIGHT_CLUSTERS_BINDING),
+];
+
 pub struct ShaderBuilder {
     program_id: u32,
-    shaders: Vec::<u32>,
+    sources: Vec<(gl::types::GLenum, String)>, // Compiled when linking, unless the cache has the program
+    profile: RendererProfile,
+    cache: Option<ProgramCache>,
 }
 
 #[allow(dead_code)]
@@ -22,6 +41,7 @@ pub enum ShaderType {
     TessellationControl,
     TessellationEvaluation,
     Geometry,
+    Compute,
 }
 
 impl Shader {
@@ -31,10 +51,32 @@ impl Shader {
         gl::GetUniformLocation(self.program_id, name_cstr.as_ptr())
     }
 
+    // gl::INVALID_INDEX if the program has no uniform block with that name
+    pub unsafe fn get_uniform_block_index(&self, name: &str) -> u32 {
+        let name_cstr = CString::new(name).expect("CString::new failed");
+        gl::GetUniformBlockIndex(self.program_id, name_cstr.as_ptr())
+    }
+
+    unsafe fn bind_uniform_blocks(program_id: u32) -> Shader {
+        let shader = Shader { program_id, draw_transforms: false };
+        for (name, binding) in UNIFORM_BLOCK_BINDINGS {
+            let block_index = shader.get_uniform_block_index(name);
+            if block_index != gl::INVALID_INDEX {
+                gl::UniformBlockBinding(program_id, block_index, binding);
+            }
+        }
+        Shader { draw_transforms: shader.get_uniform_block_index("DrawTransforms") != gl::INVALID_INDEX, ..shader }
+    }
+
     pub unsafe fn activate(&self) {
         gl::UseProgram(self.program_id);
     }
 
+    // Free the program, the Shader must not be used after this
+    pub unsafe fn delete(&self) {
+        gl::DeleteProgram(self.program_id);
+    }
+
     // * Custom method to edit shader color
     /// The power of ChatGPT and my final brain cell X)
     /// Sets a vec3 uniform in the shader program.
@@ -71,6 +113,30 @@ impl Shader {
         }
     }
 
+    // * Custom method to set a vec2 uniform in the shader program
+    pub unsafe fn set_uniform_vec2(&self, name: &str, value: &[f32; 2]) {
+        let name_cstr = CString::new(name).expect("CString::new failed");
+        let uniform_location = gl::GetUniformLocation(self.program_id, name_cstr.as_ptr());
+
+        if uniform_location != -1 {
+            gl::Uniform2fv(uniform_location, 1, value.as_ptr());
+        } else {
+            println!("Warning: uniform '{}' not found in shader!", name);
+        }
+    }
+
+    // * Custom method to set a vec4 uniform in the shader program
+    pub unsafe fn set_uniform_vec4(&self, name: &str, value: &[f32; 4]) {
+        let name_cstr = CString::new(name).expect("CString::new failed");
+        let uniform_location = gl::GetUniformLocation(self.program_id, name_cstr.as_ptr());
+
+        if uniform_location != -1 {
+            gl::Uniform4fv(uniform_location, 1, value.as_ptr());
+        } else {
+            println!("Warning: uniform '{}' not found in shader!", name);
+        }
+    }
+
     // * Custom method to set a float uniform in the shader program
     /// Sets a float uniform in the shader program.
     /// 
@@ -92,6 +158,31 @@ impl Shader {
         }
     }
 
+    // * Custom method to set an int uniform in the shader program
+    // Also used for samplers, where the value is the texture unit
+    pub unsafe fn set_uniform_int(&self, name: &str, value: i32) {
+        let name_cstr = CString::new(name).expect("CString::new failed");
+        let uniform_location = gl::GetUniformLocation(self.program_id, name_cstr.as_ptr());
+
+        if uniform_location != -1 {
+            gl::Uniform1i(uniform_location, value);
+        } else {
+            println!("Warning: uniform '{}' not found in shader!", name);
+        }
+    }
+
+    // * Custom method to set an unsigned int uniform in the shader program
+    pub unsafe fn set_uniform_uint(&self, name: &str, value: u32) {
+        let name_cstr = CString::new(name).expect("CString::new failed");
+        let uniform_location = gl::GetUniformLocation(self.program_id, name_cstr.as_ptr());
+
+        if uniform_location != -1 {
+            gl::Uniform1ui(uniform_location, value);
+        } else {
+            println!("Warning: uniform '{}' not found in shader!", name);
+        }
+    }
+
     // * Custom method to set a mat4 uniform in the shader program
     pub unsafe fn set_uniform_mat4(&self, name: &str, matrix: &glm::Mat4) {
         let name_cstr = CString::new(name).expect("CString::new failed");
@@ -114,6 +205,7 @@ impl Into<gl::types::GLenum> for ShaderType {
             ShaderType::TessellationControl     => { gl::TESS_CONTROL_SHADER    },
             ShaderType::TessellationEvaluation  => { gl::TESS_EVALUATION_SHADER } ,
             ShaderType::Geometry                => { gl::GEOMETRY_SHADER        },
+            ShaderType::Compute                 => { gl::COMPUTE_SHADER         },
         }
     }
 }
@@ -126,6 +218,7 @@ impl ShaderType {
             "tcs"  => { Ok(ShaderType::TessellationControl) },
             "tes"  => { Ok(ShaderType::TessellationEvaluation) },
             "geom" => { Ok(ShaderType::Geometry) },
+            "comp" => { Ok(ShaderType::Compute) },
             e => { Err(e.to_string()) },
         }
     }
@@ -135,17 +228,31 @@ impl ShaderBuilder {
     pub unsafe fn new() -> ShaderBuilder {
         ShaderBuilder {
             program_id: gl::CreateProgram(),
-            shaders: vec![],
+            sources: vec![],
+            profile: RendererProfile::Desktop,
+            cache: None,
         }
     }
 
+    // Load the program from `cache` if it is there, and put it there otherwise, see program_cache.rs
+    pub fn with_cache(mut self, cache: Option<ProgramCache>) -> ShaderBuilder {
+        self.cache = cache;
+        self
+    }
+
+    // Compile the shaders attached after this for another profile, see gl_caps.rs
+    pub fn with_profile(mut self, profile: RendererProfile) -> ShaderBuilder {
+        self.profile = profile;
+        self
+    }
+
     pub unsafe fn attach_file(self, shader_path: &str) -> ShaderBuilder {
         let path = Path::new(shader_path);
         if let Some(extension) = path.extension() {
             let shader_type = ShaderType::from_ext(extension)
                 .expect("Failed to parse file extension.");
             let shader_src = std::fs::read_to_string(path)
-                .expect(&format!("Failed to read shader source. {}", shader_path));
+                .unwrap_or_else(|error| panic!("Failed to read shader source. {}: {:?}", shader_path, error));
             self.compile_shader(&shader_src, shader_type)
         } else {
             panic!("Failed to read extension of file with path: {}", shader_path);
@@ -153,17 +260,13 @@ impl ShaderBuilder {
     }
 
     pub unsafe fn compile_shader(mut self, shader_src: &str, shader_type: ShaderType) -> ShaderBuilder {
-        let shader = gl::CreateShader(shader_type.into());
-        let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
-        gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
-        gl::CompileShader(shader);
-
-        if !self.check_shader_errors(shader) {
-            panic!("Shader failed to compile.");
-        }
-
-        self.shaders.push(shader);
-
+        // The sources are written for desktop GL, swap the #version line for the profile's
+        let shader_src = match (self.profile, shader_src.strip_prefix("#version 430 core")) {
+            (RendererProfile::Desktop, _) | (_, None) => shader_src.to_string(),
+            (profile, Some(rest)) => format!("{}{}", profile.shader_header(), rest.trim_start_matches(['\r', '\n'])),
+        };
+        let shader_src = uniform_block::expand_declarations(&shader_src);
+        self.sources.push((shader_type.into(), shader_src));
         self
     }
 
@@ -205,20 +308,43 @@ impl ShaderBuilder {
 
     #[must_use = "The shader program is useless if not stored in a variable."]
     pub unsafe fn link(self) -> Shader {
-        for &shader in &self.shaders {
+        if let Some(cache) = &self.cache {
+            if cache.load(self.program_id, &self.sources) {
+                gfx::note_event(format!("shader program {} loaded from the program cache", self.program_id));
+                return Shader::bind_uniform_blocks(self.program_id);
+            }
+        }
+
+        let mut shaders = vec![];
+        for (shader_type, shader_src) in &self.sources {
+            let shader = gl::CreateShader(*shader_type);
+            let c_str_shader = CString::new(shader_src.as_bytes()).unwrap();
+            gl::ShaderSource(shader, 1, &c_str_shader.as_ptr(), ptr::null());
+            gl::CompileShader(shader);
+
+            if !self.check_shader_errors(shader) {
+                panic!("Shader failed to compile.");
+            }
             gl::AttachShader(self.program_id, shader);
+            shaders.push(shader);
+        }
+        if self.cache.is_some() {
+            gl::ProgramParameteri(self.program_id, gl::PROGRAM_BINARY_RETRIEVABLE_HINT, gl::TRUE as i32);
         }
         gl::LinkProgram(self.program_id);
 
         // todo:: use this to make safer abstraction
-        self.check_linker_errors();
+        if self.check_linker_errors() {
+            if let Some(cache) = &self.cache {
+                cache.store(self.program_id, &self.sources);
+            }
+        }
 
-        for &shader in &self.shaders {
+        for shader in shaders {
             gl::DeleteShader(shader);
         }
+        gfx::note_event(format!("shader program {} compiled from {} stages", self.program_id, self.sources.len()));
 
-        Shader {
-            program_id: self.program_id
-        }
+        Shader::bind_uniform_blocks(self.program_id)
     }
 }
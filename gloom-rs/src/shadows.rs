@@ -0,0 +1,225 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::camera::Camera;
+use crate::frustum_debug::practical_splits;
+use crate::gfx;
+use crate::math;
+use crate::shader::Shader;
+use crate::uniform_block::UniformBlock;
+
+// * Cascaded shadow maps
+// Shadows from the sun. One shadow map stretched over the whole lunar terrain would give every texel
+// metres of ground, blocky up close and wasted far away. So the fly-cam's frustum is cut into cascades
+// by distance, short ones near the camera and longer ones further out, and each gets a shadow map of
+// its own (the layers of one depth texture array) that only covers that slice. Up close a texel is a
+// few centimetres, at the far end of the last cascade it is a few metres, where it can't be told apart.
+//
+// Every cascade's shadow map covers a sphere around its slice of the frustum rather than the slice
+// itself, so it keeps its size when the camera turns, and it only moves in whole texels. Both keep the
+// shadow edges from crawling while flying around. The matrices go to the shaders in a uniform block,
+// and simple.frag and pbr.frag use the first cascade a fragment is inside of, blending over to the
+// next one near its edge so there is no visible seam. Other views than the fly-cam (the chase cams,
+// the security feed) use the same cascades, they get shadows wherever the fly-cam's cascades reach.
+
+pub const MAX_CASCADES: usize = 4; // Same as MAX_CASCADES in simple.frag and pbr.frag
+pub const SHADOW_UNIT: u32 = 6;    // Texture unit of the shadow maps, after the material ones
+pub const SHADOW_CASCADES_BINDING: u32 = 1; // Uniform block binding, the draw transforms have 0
+
+// Room behind every cascade for whatever is outside of it but throws a shadow into it, a hill
+// between the slice and the sun
+const CASTER_REACH: f32 = 300.0;
+
+uniform_block! {
+    pub struct CascadeBlock as ShadowCascades {
+        cascade_matrices : [glm::Mat4; MAX_CASCADES], // World space to 0..1 in the shadow map on every axis
+        cascade_count    : i32,
+        cascade_blend    : f32, // How much of the edge of a cascade fades into the next one
+    }
+}
+
+pub struct CascadedShadows {
+    pub enabled        : bool,
+    pub cascade_count  : usize, // Up to MAX_CASCADES
+    pub distance       : f32,   // How far from the camera there are shadows
+    pub split_lambda   : f32,   // Between even (0) and logarithmic (1) splits, see frustum_debug::practical_splits
+    pub blend          : f32,   // How much of the edge of a cascade (0..1 across it) fades into the next
+    resolution         : i32,
+    splits             : Vec<f32>,
+    active             : bool,  // Whether the shadow maps were drawn this frame
+    depth_texture      : u32,
+    fbo_id             : u32,
+    uniform_buffer     : gfx::Buffer,
+    depth_shader       : ShaderHandle,
+}
+
+impl CascadedShadows {
+    /// Shadow maps of `resolution` squared for every cascade.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, resolution: i32) -> CascadedShadows {
+        // Comparing depth textures let the hardware do the shadow test (and filter the result)
+        let mut depth_texture: u32 = 0;
+        gl::GenTextures(1, &mut depth_texture);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, depth_texture);
+        gl::TexImage3D(gl::TEXTURE_2D_ARRAY, 0, gl::DEPTH_COMPONENT32F as i32, resolution, resolution, MAX_CASCADES as i32, 0, gl::DEPTH_COMPONENT, gl::FLOAT, std::ptr::null());
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MIN_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_MAG_FILTER, gl::LINEAR as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_S, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_WRAP_T, gl::CLAMP_TO_EDGE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_MODE, gl::COMPARE_REF_TO_TEXTURE as i32);
+        gl::TexParameteri(gl::TEXTURE_2D_ARRAY, gl::TEXTURE_COMPARE_FUNC, gl::LEQUAL as i32);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, 0);
+
+        // Depth only, the layer is attached before every cascade is drawn
+        let mut fbo_id: u32 = 0;
+        gl::GenFramebuffers(1, &mut fbo_id);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, fbo_id);
+        gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, depth_texture, 0, 0);
+        let no_color = gl::NONE;
+        gl::DrawBuffers(1, &no_color);
+        gl::ReadBuffer(gl::NONE);
+        if gl::CheckFramebufferStatus(gl::FRAMEBUFFER) != gl::FRAMEBUFFER_COMPLETE {
+            panic!("Shadow map framebuffer {}x{} is not complete!", resolution, resolution);
+        }
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        let empty = CascadeBlock { cascade_matrices: [glm::zero(); MAX_CASCADES], cascade_count: 0, cascade_blend: 0.0 };
+        let uniform_buffer = gfx::Buffer::new(gfx::BufferKind::Uniform, &empty.to_bytes());
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+        gfx::label_object(gfx::ObjectKind::Texture, depth_texture, "shadow cascades");
+        gfx::label_object(gfx::ObjectKind::Framebuffer, fbo_id, "shadow cascades");
+        uniform_buffer.label("ShadowCascades block");
+
+        CascadedShadows {
+            enabled: true,
+            cascade_count: MAX_CASCADES,
+            distance: 600.0,
+            split_lambda: 0.8,
+            blend: 0.1,
+            resolution,
+            splits: vec![],
+            active: false,
+            depth_texture,
+            fbo_id,
+            uniform_buffer,
+            depth_shader: assets.load_shader(&["shaders/shadow_depth.vert", "shaders/shadow_depth.frag"]),
+        }
+    }
+
+    // Far end of every cascade, from the camera, as of the last frame the shadow maps were drawn
+    pub fn splits(&self) -> &[f32] {
+        if self.active { &self.splits } else { &[] }
+    }
+
+    /// Fit the cascades to the frustum of `camera` (with views `aspect_ratio` wide, from `near_plane`
+    /// on) and draw the shadow maps for light traveling in `sun_direction`. `draw_casters` draws
+    /// everything that throws a shadow with the shader and view projection it is given. Leaves the
+    /// window bound, with the viewport it had before.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn render<F: FnMut(&Shader, &glm::Mat4)>(&mut self, assets: &AssetManager, camera: &Camera, aspect_ratio: f32, near_plane: f32, sun_direction: glm::Vec3, mut draw_casters: F) {
+        // Nothing to throw shadows with once the sun is down
+        self.active = self.enabled && sun_direction.y < 0.0;
+        if !self.active {
+            return;
+        }
+
+        let count = self.cascade_count.clamp(1, MAX_CASCADES);
+        self.splits = practical_splits(near_plane, self.distance, count, self.split_lambda);
+        let view = glm::look_at(&camera.position_f32(), &(camera.position_f32() + camera.forward()), &camera.up());
+
+        let mut block = CascadeBlock { cascade_matrices: [glm::zero(); MAX_CASCADES], cascade_count: count as i32, cascade_blend: self.blend };
+        let mut light_view_projections = vec![];
+        let mut near = near_plane;
+        for (cascade, &far) in self.splits.iter().enumerate() {
+            let light_view_projection = self.fit_cascade(&view, aspect_ratio, near, far, sun_direction);
+            // From -1..1 to 0..1, what the shadow map lookup wants
+            let to_texture = glm::translation(&glm::vec3(0.5, 0.5, 0.5)) * glm::scaling(&glm::vec3(0.5, 0.5, 0.5));
+            block.cascade_matrices[cascade] = to_texture * light_view_projection;
+            light_view_projections.push(light_view_projection);
+            near = far;
+        }
+        self.uniform_buffer.update(&block.to_bytes());
+        gl::BindBuffer(gl::UNIFORM_BUFFER, 0);
+
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+        gl::BindFramebuffer(gl::FRAMEBUFFER, self.fbo_id);
+        gl::Viewport(0, 0, self.resolution, self.resolution);
+        gfx::RenderState { cull: gfx::CullMode::Off, ..Default::default() }.apply(); // The rotor discs only have one side each way
+        gl::Enable(gl::POLYGON_OFFSET_FILL);
+        gl::PolygonOffset(2.0, 4.0); // Pushed back a bit more on slopes, so surfaces don't shadow themselves
+
+        let shader = assets.shader(self.depth_shader);
+        shader.activate();
+        for (cascade, light_view_projection) in light_view_projections.iter().enumerate() {
+            debug_scope!("shadow cascade {}", cascade);
+            gl::FramebufferTextureLayer(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, self.depth_texture, 0, cascade as i32);
+            gl::Clear(gl::DEPTH_BUFFER_BIT);
+            draw_casters(shader, light_view_projection);
+        }
+
+        gl::Disable(gl::POLYGON_OFFSET_FILL);
+        gfx::RenderState::default().apply();
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+    }
+
+    // Orthographic view from the sun onto a sphere around the part of the frustum between `near`
+    // and `far`, moved so its texels stay in the same place in the world
+    fn fit_cascade(&self, view: &glm::Mat4, aspect_ratio: f32, near: f32, far: f32, sun_direction: glm::Vec3) -> glm::Mat4 {
+        let to_world = glm::inverse(&(math::camera_perspective_between(aspect_ratio, near, far) * view));
+        let corners: Vec<glm::Vec3> = (0..8)
+            .map(|corner| {
+                let ndc = [1, 2, 4].map(|bit| if corner & bit != 0 { 1.0 } else { -1.0 });
+                let world = to_world * glm::vec4(ndc[0], ndc[1], ndc[2], 1.0);
+                world.xyz() / world.w
+            })
+            .collect();
+        let center = corners.iter().sum::<glm::Vec3>() / 8.0;
+        // Rounded up, so the size doesn't change by a hair every frame
+        let radius = corners.iter().map(|corner| glm::distance(corner, &center)).fold(0.0, f32::max).ceil();
+
+        let up = if sun_direction.y.abs() > 0.99 { glm::vec3(0.0, 0.0, 1.0) } else { glm::vec3(0.0, 1.0, 0.0) };
+        let eye = center - sun_direction * (radius + CASTER_REACH);
+        let light_view = glm::look_at(&eye, &center, &up);
+        let light_projection = glm::ortho(-radius, radius, -radius, radius, 0.0, 2.0 * radius + CASTER_REACH);
+        let mut light_view_projection = light_projection * light_view;
+
+        // Snap the world origin to a texel, which moves everything else onto texels as well
+        let texels = self.resolution as f32 * 0.5;
+        let origin = light_view_projection * glm::vec4(0.0, 0.0, 0.0, 1.0);
+        let offset = glm::vec2((origin.x * texels).round() - origin.x * texels, (origin.y * texels).round() - origin.y * texels) / texels;
+        light_view_projection[(0, 3)] += offset.x;
+        light_view_projection[(1, 3)] += offset.y;
+        light_view_projection
+    }
+
+    /// Point a lit shader (simple.frag or pbr.frag) at the shadow maps, it has to be active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn set_uniforms(&self, shader: &Shader) {
+        shader.set_uniform_int("shadows_enabled", self.active as i32);
+        shader.set_uniform_int("shadow_map", SHADOW_UNIT as i32);
+        gl::ActiveTexture(gl::TEXTURE0 + SHADOW_UNIT);
+        gl::BindTexture(gl::TEXTURE_2D_ARRAY, self.depth_texture);
+        gl::ActiveTexture(gl::TEXTURE0);
+
+        if shader.get_uniform_block_index("ShadowCascades") != gl::INVALID_INDEX {
+            gl::BindBufferBase(gl::UNIFORM_BUFFER, SHADOW_CASCADES_BINDING, self.uniform_buffer.id);
+        }
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteTextures(1, &self.depth_texture);
+        gl::DeleteFramebuffers(1, &self.fbo_id);
+        self.uniform_buffer.delete();
+    }
+}
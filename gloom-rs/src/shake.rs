@@ -0,0 +1,79 @@
+extern crate nalgebra_glm as glm;
+
+use crate::settings::CameraProjection;
+use crate::toolbox;
+
+// * Camera shake
+// The fly-cam shakes when something happens close to it: helicopters bumping into each other, or one
+// flying right past. Every event adds trauma, between 0 and 1, and the trauma wears off over a second
+// or so. How hard the camera shakes goes with the square of it, so small bumps barely show and big ones
+// stand out, and it settles down smoothly instead of stopping all at once.
+//
+// The shake moves and turns the camera a little, in its own space: sideways, up and down, forwards and
+// back, and in yaw, pitch and roll. Every one of them follows its own channel of Perlin noise, so it
+// wobbles instead of jittering from frame to frame. It goes between the view and the projection of a
+// view projection that is already worked out, after the camera has been moved for the frame, so
+// nothing that moves the camera needs to know about it.
+
+// Trauma lost per second
+const DECAY           : f32 = 0.8;
+// At full trauma
+const MAX_OFFSET      : f32 = 0.3; // Units
+const MAX_ANGLE       : f32 = 0.05; // Radians
+const NOISE_FREQUENCY : f32 = 12.0; // Wobbles per second
+// Helicopters closer than this shake the camera, more the closer they are
+const FLYBY_DISTANCE  : f32 = 15.0;
+const FLYBY_TRAUMA    : f32 = 1.5; // Per second, right next to the camera
+
+pub struct CameraShake {
+    pub enabled : bool,
+    pub trauma  : f32,
+    time        : f32,
+    noise       : toolbox::Noise,
+}
+
+impl CameraShake {
+    pub fn new() -> CameraShake {
+        CameraShake { enabled: true, trauma: 0.0, time: 0.0, noise: toolbox::Noise::new(41) }
+    }
+
+    pub fn add_trauma(&mut self, amount: f32) {
+        self.trauma = (self.trauma + amount).clamp(0.0, 1.0);
+    }
+
+    // Something `distance` away from the camera this frame, shakes it if it is close
+    pub fn flyby(&mut self, distance: f32, delta_time: f32) {
+        let closeness = (1.0 - distance / FLYBY_DISTANCE).max(0.0);
+        self.add_trauma(closeness * closeness * FLYBY_TRAUMA * delta_time);
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.time += delta_time;
+        self.trauma = (self.trauma - DECAY * delta_time).max(0.0);
+    }
+
+    // The shake in the camera's own space, identity when it is still
+    pub fn view_offset(&self) -> glm::Mat4 {
+        let shake = self.trauma * self.trauma;
+        if !self.enabled || shake == 0.0 {
+            return glm::identity();
+        }
+        // Every channel is a line through the noise of its own, far from the others
+        let channel = |i: u32| self.noise.perlin2(self.time * NOISE_FREQUENCY, i as f32 * 7.5 + 0.5) * shake;
+        let offset = glm::vec3(channel(0), channel(1), channel(2)) * MAX_OFFSET;
+        let (yaw, pitch, roll) = (channel(3) * MAX_ANGLE, channel(4) * MAX_ANGLE, channel(5) * MAX_ANGLE);
+        glm::translation(&offset)
+            * glm::rotation(roll, &glm::vec3(0.0, 0.0, 1.0))
+            * glm::rotation(pitch, &glm::vec3(1.0, 0.0, 0.0))
+            * glm::rotation(yaw, &glm::vec3(0.0, 1.0, 0.0))
+    }
+
+    /// `view_projection` shaken, as if the camera had been moved in between its view and projection.
+    pub fn apply(&self, view_projection: &glm::Mat4, aspect_ratio: f32, projection: &CameraProjection) -> glm::Mat4 {
+        if !self.enabled || self.trauma == 0.0 {
+            return *view_projection;
+        }
+        let perspective = projection.perspective(aspect_ratio);
+        perspective * self.view_offset() * glm::inverse(&perspective) * view_projection
+    }
+}
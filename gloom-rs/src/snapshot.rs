@@ -0,0 +1,184 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::gfx;
+use crate::lights::{self, WorldLight};
+use crate::material::Material;
+use crate::ring_buffer::TransformStream;
+use crate::scene_graph::{NodeId, Position, SceneNode, WorldMatrix};
+use crate::shader::Shader;
+use crate::spatial::SpatialIndex;
+
+// * Frame snapshots
+// Once the update is done for a frame, the scene graph is flattened into a snapshot: every visible
+// node with a VAO becomes a draw with its world transform worked out, plus the lights in world space
+// and where every visible node is. The render stage reads the scene from the snapshot and never looks
+// at the SceneNodes (only the editor overlays still walk the graph), so the graph can be changed for
+// the next frame while the snapshot of this one is still being drawn. The update changes the nodes
+// through the graph by ID (see SceneNode::find_mut), not through pointers kept from building it.
+//
+// There are two snapshots: the front one is drawn from, the back one is filled in and then swapped in
+// with `publish`. Their Vecs and the map are reused, so capturing doesn't allocate once the scene stops growing.
+
+#[derive(Clone, Debug)]
+pub struct DrawItem {
+    pub node_id               : NodeId,
+    pub vao_id                : u32,
+    pub index_count           : i32,
+    pub model_matrix          : glm::Mat4,
+    pub previous_model_matrix : glm::Mat4, // In the snapshot before, for the motion vectors. The same for what wasn't in it.
+    pub position              : Position,  // Where model_matrix puts the origin, as precise as the scene has it
+    pub material              : Option<Rc<Material>>, // The node's own, not a copy
+}
+
+// Where a visible node is in the snapshot, drawing anything or not
+#[derive(Clone, Copy, Debug)]
+pub struct NodePlacement {
+    pub model_matrix : glm::Mat4,
+    pub position     : Position,  // Where model_matrix puts the origin, as precise as the scene has it
+    pub rotation     : glm::Vec3, // The node's own, relative to its parent
+}
+
+pub struct FrameSnapshot {
+    pub frame  : u64,
+    pub draws  : Vec<DrawItem>,
+    pub lights : Vec<WorldLight>,
+    nodes      : HashMap<NodeId, NodePlacement>,
+}
+
+impl FrameSnapshot {
+    pub fn new() -> FrameSnapshot {
+        FrameSnapshot { frame: 0, draws: vec![], lights: vec![], nodes: HashMap::new() }
+    }
+
+    // Where the node was, None if it was hidden or not in the scene graph
+    pub fn node(&self, id: NodeId) -> Option<&NodePlacement> {
+        self.nodes.get(&id)
+    }
+
+    // Replace the contents with the scene graph below `root`, as it is right now
+    pub fn capture(&mut self, root: &SceneNode, frame: u64) {
+        self.frame = frame;
+        self.draws.clear();
+        self.lights.clear();
+        self.nodes.clear();
+        self.flatten(root, &glm::identity());
+        lights::collect_lights(root, &glm::identity(), &mut self.lights);
+    }
+
+    // Same walk as draw_scene in main.rs, hidden nodes take their children with them.
+    // Moves the node's world matrix along to its previous one, once per capture.
+    fn flatten(&mut self, node: &SceneNode, transformation_so_far: &WorldMatrix) {
+        if !node.visible {
+            return;
+        }
+        let world_matrix = transformation_so_far * node.precise_local_transform();
+        let model_matrix: glm::Mat4 = glm::convert(world_matrix);
+        node.previous_world_matrix.set(node.world_matrix.replace(Some(model_matrix)));
+        let position = world_matrix.column(3).xyz();
+        self.nodes.insert(node.id, NodePlacement { model_matrix, position, rotation: node.rotation });
+
+        if node.vao_id != 0 {
+            self.draws.push(DrawItem {
+                node_id               : node.id,
+                vao_id                : node.vao_id,
+                index_count           : node.index_count,
+                model_matrix,
+                previous_model_matrix : node.previous_world_matrix.get().unwrap_or(model_matrix),
+                position,
+                material              : node.material.clone(),
+            });
+        }
+
+        for &child_ptr in &node.children {
+            if let Some(child) = unsafe { child_ptr.as_ref() } {
+                self.flatten(child, &world_matrix);
+            }
+        }
+    }
+
+    /// Draw everything in the snapshot with `shader`, which has to be active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, view_projection_matrix: &glm::Mat4, shader: &Shader, transforms: &TransformStream) {
+        self.draw_items(self.draws.iter(), view_projection_matrix, shader, transforms);
+    }
+
+    /// Like `draw`, but skips the nodes the spatial index knows are outside the view. The index has to
+    /// be updated from this snapshot. Draws it doesn't know about are always drawn.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw_visible(&self, view_projection_matrix: &glm::Mat4, shader: &Shader, transforms: &TransformStream, spatial: &SpatialIndex) {
+        self.draw_visible_from(view_projection_matrix, view_projection_matrix, shader, transforms, spatial);
+    }
+
+    /// Like `draw_visible`, but culled against another camera than the one it is drawn with, so the
+    /// culling of one camera can be watched from another (see frustum_debug.rs).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw_visible_from(&self, view_projection_matrix: &glm::Mat4, culling_view_projection_matrix: &glm::Mat4, shader: &Shader, transforms: &TransformStream, spatial: &SpatialIndex) {
+        self.draw_visible_except(view_projection_matrix, culling_view_projection_matrix, shader, transforms, spatial, &[]);
+    }
+
+    /// Like `draw_visible_from`, without the nodes in `skipped`, for what something else stands in
+    /// for in this view (see impostor.rs).
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw_visible_except(&self, view_projection_matrix: &glm::Mat4, culling_view_projection_matrix: &glm::Mat4, shader: &Shader, transforms: &TransformStream, spatial: &SpatialIndex, skipped: &[NodeId]) {
+        let visible = spatial.visible_nodes(culling_view_projection_matrix);
+        let items = self.draws.iter()
+            .filter(|item| visible.contains(&item.node_id) || spatial.bounds(item.node_id).is_none())
+            .filter(|item| !skipped.contains(&item.node_id));
+        self.draw_items(items, view_projection_matrix, shader, transforms);
+    }
+
+    unsafe fn draw_items<'a>(&self, items: impl Iterator<Item = &'a DrawItem>, view_projection_matrix: &glm::Mat4, shader: &Shader, transforms: &TransformStream) {
+        // Only the PBR shader has materials
+        let has_materials = shader.get_uniform_location("material_albedo") != -1;
+        let default_material = Material::default();
+
+        for item in items {
+            let mvp_matrix = transforms.model_view_projection(view_projection_matrix, &item.model_matrix, &item.position);
+            transforms.set_moving(shader, &mvp_matrix, &item.model_matrix, &item.previous_model_matrix);
+            if has_materials {
+                item.material.as_deref().unwrap_or(&default_material).bind(shader);
+            }
+            gfx::DrawCall::indexed(item.vao_id, item.index_count).submit();
+        }
+        if has_materials {
+            Material::unbind_samplers();
+        }
+    }
+}
+
+pub struct SnapshotBuffer {
+    snapshots : [FrameSnapshot; 2],
+    front     : usize,
+}
+
+impl SnapshotBuffer {
+    pub fn new() -> SnapshotBuffer {
+        SnapshotBuffer { snapshots: [FrameSnapshot::new(), FrameSnapshot::new()], front: 0 }
+    }
+
+    // The snapshot being drawn
+    pub fn front(&self) -> &FrameSnapshot {
+        &self.snapshots[self.front]
+    }
+
+    // The snapshot to fill in for the next frame
+    pub fn back_mut(&mut self) -> &mut FrameSnapshot {
+        &mut self.snapshots[1 - self.front]
+    }
+
+    // The back snapshot is complete, draw from it from now on
+    pub fn publish(&mut self) {
+        self.front = 1 - self.front;
+    }
+}
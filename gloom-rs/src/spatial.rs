@@ -0,0 +1,176 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::{HashMap, HashSet};
+
+use rayon::prelude::*;
+
+use crate::assets::AssetManager;
+use crate::instancing::{frustum_planes, sphere_in_frustum};
+use crate::mesh::Mesh;
+use crate::scene_graph::NodeId;
+use crate::snapshot::FrameSnapshot;
+
+// * Spatial index of the scene nodes
+// A loose uniform grid of world space bounding spheres, one per drawn node. A node lives in the cell
+// its center is in, so a query only has to look at the cells within reach plus one cell around them,
+// as long as no node is bigger than a cell. Nodes that are (the terrain) go in a list of their own that
+// every query checks. It is updated from the frame snapshot, and a node that moved only changes cell
+// when it actually crossed into another one.
+//
+// The bounds come from the meshes: every VAO that should be indexed is registered with the mesh that
+// went into it. Draws of VAOs that weren't registered (and entities, which have node ID 0) aren't indexed
+// and are never culled.
+
+type Cell = (i32, i32, i32);
+
+#[derive(Clone, Copy, Debug)]
+pub struct Bounds {
+    pub center : glm::Vec3, // World space
+    pub radius : f32,
+}
+
+struct Entry {
+    bounds : Bounds,
+    cell   : Option<Cell>, // None for the big nodes
+    frame  : u64,          // Last frame the node was drawn, it is dropped once it isn't
+}
+
+pub struct SpatialIndex {
+    cell_size    : f32,
+    cells        : HashMap<Cell, Vec<NodeId>>,
+    big          : Vec<NodeId>,
+    entries      : HashMap<NodeId, Entry>,
+    local_bounds : HashMap<u32, Bounds>, // Per VAO, in mesh space
+}
+
+impl SpatialIndex {
+    pub fn new(cell_size: f32) -> SpatialIndex {
+        SpatialIndex { cell_size, cells: HashMap::new(), big: vec![], entries: HashMap::new(), local_bounds: HashMap::new() }
+    }
+
+    // Index the nodes drawing this VAO, with a sphere around the mesh's bounding box
+    pub fn register_mesh(&mut self, vao_id: u32, mesh: &Mesh) {
+        if mesh.vertices.is_empty() {
+            return;
+        }
+        let (min, max) = mesh.bounds();
+        self.local_bounds.insert(vao_id, Bounds { center: mesh.center(), radius: glm::distance(&min, &max) * 0.5 });
+    }
+
+    // Every part of every mesh the asset manager has uploaded
+    pub fn register_assets(&mut self, assets: &AssetManager) {
+        for asset in assets.meshes() {
+            for part in &asset.parts {
+                if let Some(mesh) = assets.model(&asset.path).part(&part.name) {
+                    self.register_mesh(part.vao_id, mesh);
+                }
+            }
+        }
+    }
+
+    fn cell_of(&self, point: &glm::Vec3) -> Cell {
+        ((point.x / self.cell_size).floor() as i32, (point.y / self.cell_size).floor() as i32, (point.z / self.cell_size).floor() as i32)
+    }
+
+    fn remove_from_cell(&mut self, node_id: NodeId, cell: Option<Cell>) {
+        let list = match cell {
+            Some(cell) => match self.cells.get_mut(&cell) {
+                Some(list) => list,
+                None => return,
+            },
+            None => &mut self.big,
+        };
+        list.retain(|&id| id != node_id);
+        if list.is_empty() {
+            if let Some(cell) = cell {
+                self.cells.remove(&cell);
+            }
+        }
+    }
+
+    // * Move every drawn node to where it is in this snapshot
+    pub fn update(&mut self, snapshot: &FrameSnapshot) {
+        for draw in &snapshot.draws {
+            let local = match self.local_bounds.get(&draw.vao_id) {
+                Some(&local) if draw.node_id != 0 => local,
+                _ => continue,
+            };
+            let center = (draw.model_matrix * glm::vec4(local.center.x, local.center.y, local.center.z, 1.0)).xyz();
+            let scale = (0..3).map(|column| glm::length(&draw.model_matrix.column(column).xyz())).fold(0.0, f32::max);
+            let bounds = Bounds { center, radius: local.radius * scale };
+            let cell = if bounds.radius > self.cell_size { None } else { Some(self.cell_of(&center)) };
+
+            let old_cell = self.entries.get(&draw.node_id).map(|entry| entry.cell);
+            if old_cell != Some(cell) {
+                if let Some(old_cell) = old_cell {
+                    self.remove_from_cell(draw.node_id, old_cell);
+                }
+                match cell {
+                    Some(cell) => self.cells.entry(cell).or_default().push(draw.node_id),
+                    None => self.big.push(draw.node_id),
+                }
+            }
+            self.entries.insert(draw.node_id, Entry { bounds, cell, frame: snapshot.frame });
+        }
+
+        // Hidden nodes aren't in the snapshot, they leave the index until they show up again
+        let gone: Vec<(NodeId, Option<Cell>)> = self.entries.iter()
+            .filter(|(_, entry)| entry.frame != snapshot.frame)
+            .map(|(&node_id, entry)| (node_id, entry.cell))
+            .collect();
+        for (node_id, cell) in gone {
+            self.remove_from_cell(node_id, cell);
+            self.entries.remove(&node_id);
+        }
+    }
+
+    pub fn bounds(&self, node_id: NodeId) -> Option<Bounds> {
+        self.entries.get(&node_id).map(|entry| entry.bounds)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    // Every node in the cells within `reach` of the point, plus the big ones. Candidates only.
+    fn candidates(&self, point: &glm::Vec3, reach: f32) -> impl Iterator<Item = NodeId> + '_ {
+        let reach = reach + self.cell_size; // A node can stick out of its cell by up to a cell
+        let low = self.cell_of(&(point - glm::vec3(reach, reach, reach)));
+        let high = self.cell_of(&(point + glm::vec3(reach, reach, reach)));
+        let cells = (low.0..=high.0).flat_map(move |x| (low.1..=high.1).flat_map(move |y| (low.2..=high.2).map(move |z| (x, y, z))));
+        cells.filter_map(move |cell| self.cells.get(&cell)).flatten().chain(&self.big).copied()
+    }
+
+    // * Nodes whose bounds come within `radius` of the point
+    pub fn nodes_near(&self, point: &glm::Vec3, radius: f32) -> Vec<NodeId> {
+        self.candidates(point, radius)
+            .filter(|node_id| {
+                let bounds = &self.entries[node_id].bounds;
+                glm::distance(point, &bounds.center) <= radius + bounds.radius
+            })
+            .collect()
+    }
+
+    // * Nodes that might be on screen
+    pub fn visible_nodes(&self, view_projection_matrix: &glm::Mat4) -> HashSet<NodeId> {
+        let planes = frustum_planes(view_projection_matrix);
+        let half_cell = self.cell_size * 0.5;
+        // A cell's nodes are all within its loose bounds, the cell grown by a cell on every side
+        let loose_radius = 3.0 * half_cell * 3.0_f32.sqrt();
+        // The cells are tested on the thread pool
+        let mut visible: HashSet<NodeId> = self.cells.par_iter()
+            .filter(|(&(x, y, z), _)| {
+                let cell_center = glm::vec3(x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5) * self.cell_size;
+                sphere_in_frustum(&planes, &[cell_center.x, cell_center.y, cell_center.z, loose_radius])
+            })
+            .flat_map_iter(|(_, nodes)| nodes.iter().copied().filter(|node_id| self.in_frustum(&planes, node_id)))
+            .collect();
+        visible.extend(self.big.iter().filter(|node_id| self.in_frustum(&planes, node_id)));
+        visible
+    }
+
+    fn in_frustum(&self, planes: &[glm::Vec4; 6], node_id: &NodeId) -> bool {
+        let bounds = &self.entries[node_id].bounds;
+        sphere_in_frustum(planes, &[bounds.center.x, bounds.center.y, bounds.center.z, bounds.radius])
+    }
+}
@@ -0,0 +1,274 @@
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::io::Write as _;
+use std::time::Instant;
+
+use crate::gfx;
+use crate::gl_caps::GlCaps;
+
+// * Frame spike capture
+// Watches the frame times for hitches, frames that take much longer than the ones around them, and
+// writes down everything about such a frame to a log so it can be put down to something: how long
+// every part of it took on the CPU and on the GPU, the draw calls, the slow things that happened in
+// it (shaders compiling, buffers growing, textures going up, see gfx::note_event), the GL state it
+// ended in and how big the scene was.
+//
+// The frame is cut into sections with `mark`, every render pass is one (see RenderGraph::execute)
+// and main.rs marks the update before them. Each mark takes the CPU time and puts a GL timestamp
+// query in the command stream. The timestamps are only back a few frames later, so the last frames
+// are kept in a ring and a spike is written out once its GPU times are in. Without timer queries the
+// log only has the CPU times.
+//
+// A frame is a spike when it took `threshold` times the median of the frames before it, and at least
+// `min_spike_ms` (at a thousand frames per second a 3 ms frame isn't a hitch anyone notices).
+
+const HISTORY_FRAMES: usize = 120; // The median is over these
+const MIN_HISTORY: usize = 30;     // No spikes before there are this many, the first frames are all slow
+const QUERY_FRAMES: usize = 4;     // Frames in flight before their timestamps are read
+
+// What the render thread knows about the size of the scene, handed in at the end of the frame
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SceneStats {
+    pub draw_items    : usize, // In the snapshot
+    pub lights        : usize,
+    pub indexed_nodes : usize, // In the spatial index
+    pub texture_bytes : usize, // On the GPU, see AssetManager::texture_memory
+    pub window_size   : (u32, u32),
+}
+
+struct Section {
+    name       : &'static str,
+    start      : Instant,
+    cpu_ms     : f32,
+    gpu_ms     : Option<f32>,
+    draw_calls : u64,
+    query      : Option<u32>, // Timestamp at the start
+}
+
+// A frame while it is in flight
+#[derive(Default)]
+struct FrameRecord {
+    frame     : u64,
+    frame_ms  : f32,
+    median_ms : f32,
+    sections  : Vec<Section>,
+    end_query : Option<u32>, // Timestamp at the end of the last section
+    events    : Vec<String>,
+    gl_state  : String,
+    scene     : SceneStats,
+    is_spike  : bool,
+}
+
+pub struct SpikeCapture {
+    pub enabled      : bool,
+    pub threshold    : f32,    // Times the median
+    pub min_spike_ms : f32,
+    pub log_path     : String,
+    frame_times      : VecDeque<f32>,
+    last_frame_end   : Option<Instant>,
+    frames           : Vec<FrameRecord>, // Ring of the frames in flight
+    current          : usize,
+    frame_draw_calls : u64,              // gfx::draw_call_count when the open section started
+    free_queries     : Vec<u32>,
+    timer_queries    : bool,
+    spike_count      : u32,
+}
+
+impl SpikeCapture {
+    pub fn new(gl_caps: &GlCaps) -> SpikeCapture {
+        SpikeCapture {
+            enabled: true,
+            threshold: 3.0,
+            min_spike_ms: 8.0,
+            log_path: String::from("frame_spikes.log"),
+            frame_times: VecDeque::with_capacity(HISTORY_FRAMES),
+            last_frame_end: None,
+            frames: (0..QUERY_FRAMES).map(|_| FrameRecord::default()).collect(),
+            current: 0,
+            frame_draw_calls: gfx::draw_call_count(),
+            free_queries: vec![],
+            timer_queries: gl_caps.timer_queries,
+            spike_count: 0,
+        }
+    }
+
+    /// Start a new section of the frame, ending the one before. The first mark of a frame starts it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn mark(&mut self, name: &'static str) {
+        if !self.enabled {
+            return;
+        }
+        let now = Instant::now();
+        self.close_section(now);
+        let query = self.timestamp();
+        self.frames[self.current].sections.push(Section { name, start: now, cpu_ms: 0.0, gpu_ms: None, draw_calls: 0, query });
+    }
+
+    /// Call once at the end of every frame, after the buffers are swapped. Reads back the timestamps
+    /// of the frame from a few frames ago, and writes it to the log if it was a spike.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn end_frame(&mut self, frame: u64, scene: SceneStats) {
+        if !self.enabled {
+            gfx::take_events();
+            return;
+        }
+        let now = Instant::now();
+        self.close_section(now);
+        let end_query = self.timestamp();
+
+        let frame_ms = self.last_frame_end.map_or(0.0, |last| now.duration_since(last).as_secs_f32() * 1000.0);
+        self.last_frame_end = Some(now);
+        let median_ms = self.median();
+        let is_spike = self.frame_times.len() >= MIN_HISTORY && frame_ms > median_ms * self.threshold && frame_ms > self.min_spike_ms;
+        if self.frame_times.len() == HISTORY_FRAMES {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_ms);
+
+        let record = &mut self.frames[self.current];
+        record.frame = frame;
+        record.frame_ms = frame_ms;
+        record.median_ms = median_ms;
+        record.end_query = end_query;
+        record.events = gfx::take_events();
+        record.scene = scene;
+        record.is_spike = is_spike;
+        if is_spike {
+            record.gl_state = gl_state();
+            self.spike_count += 1;
+            println!("Frame {} took {:.1} ms, {:.1}x the median, written to {}", frame, frame_ms, frame_ms / median_ms.max(1e-3), self.log_path);
+        }
+
+        // The oldest frame in the ring goes next, its timestamps are in by now
+        self.current = (self.current + 1) % QUERY_FRAMES;
+        self.retire(self.current);
+    }
+
+    /// Read back and write out what is still in flight, and delete the queries.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&mut self) {
+        for offset in 1..=QUERY_FRAMES {
+            self.retire((self.current + offset) % QUERY_FRAMES);
+        }
+        if !self.free_queries.is_empty() {
+            gl::DeleteQueries(self.free_queries.len() as i32, self.free_queries.as_ptr());
+            self.free_queries.clear();
+        }
+        if self.spike_count > 0 {
+            println!("{} frame spikes were written to {}", self.spike_count, self.log_path);
+        }
+    }
+
+    fn median(&self) -> f32 {
+        if self.frame_times.is_empty() {
+            return 0.0;
+        }
+        let mut sorted: Vec<f32> = self.frame_times.iter().copied().collect();
+        sorted.sort_by(|a, b| a.total_cmp(b));
+        sorted[sorted.len() / 2]
+    }
+
+    fn close_section(&mut self, now: Instant) {
+        let draw_calls = gfx::draw_call_count();
+        if let Some(section) = self.frames[self.current].sections.last_mut() {
+            section.cpu_ms = now.duration_since(section.start).as_secs_f32() * 1000.0;
+            section.draw_calls = draw_calls - self.frame_draw_calls;
+        }
+        self.frame_draw_calls = draw_calls;
+    }
+
+    unsafe fn timestamp(&mut self) -> Option<u32> {
+        if !self.timer_queries {
+            return None;
+        }
+        let query = self.free_queries.pop().unwrap_or_else(|| {
+            let mut query = 0;
+            gl::GenQueries(1, &mut query);
+            query
+        });
+        gl::QueryCounter(query, gl::TIMESTAMP);
+        Some(query)
+    }
+
+    // Waits for the result if it isn't in yet
+    unsafe fn read_timestamp(query: u32) -> u64 {
+        let mut nanoseconds: u64 = 0;
+        gl::GetQueryObjectui64v(query, gl::QUERY_RESULT, &mut nanoseconds);
+        nanoseconds
+    }
+
+    // Done with the frame in `slot`: the GPU times of a spike are read and it is written out, the
+    // queries go back to be used again
+    unsafe fn retire(&mut self, slot: usize) {
+        let mut record = std::mem::take(&mut self.frames[slot]);
+        if record.is_spike {
+            let ends: Vec<Option<u32>> = record.sections.iter().skip(1).map(|section| section.query)
+                .chain(std::iter::once(record.end_query)).collect();
+            for (section, end) in record.sections.iter_mut().zip(ends) {
+                if let (Some(start), Some(end)) = (section.query, end) {
+                    let elapsed = Self::read_timestamp(end).saturating_sub(Self::read_timestamp(start));
+                    section.gpu_ms = Some(elapsed as f32 / 1.0e6);
+                }
+            }
+            self.write(&record);
+        }
+        self.free_queries.extend(record.sections.iter().filter_map(|section| section.query));
+        self.free_queries.extend(record.end_query);
+        // Keep the allocations of the sections for the next frame in this slot
+        record.sections.clear();
+        self.frames[slot].sections = record.sections;
+    }
+
+    fn write(&self, record: &FrameRecord) {
+        let mut text = String::new();
+        let _ = writeln!(text, "== Frame {}: {:.2} ms, median {:.2} ms ({:.1}x)",
+            record.frame, record.frame_ms, record.median_ms, record.frame_ms / record.median_ms.max(1e-3));
+        let _ = writeln!(text, "{:<24} {:>9} {:>9} {:>6}", "section", "cpu ms", "gpu ms", "draws");
+        for section in &record.sections {
+            let gpu_ms = section.gpu_ms.map_or(String::from("-"), |gpu_ms| format!("{:.3}", gpu_ms));
+            let _ = writeln!(text, "{:<24} {:>9.3} {:>9} {:>6}", section.name, section.cpu_ms, gpu_ms, section.draw_calls);
+        }
+        // Whatever isn't in a section: waiting on the driver before the first mark, mostly
+        let sectioned: f32 = record.sections.iter().map(|section| section.cpu_ms).sum();
+        let _ = writeln!(text, "{:<24} {:>9.3}", "(outside of sections)", (record.frame_ms - sectioned).max(0.0));
+        if record.events.is_empty() {
+            let _ = writeln!(text, "events: none");
+        } else {
+            let _ = writeln!(text, "events:");
+            for event in &record.events {
+                let _ = writeln!(text, "  {}", event);
+            }
+        }
+        let scene = &record.scene;
+        let _ = writeln!(text, "scene: {} draw items, {} lights, {} indexed nodes, {:.1} MB of textures, {}x{} window",
+            scene.draw_items, scene.lights, scene.indexed_nodes, scene.texture_bytes as f32 / (1024.0 * 1024.0),
+            scene.window_size.0, scene.window_size.1);
+        let _ = writeln!(text, "{}", record.gl_state);
+
+        let file = std::fs::OpenOptions::new().create(true).append(true).open(&self.log_path);
+        if let Err(error) = file.and_then(|mut file| file.write_all(text.as_bytes())) {
+            println!("Could not write the frame spike to {}: {}", self.log_path, error);
+        }
+    }
+}
+
+// The GL state at the end of the frame, what the render state cache thinks it is and what GL says
+unsafe fn gl_state() -> String {
+    let get = |name: gl::types::GLenum| {
+        let mut value = 0;
+        gl::GetIntegerv(name, &mut value);
+        value
+    };
+    let mut viewport = [0; 4];
+    gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+    let error = gl::GetError();
+    format!("gl state: {:?}, depth {:?}, program {}, framebuffer {}, vertex array {}, viewport {:?}, error 0x{:X}",
+        gfx::applied_render_state(), gfx::depth_mode(), get(gl::CURRENT_PROGRAM), get(gl::DRAW_FRAMEBUFFER_BINDING),
+        get(gl::VERTEX_ARRAY_BINDING), viewport, error)
+}
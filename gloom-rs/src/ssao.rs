@@ -0,0 +1,155 @@
+extern crate nalgebra_glm as glm;
+
+use rand::prelude::*;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::post::PostChain;
+use crate::settings::CameraProjection;
+use crate::util;
+use crate::viewport::Viewport;
+
+// * Screen space ambient occlusion
+// Darkens crevices and contact points by checking, for every pixel, how many points in a small
+// hemisphere above the surface are behind other geometry in the depth buffer. Runs on the depth of
+// the HDR target after the scene is drawn, gets blurred, and the tone mapping pass multiplies it in.
+
+const KERNEL_SIZE: usize = 16; // Must match KERNEL_SIZE in ssao.frag
+const NOISE_SIZE: i32 = 4;
+
+pub struct Ssao {
+    pub radius    : f32, // World units around each point that are checked
+    pub bias      : f32, // Avoids surfaces occluding themselves
+    pub strength  : f32,
+    kernel        : Vec<glm::Vec3>,
+    noise_texture : u32,
+    ao_target     : Framebuffer,
+    blur_target   : Framebuffer,
+    shader        : ShaderHandle,
+    blur_shader   : ShaderHandle,
+}
+
+impl Ssao {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, width: u32, height: u32) -> Ssao {
+        let shader = assets.load_shader(&["shaders/fullscreen.vert", "shaders/ssao.frag"]);
+        let blur_shader = assets.load_shader(&["shaders/fullscreen.vert", "shaders/ssao_blur.frag"]);
+
+        let mut rng = rand::thread_rng();
+
+        // Points in the +Z hemisphere, more of them close to the center
+        let kernel = (0..KERNEL_SIZE).map(|i| {
+            let sample = glm::normalize(&glm::vec3(
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(-1.0..1.0),
+                rng.gen_range(0.0..1.0),
+            )) * rng.gen_range(0.0..1.0);
+            let scale = i as f32 / KERNEL_SIZE as f32;
+            sample * glm::lerp_scalar(0.1, 1.0, scale * scale)
+        }).collect();
+
+        // Random rotations around the normal
+        let noise: Vec<f32> = (0..NOISE_SIZE * NOISE_SIZE)
+            .flat_map(|_| [rng.gen_range(-1.0..1.0), rng.gen_range(-1.0..1.0)])
+            .collect();
+        let mut noise_texture: u32 = 0;
+        gl::GenTextures(1, &mut noise_texture);
+        gl::BindTexture(gl::TEXTURE_2D, noise_texture);
+        gl::TexImage2D(gl::TEXTURE_2D, 0, gl::RG16F as i32, NOISE_SIZE, NOISE_SIZE, 0, gl::RG, gl::FLOAT, util::pointer_to_array(&noise));
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MIN_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_MAG_FILTER, gl::NEAREST as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_S, gl::REPEAT as i32);
+        gl::TexParameteri(gl::TEXTURE_2D, gl::TEXTURE_WRAP_T, gl::REPEAT as i32);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+
+        Ssao {
+            radius: 3.0,
+            bias: 0.05,
+            strength: 1.2,
+            kernel,
+            noise_texture,
+            ao_target: Framebuffer::with_color_format(width as i32, height as i32, gl::R8, gl::UNSIGNED_BYTE).with_label("ssao"),
+            blur_target: Framebuffer::with_color_format(width as i32, height as i32, gl::R8, gl::UNSIGNED_BYTE).with_label("ssao blur"),
+            shader,
+            blur_shader,
+        }
+    }
+
+    /// Compute the occlusion of every view drawn into the post chain's HDR target, with `projection`.
+    /// Returns the texture with the (blurred) result, 1 is not occluded at all.
+    /// `exclude` is a pixel rectangle left unoccluded, for overlays like the minimap.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn render(&mut self, assets: &AssetManager, post_chain: &PostChain, views: &[Viewport], projection: &CameraProjection, exclude: Option<(i32, i32, i32, i32)>) -> u32 {
+        let scene = &post_chain.hdr_target;
+        self.ao_target.resize(scene.width, scene.height);
+        self.blur_target.resize(scene.width, scene.height);
+        let texture_size = [scene.width as f32, scene.height as f32];
+
+        gfx::RenderState { depth_test: gfx::DepthTest::Off, ..Default::default() }.apply();
+
+        // * Occlusion, one view at a time since every view has its own projection
+        self.ao_target.bind();
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_int("depth_texture", 0);
+        shader.set_uniform_int("noise_texture", 1);
+        shader.set_uniform_float("radius", self.radius);
+        shader.set_uniform_float("bias", self.bias);
+        shader.set_uniform_float("strength", self.strength);
+        shader.set_uniform_vec2("texture_size", &texture_size);
+        shader.set_uniform_int("reversed_z", (gfx::depth_mode() == gfx::DepthMode::Reversed) as i32);
+        for (i, sample) in self.kernel.iter().enumerate() {
+            shader.set_uniform_vec3(&format!("kernel[{}]", i), sample.as_ref());
+        }
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, scene.depth_texture);
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, self.noise_texture);
+
+        for view in views {
+            let projection = projection.perspective(view.aspect_ratio());
+            shader.set_uniform_mat4("projection", &projection);
+            shader.set_uniform_mat4("inverse_projection", &glm::inverse(&projection));
+            shader.set_uniform_vec4("view_rect", &[view.x as f32, view.y as f32, view.width as f32, view.height as f32]);
+            gl::Viewport(view.x, view.y, view.width, view.height);
+            post_chain.draw_fullscreen_triangle();
+        }
+
+        // * Blur
+        self.blur_target.bind();
+        let blur_shader = assets.shader(self.blur_shader);
+        blur_shader.activate();
+        blur_shader.set_uniform_int("ao_texture", 0);
+        blur_shader.set_uniform_vec2("texture_size", &texture_size);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, self.ao_target.color_texture);
+        post_chain.draw_fullscreen_triangle();
+
+        if let Some((x, y, width, height)) = exclude {
+            gfx::set_scissor(Some((x, y, width, height)));
+            gl::ClearColor(1.0, 1.0, 1.0, 1.0);
+            gl::Clear(gl::COLOR_BUFFER_BIT);
+            gfx::set_scissor(None);
+        }
+
+        gl::ActiveTexture(gl::TEXTURE1);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gfx::RenderState::default().apply();
+
+        self.blur_target.color_texture
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteTextures(1, &self.noise_texture);
+        self.ao_target.delete();
+        self.blur_target.delete();
+    }
+}
@@ -0,0 +1,119 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::material::Material;
+use crate::math;
+use crate::mesh::Mesh;
+use crate::scene_graph::{NodeId, SceneNode};
+use crate::util;
+
+// * Static batching
+// Small props that never move cost a draw call each, however few triangles they have. Once the scene
+// is built, the batcher bakes their world transforms into their vertices and merges every prop with
+// the same material into one VAO, drawn by a single node under the scene root. The props stay in the
+// scene graph as they were, only without a VAO of their own, so scripts, triggers and the picking
+// buffer can still find them by ID: every batch remembers which range of its indices came from which
+// node (see PickingBuffer::pick).
+//
+// Whatever moves a batched node after the batch is built has no effect on what is drawn, so only
+// props that stay where they are belong in here. The merged VAOs have no texture coordinates, which
+// is fine for the vertex colored and flat materials the props use.
+
+// Meshes with more vertices than this are drawn on their own, merging them saves nothing worth the memory
+const MAX_VERTICES: usize = 4096;
+
+// Which indices of a batch came from which node
+#[derive(Clone, Copy, Debug)]
+pub struct BatchPart {
+    pub node_id     : NodeId,
+    pub first_index : i32,
+    pub index_count : i32,
+}
+
+pub struct StaticBatch {
+    pub node_id     : NodeId, // The node drawing the batch
+    pub vao_id      : u32,
+    pub index_count : i32,
+    pub parts       : Vec<BatchPart>,
+    pub mesh        : Mesh,   // The merged vertices, in world space
+}
+
+#[derive(Default)]
+struct Group {
+    material : Option<Rc<Material>>,
+    mesh     : Option<Mesh>,
+    parts    : Vec<BatchPart>,
+}
+
+#[derive(Default)]
+pub struct StaticBatcher {
+    groups : Vec<Group>,
+    lookup : HashMap<Option<String>, usize>, // Group of a material, by its name
+}
+
+impl StaticBatcher {
+    pub fn new() -> StaticBatcher {
+        StaticBatcher::default()
+    }
+
+    /// Merge `node`, drawing `mesh` at `world_matrix`, into the batch of its material. The node keeps
+    /// its place in the scene graph but no longer draws anything itself. False, leaving the node alone,
+    /// if the mesh is too big to be worth batching.
+    pub fn add(&mut self, node: &mut SceneNode, mesh: &Mesh, world_matrix: &glm::Mat4) -> bool {
+        if mesh.vertices.len() / 3 > MAX_VERTICES {
+            return false;
+        }
+        let name = node.material.as_ref().map(|material| material.name.clone());
+        let groups = &mut self.groups;
+        let group_index = *self.lookup.entry(name).or_insert_with(|| {
+            groups.push(Group { material: node.material.clone(), ..Group::default() });
+            groups.len() - 1
+        });
+        let group = &mut self.groups[group_index];
+        let merged = group.mesh.get_or_insert_with(|| Mesh {
+            vertices: vec![],
+            normals: vec![],
+            colors: vec![],
+            texcoords: vec![],
+            indices: vec![],
+            index_count: 0,
+        });
+
+        let first_vertex = (merged.vertices.len() / 3) as u32;
+        let (first_position, first_normal) = (merged.vertices.len(), merged.normals.len());
+        merged.vertices.extend_from_slice(&mesh.vertices);
+        merged.normals.extend_from_slice(&mesh.normals);
+        math::transform_points_in_place(&mut merged.vertices[first_position..], world_matrix);
+        math::transform_normals_in_place(&mut merged.normals[first_normal..], world_matrix);
+        merged.colors.extend_from_slice(&mesh.colors);
+        group.parts.push(BatchPart { node_id: node.id, first_index: merged.index_count, index_count: mesh.index_count });
+        merged.indices.extend(mesh.indices.iter().map(|index| first_vertex + index));
+        merged.index_count += mesh.index_count;
+
+        node.vao_id = 0;
+        true
+    }
+
+    // How many nodes went into batches
+    pub fn node_count(&self) -> usize {
+        self.groups.iter().map(|group| group.parts.len()).sum()
+    }
+
+    /// Upload every batch and add a node drawing it to `parent`, which should have no transformation
+    /// of its own, the vertices are in world space already.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn build(self, parent: &mut SceneNode) -> Vec<StaticBatch> {
+        self.groups.into_iter().filter_map(|group| {
+            let mesh = group.mesh?;
+            let (vao_id, _) = util::create_vao(&mesh.vertices, &mesh.indices, &mesh.colors, &mesh.normals);
+            let mut batch_node = SceneNode::from_vao(vao_id, mesh.index_count);
+            batch_node.material = group.material;
+            parent.add_child(&batch_node);
+            Some(StaticBatch { node_id: batch_node.id, vao_id, index_count: mesh.index_count, parts: group.parts, mesh })
+        }).collect()
+    }
+}
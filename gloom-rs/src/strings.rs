@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+
+// * Strings
+// Everything the HUD, the labels and the pause menu say comes out of a string table instead of being
+// written into the code, so the program can be translated without touching it. There is a TOML file
+// per language in resources/lang, named by its language code (en.toml, nb.toml), with the strings
+// in tables:
+//
+//     [menu]
+//     resume = "Resume"
+//
+// A string is looked up by its table and name, "menu.resume". Strings with numbers in them have
+// `{name}` placeholders for them, filled in by `format` with the numbers already formatted, so a
+// translation can move them around: "Helicopter {index}".
+//
+// English is the fallback: a string a translation doesn't have is taken from en.toml, and a string
+// that isn't there either shows as its key, so it is easy to spot. The language can be changed while
+// running, from the settings page of the pause menu, and is kept in the user settings.
+
+const LANGUAGE_DIRECTORY : &str = "resources/lang";
+pub const DEFAULT_LANGUAGE : &str = "en";
+
+pub struct StringTable {
+    language : String,
+    strings  : HashMap<String, String>,
+    fallback : HashMap<String, String>, // The default language
+}
+
+// The strings of a language file by their dotted keys, empty if it can't be read
+fn load_language(language: &str) -> HashMap<String, String> {
+    let path = format!("{}/{}.toml", LANGUAGE_DIRECTORY, language);
+    let source = match std::fs::read_to_string(&path) {
+        Ok(source) => source,
+        Err(error) => {
+            println!("Failed to read {}: {}", path, error);
+            return HashMap::new();
+        }
+    };
+    let table = match source.parse::<toml::Table>() {
+        Ok(table) => table,
+        Err(error) => {
+            println!("Failed to parse {}: {}", path, error);
+            return HashMap::new();
+        }
+    };
+    let mut strings = HashMap::new();
+    flatten(&path, "", &table, &mut strings);
+    strings
+}
+
+// The strings of `table` and the tables in it, with the names of the tables in front of their keys
+fn flatten(path: &str, prefix: &str, table: &toml::Table, strings: &mut HashMap<String, String>) {
+    for (name, value) in table {
+        let key = format!("{}{}", prefix, name);
+        match value {
+            toml::Value::String(text) => {
+                strings.insert(key, text.clone());
+            }
+            toml::Value::Table(inner) => flatten(path, &format!("{}.", key), inner, strings),
+            _ => println!("{}: '{}' is not a string", path, key),
+        }
+    }
+}
+
+impl StringTable {
+    pub fn load(language: &str) -> StringTable {
+        let fallback = load_language(DEFAULT_LANGUAGE);
+        let strings = if language == DEFAULT_LANGUAGE { fallback.clone() } else { load_language(language) };
+        println!("Language: {}", language);
+        StringTable { language: language.to_string(), strings, fallback }
+    }
+
+    // The language codes there are files for, in order
+    pub fn languages() -> Vec<String> {
+        let mut languages: Vec<String> = std::fs::read_dir(LANGUAGE_DIRECTORY)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "toml"))
+                    .filter_map(|path| path.file_stem().map(|stem| stem.to_string_lossy().into_owned()))
+                    .collect()
+            })
+            .unwrap_or_default();
+        languages.sort();
+        languages
+    }
+
+    pub fn language(&self) -> &str {
+        &self.language
+    }
+
+    pub fn set_language(&mut self, language: &str) {
+        self.strings = if language == DEFAULT_LANGUAGE { self.fallback.clone() } else { load_language(language) };
+        self.language = language.to_string();
+        println!("Language: {}", language);
+    }
+
+    // Switch to the language after this one, for the menu
+    pub fn next_language(&mut self) {
+        let languages = Self::languages();
+        let index = languages.iter().position(|language| *language == self.language).map_or(0, |index| index + 1);
+        if let Some(language) = languages.get(index % languages.len().max(1)) {
+            self.set_language(language);
+        }
+    }
+
+    pub fn get<'a>(&'a self, key: &'a str) -> &'a str {
+        self.strings.get(key)
+            .or_else(|| self.fallback.get(key))
+            .map_or(key, |text| text.as_str())
+    }
+
+    /// The string with its `{name}` placeholders replaced by the values of `arguments`.
+    pub fn format(&self, key: &str, arguments: &[(&str, &str)]) -> String {
+        let mut text = self.get(key).to_string();
+        for (name, value) in arguments {
+            text = text.replace(&format!("{{{}}}", name), value);
+        }
+        text
+    }
+}
@@ -0,0 +1,156 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::motion::MotionVectors;
+use crate::post::PostChain;
+use crate::viewport::Viewport;
+
+// * Temporal anti-aliasing
+// Every frame the projection is moved by a different fraction of a pixel (the jitter), so over a few
+// frames every pixel gets sampled at different spots inside it. The resolve blends each new frame into
+// a history of the frames before, which adds up to a supersampled image: thin specular highlights and
+// the edges of the particles stop crawling, which more MSAA samples never fixed.
+//
+// For the history to line up with the new frame it is reprojected with the motion vectors (see
+// motion.rs), which say where every pixel was on the screen last frame. Where the history still
+// doesn't fit (something came out from behind something else) it is clamped to the colors around the
+// pixel in the new frame, which is what keeps it from ghosting.
+//
+// Runs in HDR, right after the scene is drawn, so everything after it works on the smooth image.
+
+const JITTER_SAMPLES: usize = 8;
+
+pub struct Taa {
+    pub enabled     : bool,
+    pub blend       : f32, // How much of every new frame goes into the history
+    history_targets : [Framebuffer; 2],
+    current         : usize, // Which of the history targets has the last frame
+    frame           : usize,
+    history_valid   : bool,
+    resolve_shader  : ShaderHandle,
+}
+
+impl Taa {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, width: u32, height: u32) -> Taa {
+        Taa {
+            enabled: true,
+            blend: 0.1,
+            history_targets: [
+                Framebuffer::with_color_format(width as i32, height as i32, gl::RGBA16F, gl::FLOAT).with_label("taa history 0"),
+                Framebuffer::with_color_format(width as i32, height as i32, gl::RGBA16F, gl::FLOAT).with_label("taa history 1"),
+            ],
+            current: 0,
+            frame: 0,
+            history_valid: false,
+            resolve_shader: assets.load_shader(&["shaders/fullscreen.vert", "shaders/taa_resolve.frag"]),
+        }
+    }
+
+    // Start over without a history, after a cut or after it was off for a while
+    pub fn reset(&mut self) {
+        self.history_valid = false;
+    }
+
+    // This frame's jitter for `view`, to go in front of its view projection
+    pub fn jitter(&self, view: &Viewport) -> glm::Mat4 {
+        if !self.enabled {
+            return glm::identity();
+        }
+        // Halton (2, 3), spreads the samples evenly over the pixel
+        let index = self.frame % JITTER_SAMPLES + 1;
+        let offset = glm::vec2(halton(index, 2) - 0.5, halton(index, 3) - 0.5);
+        // From pixels to normalized device coordinates, which go from -1 to 1
+        glm::translation(&glm::vec3(2.0 * offset.x / view.width.max(1) as f32, 2.0 * offset.y / view.height.max(1) as f32, 0.0))
+    }
+
+    /// Blend the scene drawn into the post chain's HDR target into the history, and put the result
+    /// back into the HDR target. `views` are the views drawn this frame, with the (jittered) view
+    /// projection they were drawn with, and `motion` has their motion vectors.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn resolve(&mut self, assets: &AssetManager, post_chain: &PostChain, motion: &MotionVectors, views: &[(Viewport, glm::Mat4)]) {
+        let scene = &post_chain.hdr_target;
+
+        // A history made for another window size or other views doesn't fit
+        if self.history_targets[0].width != scene.width || self.history_targets[0].height != scene.height || !motion.views_unchanged() {
+            self.history_valid = false;
+        }
+        for target in self.history_targets.iter_mut() {
+            target.resize(scene.width, scene.height);
+        }
+
+        gfx::RenderState { depth_test: gfx::DepthTest::Off, ..Default::default() }.apply();
+
+        let previous = self.current;
+        self.current = 1 - self.current;
+        self.history_targets[self.current].bind();
+
+        let shader = assets.shader(self.resolve_shader);
+        shader.activate();
+        shader.set_uniform_int("scene_texture", 0);
+        shader.set_uniform_int("depth_texture", 1);
+        shader.set_uniform_int("motion_texture", 2);
+        shader.set_uniform_int("history_texture", 3);
+        shader.set_uniform_int("history_valid", self.history_valid as i32);
+        shader.set_uniform_float("blend", self.blend);
+        shader.set_uniform_int("reversed_z", (gfx::depth_mode() == gfx::DepthMode::Reversed) as i32);
+        for (unit, texture) in [scene.color_texture, scene.depth_texture, motion.texture, self.history_targets[previous].color_texture].iter().enumerate() {
+            gl::ActiveTexture(gl::TEXTURE0 + unit as u32);
+            gl::BindTexture(gl::TEXTURE_2D, *texture);
+        }
+
+        for (i, (view, view_projection)) in views.iter().enumerate() {
+            shader.set_uniform_mat4("inverse_view_projection", &glm::inverse(view_projection));
+            shader.set_uniform_mat4("previous_view_projection", &motion.previous_view_projection(i));
+            shader.set_uniform_vec4("view_pixels", &[view.x as f32, view.y as f32, view.width as f32, view.height as f32]);
+            shader.set_uniform_vec4("view_rect", &[
+                view.x as f32 / scene.width as f32,
+                view.y as f32 / scene.height as f32,
+                view.width as f32 / scene.width as f32,
+                view.height as f32 / scene.height as f32,
+            ]);
+            gl::Viewport(view.x, view.y, view.width, view.height);
+            post_chain.draw_fullscreen_triangle();
+        }
+
+        for unit in (0..4).rev() {
+            gl::ActiveTexture(gl::TEXTURE0 + unit);
+            gl::BindTexture(gl::TEXTURE_2D, 0);
+        }
+
+        // The resolved frame goes back into the HDR target for the rest of the chain
+        gl::BindFramebuffer(gl::READ_FRAMEBUFFER, self.history_targets[self.current].fbo_id);
+        gl::BindFramebuffer(gl::DRAW_FRAMEBUFFER, scene.fbo_id);
+        gl::BlitFramebuffer(0, 0, scene.width, scene.height, 0, 0, scene.width, scene.height, gl::COLOR_BUFFER_BIT, gl::NEAREST);
+        scene.bind();
+        gfx::RenderState::default().apply();
+
+        self.history_valid = true;
+        self.frame += 1;
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        for target in &self.history_targets {
+            target.delete();
+        }
+    }
+}
+
+// The `index`th number of the Halton sequence in `base`, between 0 and 1
+fn halton(mut index: usize, base: usize) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
@@ -0,0 +1,285 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::color;
+use crate::framebuffer::Framebuffer;
+use crate::gfx;
+use crate::material::Material;
+use crate::mesh::Mesh;
+use crate::shader::Shader;
+use crate::util;
+
+// * Tessellated terrain
+// A GPU side alternative to drawing the full terrain mesh: a coarse grid of flat patches is split up
+// by the tessellation shaders, finer the closer it is to the camera, and the new vertices are moved
+// up to the height of the terrain. The heights come from a height map baked once from the terrain
+// mesh (drawn from straight above), with a bit of noise on top so there is detail up close that
+// the mesh itself doesn't have.
+// The lighting comes from the usual fragment shaders, so there is one program for each of them.
+
+const HEIGHT_MAP_SIZE: i32 = 1024;
+const PATCHES_PER_SIDE: u32 = 64;
+
+pub struct TessellatedTerrain {
+    pub enabled                : bool,
+    pub tessellation_levels    : (f32, f32), // (closest, farthest)
+    pub tessellation_distances : (f32, f32), // Full detail up to the first, the lowest from the second
+    pub detail_amplitude       : f32,
+    pub detail_scale           : f32,
+    color                      : [f32; 4],
+    bounds_min                 : glm::Vec3,
+    bounds_size                : glm::Vec3,
+    height_map                 : Framebuffer, // R32F, the height of the terrain at each (x, z)
+    patch_vao_id               : u32,
+    patch_count                : i32,
+    bake_shader                : ShaderHandle,
+    simple_shader              : ShaderHandle,
+    pbr_shader                 : ShaderHandle,
+}
+
+impl TessellatedTerrain {
+    /// Bake the height map from `terrain` (already uploaded to `vao_id`) and lay out the patches over it.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, terrain: &Mesh, vao_id: u32) -> TessellatedTerrain {
+        let mut tessellated_terrain = TessellatedTerrain {
+            enabled: false,
+            tessellation_levels: (32.0, 1.0),
+            tessellation_distances: (40.0, 800.0),
+            detail_amplitude: 0.6,
+            detail_scale: 3.0,
+            color: {
+                // The vertex colors of the mesh are decoded when they are uploaded, this one has to be too
+                let [r, g, b] = color::decode_srgb([terrain.colors[0], terrain.colors[1], terrain.colors[2]]);
+                [r, g, b, terrain.colors[3]]
+            },
+            bounds_min: glm::zero(),
+            bounds_size: glm::vec3(1.0, 1.0, 1.0),
+            height_map: Framebuffer::with_color_format(HEIGHT_MAP_SIZE, HEIGHT_MAP_SIZE, gl::R32F, gl::FLOAT).with_label("terrain height map"),
+            patch_vao_id: 0,
+            patch_count: 0,
+            bake_shader: assets.load_shader(&["shaders/height_bake.vert", "shaders/height_bake.frag"]),
+            simple_shader: assets.load_shader(&["shaders/terrain.vert", "shaders/terrain.tcs", "shaders/terrain.tes", "shaders/simple.frag"]),
+            pbr_shader: assets.load_shader(&["shaders/terrain.vert", "shaders/terrain.tcs", "shaders/terrain.tes", "shaders/pbr.frag"]),
+        };
+        tessellated_terrain.bake(assets, terrain, vao_id);
+        tessellated_terrain
+    }
+
+    /// Bake the height map again, for when the terrain mesh changed.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn bake(&mut self, assets: &AssetManager, terrain: &Mesh, vao_id: u32) {
+        let mut bounds_min = glm::vec3(f32::MAX, f32::MAX, f32::MAX);
+        let mut bounds_max = glm::vec3(f32::MIN, f32::MIN, f32::MIN);
+        for vertex in terrain.vertices.chunks(3) {
+            let vertex = glm::vec3(vertex[0], vertex[1], vertex[2]);
+            bounds_min = glm::min2(&bounds_min, &vertex);
+            bounds_max = glm::max2(&bounds_max, &vertex);
+        }
+        self.bounds_min = bounds_min - glm::vec3(0.0, 1.0, 0.0); // Room above and below so nothing is clipped
+        self.bounds_size = bounds_max - bounds_min + glm::vec3(0.0, 2.0, 0.0);
+
+        let mut viewport = [0i32; 4];
+        gl::GetIntegerv(gl::VIEWPORT, viewport.as_mut_ptr());
+
+        self.height_map.bind();
+        gl::ClearColor(bounds_min.y, 0.0, 0.0, 1.0); // Outside the mesh is as low as the terrain goes
+        gl::Clear(gl::COLOR_BUFFER_BIT | gl::DEPTH_BUFFER_BIT);
+        gfx::RenderState { cull: gfx::CullMode::Off, ..Default::default() }.apply();
+
+        let shader = assets.shader(self.bake_shader);
+        shader.activate();
+        shader.set_uniform_vec3("bounds_min", self.bounds_min.as_ref());
+        shader.set_uniform_vec3("bounds_size", self.bounds_size.as_ref());
+        gl::BindVertexArray(vao_id);
+        gfx::count_draw_call();
+        gl::DrawElements(gl::TRIANGLES, terrain.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        gl::BindVertexArray(0);
+
+        gfx::RenderState::default().apply();
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+        gl::Viewport(viewport[0], viewport[1], viewport[2], viewport[3]);
+
+        self.create_patches();
+    }
+
+    // The flat grid the tessellation starts from, four corners per patch
+    unsafe fn create_patches(&mut self) {
+        if self.patch_vao_id != 0 {
+            util::delete_vao(self.patch_vao_id);
+        }
+
+        let patch_size = glm::vec2(self.bounds_size.x, self.bounds_size.z) / PATCHES_PER_SIDE as f32;
+        let mut vertices: Vec<f32> = Vec::with_capacity((PATCHES_PER_SIDE * PATCHES_PER_SIDE * 4 * 3) as usize);
+        for z in 0..PATCHES_PER_SIDE {
+            for x in 0..PATCHES_PER_SIDE {
+                let x0 = self.bounds_min.x + x as f32 * patch_size.x;
+                let z0 = self.bounds_min.z + z as f32 * patch_size.y;
+                let (x1, z1) = (x0 + patch_size.x, z0 + patch_size.y);
+                vertices.extend_from_slice(&[x0, 0.0, z0, x1, 0.0, z0, x1, 0.0, z1, x0, 0.0, z1]);
+            }
+        }
+
+        let mut vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut vao_id);
+        gl::BindVertexArray(vao_id);
+
+        let mut vbo_id: u32 = 0;
+        gl::GenBuffers(1, &mut vbo_id);
+        gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id);
+        gl::BufferData(gl::ARRAY_BUFFER, util::byte_size_of_array(&vertices), util::pointer_to_array(&vertices), gl::STATIC_DRAW);
+        gl::VertexAttribPointer(0, 3, gl::FLOAT, gl::FALSE, 0, std::ptr::null());
+        gl::EnableVertexAttribArray(0);
+
+        gl::BindVertexArray(0);
+
+        self.patch_vao_id = vao_id;
+        self.patch_count = (PATCHES_PER_SIDE * PATCHES_PER_SIDE) as i32;
+    }
+
+    /// The program to draw with, for the lighting uniforms that are set once per frame.
+    pub fn shader<'a>(&self, assets: &'a AssetManager, pbr: bool) -> &'a Shader {
+        assets.shader(if pbr { self.pbr_shader } else { self.simple_shader })
+    }
+
+    /// Draw the terrain seen from `camera_position`. `clip_plane` is used while GL_CLIP_DISTANCE0 is on.
+    /// Leaves the terrain program active.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, pbr: bool, view_projection_matrix: &glm::Mat4, camera_position: glm::Vec3, clip_plane: [f32; 4]) {
+        let shader = self.shader(assets, pbr);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", view_projection_matrix);
+        shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+        shader.set_uniform_vec4("clip_plane", &clip_plane);
+        shader.set_uniform_vec4("terrain_color", &self.color);
+        shader.set_uniform_vec2("tessellation_levels", &[self.tessellation_levels.0, self.tessellation_levels.1]);
+        shader.set_uniform_vec2("tessellation_distances", &[self.tessellation_distances.0, self.tessellation_distances.1]);
+        shader.set_uniform_vec3("bounds_min", self.bounds_min.as_ref());
+        shader.set_uniform_vec3("bounds_size", self.bounds_size.as_ref());
+        shader.set_uniform_float("detail_amplitude", self.detail_amplitude);
+        shader.set_uniform_float("detail_scale", self.detail_scale);
+        if pbr {
+            Material::default().bind(shader);
+        }
+
+        // Out of the way of the material textures
+        let height_map_unit = 6;
+        gl::ActiveTexture(gl::TEXTURE0 + height_map_unit);
+        gl::BindTexture(gl::TEXTURE_2D, self.height_map.color_texture);
+        shader.set_uniform_int("height_map", height_map_unit as i32);
+
+        gl::PatchParameteri(gl::PATCH_VERTICES, 4);
+        gl::BindVertexArray(self.patch_vao_id);
+        gfx::count_draw_call();
+        gl::DrawArrays(gl::PATCHES, 0, self.patch_count * 4);
+        gl::BindVertexArray(0);
+
+        gl::BindTexture(gl::TEXTURE_2D, 0);
+        gl::ActiveTexture(gl::TEXTURE0);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        self.height_map.delete();
+        util::delete_vao(self.patch_vao_id);
+    }
+}
+
+// * Deformable terrain
+// The terrain mesh kept on the CPU next to its VAO, so it can change while the program runs. deform
+// pushes the vertices in a circle up or down, works the normals of every triangle around them out
+// again, and uploads only the changed part of the position and normal buffers with glBufferSubData.
+// The vertices of a circle aren't next to each other in the buffers (the OBJ isn't laid out as a
+// grid), so the part uploaded runs from the first changed vertex to the last, which is still far
+// less than all of them for a small circle.
+//
+// What is made from the mesh (the BVH, the bounds in the spatial index, the height map of the
+// tessellated terrain, the minimap) is up to the caller to make again, like after a hot reload.
+
+pub struct Terrain {
+    pub mesh         : Mesh,
+    pub vao_id       : u32,
+    vertex_triangles : Vec<Vec<u32>>, // The triangles every vertex is a corner of
+}
+
+impl Terrain {
+    /// `mesh` has to be what is in `vao_id`.
+    pub fn new(mesh: Mesh, vao_id: u32) -> Terrain {
+        let mut vertex_triangles = vec![vec![]; mesh.vertices.len() / 3];
+        for (triangle, corners) in mesh.indices.chunks_exact(3).enumerate() {
+            for &corner in corners {
+                vertex_triangles[corner as usize].push(triangle as u32);
+            }
+        }
+        Terrain { mesh, vao_id, vertex_triangles }
+    }
+
+    /// Moves the terrain within `radius` of `center` (across, the height of the center doesn't matter)
+    /// up by `delta_height`, or down if it is negative, fading out smoothly towards the edge. False if
+    /// there was no terrain there.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn deform(&mut self, center: glm::Vec3, radius: f32, delta_height: f32) -> bool {
+        let center = glm::vec2(center.x, center.z);
+        let mut moved = vec![];
+        for (index, vertex) in self.mesh.vertices.chunks_exact_mut(3).enumerate() {
+            let distance = glm::distance(&glm::vec2(vertex[0], vertex[2]), &center);
+            if distance < radius {
+                let falloff = 1.0 - (distance / radius).powi(2);
+                vertex[1] += delta_height * falloff * falloff;
+                moved.push(index);
+            }
+        }
+        if moved.is_empty() {
+            return false;
+        }
+
+        // Every corner of a triangle that has a moved corner gets a new normal
+        let mut changed: Vec<usize> = moved.iter()
+            .flat_map(|&vertex| self.vertex_triangles[vertex].iter())
+            .flat_map(|&triangle| self.mesh.indices[3 * triangle as usize..3 * triangle as usize + 3].iter().map(|&corner| corner as usize))
+            .collect();
+        changed.sort_unstable();
+        changed.dedup();
+        for &vertex in &changed {
+            let normal = self.vertex_normal(vertex);
+            self.mesh.normals[3 * vertex..3 * vertex + 3].copy_from_slice(normal.as_slice());
+        }
+
+        let (first, last) = (changed[0], changed[changed.len() - 1]);
+        if let Some(positions) = util::vertex_buffer(self.vao_id, 0) {
+            positions.update_range(3 * first, &self.mesh.vertices[3 * first..3 * (last + 1)]);
+        }
+        if let Some(normals) = util::vertex_buffer(self.vao_id, 2) {
+            normals.update_range(3 * first, &self.mesh.normals[3 * first..3 * (last + 1)]);
+        }
+        true
+    }
+
+    fn position(&self, vertex: usize) -> glm::Vec3 {
+        glm::make_vec3(&self.mesh.vertices[3 * vertex..3 * vertex + 3])
+    }
+
+    // The normals of the triangles around the vertex, the bigger ones counting more. On the same side
+    // as the normal it had, whichever way the triangles are wound.
+    fn vertex_normal(&self, vertex: usize) -> glm::Vec3 {
+        let sum = self.vertex_triangles[vertex].iter().fold(glm::Vec3::zeros(), |sum, &triangle| {
+            let corners = &self.mesh.indices[3 * triangle as usize..3 * triangle as usize + 3];
+            let [a, b, c] = [0, 1, 2].map(|i| self.position(corners[i] as usize));
+            sum + glm::cross(&(b - a), &(c - a))
+        });
+        let old_normal = glm::make_vec3(&self.mesh.normals[3 * vertex..3 * vertex + 3]);
+        if glm::length(&sum) == 0.0 {
+            return old_normal;
+        }
+        let normal = glm::normalize(&sum);
+        if glm::dot(&normal, &old_normal) < 0.0 { -normal } else { normal }
+    }
+}
@@ -0,0 +1,360 @@
+use std::collections::HashMap;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::atlas::{AtlasBuilder, TextureAtlas};
+use crate::gfx;
+use crate::viewport::Viewport;
+
+// * Bitmap text
+// A small built-in font for text drawn over the scene, so there is no font file to load: 5x7 pixel
+// glyphs of the capital letters with the Norwegian Æ, Ø and Å, the digits and some punctuation (lower
+// case is drawn as upper case, anything else as '?'). Every glyph goes into the overlay atlas as a sprite of its own, with a dark
+// outline around it so it stays readable over both the sky and the lit terrain.
+//
+// Sizes are in cells, the pixels of the font. A glyph is 5 cells wide and 7 high, the next one starts
+// 6 cells further on and the next line 9 cells further down. Its sprite has a cell of outline on every
+// side, so it is 7x9 cells and starts a cell before the glyph does.
+//
+// The text is drawn through a SpriteBatch, which takes quads of the overlay atlas placed in pixels
+// and draws them all in one call on top of a view.
+
+pub const GLYPH_WIDTH   : u32 = 5;
+pub const GLYPH_HEIGHT  : u32 = 7;
+pub const ADVANCE       : u32 = 6; // Cells from the start of one glyph to the next
+pub const LINE_HEIGHT   : u32 = 9; // Cells from one line to the next
+pub const OUTLINE       : u32 = 1; // Cells of outline around every glyph sprite
+pub const SPRITE_WIDTH  : u32 = GLYPH_WIDTH + 2 * OUTLINE;
+pub const SPRITE_HEIGHT : u32 = GLYPH_HEIGHT + 2 * OUTLINE;
+const SPRITE_SCALE      : u32 = 2; // Sprite pixels per cell
+
+// The rows of every glyph from the top, the leftmost cell in bit 4
+const GLYPHS: &[(char, [u8; 7])] = &[
+    ('A', [0x0E, 0x11, 0x11, 0x11, 0x1F, 0x11, 0x11]),
+    ('B', [0x1E, 0x11, 0x11, 0x1E, 0x11, 0x11, 0x1E]),
+    ('C', [0x0E, 0x11, 0x10, 0x10, 0x10, 0x11, 0x0E]),
+    ('D', [0x1C, 0x12, 0x11, 0x11, 0x11, 0x12, 0x1C]),
+    ('E', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x1F]),
+    ('F', [0x1F, 0x10, 0x10, 0x1E, 0x10, 0x10, 0x10]),
+    ('G', [0x0E, 0x11, 0x10, 0x17, 0x11, 0x11, 0x0F]),
+    ('H', [0x11, 0x11, 0x11, 0x1F, 0x11, 0x11, 0x11]),
+    ('I', [0x0E, 0x04, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('J', [0x07, 0x02, 0x02, 0x02, 0x02, 0x12, 0x0C]),
+    ('K', [0x11, 0x12, 0x14, 0x18, 0x14, 0x12, 0x11]),
+    ('L', [0x10, 0x10, 0x10, 0x10, 0x10, 0x10, 0x1F]),
+    ('M', [0x11, 0x1B, 0x15, 0x15, 0x11, 0x11, 0x11]),
+    ('N', [0x11, 0x11, 0x19, 0x15, 0x13, 0x11, 0x11]),
+    ('O', [0x0E, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('P', [0x1E, 0x11, 0x11, 0x1E, 0x10, 0x10, 0x10]),
+    ('Q', [0x0E, 0x11, 0x11, 0x11, 0x15, 0x12, 0x0D]),
+    ('R', [0x1E, 0x11, 0x11, 0x1E, 0x14, 0x12, 0x11]),
+    ('S', [0x0F, 0x10, 0x10, 0x0E, 0x01, 0x01, 0x1E]),
+    ('T', [0x1F, 0x04, 0x04, 0x04, 0x04, 0x04, 0x04]),
+    ('U', [0x11, 0x11, 0x11, 0x11, 0x11, 0x11, 0x0E]),
+    ('V', [0x11, 0x11, 0x11, 0x11, 0x11, 0x0A, 0x04]),
+    ('W', [0x11, 0x11, 0x11, 0x15, 0x15, 0x15, 0x0A]),
+    ('X', [0x11, 0x11, 0x0A, 0x04, 0x0A, 0x11, 0x11]),
+    ('Y', [0x11, 0x11, 0x11, 0x0A, 0x04, 0x04, 0x04]),
+    ('Z', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x10, 0x1F]),
+    ('Æ', [0x0F, 0x14, 0x14, 0x1F, 0x14, 0x14, 0x17]),
+    ('Ø', [0x01, 0x0E, 0x13, 0x15, 0x19, 0x0E, 0x10]),
+    ('Å', [0x04, 0x0A, 0x04, 0x0E, 0x11, 0x1F, 0x11]),
+    ('0', [0x0E, 0x11, 0x13, 0x15, 0x19, 0x11, 0x0E]),
+    ('1', [0x04, 0x0C, 0x04, 0x04, 0x04, 0x04, 0x0E]),
+    ('2', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x08, 0x1F]),
+    ('3', [0x1F, 0x02, 0x04, 0x02, 0x01, 0x11, 0x0E]),
+    ('4', [0x02, 0x06, 0x0A, 0x12, 0x1F, 0x02, 0x02]),
+    ('5', [0x1F, 0x10, 0x1E, 0x01, 0x01, 0x11, 0x0E]),
+    ('6', [0x06, 0x08, 0x10, 0x1E, 0x11, 0x11, 0x0E]),
+    ('7', [0x1F, 0x01, 0x02, 0x04, 0x08, 0x08, 0x08]),
+    ('8', [0x0E, 0x11, 0x11, 0x0E, 0x11, 0x11, 0x0E]),
+    ('9', [0x0E, 0x11, 0x11, 0x0F, 0x01, 0x02, 0x0C]),
+    ('.', [0x00, 0x00, 0x00, 0x00, 0x00, 0x0C, 0x0C]),
+    (',', [0x00, 0x00, 0x00, 0x00, 0x0C, 0x04, 0x08]),
+    (':', [0x00, 0x0C, 0x0C, 0x00, 0x0C, 0x0C, 0x00]),
+    ('-', [0x00, 0x00, 0x00, 0x1F, 0x00, 0x00, 0x00]),
+    ('+', [0x00, 0x04, 0x04, 0x1F, 0x04, 0x04, 0x00]),
+    ('=', [0x00, 0x00, 0x1F, 0x00, 0x1F, 0x00, 0x00]),
+    ('/', [0x00, 0x01, 0x02, 0x04, 0x08, 0x10, 0x00]),
+    ('%', [0x18, 0x19, 0x02, 0x04, 0x08, 0x13, 0x03]),
+    ('(', [0x02, 0x04, 0x08, 0x08, 0x08, 0x04, 0x02]),
+    (')', [0x08, 0x04, 0x02, 0x02, 0x02, 0x04, 0x08]),
+    ('#', [0x0A, 0x0A, 0x1F, 0x0A, 0x1F, 0x0A, 0x0A]),
+    ('!', [0x04, 0x04, 0x04, 0x04, 0x04, 0x00, 0x04]),
+    ('?', [0x0E, 0x11, 0x01, 0x02, 0x04, 0x00, 0x04]),
+    ('\'', [0x0C, 0x04, 0x08, 0x00, 0x00, 0x00, 0x00]),
+    ('_', [0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F]),
+];
+
+fn sprite_name(glyph: char) -> String {
+    format!("glyph_{:x}", glyph as u32)
+}
+
+// How the lines of a text line up with each other
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TextAlign {
+    Left,
+    Center,
+    Right,
+}
+
+// A glyph of laid out text: where its sprite goes, in cells from the top left corner of the text with
+// y down, and where the sprite is in the atlas
+#[derive(Clone, Copy, Debug)]
+pub struct PlacedGlyph {
+    pub x       : f32,
+    pub y       : f32,
+    pub uv_rect : [f32; 4],
+}
+
+pub struct BitmapFont {
+    glyphs : HashMap<char, [f32; 4]>, // Where the sprite of every glyph is in the atlas
+}
+
+impl BitmapFont {
+    // A sprite for every glyph, white with a black outline
+    pub fn add_sprites(atlas: &mut AtlasBuilder) {
+        for (glyph, rows) in GLYPHS {
+            // Cells of the sprite from the top left, outside of the glyph is empty
+            let ink = |x: i32, y: i32| {
+                let (x, y) = (x - OUTLINE as i32, y - OUTLINE as i32);
+                (0..GLYPH_WIDTH as i32).contains(&x) && (0..GLYPH_HEIGHT as i32).contains(&y)
+                    && rows[y as usize] & (1 << (GLYPH_WIDTH as i32 - 1 - x)) != 0
+            };
+            let (width, height) = (SPRITE_WIDTH * SPRITE_SCALE, SPRITE_HEIGHT * SPRITE_SCALE);
+            let mut pixels = Vec::with_capacity((width * height * 4) as usize);
+            // The first row of the image is at the bottom
+            for row in (0..height).rev() {
+                for column in 0..width {
+                    let (x, y) = ((column / SPRITE_SCALE) as i32, (row / SPRITE_SCALE) as i32);
+                    let outline = (-1..=1).any(|dy| (-1..=1).any(|dx| ink(x + dx, y + dy)));
+                    pixels.extend_from_slice(match (ink(x, y), outline) {
+                        (true, _)      => &[255, 255, 255, 255],
+                        (false, true)  => &[0, 0, 0, 255],
+                        (false, false) => &[0, 0, 0, 0],
+                    });
+                }
+            }
+            atlas.add_pixels(&sprite_name(*glyph), width, height, pixels);
+        }
+    }
+
+    /// `atlas` has to have the sprites from `add_sprites` in it.
+    pub fn new(atlas: &TextureAtlas) -> BitmapFont {
+        BitmapFont {
+            glyphs: GLYPHS.iter().map(|(glyph, _)| (*glyph, atlas.uv_rect(&sprite_name(*glyph)))).collect(),
+        }
+    }
+
+    // The width and height of `text` in cells, lines split at '\n'
+    pub fn measure(text: &str) -> (u32, u32) {
+        let columns = text.lines().map(|line| line.chars().count() as u32).max().unwrap_or(0);
+        let lines = text.lines().count() as u32;
+        let width = (columns * ADVANCE).saturating_sub(ADVANCE - GLYPH_WIDTH);
+        let height = (lines * LINE_HEIGHT).saturating_sub(LINE_HEIGHT - GLYPH_HEIGHT);
+        (width, height)
+    }
+
+    /// Lay out `text` line by line, the lines lined up on the widest one by `align`. Spaces take room
+    /// but have no sprite.
+    pub fn layout(&self, text: &str, align: TextAlign) -> Vec<PlacedGlyph> {
+        let (width, _) = Self::measure(text);
+        let mut placed = vec![];
+        for (line_index, line) in text.lines().enumerate() {
+            let (line_width, _) = Self::measure(line);
+            let line_start = match align {
+                TextAlign::Left   => 0,
+                TextAlign::Center => (width - line_width) / 2,
+                TextAlign::Right  => width - line_width,
+            };
+            for (column, glyph) in line.chars().enumerate() {
+                if glyph == ' ' {
+                    continue;
+                }
+                let glyph = glyph.to_uppercase().next().unwrap_or(glyph);
+                let uv_rect = self.glyphs.get(&glyph).or_else(|| self.glyphs.get(&'?')).copied().unwrap_or_default();
+                placed.push(PlacedGlyph {
+                    x: (line_start + column as u32 * ADVANCE) as f32 - OUTLINE as f32,
+                    y: (line_index as u32 * LINE_HEIGHT) as f32 - OUTLINE as f32,
+                    uv_rect,
+                });
+            }
+        }
+        placed
+    }
+}
+
+
+// * Sprite batches
+// Quads of the overlay atlas placed on a view in pixels, collected over a frame and drawn in one call.
+// Nothing is depth tested, the quads go over whatever is there in the order they were added.
+
+const MAX_QUADS         : usize = 2048; // Per draw, the rest aren't added
+const VERTICES_PER_QUAD : usize = 6;    // Two triangles
+
+pub struct SpriteBatch {
+    shader        : ShaderHandle,
+    atlas_texture : u32,         // Owned by the atlas
+    vao_id        : u32,
+    positions     : gfx::Buffer, // Pixels from the lower left corner of the view, 2 per vertex
+    texcoords     : gfx::Buffer, // 2 per vertex
+    colors        : gfx::Buffer, // 4 per vertex
+    // What was added since the last draw
+    position_data : Vec<f32>,
+    texcoord_data : Vec<f32>,
+    color_data    : Vec<f32>,
+}
+
+impl SpriteBatch {
+    /// Draws sprites of `atlas`.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, atlas: &TextureAtlas) -> SpriteBatch {
+        let shader = assets.load_shader(&["shaders/sprite_batch.vert", "shaders/sprite_batch.frag"]);
+
+        let mut vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut vao_id);
+        gl::BindVertexArray(vao_id);
+        let vertex_count = MAX_QUADS * VERTICES_PER_QUAD;
+        let positions = gfx::Buffer::new(gfx::BufferKind::Vertex, &vec![0.0f32; vertex_count * 2]);
+        positions.vertex_attribute(0, 2);
+        let texcoords = gfx::Buffer::new(gfx::BufferKind::Vertex, &vec![0.0f32; vertex_count * 2]);
+        texcoords.vertex_attribute(1, 2);
+        let colors = gfx::Buffer::new(gfx::BufferKind::Vertex, &vec![0.0f32; vertex_count * 4]);
+        colors.vertex_attribute(2, 4);
+        gl::BindVertexArray(0);
+        gfx::label_object(gfx::ObjectKind::VertexArray, vao_id, "sprite batch VAO");
+        positions.label("sprite batch positions");
+        texcoords.label("sprite batch texcoords");
+        colors.label("sprite batch colors");
+
+        SpriteBatch {
+            shader,
+            atlas_texture: atlas.texture.id,
+            vao_id,
+            positions,
+            texcoords,
+            colors,
+            position_data: vec![],
+            texcoord_data: vec![],
+            color_data: vec![],
+        }
+    }
+
+    // Quads added since the last draw
+    pub fn len(&self) -> usize {
+        self.position_data.len() / (2 * VERTICES_PER_QUAD)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position_data.is_empty()
+    }
+
+    /// A sprite over `rect`, (x, y, width, height) in pixels from the lower left corner of the view,
+    /// tinted with `color`. False if the batch is full and it wasn't added.
+    pub fn add_quad(&mut self, rect: [f32; 4], uv_rect: [f32; 4], color: [f32; 4]) -> bool {
+        if self.len() >= MAX_QUADS {
+            return false;
+        }
+        let [x, y, width, height] = rect;
+        let [u, v, du, dv] = uv_rect;
+        let corners = [(x, y, u, v), (x + width, y, u + du, v), (x + width, y + height, u + du, v + dv), (x, y + height, u, v + dv)];
+        for corner in [0, 1, 2, 0, 2, 3] {
+            let (x, y, u, v) = corners[corner];
+            self.position_data.extend_from_slice(&[x, y]);
+            self.texcoord_data.extend_from_slice(&[u, v]);
+            self.color_data.extend_from_slice(&color);
+        }
+        true
+    }
+
+    /// Like `add_quad`, but turned by `rotation` radians counterclockwise about the middle of `rect`.
+    pub fn add_rotated_quad(&mut self, rect: [f32; 4], rotation: f32, uv_rect: [f32; 4], color: [f32; 4]) -> bool {
+        if self.len() >= MAX_QUADS {
+            return false;
+        }
+        let [x, y, width, height] = rect;
+        let [u, v, du, dv] = uv_rect;
+        let center = (x + width / 2.0, y + height / 2.0);
+        let (sin, cos) = rotation.sin_cos();
+        let turned = |dx: f32, dy: f32| (center.0 + dx * cos - dy * sin, center.1 + dx * sin + dy * cos);
+        let (half_width, half_height) = (width / 2.0, height / 2.0);
+        let corners = [
+            (turned(-half_width, -half_height), u, v),
+            (turned(half_width, -half_height), u + du, v),
+            (turned(half_width, half_height), u + du, v + dv),
+            (turned(-half_width, half_height), u, v + dv),
+        ];
+        for corner in [0, 1, 2, 0, 2, 3] {
+            let ((x, y), u, v) = corners[corner];
+            self.position_data.extend_from_slice(&[x, y]);
+            self.texcoord_data.extend_from_slice(&[u, v]);
+            self.color_data.extend_from_slice(&color);
+        }
+        true
+    }
+
+    /// Text laid out by BitmapFont::layout with its top left corner at (`left`, `top`) in pixels,
+    /// `cell_size` pixels to a cell of the font. Whole pixels keep it sharp. Either all of it is
+    /// added or, if it doesn't fit in the batch, none of it and it returns false.
+    pub fn add_text(&mut self, glyphs: &[PlacedGlyph], left: f32, top: f32, cell_size: f32, color: [f32; 4]) -> bool {
+        if self.len() + glyphs.len() > MAX_QUADS {
+            return false;
+        }
+        let (width, height) = (SPRITE_WIDTH as f32 * cell_size, SPRITE_HEIGHT as f32 * cell_size);
+        for glyph in glyphs {
+            let glyph_top = top - glyph.y * cell_size;
+            self.add_quad([left + glyph.x * cell_size, glyph_top - height, width, height], glyph.uv_rect, color);
+        }
+        true
+    }
+
+    /// Draw what was added over `view`, with the framebuffer it is in bound, and start over.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&mut self, assets: &AssetManager, view: &Viewport) {
+        if self.is_empty() {
+            return;
+        }
+        self.positions.update_range(0, &self.position_data);
+        self.texcoords.update_range(0, &self.texcoord_data);
+        self.colors.update_range(0, &self.color_data);
+        let vertex_count = (self.len() * VERTICES_PER_QUAD) as i32;
+        self.position_data.clear();
+        self.texcoord_data.clear();
+        self.color_data.clear();
+
+        gl::Viewport(view.x, view.y, view.width, view.height);
+        gfx::RenderState {
+            depth_test: gfx::DepthTest::Off,
+            depth_write: false,
+            cull: gfx::CullMode::Off,
+            blend: gfx::Blend::Alpha,
+            scissor: Some((view.x, view.y, view.width, view.height)),
+            ..Default::default()
+        }.apply();
+
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_vec2("view_size", &[view.width.max(1) as f32, view.height.max(1) as f32]);
+        shader.set_uniform_int("sprite_atlas", 0);
+        gfx::bind_texture(0, Some(self.atlas_texture));
+        gl::BindVertexArray(self.vao_id);
+        gfx::count_draw_call();
+        gl::DrawArrays(gl::TRIANGLES, 0, vertex_count);
+        gl::BindVertexArray(0);
+        gfx::bind_texture(0, None);
+
+        gfx::RenderState::default().apply();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteVertexArrays(1, &self.vao_id);
+        self.positions.delete();
+        self.texcoords.delete();
+        self.colors.delete();
+    }
+}
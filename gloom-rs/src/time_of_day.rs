@@ -0,0 +1,117 @@
+extern crate nalgebra_glm as glm;
+
+// * Time of day
+// A clock that moves the sun across the sky. Everything that depends on where the sun is (the
+// light direction and color, the sky and ambient colors) is worked out from the clock here, so the
+// renderer just asks for it every frame.
+// T pauses the clock, [ and ] change its speed, and , and . step it one hour.
+
+pub struct TimeOfDay {
+    pub hours  : f32, // 0..24, 6 is sunrise, 12 is noon, 18 is sunset
+    pub speed  : f32, // In-game hours per real second
+    pub paused : bool,
+}
+
+// Sky colors, the night one is the clear color the scene has always used
+const NIGHT_SKY : [f32; 3] = [0.035, 0.046, 0.078];
+const DUSK_SKY  : [f32; 3] = [0.45, 0.25, 0.18];
+const DAY_SKY   : [f32; 3] = [0.28, 0.40, 0.60];
+
+impl TimeOfDay {
+    pub fn new(hours: f32) -> TimeOfDay {
+        TimeOfDay {
+            hours: hours.rem_euclid(24.0),
+            speed: 0.1,
+            paused: false,
+        }
+    }
+
+    // Starts at the time where the sun comes from the direction the scene has always been lit from
+    pub fn default_morning() -> TimeOfDay {
+        TimeOfDay::new(6.0 + 0.5_f32.atan2(1.0).to_degrees() / 15.0)
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        if !self.paused {
+            self.set_hours(self.hours + delta_time * self.speed);
+        }
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.hours = hours.rem_euclid(24.0);
+    }
+
+    // Angle of the sun above the horizon along its path, 0 at sunrise, PI/2 at noon
+    fn sun_angle(&self) -> f32 {
+        (self.hours - 6.0) / 24.0 * 2.0 * std::f32::consts::PI
+    }
+
+    // Sine of the sun's elevation, negative at night
+    pub fn sun_height(&self) -> f32 {
+        self.sun_angle().sin()
+    }
+
+    // Direction the sunlight travels in (from the sun towards the ground)
+    pub fn sun_direction(&self) -> glm::Vec3 {
+        let angle = self.sun_angle();
+        let towards_sun = rise_direction() * angle.cos() + glm::vec3(0.0, 1.0, 0.0) * angle.sin();
+        -glm::normalize(&towards_sun)
+    }
+
+    // How far the sky has turned, about the axis the sun goes around. The stars turn with it, so they
+    // rise and set along with the sun.
+    pub fn sky_rotation(&self) -> glm::Mat4 {
+        let axis = glm::cross(&rise_direction(), &glm::vec3(0.0, 1.0, 0.0));
+        glm::rotation(self.sun_angle(), &axis)
+    }
+
+    // Sunlight color and strength, warm near the horizon and gone below it
+    pub fn sun_color(&self) -> [f32; 3] {
+        let height = self.sun_height();
+        let intensity = smoothstep(-0.05, 0.15, height);
+        let warmth = 1.0 - smoothstep(0.0, 0.5, height);
+        let color = glm::lerp(&glm::vec3(1.0, 1.0, 1.0), &glm::vec3(1.0, 0.55, 0.3), warmth) * intensity;
+        [color.x, color.y, color.z]
+    }
+
+    pub fn sky_color(&self) -> [f32; 3] {
+        let height = self.sun_height();
+        let night = glm::Vec3::from(NIGHT_SKY);
+        let dusk = glm::Vec3::from(DUSK_SKY);
+        let day = glm::Vec3::from(DAY_SKY);
+
+        let color = if height < 0.0 {
+            glm::lerp(&dusk, &night, smoothstep(0.0, 0.2, -height))
+        } else {
+            glm::lerp(&dusk, &day, smoothstep(0.0, 0.4, height))
+        };
+        [color.x, color.y, color.z]
+    }
+
+    // Light coming from everywhere, so the night side isn't pitch black
+    pub fn ambient_color(&self) -> [f32; 3] {
+        let daylight = smoothstep(-0.2, 0.4, self.sun_height());
+        let color = glm::lerp(&glm::vec3(0.02, 0.025, 0.05), &glm::vec3(0.12, 0.12, 0.14), daylight);
+        [color.x, color.y, color.z]
+    }
+
+    // How much of the environment map's light to use, dimmer at night
+    pub fn ambient_intensity(&self) -> f32 {
+        0.3 + 0.7 * smoothstep(-0.2, 0.4, self.sun_height())
+    }
+
+    // How much the stars show, they fade out as the sky gets light
+    pub fn star_visibility(&self) -> f32 {
+        1.0 - smoothstep(-0.2, 0.05, self.sun_height())
+    }
+}
+
+// Horizontal direction towards the sun at sunrise
+fn rise_direction() -> glm::Vec3 {
+    glm::vec3(-0.8, 0.0, -0.6)
+}
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
@@ -34,3 +34,501 @@ pub fn simple_heading_animation(time: f32) -> Heading {
         yaw   : yaw   as f32,
     }
 }
+
+// * Combined flight motion
+// simple_heading_animation on its own flies a clean figure eight. AnimationMix lays more signals on top
+// of it without touching it: bobbing up and down, and swaying from side to side across the heading.
+// Every channel has its own amplitude, speed and phase, so helicopters sampled at the same time can
+// still move differently.
+
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationChannel {
+    pub amplitude : f32, // Scale of the heading path, in units for bobbing and sway. 0 turns the channel off
+    pub speed     : f32, // Multiplies the time
+    pub phase     : f32, // Added to the time after the speed, in seconds for the heading and radians for the rest
+}
+
+impl AnimationChannel {
+    pub fn new(amplitude: f32, speed: f32, phase: f32) -> AnimationChannel {
+        AnimationChannel { amplitude, speed, phase }
+    }
+
+    fn time(&self, time: f32) -> f32 {
+        time * self.speed + self.phase
+    }
+
+    fn wave(&self, time: f32) -> f32 {
+        self.amplitude * self.time(time).sin()
+    }
+}
+
+// Position and attitude of everything the channels add up to
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationPose {
+    pub position : glm::Vec3,
+    pub roll     : f32, // measured in radians
+    pub pitch    : f32, // measured in radians
+    pub yaw      : f32, // measured in radians
+}
+
+impl AnimationPose {
+    // The pose without its height, the way simple_heading_animation returns it
+    pub fn heading(&self) -> Heading {
+        Heading { x: self.position.x, z: self.position.z, roll: self.roll, pitch: self.pitch, yaw: self.yaw }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AnimationMix {
+    pub heading   : AnimationChannel,
+    pub bobbing   : AnimationChannel,
+    pub sway      : AnimationChannel,
+    pub sway_roll : f32, // Radians of roll into the sway per unit of it
+}
+
+impl Default for AnimationMix {
+    // Just the heading, the same as calling simple_heading_animation
+    fn default() -> AnimationMix {
+        AnimationMix {
+            heading   : AnimationChannel::new(1.0, 1.0, 0.0),
+            bobbing   : AnimationChannel::new(0.0, 1.0, 0.0),
+            sway      : AnimationChannel::new(0.0, 1.0, 0.0),
+            sway_roll : 0.0,
+        }
+    }
+}
+
+impl AnimationMix {
+    pub fn with_heading(self, heading: AnimationChannel) -> AnimationMix {
+        AnimationMix { heading, ..self }
+    }
+
+    pub fn with_bobbing(self, bobbing: AnimationChannel) -> AnimationMix {
+        AnimationMix { bobbing, ..self }
+    }
+
+    pub fn with_sway(self, sway: AnimationChannel, sway_roll: f32) -> AnimationMix {
+        AnimationMix { sway, sway_roll, ..self }
+    }
+
+    pub fn sample(&self, time: f32) -> AnimationPose {
+        let heading = simple_heading_animation(self.heading.time(time));
+        let bobbing = self.bobbing.wave(time);
+        let sway = self.sway.wave(time);
+
+        // Sideways is across the direction of flight, which is along (sin yaw, cos yaw) turned half around
+        let right = glm::vec3(heading.yaw.cos(), 0.0, -heading.yaw.sin());
+        let position = glm::vec3(heading.x, 0.0, heading.z) * self.heading.amplitude
+            + glm::vec3(0.0, bobbing, 0.0)
+            + right * sway;
+
+        AnimationPose {
+            position,
+            roll  : heading.roll + sway * self.sway_roll,
+            pitch : heading.pitch,
+            yaw   : heading.yaw,
+        }
+    }
+}
+
+// * Noise
+// Seeded gradient (Perlin), simplex, fractal (fBm) and cellular (Worley) noise in one, two and three
+// dimensions, for everything that wants something random but smooth: terrain, camera shake,
+// turbulence, textures made when the program starts. The same seed always gives the same noise.
+//
+// Perlin, simplex and fBm go from -1 to 1 and are 0 on every whole number. Worley is the distance to the
+// nearest of one random point per cell, from 0 up to a little over 1. A Noise made tileable repeats
+// every `period` units on every axis, fBm included, so it wraps around a texture or a loop of time
+// without a seam. Simplex noise is on a skewed grid that can't repeat like that: tileable simplex noise
+// blends the samples a period apart on every axis instead, and evens the contrast the blend loses back out.
+
+#[derive(Clone, Copy, Debug)]
+pub struct Noise {
+    seed   : u32,
+    period : Option<i32>,
+}
+
+// fBm: every octave twice as fine and half as strong as the one before
+const LACUNARITY : f32 = 2.0;
+const GAIN       : f32 = 0.5;
+
+// Skewing the simplex grids to squares and cubes and back, see Gustavson's "Simplex noise demystified"
+const SKEW_2D   : f32 = 0.366_025_4;  // (sqrt(3) - 1) / 2
+const UNSKEW_2D : f32 = 0.211_324_87; // (3 - sqrt(3)) / 6
+const SKEW_3D   : f32 = 1.0 / 3.0;
+const UNSKEW_3D : f32 = 1.0 / 6.0;
+
+const GRADIENTS_2D: [(f32, f32); 8] = [
+    (1.0, 0.0), (-1.0, 0.0), (0.0, 1.0), (0.0, -1.0),
+    (0.707_106_77, 0.707_106_77), (-0.707_106_77, 0.707_106_77), (0.707_106_77, -0.707_106_77), (-0.707_106_77, -0.707_106_77),
+];
+// The middles of the edges of a cube
+const GRADIENTS_3D: [(f32, f32, f32); 12] = [
+    (1.0, 1.0, 0.0), (-1.0, 1.0, 0.0), (1.0, -1.0, 0.0), (-1.0, -1.0, 0.0),
+    (1.0, 0.0, 1.0), (-1.0, 0.0, 1.0), (1.0, 0.0, -1.0), (-1.0, 0.0, -1.0),
+    (0.0, 1.0, 1.0), (0.0, -1.0, 1.0), (0.0, 1.0, -1.0), (0.0, -1.0, -1.0),
+];
+
+impl Noise {
+    pub fn new(seed: u64) -> Noise {
+        Noise { seed: (seed ^ (seed >> 32)) as u32, period: None }
+    }
+
+    /// Repeats every `period` units, at least 1.
+    pub fn tileable(self, period: u32) -> Noise {
+        Noise { period: Some(period.max(1) as i32), ..self }
+    }
+
+    // * Perlin
+
+    pub fn perlin1(&self, x: f32) -> f32 {
+        self.perlin1_with_period(x, self.period)
+    }
+
+    pub fn perlin2(&self, x: f32, y: f32) -> f32 {
+        self.perlin2_with_period(x, y, self.period)
+    }
+
+    pub fn perlin3(&self, point: &glm::Vec3) -> f32 {
+        self.perlin3_with_period(point, self.period)
+    }
+
+    fn perlin1_with_period(&self, x: f32, period: Option<i32>) -> f32 {
+        let cell = x.floor();
+        let f = x - cell;
+        let cell = cell as i32;
+        let gradient = |i: i32| self.hash(wrap(cell + i, period), 0, 0) as f32 / u32::MAX as f32 * 2.0 - 1.0;
+        // A gradient of at most 1 reaches 0.5 half way between two whole numbers
+        (lerp(gradient(0) * f, gradient(1) * (f - 1.0), fade(f)) * 2.0).clamp(-1.0, 1.0)
+    }
+
+    fn perlin2_with_period(&self, x: f32, y: f32, period: Option<i32>) -> f32 {
+        let (cell_x, cell_y) = (x.floor(), y.floor());
+        let (fx, fy) = (x - cell_x, y - cell_y);
+        let (cell_x, cell_y) = (cell_x as i32, cell_y as i32);
+        let corner = |i: i32, j: i32| {
+            let (gx, gy) = GRADIENTS_2D[self.hash(wrap(cell_x + i, period), wrap(cell_y + j, period), 0) as usize % 8];
+            gx * (fx - i as f32) + gy * (fy - j as f32)
+        };
+        let (u, v) = (fade(fx), fade(fy));
+        let value = lerp(lerp(corner(0, 0), corner(1, 0), u), lerp(corner(0, 1), corner(1, 1), u), v);
+        // Unit gradients reach sqrt(1/2) in the middle of a cell
+        (value * std::f32::consts::SQRT_2).clamp(-1.0, 1.0)
+    }
+
+    fn perlin3_with_period(&self, point: &glm::Vec3, period: Option<i32>) -> f32 {
+        let cell = point.map(|coordinate| coordinate.floor());
+        let f = point - cell;
+        let (cell_x, cell_y, cell_z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let corner = |i: i32, j: i32, k: i32| {
+            let hash = self.hash(wrap(cell_x + i, period), wrap(cell_y + j, period), wrap(cell_z + k, period));
+            let (gx, gy, gz) = GRADIENTS_3D[hash as usize % 12];
+            gx * (f.x - i as f32) + gy * (f.y - j as f32) + gz * (f.z - k as f32)
+        };
+        let (u, v, w) = (fade(f.x), fade(f.y), fade(f.z));
+        let plane = |k: i32| lerp(lerp(corner(0, 0, k), corner(1, 0, k), u), lerp(corner(0, 1, k), corner(1, 1, k), u), v);
+        lerp(plane(0), plane(1), w).clamp(-1.0, 1.0)
+    }
+
+    // * Simplex
+
+    // In one dimension the simplex grid is the same as Perlin's
+    pub fn simplex1(&self, x: f32) -> f32 {
+        self.perlin1_with_period(x, self.period)
+    }
+
+    pub fn simplex2(&self, x: f32, y: f32) -> f32 {
+        match self.period {
+            Some(period) => blend_period([x, y], period, |[x, y]| self.simplex2_untiled(x, y)),
+            None         => self.simplex2_untiled(x, y),
+        }
+    }
+
+    pub fn simplex3(&self, point: &glm::Vec3) -> f32 {
+        match self.period {
+            Some(period) => blend_period([point.x, point.y, point.z], period, |[x, y, z]| self.simplex3_untiled(&glm::vec3(x, y, z))),
+            None         => self.simplex3_untiled(point),
+        }
+    }
+
+    fn simplex2_untiled(&self, x: f32, y: f32) -> f32 {
+        // Which triangle of the skewed square the point is in
+        let skew = (x + y) * SKEW_2D;
+        let (i, j) = ((x + skew).floor(), (y + skew).floor());
+        let unskew = (i + j) * UNSKEW_2D;
+        let (x0, y0) = (x - (i - unskew), y - (j - unskew));
+        let (i1, j1) = if x0 > y0 { (1, 0) } else { (0, 1) };
+        let corners = [
+            (0, 0, x0, y0),
+            (i1, j1, x0 - i1 as f32 + UNSKEW_2D, y0 - j1 as f32 + UNSKEW_2D),
+            (1, 1, x0 - 1.0 + 2.0 * UNSKEW_2D, y0 - 1.0 + 2.0 * UNSKEW_2D),
+        ];
+        let (i, j) = (i as i32, j as i32);
+        let sum: f32 = corners.iter().map(|&(di, dj, dx, dy)| {
+            let t = 0.5 - dx * dx - dy * dy;
+            if t <= 0.0 {
+                return 0.0;
+            }
+            let (gx, gy) = GRADIENTS_2D[self.hash(i + di, j + dj, 0) as usize % 8];
+            t.powi(4) * (gx * dx + gy * dy)
+        }).sum();
+        (sum * 99.2).clamp(-1.0, 1.0)
+    }
+
+    fn simplex3_untiled(&self, point: &glm::Vec3) -> f32 {
+        // Which of the six tetrahedra of the skewed cube the point is in
+        let skew = (point.x + point.y + point.z) * SKEW_3D;
+        let cell = point.map(|coordinate| (coordinate + skew).floor());
+        let unskew = (cell.x + cell.y + cell.z) * UNSKEW_3D;
+        let d0 = point - cell.add_scalar(-unskew);
+        let (first, second) = if d0.x >= d0.y {
+            if d0.y >= d0.z      { ((1, 0, 0), (1, 1, 0)) }
+            else if d0.x >= d0.z { ((1, 0, 0), (1, 0, 1)) }
+            else                 { ((0, 0, 1), (1, 0, 1)) }
+        } else if d0.y < d0.z    { ((0, 0, 1), (0, 1, 1)) }
+        else if d0.x < d0.z      { ((0, 1, 0), (0, 1, 1)) }
+        else                     { ((0, 1, 0), (1, 1, 0)) };
+        let offset = |(di, dj, dk): (i32, i32, i32), corner: f32| (di, dj, dk, d0 - glm::vec3(di as f32, dj as f32, dk as f32).add_scalar(-corner * UNSKEW_3D));
+        let corners = [offset((0, 0, 0), 0.0), offset(first, 1.0), offset(second, 2.0), offset((1, 1, 1), 3.0)];
+        let (i, j, k) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let sum: f32 = corners.iter().map(|&(di, dj, dk, d)| {
+            let t = 0.6 - glm::dot(&d, &d);
+            if t <= 0.0 {
+                return 0.0;
+            }
+            let (gx, gy, gz) = GRADIENTS_3D[self.hash(i + di, j + dj, k + dk) as usize % 12];
+            t.powi(4) * (gx * d.x + gy * d.y + gz * d.z)
+        }).sum();
+        (sum * 32.0).clamp(-1.0, 1.0)
+    }
+
+    // * fBm
+    // `octaves` of Perlin noise added up, then scaled back to between -1 and 1
+
+    pub fn fbm1(&self, x: f32, octaves: u32) -> f32 {
+        self.octaves(octaves, |frequency, period, octave| self.perlin1_with_period(x * frequency + octave, period))
+    }
+
+    pub fn fbm2(&self, x: f32, y: f32, octaves: u32) -> f32 {
+        self.octaves(octaves, |frequency, period, octave| self.perlin2_with_period(x * frequency + octave, y * frequency, period))
+    }
+
+    pub fn fbm3(&self, point: &glm::Vec3, octaves: u32) -> f32 {
+        self.octaves(octaves, |frequency, period, octave| self.perlin3_with_period(&(point * frequency).add_scalar(octave), period))
+    }
+
+    // Octaves are moved apart by a whole number, so they don't all line up at 0, which keeps them tileable
+    fn octaves(&self, octaves: u32, sample: impl Fn(f32, Option<i32>, f32) -> f32) -> f32 {
+        let (mut sum, mut amplitude, mut total, mut frequency) = (0.0, 1.0, 0.0, 1.0);
+        for octave in 0..octaves {
+            let period = self.period.map(|period| period * LACUNARITY.powi(octave as i32) as i32);
+            sum += sample(frequency, period, (octave * 31) as f32) * amplitude;
+            total += amplitude;
+            amplitude *= GAIN;
+            frequency *= LACUNARITY;
+        }
+        if total > 0.0 { sum / total } else { 0.0 }
+    }
+
+    // * Worley
+
+    pub fn worley1(&self, x: f32) -> f32 {
+        let cell = x.floor() as i32;
+        (-1..=1).map(|i| {
+            let feature = (cell + i) as f32 + self.unit(wrap(cell + i, self.period), 0, 0, 0);
+            (feature - x).abs()
+        }).fold(f32::MAX, f32::min)
+    }
+
+    pub fn worley2(&self, x: f32, y: f32) -> f32 {
+        let (cell_x, cell_y) = (x.floor() as i32, y.floor() as i32);
+        let mut nearest = f32::MAX;
+        for j in -1..=1 {
+            for i in -1..=1 {
+                let (wrapped_x, wrapped_y) = (wrap(cell_x + i, self.period), wrap(cell_y + j, self.period));
+                let feature_x = (cell_x + i) as f32 + self.unit(wrapped_x, wrapped_y, 0, 0);
+                let feature_y = (cell_y + j) as f32 + self.unit(wrapped_x, wrapped_y, 0, 1);
+                nearest = nearest.min(((feature_x - x).powi(2) + (feature_y - y).powi(2)).sqrt());
+            }
+        }
+        nearest
+    }
+
+    pub fn worley3(&self, point: &glm::Vec3) -> f32 {
+        let cell = point.map(|coordinate| coordinate.floor());
+        let (cell_x, cell_y, cell_z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+        let mut nearest = f32::MAX;
+        for k in -1..=1 {
+            for j in -1..=1 {
+                for i in -1..=1 {
+                    let wrapped = (wrap(cell_x + i, self.period), wrap(cell_y + j, self.period), wrap(cell_z + k, self.period));
+                    let unit = |axis: u32| self.unit(wrapped.0, wrapped.1, wrapped.2, axis);
+                    let feature = cell + glm::vec3(i as f32 + unit(0), j as f32 + unit(1), k as f32 + unit(2));
+                    nearest = nearest.min(glm::distance(&feature, point));
+                }
+            }
+        }
+        nearest
+    }
+
+    // * Hashing lattice points
+
+    fn hash(&self, x: i32, y: i32, z: i32) -> u32 {
+        let mut h = self.seed
+            ^ (x as u32).wrapping_mul(0x8da6_b343)
+            ^ (y as u32).wrapping_mul(0xd816_3841)
+            ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+        // Murmur3's finalizer, so neighbouring points get unrelated values
+        h ^= h >> 16;
+        h = h.wrapping_mul(0x85eb_ca6b);
+        h ^= h >> 13;
+        h = h.wrapping_mul(0xc2b2_ae35);
+        h ^ (h >> 16)
+    }
+
+    // From 0 to 1, a different one for every axis of the same point
+    fn unit(&self, x: i32, y: i32, z: i32, axis: u32) -> f32 {
+        let h = self.hash(x, y, z).wrapping_add(axis.wrapping_mul(0x9e37_79b9));
+        let h = Noise { seed: h, period: None }.hash(0, 0, 0);
+        (h >> 8) as f32 / (1u32 << 24) as f32
+    }
+}
+
+// Wraps the point into the first period, then mixes the samples there and one period back on every axis,
+// each weighed by how close the point is to that end. Going out one end of the period lands on the same
+// samples with the same weights as coming in the other. Mixing lowers the contrast the most in the middle,
+// dividing by the length of the weights puts it back.
+fn blend_period<const N: usize>(point: [f32; N], period: i32, sample: impl Fn([f32; N]) -> f32) -> f32 {
+    let period = period as f32;
+    let wrapped = point.map(|coordinate| coordinate.rem_euclid(period));
+    let (mut sum, mut weights) = (0.0, 0.0);
+    for corner in 0..1 << N {
+        let (mut shifted, mut weight) = (wrapped, 1.0);
+        for axis in 0..N {
+            let t = wrapped[axis] / period;
+            if corner >> axis & 1 == 1 {
+                shifted[axis] -= period;
+                weight *= t;
+            } else {
+                weight *= 1.0 - t;
+            }
+        }
+        sum += sample(shifted) * weight;
+        weights += weight * weight;
+    }
+    (sum / f32::sqrt(weights)).clamp(-1.0, 1.0)
+}
+
+fn wrap(i: i32, period: Option<i32>) -> i32 {
+    match period {
+        Some(period) => i.rem_euclid(period),
+        None         => i,
+    }
+}
+
+// Ken Perlin's 6t^5 - 15t^4 + 10t^3, flat at both ends so the cells join without a crease
+fn fade(t: f32) -> f32 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(a: f32, b: f32, t: f32) -> f32 {
+    a + (b - a) * t
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SEEDS: [u64; 3] = [0, 1, 0xdead_beef];
+
+    // Points spread over a few cells, off the whole numbers
+    fn points() -> impl Iterator<Item = glm::Vec3> {
+        (0..400).map(|i| {
+            let i = i as f32;
+            glm::vec3(i * 0.173 - 31.0, i * 0.291 - 47.0, i * 0.057 + 3.0)
+        })
+    }
+
+    fn all_kinds(noise: &Noise, point: &glm::Vec3) -> [f32; 9] {
+        [
+            noise.perlin1(point.x), noise.perlin2(point.x, point.y), noise.perlin3(point),
+            noise.simplex1(point.x), noise.simplex2(point.x, point.y), noise.simplex3(point),
+            noise.fbm1(point.x, 4), noise.fbm2(point.x, point.y, 4), noise.fbm3(point, 4),
+        ]
+    }
+
+    fn worleys(noise: &Noise, point: &glm::Vec3) -> [f32; 3] {
+        [noise.worley1(point.x), noise.worley2(point.x, point.y), noise.worley3(point)]
+    }
+
+    #[test]
+    fn stays_in_range() {
+        for seed in SEEDS {
+            for noise in [Noise::new(seed), Noise::new(seed).tileable(5)] {
+                for point in points() {
+                    for value in all_kinds(&noise, &point) {
+                        assert!((-1.0..=1.0).contains(&value), "{} at {:?}", value, point);
+                    }
+                    for value in worleys(&noise, &point) {
+                        // The nearest feature point is at worst in a corner of a neighbouring cell
+                        assert!((0.0..=3.0f32.sqrt()).contains(&value), "{} at {:?}", value, point);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn is_zero_on_whole_numbers() {
+        let noise = Noise::new(7);
+        for i in -5..5 {
+            let point = glm::vec3(i as f32, (i * 3) as f32, (i * 7) as f32);
+            assert_eq!(noise.perlin1(point.x), 0.0);
+            assert_eq!(noise.perlin2(point.x, point.y), 0.0);
+            assert_eq!(noise.perlin3(&point), 0.0);
+        }
+    }
+
+    #[test]
+    fn is_continuous() {
+        let step = 1e-3;
+        for seed in SEEDS {
+            for noise in [Noise::new(seed), Noise::new(seed).tileable(3)] {
+                for point in points() {
+                    let near = point.add_scalar(step);
+                    for (a, b) in all_kinds(&noise, &point).iter().zip(&all_kinds(&noise, &near)) {
+                        assert!((a - b).abs() < 0.05, "{} jumps to {} at {:?}", a, b, point);
+                    }
+                    for (a, b) in worleys(&noise, &point).iter().zip(&worleys(&noise, &near)) {
+                        // A distance can't change faster than the point moves
+                        assert!((a - b).abs() <= step * 3.0f32.sqrt() + 1e-4, "{} jumps to {} at {:?}", a, b, point);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tileable_repeats_every_period() {
+        let period = 4;
+        for seed in SEEDS {
+            let noise = Noise::new(seed).tileable(period);
+            for point in points() {
+                let shifted = point + glm::vec3(1.0, -2.0, 3.0) * period as f32;
+                for (a, b) in all_kinds(&noise, &point).iter().zip(&all_kinds(&noise, &shifted)) {
+                    assert!((a - b).abs() < 1e-3, "{} != {} at {:?}", a, b, point);
+                }
+                for (a, b) in worleys(&noise, &point).iter().zip(&worleys(&noise, &shifted)) {
+                    assert!((a - b).abs() < 1e-3, "{} != {} at {:?}", a, b, point);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn same_seed_same_noise() {
+        let point = glm::vec3(1.3, 2.7, -0.4);
+        assert_eq!(all_kinds(&Noise::new(3), &point), all_kinds(&Noise::new(3), &point));
+        assert_ne!(all_kinds(&Noise::new(3), &point), all_kinds(&Noise::new(4), &point));
+    }
+}
@@ -0,0 +1,240 @@
+extern crate nalgebra_glm as glm;
+
+use std::collections::VecDeque;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::gfx;
+use crate::scene_graph::SceneNode;
+
+// * Trails
+// Ribbons left behind by things that move: the vortices off the tips of the main rotors, and the smoke
+// behind the flares. A trail remembers where its source was over the last second or so, and draws a
+// strip through those points, wide across the line of sight so it is never seen edge on. The oldest
+// points fade out and are dropped.
+//
+// A trail either follows a point on a node, read from the world matrix the snapshot leaves on it, or is
+// fed its points by whatever owns it (see ProjectileSystem). Once released it stops growing and is
+// removed when its last point has faded. The points of all trails go into the same vertex buffers,
+// which are made big enough for MAX_VERTICES once and rewritten every frame.
+
+pub type TrailId = usize;
+
+const MAX_POINTS   : usize = 128;  // Per trail, the oldest go first past it
+const MAX_VERTICES : usize = 8192; // Two per point, of all trails together
+
+#[derive(Clone, Copy, Debug)]
+pub struct TrailStyle {
+    pub width       : f32,
+    pub color       : [f32; 4], // Linear, the alpha at the head of the trail
+    pub lifetime    : f32,      // Seconds a point stays
+    pub min_spacing : f32,      // A point closer than this to the one before moves it instead
+}
+
+impl TrailStyle {
+    // Thin and pale, gone quickly
+    pub fn vortex() -> TrailStyle {
+        TrailStyle { width: 0.15, color: [0.8, 0.85, 0.9, 0.35], lifetime: 0.6, min_spacing: 0.05 }
+    }
+
+    // Wide and grey, hanging in the air for a while
+    pub fn smoke() -> TrailStyle {
+        TrailStyle { width: 0.6, color: [0.5, 0.48, 0.45, 0.5], lifetime: 2.5, min_spacing: 0.2 }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct TrailPoint {
+    position : glm::Vec3,
+    time     : f32,
+}
+
+struct Trail {
+    style    : TrailStyle,
+    source   : Option<(*const SceneNode, glm::Vec3)>, // Node and the point on it in its own space
+    points   : VecDeque<TrailPoint>,                  // Oldest first
+    released : bool,
+}
+
+pub struct TrailRenderer {
+    pub enabled : bool,
+    trails      : Vec<Option<Trail>>, // Indexed by TrailId, None once removed
+    vao_id      : u32,
+    points      : gfx::Buffer,        // World position and side
+    tangents    : gfx::Buffer,        // Direction along the trail and width
+    colors      : gfx::Buffer,
+    strips      : Vec<(i32, i32)>,    // First vertex and vertex count of every trail uploaded
+    shader      : ShaderHandle,
+}
+
+impl TrailRenderer {
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager) -> TrailRenderer {
+        let shader = assets.load_shader(&["shaders/trail.vert", "shaders/trail.frag"]);
+        let empty = vec![[0.0f32; 4]; MAX_VERTICES];
+        let mut vao_id: u32 = 0;
+        gl::GenVertexArrays(1, &mut vao_id);
+        gl::BindVertexArray(vao_id);
+        let points = gfx::Buffer::new(gfx::BufferKind::Vertex, &empty);
+        points.vertex_attribute(0, 4);
+        let tangents = gfx::Buffer::new(gfx::BufferKind::Vertex, &empty);
+        tangents.vertex_attribute(1, 4);
+        let colors = gfx::Buffer::new(gfx::BufferKind::Vertex, &empty);
+        colors.vertex_attribute(2, 4);
+        gl::BindVertexArray(0);
+        gfx::label_object(gfx::ObjectKind::VertexArray, vao_id, "trails VAO");
+        points.label("trail points");
+        tangents.label("trail tangents");
+        colors.label("trail colors");
+
+        TrailRenderer { enabled: true, trails: vec![], vao_id, points, tangents, colors, strips: vec![], shader }
+    }
+
+    // A trail fed with push
+    pub fn add(&mut self, style: TrailStyle) -> TrailId {
+        self.insert(Trail { style, source: None, points: VecDeque::new(), released: false })
+    }
+
+    // A trail behind `point` on `node`, in the node's own space. The node has to outlive the trail.
+    pub fn follow(&mut self, node: &SceneNode, point: glm::Vec3, style: TrailStyle) -> TrailId {
+        self.insert(Trail { style, source: Some((node as *const SceneNode, point)), points: VecDeque::new(), released: false })
+    }
+
+    fn insert(&mut self, trail: Trail) -> TrailId {
+        match self.trails.iter().position(Option::is_none) {
+            Some(id) => {
+                self.trails[id] = Some(trail);
+                id
+            }
+            None => {
+                self.trails.push(Some(trail));
+                self.trails.len() - 1
+            }
+        }
+    }
+
+    pub fn push(&mut self, id: TrailId, position: glm::Vec3, now: f32) {
+        if let Some(trail) = self.trails.get_mut(id).and_then(Option::as_mut) {
+            if !trail.released {
+                trail.push(position, now);
+            }
+        }
+    }
+
+    // Stop growing the trail, it is removed once it has faded
+    pub fn release(&mut self, id: TrailId) {
+        if let Some(trail) = self.trails.get_mut(id).and_then(Option::as_mut) {
+            trail.released = true;
+        }
+    }
+
+    // * Grow the trails that follow nodes, and drop the points that have faded
+    // Call after the snapshot is captured, it leaves the world matrices on the nodes.
+    pub fn update(&mut self, now: f32) {
+        for slot in &mut self.trails {
+            let trail = match slot {
+                Some(trail) => trail,
+                None => continue,
+            };
+            if let (Some((node, point)), false) = (trail.source, trail.released) {
+                if let Some(world_matrix) = unsafe { (*node).world_matrix.get() } {
+                    trail.push((world_matrix * glm::vec4(point.x, point.y, point.z, 1.0)).xyz(), now);
+                }
+            }
+            let lifetime = trail.style.lifetime;
+            while trail.points.front().is_some_and(|oldest| now - oldest.time > lifetime) {
+                trail.points.pop_front();
+            }
+            if trail.released && trail.points.is_empty() {
+                *slot = None;
+            }
+        }
+    }
+
+    /// Write the points of every trail into the vertex buffers, two vertices each.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn upload(&mut self, now: f32) {
+        let (mut points, mut tangents, mut colors) = (vec![], vec![], vec![]);
+        self.strips.clear();
+        for trail in self.trails.iter().flatten() {
+            let count = trail.points.len();
+            if count < 2 || points.len() + count * 2 > MAX_VERTICES {
+                continue;
+            }
+            self.strips.push((points.len() as i32, count as i32 * 2));
+            let [r, g, b, a] = trail.style.color;
+            for i in 0..count {
+                let point = trail.points[i];
+                let (previous, next) = (trail.points[i.saturating_sub(1)].position, trail.points[(i + 1).min(count - 1)].position);
+                let along = next - previous;
+                let tangent = if glm::length(&along) > 1e-6 { glm::normalize(&along) } else { glm::vec3(0.0, 1.0, 0.0) };
+                // Fades with age, and thins out towards the tail
+                let fade = (1.0 - (now - point.time) / trail.style.lifetime).clamp(0.0, 1.0);
+                for side in [-1.0, 1.0] {
+                    points.push([point.position.x, point.position.y, point.position.z, side]);
+                    tangents.push([tangent.x, tangent.y, tangent.z, trail.style.width * (0.3 + 0.7 * fade)]);
+                    colors.push([r, g, b, a * fade]);
+                }
+            }
+        }
+        if !points.is_empty() {
+            self.points.update_range(0, &points);
+            self.tangents.update_range(0, &tangents);
+            self.colors.update_range(0, &colors);
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.strips.is_empty()
+    }
+
+    /// Draw the trails uploaded last into the active view, see-through and without writing depth.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, view_projection: &glm::Mat4, camera_position: glm::Vec3) {
+        if !self.enabled || self.strips.is_empty() {
+            return;
+        }
+        gfx::RenderState { depth_write: false, cull: gfx::CullMode::Off, ..Default::default() }.apply();
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", view_projection);
+        shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+        gl::BindVertexArray(self.vao_id);
+        for &(first, count) in &self.strips {
+            gfx::count_draw_call();
+            gl::DrawArrays(gl::TRIANGLE_STRIP, first, count);
+        }
+        gl::BindVertexArray(0);
+        gfx::RenderState::default().apply();
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        gl::DeleteVertexArrays(1, &self.vao_id);
+        self.points.delete();
+        self.tangents.delete();
+        self.colors.delete();
+    }
+}
+
+impl Trail {
+    fn push(&mut self, position: glm::Vec3, now: f32) {
+        // Too close to the last one, it moves along so the trail stays on its source
+        if self.points.len() >= 2 {
+            let before_last = self.points[self.points.len() - 2].position;
+            if glm::distance(&before_last, &position) < self.style.min_spacing {
+                *self.points.back_mut().unwrap() = TrailPoint { position, time: now };
+                return;
+            }
+        }
+        self.points.push_back(TrailPoint { position, time: now });
+        if self.points.len() > MAX_POINTS {
+            self.points.pop_front();
+        }
+    }
+}
@@ -0,0 +1,201 @@
+extern crate nalgebra_glm as glm;
+
+use glutin::event::VirtualKeyCode;
+
+use crate::events::Action;
+use crate::scene_graph::NodeId;
+use crate::snapshot::FrameSnapshot;
+use crate::spatial::SpatialIndex;
+
+// * Gameplay events and triggers
+// Subsystems publish GameEvents on the EventBus during the frame, and once per frame the bus hands
+// every event to every subscriber. Gameplay behavior ("open the door when the helicopter is over the
+// pad") is a subscriber set up next to the scene, so the render loop doesn't need to know about it.
+//
+// The TriggerWatcher publishes the spatial events: trigger volumes (boxes or spheres placed in the
+// world) report when a tracked node or the camera goes in or out of them, and tracked nodes report
+// when they bump into each other. It works on the frame snapshot, so it only sees nodes with something
+// to draw, and a subscriber changing a node shows up from the next frame on. The spatial index is the
+// broad phase for the collisions, only watched nodes near each other are compared.
+
+pub type RegionId = usize;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum GameEvent {
+    NodeEnteredRegion { node_id: NodeId, region: RegionId },
+    NodeLeftRegion { node_id: NodeId, region: RegionId },
+    CameraEnteredRegion { region: RegionId },
+    CameraLeftRegion { region: RegionId },
+    Collision { first: NodeId, second: NodeId }, // Bounding spheres started touching
+    AnimationFinished { node_id: NodeId },         // A non-looping path or a keyframe animation came to its end
+    KeyAction(VirtualKeyCode),                     // A key went down this frame
+    Action { action: Action, target: Option<NodeId> }, // A bound key went down, with the selected node
+    NodeClicked { node_id: NodeId },               // Picked with the mouse
+}
+
+// * Event bus
+type Subscriber = Box<dyn FnMut(&GameEvent)>;
+
+pub struct EventBus {
+    queue       : Vec<GameEvent>,
+    subscribers : Vec<Subscriber>,
+}
+
+impl EventBus {
+    pub fn new() -> EventBus {
+        EventBus { queue: vec![], subscribers: vec![] }
+    }
+
+    // Called with every event from now on, subscribers pick out the ones they care about
+    pub fn subscribe(&mut self, subscriber: impl FnMut(&GameEvent) + 'static) {
+        self.subscribers.push(Box::new(subscriber));
+    }
+
+    pub fn publish(&mut self, event: GameEvent) {
+        self.queue.push(event);
+    }
+
+    // Hand the events published since last time to the subscribers, in the order they came in
+    pub fn dispatch(&mut self) {
+        for event in self.queue.drain(..) {
+            for subscriber in &mut self.subscribers {
+                subscriber(&event);
+            }
+        }
+    }
+}
+
+// * Trigger volumes and colliders
+// Volumes are placed in world space. A node is inside when its origin is.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VolumeShape {
+    Box { min: glm::Vec3, max: glm::Vec3 }, // Axis aligned
+    Sphere { center: glm::Vec3, radius: f32 },
+}
+
+pub struct Region {
+    pub name  : String,
+    pub shape : VolumeShape,
+}
+
+impl Region {
+    pub fn contains(&self, point: &glm::Vec3) -> bool {
+        match self.shape {
+            VolumeShape::Box { min, max } => (0..3).all(|axis| point[axis] >= min[axis] && point[axis] <= max[axis]),
+            VolumeShape::Sphere { center, radius } => glm::distance2(point, &center) <= radius * radius,
+        }
+    }
+}
+
+// Work out which regions `position` went into and came out of since `inside` was last updated.
+// Nowhere (None) is outside of everything.
+fn region_crossings(regions: &[Region], inside: &mut Vec<RegionId>, position: Option<glm::Vec3>) -> Vec<(RegionId, bool)> {
+    let mut crossings = vec![];
+    for (region_id, region) in regions.iter().enumerate() {
+        let is_inside = position.is_some_and(|position| region.contains(&position));
+        let was_inside = inside.contains(&region_id);
+        if is_inside && !was_inside {
+            inside.push(region_id);
+            crossings.push((region_id, true));
+        } else if !is_inside && was_inside {
+            inside.retain(|&id| id != region_id);
+            crossings.push((region_id, false));
+        }
+    }
+    crossings
+}
+
+struct WatchedNode {
+    node_id : NodeId,
+    radius  : f32, // Bounding sphere around the node's origin, for collisions
+    inside  : Vec<RegionId>,
+}
+
+pub struct TriggerWatcher {
+    pub regions   : Vec<Region>,
+    watched       : Vec<WatchedNode>,
+    touching      : Vec<(NodeId, NodeId)>,  // Pairs that collided and haven't separated yet
+    camera_inside : Option<Vec<RegionId>>,  // None while the camera isn't tracked
+}
+
+impl TriggerWatcher {
+    pub fn new() -> TriggerWatcher {
+        TriggerWatcher { regions: vec![], watched: vec![], touching: vec![], camera_inside: None }
+    }
+
+    pub fn add_box(&mut self, name: &str, min: glm::Vec3, max: glm::Vec3) -> RegionId {
+        self.add_region(name, VolumeShape::Box { min, max })
+    }
+
+    pub fn add_sphere(&mut self, name: &str, center: glm::Vec3, radius: f32) -> RegionId {
+        self.add_region(name, VolumeShape::Sphere { center, radius })
+    }
+
+    pub fn add_region(&mut self, name: &str, shape: VolumeShape) -> RegionId {
+        self.regions.push(Region { name: name.to_string(), shape });
+        self.regions.len() - 1
+    }
+
+    // Publish CameraEnteredRegion/CameraLeftRegion for the camera passed to `update`
+    pub fn track_camera(&mut self) {
+        self.camera_inside.get_or_insert_with(Vec::new);
+    }
+
+    // Publish region and collision events for this node. It needs a VAO to be in the snapshot.
+    pub fn watch(&mut self, node_id: NodeId, radius: f32) {
+        self.watched.push(WatchedNode { node_id, radius, inside: vec![] });
+    }
+
+    // Compare where the watched nodes and the camera are now with where they were last time
+    pub fn update(&mut self, snapshot: &FrameSnapshot, spatial: &SpatialIndex, camera_position: glm::Vec3, bus: &mut EventBus) {
+        let positions: Vec<Option<glm::Vec3>> = self.watched.iter()
+            .map(|watched| snapshot.draws.iter()
+                .find(|draw| draw.node_id == watched.node_id)
+                .map(|draw| (draw.model_matrix * glm::vec4(0.0, 0.0, 0.0, 1.0)).xyz()))
+            .collect();
+
+        // Hidden nodes count as being nowhere, so they leave every region
+        for (watched, &position) in self.watched.iter_mut().zip(&positions) {
+            for (region, entered) in region_crossings(&self.regions, &mut watched.inside, position) {
+                bus.publish(if entered {
+                    GameEvent::NodeEnteredRegion { node_id: watched.node_id, region }
+                } else {
+                    GameEvent::NodeLeftRegion { node_id: watched.node_id, region }
+                });
+            }
+        }
+        if let Some(camera_inside) = &mut self.camera_inside {
+            for (region, entered) in region_crossings(&self.regions, camera_inside, Some(camera_position)) {
+                bus.publish(if entered { GameEvent::CameraEnteredRegion { region } } else { GameEvent::CameraLeftRegion { region } });
+            }
+        }
+
+        // Every pair once, only when they start touching. A node's origin is inside its mesh bounds, so
+        // the nodes whose bounds are out of reach can't be touching. Nodes the index doesn't know about
+        // are compared with every other one.
+        let max_radius = self.watched.iter().map(|watched| watched.radius).fold(0.0, f32::max);
+        let mut touching = vec![];
+        for i in 0..self.watched.len() {
+            let a = match positions[i] {
+                Some(a) => a,
+                None => continue,
+            };
+            let nearby = spatial.nodes_near(&a, self.watched[i].radius + max_radius);
+            for (other, &b) in self.watched[i + 1..].iter().zip(&positions[i + 1..]) {
+                if spatial.bounds(other.node_id).is_some() && !nearby.contains(&other.node_id) {
+                    continue;
+                }
+                if let Some(b) = b {
+                    let pair = (self.watched[i].node_id, other.node_id);
+                    if glm::distance(&a, &b) < self.watched[i].radius + other.radius {
+                        if !self.touching.contains(&pair) {
+                            bus.publish(GameEvent::Collision { first: pair.0, second: pair.1 });
+                        }
+                        touching.push(pair);
+                    }
+                }
+            }
+        }
+        self.touching = touching;
+    }
+}
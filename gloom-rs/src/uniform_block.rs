@@ -0,0 +1,220 @@
+extern crate nalgebra_glm as glm;
+
+use crate::clusters::ClusterBlock;
+use crate::shadows::CascadeBlock;
+
+// * Uniform blocks
+// Structs that go into std140 uniform blocks. std140 puts every member on a boundary of its own
+// (16 bytes for vec3s, matrices and arrays, whose elements are padded out to 16 bytes each too),
+// which is easy to get wrong by hand with #[repr(C)] and padding fields, and then the shader just
+// reads garbage. A struct declared with uniform_block! works the offsets out from its member types,
+// packs its values into bytes laid out that way, and writes the GLSL declaration of the block. The
+// shaders pull the declaration in with a line
+//     #pragma uniform_block ShadowCascades
+// (see expand_declarations), so the Rust and GLSL sides can't drift apart.
+//
+// A declarative macro rather than a derive, a derive needs a proc-macro crate of its own.
+
+// A type that can be a member of a std140 block
+pub trait Std140 {
+    const GLSL_TYPE : &'static str;
+    const ALIGNMENT : usize;
+    const SIZE      : usize;
+
+    // Into `bytes`, which start at the member's offset
+    fn write(&self, bytes: &mut [u8]);
+
+    // `name` declared as this type
+    fn glsl_member(name: &str) -> String {
+        format!("{} {}", Self::GLSL_TYPE, name)
+    }
+}
+
+fn write_words(words: impl IntoIterator<Item = [u8; 4]>, bytes: &mut [u8]) {
+    for (word, destination) in words.into_iter().zip(bytes.chunks_exact_mut(4)) {
+        destination.copy_from_slice(&word);
+    }
+}
+
+macro_rules! std140_scalar {
+    ($type:ty, $glsl_type:expr) => {
+        impl Std140 for $type {
+            const GLSL_TYPE : &'static str = $glsl_type;
+            const ALIGNMENT : usize = 4;
+            const SIZE      : usize = 4;
+
+            fn write(&self, bytes: &mut [u8]) {
+                bytes[..4].copy_from_slice(&self.to_ne_bytes());
+            }
+        }
+    };
+}
+
+std140_scalar!(f32, "float");
+std140_scalar!(i32, "int");
+std140_scalar!(u32, "uint");
+
+// Vectors of 3 are aligned like vectors of 4, but the next member can go into the 4th component
+macro_rules! std140_vector {
+    ($type:ty, $glsl_type:expr, $components:expr) => {
+        impl Std140 for $type {
+            const GLSL_TYPE : &'static str = $glsl_type;
+            const ALIGNMENT : usize = if $components == 2 { 8 } else { 16 };
+            const SIZE      : usize = 4 * $components;
+
+            fn write(&self, bytes: &mut [u8]) {
+                write_words(self.iter().map(|component| component.to_ne_bytes()), bytes);
+            }
+        }
+    };
+}
+
+std140_vector!(glm::Vec2, "vec2", 2);
+std140_vector!(glm::Vec3, "vec3", 3);
+std140_vector!(glm::Vec4, "vec4", 4);
+std140_vector!(glm::IVec2, "ivec2", 2);
+std140_vector!(glm::IVec4, "ivec4", 4);
+
+// Column major like glm, every column a vec4
+impl Std140 for glm::Mat4 {
+    const GLSL_TYPE : &'static str = "mat4";
+    const ALIGNMENT : usize = 16;
+    const SIZE      : usize = 64;
+
+    fn write(&self, bytes: &mut [u8]) {
+        write_words(self.iter().map(|value| value.to_ne_bytes()), bytes);
+    }
+}
+
+// Every element starts on a 16 byte boundary, even floats
+impl<T: Std140, const N: usize> Std140 for [T; N] {
+    const GLSL_TYPE : &'static str = T::GLSL_TYPE;
+    const ALIGNMENT : usize = align(T::ALIGNMENT, 16);
+    const SIZE      : usize = align(T::SIZE, 16) * N;
+
+    fn write(&self, bytes: &mut [u8]) {
+        let stride = align(T::SIZE, 16);
+        for (i, element) in self.iter().enumerate() {
+            element.write(&mut bytes[i * stride..]);
+        }
+    }
+
+    fn glsl_member(name: &str) -> String {
+        format!("{} {}[{}]", T::GLSL_TYPE, name, N)
+    }
+}
+
+// `offset` moved up to the next multiple of `alignment`
+pub const fn align(offset: usize, alignment: usize) -> usize {
+    offset.div_ceil(alignment) * alignment
+}
+
+// One member of a block, where it goes is worked out by UniformBlock::layout
+pub struct Member {
+    pub name        : &'static str,
+    pub declaration : String, // In GLSL
+    pub alignment   : usize,
+    pub size        : usize,
+}
+
+impl Member {
+    pub fn of<T: Std140>(name: &'static str) -> Member {
+        Member { name, declaration: T::glsl_member(name), alignment: T::ALIGNMENT, size: T::SIZE }
+    }
+}
+
+// Implemented by uniform_block!
+pub trait UniformBlock {
+    const NAME: &'static str; // Of the block in GLSL
+
+    fn members() -> Vec<Member>;
+
+    // Writes every member at its offset in `offsets`, in the order of `members`
+    fn write_members(&self, offsets: &[usize], bytes: &mut [u8]);
+
+    // The byte offset of every member, and the size of the whole block
+    fn layout() -> (Vec<usize>, usize) {
+        let mut offsets = vec![];
+        let mut end = 0;
+        for member in Self::members() {
+            let offset = align(end, member.alignment);
+            offsets.push(offset);
+            end = offset + member.size;
+        }
+        // The block as a whole is aligned like a vec4
+        (offsets, align(end, 16))
+    }
+
+    // The values laid out the way std140 wants them, to be uploaded into the uniform buffer
+    fn to_bytes(&self) -> Vec<u8> {
+        let (offsets, size) = Self::layout();
+        let mut bytes = vec![0; size];
+        self.write_members(&offsets, &mut bytes);
+        bytes
+    }
+
+    fn glsl_declaration() -> String {
+        let members: String = Self::members().iter().map(|member| format!("    {};\n", member.declaration)).collect();
+        format!("layout(std140) uniform {} {{\n{}}};", Self::NAME, members)
+    }
+}
+
+// Declares a struct that is uploaded into the GLSL block named after `as`. The member names are the
+// ones the shaders see, so they should be unique among the shader's uniforms.
+//
+//     uniform_block! {
+//         pub struct SunBlock as Sun {
+//             sun_direction : glm::Vec3,
+//             sun_intensity : f32,
+//         }
+//     }
+macro_rules! uniform_block {
+    ($(#[$attribute:meta])* $visibility:vis struct $name:ident as $block:ident {
+        $($(#[$member_attribute:meta])* $member:ident : $member_type:ty),* $(,)?
+    }) => {
+        $(#[$attribute])*
+        #[derive(Clone, Copy)]
+        $visibility struct $name {
+            $($(#[$member_attribute])* pub $member : $member_type),*
+        }
+
+        impl $crate::uniform_block::UniformBlock for $name {
+            const NAME: &'static str = stringify!($block);
+
+            fn members() -> Vec<$crate::uniform_block::Member> {
+                vec![$($crate::uniform_block::Member::of::<$member_type>(stringify!($member))),*]
+            }
+
+            fn write_members(&self, offsets: &[usize], bytes: &mut [u8]) {
+                let mut offsets = offsets.iter();
+                $($crate::uniform_block::Std140::write(&self.$member, &mut bytes[*offsets.next().unwrap()..]);)*
+            }
+        }
+    };
+}
+
+// The blocks the shaders can pull in with #pragma uniform_block
+fn declaration(block_name: &str) -> Option<String> {
+    match block_name {
+        CascadeBlock::NAME => Some(CascadeBlock::glsl_declaration()),
+        ClusterBlock::NAME => Some(ClusterBlock::glsl_declaration()),
+        _ => None,
+    }
+}
+
+// Swaps every `#pragma uniform_block <Name>` line in a shader source for the declaration of the
+// block, anything after the name (a comment) is dropped
+pub fn expand_declarations(shader_src: &str) -> String {
+    if !shader_src.contains("#pragma uniform_block") {
+        return shader_src.to_string();
+    }
+    shader_src.lines().map(|line| {
+        match line.trim().strip_prefix("#pragma uniform_block") {
+            Some(rest) => {
+                let block_name = rest.split_whitespace().next().unwrap_or_default();
+                declaration(block_name).unwrap_or_else(|| panic!("No uniform block named '{}', see uniform_block.rs", block_name))
+            }
+            None => line.to_string(),
+        }
+    }).collect::<Vec<_>>().join("\n")
+}
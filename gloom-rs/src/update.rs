@@ -0,0 +1,76 @@
+extern crate nalgebra_glm as glm;
+
+use crate::path::PathFollower;
+use crate::rotor::{Engine, FlightState};
+use crate::scene_graph::{self, NodeId, SceneNode};
+use crate::toolbox;
+
+// * Scene update
+// The scene graph is held together by raw pointers, which can't be handed to other threads.
+// So updating is split in two: working out where everything should be only needs plain data,
+// then writing the results into the nodes is a short serial pass. There are only a handful of
+// helicopters, far too few to be worth sending to rayon's pool. That is kept for the loops over
+// many things: the particles (see ecs::update_particles) and the culling (see spatial.rs and
+// instancing.rs). Nothing in here touches GL, that stays on the render thread.
+
+#[derive(Clone, Copy, Debug)]
+pub struct HelicopterPose {
+    pub position         : glm::Vec3,
+    pub rotation         : glm::Vec3, // x = pitch, y = yaw, z = roll
+    pub main_rotor_angle : f32,
+    pub tail_rotor_angle : f32,
+    pub searchlight_roll : f32,
+}
+
+// Where the parts are among the children of a helicopter root, the way main.rs builds it
+pub const DOOR_CHILD            : usize = 1;
+pub const MAIN_ROTOR_CHILD      : usize = 2;
+pub const TAIL_ROTOR_CHILD      : usize = 3;
+pub const MAIN_ROTOR_DISC_CHILD : usize = 4;
+pub const TAIL_ROTOR_DISC_CHILD : usize = 5;
+
+impl HelicopterPose {
+    // The searchlight is somewhere below the helicopter root, after the navigation lights
+    pub fn apply_to(&self, helicopter_root_node: &mut SceneNode, searchlight_id: NodeId) {
+        helicopter_root_node.position = scene_graph::position_from_f32(&self.position);
+        helicopter_root_node.rotation = self.rotation;
+        helicopter_root_node.get_child(MAIN_ROTOR_CHILD).rotation.y = self.main_rotor_angle;
+        helicopter_root_node.get_child(TAIL_ROTOR_CHILD).rotation.x = self.tail_rotor_angle;
+        if let Some(searchlight_node) = helicopter_root_node.find_mut(searchlight_id) {
+            searchlight_node.rotation.z = self.searchlight_roll;
+        }
+    }
+}
+
+// This frame's pose of every helicopter, one per route and engine: along its route, or along the
+// figure eight of toolbox::AnimationMix if it has none, only moving on while its engine is Flying.
+pub fn helicopter_poses(routes: &mut [Option<PathFollower>], engines: &[Engine], elapsed: f32, delta_time: f32) -> Vec<HelicopterPose> {
+    routes.iter_mut().zip(engines).enumerate().map(|(i, (route, engine))| {
+        let flying = engine.state == FlightState::Flying;
+        let (position, heading) = match route {
+            Some(route) => {
+                if flying {
+                    route.update(delta_time);
+                }
+                route.heading()
+            }
+            None => {
+                let offset = i as f32; // For each helicopter
+                let pose = toolbox::AnimationMix::default()
+                    .with_heading(toolbox::AnimationChannel::new(1.0, 1.0, offset * 0.8))
+                    .with_bobbing(toolbox::AnimationChannel::new(0.4, 1.7, offset * 2.1))
+                    .with_sway(toolbox::AnimationChannel::new(0.6, 0.9, offset * 1.3), 0.15)
+                    .sample(engine.flight_time);
+                (pose.position, pose.heading())
+            }
+        };
+
+        HelicopterPose {
+            position         : position + glm::vec3(0.0, engine.height_offset, 0.0),
+            rotation         : glm::vec3(heading.pitch * engine.tilt, heading.yaw, heading.roll * engine.tilt),
+            main_rotor_angle : engine.main_rotor.angle,
+            tail_rotor_angle : engine.tail_rotor.angle,
+            searchlight_roll : (elapsed * 0.8 + i as f32).sin() * 0.6, // Sweeping from side to side
+        }
+    }).collect()
+}
@@ -0,0 +1,346 @@
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::path::PathBuf;
+
+use glutin::event::VirtualKeyCode;
+
+use crate::display::WindowMode;
+use crate::events::{self, Action, ActionMap};
+use crate::sampler::TextureQuality;
+use crate::settings::{FogMode, RendererSettings};
+
+// * User settings
+// What the user changed that should still be that way the next time the program starts: the mouse
+// sensitivity, the keys of the actions, the window size and mode, the language and the quality
+// toggles of the renderer. Read once at startup, before the window is made, and written when the program exits.
+//
+// The file is settings.toml in a gloom-rs folder of the platform's config directory (~/.config on
+// Linux, AppData\Roaming on Windows, Library/Application Support on macOS), TOML like the language
+// files, so it can be edited by hand:
+//
+//     mouse_sensitivity = 0.005
+//     window_size = [1280, 720]
+//     frame_cap = "none"
+//
+//     [keys]
+//     ToggleDoor = "E"
+//
+// Everything is optional. What is missing, or can't be read, stays as the program sets it up, so an
+// old file or a typo only costs that one setting (and a warning). A file that isn't TOML at all costs
+// all of them.
+
+const FILE_NAME : &str = "settings.toml";
+
+#[derive(Clone, Debug, Default)]
+pub struct UserSettings {
+    pub mouse_sensitivity : Option<f32>,
+    pub window_size       : Option<(u32, u32)>, // Physical pixels, of the window when it was last windowed
+    pub window_mode       : Option<WindowMode>,
+    pub language          : Option<String>,    // Code of a file in resources/lang (see strings.rs)
+    pub vsync             : Option<bool>,
+    pub frame_cap         : Option<Option<u32>>,
+    pub fog               : Option<bool>,
+    pub fog_mode          : Option<FogMode>,
+    pub pbr               : Option<bool>,
+    pub hdr               : Option<bool>,
+    pub exposure          : Option<f32>,
+    pub auto_exposure     : Option<bool>,
+    pub ssao              : Option<bool>,
+    pub texture_quality   : Option<TextureQuality>,
+    pub key_bindings      : Vec<(Action, VirtualKeyCode)>,
+}
+
+// The value of an enum by its name, `options` are all the values it can have
+fn parse_named<T: Copy + Debug>(value: &toml::Value, options: &[T]) -> Option<T> {
+    let name = value.as_str()?;
+    options.iter().copied().find(|option| format!("{:?}", option) == name)
+}
+
+// A number written with or without a decimal point
+fn parse_f32(value: &toml::Value) -> Option<f32> {
+    value.as_float().or_else(|| value.as_integer().map(|integer| integer as f64)).map(|float| float as f32)
+}
+
+fn parse_positive_u32(value: &toml::Value) -> Option<u32> {
+    value.as_integer().and_then(|integer| u32::try_from(integer).ok()).filter(|&integer| integer > 0)
+}
+
+// Through the shortest text for the f32, so 0.005 is written as that and not as 0.004999999888241291
+fn float_value(value: f32) -> toml::Value {
+    toml::Value::Float(value.to_string().parse().unwrap_or(value as f64))
+}
+
+fn named_value<T: Debug>(value: T) -> toml::Value {
+    toml::Value::String(format!("{:?}", value))
+}
+
+impl UserSettings {
+    pub fn path() -> Option<PathBuf> {
+        dirs::config_dir().map(|dir| dir.join("gloom-rs").join(FILE_NAME))
+    }
+
+    /// The settings saved the last time, nothing set if there is no file yet.
+    pub fn load() -> UserSettings {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => {
+                println!("No config directory on this platform, the settings won't be saved");
+                return UserSettings::default();
+            }
+        };
+        match std::fs::read_to_string(&path) {
+            Ok(source) => {
+                println!("Loaded settings from {}", path.display());
+                Self::parse(&source, &path.display().to_string())
+            }
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => UserSettings::default(),
+            Err(error) => {
+                println!("Failed to read {}: {}", path.display(), error);
+                UserSettings::default()
+            }
+        }
+    }
+
+    // Settings that can't be read are skipped with a warning naming `source_name` and the setting
+    pub fn parse(source: &str, source_name: &str) -> UserSettings {
+        let mut user_settings = UserSettings::default();
+        let table = match source.parse::<toml::Table>() {
+            Ok(table) => table,
+            Err(error) => {
+                println!("Failed to parse {}: {}", source_name, error);
+                return user_settings;
+            }
+        };
+        for (name, value) in &table {
+            if let ("keys", toml::Value::Table(keys)) = (name.as_str(), value) {
+                for (action, key) in keys {
+                    if !user_settings.bind(action, key) {
+                        println!("{}: ignored keys.{} = {}", source_name, action, key);
+                    }
+                }
+            } else if !user_settings.set(name, value) {
+                println!("{}: ignored {} = {}", source_name, name, value);
+            }
+        }
+        user_settings
+    }
+
+    // False if there is no action called `action` or `key` isn't the name of a key
+    fn bind(&mut self, action: &str, key: &toml::Value) -> bool {
+        match (Action::from_name(action), key.as_str().and_then(events::key_from_name)) {
+            (Some(action), Some(key)) => {
+                self.key_bindings.retain(|&(bound_action, _)| bound_action != action);
+                self.key_bindings.push((action, key));
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // False if there is no setting called `name` or `value` isn't one of its values
+    fn set(&mut self, name: &str, value: &toml::Value) -> bool {
+        match name {
+            "mouse_sensitivity" => {
+                self.mouse_sensitivity = parse_f32(value).filter(|sensitivity| *sensitivity > 0.0);
+                self.mouse_sensitivity.is_some()
+            }
+            "window_size" => {
+                let sizes: Vec<u32> = value.as_array().map_or(vec![], |sizes| sizes.iter().filter_map(parse_positive_u32).collect());
+                self.window_size = match sizes[..] {
+                    [width, height] => Some((width, height)),
+                    _ => None,
+                };
+                self.window_size.is_some()
+            }
+            "window_mode" => {
+                self.window_mode = parse_named(value, &[WindowMode::Windowed, WindowMode::Borderless, WindowMode::Exclusive]);
+                self.window_mode.is_some()
+            }
+            "language" => {
+                self.language = value.as_str().filter(|language| !language.is_empty()).map(str::to_string);
+                self.language.is_some()
+            }
+            "vsync" => {
+                self.vsync = value.as_bool();
+                self.vsync.is_some()
+            }
+            "frame_cap" => {
+                self.frame_cap = match value.as_str() {
+                    Some("none") => Some(None),
+                    _            => parse_positive_u32(value).map(Some),
+                };
+                self.frame_cap.is_some()
+            }
+            "fog" => {
+                self.fog = value.as_bool();
+                self.fog.is_some()
+            }
+            "fog_mode" => {
+                self.fog_mode = parse_named(value, &[FogMode::Linear, FogMode::Exponential, FogMode::ExponentialSquared]);
+                self.fog_mode.is_some()
+            }
+            "pbr" => {
+                self.pbr = value.as_bool();
+                self.pbr.is_some()
+            }
+            "hdr" => {
+                self.hdr = value.as_bool();
+                self.hdr.is_some()
+            }
+            "exposure" => {
+                self.exposure = parse_f32(value).filter(|exposure| *exposure > 0.0);
+                self.exposure.is_some()
+            }
+            "auto_exposure" => {
+                self.auto_exposure = value.as_bool();
+                self.auto_exposure.is_some()
+            }
+            "ssao" => {
+                self.ssao = value.as_bool();
+                self.ssao.is_some()
+            }
+            "texture_quality" => {
+                self.texture_quality = parse_named(value, &[TextureQuality::Low, TextureQuality::Medium, TextureQuality::High, TextureQuality::Ultra]);
+                self.texture_quality.is_some()
+            }
+            _ => false,
+        }
+    }
+
+    // Set what was saved of the renderer settings, before the renderer checks them against the driver
+    pub fn apply_to(&self, settings: &mut RendererSettings) {
+        if let Some(vsync) = self.vsync { settings.vsync = vsync; }
+        if let Some(frame_cap) = self.frame_cap { settings.frame_cap = frame_cap; }
+        if let Some(fog) = self.fog { settings.fog.enabled = fog; }
+        if let Some(fog_mode) = self.fog_mode { settings.fog.mode = fog_mode; }
+        if let Some(pbr) = self.pbr { settings.pbr = pbr; }
+        if let Some(hdr) = self.hdr { settings.hdr = hdr; }
+        if let Some(exposure) = self.exposure { settings.exposure = exposure; }
+        if let Some(auto_exposure) = self.auto_exposure { settings.auto_exposure = auto_exposure; }
+        if let Some(ssao) = self.ssao { settings.ssao = ssao; }
+        if let Some(texture_quality) = self.texture_quality { settings.texture_quality = texture_quality; }
+    }
+
+    // Bind the saved keys, over the ones the program set up
+    pub fn apply_key_bindings(&self, action_map: &mut ActionMap) {
+        for &(action, key) in &self.key_bindings {
+            action_map.rebind(action, key);
+        }
+    }
+
+    // Everything as it is now, to be saved
+    pub fn capture(settings: &RendererSettings, mouse_sensitivity: f32, window_size: Option<(u32, u32)>, window_mode: WindowMode, action_map: &ActionMap, language: &str) -> UserSettings {
+        UserSettings {
+            mouse_sensitivity: Some(mouse_sensitivity),
+            window_size,
+            window_mode: Some(window_mode),
+            language: Some(language.to_string()),
+            vsync: Some(settings.vsync),
+            frame_cap: Some(settings.frame_cap),
+            fog: Some(settings.fog.enabled),
+            fog_mode: Some(settings.fog.mode),
+            pbr: Some(settings.pbr),
+            hdr: Some(settings.hdr),
+            exposure: Some(settings.exposure),
+            auto_exposure: Some(settings.auto_exposure),
+            ssao: Some(settings.ssao),
+            texture_quality: Some(settings.texture_quality),
+            key_bindings: Action::ALL.iter()
+                .filter_map(|&action| action_map.key_for(action).map(|key| (action, key)))
+                .collect(),
+        }
+    }
+
+    // The file, with the settings that are set
+    pub fn to_text(&self) -> String {
+        let mut table = toml::Table::new();
+        let mut setting = |name: &str, value: Option<toml::Value>| {
+            if let Some(value) = value {
+                table.insert(name.to_string(), value);
+            }
+        };
+        setting("mouse_sensitivity", self.mouse_sensitivity.map(float_value));
+        setting("window_size", self.window_size.map(|(width, height)| toml::Value::Array(vec![width.into(), height.into()])));
+        setting("window_mode", self.window_mode.map(named_value));
+        setting("language", self.language.clone().map(toml::Value::String));
+        setting("vsync", self.vsync.map(toml::Value::Boolean));
+        setting("frame_cap", self.frame_cap.map(|cap| cap.map_or("none".into(), |fps| fps.into())));
+        setting("fog", self.fog.map(toml::Value::Boolean));
+        setting("fog_mode", self.fog_mode.map(named_value));
+        setting("pbr", self.pbr.map(toml::Value::Boolean));
+        setting("hdr", self.hdr.map(toml::Value::Boolean));
+        setting("exposure", self.exposure.map(float_value));
+        setting("auto_exposure", self.auto_exposure.map(toml::Value::Boolean));
+        setting("ssao", self.ssao.map(toml::Value::Boolean));
+        setting("texture_quality", self.texture_quality.map(named_value));
+        if !self.key_bindings.is_empty() {
+            let keys = self.key_bindings.iter().map(|(action, key)| (format!("{:?}", action), named_value(key))).collect();
+            table.insert("keys".to_string(), toml::Value::Table(keys));
+        }
+        format!("# Gloom-rs settings, written when the program exits\n{}", table)
+    }
+
+    /// Write the settings file, making its folder if it isn't there yet.
+    pub fn save(&self) {
+        let path = match Self::path() {
+            Some(path) => path,
+            None => return,
+        };
+        let written = path.parent()
+            .map_or(Ok(()), std::fs::create_dir_all)
+            .and_then(|_| std::fs::write(&path, self.to_text()));
+        match written {
+            Ok(()) => println!("Saved settings to {}", path.display()),
+            Err(error) => println!("Failed to save settings to {}: {}", path.display(), error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_back_what_it_writes() {
+        let written = UserSettings {
+            mouse_sensitivity : Some(0.005),
+            window_size       : Some((1280, 720)),
+            window_mode       : Some(WindowMode::Borderless),
+            language          : Some("nb".to_string()),
+            frame_cap         : Some(None),
+            fog_mode          : Some(FogMode::Exponential),
+            exposure          : Some(1.5),
+            key_bindings      : vec![(Action::ToggleDoor, VirtualKeyCode::F)],
+            ..UserSettings::default()
+        };
+        let text = written.to_text();
+        assert!(text.contains("mouse_sensitivity = 0.005\n"), "{}", text);
+
+        let read = UserSettings::parse(&text, "test");
+        assert_eq!(read.mouse_sensitivity, Some(0.005));
+        assert_eq!(read.window_size, Some((1280, 720)));
+        assert_eq!(read.window_mode, Some(WindowMode::Borderless));
+        assert_eq!(read.language.as_deref(), Some("nb"));
+        assert_eq!(read.frame_cap, Some(None));
+        assert_eq!(read.fog_mode, Some(FogMode::Exponential));
+        assert_eq!(read.exposure, Some(1.5));
+        assert_eq!(read.vsync, None);
+        assert_eq!(read.key_bindings, vec![(Action::ToggleDoor, VirtualKeyCode::F)]);
+    }
+
+    #[test]
+    fn a_bad_value_only_costs_its_setting() {
+        let read = UserSettings::parse("vsync = \"maybe\"\nframe_cap = 0\nexposure = 2\nwindow_size = [640]\n\n[keys]\nToggleEngine = \"NoSuchKey\"\nToggleDoor = \"G\"\n", "test");
+        assert_eq!(read.vsync, None);
+        assert_eq!(read.frame_cap, None);
+        assert_eq!(read.exposure, Some(2.0));
+        assert_eq!(read.window_size, None);
+        assert_eq!(read.key_bindings, vec![(Action::ToggleDoor, VirtualKeyCode::G)]);
+    }
+
+    #[test]
+    fn not_toml_sets_nothing() {
+        let read = UserSettings::parse("mouse_sensitivity = 0.005 0.006", "test");
+        assert_eq!(read.mouse_sensitivity, None);
+    }
+}
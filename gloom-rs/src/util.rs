@@ -1,12 +1,17 @@
-use std::{ffi::CString, mem, os::raw::c_void, path::Path};
+use std::{mem, os::raw::c_void, path::Path, sync::Mutex};
 use libc;
-use rand::prelude::*;
+use crate::color;
+use crate::gfx;
 
 pub unsafe fn get_gl_string(name: gl::types::GLenum) -> String {
     std::ffi::CStr::from_ptr(gl::GetString(name) as *mut libc::c_char).to_string_lossy().to_string()
 }
 
-// Debug callback to panic upon encountering any OpenGL error
+// The first OpenGL error since raise_recorded_gl_error last looked
+static RECORDED_GL_ERROR: Mutex<Option<String>> = Mutex::new(None);
+
+// Debug callback that records any OpenGL error. It is called from inside the driver, where a panic
+// can't unwind and would abort the whole program, so the panic comes from raise_recorded_gl_error.
 pub extern "system" fn debug_callback(
     source: u32, e_type: u32, id: u32,
     severity: u32, _length: i32,
@@ -24,14 +29,25 @@ pub extern "system" fn debug_callback(
             _ => "unknown",
         };
         unsafe {
-            let string = CString::from_raw(msg as *mut libc::c_char);
-            let error_message = String::from_utf8_lossy(string.as_bytes()).to_string();
-            panic!("{}: Error of severity {} raised from {}: {}\n",
-                id, severity_string, source, error_message);
+            // The driver owns the message, so only borrow it
+            let error_message = std::ffi::CStr::from_ptr(msg).to_string_lossy().to_string();
+            if let Ok(mut recorded) = RECORDED_GL_ERROR.lock() {
+                recorded.get_or_insert(format!("{}: Error of severity {} raised from {}: {}\n",
+                    id, severity_string, source, error_message));
+            }
         }
     }
 }
 
+// Panic with the error debug_callback recorded, if there was one. Called at the end of every frame,
+// where the panic is caught and shown on the error screen (see main.rs).
+pub fn raise_recorded_gl_error() {
+    let recorded = RECORDED_GL_ERROR.lock().ok().and_then(|mut recorded| recorded.take());
+    if let Some(message) = recorded {
+        panic!("{}", message);
+    }
+}
+
 // Get the size of an arbitrary array of numbers measured in bytes
 // Example usage:  byte_size_of_array(my_array)
 pub fn byte_size_of_array<T>(val: &[T]) -> isize {
@@ -143,6 +159,21 @@ pub unsafe fn create_vao(
      */
     gl::BindVertexArray(vao_id);
 
+    // * Fill the VAO with buffers for all the vertex data
+    let vbo_id = fill_bound_vao(vertices, indices, colors, normals);
+
+    // * Return the ID of the VAO
+    return (vao_id, vbo_id)
+}
+
+// * Generate buffers for the currently bound VAO and fill them
+// Split out of create_vao so an existing VAO can be filled again (see refill_vao)
+unsafe fn fill_bound_vao(
+    vertices: &Vec<f32>, 
+    indices: &Vec<u32>, 
+    colors: &Vec<f32>,
+    normals: &Vec<f32>
+) -> u32 {
     // * Generate a VBO and bind it (Vertex Buffer Object) for vertices
     /*
      This step is very similar to the VAO generation, only with VBO ID instead and binding that
@@ -152,9 +183,7 @@ pub unsafe fn create_vao(
      We are going to be using very basic ARRAY type buffer for all our data storage
      There are other but I have no idea what they do, supposedly better performance and space usage for different data buffer types
      */
-    let mut vbo_id: u32 = 0;
-    gl::GenBuffers(1, &mut vbo_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id);
+    // (gfx::Buffer does the generating, binding and filling in one go, the fill is explained below)
 
     // * Fill it with data for vertices
     /*
@@ -170,12 +199,7 @@ pub unsafe fn create_vao(
      
      (Many other complex usages here for better performance when rendering, however we stick with basics cuz this is getting confusing for me lol)
      */
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertices),
-        pointer_to_array(vertices),
-        gl::STATIC_DRAW
-    );
+    let vertex_buffer = gfx::Buffer::new(gfx::BufferKind::Vertex, vertices);
 
     // * Configure a VAP for the data and enable it (Vertex Attribute Pointer)
     /*
@@ -197,33 +221,16 @@ pub unsafe fn create_vao(
 
     let position_attribute_index: u32 = 0;
     let number_of_vertexes_per_triangle: i32 = 3;
-    let stride: i32 = number_of_vertexes_per_triangle * size_of::<f32>();
-    gl::VertexAttribPointer(
-        position_attribute_index,
-        number_of_vertexes_per_triangle,
-        gl::FLOAT,
-        gl::FALSE,
-        0,
-        std::ptr::null()
-    );
-    gl::EnableVertexAttribArray(position_attribute_index); // Array/Pointer, same stuff at the end of the day, just some renaming, still enables VAP
+    vertex_buffer.vertex_attribute(position_attribute_index, number_of_vertexes_per_triangle); // Sets the VAP and enables it
 
     // * Generate a VBO and bind it (Vertex Buffer Object) for colors
     /*
      Here we generate a second VBO, this time for the vertex colors.
      The process is identical to generating the VBO for vertices.
      */
-    if !colors.is_empty() {
-        let mut vbo_id_color: u32 = 0;
-        gl::GenBuffers(1, &mut vbo_id_color);
-        gl::BindBuffer(gl::ARRAY_BUFFER, vbo_id_color);
-        gl::BufferData(
-            gl::ARRAY_BUFFER,
-            byte_size_of_array(colors),
-            pointer_to_array(colors),
-            gl::STATIC_DRAW,
-        );
-    }
+    // (The colors are picked by eye, in sRGB, and the shaders light in linear space, see color.rs)
+    let colors = color::decode_srgb_vertex_colors(colors);
+    let color_buffer = if colors.is_empty() { None } else { Some(gfx::Buffer::new(gfx::BufferKind::Vertex, &colors)) };
 
     // * Configure a VAP for the color data and enable it
     /*
@@ -239,19 +246,11 @@ pub unsafe fn create_vao(
      
      Lastly We enable VAP :)
      */
-    if !colors.is_empty() {
+    if let Some(color_buffer) = &color_buffer {
         let color_attribute_index: u32 = 1;
         let color_components_per_vertex = if colors.len() % 4 == 0 { 4 } else { 3 }; // Directly check if RGBA or RGB
 
-        gl::VertexAttribPointer(
-            color_attribute_index,
-            color_components_per_vertex,
-            gl::FLOAT,
-            gl::FALSE,
-            0,
-            std::ptr::null(),
-        );
-        gl::EnableVertexAttribArray(color_attribute_index);
+        color_buffer.vertex_attribute(color_attribute_index, color_components_per_vertex);
     }
 
     // * Generate a VBO and bind it (Vertex Buffer Object) for texture coordinates
@@ -281,27 +280,11 @@ pub unsafe fn create_vao(
     //     gl::EnableVertexAttribArray(texcoord_attribute_index);
     // }
 
-    let mut normals_id: u32 = 0;
-    gl::GenBuffers(1, &mut normals_id);
-    gl::BindBuffer(gl::ARRAY_BUFFER, normals_id);
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(normals),
-        pointer_to_array(normals),
-        gl::STATIC_DRAW
-    );
+    let normal_buffer = gfx::Buffer::new(gfx::BufferKind::Vertex, normals);
 
     let normal_attribute_index: u32 = 2;
     let number_of_normals_per_triangle: i32 = 3;
-    gl::VertexAttribPointer(
-        normal_attribute_index,
-        number_of_normals_per_triangle,
-        gl::FLOAT,
-        gl::FALSE,
-        0,
-        std::ptr::null()
-    );
-    gl::EnableVertexAttribArray(normal_attribute_index); 
+    normal_buffer.vertex_attribute(normal_attribute_index, number_of_normals_per_triangle);
 
     // * Generate a IBO and bind it (Indices Buffer Object)
     /*
@@ -322,10 +305,6 @@ pub unsafe fn create_vao(
      ELEMENT_ARRAY_BUFFER is the one responsible for this
      Now instead of Vertexes, we specify for Indices, same process as with VBO
      */
-    let mut ibo_id: u32 = 0;
-    gl::GenBuffers(1, &mut ibo_id);
-    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ibo_id);
-
     // * Fill it with data
     /*
      Here we fill the IBO with indices data
@@ -339,269 +318,179 @@ pub unsafe fn create_vao(
      ELEMENT_ARRAY_BUFFER is the one responsible for this
      Now instead of Vertexes, we specify for Indices, same process as with VBO
     */
-    gl::BufferData(
-        gl::ELEMENT_ARRAY_BUFFER,
-        byte_size_of_array(indices),
-        pointer_to_array(indices),
-        gl::STATIC_DRAW
-    );
+    gfx::Buffer::new(gfx::BufferKind::Index, indices); // Stays with the VAO, it is deleted through it
 
-    // * Return the ID of the VAO
-    return (vao_id, vbo_id)
+    vertex_buffer.id
 }
 
-// * Update VAO with new vertices
-pub unsafe fn update_vao_with_new_vertices(vao_id: u32, vertex_buffer_id: u32, vertices: &Vec<f32>) {
-    // 1. Bind the VAO
+// * Replace all the data of an existing VAO
+// The VAO ID stays the same, so anything referring to it (like SceneNodes) keeps working.
+// The old buffers are looked up through the VAO itself and deleted.
+pub unsafe fn refill_vao(
+    vao_id: u32,
+    vertices: &Vec<f32>, 
+    indices: &Vec<u32>, 
+    colors: &Vec<f32>,
+    normals: &Vec<f32>
+) -> u32 {
     gl::BindVertexArray(vao_id);
 
-    // 2. Bind the existing VBO
-    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer_id);
+    let old_buffers = bound_vao_buffers();
 
-    // 3. Reallocate and fill the buffer with new data
-    gl::BufferData(
-        gl::ARRAY_BUFFER,
-        byte_size_of_array(vertices),
-        pointer_to_array(vertices),
-        gl::STATIC_DRAW,
-    );
+    let vbo_id = fill_bound_vao(vertices, indices, colors, normals);
+
+    gl::DeleteBuffers(old_buffers.len() as i32, old_buffers.as_ptr());
 
-    // 4. Unbind the VAO to prevent accidental modification
     gl::BindVertexArray(0);
+    vbo_id
 }
 
+// * Delete a VAO together with all the buffers it uses
+pub unsafe fn delete_vao(vao_id: u32) {
+    gl::BindVertexArray(vao_id);
+    let buffers = bound_vao_buffers();
+    gl::BindVertexArray(0);
 
+    gl::DeleteBuffers(buffers.len() as i32, buffers.as_ptr());
+    gl::DeleteVertexArrays(1, &vao_id);
+}
 
-// * Scaling Transform
-pub fn scale_vertices(vertices: &Vec<f32>, scale_x: f32, scale_y: f32, scale_z: f32) -> Vec<f32> {
-    // Create a scaling matrix using glm
-    let scaling_matrix = glm::scaling(&glm::vec3(scale_x, scale_y, scale_z));
-    
-    // Create a new vector to store the scaled vertices
-    let mut scaled_vertices: Vec<f32> = Vec::with_capacity(vertices.len());
-
-    // Iterate through each vertex in the vertices vector
-    for i in 0..(vertices.len() / 3) {
-        // Create a vec4 from the vertex position, with w = 1 for homogeneous coordinates
-        let vertex = glm::vec4(vertices[3 * i], vertices[3 * i + 1], vertices[3 * i + 2], 1.0);
-
-        // Apply the scaling transformation
-        let scaled_vertex = scaling_matrix * vertex;
+// * Point a VAO at the positions, normals and indices another one reads, with colors of its own
+// For one mesh in several colors (see AssetManager::load_mesh): the geometry is uploaded once and
+// every color only adds a VAO and a color buffer. The color buffer `vao_id` had is deleted, the
+// shared ones are left to `geometry_vao`. Returns the new color buffer.
+pub unsafe fn share_geometry(vao_id: u32, geometry_vao: u32, colors: &[f32]) -> u32 {
+    let positions = vertex_buffer(geometry_vao, 0);
+    let normals = vertex_buffer(geometry_vao, 2);
+    gl::BindVertexArray(geometry_vao);
+    let mut index_buffer_id: i32 = 0;
+    gl::GetIntegerv(gl::ELEMENT_ARRAY_BUFFER_BINDING, &mut index_buffer_id);
 
-        // Store the scaled vertex into the new vector
-        scaled_vertices.push(scaled_vertex.x);
-        scaled_vertices.push(scaled_vertex.y);
-        scaled_vertices.push(scaled_vertex.z);
+    gl::BindVertexArray(vao_id);
+    let old_color_buffer = bound_color_buffer();
+    if let Some(positions) = positions {
+        positions.vertex_attribute(0, 3);
     }
+    if let Some(normals) = normals {
+        normals.vertex_attribute(2, 3);
+    }
+    gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, index_buffer_id as u32);
+    let colors = color::decode_srgb_vertex_colors(colors);
+    let color_buffer = gfx::Buffer::new(gfx::BufferKind::Vertex, &colors);
+    color_buffer.vertex_attribute(1, if colors.len().is_multiple_of(4) { 4 } else { 3 });
+    gl::BindVertexArray(0);
 
-    return scaled_vertices;
-}
-
-// * Rotation Transfomations
-/// Function to calculate a rotation matrix around the X-axis
-pub fn rotation_matrix_x(angle: f32) -> glm::Mat4 {
-    glm::rotation(angle, &glm::vec3(1.0, 0.0, 0.0))
-}
-
-/// Function to calculate a rotation matrix around the Y-axis
-pub fn rotation_matrix_y(angle: f32) -> glm::Mat4 {
-    glm::rotation(angle, &glm::vec3(0.0, 1.0, 0.0))
+    if old_color_buffer != 0 {
+        gl::DeleteBuffers(1, &old_color_buffer);
+    }
+    color_buffer.id
 }
 
-/// Function to calculate a rotation matrix around the Z-axis
-pub fn rotation_matrix_z(angle: f32) -> glm::Mat4 {
-    glm::rotation(angle, &glm::vec3(0.0, 0.0, 1.0))
-}
+// * Delete a VAO made with share_geometry, and its color buffer, but not the geometry it shares
+pub unsafe fn delete_shared_vao(vao_id: u32) {
+    gl::BindVertexArray(vao_id);
+    let color_buffer = bound_color_buffer();
+    gl::BindVertexArray(0);
 
-/// Function to apply a rotation to an array of vertices.
-/// - `vertices`: The input array of vertex positions.
-/// - `rotation_x`: Rotation around the X-axis in radians.
-/// - `rotation_y`: Rotation around the Y-axis in radians.
-/// - `rotation_z`: Rotation around the Z-axis in radians.
-/// Returns a new array of rotated vertices.
-pub fn rotate_vertices(
-    vertices: &Vec<f32>,
-    rotation_x: f32,
-    rotation_y: f32,
-    rotation_z: f32
-) -> Vec<f32> {
-    // Step 1: Calculate the individual rotation matrices
-    let rotation_matrix_x = rotation_matrix_x(rotation_x);
-    let rotation_matrix_y = rotation_matrix_y(rotation_y);
-    let rotation_matrix_z = rotation_matrix_z(rotation_z);
-
-    // Step 2: Combine the rotation matrices
-    let rotation_matrix = rotation_matrix_z * rotation_matrix_y * rotation_matrix_x;
-
-    // Step 3: Apply the combined rotation matrix to each vertex
-    let mut rotated_vertices: Vec<f32> = Vec::with_capacity(vertices.len());
-
-    for i in 0..(vertices.len() / 3) {
-        // Create a vec4 from the vertex position, with w = 1 for homogeneous coordinates
-        let vertex = glm::vec4(vertices[3 * i], vertices[3 * i + 1], vertices[3 * i + 2], 1.0);
-
-        // Apply the rotation transformation
-        let rotated_vertex = rotation_matrix * vertex;
-
-        // Store the rotated vertex into the new vector
-        rotated_vertices.push(rotated_vertex.x);
-        rotated_vertices.push(rotated_vertex.y);
-        rotated_vertices.push(rotated_vertex.z);
+    if color_buffer != 0 {
+        gl::DeleteBuffers(1, &color_buffer);
     }
-
-    // Return the new array of rotated vertices
-    rotated_vertices
+    gl::DeleteVertexArrays(1, &vao_id);
 }
 
-// * Translation Transformation
-/// Function to calculate a translation matrix
-pub fn translation_matrix(translate_x: f32, translate_y: f32, translate_z: f32) -> glm::Mat4 {
-    glm::translation(&glm::vec3(translate_x, translate_y, translate_z))
+unsafe fn bound_color_buffer() -> u32 {
+    let mut buffer_id: i32 = 0;
+    gl::GetVertexAttribiv(1, gl::VERTEX_ATTRIB_ARRAY_BUFFER_BINDING, &mut buffer_id);
+    buffer_id as u32
 }
 
-/// Function to apply a translation to an array of vertices.
-/// - `vertices`: The input array of vertex positions.
-/// - `translate_x`: Translation along the X-axis.
-/// - `translate_y`: Translation along the Y-axis.
-/// - `translate_z`: Translation along the Z-axis.
-/// Returns a new array of translated vertices.
-pub fn translate_vertices(
-    vertices: &Vec<f32>,
-    translate_x: f32,
-    translate_y: f32,
-    translate_z: f32
-) -> Vec<f32> {
-    // Create a translation matrix
-    let translation_matrix = translation_matrix(translate_x, translate_y, translate_z);
-
-    // Create a new vector to store the translated vertices
-    let mut translated_vertices: Vec<f32> = Vec::with_capacity(vertices.len());
-
-    // Iterate through each vertex and apply the translation
-    for i in 0..(vertices.len() / 3) {
-        // Create a vec4 from the vertex position, with w = 1 for homogeneous coordinates
-        let vertex = glm::vec4(vertices[3 * i], vertices[3 * i + 1], vertices[3 * i + 2], 1.0);
-
-        // Apply the translation transformation
-        let translated_vertex = translation_matrix * vertex;
-
-        // Store the translated vertex into the new vector
-        translated_vertices.push(translated_vertex.x);
-        translated_vertices.push(translated_vertex.y);
-        translated_vertices.push(translated_vertex.z);
+// * The buffer vertex attribute `location` of a VAO reads from, None if it isn't fed from one
+pub unsafe fn vertex_buffer(vao_id: u32, location: u32) -> Option<gfx::Buffer> {
+    gl::BindVertexArray(vao_id);
+    let mut buffer_id: i32 = 0;
+    gl::GetVertexAttribiv(location, gl::VERTEX_ATTRIB_ARRAY_BUFFER_BINDING, &mut buffer_id);
+    gl::BindVertexArray(0);
+    if buffer_id == 0 {
+        return None;
     }
 
-    translated_vertices
+    let mut size: i32 = 0;
+    gl::BindBuffer(gl::ARRAY_BUFFER, buffer_id as u32);
+    gl::GetBufferParameteriv(gl::ARRAY_BUFFER, gl::BUFFER_SIZE, &mut size);
+    Some(gfx::Buffer { id: buffer_id as u32, kind: gfx::BufferKind::Vertex, size: size as usize })
 }
 
+// IDs of the buffers the currently bound VAO reads from (the four attributes and the indices)
+unsafe fn bound_vao_buffers() -> Vec<u32> {
+    let mut buffers: Vec<u32> = vec![];
+    for attribute_index in 0..4 {
+        let mut buffer_id: i32 = 0;
+        gl::GetVertexAttribiv(attribute_index, gl::VERTEX_ATTRIB_ARRAY_BUFFER_BINDING, &mut buffer_id);
+        if buffer_id != 0 { buffers.push(buffer_id as u32); }
+    }
+    let mut index_buffer_id: i32 = 0;
+    gl::GetIntegerv(gl::ELEMENT_ARRAY_BUFFER_BINDING, &mut index_buffer_id);
+    if index_buffer_id != 0 { buffers.push(index_buffer_id as u32); }
 
-// * For calculating direction of camera 
-// So that WASD responds to camera movement
-// This means even if we are 180*, ie backwards, when we press W (froward)
-// Without this function => We would go backwards
-// With this function, camera view knows we are back so it takes this into account when transforming 
-// Meaning we will move forward as intended
-pub fn calculate_direction(yaw: f32, pitch: f32) -> glm::Vec3 {
-    glm::vec3(
-        yaw.cos() * pitch.cos(),
-        pitch.sin(),
-        yaw.sin() * pitch.cos(),
-    )
+    buffers.sort();
+    buffers.dedup();
+    buffers
 }
 
-pub fn calculate_right_vector(yaw: f32) -> glm::Vec3 {
-    glm::vec3(yaw.sin(), 0.0, -yaw.cos()).normalize()
-}
+// * Generate a VAO for a flat textured quad
+// Centered on the origin in the XY plane, facing +Z, with texture coordinates at location 3
+// (location 2 is taken by the normals). Returns the VAO ID and the index count.
+pub unsafe fn create_quad_vao(width: f32, height: f32) -> (u32, i32) {
+    let (w, h) = (width / 2.0, height / 2.0);
+    let vertices: Vec<f32> = vec![
+        -w, -h, 0.0,
+         w, -h, 0.0,
+         w,  h, 0.0,
+        -w,  h, 0.0,
+    ];
+    let colors: Vec<f32> = vec![1.0; 4 * 4];
+    let normals: Vec<f32> = vec![
+        0.0, 0.0, 1.0,
+        0.0, 0.0, 1.0,
+        0.0, 0.0, 1.0,
+        0.0, 0.0, 1.0,
+    ];
+    let texcoords: Vec<f32> = vec![
+        0.0, 0.0,
+        1.0, 0.0,
+        1.0, 1.0,
+        0.0, 1.0,
+    ];
+    let indices: Vec<u32> = vec![0, 1, 2, 0, 2, 3];
+
+    let (vao_id, _) = create_vao(&vertices, &indices, &colors, &normals);
+
+    // The texture coordinates go in their own buffer, create_vao left the VAO bound
+    let texcoord_buffer = gfx::Buffer::new(gfx::BufferKind::Vertex, &texcoords);
+
+    let texcoord_attribute_index: u32 = 3;
+    texcoord_buffer.vertex_attribute(texcoord_attribute_index, 2);
 
-pub fn calculate_up_vector(forward: glm::Vec3, right: glm::Vec3) -> glm::Vec3 {
-    glm::cross(&right, &forward).normalize()
+    gl::BindVertexArray(0);
+    (vao_id, indices.len() as i32)
 }
 
+// * Update VAO with new vertices
+pub unsafe fn update_vao_with_new_vertices(vao_id: u32, vertex_buffer_id: u32, vertices: &Vec<f32>) {
+    // 1. Bind the VAO
+    gl::BindVertexArray(vao_id);
 
+    // 2. Bind the existing VBO
+    gl::BindBuffer(gl::ARRAY_BUFFER, vertex_buffer_id);
 
-// * Apply transformations to the world from camera view
-pub fn calculate_transformation_from_camera_to_world_view(
-    window_aspect_ratio: f32,
-    camera_position: glm::Vec3,
-    camera_forward: glm::Vec3,
-    camera_up: glm::Vec3
-) -> glm::Mat4 {
-    // Calculate camera perspective
-    let camera_aspect_ratio = window_aspect_ratio;
-    let camera_perspective_matrix: glm::Mat4 = glm::perspective(camera_aspect_ratio, 45.0_f32.to_radians(), 1.0, 10000.0);
-
-    // Calculate camera transformations
-    // Build the view matrix based on the camera position and orientation
-    let camera_rotation_matrix = glm::look_at(
-        &camera_position, 
-        &(camera_position + camera_forward), 
-        &camera_up
+    // 3. Reallocate and fill the buffer with new data
+    gl::BufferData(
+        gl::ARRAY_BUFFER,
+        byte_size_of_array(vertices),
+        pointer_to_array(vertices),
+        gl::STATIC_DRAW,
     );
 
-    // Combine the matrices
-    let view_projection_matrix: glm::Mat4 = camera_perspective_matrix * camera_rotation_matrix;
-
-    // Return
-    return view_projection_matrix;
-}
-
-
-
-// * Generalized transformation function
-// This function calculates the full transformation matrix for an object, including its position, 
-// rotation, and scaling, and applies the view-projection matrix from the camera.
-// Parameters:
-// - `position`: The object's position in world coordinates.
-// - `rotation`: The object's rotation angles around each axis (x, y, z) in radians.
-// - `scale`: The scaling factor for the object in each direction (x, y, z).
-// - `view_projection_matrix`: The combined view-projection matrix from the camera.
-pub fn calculate_transformation_object(
-    position: glm::Vec3,
-    rotation: glm::Vec3,
-    scale: glm::Vec3,
-    view_projection_matrix: glm::Mat4,
-) -> glm::Mat4 {
-    // Compute rotation in Objects frame
-    let object_rotation_matrix_x = glm::rotation(rotation.x, &glm::vec3(1.0, 0.0, 0.0));
-    let object_rotation_matrix_y = glm::rotation(rotation.y, &glm::vec3(0.0, 1.0, 0.0));
-    let object_rotation_matrix_z = glm::rotation(rotation.z, &glm::vec3(0.0, 0.0, 1.0));
-
-    let object_rotation_matrix: glm::Mat4 = 
-        object_rotation_matrix_z * 
-        object_rotation_matrix_y *
-        object_rotation_matrix_x;
-
-    // Before we do anything else we scale, rotate and put object into start position
-    // 1. Scale
-    // 2. Rotate
-    // 3. Translate
-    let object_transform_matrix: glm::Mat4 = 
-        glm::translation(&position) *
-        object_rotation_matrix *
-        glm::scaling(&scale);
-
-    // Combine matrices to form view projection of rotating orca matrix
-    // 1. Apply start position of Orca
-    // 2. Translate Orca to the origin
-    // 3. Apply rotation animation
-    // 4. Apply translation animation
-    // 5. Translate Orca back to its original position
-    // 6. Apply view-projection transformation
-    let view_projection_matrix_object: glm::Mat4 = 
-        view_projection_matrix * 
-        object_transform_matrix;
-    
-    // Return
-    return view_projection_matrix_object;
-}
-
-
-// * Random float generator
-pub fn random_float_in_range(min: f32, max: f32) -> f32 {
-    let mut rng = rand::thread_rng();
-    let range = max - min;
-    rng.gen::<f32>() * range + min
+    // 4. Unbind the VAO to prevent accidental modification
+    gl::BindVertexArray(0);
 }
-
-
-
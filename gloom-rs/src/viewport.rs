@@ -0,0 +1,87 @@
+use crate::camera::Camera;
+use crate::gfx;
+
+// * Split screen
+// Divides the window into 1, 2 or 4 regions, each drawn from its own camera.
+// Region 0 is always the fly-cam, the others are filled in by whoever owns the manager.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SplitLayout {
+    Single,
+    Two,  // Side by side
+    Four, // 2x2 grid
+}
+
+impl SplitLayout {
+    pub fn next(self) -> SplitLayout {
+        match self {
+            SplitLayout::Single => SplitLayout::Two,
+            SplitLayout::Two    => SplitLayout::Four,
+            SplitLayout::Four   => SplitLayout::Single,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Viewport {
+    pub x      : i32,
+    pub y      : i32,
+    pub width  : i32,
+    pub height : i32,
+}
+
+impl Viewport {
+    pub fn aspect_ratio(&self) -> f32 {
+        self.width.max(1) as f32 / self.height.max(1) as f32
+    }
+
+    /// Restrict drawing and clearing to this region.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn activate(&self) {
+        gl::Viewport(self.x, self.y, self.width, self.height);
+        gfx::set_scissor(Some((self.x, self.y, self.width, self.height)));
+    }
+}
+
+pub struct ViewportManager {
+    pub layout  : SplitLayout,
+    pub cameras : Vec<Camera>, // One per region, for the biggest layout
+}
+
+impl ViewportManager {
+    pub fn new(layout: SplitLayout, cameras: Vec<Camera>) -> ViewportManager {
+        assert!(cameras.len() >= 4, "ViewportManager needs a camera for each of the 4 regions");
+        ViewportManager { layout, cameras }
+    }
+
+    // Pixel regions for the current layout. OpenGL has y going up, so the first row is the top one.
+    pub fn viewports(&self, window_width: u32, window_height: u32) -> Vec<Viewport> {
+        let (w, h) = (window_width as i32, window_height as i32);
+        match self.layout {
+            SplitLayout::Single => vec![
+                Viewport { x: 0, y: 0, width: w, height: h },
+            ],
+            SplitLayout::Two => vec![
+                Viewport { x: 0,     y: 0, width: w / 2,     height: h },
+                Viewport { x: w / 2, y: 0, width: w - w / 2, height: h },
+            ],
+            SplitLayout::Four => vec![
+                Viewport { x: 0,     y: h / 2, width: w / 2,     height: h - h / 2 },
+                Viewport { x: w / 2, y: h / 2, width: w - w / 2, height: h - h / 2 },
+                Viewport { x: 0,     y: 0,     width: w / 2,     height: h / 2 },
+                Viewport { x: w / 2, y: 0,     width: w - w / 2, height: h / 2 },
+            ],
+        }
+    }
+
+    /// Done drawing the split views, go back to the whole window.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn reset(&self, window_width: u32, window_height: u32) {
+        gfx::set_scissor(None);
+        gl::Viewport(0, 0, window_width as i32, window_height as i32);
+    }
+}
@@ -0,0 +1,204 @@
+extern crate nalgebra_glm as glm;
+
+use crate::assets::{AssetManager, ShaderHandle};
+use crate::camera::Camera;
+use crate::gfx;
+use crate::reflection::PlanarReflection;
+use crate::util;
+use crate::viewport::Viewport;
+
+// * Water
+// A stretch of open water for scenes set on the Earth instead of the moon, placed from the asset
+// manifest with `water` or `reflective-water` in place of a path. It is a flat square grid, moved by a
+// sum of Gerstner waves in the vertex shader: every wave moves the points of the surface around in
+// circles, up and down and back and forth along the way it travels, so the crests come out sharp and
+// the troughs wide, like on the sea. How fast a wave goes follows from its length, by the dispersion of
+// waves on deep water, so the long swell overtakes the short chop on top of it.
+//
+// The normal of the surface is worked out from the same sum, from how the waves tilt it, so it is
+// exact at every vertex however coarse the grid. The fragment shader mixes the color of the water with
+// what it reflects by Schlick's Fresnel term: looking down the water is dark and clear, towards the
+// horizon it turns into a mirror. What it mirrors is the sky color, or the scene drawn mirrored about
+// the water (see PlanarReflection) for reflective water, the look up pushed aside by the tilt of the
+// waves. The sun glints off the slopes facing it and the highest crests break into foam.
+//
+// The waves are all in world space, so two surfaces next to each other line up at the seam.
+
+const GRID_CELLS : usize = 192;  // Squares along a side of the grid
+const MAX_WAVES  : usize = 8;    // Has to match water.vert
+const GRAVITY    : f32 = 9.81;   // The Earth's, for how fast the waves go
+
+#[derive(Clone, Copy, Debug)]
+pub struct GerstnerWave {
+    pub direction  : glm::Vec2, // Unit length, the way it travels over XZ
+    pub wavelength : f32,       // From crest to crest
+    pub amplitude  : f32,       // Height of the crests over the still water
+    pub steepness  : f32,       // 0 for round crests, 1 for the sharpest before they loop over
+}
+
+impl GerstnerWave {
+    // A wave travelling `angle` radians around from +X
+    pub fn new(angle: f32, wavelength: f32, amplitude: f32, steepness: f32) -> GerstnerWave {
+        GerstnerWave { direction: glm::vec2(angle.cos(), angle.sin()), wavelength, amplitude, steepness }
+    }
+
+    // Radians per second of the wave going past a point
+    pub fn frequency(&self) -> f32 {
+        (GRAVITY * 2.0 * std::f32::consts::PI / self.wavelength).sqrt()
+    }
+}
+
+// A long swell with shorter waves on top, all going roughly the way the wind blows at `angle`
+pub fn ocean_waves(angle: f32) -> Vec<GerstnerWave> {
+    vec![
+        GerstnerWave::new(angle,        24.0, 0.45, 0.6),
+        GerstnerWave::new(angle + 0.4,  15.0, 0.25, 0.5),
+        GerstnerWave::new(angle - 0.5,  9.0,  0.14, 0.6),
+        GerstnerWave::new(angle + 1.1,  5.5,  0.08, 0.5),
+        GerstnerWave::new(angle - 0.9,  3.5,  0.05, 0.6),
+        GerstnerWave::new(angle + 0.2,  2.0,  0.02, 0.4),
+    ]
+}
+
+pub struct WaterSurface {
+    pub enabled : bool,
+    pub center  : glm::Vec3,         // Of the still water
+    pub size    : f32,               // Side length
+    pub color   : [f32; 3],          // Of deep water, linear
+    pub waves   : Vec<GerstnerWave>, // Up to MAX_WAVES, the rest are left out
+    time        : f32,
+    reflection  : Option<PlanarReflection>,
+    vao_id      : u32,
+    index_count : i32,
+    shader      : ShaderHandle,
+}
+
+impl WaterSurface {
+    /// `size` by `size` of water around `center`, mirroring the scene if `reflections`.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn new(assets: &mut AssetManager, center: glm::Vec3, size: f32, color: [f32; 3], reflections: bool, width: u32, height: u32) -> WaterSurface {
+        let (vao_id, index_count) = grid_vao(size, GRID_CELLS);
+        WaterSurface {
+            enabled: true,
+            center,
+            size,
+            color,
+            waves: ocean_waves(0.3),
+            time: 0.0,
+            reflection: if reflections { Some(PlanarReflection::new(center.y, width, height, "water reflection")) } else { None },
+            vao_id,
+            index_count,
+            shader: assets.load_shader(&["shaders/water.vert", "shaders/water.frag"]),
+        }
+    }
+
+    pub fn update(&mut self, delta_time: f32) {
+        self.time += delta_time;
+    }
+
+    // Whether the mirrored scene has to be drawn for it this frame
+    pub fn needs_planar_pass(&self) -> bool {
+        self.enabled && self.reflection.is_some()
+    }
+
+    /// Draw the scene mirrored about the still water for every view, see PlanarReflection::render.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn render_reflections<F: FnMut(&glm::Mat4, glm::Vec3, [f32; 4])>(
+        &mut self,
+        window_width: u32,
+        window_height: u32,
+        views: &[(Viewport, Camera, glm::Mat4)],
+        sky_color: [f32; 3],
+        draw: F,
+    ) {
+        if let (true, Some(reflection)) = (self.enabled, self.reflection.as_mut()) {
+            reflection.height = self.center.y;
+            reflection.render(window_width, window_height, views, sky_color, draw);
+        }
+    }
+
+    /// Draw the water into the active view. The sun colors are linear, like the scene shader's.
+    ///
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn draw(&self, assets: &AssetManager, view_projection: &glm::Mat4, camera_position: glm::Vec3, sun_direction: glm::Vec3, sun_color: [f32; 3], sky_color: [f32; 3]) {
+        if !self.enabled {
+            return;
+        }
+        let shader = assets.shader(self.shader);
+        shader.activate();
+        shader.set_uniform_mat4("view_projection", view_projection);
+        shader.set_uniform_vec3("origin", self.center.as_ref());
+        shader.set_uniform_float("time", self.time);
+        shader.set_uniform_vec3("camera_position", camera_position.as_ref());
+        shader.set_uniform_vec3("deep_color", &self.color);
+        shader.set_uniform_vec3("sun_direction", sun_direction.as_ref());
+        shader.set_uniform_vec3("sun_color", &sun_color);
+        shader.set_uniform_vec3("sky_color", &sky_color);
+
+        // The steepness is shared out over the waves, so all of them together never loop over
+        let count = self.waves.len().min(MAX_WAVES);
+        shader.set_uniform_int("wave_count", count as i32);
+        for (i, wave) in self.waves.iter().take(count).enumerate() {
+            let number = 2.0 * std::f32::consts::PI / wave.wavelength;
+            let sharpness = wave.steepness / (number * wave.amplitude * count as f32).max(1e-6);
+            shader.set_uniform_vec4(&format!("waves[{}]", i), &[wave.direction.x, wave.direction.y, number, wave.amplitude]);
+            shader.set_uniform_vec2(&format!("wave_motion[{}]", i), &[sharpness, wave.frequency()]);
+        }
+
+        shader.set_uniform_int("planar_reflection", self.reflection.is_some() as i32);
+        shader.set_uniform_int("reflection_texture", 0);
+        if let Some(reflection) = &self.reflection {
+            let (texture, texture_size) = reflection.texture();
+            shader.set_uniform_vec2("texture_size", &texture_size);
+            gfx::bind_texture(0, Some(texture));
+        }
+
+        gfx::RenderState { cull: gfx::CullMode::Off, ..Default::default() }.apply();
+        gl::BindVertexArray(self.vao_id);
+        gfx::count_draw_call();
+        gl::DrawElements(gl::TRIANGLES, self.index_count, gl::UNSIGNED_INT, std::ptr::null());
+        gl::BindVertexArray(0);
+        gfx::RenderState::default().apply();
+        gfx::bind_texture(0, None);
+    }
+
+    /// # Safety
+    /// Needs a current OpenGL context.
+    pub unsafe fn delete(&self) {
+        util::delete_vao(self.vao_id);
+        if let Some(reflection) = &self.reflection {
+            reflection.delete();
+        }
+    }
+}
+
+// A flat grid `size` across in the XZ plane around the origin, `cells` squares along a side
+unsafe fn grid_vao(size: f32, cells: usize) -> (u32, i32) {
+    let side = cells + 1;
+    let mut vertices = Vec::with_capacity(side * side * 3);
+    for row in 0..side {
+        for column in 0..side {
+            let (x, z) = (column as f32 / cells as f32 - 0.5, row as f32 / cells as f32 - 0.5);
+            vertices.extend_from_slice(&[x * size, 0.0, z * size]);
+        }
+    }
+    let mut indices = Vec::with_capacity(cells * cells * 6);
+    for row in 0..cells {
+        for column in 0..cells {
+            let a = (row * side + column) as u32;
+            let (b, c, d) = (a + 1, a + side as u32, a + side as u32 + 1);
+            indices.extend_from_slice(&[a, c, b, b, c, d]);
+        }
+    }
+    let colors = vec![1.0; side * side * 4];
+    let normals = (0..side * side).flat_map(|_| [0.0, 1.0, 0.0]).collect();
+    let (vao_id, _) = util::create_vao(&vertices, &indices, &colors, &normals);
+    gl::BindVertexArray(0);
+    gfx::label_object(gfx::ObjectKind::VertexArray, vao_id, "water VAO");
+    (vao_id, indices.len() as i32)
+}
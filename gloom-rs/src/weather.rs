@@ -0,0 +1,193 @@
+extern crate nalgebra_glm as glm;
+
+use crate::ecs;
+use crate::settings::FogSettings;
+
+// * Weather
+// A preset says how thick the fog is, what color the haze in the air is, how much the light is dimmed
+// and tinted, and how much dust or snow is blowing around. Switching presets doesn't happen at once:
+// everything blends over to the new preset over a few seconds, so a dust storm rolls in.
+//
+// The dust and snow are two particle emitters in the ECS that follow the camera around, so there is
+// weather wherever one looks without filling the whole terrain with particles. The dust comes in
+// gusts, from upwind of the camera. Snow doesn't fall on the moon, it is there for Earth scenes.
+// Q cycles the presets.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WeatherPreset {
+    Clear,
+    DustStorm,
+    Snow,
+}
+
+impl WeatherPreset {
+    pub fn next(self) -> WeatherPreset {
+        match self {
+            WeatherPreset::Clear     => WeatherPreset::DustStorm,
+            WeatherPreset::DustStorm => WeatherPreset::Snow,
+            WeatherPreset::Snow      => WeatherPreset::Clear,
+        }
+    }
+
+    fn parameters(self) -> WeatherParameters {
+        match self {
+            WeatherPreset::Clear => WeatherParameters {
+                fog_start: 300.0, fog_end: 850.0, fog_density: 0.003, // The FogSettings defaults
+                haze: [0.0, 0.0, 0.0], haze_amount: 0.0,
+                light: [1.0, 1.0, 1.0],
+                dust: 0.0, snow: 0.0,
+            },
+            WeatherPreset::DustStorm => WeatherParameters {
+                fog_start: 15.0, fog_end: 220.0, fog_density: 0.012,
+                haze: [0.55, 0.38, 0.22], haze_amount: 0.85,
+                light: [0.7, 0.5, 0.35],
+                dust: 1.0, snow: 0.0,
+            },
+            WeatherPreset::Snow => WeatherParameters {
+                fog_start: 40.0, fog_end: 420.0, fog_density: 0.006,
+                haze: [0.75, 0.78, 0.82], haze_amount: 0.7,
+                light: [0.75, 0.8, 0.9],
+                dust: 0.0, snow: 1.0,
+            },
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct WeatherParameters {
+    fog_start   : f32,
+    fog_end     : f32,
+    fog_density : f32,
+    haze        : [f32; 3], // Hue the sky and the fog take on, at the brightness the sky already has
+    haze_amount : f32,
+    light       : [f32; 3], // Multiplied into the sun and ambient light
+    dust        : f32,      // How much of the dust emitter's rate, 0..1
+    snow        : f32,
+}
+
+impl WeatherParameters {
+    fn lerp(&self, other: &WeatherParameters, t: f32) -> WeatherParameters {
+        let mix = |a: f32, b: f32| a + (b - a) * t;
+        let mix3 = |a: [f32; 3], b: [f32; 3]| [mix(a[0], b[0]), mix(a[1], b[1]), mix(a[2], b[2])];
+        WeatherParameters {
+            fog_start   : mix(self.fog_start, other.fog_start),
+            fog_end     : mix(self.fog_end, other.fog_end),
+            fog_density : mix(self.fog_density, other.fog_density),
+            haze        : mix3(self.haze, other.haze),
+            haze_amount : mix(self.haze_amount, other.haze_amount),
+            light       : mix3(self.light, other.light),
+            dust        : mix(self.dust, other.dust),
+            snow        : mix(self.snow, other.snow),
+        }
+    }
+}
+
+// Particles per second at full strength, and the wind the dust blows with
+const DUST_RATE: f32 = 150.0;
+const SNOW_RATE: f32 = 120.0;
+const WIND: glm::Vec3 = glm::Vec3::new(14.0, 0.5, 8.0);
+
+pub struct Weather {
+    pub preset          : WeatherPreset,
+    pub transition_time : f32, // Seconds to blend over to a new preset
+    current             : WeatherParameters,
+    time                : f32,
+    wind                : glm::Vec3,
+    dust                : ecs::Entity,
+    snow                : ecs::Entity,
+}
+
+impl Weather {
+    // Spawns the dust and snow emitters into `world`. The meshes are (VAO, index count, the middle of
+    // the mesh), small ones painted in the color of the dust and the snow.
+    pub fn new(world: &mut ecs::World, dust_mesh: (u32, i32, glm::Vec3), snow_mesh: (u32, i32, glm::Vec3)) -> Weather {
+        let dust = world.spawn();
+        world.transforms.insert(dust, ecs::Transform::at(glm::zero()));
+        let mut dust_emitter = ecs::ParticleEmitter::new(dust_mesh.0, dust_mesh.1, None, 0.0, 5.0);
+        dust_emitter.mesh_center = dust_mesh.2;
+        dust_emitter.area = glm::vec3(40.0, 6.0, 40.0);
+        dust_emitter.spread = 2.0;
+        dust_emitter.size = 0.01;
+        dust_emitter.max_count = 750;
+        world.particle_emitters.insert(dust, dust_emitter);
+
+        let snow = world.spawn();
+        world.transforms.insert(snow, ecs::Transform::at(glm::zero()));
+        let mut snow_emitter = ecs::ParticleEmitter::new(snow_mesh.0, snow_mesh.1, None, 0.0, 8.0);
+        snow_emitter.mesh_center = snow_mesh.2;
+        snow_emitter.area = glm::vec3(50.0, 5.0, 50.0);
+        snow_emitter.velocity = glm::vec3(1.0, -4.0, 0.6);
+        snow_emitter.spread = 0.5;
+        snow_emitter.size = 0.006;
+        snow_emitter.max_count = 960;
+        world.particle_emitters.insert(snow, snow_emitter);
+
+        Weather {
+            preset: WeatherPreset::Clear,
+            transition_time: 6.0,
+            current: WeatherPreset::Clear.parameters(),
+            time: 0.0,
+            wind: glm::zero(),
+            dust,
+            snow,
+        }
+    }
+
+    // * Blend towards the preset and move the emitters along with the camera
+    // Call before ecs::update_transforms, so the emitters are in place when the particles are spawned
+    pub fn update(&mut self, world: &mut ecs::World, camera_position: glm::Vec3, delta_time: f32) {
+        self.time += delta_time;
+        let t = (delta_time / self.transition_time.max(1e-3)).min(1.0);
+        self.current = self.current.lerp(&self.preset.parameters(), t);
+
+        // Gusts, a couple of slow waves on top of each other
+        let gust = 1.0 + 0.5 * (self.time * 0.7).sin() + 0.3 * (self.time * 1.9).sin();
+        let wind = WIND * gust;
+        self.wind = wind * (0.2 + 0.8 * self.current.dust);
+
+        if let Some(transform) = world.transforms.get_mut(self.dust) {
+            transform.position = camera_position - glm::normalize(&WIND) * 40.0 + glm::vec3(0.0, 3.0, 0.0);
+        }
+        if let Some(emitter) = world.particle_emitters.get_mut(self.dust) {
+            emitter.rate = DUST_RATE * self.current.dust * gust.max(0.0);
+            emitter.velocity = wind;
+        }
+        if let Some(transform) = world.transforms.get_mut(self.snow) {
+            transform.position = camera_position + glm::vec3(0.0, 25.0, 0.0);
+        }
+        if let Some(emitter) = world.particle_emitters.get_mut(self.snow) {
+            emitter.rate = SNOW_RATE * self.current.snow;
+        }
+    }
+
+    // Fog distances of the weather. The fog color is the sky color, see `sky_color`.
+    pub fn apply_fog(&self, fog: &mut FogSettings) {
+        fog.start = self.current.fog_start;
+        fog.end = self.current.fog_end;
+        fog.density = self.current.fog_density;
+    }
+
+    // The sky (and fog) color seen through the haze, it keeps its brightness so nights stay dark
+    pub fn sky_color(&self, sky_color: [f32; 3]) -> [f32; 3] {
+        let luma = |color: [f32; 3]| 0.2126 * color[0] + 0.7152 * color[1] + 0.0722 * color[2];
+        let scale = if luma(self.current.haze) > 1e-4 { luma(sky_color) / luma(self.current.haze) } else { 0.0 };
+        let haze = self.current.haze.map(|channel| channel * scale);
+        let amount = self.current.haze_amount;
+        [0, 1, 2].map(|i| sky_color[i] + (haze[i] - sky_color[i]) * amount)
+    }
+
+    // The wind with its gusts, a breeze in clear weather and the full wind of a dust storm
+    pub fn wind(&self) -> glm::Vec3 {
+        self.wind
+    }
+
+    // How much of what is beyond the sky shows through the haze, the stars and the Earth
+    pub fn sky_clearness(&self) -> f32 {
+        1.0 - self.current.haze_amount
+    }
+
+    // The sun or ambient light color, dimmed and tinted by what is in the air
+    pub fn light_color(&self, light_color: [f32; 3]) -> [f32; 3] {
+        [0, 1, 2].map(|i| light_color[i] * self.current.light[i])
+    }
+}